@@ -0,0 +1,227 @@
+// Interpreter throughput, and heap allocations per `eval()`.
+//
+//     cargo bench --bench eval
+//
+// The allocation counts are printed before the timings.  They come from a
+// counting global allocator, and only count the allocations of the calls
+// being measured.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fasteval2::{
+    eval_compiled_ref, CompiledExpression, Compiler, EmptyNamespace, Evaler, Parser, Slab,
+};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocs_per_call(n: usize, mut f: impl FnMut()) -> f64 {
+    let before = ALLOCS.with(Cell::get);
+    for _ in 0..n {
+        f();
+    }
+    (ALLOCS.with(Cell::get) - before) as f64 / n as f64
+}
+
+// 30 terms, with every binary operator and a few parentheses:
+const EXPR_30: &str = "x*2 + y/3 - x^2 + 4*y*x - 5 % 3 + (x - y)*6 + 7/x - y^2 + 8 \
+                       - x*y/9 + (10 + x)*(y - 11) + 12 < 13 && x >= 1 || y != 0";
+
+fn eval_30_terms(c: &mut Criterion) {
+    let mut slab = Slab::new();
+    let expr = Parser::new()
+        .parse(EXPR_30, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut ns = BTreeMap::new();
+    ns.insert("x".to_string(), 1.5);
+    ns.insert("y".to_string(), -2.25);
+
+    let n = allocs_per_call(1000, || {
+        black_box(expr.eval(&slab, &mut ns).unwrap());
+    });
+    println!("eval_30_terms: {} allocations per eval", n);
+
+    c.bench_function("eval_30_terms", |b| {
+        b.iter(|| black_box(expr).eval(&slab, &mut ns).unwrap())
+    });
+}
+
+// Short expressions of 3 to 7 tokens, like most configuration formulas:
+const SHORT: [&str; 12] = [
+    "x + 1",
+    "2 * y - 3",
+    "x * x + y",
+    "(x + 1) / 2",
+    "x ^ 2 - y",
+    "x < 3 && y",
+    "1 - x * 2",
+    "y / x",
+    "-x + 4",
+    "x % 3 == 1",
+    "x * 2 + y - 1",
+    "x ^ 2 + y ^ 2",
+];
+
+fn short_batch(c: &mut Criterion) {
+    let mut slab = Slab::new();
+    let parser = Parser::new();
+    let mut ns = BTreeMap::new();
+    ns.insert("x".to_string(), 1.5);
+    ns.insert("y".to_string(), -2.25);
+    let batch: Vec<&str> = SHORT.iter().cycle().take(1000).copied().collect();
+
+    let (mut parse_allocs, mut eval_allocs) = (0, 0);
+    for s in &batch {
+        let before = ALLOCS.with(Cell::get);
+        let expr_i = parser.parse(s, &mut slab.ps).unwrap();
+        let parsed = ALLOCS.with(Cell::get);
+        black_box(expr_i.from(&slab.ps).eval(&slab, &mut ns).unwrap());
+        parse_allocs += parsed - before;
+        eval_allocs += ALLOCS.with(Cell::get) - parsed;
+    }
+    let per_expr = |n: usize| n as f64 / batch.len() as f64;
+    println!(
+        "short_batch: {} allocations per parse, {} per eval",
+        per_expr(parse_allocs),
+        per_expr(eval_allocs)
+    );
+
+    c.bench_function("short_batch_parse_eval_1000x", |b| {
+        b.iter(|| {
+            for s in &batch {
+                let expr = parser.parse(black_box(s), &mut slab.ps).unwrap();
+                black_box(expr.from(&slab.ps).eval(&slab, &mut ns).unwrap());
+            }
+        })
+    });
+}
+
+// 20 distinct variables, looked up by name or by symbol:
+const VARS_20: &str = "alpha + beta*2 - gamma + delta/3 + epsilon*zeta - eta + theta^2 \
+                       + iota - kappa*lambda + mu - nu/4 + xi*omicron - sigma \
+                       + tau - upsilon*phi_ + chi";
+
+fn lookups_20_vars(c: &mut Criterion) {
+    let compiled = CompiledExpression::compile(VARS_20, &mut EmptyNamespace).unwrap();
+    let bound = compiled.bind().unwrap();
+    let mut ns = BTreeMap::new();
+    let mut vals = vec![0.0; bound.symbols().len()];
+    for (i, name) in bound.symbols().names().enumerate() {
+        ns.insert(name.to_string(), i as f64 + 0.5);
+        vals[i] = i as f64 + 0.5;
+    }
+    assert_eq!(vals.len(), 20);
+    assert_eq!(
+        compiled.eval(&mut ns).unwrap(),
+        bound.eval(&vals, &mut EmptyNamespace).unwrap()
+    );
+
+    c.bench_function("lookups_20_vars_100kx_by_name", |b| {
+        b.iter(|| {
+            for _ in 0..100_000 {
+                black_box(compiled.eval(black_box(&mut ns)).unwrap());
+            }
+        })
+    });
+    c.bench_function("lookups_20_vars_100kx_by_symbol", |b| {
+        b.iter(|| {
+            for _ in 0..100_000 {
+                black_box(bound.eval(black_box(&vals), &mut EmptyNamespace).unwrap());
+            }
+        })
+    });
+}
+
+// Method calls vs. eval_compiled_ref!() on three shapes of compiled
+// expressions: a constant-folded one, which the macro returns without a call,
+// and a lone variable and a compound expression, which both reach eval():
+const SHAPES: [(&str, &str); 3] = [
+    ("const", "3 * 3 - 3 / 3"),
+    ("var", "x"),
+    ("compound", "sin(x)*k + (a-b)/(c+1)"),
+];
+
+fn eval_compiled_shapes(c: &mut Criterion) {
+    let mut ns = |name: &str, _: Vec<f64>| match name {
+        "x" => Some(0.5),
+        "k" => Some(1.5),
+        "a" => Some(2.5),
+        "b" => Some(3.5),
+        "c" => Some(4.5),
+        _ => None,
+    };
+    for (shape, expr_str) in SHAPES {
+        let mut slab = Slab::new();
+        let instr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut ns);
+
+        c.bench_function(&format!("shape_{}_method_1000x", shape), |b| {
+            b.iter(|| {
+                for _ in 0..1000 {
+                    black_box(black_box(&instr).eval(&slab, &mut ns).unwrap());
+                }
+            })
+        });
+        c.bench_function(&format!("shape_{}_macro_1000x", shape), |b| {
+            b.iter(|| {
+                for _ in 0..1000 {
+                    let instr = black_box(&instr);
+                    let val = (|| -> Result<f64, fasteval2::Error> {
+                        Ok(eval_compiled_ref!(instr, &slab, &mut ns))
+                    })();
+                    black_box(val.unwrap());
+                }
+            })
+        });
+    }
+}
+
+// Identifiers are stored inline in the parse slab, so parsing them doesn't
+// allocate:
+fn parse_20_vars(c: &mut Criterion) {
+    let mut slab = Slab::new();
+    let parser = Parser::new();
+    parser.parse(VARS_20, &mut slab.ps).unwrap();
+
+    let n = allocs_per_call(1000, || {
+        black_box(parser.parse(VARS_20, &mut slab.ps).unwrap());
+    });
+    println!("parse_20_vars: {} allocations per parse", n);
+
+    c.bench_function("parse_20_vars", |b| {
+        b.iter(|| black_box(parser.parse(black_box(VARS_20), &mut slab.ps).unwrap()))
+    });
+}
+
+criterion_group!(
+    benches,
+    eval_30_terms,
+    short_batch,
+    eval_compiled_shapes,
+    lookups_20_vars,
+    parse_20_vars
+);
+criterion_main!(benches);
@@ -2,6 +2,7 @@
 //!
 //! Example Session:
 //!
+//! ```text
 //! github.com/fasteval$ rlwrap cargo run --release --example repl
 //!     Finished release [optimized] target(s) in 0.01s
 //!      Running `target/release/examples/repl`
@@ -39,6 +40,7 @@
 //! >>> 1+2*3/4^5%6 + print("log(100K) =",log(100K)) + log(e(),100) + [3*(3-3)/3] + (2<3) && 1.23
 //! log(100K) = 5
 //! 1.23
+//! ```
 
 use fasteval2::Evaler; // Import this trait for '.eval()' functionality.
 use fasteval2::{Parser, Slab};
@@ -68,7 +70,7 @@ fn repl() {
             None => break,
         };
         let mut line = line.trim().to_string();
-        if line == "" {
+        if line.is_empty() {
             continue;
         }
 
@@ -85,7 +87,7 @@ fn repl() {
             eprintln!("Entered scope[{}]", ns_stack.len() - 1);
             continue;
         } else if pieces[0] == "pop" {
-            let mut return_value = std::f64::NAN;
+            let mut return_value = f64::NAN;
             let mut has_return_value = false;
             if let Some(v) = ns_stack.last().unwrap().get(&ans_key) {
                 return_value = *v;
@@ -26,7 +26,7 @@ fn main() -> Result<(), fasteval2::Error> {
         unsafe { slab.ps.add_unsafe_var("deg".to_string(), &deg); } // `add_unsafe_var()` only exists if the `unsafe-vars` feature is enabled: `cargo test --features unsafe-vars`
 
         let expr_str = "sin(deg/360 * 2*pi())";
-        let compiled = parser.parse(expr_str, &mut slab.ps)?.from(&slab.ps).compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        let compiled = parser.parse(expr_str, &mut slab.ps)?.from(&slab.ps).compile(&slab.ps, &mut slab.cs, &mut fasteval2::EmptyNamespace);
 
         let mut ns = fasteval2::EmptyNamespace;  // We only define unsafe variables, not normal variables,
                                                 // so EmptyNamespace is fine.
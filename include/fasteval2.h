@@ -0,0 +1,100 @@
+#ifndef FASTEVAL2_H
+#define FASTEVAL2_H
+
+/* Generated by cbindgen from src/ffi.rs.  Do not edit. */
+
+#include <stddef.h>
+
+// A parsed and compiled formula, created by `fe_parse()` and destroyed by
+// `fe_expr_free()`.
+//
+// C only sees it behind a pointer.  It can be evaluated from several threads
+// at the same time.
+typedef struct FeExpr FeExpr;
+
+// The result of an `fe_*` function.  `FE_OK` is zero, errors are positive.
+typedef int FeStatus;
+
+// A variable for `fe_eval()`: a NUL-terminated name and its value.
+typedef struct FeVar {
+  const char *name;
+  double value;
+} FeVar;
+
+// Success.
+#define FE_OK 0
+
+// A required pointer argument was null.
+#define FE_NULL_POINTER 1
+
+// A string argument was not valid UTF-8.
+#define FE_INVALID_UTF8 2
+
+// The formula could not be parsed or compiled.
+#define FE_PARSE_ERROR 3
+
+// The formula could not be evaluated, for example because a variable is missing.
+#define FE_EVAL_ERROR 4
+
+// The crate panicked.  The panic was caught, but its message is only
+// available through `fe_error_message()`.
+#define FE_PANIC 5
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parses and compiles the NUL-terminated formula `src`, and stores a new
+// `FeExpr` in `*out`.
+//
+// `src` is only borrowed during the call.  On success the caller owns
+// `*out`, and must release it with `fe_expr_free()`.  On failure `*out` is
+// set to null, unless `out` itself is null.
+//
+// # Safety
+// `src` must be null or point to a NUL-terminated string, and `out` must be
+// null or valid for writing a pointer.
+FeStatus fe_parse(const char *src, struct FeExpr **out);
+
+// Evaluates `expr` with the `n` variables in `vars`, and stores the result
+// in `*out`.
+//
+// Everything is only borrowed during the call; the caller keeps ownership of
+// `expr`, `vars` and the names.  `vars` may be null when `n` is zero.  If a
+// name appears more than once, the first one wins.  Formulas can use the
+// built-in functions, but not custom ones.  `*out` is left unchanged on
+// failure.
+//
+// # Safety
+// `expr` must be null or come from `fe_parse()` and not be freed yet.
+// `vars` must be null or point to `n` `FeVar`s, whose names are null or
+// NUL-terminated strings.  `out` must be null or valid for writing a `double`.
+FeStatus fe_eval(const struct FeExpr *expr, const struct FeVar *vars, size_t n, double *out);
+
+// Destroys an `FeExpr` from `fe_parse()`.  Passing null does nothing.
+//
+// # Safety
+// `expr` must be null or come from `fe_parse()`, and must not be used (or
+// freed) again afterwards.
+void fe_expr_free(struct FeExpr *expr);
+
+// Writes a NUL-terminated description of `status` into `buf`, and returns
+// the length of the whole description in bytes, without the NUL (like
+// `snprintf()`).
+//
+// If `status` is the status of the last failed `fe_*` call on this thread,
+// the description includes the details of that failure, like the name of a
+// missing variable.  At most `len` bytes are written, so the message is cut
+// off (at a UTF-8 character boundary) when the return value is `len` or more.
+// With a null `buf` or a `len` of zero nothing is written, which can be used
+// to find out the size that is needed.  The caller owns `buf`.
+//
+// # Safety
+// `buf` must be null or valid for writing `len` bytes.
+size_t fe_error_message(FeStatus status, char *buf, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FASTEVAL2_H */
@@ -0,0 +1,1136 @@
+//! A compact, versioned binary encoding of compiled expressions.
+//!
+//! A [`CompiledExpression`](struct.CompiledExpression.html) bundles a compiled
+//! `Instruction` with the `Slab` that it refers to.  It can be converted to
+//! bytes with `to_bytes()` and loaded again with `from_bytes()`, without any
+//! parsing or compiling.  This is useful for embedding pre-compiled
+//! expressions into firmware images or other places where parsing at startup
+//! is undesirable.
+//!
+//! # Format
+//!
+//! The format is stable: a given format version will always be loadable by
+//! later releases of `fasteval`.
+//!
+//! ```text
+//! magic:   b"FEVB"
+//! version: u8                  (currently 1)
+//! code:    token*
+//!
+//! token  := 0x00 f64                  (a constant operand)
+//!         | opcode:u8 immediate*      (an instruction)
+//! f64    := 8 bytes, little-endian IEEE-754
+//! count  := varint                    (unsigned LEB128)
+//! string := count utf8-bytes
+//! bool   := 0x00 | 0x01
+//! ```
+//!
+//! The instruction tree is stored in postfix order (operands before the
+//! instruction that uses them), so the loader is a simple stack machine.
+//! There are no slab indices in the encoding, and the loader does not
+//! recurse, so deeply-nested input can't overflow the call stack.  The loader
+//! validates every opcode, count, string and operand, and it limits the depth
+//! of the instruction tree (because evaluation is recursive), so corrupted input
+//! produces a [`Bytecode`](../error/enum.Error.html#variant.Bytecode) error
+//! rather than a panic.
+//!
+//! A sub-expression that is shared after
+//! [`eliminate_common_subexpressions()`](../compiler/enum.Instruction.html#method.eliminate_common_subexpressions)
+//! is written out at every use, but the loaded expression still evaluates it
+//! only once.
+//!
+//! On real-time threads,
+//! [`eval_with_stack()`](struct.CompiledExpression.html#method.eval_with_stack)
+//! evaluates with a caller-provided working stack and doesn't allocate.
+//! [`reduce_rows()`](struct.CompiledExpression.html#method.reduce_rows)
+//! evaluates once per row of data and folds the results, like a spreadsheet
+//! `SUMPRODUCT`.
+//! [`eval_incremental()`](struct.CompiledExpression.html#method.eval_incremental)
+//! re-evaluates only the parts of the expression that depend on the variables
+//! which changed since the previous evaluation.
+//! [`bind()`](struct.CompiledExpression.html#method.bind) resolves the
+//! variables to [`Symbol`](../symbol/struct.Symbol.html)s once, so that they
+//! are read from a slice instead of being looked up by name.
+//!
+//! `print()` calls, error placeholders from
+//! [`parse_with_recovery()`](../parser/struct.Parser.html#method.parse_with_recovery)
+//! and Unsafe Variables can't be encoded.
+//!
+//! # Examples
+//!
+//! ```
+//! use fasteval2::{CompiledExpression, EmptyNamespace};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let compiled = CompiledExpression::compile("sin(x) * 2", &mut EmptyNamespace)?;
+//!     let bytes = compiled.to_bytes()?;
+//!
+//!     // ...later, possibly on another machine:
+//!     let loaded = CompiledExpression::from_bytes(&bytes)?;
+//!     let mut ns = |name: &str, _: Vec<f64>| if name == "x" { Some(0.0) } else { None };
+//!     assert_eq!(loaded.eval(&mut ns)?, 0.0);
+//!     Ok(())
+//! }
+//! ```
+
+#[cfg(feature = "unsafe-vars")]
+use crate::compiler::Instruction::IUnsafeVar;
+use crate::compiler::{
+    Compiler,
+    Instruction::{
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    },
+    InstructionI, IC,
+};
+use crate::disasm::{disassemble, InstructionView};
+use crate::error::Error;
+use crate::evaler::Evaler;
+#[cfg(feature = "std")]
+use crate::evalns::{is_impure, CacheNamespace};
+use crate::evalns::{EvalNamespace, StackNamespace};
+use crate::parser::{Parser, DEFAULT_EXPR_LEN_LIMIT};
+use crate::slab::{CompileSlab, Slab};
+
+#[cfg(feature = "std")]
+use alloc::{boxed::Box, collections::BTreeSet};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+#[cfg(feature = "std")]
+use core::mem;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+
+const MAGIC: &[u8; 4] = b"FEVB";
+
+/// The current version of the bytecode format, written by `to_bytes()`.
+pub const BYTECODE_VERSION: u8 = 1;
+
+// Opcodes.  Never change or re-use these values; only add new ones.
+const OP_CONST: u8 = 1;
+const OP_NEG: u8 = 2;
+const OP_NOT: u8 = 3;
+const OP_INV: u8 = 4;
+const OP_ADD: u8 = 5;
+const OP_MUL: u8 = 6;
+const OP_MOD: u8 = 7;
+const OP_EXP: u8 = 8;
+const OP_LT: u8 = 9;
+const OP_LTE: u8 = 10;
+const OP_EQ: u8 = 11;
+const OP_NE: u8 = 12;
+const OP_GTE: u8 = 13;
+const OP_GT: u8 = 14;
+const OP_OR: u8 = 15;
+const OP_AND: u8 = 16;
+const OP_VAR: u8 = 17;
+const OP_FUNC: u8 = 18;
+const OP_INT: u8 = 19;
+const OP_CEIL: u8 = 20;
+const OP_FLOOR: u8 = 21;
+const OP_ABS: u8 = 22;
+const OP_SIGN: u8 = 23;
+const OP_LOG: u8 = 24;
+const OP_ROUND: u8 = 25;
+const OP_MIN: u8 = 26;
+const OP_MAX: u8 = 27;
+const OP_PIECEWISE: u8 = 28;
+const OP_SUM: u8 = 29;
+const OP_INTERP: u8 = 30;
+const OP_SIN: u8 = 31;
+const OP_COS: u8 = 32;
+const OP_TAN: u8 = 33;
+const OP_ASIN: u8 = 34;
+const OP_ACOS: u8 = 35;
+const OP_ATAN: u8 = 36;
+const OP_SINH: u8 = 37;
+const OP_COSH: u8 = 38;
+const OP_TANH: u8 = 39;
+const OP_ASINH: u8 = 40;
+const OP_ACOSH: u8 = 41;
+const OP_ATANH: u8 = 42;
+const OP_SQRT: u8 = 43;
+const OP_FMA: u8 = 44;
+const OP_TEMPS: u8 = 45;
+const OP_TEMP: u8 = 46;
+const OP_COALESCE: u8 = 47;
+const OP_NORM: u8 = 48;
+const OP_LET: u8 = 49;
+const OP_QUOTIENT: u8 = 50;
+const OP_IN: u8 = 51;
+
+const TOK_CONST: u8 = 0; // A constant operand, `IC::C`.
+
+// Evaluation and encoding don't recurse, but the compiler's passes (like
+// `eliminate_common_subexpressions()`) and the JIT do, so the loader rejects
+// trees that are too deep.  No expression accepted by the default Parser
+// compiles deeper than this:
+const MAX_DEPTH: usize = DEFAULT_EXPR_LEN_LIMIT;
+
+/// A compiled `Instruction`, together with the `Slab` that it refers to.
+///
+/// [See the `bytecode module` documentation.](index.html)
+#[derive(Debug)]
+pub struct CompiledExpression {
+    slab: Slab,
+    instr: Instruction,
+    #[cfg(feature = "std")]
+    cache: Option<Box<IncrementalCache>>,
+}
+
+/// The state of `eval_incremental()`: a copy of the expression in which the
+/// result of every sub-instruction is kept in a temporary slot.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct IncrementalCache {
+    slab: Slab,
+    root: InstructionI,
+    /// The first slot, after the ones that are used by `ITemps`.
+    base: usize,
+    /// The names that each slot depends on, or `None` if it must always be recomputed.
+    deps: Vec<Option<BTreeSet<String>>>,
+    vals: Vec<Option<f64>>,
+}
+
+#[cfg(feature = "std")]
+impl IncrementalCache {
+    fn new(compiled: &CompiledExpression) -> Result<Self, Error> {
+        let (mut slab, root, base) = compiled.copy_into_slab()?;
+
+        // Move every Instruction to the end of the slab and leave an ITemp in its
+        // place, so everything that refers to it goes through the cache:
+        let mut deps = Vec::new();
+        for i in 0..slab.cs.instrs.len() {
+            let instr = slab.cs.get_instr(InstructionI(i));
+            if matches!(instr, IConst(_) | IVar(_) | ITemp { .. }) {
+                continue;
+            }
+            let names = instr.var_names(&slab);
+            let slot = base + deps.len();
+            deps.push((!names.iter().any(|name| is_impure(name))).then_some(names));
+
+            let moved_i = InstructionI(slab.cs.instrs.len());
+            if let Some(instr) = slab.cs.instrs_mut().get_mut(i) {
+                let moved = mem::replace(
+                    instr,
+                    ITemp {
+                        slot,
+                        instr: moved_i,
+                    },
+                );
+                slab.cs.push_instr(moved);
+            }
+        }
+
+        Ok(Self {
+            slab,
+            root,
+            base,
+            vals: vec![None; deps.len()],
+            deps,
+        })
+    }
+}
+
+impl CompiledExpression {
+    /// Bundles a compiled `Instruction` with its `Slab`.
+    ///
+    /// Returns a `Bytecode` error if the `Instruction` contains something that
+    /// can't be encoded, like a `print()` call.
+    pub fn new(instr: Instruction, slab: Slab) -> Result<Self, Error> {
+        encode_instr(&mut Vec::new(), &instr, &slab.cs)?;
+        Ok(Self {
+            slab,
+            instr,
+            #[cfg(feature = "std")]
+            cache: None,
+        })
+    }
+
+    // Copies the expression, with the top-level Instruction pushed into the
+    // copied slab.  Also returns the first temporary slot after the ones that
+    // are used by `ITemps`, which is free for other uses.
+    pub(crate) fn copy_into_slab(&self) -> Result<(Slab, InstructionI, usize), Error> {
+        // Instructions can't be cloned, so copy them through the encoding:
+        let CompiledExpression {
+            mut slab, instr, ..
+        } = CompiledExpression::from_bytes(&self.to_bytes()?)?;
+        let root = slab.cs.push_instr(instr);
+        let base = slab
+            .cs
+            .instrs
+            .iter()
+            .map(|instr| match instr {
+                ITemps { slots, .. } => *slots,
+                _ => 0,
+            })
+            .sum();
+        Ok((slab, root, base))
+    }
+
+    /// Parses and compiles `expr_str`.
+    ///
+    /// `ns` is only used for constant-folding during compilation.
+    pub fn compile(expr_str: &str, ns: &mut impl EvalNamespace) -> Result<Self, Error> {
+        let mut slab = Slab::new();
+        let instr = Parser::new()
+            .parse(expr_str, &mut slab.ps)?
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, ns);
+        Self::new(instr, slab)
+    }
+
+    /// Evaluates the compiled expression.
+    #[inline]
+    pub fn eval(&self, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
+        self.instr.eval(&self.slab, ns)
+    }
+
+    /// Evaluates the compiled expression, using `stack` as working space
+    /// instead of allocating.
+    ///
+    /// `stack` is cleared first.  If its capacity is at least
+    /// [`stack_depth()`](#method.stack_depth), a successful evaluation never
+    /// allocates, so one buffer can be sized up front and reused on a
+    /// real-time thread.  A smaller buffer also works, but it grows as needed.
+    ///
+    /// Custom functions with arguments still allocate, because
+    /// [`EvalNamespace::lookup()`](../evalns/trait.EvalNamespace.html#tymethod.lookup)
+    /// takes the arguments as a `Vec`.  What the Namespace itself does is up to
+    /// the Namespace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fasteval2::{CompiledExpression, EmptyNamespace, EvalNS};
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let compiled = CompiledExpression::compile("interp(x, 0,0, 10,100) + 1", &mut EmptyNamespace)?;
+    ///     let mut stack = Vec::with_capacity(compiled.stack_depth());
+    ///
+    ///     let mut ns = EvalNS::new(|name: &str, _: Vec<f64>| if name == "x" { Some(2.5) } else { None });
+    ///     assert_eq!(compiled.eval_with_stack(&mut stack, &mut ns)?, 26.0);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn eval_with_stack(
+        &self,
+        stack: &mut Vec<f64>,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<f64, Error> {
+        stack.clear();
+        let mut stack_ns = StackNamespace { stack, inner: ns };
+        self.instr.eval(&self.slab, &mut stack_ns)
+    }
+
+    /// Evaluates the compiled expression, reusing the results from the previous
+    /// call for the parts that don't depend on any of the `changed` names.
+    ///
+    /// `changed` must contain every variable and custom function whose value
+    /// changed since the previous call.  The first call evaluates everything
+    /// and builds the cache, which keeps the result of every sub-expression.
+    /// `rand()`, `randint()` and `now()` are called every time.  Only available
+    /// with the `std` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fasteval2::{CompiledExpression, EmptyNamespace};
+    /// use std::collections::{BTreeMap, HashSet};
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let mut compiled = CompiledExpression::compile("sin(y) * 2 + x", &mut EmptyNamespace)?;
+    ///     let mut ns = BTreeMap::from([("x".to_string(), 1.0), ("y".to_string(), 0.0)]);
+    ///     assert_eq!(compiled.eval_incremental(&HashSet::new(), &mut ns)?, 1.0);
+    ///
+    ///     // Only `x` changed, so `sin(y) * 2` is not calculated again:
+    ///     ns.insert("x".to_string(), 5.0);
+    ///     let changed = HashSet::from(["x".to_string()]);
+    ///     assert_eq!(compiled.eval_incremental(&changed, &mut ns)?, 5.0);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn eval_incremental(
+        &mut self,
+        changed: &HashSet<String>,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<f64, Error> {
+        if self.cache.is_none() {
+            self.cache = Some(Box::new(IncrementalCache::new(self)?));
+        }
+        let IncrementalCache {
+            slab,
+            root,
+            base,
+            deps,
+            vals,
+        } = match self.cache.as_deref_mut() {
+            Some(cache) => cache,
+            None => return Err(Error::Unreachable),
+        };
+
+        for (val, deps) in vals.iter_mut().zip(deps.iter()) {
+            if deps
+                .as_ref()
+                .is_none_or(|deps| deps.iter().any(|name| changed.contains(name)))
+            {
+                *val = None;
+            }
+        }
+        let mut cache_ns = CacheNamespace {
+            vals,
+            base: *base,
+            inner: ns,
+        };
+        slab.cs.get_instr(*root).eval(slab, &mut cache_ns)
+    }
+
+    /// Evaluates the compiled expression once per row, and folds the results
+    /// together with `combine`, starting from `init`.
+    ///
+    /// Each row provides the values of `var_names`, in the same order.  The
+    /// expression may also use the named constants like `phi`, but any other
+    /// name is undefined.  A row with fewer values than `var_names` is an
+    /// `Expected` error.  The working stack is reused between rows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fasteval2::{CompiledExpression, EmptyNamespace};
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let compiled = CompiledExpression::compile("price * qty", &mut EmptyNamespace)?;
+    ///     let rows = [2.0, 3.0, 10.0, 1.0, 0.5, 8.0];
+    ///
+    ///     let total = compiled.reduce_rows(&["price", "qty"], rows.chunks(2), 0.0, |a, b| a + b)?;
+    ///     assert_eq!(total, 20.0);
+    ///     let largest = compiled.reduce_rows(&["price", "qty"], rows.chunks(2), f64::MIN, f64::max)?;
+    ///     assert_eq!(largest, 10.0);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn reduce_rows<'r>(
+        &self,
+        var_names: &[&str],
+        rows: impl IntoIterator<Item = &'r [f64]>,
+        init: f64,
+        mut combine: impl FnMut(f64, f64) -> f64,
+    ) -> Result<f64, Error> {
+        let mut stack = Vec::with_capacity(self.stack_depth());
+        let mut acc = init;
+        for (i, row) in rows.into_iter().enumerate() {
+            if row.len() < var_names.len() {
+                return Err(Error::Expected(format!(
+                    "{} values in row {}, got {}",
+                    var_names.len(),
+                    i,
+                    row.len()
+                )));
+            }
+            let mut ns = |name: &str, _args: Vec<f64>| {
+                var_names.iter().position(|v| *v == name).map(|k| row[k])
+            };
+            acc = combine(acc, self.eval_with_stack(&mut stack, &mut ns)?);
+        }
+        Ok(acc)
+    }
+
+    /// Like [`reduce_rows()`](#method.reduce_rows), but adds up the results.
+    pub fn sum_rows<'r>(
+        &self,
+        var_names: &[&str],
+        rows: impl IntoIterator<Item = &'r [f64]>,
+    ) -> Result<f64, Error> {
+        self.reduce_rows(var_names, rows, 0.0, |a, b| a + b)
+    }
+
+    /// Returns the minimum capacity of the working stack for
+    /// [`eval_with_stack()`](#method.eval_with_stack).
+    ///
+    /// Only `interp()` breakpoints and the temporaries of
+    /// [`eliminate_common_subexpressions()`](../compiler/enum.Instruction.html#method.eliminate_common_subexpressions)
+    /// are kept on the stack, so this is often 0.  It is always 0 for deeply
+    /// nested expressions, which are evaluated without recursion, on the heap.
+    pub fn stack_depth(&self) -> usize {
+        self.instr.stack_depth(&self.slab)
+    }
+
+    /// Lists the instructions that the expression compiled into, in postfix
+    /// order.
+    ///
+    /// [See the `disasm module` documentation.](../disasm/index.html)
+    pub fn instructions(&self) -> impl Iterator<Item = InstructionView<'_>> {
+        disassemble(&self.instr, &self.slab.cs).into_iter()
+    }
+
+    /// Returns the top-level `Instruction`, for use with
+    /// [`eval_compiled_ref!()`](../macro.eval_compiled_ref.html).
+    #[inline]
+    pub fn instr(&self) -> &Instruction {
+        &self.instr
+    }
+
+    /// Returns the `Slab` that the `Instruction` refers to.
+    #[inline]
+    pub fn slab(&self) -> &Slab {
+        &self.slab
+    }
+
+    /// Encodes the compiled expression.  [See the format description.](index.html#format)
+    ///
+    /// Returns a `Bytecode` error if the `Instruction` contains something that
+    /// can't be encoded, like `new()` does.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(MAGIC);
+        out.push(BYTECODE_VERSION);
+        encode_instr(&mut out, &self.instr, &self.slab.cs)?;
+        Ok(out)
+    }
+
+    /// Loads a compiled expression that was encoded with `to_bytes()`.
+    ///
+    /// Returns a `Bytecode` error if the input is not valid.
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader { bs };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(Error::Bytecode("bad magic header".to_string()));
+        }
+        let version = r.u8()?;
+        if version != BYTECODE_VERSION {
+            return Err(Error::Bytecode(format!(
+                "unsupported format version {}",
+                version
+            )));
+        }
+
+        let mut slab = Slab::new();
+        let instr = r.code(&mut slab.cs)?;
+        Ok(Self {
+            slab,
+            instr,
+            #[cfg(feature = "std")]
+            cache: None,
+        })
+    }
+}
+
+/// A disassembly listing, with one instruction per line.
+///
+/// [See the `disasm module` documentation.](../disasm/index.html)
+impl fmt::Display for CompiledExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, view) in self.instructions().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", view)?;
+        }
+        Ok(())
+    }
+}
+
+//---- Encoding:
+
+fn encode_varint(out: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+fn encode_str(out: &mut Vec<u8>, s: &str) {
+    encode_varint(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+fn encode_f64(out: &mut Vec<u8>, x: f64) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+// An entry of the encoder's work stack:
+enum Pending<'a> {
+    Ic(&'a IC),
+    Instr(&'a Instruction),
+    // The operands of the Instruction are written; its opcode is next.
+    Op(&'a Instruction),
+}
+
+// Writes `instr` in postfix order.  It uses an explicit stack, so that deep
+// trees can't overflow the native one.
+fn encode_instr(out: &mut Vec<u8>, instr: &Instruction, cs: &CompileSlab) -> Result<(), Error> {
+    let mut work = vec![Pending::Instr(instr)];
+    while let Some(pending) = work.pop() {
+        match pending {
+            Pending::Ic(IC::C(c)) => {
+                out.push(TOK_CONST);
+                encode_f64(out, *c);
+            }
+            Pending::Ic(IC::I(i)) => work.push(Pending::Instr(get_instr!(cs, i))),
+            Pending::Instr(instr) => {
+                work.push(Pending::Op(instr));
+                let start = work.len();
+                push_operands(&mut work, instr, cs);
+                work[start..].reverse();
+            }
+            Pending::Op(instr) => encode_op(out, instr)?,
+        }
+    }
+    Ok(())
+}
+
+// Pushes the operands of `instr`, from left to right:
+fn push_operands<'a>(work: &mut Vec<Pending<'a>>, instr: &'a Instruction, cs: &'a CompileSlab) {
+    let child = |i: &InstructionI| Pending::Instr(get_instr!(cs, i));
+    match instr {
+        IConst(_) | IVar(_) | IPrintFunc(_) | IError(_) => (),
+        #[cfg(feature = "unsafe-vars")]
+        IUnsafeVar { .. } => (),
+
+        INeg(i)
+        | INot(i)
+        | IInv(i)
+        | IFuncInt(i)
+        | IFuncCeil(i)
+        | IFuncFloor(i)
+        | IFuncAbs(i)
+        | IFuncSign(i)
+        | IFuncSin(i)
+        | IFuncCos(i)
+        | IFuncTan(i)
+        | IFuncASin(i)
+        | IFuncACos(i)
+        | IFuncATan(i)
+        | IFuncSinH(i)
+        | IFuncCosH(i)
+        | IFuncTanH(i)
+        | IFuncASinH(i)
+        | IFuncACosH(i)
+        | IFuncATanH(i)
+        | IFuncSqrt(i)
+        | ITemps { body: i, .. }
+        | ITemp { instr: i, .. } => work.push(child(i)),
+
+        IAdd(i, ic)
+        | IMul(i, ic)
+        | IOR(i, ic)
+        | IAND(i, ic)
+        | IFuncMin(i, ic)
+        | IFuncMax(i, ic) => {
+            work.push(child(i));
+            work.push(Pending::Ic(ic));
+        }
+        IMod {
+            dividend: a,
+            divisor: b,
+        }
+        | IExp { base: a, power: b }
+        | ILT(a, b)
+        | ILTE(a, b)
+        | IEQ(a, b)
+        | INE(a, b)
+        | IGTE(a, b)
+        | IGT(a, b)
+        | IFuncLog { base: a, of: b }
+        | IFuncRound { modulus: a, of: b }
+        | IFuncCoalesce {
+            value: a,
+            fallback: b,
+        }
+        | IFuncLet {
+            value: a, expr: b, ..
+        }
+        | IFuncQuotient {
+            dividend: a,
+            divisor: b,
+        } => work.extend([a, b].map(Pending::Ic)),
+
+        IFunc { args, .. } | IFuncNorm { args, .. } => work.extend(args.iter().map(Pending::Ic)),
+        IFuncIn { value, list, .. } => {
+            work.push(Pending::Ic(value));
+            work.extend(list.iter().map(Pending::Ic));
+        }
+        IFuncPiecewise { branches, default } => {
+            for (cond, val) in branches {
+                work.extend([cond, val].map(Pending::Ic));
+            }
+            work.push(Pending::Ic(default));
+        }
+        IFuncSum { from, to, expr, .. } => work.extend([from, to, expr].map(Pending::Ic)),
+        IFuncInterp { x, points, .. } => {
+            work.push(Pending::Ic(x));
+            for (px, py) in points {
+                work.extend([px, py].map(Pending::Ic));
+            }
+        }
+        IFuncFma { a, b, c } => work.extend([a, b, c].map(Pending::Ic)),
+    }
+}
+
+// Writes the opcode and the immediates of `instr`, after its operands:
+fn encode_op(out: &mut Vec<u8>, instr: &Instruction) -> Result<(), Error> {
+    let op = match instr {
+        IConst(c) => {
+            out.push(OP_CONST);
+            encode_f64(out, *c);
+            return Ok(());
+        }
+
+        INeg(_) => OP_NEG,
+        INot(_) => OP_NOT,
+        IInv(_) => OP_INV,
+
+        IAdd(..) => OP_ADD,
+        IMul(..) => OP_MUL,
+        IMod { .. } => OP_MOD,
+        IExp { .. } => OP_EXP,
+
+        ILT(..) => OP_LT,
+        ILTE(..) => OP_LTE,
+        IEQ(..) => OP_EQ,
+        INE(..) => OP_NE,
+        IGTE(..) => OP_GTE,
+        IGT(..) => OP_GT,
+
+        IOR(..) => OP_OR,
+        IAND(..) => OP_AND,
+
+        IVar(name) => {
+            out.push(OP_VAR);
+            encode_str(out, name);
+            return Ok(());
+        }
+        IFunc { name, args } => {
+            out.push(OP_FUNC);
+            encode_str(out, name);
+            encode_varint(out, args.len());
+            return Ok(());
+        }
+
+        IFuncInt(_) => OP_INT,
+        IFuncCeil(_) => OP_CEIL,
+        IFuncFloor(_) => OP_FLOOR,
+        IFuncAbs(_) => OP_ABS,
+        IFuncSign(_) => OP_SIGN,
+        IFuncLog { .. } => OP_LOG,
+        IFuncRound { .. } => OP_ROUND,
+        IFuncMin(..) => OP_MIN,
+        IFuncMax(..) => OP_MAX,
+        IFuncPiecewise { branches, .. } => {
+            out.push(OP_PIECEWISE);
+            encode_varint(out, branches.len());
+            return Ok(());
+        }
+        IFuncSum { var, .. } => {
+            out.push(OP_SUM);
+            encode_str(out, var);
+            return Ok(());
+        }
+        IFuncInterp {
+            extrapolate,
+            points,
+            ..
+        } => {
+            out.push(OP_INTERP);
+            out.push(*extrapolate as u8);
+            encode_varint(out, points.len());
+            return Ok(());
+        }
+
+        IFuncSin(_) => OP_SIN,
+        IFuncCos(_) => OP_COS,
+        IFuncTan(_) => OP_TAN,
+        IFuncASin(_) => OP_ASIN,
+        IFuncACos(_) => OP_ACOS,
+        IFuncATan(_) => OP_ATAN,
+        IFuncSinH(_) => OP_SINH,
+        IFuncCosH(_) => OP_COSH,
+        IFuncTanH(_) => OP_TANH,
+        IFuncASinH(_) => OP_ASINH,
+        IFuncACosH(_) => OP_ACOSH,
+        IFuncATanH(_) => OP_ATANH,
+        IFuncSqrt(_) => OP_SQRT,
+        IFuncFma { .. } => OP_FMA,
+        IFuncCoalesce { .. } => OP_COALESCE,
+        IFuncQuotient { .. } => OP_QUOTIENT,
+        IFuncNorm { squared, args } => {
+            out.push(OP_NORM);
+            out.push(*squared as u8);
+            encode_varint(out, args.len());
+            return Ok(());
+        }
+        IFuncIn { negated, list, .. } => {
+            out.push(OP_IN);
+            out.push(*negated as u8);
+            encode_varint(out, list.len());
+            return Ok(());
+        }
+        IFuncLet { var, .. } => {
+            out.push(OP_LET);
+            encode_str(out, var);
+            return Ok(());
+        }
+        ITemps { slots, .. } => {
+            out.push(OP_TEMPS);
+            encode_varint(out, *slots);
+            return Ok(());
+        }
+        ITemp { slot, .. } => {
+            out.push(OP_TEMP);
+            encode_varint(out, *slot);
+            return Ok(());
+        }
+
+        IPrintFunc(_) => return Err(Error::Bytecode("print() can't be encoded".to_string())),
+        IError(err) => {
+            return Err(Error::Bytecode(format!(
+                "error placeholders can't be encoded: {}",
+                err
+            )))
+        }
+        #[cfg(feature = "unsafe-vars")]
+        IUnsafeVar { name, .. } => {
+            return Err(Error::Bytecode(format!(
+                "unsafe variable '{}' can't be encoded",
+                name
+            )))
+        }
+    };
+    out.push(op);
+    Ok(())
+}
+
+//---- Decoding:
+
+// An entry of the loader's operand stack:
+enum Operand {
+    Const(f64),
+    Instr(Instruction, usize), // (instr, depth)
+}
+
+struct Reader<'a> {
+    bs: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.bs.len() {
+            return Err(Error::Bytecode("unexpected end of input".to_string()));
+        }
+        let (head, tail) = self.bs.split_at(n);
+        self.bs = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+    fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(Error::Bytecode(format!("invalid bool {}", b))),
+        }
+    }
+    fn f64(&mut self) -> Result<f64, Error> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(buf))
+    }
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            n |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return usize::try_from(n)
+                    .map_err(|_| Error::Bytecode("varint too large".to_string()));
+            }
+        }
+        Err(Error::Bytecode("varint too long".to_string()))
+    }
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.varint()?;
+        match core::str::from_utf8(self.take(len)?) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => Err(Error::Bytecode("invalid utf8 in string".to_string())),
+        }
+    }
+
+    // Runs the stack machine, and returns the root Instruction:
+    fn code(&mut self, cs: &mut CompileSlab) -> Result<Instruction, Error> {
+        let mut stack = Vec::<Operand>::with_capacity(16);
+        while !self.bs.is_empty() {
+            let op = self.u8()?;
+            let operand = match op {
+                TOK_CONST => Operand::Const(self.f64()?),
+                OP_CONST => Operand::Instr(IConst(self.f64()?), 1),
+                OP_VAR => Operand::Instr(IVar(self.string()?), 1),
+                _ => self.instr(op, &mut stack, cs)?,
+            };
+            stack.push(operand);
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(Operand::Instr(instr, _)), true) => Ok(instr),
+            (Some(Operand::Const(_)), true) => Err(Error::Bytecode(
+                "the result must be an instruction".to_string(),
+            )),
+            (None, _) => Err(Error::Bytecode("no instructions".to_string())),
+            (Some(_), false) => Err(Error::Bytecode(format!("{} unused operands", stack.len()))),
+        }
+    }
+
+    // Builds an Instruction from the operands on top of the stack.
+    fn instr(
+        &mut self,
+        op: u8,
+        stack: &mut Vec<Operand>,
+        cs: &mut CompileSlab,
+    ) -> Result<Operand, Error> {
+        // Read the immediates, and find the number of operands that the opcode consumes:
+        let (mut extrapolate, mut squared, mut negated) = (false, false, false);
+        let (n, name, count) = match op {
+            OP_NEG | OP_NOT | OP_INV | OP_INT | OP_CEIL | OP_FLOOR | OP_ABS | OP_SIGN | OP_SIN
+            | OP_COS | OP_TAN | OP_ASIN | OP_ACOS | OP_ATAN | OP_SINH | OP_COSH | OP_TANH
+            | OP_ASINH | OP_ACOSH | OP_ATANH | OP_SQRT => (1, None, 0),
+            OP_ADD | OP_MUL | OP_MOD | OP_EXP | OP_LT | OP_LTE | OP_EQ | OP_NE | OP_GTE | OP_GT
+            | OP_OR | OP_AND | OP_LOG | OP_ROUND | OP_MIN | OP_MAX | OP_COALESCE | OP_QUOTIENT => {
+                (2, None, 0)
+            }
+            OP_FMA => (3, None, 0),
+            OP_TEMPS | OP_TEMP => (1, None, self.varint()?),
+            OP_FUNC => {
+                let name = self.string()?;
+                let count = self.varint()?;
+                (count, Some(name), count)
+            }
+            OP_PIECEWISE => {
+                let count = self.varint()?;
+                (count.saturating_mul(2).saturating_add(1), None, count)
+            }
+            OP_SUM => (3, Some(self.string()?), 0),
+            OP_LET => (2, Some(self.string()?), 0),
+            OP_INTERP => {
+                extrapolate = self.bool()?;
+                let count = self.varint()?;
+                if count < 2 {
+                    return Err(Error::Bytecode(
+                        "interp needs at least two breakpoints".to_string(),
+                    ));
+                }
+                (count.saturating_mul(2).saturating_add(1), None, count)
+            }
+            OP_NORM => {
+                squared = self.bool()?;
+                let count = self.varint()?;
+                if count == 0 {
+                    return Err(Error::Bytecode(
+                        "norm needs at least one argument".to_string(),
+                    ));
+                }
+                (count, None, count)
+            }
+            OP_IN => {
+                negated = self.bool()?;
+                let count = self.varint()?;
+                if count == 0 {
+                    return Err(Error::Bytecode("in needs at least one value".to_string()));
+                }
+                (count.saturating_add(1), None, count)
+            }
+            _ => return Err(Error::Bytecode(format!("unknown opcode {}", op))),
+        };
+        if n > stack.len() {
+            return Err(Error::Bytecode(format!(
+                "opcode {} needs {} operands, but only {} are available",
+                op,
+                n,
+                stack.len()
+            )));
+        }
+        let args_start = stack.len() - n;
+        let mut depth = 1;
+        for arg in &stack[args_start..] {
+            if let Operand::Instr(_, d) = arg {
+                depth = depth.max(d + 1);
+            }
+        }
+        if depth > MAX_DEPTH {
+            return Err(Error::Bytecode("too deep".to_string()));
+        }
+        let mut args = stack.drain(args_start..);
+
+        // Converts the next operand (left-to-right):
+        let mut ic = || -> Result<IC, Error> {
+            match args.next() {
+                Some(Operand::Const(c)) => Ok(IC::C(c)),
+                Some(Operand::Instr(instr, _)) => Ok(IC::I(cs.push_instr(instr))),
+                None => Err(Error::Unreachable),
+            }
+        };
+        macro_rules! instr_i {
+            () => {
+                match ic()? {
+                    IC::I(i) => i,
+                    IC::C(_) => {
+                        return Err(Error::Bytecode(format!(
+                            "opcode {} needs an instruction operand, not a constant",
+                            op
+                        )))
+                    }
+                }
+            };
+        }
+        macro_rules! pairs {
+            ($count:expr) => {{
+                let mut pairs = Vec::with_capacity($count);
+                for _ in 0..$count {
+                    pairs.push((ic()?, ic()?));
+                }
+                pairs
+            }};
+        }
+
+        let instr = match op {
+            OP_NEG => INeg(instr_i!()),
+            OP_NOT => INot(instr_i!()),
+            OP_INV => IInv(instr_i!()),
+
+            OP_ADD => IAdd(instr_i!(), ic()?),
+            OP_MUL => IMul(instr_i!(), ic()?),
+            OP_MOD => IMod {
+                dividend: ic()?,
+                divisor: ic()?,
+            },
+            OP_EXP => IExp {
+                base: ic()?,
+                power: ic()?,
+            },
+
+            OP_LT => ILT(ic()?, ic()?),
+            OP_LTE => ILTE(ic()?, ic()?),
+            OP_EQ => IEQ(ic()?, ic()?),
+            OP_NE => INE(ic()?, ic()?),
+            OP_GTE => IGTE(ic()?, ic()?),
+            OP_GT => IGT(ic()?, ic()?),
+
+            OP_OR => IOR(instr_i!(), ic()?),
+            OP_AND => IAND(instr_i!(), ic()?),
+
+            OP_FUNC => {
+                let mut args = Vec::with_capacity(count);
+                for _ in 0..count {
+                    args.push(ic()?);
+                }
+                IFunc {
+                    name: name.unwrap_or_default(),
+                    args,
+                }
+            }
+
+            OP_INT => IFuncInt(instr_i!()),
+            OP_CEIL => IFuncCeil(instr_i!()),
+            OP_FLOOR => IFuncFloor(instr_i!()),
+            OP_ABS => IFuncAbs(instr_i!()),
+            OP_SIGN => IFuncSign(instr_i!()),
+            OP_LOG => IFuncLog {
+                base: ic()?,
+                of: ic()?,
+            },
+            OP_ROUND => IFuncRound {
+                modulus: ic()?,
+                of: ic()?,
+            },
+            OP_MIN => IFuncMin(instr_i!(), ic()?),
+            OP_MAX => IFuncMax(instr_i!(), ic()?),
+            OP_PIECEWISE => IFuncPiecewise {
+                branches: pairs!(count),
+                default: ic()?,
+            },
+            OP_SUM => IFuncSum {
+                var: name.unwrap_or_default(),
+                from: ic()?,
+                to: ic()?,
+                expr: ic()?,
+            },
+            OP_INTERP => IFuncInterp {
+                extrapolate,
+                x: ic()?,
+                points: pairs!(count),
+            },
+
+            OP_SIN => IFuncSin(instr_i!()),
+            OP_COS => IFuncCos(instr_i!()),
+            OP_TAN => IFuncTan(instr_i!()),
+            OP_ASIN => IFuncASin(instr_i!()),
+            OP_ACOS => IFuncACos(instr_i!()),
+            OP_ATAN => IFuncATan(instr_i!()),
+            OP_SINH => IFuncSinH(instr_i!()),
+            OP_COSH => IFuncCosH(instr_i!()),
+            OP_TANH => IFuncTanH(instr_i!()),
+            OP_ASINH => IFuncASinH(instr_i!()),
+            OP_ACOSH => IFuncACosH(instr_i!()),
+            OP_ATANH => IFuncATanH(instr_i!()),
+            OP_SQRT => IFuncSqrt(instr_i!()),
+            OP_FMA => IFuncFma {
+                a: ic()?,
+                b: ic()?,
+                c: ic()?,
+            },
+            OP_COALESCE => IFuncCoalesce {
+                value: ic()?,
+                fallback: ic()?,
+            },
+            OP_QUOTIENT => IFuncQuotient {
+                dividend: ic()?,
+                divisor: ic()?,
+            },
+            OP_NORM => {
+                let mut args = Vec::with_capacity(count);
+                for _ in 0..count {
+                    args.push(ic()?);
+                }
+                IFuncNorm { squared, args }
+            }
+            OP_IN => {
+                let value = ic()?;
+                let mut list = Vec::with_capacity(count);
+                for _ in 0..count {
+                    list.push(ic()?);
+                }
+                IFuncIn {
+                    negated,
+                    value,
+                    list,
+                }
+            }
+            OP_LET => IFuncLet {
+                var: name.unwrap_or_default(),
+                value: ic()?,
+                expr: ic()?,
+            },
+            OP_TEMPS => ITemps {
+                slots: count,
+                body: instr_i!(),
+            },
+            OP_TEMP => ITemp {
+                slot: count,
+                instr: instr_i!(),
+            },
+
+            _ => return Err(Error::Unreachable),
+        };
+        Ok(Operand::Instr(instr, depth))
+    }
+}
@@ -0,0 +1,337 @@
+//! A least-recently-used cache of compiled expressions, and a cache of the last
+//! result of one expression.
+//!
+//! Servers often receive the same few formula strings over and over.  An
+//! [`ExpressionCache`](struct.ExpressionCache.html) maps each source string to
+//! its [`CompiledExpression`](../bytecode/struct.CompiledExpression.html), so
+//! that a string is only parsed and compiled the first time that it's seen:
+//!
+//! ```
+//! use fasteval2::{ExpressionCache, Parser};
+//! use std::sync::Arc;
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let parser = Parser::new();
+//!     let mut cache = ExpressionCache::new(100);
+//!
+//!     let first = cache.get_or_parse("x * 2 + 1", &parser)?;
+//!     let again = cache.get_or_parse("x * 2 + 1", &parser)?;
+//!     assert!(Arc::ptr_eq(&first, &again));
+//!     assert_eq!((cache.hits(), cache.misses()), (1, 1));
+//!
+//!     let mut ns = |name: &str, _: Vec<f64>| if name == "x" { Some(3.0) } else { None };
+//!     assert_eq!(again.eval(&mut ns)?, 7.0);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The cache hands out `Arc` handles, so a cache hit doesn't copy the
+//! expression, and an evicted expression stays alive for as long as someone
+//! is still evaluating it.  Evaluation only needs a shared reference, so one
+//! handle can be evaluated from many threads at the same time.  The cache
+//! itself needs `&mut self` for every lookup (to keep track of recency), so
+//! share it between threads with a `Mutex`, and release the lock before
+//! evaluating.
+//!
+//! Expressions are compiled with an
+//! [`EmptyNamespace`](../evalns/struct.EmptyNamespace.html), so only the
+//! built-in functions are constant-folded.  Strings that fail to parse or
+//! compile (like `print()` calls, which a `CompiledExpression` can't hold) are
+//! not cached, and they fail again every time.
+//!
+//! With the `unsafe-vars` feature, the handles can't be sent to other
+//! threads, because Unsafe Variables are raw pointers.
+//!
+//! # Caching results
+//!
+//! A [`CachingExpr`](struct.CachingExpr.html) wraps one `CompiledExpression`
+//! whose inputs change rarely.  It remembers the values of the variables and
+//! the result of the last evaluation, and when the variables still have the
+//! same values, it returns that result without evaluating again:
+//!
+//! ```
+//! use fasteval2::{CachingExpr, CompiledExpression, EmptyNamespace};
+//! use std::collections::BTreeMap;
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let compiled = CompiledExpression::compile("sqrt(x^2 + y^2)", &mut EmptyNamespace)?;
+//!     let mut caching = CachingExpr::new(compiled);
+//!     assert_eq!(caching.var_names(), ["x", "y"]);
+//!
+//!     let mut ns = BTreeMap::from([("x".to_string(), 3.0), ("y".to_string(), 4.0)]);
+//!     assert_eq!(caching.eval(&mut ns)?, 5.0);
+//!     assert_eq!(caching.eval(&mut ns)?, 5.0); // Not evaluated again.
+//!     assert_eq!((caching.hits(), caching.misses()), (1, 1));
+//!
+//!     ns.insert("y".to_string(), 0.0);
+//!     assert_eq!(caching.eval(&mut ns)?, 3.0);
+//!     assert_eq!((caching.hits(), caching.misses()), (1, 2));
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Checking the cache still looks up every variable, so this only pays off
+//! for expressions that cost more than their lookups.  Custom functions are
+//! assumed to return the same result for the same arguments, so call
+//! [`invalidate()`](struct.CachingExpr.html#method.invalidate) when one of them
+//! changes.  Expressions that call `rand()`, `randint()` or `now()` are
+//! evaluated every time.
+
+use crate::bytecode::CompiledExpression;
+use crate::compiler::{
+    Compiler,
+    Instruction::{IFunc, IVar},
+};
+use crate::error::Error;
+use crate::evalns::{is_impure, EmptyNamespace, EvalNamespace};
+use crate::parser::Parser;
+use crate::slab::Slab;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A least-recently-used cache of compiled expressions, keyed by source string.
+///
+/// [See the `cache module` documentation.](index.html)
+#[derive(Debug)]
+pub struct ExpressionCache {
+    entries: BTreeMap<String, Entry>,
+    capacity: usize,
+    tick: u64, // Incremented on every lookup.
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    expr: Arc<CompiledExpression>,
+    last_used: u64,
+}
+
+impl ExpressionCache {
+    /// Creates an empty cache that holds at most `capacity` expressions.
+    ///
+    /// A cache with a capacity of 0 doesn't hold anything, so every lookup is
+    /// a miss.
+    pub fn new(capacity: usize) -> Self {
+        ExpressionCache {
+            entries: BTreeMap::new(),
+            capacity,
+            tick: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Returns the cached expression for `src`, or parses and compiles it with
+    /// `parser` and caches the result.
+    ///
+    /// When a new expression is added to a full cache, the least-recently-used
+    /// one is evicted.  Finding it takes a scan over the entries, which is
+    /// cheap next to the parse that a miss already does.
+    #[cfg_attr(feature = "unsafe-vars", allow(clippy::arc_with_non_send_sync))]
+    pub fn get_or_parse(
+        &mut self,
+        src: &str,
+        parser: &Parser,
+    ) -> Result<Arc<CompiledExpression>, Error> {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(src) {
+            entry.last_used = self.tick;
+            self.hits += 1;
+            return Ok(Arc::clone(&entry.expr));
+        }
+        self.misses += 1;
+
+        let mut slab = Slab::new();
+        let instr = parser.parse(src, &mut slab.ps)?.from(&slab.ps).compile(
+            &slab.ps,
+            &mut slab.cs,
+            &mut EmptyNamespace,
+        );
+        let expr = Arc::new(CompiledExpression::new(instr, slab)?);
+        if self.capacity == 0 {
+            return Ok(expr);
+        }
+
+        if self.entries.len() >= self.capacity {
+            let lru = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = lru {
+                self.entries.remove(&key);
+                self.evictions += 1;
+            }
+        }
+        self.entries.insert(
+            src.to_string(),
+            Entry {
+                expr: Arc::clone(&expr),
+                last_used: self.tick,
+            },
+        );
+        Ok(expr)
+    }
+
+    /// Returns `true` if `src` is cached, without counting a lookup.
+    #[inline]
+    pub fn contains(&self, src: &str) -> bool {
+        self.entries.contains_key(src)
+    }
+
+    /// Removes every cached expression.  The counters are kept.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of cached expressions.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the maximum number of cached expressions.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of lookups that found a cached expression.
+    #[inline]
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of lookups that had to parse, including the ones
+    /// that failed.
+    #[inline]
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the number of expressions that were evicted to make room.
+    #[inline]
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
+
+/// A compiled expression that reuses its last result while the values of its
+/// variables don't change.
+///
+/// [See the `cache module` documentation.](index.html#caching-results)
+#[derive(Debug)]
+pub struct CachingExpr {
+    expr: CompiledExpression,
+    var_names: Vec<String>,
+    impure: bool,
+    /// The values of `var_names` and the result of the last evaluation.
+    last: Option<(Vec<Option<f64>>, f64)>,
+    vals: Vec<Option<f64>>, // Reused between calls.
+    keybuf: String,
+    hits: u64,
+    misses: u64,
+}
+
+impl CachingExpr {
+    /// Wraps `expr`, with an empty cache.
+    pub fn new(expr: CompiledExpression) -> Self {
+        let mut var_names = BTreeSet::new();
+        let mut impure = false;
+        let instrs = expr.slab().cs.instrs.iter().chain([expr.instr()]);
+        for instr in instrs {
+            match instr {
+                IVar(name) => {
+                    var_names.insert(name.as_str());
+                }
+                IFunc { name, .. } => impure |= is_impure(name),
+                _ => (),
+            }
+        }
+        let var_names: Vec<String> = var_names.into_iter().map(String::from).collect();
+        CachingExpr {
+            vals: Vec::with_capacity(var_names.len()),
+            var_names,
+            impure,
+            last: None,
+            keybuf: String::new(),
+            hits: 0,
+            misses: 0,
+            expr,
+        }
+    }
+
+    /// Evaluates the expression, or returns the result of the previous call if
+    /// every variable still has the same value in `ns`.
+    ///
+    /// Errors are not cached, so a failed evaluation is tried again next time.
+    pub fn eval(&mut self, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
+        self.vals.clear();
+        for name in &self.var_names {
+            let val = ns.try_lookup(name, Vec::new(), &mut self.keybuf)?;
+            self.vals.push(val);
+        }
+        if let Some((last_vals, result)) = &self.last {
+            // Compare the bits, so that a NaN input is the same as before:
+            let same =
+                |a: &Option<f64>, b: &Option<f64>| a.map(f64::to_bits) == b.map(f64::to_bits);
+            if !self.impure && last_vals.iter().zip(&self.vals).all(|(a, b)| same(a, b)) {
+                self.hits += 1;
+                return Ok(*result);
+            }
+        }
+
+        self.misses += 1;
+        self.last = None;
+        let result = self.expr.eval(ns)?;
+        self.last = Some((self.vals.clone(), result));
+        Ok(result)
+    }
+
+    /// Forgets the cached result, so that the next call evaluates again.
+    ///
+    /// Call this when a custom function that the expression calls has changed.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.last = None;
+    }
+
+    /// Returns the names of the variables whose values are compared, in
+    /// alphabetical order.
+    #[inline]
+    pub fn var_names(&self) -> &[String] {
+        &self.var_names
+    }
+
+    /// Returns the wrapped expression.
+    #[inline]
+    pub fn expr(&self) -> &CompiledExpression {
+        &self.expr
+    }
+
+    /// Returns the number of calls that reused the cached result.
+    #[inline]
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of calls that evaluated the expression, including the
+    /// ones that failed.
+    #[inline]
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
@@ -0,0 +1,383 @@
+//! Converts an `Expression` into a tree of native closures.
+//!
+//! See [`Expression::to_fn()`](../parser/struct.Expression.html#method.to_fn).
+
+#[cfg(feature = "unsafe-vars")]
+use crate::compiler::Instruction::IUnsafeVar;
+use crate::compiler::{
+    coalesce, interp, log, quotient, Compiler,
+    Instruction::{
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    },
+    SumSq, IC,
+};
+use crate::error::Error;
+use crate::evaler::named_constant;
+use crate::evalns::EmptyNamespace;
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+use crate::parser::Expression;
+use crate::slab::{CompileSlab, Slab};
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+type Node = Box<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+impl Expression {
+    /// Converts this `Expression` into a native closure.
+    ///
+    /// Each variable name is resolved to a position in `vars` ahead of time, so
+    /// calling the closure with a slice of values (in the same order) involves
+    /// no string lookups and no Namespace: just arithmetic and slice indexing.
+    /// The closure owns all of its data, so it doesn't borrow `slab`, and it
+    /// can be shared between threads.
+    ///
+    /// Names that aren't listed in `vars` (other than the named constants like
+    /// `phi`) and calls to custom functions produce an `UndefinedVariable` or
+    /// `UndefinedFunction` error here, rather than when the closure is called.
+    /// `print()` and Unsafe Variables can't be converted.
+    ///
+    /// The results are the same as evaluating the compiled `Instruction`.
+    /// The closure returns NaN if it is given fewer values than `vars.len()`,
+    /// or if a built-in function fails (like `interp()` with unsorted
+    /// breakpoints).
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = Parser::new().parse("x^2 + y", &mut slab.ps)?.from(&slab.ps);
+    ///     let f = expr_ref.to_fn(&slab, &["x", "y"])?;
+    ///     assert_eq!(f(&[3.0, 1.0]), 10.0);
+    ///     assert_eq!(f(&[4.0, 0.5]), 16.5);
+    ///
+    ///     assert!(expr_ref.to_fn(&slab, &["x"]).is_err()); // 'y' is missing.
+    ///     Ok(())
+    /// }
+    /// ```
+    #[allow(clippy::type_complexity)] // Spelled out, so the docs show the full type.
+    pub fn to_fn(
+        &self,
+        slab: &Slab,
+        vars: &[&str],
+    ) -> Result<Box<dyn Fn(&[f64]) -> f64 + Send + Sync>, Error> {
+        // Compile into a private CompileSlab, so `slab` is left untouched:
+        let mut cs = CompileSlab {
+            instrs: Vec::new(),
+            def_instr: Default::default(),
+            depth: Default::default(),
+        };
+        let instr = self.compile(&slab.ps, &mut cs, &mut EmptyNamespace);
+        let slots: Vec<String> = vars.iter().map(|v| v.to_string()).collect();
+        let node = build(&instr, &cs, &slots)?;
+        let n = slots.len();
+        Ok(Box::new(move |vals: &[f64]| {
+            if vals.len() < n {
+                return f64::NAN;
+            }
+            node(vals)
+        }))
+    }
+}
+
+fn build_ic(ic: &IC, cs: &CompileSlab, slots: &[String]) -> Result<Node, Error> {
+    match ic {
+        IC::C(c) => {
+            let c = *c;
+            Ok(Box::new(move |_| c))
+        }
+        IC::I(i) => build(cs.get_instr(*i), cs, slots),
+    }
+}
+
+// `slots` are the variable names, by position.  sum() appends its loop
+// variable for its body, so the last matching slot wins.
+fn build(instr: &Instruction, cs: &CompileSlab, slots: &[String]) -> Result<Node, Error> {
+    macro_rules! ii {
+        ($i:expr) => {
+            build(cs.get_instr(*$i), cs, slots)?
+        };
+    }
+    macro_rules! ic {
+        ($ic:expr) => {
+            build_ic($ic, cs, slots)?
+        };
+    }
+    macro_rules! unary {
+        ($f:expr, |$x:ident| $body:expr) => {{
+            let f = $f;
+            Box::new(move |v: &[f64]| {
+                let $x = f(v);
+                $body
+            })
+        }};
+    }
+    macro_rules! binary {
+        ($l:expr, $r:expr, |$a:ident, $b:ident| $body:expr) => {{
+            let (l, r) = ($l, $r);
+            Box::new(move |v: &[f64]| {
+                let ($a, $b) = (l(v), r(v));
+                $body
+            })
+        }};
+    }
+
+    Ok(match instr {
+        IConst(c) => {
+            let c = *c;
+            Box::new(move |_| c)
+        }
+
+        INeg(i) => unary!(ii!(i), |x| -x),
+        INot(i) => unary!(ii!(i), |x| bool_to_f64!(f64_eq!(x, 0.0))),
+        IInv(i) => unary!(ii!(i), |x| 1.0 / x),
+
+        // Constant operands are very common here, so save a call for them:
+        IAdd(li, IC::C(c)) => {
+            let c = *c;
+            unary!(ii!(li), |x| x + c)
+        }
+        IMul(li, IC::C(c)) => {
+            let c = *c;
+            unary!(ii!(li), |x| x * c)
+        }
+        IAdd(li, ric) => binary!(ii!(li), ic!(ric), |a, b| a + b),
+        IMul(li, ric) => binary!(ii!(li), ic!(ric), |a, b| a * b),
+        IMod { dividend, divisor } => binary!(ic!(dividend), ic!(divisor), |a, b| a % b),
+        IExp { base, power } => binary!(ic!(base), ic!(power), |a, b| a.powf(b)),
+
+        ILT(l, r) => binary!(ic!(l), ic!(r), |a, b| bool_to_f64!(a < b)),
+        ILTE(l, r) => binary!(ic!(l), ic!(r), |a, b| bool_to_f64!(a <= b)),
+        IEQ(l, r) => binary!(ic!(l), ic!(r), |a, b| bool_to_f64!(f64_eq!(a, b))),
+        INE(l, r) => binary!(ic!(l), ic!(r), |a, b| bool_to_f64!(f64_ne!(a, b))),
+        IGTE(l, r) => binary!(ic!(l), ic!(r), |a, b| bool_to_f64!(a >= b)),
+        IGT(l, r) => binary!(ic!(l), ic!(r), |a, b| bool_to_f64!(a > b)),
+
+        IOR(li, ric) => {
+            let (l, r) = (ii!(li), ic!(ric));
+            Box::new(move |v| {
+                let left = l(v);
+                if f64_ne!(left, 0.0) {
+                    left
+                } else {
+                    r(v)
+                }
+            })
+        }
+        IAND(li, ric) => {
+            let (l, r) = (ii!(li), ic!(ric));
+            Box::new(move |v| {
+                let left = l(v);
+                if f64_eq!(left, 0.0) {
+                    left
+                } else {
+                    r(v)
+                }
+            })
+        }
+
+        IVar(name) => var(name, slots)?,
+        IFunc { name, args } => {
+            if !args.is_empty() {
+                return Err(Error::UndefinedFunction {
+                    name: name.clone(),
+                    arg_count: args.len(),
+                });
+            }
+            var(name, slots)?
+        }
+
+        IFuncInt(i) => unary!(ii!(i), |x| x.trunc()),
+        IFuncCeil(i) => unary!(ii!(i), |x| x.ceil()),
+        IFuncFloor(i) => unary!(ii!(i), |x| x.floor()),
+        IFuncAbs(i) => unary!(ii!(i), |x| x.abs()),
+        IFuncSign(i) => unary!(ii!(i), |x| x.signum()),
+        IFuncLog { base, of } => binary!(ic!(base), ic!(of), |b, n| log(b, n)),
+        IFuncRound { modulus, of } => {
+            binary!(ic!(modulus), ic!(of), |m, n| (n / m).round() * m)
+        }
+        IFuncMin(li, ric) => binary!(ii!(li), ic!(ric), |a, b| {
+            if a.is_nan() || b.is_nan() {
+                f64::NAN
+            } else if a < b {
+                a
+            } else {
+                b
+            }
+        }),
+        IFuncMax(li, ric) => binary!(ii!(li), ic!(ric), |a, b| {
+            if a.is_nan() || b.is_nan() {
+                f64::NAN
+            } else if a > b {
+                a
+            } else {
+                b
+            }
+        }),
+        IFuncPiecewise { branches, default } => {
+            let mut nodes = Vec::with_capacity(branches.len());
+            for (cond_ic, val_ic) in branches {
+                nodes.push((ic!(cond_ic), ic!(val_ic)));
+            }
+            let default = ic!(default);
+            Box::new(move |v| {
+                for (cond, val) in &nodes {
+                    if f64_ne!(cond(v), 0.0) {
+                        return val(v);
+                    }
+                }
+                default(v)
+            })
+        }
+        IFuncSum {
+            var,
+            from,
+            to,
+            expr,
+        } => {
+            let (from, to) = (ic!(from), ic!(to));
+            let n = slots.len();
+            let mut inner = slots.to_vec();
+            inner.push(var.clone());
+            let body = build_ic(expr, cs, &inner)?;
+            Box::new(move |v| {
+                // The same limit as the interpreter, to avoid endless loops:
+                const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+                let (from, to) = (from(v), to(v));
+                if from <= to && (from.abs() >= MAX_EXACT || to.abs() >= MAX_EXACT) {
+                    return f64::NAN;
+                }
+                let mut buf = Vec::with_capacity(n + 1);
+                buf.extend_from_slice(&v[..n]);
+                buf.push(from);
+                let mut sum = 0.0;
+                while buf[n] <= to {
+                    sum += body(&buf);
+                    buf[n] += 1.0;
+                }
+                sum
+            })
+        }
+        IFuncLet { var, value, expr } => {
+            let value = ic!(value);
+            let n = slots.len();
+            let mut inner = slots.to_vec();
+            inner.push(var.clone());
+            let body = build_ic(expr, cs, &inner)?;
+            Box::new(move |v| {
+                let mut buf = Vec::with_capacity(n + 1);
+                buf.extend_from_slice(&v[..n]);
+                buf.push(value(v));
+                body(&buf)
+            })
+        }
+        IFuncInterp {
+            extrapolate,
+            x,
+            points,
+        } => {
+            let extrapolate = *extrapolate;
+            let x = ic!(x);
+            let mut nodes = Vec::with_capacity(points.len());
+            for (px_ic, py_ic) in points {
+                nodes.push((ic!(px_ic), ic!(py_ic)));
+            }
+            Box::new(move |v| {
+                let points: Vec<f64> = nodes.iter().flat_map(|(px, py)| [px(v), py(v)]).collect();
+                interp(x(v), &points, extrapolate).unwrap_or(f64::NAN)
+            })
+        }
+
+        IFuncSin(i) => unary!(ii!(i), |x| x.sin()),
+        IFuncCos(i) => unary!(ii!(i), |x| x.cos()),
+        IFuncTan(i) => unary!(ii!(i), |x| x.tan()),
+        IFuncASin(i) => unary!(ii!(i), |x| x.asin()),
+        IFuncACos(i) => unary!(ii!(i), |x| x.acos()),
+        IFuncATan(i) => unary!(ii!(i), |x| x.atan()),
+        IFuncSinH(i) => unary!(ii!(i), |x| x.sinh()),
+        IFuncCosH(i) => unary!(ii!(i), |x| x.cosh()),
+        IFuncTanH(i) => unary!(ii!(i), |x| x.tanh()),
+        IFuncASinH(i) => unary!(ii!(i), |x| x.asinh()),
+        IFuncACosH(i) => unary!(ii!(i), |x| x.acosh()),
+        IFuncATanH(i) => unary!(ii!(i), |x| x.atanh()),
+        IFuncSqrt(i) => unary!(ii!(i), |x| x.sqrt()),
+        IFuncFma { a, b, c } => {
+            let (a, b, c) = (ic!(a), ic!(b), ic!(c));
+            Box::new(move |v| a(v).mul_add(b(v), c(v)))
+        }
+        IFuncCoalesce { value, fallback } => {
+            binary!(ic!(value), ic!(fallback), |v, f| coalesce(v, f))
+        }
+        IFuncQuotient { dividend, divisor } => {
+            binary!(ic!(dividend), ic!(divisor), |a, b| quotient(a, b))
+        }
+        IFuncIn {
+            negated,
+            value,
+            list,
+        } => {
+            let negated = *negated;
+            let value = ic!(value);
+            let mut nodes = Vec::with_capacity(list.len());
+            for item in list {
+                nodes.push(ic!(item));
+            }
+            Box::new(move |v| {
+                let x = value(v);
+                bool_to_f64!(nodes.iter().any(|item| f64_eq!(x, item(v))) != negated)
+            })
+        }
+        IFuncNorm { squared, args } => {
+            let squared = *squared;
+            let mut nodes = Vec::with_capacity(args.len());
+            for arg in args {
+                nodes.push(ic!(arg));
+            }
+            Box::new(move |v| {
+                let mut acc = SumSq::default();
+                for node in &nodes {
+                    acc.add(node(v));
+                }
+                acc.finish(squared)
+            })
+        }
+        // Shared sub-expressions are simply rebuilt at every use:
+        ITemps { body: i, .. } | ITemp { instr: i, .. } => ii!(i),
+
+        IPrintFunc(_) => {
+            return Err(Error::WrongArgs(
+                "print() can't be converted to a closure".to_string(),
+            ))
+        }
+        IError(err) => return Err(err.clone()),
+        #[cfg(feature = "unsafe-vars")]
+        IUnsafeVar { .. } => {
+            return Err(Error::WrongArgs(
+                "Unsafe Variables can't be converted to a closure".to_string(),
+            ))
+        }
+    })
+}
+
+fn var(name: &str, slots: &[String]) -> Result<Node, Error> {
+    if let Some(i) = slots.iter().rposition(|s| s == name) {
+        return Ok(Box::new(move |v| v[i]));
+    }
+    match named_constant(name) {
+        Some(c) => Ok(Box::new(move |_| c)),
+        None => Err(Error::UndefinedVariable {
+            name: name.to_string(),
+        }),
+    }
+}
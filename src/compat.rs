@@ -0,0 +1,5 @@
+//! Compatibility layers for migrating from other expression crates.
+//!
+//! * [`meval`](meval/index.html) -- The `Context` builder of the `meval` crate.
+
+pub mod meval;
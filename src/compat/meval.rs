@@ -0,0 +1,236 @@
+//! A drop-in for the `Context` of the `meval` crate.
+//!
+//! Code that builds a `meval::Context` keeps working after changing the
+//! import:
+//!
+//! ```
+//! use fasteval2::compat::meval::{eval_str_with_context, Context};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut ctx = Context::new();
+//!     ctx.var("x", 3.0)
+//!         .func("double", |x| x * 2.0)
+//!         .func2("hypot", f64::hypot)
+//!         .funcn("sum3", |xs| xs.iter().sum(), 3);
+//!
+//!     assert_eq!(eval_str_with_context("double(x) + hypot(3, 4) + sum3(1, 2, 3)", &ctx)?, 17.0);
+//!     assert_eq!(eval_str_with_context("pi * 0 + exp(0)", &ctx)?, 1.0);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! A `Context` is an ordinary [Namespace](../../evalns/index.html), so it also
+//! works with the rest of `fasteval2`, like
+//! [`ez_eval()`](../../ez/fn.ez_eval.html), compiled expressions and
+//! [`EvalNS`](../../evalns/struct.EvalNS.html) limits.
+//!
+//! # Differences from `meval`
+//!
+//! * `Context::new()` defines the `meval` built-ins that `fasteval2` doesn't
+//!   already have: the `pi` and `e` variables, and the `exp()`, `ln()`,
+//!   `signum()` and `atan2()` functions.  The other `meval` built-ins (`sqrt()`,
+//!   `sin()`, `abs()`, `max()`, ...) are `fasteval2` built-ins, which a
+//!   `Context` can't override.
+//! * Unary minus binds tighter than `^`: `-2^2` is `4`, not `-4` like in `meval`.
+//! * A function that gets the wrong number of arguments fails with a
+//!   [`WrongArgCount`](../../error/enum.Error.html#variant.WrongArgCount) error, and
+//!   all errors are `fasteval2` [`Error`](../../error/enum.Error.html)s.
+
+use crate::error::{Arity, Error};
+use crate::evalns::EvalNamespace;
+use crate::ez::ez_eval;
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+
+use alloc::collections::BTreeMap;
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::f64::consts;
+use core::ops::RangeFrom;
+
+/// The number of arguments that a [`funcn()`](struct.Context.html#method.funcn)
+/// function accepts.
+///
+/// `3.into()` is `Exact(3)`, and `(2..).into()` is `AtLeast(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgGuard {
+    /// Exactly this many arguments.
+    Exact(usize),
+    /// At least this many arguments.
+    AtLeast(usize),
+}
+
+impl From<usize> for ArgGuard {
+    fn from(n: usize) -> Self {
+        ArgGuard::Exact(n)
+    }
+}
+
+impl From<RangeFrom<usize>> for ArgGuard {
+    fn from(r: RangeFrom<usize>) -> Self {
+        ArgGuard::AtLeast(r.start)
+    }
+}
+
+impl From<ArgGuard> for Arity {
+    fn from(guard: ArgGuard) -> Self {
+        match guard {
+            ArgGuard::Exact(n) => Arity::Exact(n),
+            ArgGuard::AtLeast(n) => Arity::AtLeast(n),
+        }
+    }
+}
+
+impl ArgGuard {
+    fn check(self, name: &str, got: usize) -> Result<(), Error> {
+        let expected = Arity::from(self);
+        if expected.accepts(got) {
+            Ok(())
+        } else {
+            Err(Error::WrongArgCount {
+                name: name.to_string(),
+                expected,
+                got,
+            })
+        }
+    }
+}
+
+/// Variables and custom functions, defined with the `meval` method names.
+///
+/// [See the `compat::meval` module documentation.](index.html)
+pub struct Context<'a> {
+    vars: BTreeMap<String, f64>,
+    #[allow(clippy::type_complexity)]
+    funcs: BTreeMap<String, (ArgGuard, Box<dyn Fn(&[f64]) -> f64 + 'a>)>,
+}
+
+impl Default for Context<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Context<'a> {
+    /// Creates a `Context` with the `meval` built-ins that `fasteval2` doesn't
+    /// already have.
+    pub fn new() -> Self {
+        let mut ctx = Self::empty();
+        ctx.var("pi", consts::PI)
+            .var("e", consts::E)
+            .func("exp", f64::exp)
+            .func("ln", f64::ln)
+            .func("signum", f64::signum)
+            .func2("atan2", f64::atan2);
+        ctx
+    }
+
+    /// Creates a `Context` without any variables or functions.
+    pub fn empty() -> Self {
+        Context {
+            vars: BTreeMap::new(),
+            funcs: BTreeMap::new(),
+        }
+    }
+
+    /// Defines a variable.
+    pub fn var<S: Into<String>>(&mut self, var: S, value: f64) -> &mut Self {
+        self.vars.insert(var.into(), value);
+        self
+    }
+
+    /// Defines a function of one argument.
+    pub fn func<S, F>(&mut self, name: S, func: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(f64) -> f64 + 'a,
+    {
+        self.funcn(name, move |xs| func(xs[0]), 1)
+    }
+
+    /// Defines a function of two arguments.
+    pub fn func2<S, F>(&mut self, name: S, func: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(f64, f64) -> f64 + 'a,
+    {
+        self.funcn(name, move |xs| func(xs[0], xs[1]), 2)
+    }
+
+    /// Defines a function of three arguments.
+    pub fn func3<S, F>(&mut self, name: S, func: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(f64, f64, f64) -> f64 + 'a,
+    {
+        self.funcn(name, move |xs| func(xs[0], xs[1], xs[2]), 3)
+    }
+
+    /// Defines a function whose number of arguments is checked by `n_args`:
+    /// `3` for exactly three arguments, or `2..` for two or more.
+    pub fn funcn<S, F, N>(&mut self, name: S, func: F, n_args: N) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn(&[f64]) -> f64 + 'a,
+        N: Into<ArgGuard>,
+    {
+        self.funcs
+            .insert(name.into(), (n_args.into(), Box::new(func)));
+        self
+    }
+
+    fn find(&self, name: &str, args: &[f64]) -> Result<Option<f64>, Error> {
+        if args.is_empty() {
+            if let Some(val) = self.vars.get(name) {
+                return Ok(Some(*val));
+            }
+        }
+        match self.funcs.get(name) {
+            Some((guard, func)) => {
+                guard.check(name, args.len())?;
+                Ok(Some(func(args)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+macro_rules! context_namespace {
+    ($ty:ty) => {
+        impl EvalNamespace for $ty {
+            #[inline]
+            fn lookup(&mut self, name: &str, args: Vec<f64>, _keybuf: &mut String) -> Option<f64> {
+                self.find(name, &args).unwrap_or(None)
+            }
+            #[inline]
+            fn try_lookup(
+                &mut self,
+                name: &str,
+                args: Vec<f64>,
+                _keybuf: &mut String,
+            ) -> Result<Option<f64>, Error> {
+                self.find(name, &args)
+            }
+        }
+    };
+}
+context_namespace!(Context<'_>);
+context_namespace!(&Context<'_>);
+context_namespace!(&mut Context<'_>);
+
+/// Evaluates `expr` with the built-ins of `Context::new()`.
+pub fn eval_str<S: AsRef<str>>(expr: S) -> Result<f64, Error> {
+    eval_str_with_context(expr, Context::new())
+}
+
+/// Evaluates `expr` with `ctx`, which can be a `Context`, a `&Context` or a
+/// `&mut Context`.
+pub fn eval_str_with_context<S: AsRef<str>>(
+    expr: S,
+    mut ctx: impl EvalNamespace,
+) -> Result<f64, Error> {
+    ez_eval(expr.as_ref(), &mut ctx)
+}
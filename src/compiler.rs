@@ -20,6 +20,16 @@
 //! * Constant terms are combined.
 //! * Logical operator short-circuits are applied and no-op branches are discarded.
 //!
+//! ## Peephole Optimization
+//! Some patterns only appear after sub-expressions have been compiled separately,
+//! like the negation in `-x * 3`.  The opt-in [`Optimizer`](../optimizer/struct.Optimizer.html)
+//! pass rewrites those without changing results.
+//!
+//! ## Common Subexpression Elimination
+//! [`eliminate_common_subexpressions()`](enum.Instruction.html#method.eliminate_common_subexpressions)
+//! computes repeated sub-expressions, like the `s-k` in `(s-k)*(s-k)`, only
+//! once per evaluation.  [See the `cse module` documentation.](../cse/index.html)
+//!
 //! ## Optimized Memory Layout and Execution
 //! * Variable-length `Expression`/`Value` AST nodes are converted into constant-sized `Instruction` nodes.
 //! * The `IC` enumeration helps to eliminate expensive function calls.
@@ -33,8 +43,10 @@ use crate::parser::{
     ExprPair, Expression, PrintFunc,
     StdFunc::{
         self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
-        EFuncCeil, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncInt, EFuncLog, EFuncMax, EFuncMin,
-        EFuncPi, EFuncRound, EFuncSign, EFuncSin, EFuncSinH, EFuncSqrt, EFuncTan, EFuncTanH, EVar,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
     },
     UnaryOp::{self, ENeg, ENot, EParentheses, EPos},
     Value,
@@ -59,13 +71,15 @@ macro_rules! bool_to_f64 {
 /// It behaves much like a pointer or reference, but it is 'safe' (unlike a raw
 /// pointer) and is not managed by the Rust borrow checker (unlike a reference).
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InstructionI(pub usize);
 
 /// This enumeration boosts performance because it eliminates expensive function calls for constant values.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IC {
     I(InstructionI),
-    C(f64),
+    C(#[cfg_attr(feature = "serde", serde(with = "crate::serde_f64"))] f64),
 }
 
 macro_rules! instr_to_ic {
@@ -76,23 +90,13 @@ macro_rules! instr_to_ic {
         }
     };
 }
-macro_rules! ic_to_instr {
-    ($cslab:expr, $dst:ident, $ic:ident) => {
-        match $ic {
-            IC::C(c) => {
-                $dst = IConst(*c);
-                &$dst
-            }
-            IC::I(i) => get_instr!($cslab, i),
-        }
-    };
-}
 
 /// An `Instruction` is an optimized AST node resulting from compilation.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     //---- Primitive Value Types:
-    IConst(f64),
+    IConst(#[cfg_attr(feature = "serde", serde(with = "crate::serde_f64"))] f64),
 
     //---- Unary Ops:
     // Parentheses is a noop
@@ -129,11 +133,6 @@ pub enum Instruction {
 
     //---- Callables:
     IVar(String),
-    #[cfg(feature = "unsafe-vars")]
-    IUnsafeVar {
-        name: String,
-        ptr: *const f64,
-    },
     IFunc {
         name: String,
         args: Vec<IC>,
@@ -154,6 +153,21 @@ pub enum Instruction {
     },
     IFuncMin(InstructionI, IC),
     IFuncMax(InstructionI, IC),
+    IFuncPiecewise {
+        branches: Vec<(IC, IC)>,
+        default: IC,
+    },
+    IFuncSum {
+        var: String,
+        from: IC,
+        to: IC,
+        expr: IC,
+    },
+    IFuncInterp {
+        extrapolate: bool,
+        x: IC,
+        points: Vec<(IC, IC)>,
+    },
 
     IFuncSin(InstructionI),
     IFuncCos(InstructionI),
@@ -170,20 +184,246 @@ pub enum Instruction {
     IFuncSqrt(InstructionI),
 
     IPrintFunc(PrintFunc), // Not optimized (it would be pointless because of i/o bottleneck).
+
+    /// Compiled from an `EError` placeholder, or from a tuple.  Evaluating it
+    /// returns the contained `Error`.
+    IError(Error),
+
+    // Newer variants are added here, so the serialized indices of the ones above don't change:
+    IFuncFma {
+        a: IC,
+        b: IC,
+        c: IC,
+    },
+    /// Produced by [`eliminate_common_subexpressions()`](#method.eliminate_common_subexpressions):
+    /// evaluates `body` with room for `slots` temporary values.
+    ITemps {
+        slots: usize,
+        body: InstructionI,
+    },
+    /// A shared sub-expression.  `instr` is evaluated the first time that
+    /// `slot` is needed during an evaluation of the enclosing `ITemps`, and the
+    /// stored value is reused after that.  (Anywhere else, `instr` is simply
+    /// evaluated every time.)
+    ITemp {
+        slot: usize,
+        instr: InstructionI,
+    },
+    IFuncCoalesce {
+        value: IC,
+        fallback: IC,
+    },
+    IFuncNorm {
+        squared: bool,
+        args: Vec<IC>,
+    },
+    /// Evaluates `value`, and then `expr` with `var` bound to the result.
+    IFuncLet {
+        var: String,
+        value: IC,
+        expr: IC,
+    },
+    /// Compiled from `quotient()`.  (`remainder()` compiles to an `IMod`.)
+    IFuncQuotient {
+        dividend: IC,
+        divisor: IC,
+    },
+    /// Compiled from `in`, or `not in` if `negated`.  The items of `list` are
+    /// evaluated in order, until one of them equals `value`.
+    IFuncIn {
+        negated: bool,
+        value: IC,
+        list: Vec<IC>,
+    },
+
+    // Kept last so that the other variants have the same index with or without the feature:
+    #[cfg(feature = "unsafe-vars")]
+    #[cfg_attr(feature = "serde", serde(skip))] // Pointers can't be serialized.
+    IUnsafeVar {
+        name: String,
+        ptr: *const f64,
+    },
 }
+use crate::evalns::{is_impure, ModMode};
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
 use crate::{eval_var, EvalNamespace};
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 #[cfg(feature = "unsafe-vars")]
 use Instruction::IUnsafeVar;
 use Instruction::{
-    IAdd, IConst, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH, IFuncATan, IFuncATanH,
-    IFuncAbs, IFuncCeil, IFuncCos, IFuncCosH, IFuncFloor, IFuncInt, IFuncLog, IFuncMax, IFuncMin,
-    IFuncRound, IFuncSign, IFuncSin, IFuncSinH, IFuncSqrt, IFuncTan, IFuncTanH, IInv, IMod, IMul,
-    INeg, INot, IPrintFunc, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH, IFuncATan,
+    IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor, IFuncFma,
+    IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin, IFuncNorm,
+    IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH, IFuncSqrt, IFuncSum,
+    IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp, ITemps, IVar, IAND, IEQ,
+    IGT, IGTE, ILT, ILTE, INE, IOR,
 };
 
 impl Default for Instruction {
     fn default() -> Self {
-        IConst(std::f64::NAN)
+        IConst(f64::NAN)
+    }
+}
+
+// Lists the sub-instructions of `$instr`, from left to right.  Thanks to match
+// ergonomics, the same code yields `&InstructionI`s or `&mut InstructionI`s.
+macro_rules! instr_children {
+    ($instr:expr) => {{
+        let mut out = Vec::new();
+        match $instr {
+            IConst(_) | IVar(_) | IPrintFunc(_) | IError(_) => {}
+            #[cfg(feature = "unsafe-vars")]
+            IUnsafeVar { .. } => {}
+
+            INeg(i)
+            | INot(i)
+            | IInv(i)
+            | IFuncInt(i)
+            | IFuncCeil(i)
+            | IFuncFloor(i)
+            | IFuncAbs(i)
+            | IFuncSign(i)
+            | IFuncSin(i)
+            | IFuncCos(i)
+            | IFuncTan(i)
+            | IFuncASin(i)
+            | IFuncACos(i)
+            | IFuncATan(i)
+            | IFuncSinH(i)
+            | IFuncCosH(i)
+            | IFuncTanH(i)
+            | IFuncASinH(i)
+            | IFuncACosH(i)
+            | IFuncATanH(i)
+            | IFuncSqrt(i)
+            | ITemps { body: i, .. }
+            | ITemp { instr: i, .. } => out.push(i),
+
+            IAdd(i, r) | IMul(i, r) | IOR(i, r) | IAND(i, r) | IFuncMin(i, r) | IFuncMax(i, r) => {
+                out.push(i);
+                if let IC::I(r) = r {
+                    out.push(r);
+                }
+            }
+            IMod {
+                dividend: l,
+                divisor: r,
+            }
+            | IExp { base: l, power: r }
+            | ILT(l, r)
+            | ILTE(l, r)
+            | IEQ(l, r)
+            | INE(l, r)
+            | IGTE(l, r)
+            | IGT(l, r)
+            | IFuncLog { base: l, of: r }
+            | IFuncRound { modulus: l, of: r } => {
+                for ic in [l, r] {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFunc { args, .. } => {
+                for ic in args {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFuncPiecewise { branches, default } => {
+                for (cond, val) in branches {
+                    for ic in [cond, val] {
+                        if let IC::I(i) = ic {
+                            out.push(i);
+                        }
+                    }
+                }
+                if let IC::I(i) = default {
+                    out.push(i);
+                }
+            }
+            IFuncSum { from, to, expr, .. } => {
+                for ic in [from, to, expr] {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFuncLet { value, expr, .. } => {
+                for ic in [value, expr] {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFuncInterp { x, points, .. } => {
+                if let IC::I(i) = x {
+                    out.push(i);
+                }
+                for (px, py) in points {
+                    for ic in [px, py] {
+                        if let IC::I(i) = ic {
+                            out.push(i);
+                        }
+                    }
+                }
+            }
+            IFuncFma { a, b, c } => {
+                for ic in [a, b, c] {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFuncCoalesce {
+                value: a,
+                fallback: b,
+            }
+            | IFuncQuotient {
+                dividend: a,
+                divisor: b,
+            } => {
+                for ic in [a, b] {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFuncNorm { args, .. } => {
+                for ic in args {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+            IFuncIn { value, list, .. } => {
+                for ic in Some(value).into_iter().chain(list) {
+                    if let IC::I(i) = ic {
+                        out.push(i);
+                    }
+                }
+            }
+        }
+        out
+    }};
+}
+
+impl Instruction {
+    /// The sub-instructions of this `Instruction`, from left to right.
+    pub(crate) fn children(&self) -> Vec<&InstructionI> {
+        instr_children!(self)
+    }
+
+    /// Like `children()`, but the indices can be redirected.
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut InstructionI> {
+        instr_children!(self)
     }
 }
 
@@ -220,15 +460,13 @@ impl<'s> ExprSlice<'s> {
         sl
     }
     fn split(&self, bop: BinaryOp, dst: &mut Vec<ExprSlice<'s>>) {
-        dst.push(ExprSlice::new(&self.first));
+        dst.push(ExprSlice::new(self.first));
         for exprpair in self.pairs.iter() {
             if exprpair.0 == bop {
                 dst.push(ExprSlice::new(&exprpair.1));
-            } else {
-                match dst.last_mut() {
-                    Some(cur) => cur.pairs.push(exprpair),
-                    None => (), // unreachable
-                }
+            } else if let Some(cur) = dst.last_mut() {
+                // Always true, since the first slice was pushed above.
+                cur.pairs.push(exprpair);
             }
         }
     }
@@ -238,16 +476,14 @@ impl<'s> ExprSlice<'s> {
         xsdst: &mut Vec<ExprSlice<'s>>,
         opdst: &mut Vec<&'s BinaryOp>,
     ) {
-        xsdst.push(ExprSlice::new(&self.first));
+        xsdst.push(ExprSlice::new(self.first));
         for exprpair in self.pairs.iter() {
             if search.contains(&exprpair.0) {
                 xsdst.push(ExprSlice::new(&exprpair.1));
                 opdst.push(&exprpair.0);
-            } else {
-                match xsdst.last_mut() {
-                    Some(cur) => cur.pairs.push(exprpair),
-                    None => (), // unreachable
-                }
+            } else if let Some(cur) = xsdst.last_mut() {
+                // Always true, since the first slice was pushed above.
+                cur.pairs.push(exprpair);
             }
         }
     }
@@ -257,16 +493,16 @@ impl<'s> ExprSlice<'s> {
 #[macro_export]
 macro_rules! f64_eq {
     ($l:ident, $r:literal) => {
-        ($l - $r).abs() <= 8.0 * std::f64::EPSILON
+        ($l - $r).abs() <= 8.0 * f64::EPSILON
     };
     ($l:ident, $r:ident) => {
-        ($l - $r).abs() <= 8.0 * std::f64::EPSILON
+        ($l - $r).abs() <= 8.0 * f64::EPSILON
     };
     ($l:expr, $r:literal) => {
-        ($l - $r).abs() <= 8.0 * std::f64::EPSILON
+        ($l - $r).abs() <= 8.0 * f64::EPSILON
     };
     ($l:expr, $r:expr) => {
-        (($l) - ($r)).abs() <= 8.0 * std::f64::EPSILON
+        (($l) - ($r)).abs() <= 8.0 * f64::EPSILON
     };
 }
 
@@ -276,16 +512,16 @@ macro_rules! f64_eq {
 #[macro_export]
 macro_rules! f64_ne {
     ($l:ident, $r:literal) => {
-        ($l - $r).abs() > 8.0 * std::f64::EPSILON
+        ($l - $r).abs() > 8.0 * f64::EPSILON
     };
     ($l:ident, $r:ident) => {
-        ($l - $r).abs() > 8.0 * std::f64::EPSILON
+        ($l - $r).abs() > 8.0 * f64::EPSILON
     };
     ($l:expr, $r:literal) => {
-        ($l - $r).abs() > 8.0 * std::f64::EPSILON
+        ($l - $r).abs() > 8.0 * f64::EPSILON
     };
     ($l:expr, $r:expr) => {
-        (($l) - ($r)).abs() > 8.0 * std::f64::EPSILON
+        (($l) - ($r)).abs() > 8.0 * f64::EPSILON
     };
 }
 fn neg_wrap(instr: Instruction, cslab: &mut CompileSlab) -> Instruction {
@@ -376,6 +612,86 @@ pub(crate) fn log(base: f64, n: f64) -> f64 {
     n.log(base)
 }
 
+/// The running sum of squares of `sumsq()` and `norm()`.
+#[derive(Default)]
+pub(crate) struct SumSq {
+    sum: f64,
+    count: usize,
+    last: f64,
+}
+
+impl SumSq {
+    pub(crate) fn add(&mut self, x: f64) {
+        self.sum += x * x;
+        self.count += 1;
+        self.last = x;
+    }
+
+    /// Returns the sum of squares, or its square root for `norm()`.  The norm
+    /// of a single value is its `abs()`, which can't overflow.
+    pub(crate) fn finish(&self, squared: bool) -> f64 {
+        match (squared, self.count) {
+            (true, _) => self.sum,
+            (false, 1) => self.last.abs(),
+            (false, _) => self.sum.sqrt(),
+        }
+    }
+}
+
+// The quotient that goes with `%`: `dividend / divisor` rounded toward zero,
+// so that `quotient(a, b) * b + a % b == a`.  `%` is the truncated remainder
+// in every `ModMode`, so a floored quotient would break that for negative
+// operands.
+pub(crate) fn quotient(dividend: f64, divisor: f64) -> f64 {
+    // The subtraction leaves a multiple of `divisor`, so rounding only removes
+    // the error of the division:
+    ((dividend - dividend % divisor) / divisor).round()
+}
+
+pub(crate) fn coalesce(value: f64, fallback: f64) -> f64 {
+    if value.is_finite() {
+        value
+    } else {
+        fallback
+    }
+}
+
+/// Linear interpolation through `points`, which must have strictly-increasing x values.
+///
+/// `points` holds the breakpoints as flat `x0, y0, x1, y1, ...` pairs, so that
+/// they can live on the working stack of `eval_with_stack()`.
+///
+/// Probes outside of the table are clamped to the end values, unless
+/// `extrapolate` is set, in which case the first/last segment is extended.
+pub(crate) fn interp(x: f64, points: &[f64], extrapolate: bool) -> Result<f64, Error> {
+    let (px, py) = (|i: usize| points[2 * i], |i: usize| points[2 * i + 1]);
+    let n = points.len() / 2;
+    // `partial_cmp()` makes NaN breakpoints an error as well:
+    if n < 2 || (1..n).any(|i| px(i - 1).partial_cmp(&px(i)) != Some(core::cmp::Ordering::Less)) {
+        let name = if extrapolate { "interp_ext" } else { "interp" };
+        return Err(Error::WrongArgs(format!(
+            "{}: x-breakpoints must be strictly increasing",
+            name
+        )));
+    }
+    if x.is_nan() {
+        return Ok(x);
+    }
+    let last = n - 1;
+    if !extrapolate {
+        if x <= px(0) {
+            return Ok(py(0));
+        }
+        if x >= px(last) {
+            return Ok(py(last));
+        }
+    }
+    // Find the segment containing x (or the nearest end segment, for extrapolation):
+    let i = (1..last).take_while(|&i| px(i) <= x).count();
+    let ((x0, y0), (x1, y1)) = ((px(i), py(i)), (px(i + 1), py(i + 1)));
+    Ok(y0 + (x - x0) * (y1 - y0) / (x1 - x0))
+}
+
 // Can't inline recursive functions:
 fn push_mul_leaves(
     instrs: &mut Vec<Instruction>,
@@ -476,12 +792,12 @@ impl Compiler for ExprSlice<'_> {
             self.split_multi(&[EEQ, ENE, ELT, EGT, ELTE, EGTE], &mut xss, &mut ops);
             let mut out = match xss.first() {
                 Some(xs) => xs.compile(pslab, cslab, ns),
-                None => IConst(std::f64::NAN), // unreachable
+                None => IConst(f64::NAN), // unreachable
             };
             for (i, op) in ops.into_iter().enumerate() {
                 let instr = match xss.get(i + 1) {
                     Some(xs) => xs.compile(pslab, cslab, ns),
-                    None => IConst(std::f64::NAN), // unreachable
+                    None => IConst(f64::NAN), // unreachable
                 };
                 if let IConst(l) = out {
                     if let IConst(r) = instr {
@@ -492,7 +808,7 @@ impl Compiler for ExprSlice<'_> {
                             EGT => IConst(bool_to_f64!(l > r)),
                             ELTE => IConst(bool_to_f64!(l <= r)),
                             EGTE => IConst(bool_to_f64!(l >= r)),
-                            _ => IConst(std::f64::NAN), // unreachable
+                            _ => IConst(f64::NAN), // unreachable
                         };
                         continue;
                     }
@@ -504,7 +820,7 @@ impl Compiler for ExprSlice<'_> {
                     EGT => IGT(instr_to_ic!(cslab, out), instr_to_ic!(cslab, instr)),
                     ELTE => ILTE(instr_to_ic!(cslab, out), instr_to_ic!(cslab, instr)),
                     EGTE => IGTE(instr_to_ic!(cslab, out), instr_to_ic!(cslab, instr)),
-                    _ => IConst(std::f64::NAN), // unreachable
+                    _ => IConst(f64::NAN), // unreachable
                 };
             }
             return out;
@@ -674,8 +990,11 @@ impl Compiler for ExprSlice<'_> {
                     if out_set {
                         if let IConst(dividend) = out {
                             if let IConst(divisor) = instr {
-                                out = IConst(dividend % divisor);
-                                continue;
+                                // Non-integers are left to the Namespace of the evaluation:
+                                if ModMode::is_foldable(dividend, divisor) {
+                                    out = IConst(dividend % divisor);
+                                    continue;
+                                }
                             }
                         }
                         out = IMod {
@@ -700,8 +1019,11 @@ impl Compiler for ExprSlice<'_> {
                     if out_set {
                         if let IConst(power) = out {
                             if let IConst(base) = instr {
-                                out = IConst(base.powf(power));
-                                continue;
+                                // `0^0` is left to the Namespace of the evaluation:
+                                if base != 0.0 || power != 0.0 {
+                                    out = IConst(base.powf(power));
+                                    continue;
+                                }
                             }
                         }
                         out = IExp {
@@ -736,7 +1058,7 @@ impl Compiler for ExprSlice<'_> {
             //              }
             //              IExp{base:cslab.push_instr(base), power:cslab.push_instr(power)}
             //          }
-            ENE | EEQ | EGTE | ELTE | EGT | ELT => IConst(std::f64::NAN), // unreachable
+            ENE | EEQ | EGTE | ELTE | EGT | ELT => IConst(f64::NAN), // unreachable
         }
     }
 }
@@ -748,7 +1070,12 @@ impl Compiler for Expression {
         cslab: &mut CompileSlab,
         ns: &mut impl EvalNamespace,
     ) -> Instruction {
-        let top = ExprSlice::from_expr(&self);
+        // Instructions have one value, so a tuple can only be evaluated
+        // uncompiled, with `eval_vec()`:
+        if !self.tuple.is_empty() {
+            return IError(Error::NotAllowed("tuple".to_string()));
+        }
+        let top = ExprSlice::from_expr(self);
         top.compile(pslab, cslab, ns)
     }
 }
@@ -765,6 +1092,7 @@ impl Compiler for Value {
             Value::EUnaryOp(u) => u.compile(pslab, cslab, ns),
             Value::EStdFunc(f) => f.compile(pslab, cslab, ns),
             Value::EPrintFunc(pf) => IPrintFunc(pf.clone()),
+            Value::EError(err) => IError(err.clone()),
         }
     }
 }
@@ -807,10 +1135,10 @@ impl Compiler for StdFunc {
         ns: &mut impl EvalNamespace,
     ) -> Instruction {
         match self {
-            EVar(name) => IVar(name.clone()),
+            EVar(name) => IVar(name.to_string()),
             #[cfg(feature = "unsafe-vars")]
             EUnsafeVar { name, ptr } => IUnsafeVar {
-                name: name.clone(),
+                name: name.to_string(),
                 ptr: *ptr,
             },
             EFunc { name, args: xis } => {
@@ -826,22 +1154,20 @@ impl Compiler for StdFunc {
                     }
                     args.push(instr_to_ic!(cslab, instr));
                 }
-                if is_all_const {
-                    let computed_value = eval_var!(ns, name, f64_args, unsafe {
-                        #[allow(invalid_reference_casting)]
-                        &mut *(&pslab.char_buf as *const _ as *mut _)
-                    });
+                // Random functions must be called again for every evaluation:
+                if is_all_const && !is_impure(name) {
+                    let computed_value = eval_var!(ns, name, f64_args, &mut String::new());
                     if let Ok(value) = computed_value {
                         IConst(value)
                     } else {
                         IFunc {
-                            name: name.clone(),
+                            name: name.to_string(),
                             args,
                         }
                     }
                 } else {
                     IFunc {
-                        name: name.clone(),
+                        name: name.to_string(),
                         args,
                     }
                 }
@@ -1025,9 +1351,104 @@ impl Compiler for StdFunc {
                 //assert!(out_set);
                 out
             }
+            EFuncPiecewise {
+                branches: bs,
+                default: di,
+            } => {
+                let mut branches = Vec::<(IC, IC)>::with_capacity(bs.len());
+                let mut default = None;
+                for (ci, vi) in bs {
+                    let cond = get_expr!(pslab, ci).compile(pslab, cslab, ns);
+                    if let IConst(c) = cond {
+                        if f64_eq!(c, 0.0) {
+                            continue; // This branch can never be taken.
+                        }
+                        // This branch is always taken, so the rest are unreachable:
+                        default = Some(get_expr!(pslab, vi).compile(pslab, cslab, ns));
+                        break;
+                    }
+                    let val = get_expr!(pslab, vi).compile(pslab, cslab, ns);
+                    branches.push((instr_to_ic!(cslab, cond), instr_to_ic!(cslab, val)));
+                }
+                let default = match default {
+                    Some(instr) => instr,
+                    None => get_expr!(pslab, di).compile(pslab, cslab, ns),
+                };
+                if branches.is_empty() {
+                    default
+                } else {
+                    IFuncPiecewise {
+                        branches,
+                        default: instr_to_ic!(cslab, default),
+                    }
+                }
+            }
 
-            EFuncE => IConst(std::f64::consts::E),
-            EFuncPi => IConst(std::f64::consts::PI),
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                let from = get_expr!(pslab, from).compile(pslab, cslab, ns);
+                let to = get_expr!(pslab, to).compile(pslab, cslab, ns);
+                let expr = get_expr!(pslab, expr).compile(pslab, cslab, ns);
+                IFuncSum {
+                    var: var.to_string(),
+                    from: instr_to_ic!(cslab, from),
+                    to: instr_to_ic!(cslab, to),
+                    expr: instr_to_ic!(cslab, expr),
+                }
+            }
+            EFuncLet { var, value, expr } => {
+                let value = get_expr!(pslab, value).compile(pslab, cslab, ns);
+                let expr = get_expr!(pslab, expr).compile(pslab, cslab, ns);
+                IFuncLet {
+                    var: var.to_string(),
+                    value: instr_to_ic!(cslab, value),
+                    expr: instr_to_ic!(cslab, expr),
+                }
+            }
+            EFuncInterp {
+                extrapolate,
+                x: xi,
+                points: ps,
+            } => {
+                let x = get_expr!(pslab, xi).compile(pslab, cslab, ns);
+                let mut points = Vec::<(Instruction, Instruction)>::with_capacity(ps.len());
+                for (pxi, pyi) in ps {
+                    points.push((
+                        get_expr!(pslab, pxi).compile(pslab, cslab, ns),
+                        get_expr!(pslab, pyi).compile(pslab, cslab, ns),
+                    ));
+                }
+                if let IConst(xc) = x {
+                    let mut const_points = Vec::<f64>::with_capacity(2 * points.len());
+                    for (px, py) in &points {
+                        if let (IConst(pxc), IConst(pyc)) = (px, py) {
+                            const_points.extend([*pxc, *pyc]);
+                        }
+                    }
+                    if const_points.len() == 2 * points.len() {
+                        // Invalid tables are left for eval() to report:
+                        if let Ok(val) = interp(xc, &const_points, *extrapolate) {
+                            return IConst(val);
+                        }
+                    }
+                }
+                let mut point_ics = Vec::<(IC, IC)>::with_capacity(points.len());
+                for (px, py) in points {
+                    point_ics.push((instr_to_ic!(cslab, px), instr_to_ic!(cslab, py)));
+                }
+                IFuncInterp {
+                    extrapolate: *extrapolate,
+                    x: instr_to_ic!(cslab, x),
+                    points: point_ics,
+                }
+            }
+
+            EFuncE => IConst(core::f64::consts::E),
+            EFuncPi => IConst(core::f64::consts::PI),
 
             EFuncSin(i) => {
                 let instr = get_expr!(pslab, i).compile(pslab, cslab, ns);
@@ -1133,6 +1554,99 @@ impl Compiler for StdFunc {
                     IFuncSqrt(cslab.push_instr(instr))
                 }
             }
+            EFuncFma { a, b, c } => {
+                let a = get_expr!(pslab, a).compile(pslab, cslab, ns);
+                let b = get_expr!(pslab, b).compile(pslab, cslab, ns);
+                let c = get_expr!(pslab, c).compile(pslab, cslab, ns);
+                if let (IConst(a), IConst(b), IConst(c)) = (&a, &b, &c) {
+                    return IConst(a.mul_add(*b, *c));
+                }
+                IFuncFma {
+                    a: instr_to_ic!(cslab, a),
+                    b: instr_to_ic!(cslab, b),
+                    c: instr_to_ic!(cslab, c),
+                }
+            }
+            EFuncCoalesce { value, fallback } => {
+                let value = get_expr!(pslab, value).compile(pslab, cslab, ns);
+                let fallback = get_expr!(pslab, fallback).compile(pslab, cslab, ns);
+                if let (IConst(v), IConst(f)) = (&value, &fallback) {
+                    return IConst(coalesce(*v, *f));
+                }
+                IFuncCoalesce {
+                    value: instr_to_ic!(cslab, value),
+                    fallback: instr_to_ic!(cslab, fallback),
+                }
+            }
+            EFuncQuotient { dividend, divisor } | EFuncRemainder { dividend, divisor } => {
+                let dividend = get_expr!(pslab, dividend).compile(pslab, cslab, ns);
+                let divisor = get_expr!(pslab, divisor).compile(pslab, cslab, ns);
+                let is_quotient = matches!(self, EFuncQuotient { .. });
+                if let (IConst(a), IConst(b)) = (&dividend, &divisor) {
+                    if ModMode::is_foldable(*a, *b) {
+                        return IConst(if is_quotient { quotient(*a, *b) } else { a % b });
+                    }
+                }
+                let (dividend, divisor) =
+                    (instr_to_ic!(cslab, dividend), instr_to_ic!(cslab, divisor));
+                if is_quotient {
+                    IFuncQuotient { dividend, divisor }
+                } else {
+                    IMod { dividend, divisor }
+                }
+            }
+            EFuncIn {
+                negated,
+                value,
+                list: xis,
+            } => {
+                let value = get_expr!(pslab, value).compile(pslab, cslab, ns);
+                let mut list = Vec::<Instruction>::with_capacity(xis.len());
+                for xi in xis {
+                    list.push(get_expr!(pslab, xi).compile(pslab, cslab, ns));
+                }
+                if let IConst(v) = value {
+                    if list.iter().all(|instr| matches!(instr, IConst(_))) {
+                        let found = list
+                            .iter()
+                            .any(|instr| matches!(instr, IConst(c) if f64_eq!(v, *c)));
+                        return IConst(bool_to_f64!(found != *negated));
+                    }
+                }
+                let mut ics = Vec::<IC>::with_capacity(list.len());
+                for instr in list {
+                    ics.push(instr_to_ic!(cslab, instr));
+                }
+                IFuncIn {
+                    negated: *negated,
+                    value: instr_to_ic!(cslab, value),
+                    list: ics,
+                }
+            }
+            EFuncNorm { squared, args: xis } => {
+                let mut args = Vec::<Instruction>::with_capacity(xis.len());
+                let mut acc = SumSq::default();
+                let mut is_all_const = true;
+                for xi in xis {
+                    let instr = get_expr!(pslab, xi).compile(pslab, cslab, ns);
+                    match instr {
+                        IConst(c) => acc.add(c),
+                        _ => is_all_const = false,
+                    }
+                    args.push(instr);
+                }
+                if is_all_const {
+                    return IConst(acc.finish(*squared));
+                }
+                let mut ics = Vec::<IC>::with_capacity(args.len());
+                for instr in args {
+                    ics.push(instr_to_ic!(cslab, instr));
+                }
+                IFuncNorm {
+                    squared: *squared,
+                    args: ics,
+                }
+            }
         }
     }
 }
@@ -0,0 +1,296 @@
+//! This module evaluates parsed `Expression`s with complex numbers.
+//!
+//! The [`ComplexEvaler`](trait.ComplexEvaler.html) trait provides an
+//! `eval_complex()` method that returns a `(re, im)` pair:
+//!
+//! * `i` is the imaginary unit, unless the Namespace defines a variable `i`.
+//! * `+ - * / ^` and unary `-` work on complex values.  `^` with an integral
+//!   real power is calculated by multiplication, so `i^2` is exactly `-1`.
+//! * `sqrt()` returns the principal root, so `sqrt(-4)` is `2i`, and `abs()`
+//!   returns the modulus.
+//! * Everything else needs real values: comparisons, `%`, `!`, `and`/`or`,
+//!   the conditions of `piecewise()` and the arguments of custom functions produce
+//!   `Error::NotReal` for values with a nonzero imaginary part.  The other built-in
+//!   functions are calculated with the normal `f64` evaler, so their arguments
+//!   can't use `i`.
+//!
+//! Real-only expressions give the same result as `eval()`, with a zero
+//! imaginary part -- except for the square roots and fractional powers of
+//! negative numbers, which `eval()` calculates as NaN.  Only parsed `Expression`s can be evaluated this way,
+//! because the compiler performs its constant-folding with `f64`.
+//!
+//! # Examples
+//! ```
+//! use fasteval2::ComplexEvaler;  // use this trait so we can call eval_complex().
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = fasteval2::Slab::new();
+//!     let mut ns = fasteval2::EmptyNamespace;
+//!
+//!     let expr_ref = fasteval2::Parser::new().parse("(1 + 2*i) * i", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(expr_ref.eval_complex(&slab, &mut ns)?, (-2.0, 1.0));
+//!
+//!     let expr_ref = fasteval2::Parser::new().parse("i < 1", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(expr_ref.eval_complex(&slab, &mut ns), Err(fasteval2::Error::NotReal("0+1i".to_string())));
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::error::Error;
+use crate::evaler::{named_constant, Evaler, OP_PASSES};
+use crate::evalns::EvalNamespace;
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    remove_no_panic,
+    BinaryOp::{self, EAdd, EDiv, EExp, EMul, ESub},
+    Expression,
+    StdFunc::{self, EFunc, EFuncAbs, EFuncPiecewise, EFuncSqrt, EVar},
+    UnaryOp::{self, ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// A complex number, as `(re, im)`.
+type C = (f64, f64);
+
+/// You must `use` this trait so you can call `.eval_complex()`.
+pub trait ComplexEvaler {
+    /// Evaluate this `Expression` with complex numbers and return `(re, im)`.
+    ///
+    /// Returns a `fasteval2::Error` if there are any problems, such as
+    /// undefined variables, or complex values where real ones are needed.
+    fn eval_complex(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<(f64, f64), Error>;
+}
+
+/// Returns the real part of `c`, but only if its imaginary part is zero.
+fn to_real(c: C) -> Result<f64, Error> {
+    if c.1 != 0.0 {
+        return Err(Error::NotReal(format!("{}{:+}i", c.0, c.1)));
+    }
+    Ok(c.0)
+}
+
+fn mul(a: C, b: C) -> C {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+fn div(a: C, b: C) -> C {
+    if b.1 == 0.0 {
+        return (a.0 / b.0, a.1 / b.0);
+    }
+    let d = b.0 * b.0 + b.1 * b.1;
+    ((a.0 * b.0 + a.1 * b.1) / d, (a.1 * b.0 - a.0 * b.1) / d)
+}
+
+fn abs(c: C) -> f64 {
+    c.0.hypot(c.1)
+}
+
+fn sqrt(c: C) -> C {
+    if c.1 == 0.0 {
+        return if c.0 >= 0.0 {
+            (c.0.sqrt(), 0.0)
+        } else {
+            (0.0, (-c.0).sqrt())
+        };
+    }
+    let re = ((abs(c) + c.0) / 2.0).sqrt();
+    let im = ((abs(c) - c.0) / 2.0).sqrt();
+    (re, im.copysign(c.1))
+}
+
+fn pow(base: C, power: C) -> C {
+    if power.1 == 0.0 {
+        let n = power.0;
+        // Real results are the same as eval()'s:
+        if base.1 == 0.0 && (base.0 >= 0.0 || n.fract() == 0.0) {
+            return (base.0.powf(n), 0.0);
+        }
+        // Integral powers by squaring, which is exact for small Gaussian integers:
+        if n.fract() == 0.0 && n.abs() <= 64.0 {
+            let (mut acc, mut sq, mut k) = ((1.0, 0.0), base, n.abs() as u32);
+            while k > 0 {
+                if k & 1 == 1 {
+                    acc = mul(acc, sq);
+                }
+                sq = mul(sq, sq);
+                k >>= 1;
+            }
+            return if n < 0.0 { div((1.0, 0.0), acc) } else { acc };
+        }
+    }
+    if base == (0.0, 0.0) {
+        return (0.0, 0.0);
+    }
+    // base^power = exp(power * ln(base)):
+    let ln = (abs(base).ln(), base.1.atan2(base.0));
+    let (re, im) = mul(power, ln);
+    let m = re.exp();
+    (m * im.cos(), m * im.sin())
+}
+
+#[inline]
+fn bool_to_c(b: bool) -> C {
+    (if b { 1.0 } else { 0.0 }, 0.0)
+}
+
+impl BinaryOp {
+    fn binaryop_eval_complex(self, left: C, right: C) -> Result<C, Error> {
+        match self {
+            EAdd => Ok((left.0 + right.0, left.1 + right.1)),
+            ESub => Ok((left.0 - right.0, left.1 - right.1)),
+            EMul => Ok(mul(left, right)),
+            EDiv => Ok(div(left, right)),
+            EExp => Ok(pow(left, right)),
+            // Everything else is only defined for real values:
+            _ => {
+                let (left, right) = (to_real(left)?, to_real(right)?);
+                Ok((self.binaryop_eval(Some(&left), Some(&right)), 0.0))
+            }
+        }
+    }
+}
+
+impl ComplexEvaler for Expression {
+    fn eval_complex(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<C, Error> {
+        // This follows the same order-of-operations as the f64 evaler.
+        // See Expression::eval() for details.
+        if !self.tuple.is_empty() {
+            return Err(Error::NotAllowed("tuple".to_string()));
+        }
+        let mut vals = Vec::<C>::with_capacity(self.pairs.len() + 1);
+        let mut ops = Vec::<BinaryOp>::with_capacity(self.pairs.len());
+        vals.push(self.first.eval_complex(slab, ns)?);
+        for pair in self.pairs.iter() {
+            ns.tick()?;
+            ops.push(pair.0);
+            vals.push(pair.1.eval_complex(slab, ns)?);
+        }
+
+        fn eval_op(vals: &mut Vec<C>, ops: &mut Vec<BinaryOp>, i: usize) -> Result<(), Error> {
+            let (op, left, right) = match (ops.get(i), vals.get(i), vals.get(i + 1)) {
+                (Some(op), Some(left), Some(right)) => (*op, *left, *right),
+                _ => return Err(Error::Unreachable),
+            };
+            let res = op.binaryop_eval_complex(left, right)?;
+            if let Some(val_ref) = vals.get_mut(i) {
+                *val_ref = res;
+            }
+            remove_no_panic(vals, i + 1);
+            remove_no_panic(ops, i);
+            Ok(())
+        }
+
+        for (search, rtol) in OP_PASSES {
+            if rtol {
+                for i in (0..ops.len()).rev() {
+                    if ops.get(i).is_some_and(|op| search.contains(op)) {
+                        eval_op(&mut vals, &mut ops, i)?;
+                    }
+                }
+            } else {
+                let mut i = 0;
+                while let Some(op) = ops.get(i) {
+                    if search.contains(op) {
+                        eval_op(&mut vals, &mut ops, i)?;
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+
+        if !ops.is_empty() || vals.len() != 1 {
+            return Err(Error::Unreachable);
+        }
+        match vals.first() {
+            Some(val) => Ok(*val),
+            None => Err(Error::Unreachable),
+        }
+    }
+}
+
+impl ComplexEvaler for Value {
+    fn eval_complex(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<C, Error> {
+        match self {
+            EConstant(c) => Ok((*c, 0.0)),
+            EUnaryOp(u) => u.eval_complex(slab, ns),
+            EStdFunc(f) => f.eval_complex(slab, ns),
+            EPrintFunc(f) => Ok((f.eval(slab, ns)?, 0.0)),
+            EError(err) => Err(err.clone()),
+        }
+    }
+}
+
+impl ComplexEvaler for UnaryOp {
+    fn eval_complex(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<C, Error> {
+        match self {
+            EPos(val_i) => get_val!(slab.ps, val_i).eval_complex(slab, ns),
+            ENeg(val_i) => {
+                let (re, im) = get_val!(slab.ps, val_i).eval_complex(slab, ns)?;
+                Ok((-re, -im))
+            }
+            ENot(val_i) => Ok(bool_to_c(
+                to_real(get_val!(slab.ps, val_i).eval_complex(slab, ns)?)? == 0.0,
+            )),
+            EParentheses(expr_i) => get_expr!(slab.ps, expr_i).eval_complex(slab, ns),
+        }
+    }
+}
+
+impl ComplexEvaler for StdFunc {
+    fn eval_complex(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<C, Error> {
+        ns.tick()?;
+        match self {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { ptr, .. } => Ok((unsafe { **ptr }, 0.0)),
+
+            EVar(name) => match ns.try_lookup(name, Vec::new(), &mut String::new())? {
+                Some(f) => Ok((f, 0.0)),
+                None if name == "i" => Ok((0.0, 1.0)),
+                None => match named_constant(name) {
+                    Some(f) => Ok((f, 0.0)),
+                    None => Err(Error::UndefinedVariable {
+                        name: name.to_string(),
+                    }),
+                },
+            },
+            EFunc { name, args: xis } => {
+                let mut args = Vec::with_capacity(xis.len());
+                for xi in xis {
+                    args.push(to_real(get_expr!(slab.ps, xi).eval_complex(slab, ns)?)?)
+                }
+                match ns.try_lookup(name, args, &mut String::new())? {
+                    Some(f) => Ok((f, 0.0)),
+                    None => Err(Error::UndefinedFunction {
+                        name: name.to_string(),
+                        arg_count: xis.len(),
+                    }),
+                }
+            }
+
+            EFuncAbs(expr_i) => Ok((abs(get_expr!(slab.ps, expr_i).eval_complex(slab, ns)?), 0.0)),
+            EFuncSqrt(expr_i) => Ok(sqrt(get_expr!(slab.ps, expr_i).eval_complex(slab, ns)?)),
+            EFuncPiecewise { branches, default } => {
+                for (cond_i, val_i) in branches {
+                    if to_real(get_expr!(slab.ps, cond_i).eval_complex(slab, ns)?)? != 0.0 {
+                        return get_expr!(slab.ps, val_i).eval_complex(slab, ns);
+                    }
+                }
+                get_expr!(slab.ps, default).eval_complex(slab, ns)
+            }
+
+            // Everything else has no complex definition, so calculate with f64:
+            _ => Ok((self.eval(slab, ns)?, 0.0)),
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Static cost estimates for expressions.
+//!
+//! [`Expression::cost()`](../parser/struct.Expression.html#method.cost) and
+//! [`Instruction::cost()`](../compiler/enum.Instruction.html#method.cost)
+//! walk an expression without evaluating anything, and count what an
+//! evaluation would do: operators, variable lookups, function calls and
+//! `sum()` loops.  Use this to reject expensive user-submitted formulas
+//! before they reach a hot path.
+//!
+//! The counts are structural: an operator inside a `sum()` body counts once,
+//! no matter how many times the loop runs.  The [`score`](struct.CostReport.html#structfield.score)
+//! combines everything into a single number, using these weights, multiplied
+//! by the number of times that each part runs:
+//!
+//! | Part                                                       | Weight |
+//! |------------------------------------------------------------|--------|
+//! | Constants, parentheses and Unsafe Variables                | 0      |
+//! | Operators, except `^` (including `-x` and `!x`)            | 1      |
+//! | `^`                                                        | 4      |
+//! | Variable lookups (including `sum()` loop variables)        | 2      |
+//! | `int`, `ceil`, `floor`, `abs`, `sign`, `round`, `min`, `max`, `piecewise`, `e`, `pi` | 1 |
+//! | `log`, `interp`, `fma`, trigonometric functions and `sqrt` | 4      |
+//! | Custom functions (including `rand`) and `print`            | 10     |
+//! | Each iteration of a `sum()` loop                           | 1      |
+//!
+//! The number of iterations of a `sum()` is known if its bounds are numeric
+//! literals (in the compiled form, anything that was folded into a constant).
+//! Otherwise, [`max_trips`](struct.CostReport.html#structfield.max_trips) is
+//! `None`, and the score assumes 100 iterations.
+//!
+//! The compiled form counts what it will actually execute: constants are
+//! folded, `x / y` becomes `x * (1/y)`, `max(a, b, c)` becomes two binary
+//! `max` calls, and a sub-expression that is shared by
+//! [common subexpression elimination](../cse/index.html) only counts once.
+//!
+//! # Example
+//! ```
+//! use fasteval2::{Parser, Slab};
+//!
+//! let mut slab = Slab::new();
+//! let expr_ref = Parser::new()
+//!     .parse("sum(i, 1, 10, sin(i*x)) + f(y) + y", &mut slab.ps)
+//!     .unwrap()
+//!     .from(&slab.ps);
+//! let cost = expr_ref.cost(&slab);
+//! assert_eq!(cost.ops, 3);
+//! assert_eq!(cost.vars, 2); // x and y.  (`i` is the loop variable.)
+//! assert_eq!(cost.call_count(), 2);
+//! assert_eq!(cost.calls["sin"], 1);
+//! assert_eq!(cost.max_trips, Some(10));
+//! ```
+
+#[cfg(feature = "unsafe-vars")]
+use crate::compiler::Instruction::IUnsafeVar;
+use crate::compiler::{
+    Instruction::{
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    },
+    IC,
+};
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    BinaryOp::EExp,
+    Expression,
+    ExpressionOrString::{EExpr, EStr},
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+const OP: f64 = 1.0;
+const POW: f64 = 4.0;
+const VAR: f64 = 2.0;
+const CHEAP: f64 = 1.0;
+const MATH: f64 = 4.0;
+const CALL: f64 = 10.0;
+const ITERATION: f64 = 1.0;
+const UNKNOWN_TRIPS: f64 = 100.0;
+
+/// What an evaluation would do.  [See the `cost module` documentation.](index.html)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostReport {
+    /// The number of operators, including `-x` and `!x`.
+    pub ops: usize,
+    /// The number of distinct variables that are looked up in the namespace.
+    /// `sum()` loop variables and Unsafe Variables are not included.
+    pub vars: usize,
+    /// The number of calls of each function, both built-in (like `sin`) and
+    /// custom.  `sum()` loops are counted in `loops` instead.
+    pub calls: BTreeMap<String, usize>,
+    /// The number of `sum()` loops.
+    pub loops: usize,
+    /// The largest number of times that a loop body runs during one
+    /// evaluation (nested loops multiply).  This is `Some(0)` if there are no
+    /// loops, and `None` if a loop's bounds aren't constant.
+    pub max_trips: Option<u64>,
+    /// The weighted cost, for comparing expressions with each other.
+    pub score: f64,
+}
+
+impl CostReport {
+    /// The total number of function calls.
+    pub fn call_count(&self) -> usize {
+        self.calls.values().sum()
+    }
+}
+
+impl Expression {
+    /// Estimates the cost of evaluating this `Expression`, without
+    /// evaluating anything.  [See the `cost module` documentation.](../cost/index.html)
+    pub fn cost(&self, slab: &Slab) -> CostReport {
+        let mut walk = Walk::default();
+        walk.expr(self, slab);
+        walk.finish()
+    }
+}
+
+impl Instruction {
+    /// Estimates the cost of evaluating this `Instruction`, without
+    /// evaluating anything.  [See the `cost module` documentation.](../cost/index.html)
+    pub fn cost(&self, slab: &Slab) -> CostReport {
+        let mut walk = Walk::default();
+        walk.instr(self, slab);
+        walk.finish()
+    }
+}
+
+struct Walk<'a> {
+    report: CostReport,
+    vars: BTreeSet<&'a str>,
+    bound: Vec<&'a str>, // Loop variables of the enclosing sum()s.
+    temps: BTreeSet<usize>,
+    runs: f64, // How many times the current part runs.
+    max_trips: Option<f64>,
+}
+
+impl Default for Walk<'_> {
+    fn default() -> Self {
+        Walk {
+            report: CostReport::default(),
+            vars: BTreeSet::new(),
+            bound: Vec::new(),
+            temps: BTreeSet::new(),
+            runs: 1.0,
+            max_trips: Some(0.0),
+        }
+    }
+}
+
+impl<'a> Walk<'a> {
+    fn finish(mut self) -> CostReport {
+        self.report.vars = self.vars.len();
+        self.report.max_trips = self.max_trips.map(|n| n as u64);
+        self.report
+    }
+
+    fn op(&mut self, weight: f64) {
+        self.report.ops += 1;
+        self.report.score += weight * self.runs;
+    }
+
+    fn call(&mut self, name: &str, weight: f64) {
+        *self.report.calls.entry(name.to_string()).or_insert(0) += 1;
+        self.report.score += weight * self.runs;
+    }
+
+    fn var(&mut self, name: &'a str) {
+        if !self.bound.contains(&name) {
+            self.vars.insert(name);
+        }
+        self.report.score += VAR * self.runs;
+    }
+
+    // `body` walks the loop body.  The bounds have already been walked.
+    fn sum(
+        &mut self,
+        var: &'a str,
+        from: Option<f64>,
+        to: Option<f64>,
+        body: impl FnOnce(&mut Self),
+    ) {
+        self.report.loops += 1;
+        let trips = match (from, to) {
+            (Some(from), Some(to)) if from <= to => Some((to - from).floor() + 1.0),
+            (Some(_), Some(_)) => Some(0.0),
+            _ => None,
+        };
+        let outer = self.runs;
+        self.runs *= trips.unwrap_or(UNKNOWN_TRIPS);
+        self.max_trips = match (self.max_trips, trips) {
+            (Some(max), Some(_)) => Some(max.max(self.runs)),
+            _ => None,
+        };
+        self.report.score += ITERATION * self.runs;
+        self.scope(var, body);
+        self.runs = outer;
+    }
+
+    // `body` walks the body of a `let` or `sum()`, where `var` is bound.
+    fn scope(&mut self, var: &'a str, body: impl FnOnce(&mut Self)) {
+        self.bound.push(var);
+        body(self);
+        self.bound.pop();
+    }
+
+    //---- Parsed form:
+
+    fn expr(&mut self, expr: &'a Expression, slab: &'a Slab) {
+        self.value(&expr.first, slab);
+        for pair in &expr.pairs {
+            self.op(if pair.0 == EExp { POW } else { OP });
+            self.value(&pair.1, slab);
+        }
+        for xi in &expr.tuple {
+            self.expr(get_expr!(slab.ps, xi), slab);
+        }
+    }
+
+    fn value(&mut self, val: &'a Value, slab: &'a Slab) {
+        match val {
+            EConstant(_) | EError(_) => (),
+            EUnaryOp(EPos(val_i)) => self.value(get_val!(slab.ps, val_i), slab),
+            EUnaryOp(ENeg(val_i)) | EUnaryOp(ENot(val_i)) => {
+                self.op(OP);
+                self.value(get_val!(slab.ps, val_i), slab);
+            }
+            EUnaryOp(EParentheses(expr_i)) => self.expr(get_expr!(slab.ps, expr_i), slab),
+            EStdFunc(f) => self.stdfunc(f, slab),
+            EPrintFunc(f) => {
+                self.call("print", CALL);
+                for x_or_s in &f.0 {
+                    match x_or_s {
+                        EExpr(xi) => self.expr(get_expr!(slab.ps, xi), slab),
+                        EStr(_) => (),
+                    }
+                }
+            }
+        }
+    }
+
+    fn stdfunc(&mut self, f: &'a StdFunc, slab: &'a Slab) {
+        let (name, weight, args): (&str, f64, Vec<_>) = match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { .. } => return,
+            EVar(name) => return self.var(name),
+            EFunc { name, args } => (name, CALL, args.iter().collect()),
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                let (from, to) = (get_expr!(slab.ps, from), get_expr!(slab.ps, to));
+                self.expr(from, slab);
+                self.expr(to, slab);
+                let body = get_expr!(slab.ps, expr);
+                return self.sum(var, literal(from, slab), literal(to, slab), |walk| {
+                    walk.expr(body, slab)
+                });
+            }
+            EFuncLet { var, value, expr } => {
+                self.expr(get_expr!(slab.ps, value), slab);
+                let body = get_expr!(slab.ps, expr);
+                return self.scope(var, |walk| walk.expr(body, slab));
+            }
+
+            EFuncInt(xi) => ("int", CHEAP, vec![xi]),
+            EFuncCeil(xi) => ("ceil", CHEAP, vec![xi]),
+            EFuncFloor(xi) => ("floor", CHEAP, vec![xi]),
+            EFuncAbs(xi) => ("abs", CHEAP, vec![xi]),
+            EFuncSign(xi) => ("sign", CHEAP, vec![xi]),
+            EFuncRound { modulus, expr } => {
+                ("round", CHEAP, modulus.iter().chain([expr]).collect())
+            }
+            EFuncMin { first, rest } => ("min", CHEAP, [first].into_iter().chain(rest).collect()),
+            EFuncMax { first, rest } => ("max", CHEAP, [first].into_iter().chain(rest).collect()),
+            EFuncPiecewise { branches, default } => (
+                "piecewise",
+                CHEAP,
+                branches
+                    .iter()
+                    .flat_map(|(c, v)| [c, v])
+                    .chain([default])
+                    .collect(),
+            ),
+            EFuncE => ("e", CHEAP, vec![]),
+            EFuncPi => ("pi", CHEAP, vec![]),
+
+            EFuncLog { base, expr } => ("log", MATH, base.iter().chain([expr]).collect()),
+            EFuncInterp {
+                extrapolate,
+                x,
+                points,
+            } => (
+                if *extrapolate { "interp_ext" } else { "interp" },
+                MATH,
+                [x].into_iter()
+                    .chain(points.iter().flat_map(|(px, py)| [px, py]))
+                    .collect(),
+            ),
+            EFuncFma { a, b, c } => ("fma", MATH, vec![a, b, c]),
+            EFuncCoalesce { value, fallback } => ("coalesce", CHEAP, vec![value, fallback]),
+            EFuncIn { value, list, .. } => ("in", CHEAP, [value].into_iter().chain(list).collect()),
+            EFuncQuotient { dividend, divisor } => ("quotient", MATH, vec![dividend, divisor]),
+            EFuncRemainder { dividend, divisor } => ("remainder", CHEAP, vec![dividend, divisor]),
+            EFuncNorm { squared, args } => (
+                if *squared { "sumsq" } else { "norm" },
+                MATH,
+                args.iter().collect(),
+            ),
+            EFuncSin(xi) => ("sin", MATH, vec![xi]),
+            EFuncCos(xi) => ("cos", MATH, vec![xi]),
+            EFuncTan(xi) => ("tan", MATH, vec![xi]),
+            EFuncASin(xi) => ("asin", MATH, vec![xi]),
+            EFuncACos(xi) => ("acos", MATH, vec![xi]),
+            EFuncATan(xi) => ("atan", MATH, vec![xi]),
+            EFuncSinH(xi) => ("sinh", MATH, vec![xi]),
+            EFuncCosH(xi) => ("cosh", MATH, vec![xi]),
+            EFuncTanH(xi) => ("tanh", MATH, vec![xi]),
+            EFuncASinH(xi) => ("asinh", MATH, vec![xi]),
+            EFuncACosH(xi) => ("acosh", MATH, vec![xi]),
+            EFuncATanH(xi) => ("atanh", MATH, vec![xi]),
+            EFuncSqrt(xi) => ("sqrt", MATH, vec![xi]),
+        };
+        self.call(name, weight);
+        for xi in args {
+            self.expr(get_expr!(slab.ps, xi), slab);
+        }
+    }
+
+    //---- Compiled form:
+
+    fn ic(&mut self, ic: &'a IC, slab: &'a Slab) {
+        if let IC::I(i) = ic {
+            self.instr(get_instr!(slab.cs, i), slab);
+        }
+    }
+
+    fn instr(&mut self, instr: &'a Instruction, slab: &'a Slab) {
+        let call = match instr {
+            IConst(_) | IError(_) => return,
+            #[cfg(feature = "unsafe-vars")]
+            IUnsafeVar { .. } => return,
+            IVar(name) => return self.var(name),
+            ITemps { body: i, .. } => return self.instr(get_instr!(slab.cs, i), slab),
+            ITemp { slot, instr: i } => {
+                // The value is reused after the first time:
+                if self.temps.insert(*slot) {
+                    self.instr(get_instr!(slab.cs, i), slab);
+                }
+                return;
+            }
+            IFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                self.ic(from, slab);
+                self.ic(to, slab);
+                let constant = |ic: &IC| match ic {
+                    IC::C(c) => Some(*c),
+                    IC::I(_) => None,
+                };
+                return self.sum(var, constant(from), constant(to), |walk| {
+                    walk.ic(expr, slab)
+                });
+            }
+            IFuncLet { var, value, expr } => {
+                self.ic(value, slab);
+                return self.scope(var, |walk| walk.ic(expr, slab));
+            }
+            INeg(_)
+            | INot(_)
+            | IInv(_)
+            | IAdd(..)
+            | IMul(..)
+            | IMod { .. }
+            | ILT(..)
+            | ILTE(..)
+            | IEQ(..)
+            | INE(..)
+            | IGTE(..)
+            | IGT(..)
+            | IOR(..)
+            | IAND(..) => {
+                self.op(OP);
+                None
+            }
+            IExp { .. } => {
+                self.op(POW);
+                None
+            }
+            IFunc { name, .. } => Some((name.as_str(), CALL)),
+            IPrintFunc(_) => Some(("print", CALL)),
+            IFuncInt(_) => Some(("int", CHEAP)),
+            IFuncCeil(_) => Some(("ceil", CHEAP)),
+            IFuncFloor(_) => Some(("floor", CHEAP)),
+            IFuncAbs(_) => Some(("abs", CHEAP)),
+            IFuncSign(_) => Some(("sign", CHEAP)),
+            IFuncRound { .. } => Some(("round", CHEAP)),
+            IFuncMin(..) => Some(("min", CHEAP)),
+            IFuncMax(..) => Some(("max", CHEAP)),
+            IFuncPiecewise { .. } => Some(("piecewise", CHEAP)),
+            IFuncLog { .. } => Some(("log", MATH)),
+            IFuncInterp { extrapolate, .. } => {
+                Some((if *extrapolate { "interp_ext" } else { "interp" }, MATH))
+            }
+            IFuncFma { .. } => Some(("fma", MATH)),
+            IFuncCoalesce { .. } => Some(("coalesce", CHEAP)),
+            IFuncIn { .. } => Some(("in", CHEAP)),
+            IFuncQuotient { .. } => Some(("quotient", MATH)),
+            IFuncNorm { squared, .. } => Some((if *squared { "sumsq" } else { "norm" }, MATH)),
+            IFuncSin(_) => Some(("sin", MATH)),
+            IFuncCos(_) => Some(("cos", MATH)),
+            IFuncTan(_) => Some(("tan", MATH)),
+            IFuncASin(_) => Some(("asin", MATH)),
+            IFuncACos(_) => Some(("acos", MATH)),
+            IFuncATan(_) => Some(("atan", MATH)),
+            IFuncSinH(_) => Some(("sinh", MATH)),
+            IFuncCosH(_) => Some(("cosh", MATH)),
+            IFuncTanH(_) => Some(("tanh", MATH)),
+            IFuncASinH(_) => Some(("asinh", MATH)),
+            IFuncACosH(_) => Some(("acosh", MATH)),
+            IFuncATanH(_) => Some(("atanh", MATH)),
+            IFuncSqrt(_) => Some(("sqrt", MATH)),
+        };
+        if let Some((name, weight)) = call {
+            self.call(name, weight);
+        }
+        match instr {
+            IPrintFunc(f) => {
+                for x_or_s in &f.0 {
+                    if let EExpr(xi) = x_or_s {
+                        self.expr(get_expr!(slab.ps, xi), slab);
+                    }
+                }
+            }
+            _ => {
+                for i in instr.children() {
+                    self.instr(get_instr!(slab.cs, i), slab);
+                }
+            }
+        }
+    }
+}
+
+// A numeric literal, possibly negated or in parentheses:
+fn literal(expr: &Expression, slab: &Slab) -> Option<f64> {
+    if !expr.pairs.is_empty() {
+        return None;
+    }
+    let mut val = &expr.first;
+    let mut sign = 1.0;
+    loop {
+        match val {
+            EConstant(c) => return Some(sign * c),
+            EUnaryOp(ENeg(val_i)) => {
+                sign = -sign;
+                val = get_val!(slab.ps, val_i);
+            }
+            EUnaryOp(EPos(val_i)) => val = get_val!(slab.ps, val_i),
+            EUnaryOp(EParentheses(expr_i)) => {
+                return literal(get_expr!(slab.ps, expr_i), slab).map(|c| sign * c)
+            }
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Common subexpression elimination for compiled `Instruction`s.
+//!
+//! Formulas often repeat sub-terms: `(s-k)*(s-k)*vol` computes `s-k` twice,
+//! and bigger formulas repeat whole call trees.
+//! [`Instruction::eliminate_common_subexpressions()`](../compiler/enum.Instruction.html#method.eliminate_common_subexpressions)
+//! finds structurally identical sub-expressions and replaces every use with an
+//! `ITemp` that refers to a single copy, so each of them is computed at most
+//! once per evaluation.  The shared values are computed at their first use,
+//! so short-circuits and `piecewise()` branches behave exactly as before: a
+//! shared sub-expression that isn't reached isn't evaluated.
+//!
+//! Sub-expressions are never shared if they contain `print()`, `rand()`,
+//! `randint()`, `now()` or an error placeholder, or if they are inside the body of a
+//! `sum()`, where the loop variable changes between iterations, or of a `let`,
+//! where the bound name can shadow a variable.  (A whole `sum()` or `let` can
+//! be shared.)  Variables and custom functions are assumed to
+//! return the same value for the same arguments during an evaluation.
+//!
+//! If you also use the [`Optimizer`](../optimizer/struct.Optimizer.html), run
+//! it first.
+//!
+//! # Example
+//! ```
+//! use fasteval2::{Compiler, Evaler};
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = fasteval2::Slab::new();
+//!     let instr = fasteval2::Parser::new()
+//!         .parse("(s-k)*(s-k)*vol", &mut slab.ps)?
+//!         .from(&slab.ps)
+//!         .compile(&slab.ps, &mut slab.cs, &mut fasteval2::EmptyNamespace);
+//!
+//!     let (instr, report) = instr.eliminate_common_subexpressions(&mut slab.cs);
+//!     assert_eq!(report.temps, 1);
+//!     assert_eq!(report.eliminated, 4); // `s`, `k`, `-k` and `s + -k`.
+//!
+//!     let mut lookups = 0;
+//!     let mut ns = |name: &str, _: Vec<f64>| {
+//!         lookups += 1;
+//!         match name {
+//!             "s" => Some(5.0),
+//!             "k" => Some(3.0),
+//!             "vol" => Some(0.5),
+//!             _ => None,
+//!         }
+//!     };
+//!     assert_eq!(instr.eval(&slab, &mut ns)?, 2.0);
+//!     assert_eq!(lookups, 3);
+//!     Ok(())
+//! }
+//! ```
+
+use crate::compiler::{
+    Instruction::{
+        self, IConst, IError, IFunc, IFuncLet, IFuncSum, IPrintFunc, ITemp, ITemps, IVar,
+    },
+    InstructionI, IC,
+};
+use crate::evalns::is_impure;
+use crate::slab::CompileSlab;
+
+use alloc::collections::BTreeMap;
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+use core::mem;
+
+/// What [`eliminate_common_subexpressions()`](../compiler/enum.Instruction.html#method.eliminate_common_subexpressions) did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CseReport {
+    /// The number of shared sub-expressions, each of which has a temporary slot.
+    pub temps: usize,
+    /// The number of `Instruction`s that no longer need to be evaluated.
+    pub eliminated: usize,
+}
+
+impl Instruction {
+    /// Shares the repeated sub-expressions of this `Instruction`, so that each
+    /// of them is only evaluated once.
+    ///
+    /// The results of evaluation don't change.  If nothing is repeated, the
+    /// `Instruction` is returned unchanged; otherwise it is wrapped in an
+    /// `ITemps`.  [See the `cse module` documentation.](../cse/index.html)
+    pub fn eliminate_common_subexpressions(
+        self,
+        cslab: &mut CompileSlab,
+    ) -> (Instruction, CseReport) {
+        let root = cslab.push_instr(self);
+        let mut cse = Cse {
+            vn_of: vec![0; cslab.instrs.len()],
+            ..Cse::default()
+        };
+        cse.number(root.0, false, cslab);
+
+        // Count the uses of each distinct sub-expression:
+        let mut refs = vec![0usize; cse.reps.len()];
+        for &rep in &cse.reps {
+            for child in cslab.instrs[rep].children() {
+                refs[cse.vn_of[child.0]] += 1;
+            }
+        }
+        let mut temps = 0;
+        cse.slot_of = (0..cse.reps.len())
+            .map(|vn| {
+                (refs[vn] > 1 && cse.shareable[vn]).then(|| {
+                    temps += 1;
+                    temps - 1
+                })
+            })
+            .collect();
+        if temps == 0 {
+            let report = CseReport::default();
+            return (cslab.take_instr(root), report);
+        }
+
+        cse.done = vec![false; temps];
+        cse.rewrite(root.0, cslab);
+        let report = CseReport {
+            temps,
+            eliminated: cse.before - cse.after,
+        };
+        (
+            ITemps {
+                slots: temps,
+                body: root,
+            },
+            report,
+        )
+    }
+}
+
+#[derive(Default)]
+struct Cse {
+    // Value numbers: Instructions with the same number compute the same value.
+    vn_of: Vec<usize>,
+    keys: BTreeMap<String, usize>,
+    // These are indexed by value number:
+    reps: Vec<usize>, // The first Instruction with each number.
+    pure: Vec<bool>,
+    shareable: Vec<bool>,
+    slot_of: Vec<Option<usize>>,
+
+    done: Vec<bool>, // By slot.
+    before: usize,
+    after: usize,
+}
+
+impl Cse {
+    // Assigns value numbers to the tree at `i`, bottom-up.
+    fn number(&mut self, i: usize, in_loop: bool, cslab: &mut CompileSlab) -> usize {
+        self.before += 1;
+        let mut node = mem::take(&mut cslab.instrs_mut()[i]);
+        let body = match &node {
+            IFuncSum { expr: IC::I(e), .. } | IFuncLet { expr: IC::I(e), .. } => Some(*e),
+            _ => None,
+        };
+        let mut pure = match &node {
+            IPrintFunc(_) | IError(_) => false,
+            IVar(name) | IFunc { name, .. } => !is_impure(name),
+            _ => true,
+        };
+
+        // The key is the node with its children replaced by their value numbers:
+        let mut children = Vec::new();
+        for child in node.children_mut() {
+            let vn = self.number(child.0, in_loop || body == Some(*child), cslab);
+            pure &= self.pure[vn];
+            children.push(*child);
+            *child = InstructionI(vn);
+        }
+        // Loop bodies get their own keys, so they never match anything outside:
+        let key = pure.then(|| format!("{}{:?}", if in_loop { "loop " } else { "" }, node));
+        for (child, orig) in node.children_mut().into_iter().zip(children) {
+            *child = orig;
+        }
+        let shareable = pure && !in_loop && !matches!(node, IConst(_));
+        cslab.instrs_mut()[i] = node;
+
+        let existing = key.as_ref().and_then(|key| self.keys.get(key).copied());
+        let vn = existing.unwrap_or_else(|| {
+            let vn = self.reps.len();
+            self.reps.push(i);
+            self.pure.push(pure);
+            self.shareable.push(shareable);
+            if let Some(key) = key {
+                self.keys.insert(key, vn);
+            }
+            vn
+        });
+        self.vn_of[i] = vn;
+        vn
+    }
+
+    // Redirects the uses of shared sub-expressions to `ITemp`s, top-down.
+    fn rewrite(&mut self, i: usize, cslab: &mut CompileSlab) {
+        self.after += 1;
+        let mut node = mem::take(&mut cslab.instrs_mut()[i]);
+        for child in node.children_mut() {
+            let vn = self.vn_of[child.0];
+            match self.slot_of[vn] {
+                Some(slot) => {
+                    let rep = self.reps[vn];
+                    if !self.done[slot] {
+                        self.done[slot] = true;
+                        self.rewrite(rep, cslab);
+                    }
+                    *child = cslab.push_instr(ITemp {
+                        slot,
+                        instr: InstructionI(rep),
+                    });
+                }
+                None => self.rewrite(child.0, cslab),
+            }
+        }
+        cslab.instrs_mut()[i] = node;
+    }
+}
+
+/// Numbers the distinct values of the tree at `root`, for the memo of a stable
+/// evaluation (see `EvalNamespace::is_stable()`).  Returns the class of each
+/// Instruction of `cs` (`None` if it can't be memoized) and the number of
+/// classes.
+///
+/// The classes use the same keys as `Cse::number()`, but `cs` isn't changed,
+/// and the walks use explicit stacks, because the evaluator that uses them is
+/// the one for deep trees.  `ITemp`s and `ITemps` have the class of what they
+/// wrap.
+pub(crate) fn value_classes(root: &Instruction, cs: &CompileSlab) -> (Vec<Option<usize>>, usize) {
+    let n = cs.instrs.len();
+    let body_of = |instr: &Instruction| match instr {
+        IFuncSum { expr: IC::I(e), .. } | IFuncLet { expr: IC::I(e), .. } => Some(e.0),
+        _ => None,
+    };
+
+    // Find what is reached from inside a loop body, top-down.  A node is
+    // walked again if it is first reached outside, and later inside:
+    let mut reach = vec![0u8; n]; // 0: unreached, 1: outside loops, 2: inside.
+    let mut work: Vec<(usize, bool)> = (root.children().into_iter())
+        .map(|child| (child.0, body_of(root) == Some(child.0)))
+        .collect();
+    while let Some((i, in_loop)) = work.pop() {
+        let level = if in_loop { 2 } else { 1 };
+        if i >= n || reach[i] >= level {
+            continue;
+        }
+        reach[i] = level;
+        let node = &cs.instrs[i];
+        let body = body_of(node);
+        work.extend(
+            (node.children().into_iter()).map(|child| (child.0, in_loop || body == Some(child.0))),
+        );
+    }
+
+    // Number the reached nodes bottom-up, with a post-order walk.  A node
+    // that is reached again while it is being numbered (which only bad data
+    // can do) is impure:
+    const PENDING: usize = usize::MAX;
+    let mut vn_of: Vec<Option<usize>> = vec![None; n];
+    let mut pure = Vec::new();
+    let mut keys = BTreeMap::new();
+    let mut work: Vec<(usize, bool)> = (root.children().into_iter())
+        .map(|child| (child.0, false))
+        .collect();
+    while let Some((i, children_done)) = work.pop() {
+        if i >= n || (!children_done && vn_of[i].is_some()) {
+            continue;
+        }
+        let node = &cs.instrs[i];
+        if !children_done {
+            vn_of[i] = Some(PENDING);
+            work.push((i, true));
+            work.extend(node.children().into_iter().map(|child| (child.0, false)));
+            continue;
+        }
+
+        // Like `ITemp`s, `ITemps` compute what they wrap:
+        let wrapped = match node {
+            ITemp { instr, .. } => Some(instr.0),
+            ITemps { body, .. } => Some(body.0),
+            _ => None,
+        };
+        let vn = match wrapped.and_then(|w| vn_of.get(w).copied().flatten()) {
+            Some(vn) if vn != PENDING => vn,
+            _ => {
+                let mut node_pure = match node {
+                    IPrintFunc(_) | IError(_) => false,
+                    IVar(name) | IFunc { name, .. } => !is_impure(name),
+                    _ => wrapped.is_none(),
+                };
+                for child in node.children() {
+                    match vn_of.get(child.0).copied().flatten() {
+                        Some(vn) if vn != PENDING => node_pure &= pure[vn],
+                        _ => node_pure = false,
+                    }
+                }
+                let in_loop = reach[i] == 2;
+                let key = node_pure.then(|| renumbered_key(node, in_loop, &vn_of));
+                match key.as_ref().and_then(|key| keys.get(key).copied()) {
+                    Some(vn) => vn,
+                    None => {
+                        let vn = pure.len();
+                        pure.push(node_pure);
+                        if let Some(key) = key {
+                            keys.insert(key, vn);
+                        }
+                        vn
+                    }
+                }
+            }
+        };
+        vn_of[i] = Some(vn);
+    }
+
+    // Renumber the classes that can be memoized:
+    let mut class_of_vn = vec![None; pure.len()];
+    let mut classes = 0;
+    let class_of = (0..n)
+        .map(|i| {
+            let vn = vn_of[i].filter(|&vn| vn != PENDING)?;
+            let memo = pure[vn] && reach[i] == 1 && !matches!(cs.instrs[i], IConst(_));
+            if !memo {
+                return None;
+            }
+            Some(*class_of_vn[vn].get_or_insert_with(|| {
+                classes += 1;
+                classes - 1
+            }))
+        })
+        .collect();
+    (class_of, classes)
+}
+
+// Formats the key of `node` for `value_classes()`, which can't edit the node
+// like `Cse::number()` does: the indices of the children, which `Debug` writes
+// as `InstructionI(i)`, are replaced by their value numbers.  (Only the keys
+// of pure nodes are formatted, and their names and constants can't contain
+// that text.)
+fn renumbered_key(node: &Instruction, in_loop: bool, vn_of: &[Option<usize>]) -> String {
+    const INDEX: &str = "InstructionI(";
+    let debug = format!("{:?}", node);
+    let mut key = String::from(if in_loop { "loop " } else { "" });
+    let mut rest = debug.as_str();
+    while let Some(at) = rest.find(INDEX) {
+        let (before, after) = rest.split_at(at + INDEX.len());
+        key.push_str(before);
+        let end = after.find(')').unwrap_or(after.len());
+        let vn = after[..end]
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| vn_of.get(i).copied().flatten());
+        let _ = write!(key, "{:?}", vn);
+        rest = &after[end..];
+    }
+    key.push_str(rest);
+    key
+}
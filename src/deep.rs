@@ -0,0 +1,649 @@
+//! Evaluates compiled `Instruction`s with an explicit stack, instead of
+//! recursion.
+//!
+//! `Instruction::eval()` is recursive, which is fast, but every level of
+//! nesting takes a native stack frame.  So `eval()` only recurses while the
+//! Instructions of the `CompileSlab` are nested at most `MAX_RECURSIVE`
+//! levels deep (the depth is measured once, after the slab changes).  Deeper
+//! slabs are evaluated here, where the pending work and the intermediate
+//! values live in `Vec`s on the heap, so that the nesting depth is only
+//! limited by memory.  This allocates, so `eval_with_stack()` is only free of
+//! allocations for the shallower slabs.
+//!
+//! The results, errors and `tick()`s are the same as those of the recursive
+//! evaluator, step by step.  The difference is that the temporaries and the
+//! `interp()` breakpoints are kept here too, and never on the working stack
+//! of [`CompiledExpression::eval_with_stack()`](../bytecode/struct.CompiledExpression.html#method.eval_with_stack).
+//!
+//! Stable Namespaces (see `EvalNamespace::is_stable()`) are always evaluated
+//! here, because this evaluator also memoizes: the value of each class of
+//! equal pure sub-expressions is kept after its first evaluation, and reused
+//! without `tick()`s.
+
+#[cfg(feature = "unsafe-vars")]
+use crate::compiler::Instruction::IUnsafeVar;
+use crate::compiler::{
+    coalesce, interp, log,
+    Instruction::{
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    },
+    InstructionI, SumSq, IC,
+};
+use crate::cse::value_classes;
+use crate::error::Error;
+use crate::evaler::{check_sum_bounds, is_null, named_constant, null_or, null_or2, Evaler};
+use crate::evalns::{EvalNamespace, Layer, LayerNamespace, StableNamespace};
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+use crate::slab::Slab;
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// The deepest nesting of a `CompileSlab` that `eval()` evaluates
+/// recursively.  It is enough for a sum of 30 terms, and 32 levels take
+/// less than 8 KiB of stack in optimized builds, and less than 1 MiB in
+/// unoptimized ones.
+pub(crate) const MAX_RECURSIVE: usize = 32;
+
+// The pending work of an evaluation.  Each step pushes one value (or fails),
+// unless it is noted otherwise.
+enum Step<'s> {
+    // Like `eval_compiled_ref!()`: constants are read without a `tick()`.
+    Child(InstructionI),
+    // Like `eval_ic_ref!()`.
+    Ic(&'s IC),
+    // A call of `eval()`.
+    Eval(&'s Instruction),
+    // Computes the result of an `Instruction` from the values of its operands,
+    // which are on top of the value stack.
+    Apply(&'s Instruction),
+    // The left operand is on top of the value stack.
+    And(&'s IC),
+    Or(&'s IC),
+    // The condition of `branches[i]` is on top of the value stack.
+    Branch {
+        branches: &'s [(IC, IC)],
+        default: &'s IC,
+        i: usize,
+    },
+    // The value of an `in` is on top of the value stack, under the value of
+    // `list[i - 1]` if `i > 0`.
+    In {
+        negated: bool,
+        list: &'s [IC],
+        i: usize,
+    },
+    // The bounds of a `sum()` are on top of the value stack.
+    SumStart {
+        var: &'s str,
+        expr: &'s IC,
+    },
+    // The next iteration of a `sum()`.  After the first one, the loop variable
+    // is the innermost layer, and the value of the body is on top of the value
+    // stack.
+    Sum {
+        var: &'s str,
+        val: f64,
+        to: f64,
+        expr: &'s IC,
+        sum: f64,
+        first: bool,
+    },
+    // The value of a `let` is on top of the value stack.
+    Let {
+        var: &'s str,
+        expr: &'s IC,
+    },
+    // Stores the value on top of the value stack, and leaves it there.
+    SetTemp(usize),
+    // Removes the innermost layer, and leaves the value stack alone.
+    PopLayer,
+    // Memoizes the value on top of the value stack, and leaves it there.
+    Memo(usize),
+}
+
+// The values of the sub-expressions of a stable evaluation, by class.  Both
+// are empty otherwise.
+#[derive(Default)]
+struct Memo {
+    class_of: Vec<Option<usize>>,
+    vals: Vec<Option<f64>>,
+}
+
+impl Memo {
+    // Evaluates the Instruction at `i`, unless its value is known:
+    #[inline]
+    fn eval<'s>(
+        &self,
+        i: InstructionI,
+        instr: &'s Instruction,
+        work: &mut Vec<Step<'s>>,
+        vals: &mut Vec<f64>,
+    ) {
+        match self.class_of.get(i.0).copied().flatten() {
+            Some(class) => match self.vals.get(class).copied().flatten() {
+                Some(val) => vals.push(val),
+                None => {
+                    work.push(Step::Memo(class));
+                    work.push(Step::Eval(instr));
+                }
+            },
+            None => work.push(Step::Eval(instr)),
+        }
+    }
+}
+
+// Pops an operand.  The steps always leave the operands that they need, so
+// the NaN is never used.
+#[inline]
+fn pop(vals: &mut Vec<f64>) -> f64 {
+    vals.pop().unwrap_or(f64::NAN)
+}
+
+/// Evaluates `instr` like `instr.eval(slab, ns)` does, but without
+/// recursion.
+pub(crate) fn eval(
+    instr: &Instruction,
+    slab: &Slab,
+    ns: &mut dyn EvalNamespace,
+) -> Result<f64, Error> {
+    if ns.is_stable() {
+        let (class_of, classes) = value_classes(instr, &slab.cs);
+        let memo = Memo {
+            class_of,
+            vals: vec![None; classes],
+        };
+        return run(instr, slab, &mut StableNamespace::new(ns), memo);
+    }
+    run(instr, slab, ns, Memo::default())
+}
+
+fn run(
+    instr: &Instruction,
+    slab: &Slab,
+    ns: &mut dyn EvalNamespace,
+    mut memo: Memo,
+) -> Result<f64, Error> {
+    let mut ns = LayerNamespace {
+        layers: Vec::new(),
+        inner: ns,
+    };
+    let mut work = vec![Step::Eval(instr)];
+    let mut vals = Vec::new();
+
+    while let Some(step) = work.pop() {
+        match step {
+            Step::Child(i) => match get_instr!(slab.cs, i) {
+                IConst(c) => vals.push(*c),
+                #[cfg(feature = "unsafe-vars")]
+                IUnsafeVar { ptr, .. } => vals.push(unsafe { **ptr }),
+                instr => memo.eval(i, instr, &mut work, &mut vals),
+            },
+            Step::Ic(ic) => match ic {
+                IC::C(c) => vals.push(*c),
+                IC::I(i) => match get_instr!(slab.cs, i) {
+                    #[cfg(feature = "unsafe-vars")]
+                    IUnsafeVar { ptr, .. } => vals.push(unsafe { **ptr }),
+                    instr => memo.eval(*i, instr, &mut work, &mut vals),
+                },
+            },
+            Step::Eval(instr) => {
+                ns.tick()?;
+                schedule(instr, slab, &mut ns, &mut work, &mut vals)?;
+            }
+            Step::Apply(instr) => {
+                let val = apply(instr, &mut ns, &mut vals)?;
+                vals.push(val);
+            }
+            Step::And(ric) => {
+                let left = pop(&mut vals);
+                if f64_eq!(left, 0.0) {
+                    vals.push(left);
+                } else {
+                    work.push(Step::Ic(ric));
+                }
+            }
+            Step::Or(ric) => {
+                let left = pop(&mut vals);
+                if f64_ne!(left, 0.0) {
+                    vals.push(left);
+                } else {
+                    work.push(Step::Ic(ric));
+                }
+            }
+            Step::Branch {
+                branches,
+                default,
+                i,
+            } => {
+                if f64_ne!(pop(&mut vals), 0.0) {
+                    work.push(Step::Ic(&branches[i].1));
+                } else if let Some((cond_ic, _)) = branches.get(i + 1) {
+                    work.push(Step::Branch {
+                        branches,
+                        default,
+                        i: i + 1,
+                    });
+                    work.push(Step::Ic(cond_ic));
+                } else {
+                    work.push(Step::Ic(default));
+                }
+            }
+            Step::In { negated, list, i } => {
+                let item = if i > 0 { Some(pop(&mut vals)) } else { None };
+                let value = pop(&mut vals);
+                let found = matches!(item, Some(item) if f64_eq!(value, item));
+                if i == 0 && is_null(&ns, value) {
+                    vals.push(value);
+                } else if found || i == list.len() {
+                    vals.push(bool_to_f64!(found != negated));
+                } else {
+                    vals.push(value);
+                    work.push(Step::In {
+                        negated,
+                        list,
+                        i: i + 1,
+                    });
+                    work.push(Step::Ic(&list[i]));
+                }
+            }
+            Step::SumStart { var, expr } => {
+                let to = pop(&mut vals);
+                let from = pop(&mut vals);
+                check_sum_bounds(from, to)?;
+                work.push(Step::Sum {
+                    var,
+                    val: from,
+                    to,
+                    expr,
+                    sum: 0.0,
+                    first: true,
+                });
+            }
+            Step::Sum {
+                var,
+                mut val,
+                to,
+                expr,
+                mut sum,
+                first,
+            } => {
+                if !first {
+                    ns.layers.pop();
+                    sum += pop(&mut vals);
+                    val += 1.0;
+                }
+                if val <= to {
+                    ns.tick()?;
+                    ns.layers.push(Layer::Scope(var, val));
+                    work.push(Step::Sum {
+                        var,
+                        val,
+                        to,
+                        expr,
+                        sum,
+                        first: false,
+                    });
+                    work.push(Step::Ic(expr));
+                } else {
+                    vals.push(sum);
+                }
+            }
+            Step::Let { var, expr } => {
+                let val = pop(&mut vals);
+                ns.layers.push(Layer::Scope(var, val));
+                work.push(Step::PopLayer);
+                work.push(Step::Ic(expr));
+            }
+            Step::SetTemp(slot) => {
+                if let Some(&val) = vals.last() {
+                    ns.set_temp(slot, val);
+                }
+            }
+            Step::PopLayer => {
+                ns.layers.pop();
+            }
+            Step::Memo(class) => {
+                if let (Some(&val), Some(slot)) = (vals.last(), memo.vals.get_mut(class)) {
+                    *slot = Some(val);
+                }
+            }
+        }
+    }
+    Ok(pop(&mut vals))
+}
+
+// Does what `eval()` does with `instr` after the `tick()`: either computes a
+// value now, or schedules the steps that will.  The steps run in the reverse
+// order of their pushes.
+fn schedule<'s>(
+    instr: &'s Instruction,
+    slab: &'s Slab,
+    ns: &mut LayerNamespace<'s, '_>,
+    work: &mut Vec<Step<'s>>,
+    vals: &mut Vec<f64>,
+) -> Result<(), Error> {
+    match instr {
+        IConst(c) => vals.push(*c),
+        #[cfg(feature = "unsafe-vars")]
+        IUnsafeVar { ptr, .. } => vals.push(unsafe { **ptr }),
+        IVar(name) => {
+            let val = match ns.try_lookup(name, Vec::new(), &mut String::new()) {
+                Ok(Some(val)) => val,
+                Ok(None) => named_constant(name).ok_or_else(|| Error::UndefinedVariable {
+                    name: name.to_string(),
+                })?,
+                Err(err) => named_constant(name).ok_or(err)?,
+            };
+            vals.push(val);
+        }
+        IPrintFunc(pf) => vals.push(pf.eval(slab, ns)?),
+        IError(err) => return Err(err.clone()),
+
+        INeg(i) | INot(i) | IInv(i) | IFuncInt(i) | IFuncCeil(i) | IFuncFloor(i) | IFuncAbs(i)
+        | IFuncSign(i) | IFuncSin(i) | IFuncCos(i) | IFuncTan(i) | IFuncASin(i) | IFuncACos(i)
+        | IFuncATan(i) | IFuncSinH(i) | IFuncCosH(i) | IFuncTanH(i) | IFuncASinH(i)
+        | IFuncACosH(i) | IFuncATanH(i) | IFuncSqrt(i) => {
+            work.push(Step::Apply(instr));
+            work.push(Step::Child(*i));
+        }
+        IAdd(li, ric) | IMul(li, ric) | IFuncMin(li, ric) | IFuncMax(li, ric) => {
+            work.push(Step::Apply(instr));
+            work.push(Step::Ic(ric));
+            work.push(Step::Child(*li));
+        }
+        IAND(li, ric) => {
+            work.push(Step::And(ric));
+            work.push(Step::Child(*li));
+        }
+        IOR(li, ric) => {
+            work.push(Step::Or(ric));
+            work.push(Step::Child(*li));
+        }
+        IExp { base: a, power: b }
+        | IMod {
+            dividend: a,
+            divisor: b,
+        }
+        | IFuncLog { base: a, of: b }
+        | IFuncRound { modulus: a, of: b }
+        | IFuncCoalesce {
+            value: a,
+            fallback: b,
+        }
+        | IFuncQuotient {
+            dividend: a,
+            divisor: b,
+        }
+        | IEQ(a, b)
+        | INE(a, b)
+        | ILT(a, b)
+        | ILTE(a, b)
+        | IGTE(a, b)
+        | IGT(a, b) => {
+            work.push(Step::Apply(instr));
+            work.push(Step::Ic(b));
+            work.push(Step::Ic(a));
+        }
+        IFuncFma { a, b, c } => {
+            work.push(Step::Apply(instr));
+            work.extend([c, b, a].map(Step::Ic));
+        }
+        IFunc { args, .. } | IFuncNorm { args, .. } => {
+            work.push(Step::Apply(instr));
+            work.extend(args.iter().rev().map(Step::Ic));
+        }
+        IFuncInterp { x, points, .. } => {
+            work.push(Step::Apply(instr));
+            for (px_ic, py_ic) in points.iter().rev() {
+                work.push(Step::Ic(py_ic));
+                work.push(Step::Ic(px_ic));
+            }
+            work.push(Step::Ic(x));
+        }
+        IFuncPiecewise { branches, default } => match branches.first() {
+            Some((cond_ic, _)) => {
+                work.push(Step::Branch {
+                    branches,
+                    default,
+                    i: 0,
+                });
+                work.push(Step::Ic(cond_ic));
+            }
+            None => work.push(Step::Ic(default)),
+        },
+        IFuncIn {
+            negated,
+            value,
+            list,
+        } => {
+            work.push(Step::In {
+                negated: *negated,
+                list,
+                i: 0,
+            });
+            work.push(Step::Ic(value));
+        }
+        IFuncSum {
+            var,
+            from,
+            to,
+            expr,
+        } => {
+            work.push(Step::SumStart { var, expr });
+            work.push(Step::Ic(to));
+            work.push(Step::Ic(from));
+        }
+        IFuncLet { var, value, expr } => {
+            work.push(Step::Let { var, expr });
+            work.push(Step::Ic(value));
+        }
+
+        ITemp { slot, instr } => match ns.temp(*slot) {
+            Some(val) => vals.push(val),
+            None => {
+                work.push(Step::SetTemp(*slot));
+                work.push(Step::Child(*instr));
+            }
+        },
+        ITemps { slots, body } => {
+            // Like eval(), limit the allocation in case of bad data:
+            let slots = (*slots).min(slab.cs.instrs.len());
+            ns.layers.push(Layer::Temps(vec![None; slots]));
+            work.push(Step::PopLayer);
+            work.push(Step::Eval(get_instr!(slab.cs, body)));
+        }
+    }
+    Ok(())
+}
+
+// Pops the operands that `schedule()` evaluated for `instr`, and returns its
+// value.
+fn apply<'s>(
+    instr: &'s Instruction,
+    ns: &mut LayerNamespace<'s, '_>,
+    vals: &mut Vec<f64>,
+) -> Result<f64, Error> {
+    // The operands, in the order in which they were pushed:
+    macro_rules! pop2 {
+        () => {{
+            let right = pop(vals);
+            (pop(vals), right)
+        }};
+    }
+    let ns_ref = &*ns;
+    let val = match instr {
+        INeg(_) => {
+            let val = pop(vals);
+            null_or(ns_ref, val, || -val)
+        }
+        IInv(_) => {
+            let val = pop(vals);
+            null_or(ns_ref, val, || 1.0 / val)
+        }
+        INot(_) => {
+            let val = pop(vals);
+            null_or(ns_ref, val, || bool_to_f64!(f64_eq!(val, 0.0)))
+        }
+        IFuncInt(_) => pop(vals).trunc(),
+        IFuncCeil(_) => pop(vals).ceil(),
+        IFuncFloor(_) => pop(vals).floor(),
+        IFuncAbs(_) => pop(vals).abs(),
+        IFuncSign(_) => pop(vals).signum(),
+        IFuncSin(_) => pop(vals).sin(),
+        IFuncCos(_) => pop(vals).cos(),
+        IFuncTan(_) => pop(vals).tan(),
+        IFuncASin(_) => pop(vals).asin(),
+        IFuncACos(_) => pop(vals).acos(),
+        IFuncATan(_) => pop(vals).atan(),
+        IFuncSinH(_) => pop(vals).sinh(),
+        IFuncCosH(_) => pop(vals).cosh(),
+        IFuncTanH(_) => pop(vals).tanh(),
+        IFuncASinH(_) => pop(vals).asinh(),
+        IFuncACosH(_) => pop(vals).acosh(),
+        IFuncATanH(_) => pop(vals).atanh(),
+        IFuncSqrt(_) => pop(vals).sqrt(),
+
+        IAdd(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || left + right)
+        }
+        IMul(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || left * right)
+        }
+        IMod { .. } => {
+            let (dividend, divisor) = pop2!();
+            if is_null(ns_ref, dividend) {
+                dividend
+            } else if is_null(ns_ref, divisor) {
+                divisor
+            } else {
+                ns.mod_mode().rem(dividend, divisor)?
+            }
+        }
+        IExp { .. } => {
+            let (base, power) = pop2!();
+            if is_null(ns_ref, base) {
+                base
+            } else if is_null(ns_ref, power) {
+                power
+            } else {
+                ns.zero_pow_zero().pow(base, power)?
+            }
+        }
+        IEQ(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || bool_to_f64!(f64_eq!(left, right)))
+        }
+        INE(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || bool_to_f64!(f64_ne!(left, right)))
+        }
+        ILT(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || bool_to_f64!(left < right))
+        }
+        ILTE(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || bool_to_f64!(left <= right))
+        }
+        IGTE(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || bool_to_f64!(left >= right))
+        }
+        IGT(..) => {
+            let (left, right) = pop2!();
+            null_or2(ns_ref, left, right, || bool_to_f64!(left > right))
+        }
+        IFuncMin(..) => {
+            let (left, right) = pop2!();
+            if left.is_nan() || right.is_nan() {
+                f64::NAN
+            } else if left < right {
+                left
+            } else {
+                right
+            }
+        }
+        IFuncMax(..) => {
+            let (left, right) = pop2!();
+            if left.is_nan() || right.is_nan() {
+                f64::NAN
+            } else if left > right {
+                left
+            } else {
+                right
+            }
+        }
+        IFuncLog { .. } => {
+            let (base, of) = pop2!();
+            log(base, of)
+        }
+        IFuncRound { .. } => {
+            let (modulus, of) = pop2!();
+            (of / modulus).round() * modulus
+        }
+        IFuncCoalesce { .. } => {
+            let (value, fallback) = pop2!();
+            coalesce(value, fallback)
+        }
+        IFuncQuotient { .. } => {
+            let (dividend, divisor) = pop2!();
+            if is_null(ns_ref, dividend) {
+                dividend
+            } else if is_null(ns_ref, divisor) {
+                divisor
+            } else {
+                ns.mod_mode().quotient(dividend, divisor)?
+            }
+        }
+        IFuncFma { .. } => {
+            let c = pop(vals);
+            let (a, b) = pop2!();
+            a.mul_add(b, c)
+        }
+        IFuncNorm { squared, args } => {
+            let mut acc = SumSq::default();
+            for &val in &vals[vals.len() - args.len()..] {
+                acc.add(val);
+            }
+            vals.truncate(vals.len() - args.len());
+            acc.finish(*squared)
+        }
+        IFuncInterp {
+            extrapolate,
+            points,
+            ..
+        } => {
+            let base = vals.len() - 1 - 2 * points.len();
+            let val = interp(vals[base], &vals[base + 1..], *extrapolate);
+            vals.truncate(base);
+            val?
+        }
+        IFunc { name, args: ics } => {
+            let args = vals.split_off(vals.len() - ics.len());
+            let arg_count = args.len();
+            match ns.try_lookup(name, args, &mut String::new())? {
+                Some(val) => val,
+                None => {
+                    return Err(Error::UndefinedFunction {
+                        name: name.to_string(),
+                        arg_count,
+                    })
+                }
+            }
+        }
+        _ => f64::NAN, // unreachable
+    };
+    Ok(val)
+}
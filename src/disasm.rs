@@ -0,0 +1,342 @@
+//! A read-only view of compiled expressions, for debugging and profiling.
+//!
+//! [`CompiledExpression::instructions()`](../bytecode/struct.CompiledExpression.html#method.instructions)
+//! lists the instructions that an expression compiled into, and the
+//! `Display` form of a `CompiledExpression` is a disassembly listing with one
+//! instruction per line:
+//!
+//! ```text
+//! 0: var x
+//! 1: sin %0
+//! 2: mul %1, 2
+//! 3: var y
+//! 4: add %2, %3
+//! ```
+//!
+//! The listing is in postfix order: the operands of an instruction always come
+//! before it (`%N` refers to the instruction at position `N`), and the
+//! top-level instruction is last.  Instructions that aren't reachable from the
+//! top-level instruction are not listed, and a sub-expression that is shared
+//! after [common subexpression elimination](../cse/index.html) is only listed
+//! once (each use is a `temp` instruction that refers to it), so the length of
+//! the listing is the number of instructions that an evaluation executes, at
+//! most.  That makes it usable as a regression guard
+//! for the compiler's folding:
+//!
+//! ```
+//! use fasteval2::{CompiledExpression, EmptyNamespace};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let compiled = CompiledExpression::compile("sin(x) * (1 + 1) + y", &mut EmptyNamespace)?;
+//!     assert_eq!(compiled.instructions().count(), 5);
+//!     assert_eq!(compiled.instructions().last().unwrap().op, "add");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The parser doesn't keep track of source positions, so the views don't have
+//! source spans.
+//!
+//! The operation names are the names of the corresponding expression functions
+//! (`sin`, `interp_ext`, `sum`, ...), or short names for the operators: `neg`,
+//! `not`, `inv` (`1/x`), `add`, `mul`, `mod`, `pow`, `lt`, `lte`, `eq`, `ne`,
+//! `gte`, `gt`, `or`, `and`.  Variables are `var`, custom functions are
+//! `call`, a `let` binding is `let`, a top-level constant is `const`, and the temporaries of common
+//! subexpression elimination are `temps` (the scope) and `temp` (one shared
+//! value).
+
+#[cfg(feature = "unsafe-vars")]
+use crate::compiler::Instruction::IUnsafeVar;
+use crate::compiler::{
+    Instruction::{
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    },
+    InstructionI, IC,
+};
+use crate::slab::CompileSlab;
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+/// One instruction of a compiled expression.
+///
+/// The `Display` form looks like `4: add %2, %3`.
+///
+/// [See the `disasm module` documentation.](index.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionView<'a> {
+    /// The position of this instruction in the listing.
+    pub index: usize,
+    /// The name of the operation, like `add` or `sin`.
+    pub op: &'static str,
+    /// The operands, from left to right.
+    pub operands: Vec<Operand<'a>>,
+}
+
+/// An operand of an [`InstructionView`](struct.InstructionView.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand<'a> {
+    /// A constant value.  Displayed as the number.
+    Const(f64),
+    /// The name of a variable, custom function or `sum()` loop variable.
+    /// Displayed as the name.
+    Name(&'a str),
+    /// The result of the instruction at this position in the listing.
+    /// Displayed as `%N`.
+    Child(usize),
+    /// The slot of a temporary.  Displayed as `$N`.
+    Slot(usize),
+}
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Const(c) => write!(f, "{}", c),
+            Operand::Name(name) => write!(f, "{}", name),
+            Operand::Child(index) => write!(f, "%{}", index),
+            Operand::Slot(slot) => write!(f, "${}", slot),
+        }
+    }
+}
+
+impl fmt::Display for InstructionView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.index, self.op)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists `instr` and everything that it refers to, in postfix order.
+pub(crate) fn disassemble<'a>(
+    instr: &'a Instruction,
+    cs: &'a CompileSlab,
+) -> Vec<InstructionView<'a>> {
+    let mut lister = Lister {
+        cs,
+        views: Vec::new(),
+        // The last entry stands for the out-of-bounds indices, which all
+        // refer to the default Instruction:
+        listed: vec![None; cs.instrs.len() + 1],
+    };
+
+    // Lists the Instructions in postfix order with an explicit stack, so that
+    // deep trees can't overflow the native one.  An Instruction is expanded
+    // (its operands are scheduled) the first time that it is popped, and
+    // listed the second time.
+    let mut work: Vec<(&Instruction, Option<usize>, bool)> = vec![(instr, None, false)];
+    while let Some((instr, slot, expanded)) = work.pop() {
+        if let Some(slot) = slot {
+            if lister.listed[slot].is_some() {
+                continue;
+            }
+        }
+        if expanded {
+            let index = lister.push(instr);
+            if let Some(slot) = slot {
+                lister.listed[slot] = Some(index);
+            }
+            continue;
+        }
+        work.push((instr, slot, true));
+        for i in instr.children().into_iter().rev() {
+            work.push((get_instr!(cs, i), Some(lister.slot(i)), false));
+        }
+    }
+    lister.views
+}
+
+struct Lister<'a> {
+    cs: &'a CompileSlab,
+    views: Vec<InstructionView<'a>>,
+    listed: Vec<Option<usize>>, // The position of each slab Instruction in the listing.
+}
+
+impl<'a> Lister<'a> {
+    // The entry of a slab Instruction in `listed`:
+    fn slot(&self, i: &InstructionI) -> usize {
+        i.0.min(self.cs.instrs.len())
+    }
+
+    // The position of a slab Instruction that is already listed:
+    fn child(&self, i: &InstructionI) -> usize {
+        self.listed[self.slot(i)].unwrap_or(0)
+    }
+
+    fn ic(&self, ic: &IC) -> Operand<'a> {
+        match ic {
+            IC::C(c) => Operand::Const(*c),
+            IC::I(i) => Operand::Child(self.child(i)),
+        }
+    }
+
+    // Lists `instr`, whose operands are already listed:
+    fn push(&mut self, instr: &'a Instruction) -> usize {
+        macro_rules! unary {
+            ($op:expr, $i:ident) => {
+                ($op, vec![Operand::Child(self.child($i))])
+            };
+        }
+        macro_rules! instr_ic {
+            ($op:expr, $i:ident, $ic:ident) => {{
+                let left = Operand::Child(self.child($i));
+                ($op, vec![left, self.ic($ic)])
+            }};
+        }
+        macro_rules! ic_ic {
+            ($op:expr, $a:ident, $b:ident) => {{
+                let left = self.ic($a);
+                ($op, vec![left, self.ic($b)])
+            }};
+        }
+
+        let (op, operands) = match instr {
+            IConst(c) => ("const", vec![Operand::Const(*c)]),
+
+            INeg(i) => unary!("neg", i),
+            INot(i) => unary!("not", i),
+            IInv(i) => unary!("inv", i),
+
+            IAdd(i, ic) => instr_ic!("add", i, ic),
+            IMul(i, ic) => instr_ic!("mul", i, ic),
+            IMod { dividend, divisor } => ic_ic!("mod", dividend, divisor),
+            IExp { base, power } => ic_ic!("pow", base, power),
+
+            ILT(a, b) => ic_ic!("lt", a, b),
+            ILTE(a, b) => ic_ic!("lte", a, b),
+            IEQ(a, b) => ic_ic!("eq", a, b),
+            INE(a, b) => ic_ic!("ne", a, b),
+            IGTE(a, b) => ic_ic!("gte", a, b),
+            IGT(a, b) => ic_ic!("gt", a, b),
+
+            IOR(i, ic) => instr_ic!("or", i, ic),
+            IAND(i, ic) => instr_ic!("and", i, ic),
+
+            IVar(name) => ("var", vec![Operand::Name(name)]),
+            #[cfg(feature = "unsafe-vars")]
+            IUnsafeVar { name, .. } => ("unsafe_var", vec![Operand::Name(name)]),
+            IFunc { name, args } => {
+                let mut operands = vec![Operand::Name(name)];
+                for arg in args {
+                    operands.push(self.ic(arg));
+                }
+                ("call", operands)
+            }
+
+            IFuncInt(i) => unary!("int", i),
+            IFuncCeil(i) => unary!("ceil", i),
+            IFuncFloor(i) => unary!("floor", i),
+            IFuncAbs(i) => unary!("abs", i),
+            IFuncSign(i) => unary!("sign", i),
+            IFuncLog { base, of } => ic_ic!("log", base, of),
+            IFuncRound { modulus, of } => ic_ic!("round", modulus, of),
+            IFuncMin(i, ic) => instr_ic!("min", i, ic),
+            IFuncMax(i, ic) => instr_ic!("max", i, ic),
+            IFuncPiecewise { branches, default } => {
+                let mut operands = Vec::with_capacity(2 * branches.len() + 1);
+                for (cond, val) in branches {
+                    operands.push(self.ic(cond));
+                    operands.push(self.ic(val));
+                }
+                operands.push(self.ic(default));
+                ("piecewise", operands)
+            }
+            IFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                let mut operands = vec![Operand::Name(var)];
+                operands.push(self.ic(from));
+                operands.push(self.ic(to));
+                operands.push(self.ic(expr));
+                ("sum", operands)
+            }
+            IFuncInterp {
+                extrapolate,
+                x,
+                points,
+            } => {
+                let mut operands = Vec::with_capacity(2 * points.len() + 1);
+                operands.push(self.ic(x));
+                for (px, py) in points {
+                    operands.push(self.ic(px));
+                    operands.push(self.ic(py));
+                }
+                (if *extrapolate { "interp_ext" } else { "interp" }, operands)
+            }
+
+            IFuncSin(i) => unary!("sin", i),
+            IFuncCos(i) => unary!("cos", i),
+            IFuncTan(i) => unary!("tan", i),
+            IFuncASin(i) => unary!("asin", i),
+            IFuncACos(i) => unary!("acos", i),
+            IFuncATan(i) => unary!("atan", i),
+            IFuncSinH(i) => unary!("sinh", i),
+            IFuncCosH(i) => unary!("cosh", i),
+            IFuncTanH(i) => unary!("tanh", i),
+            IFuncASinH(i) => unary!("asinh", i),
+            IFuncACosH(i) => unary!("acosh", i),
+            IFuncATanH(i) => unary!("atanh", i),
+            IFuncSqrt(i) => unary!("sqrt", i),
+            IFuncFma { a, b, c } => {
+                let a = self.ic(a);
+                let b = self.ic(b);
+                ("fma", vec![a, b, self.ic(c)])
+            }
+            IFuncCoalesce { value, fallback } => {
+                let value = self.ic(value);
+                ("coalesce", vec![value, self.ic(fallback)])
+            }
+            IFuncQuotient { dividend, divisor } => {
+                let dividend = self.ic(dividend);
+                ("quotient", vec![dividend, self.ic(divisor)])
+            }
+            IFuncNorm { squared, args } => (
+                if *squared { "sumsq" } else { "norm" },
+                args.iter().map(|arg| self.ic(arg)).collect(),
+            ),
+            IFuncIn {
+                negated,
+                value,
+                list,
+            } => (
+                if *negated { "not_in" } else { "in" },
+                Some(value)
+                    .into_iter()
+                    .chain(list)
+                    .map(|ic| self.ic(ic))
+                    .collect(),
+            ),
+            IFuncLet { var, value, expr } => {
+                let mut operands = vec![Operand::Name(var)];
+                operands.push(self.ic(value));
+                operands.push(self.ic(expr));
+                ("let", operands)
+            }
+
+            ITemps { body, .. } => unary!("temps", body),
+            ITemp { slot, instr } => (
+                "temp",
+                vec![Operand::Slot(*slot), Operand::Child(self.child(instr))],
+            ),
+
+            IPrintFunc(_) => ("print", Vec::new()),
+            IError(_) => ("error", Vec::new()),
+        };
+        self.views.push(InstructionView {
+            index: self.views.len(),
+            op,
+            operands,
+        });
+        self.views.len() - 1
+    }
+}
@@ -1,13 +1,51 @@
 //! This module contains `fasteval`'s Error type: an `enum` that contains all errors
 //! that can be produced by the `fasteval` API.
+//!
+//! Errors are flat values: there is no chain of context messages, so an error
+//! from deep inside a nested expression is the same `Error` as the one that the
+//! innermost failure produced, and matching on it works at any depth.  To find
+//! where a parse error is in the source string, use
+//! [`Parser::parse_spanned()`](../parser/struct.Parser.html#method.parse_spanned)
+//! (or [`parse_all_errors()`](../parser/struct.Parser.html#method.parse_all_errors)
+//! for all of them), and to see how far an evaluation got, use
+//! [`eval_trace()`](../parser/struct.Expression.html#method.eval_trace).
+//!
+//! `Error` implements `std::error::Error`, so it works with `?` and with crates
+//! like `anyhow`.  Where a standard library error caused the failure (like the
+//! `ParseFloatError` of a `ParseF64` error), `source()` returns it.
 
-use std::fmt;
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt;
+#[cfg(feature = "std")]
+use core::num::ParseFloatError;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
 
 /// This is the error type used in `fasteval`'s `Result`s.
 ///
 /// For performance reasons, `fasteval` makes an effort to always return `Error`s
 /// instead of using `panic!()`.
+///
+/// Match on the variants to tell errors apart:
+///
+/// ```
+/// use fasteval2::{ez_eval, EmptyNamespace, Error};
+///
+/// match ez_eval("x + 1", &mut EmptyNamespace) {
+///     Err(Error::UndefinedVariable { name }) => assert_eq!(name, "x"),
+///     Err(Error::TooLong) | Err(Error::TooDeep) => panic!("expression too large"),
+///     other => panic!("unexpected result: {:?}", other),
+/// }
+/// ```
+///
+/// New variants may be added in future releases, so matches need a wildcard arm.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Error {
     /// Too many Expressions/Values/Instructions were stored in the Slab.
     ///
@@ -29,9 +67,13 @@ pub enum Error {
     /// Reached an unexpected End Of Input during parsing.
     ///
     /// The `String` field contains information about what was being parsed
-    /// when the EOF was reached.
+    /// when the EOF was reached.  For example, a dangling operator like `"1 +"`
+    /// produces `EofWhileParsing("value")`.
     EofWhileParsing(String),
 
+    /// The expression string was empty or only contained whitespace.
+    EmptyExpression,
+
     /// UTF8 decoding error.
     ///
     /// The `String` field contains information about what was being parsed
@@ -50,6 +92,13 @@ pub enum Error {
     /// be expensive to parse.
     TooDeep,
 
+    /// The expression referenced too many distinct variables and custom functions.
+    ///
+    /// This is returned by
+    /// [`Expression::validate_var_limit()`](../parser/struct.Expression.html#method.validate_var_limit).
+    /// The `usize` field contains the number of distinct names that were found.
+    TooManyVars(usize),
+
     /// An expression was parsed, but there is still input data remaining.
     ///
     /// The `String` field contains the un-parsed input data.
@@ -60,7 +109,8 @@ pub enum Error {
 
     /// An error occurred during the parsing of a f64 number.
     ///
-    /// The `String` field contains the data that caused the error.
+    /// The `String` field contains the data that caused the error, and
+    /// `source()` returns the `std::num::ParseFloatError`.
     ParseF64(String),
 
     /// The expected input data was not found.
@@ -68,15 +118,113 @@ pub enum Error {
     /// The `String` field tells you what was expected.
     Expected(String),
 
-    /// A function was called with the wrong arguments.
+    /// A function was called with arguments that it can't use, like an empty
+    /// `randint()` range.
     ///
     /// The `String` field contains information about the expected arguments.
     WrongArgs(String),
 
-    /// The expression tried to use an undefined variable/function.
+    /// A function was called with the wrong number of arguments.
+    ///
+    /// `expected` is the number of arguments that the function `name` accepts,
+    /// and `got` is the number of arguments in the call.  Built-in functions
+    /// are checked when parsing (see
+    /// [`builtin_arity()`](../parser/fn.builtin_arity.html)), and custom
+    /// functions when evaluating, if the Namespace knows their arity.
+    WrongArgCount {
+        name: String,
+        expected: Arity,
+        got: usize,
+    },
+
+    /// The expression used a variable that isn't defined.
+    ///
+    /// You can define variables with a Namespace.
+    UndefinedVariable { name: String },
+
+    /// The expression called a custom function that isn't defined.
+    ///
+    /// You can define functions with a Namespace.  `arg_count` is the number
+    /// of arguments in the call, so `f()` has an `arg_count` of 0.
+    UndefinedFunction { name: String, arg_count: usize },
+
+    /// Integer arithmetic overflowed during `eval_i64()`.
+    IntegerOverflow,
+
+    /// An integer division or modulo by zero occurred during `eval_i64()`, or
+    /// during a `%` with [`ModMode::Integer`](../evalns/enum.ModMode.html#variant.Integer).
+    DivideByZero,
+
+    /// A non-integral value was found during `eval_i64()`, or in an operand of
+    /// `%` with [`ModMode::Integer`](../evalns/enum.ModMode.html#variant.Integer).
+    ///
+    /// The `String` field contains the offending value.
+    NotAnInteger(String),
+
+    /// A value with a nonzero imaginary part was found during `eval_complex()`
+    /// where a real value is needed, like in a comparison.
+    ///
+    /// The `String` field contains the offending value, like `"0+1i"`.
+    NotReal(String),
+
+    /// The evaluation exceeded its operation budget.
+    ///
+    /// See [`EvalNS::with_budget()`](../evalns/struct.EvalNS.html#method.with_budget).
+    /// The `u64` field contains the number of operations consumed.
+    BudgetExceeded(u64),
+
+    /// The evaluation was still running at its deadline.
+    ///
+    /// See [`EvalNS::with_deadline()`](../evalns/struct.EvalNS.html#method.with_deadline).
+    Timeout,
+
+    /// A compiled expression could not be encoded to, or loaded from, bytecode.
+    ///
+    /// See the [`bytecode`](../bytecode/index.html) module.
+    /// The `String` field contains a description of the problem.
+    Bytecode(String),
+
+    /// The expression used something that a
+    /// [`ValidationSchema`](../validate/struct.ValidationSchema.html) doesn't allow,
+    /// or that can't be converted to [`rpn`](../rpn/index.html) tokens.  It is
+    /// also the error of a tuple that is evaluated as a single value, and of
+    /// an Unsafe Variable in [`par_eval_all()`](../par/fn.par_eval_all.html).
+    ///
+    /// The `String` field contains the offending operator, function or
+    /// variable, like `"<"`, or `"tuple"`.
+    NotAllowed(String),
+
+    /// A strict [`EvalNS`](../evalns/struct.EvalNS.html) got a NaN or infinite
+    /// value from its Namespace.
     ///
-    /// You can define variables/functions with a Namespace.
-    Undefined(String),
+    /// See [`EvalNS::with_strict()`](../evalns/struct.EvalNS.html#method.with_strict).
+    /// The `String` field contains the name of the variable or function.
+    NotFinite(String),
+
+    /// The result of an evaluation was outside of the range that its
+    /// [`EvalNS`](../evalns/struct.EvalNS.html) allows.
+    ///
+    /// See [`EvalNS::with_result_range()`](../evalns/struct.EvalNS.html#method.with_result_range).
+    OutOfRange {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_f64"))]
+        value: f64,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_f64"))]
+        min: f64,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_f64"))]
+        max: f64,
+    },
+
+    /// The evaluation computed `0^0`, and its Namespace rejects it.
+    ///
+    /// See [`EvalNS::with_zero_pow_zero()`](../evalns/struct.EvalNS.html#method.with_zero_pow_zero).
+    ZeroPowZero,
+
+    /// A [`ParserBuilder`](../parser/struct.ParserBuilder.html) was given
+    /// options that can't be used together, like a decimal comma and thousands
+    /// separators.
+    ///
+    /// The `String` field describes the conflict.
+    InvalidConfig(String),
 
     /// This error should never occur because it is only produced by code paths
     /// that should never execute.  This is more performant than using the
@@ -84,12 +232,193 @@ pub enum Error {
     Unreachable,
 }
 
-impl std::error::Error for Error {
-    // The defaults are fine for now.
+/// With the `std` feature, `ParseF64` errors have the `ParseFloatError` of the
+/// number as their source:
+///
+/// ```
+/// use fasteval2::{ez_eval, EmptyNamespace};
+/// use std::error::Error;
+/// use std::num::ParseFloatError;
+///
+/// let err = ez_eval("1 + 2e", &mut EmptyNamespace).unwrap_err();
+/// let source = err.source().unwrap();
+/// assert!(source.downcast_ref::<ParseFloatError>().is_some());
+/// ```
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::ParseF64(tok) => float_cause(tok).map(|err| err as _),
+            _ => None,
+        }
+    }
+}
+
+// A ParseFloatError is nothing but its kind (empty or invalid), so the cause of
+// a ParseF64 error is re-created exactly by parsing the number again:
+#[cfg(feature = "std")]
+fn float_cause(tok: &str) -> Option<&'static ParseFloatError> {
+    static CAUSES: OnceLock<[Result<f64, ParseFloatError>; 2]> = OnceLock::new();
+    let err = tok.parse::<f64>().err()?;
+    CAUSES
+        .get_or_init(|| ["".parse(), "-".parse()])
+        .iter()
+        .find_map(|cause| cause.as_ref().err().filter(|cause| **cause == err))
 }
 
+/// The `Display` form is a single line, the same as the `Debug` form.  (Line
+/// breaks in the `String` fields are escaped.)  For a multi-line report with
+/// the source line, see [`SpannedError::render()`](struct.SpannedError.html#method.render).
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{:?}", self) // Re-use Debug for now...
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The number of arguments that a function accepts.
+///
+/// Found in [`WrongArgCount`](enum.Error.html#variant.WrongArgCount) errors.
+/// The `Display` form reads like `"1 to 2 args"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Arity {
+    /// Exactly this many.
+    Exact(usize),
+    /// From `.0` to `.1`, inclusive.
+    Range(usize, usize),
+    /// At least this many.
+    AtLeast(usize),
+    /// An odd number, and at least this many, like the
+    /// `cond1,val1, cond2,val2, ..., default` of `piecewise()`.
+    OddAtLeast(usize),
+}
+
+impl Arity {
+    /// Returns whether a call with `n` arguments is allowed.
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exact(m) => n == m,
+            Arity::Range(lo, hi) => lo <= n && n <= hi,
+            Arity::AtLeast(m) => n >= m,
+            Arity::OddAtLeast(m) => n >= m && n % 2 == 1,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args = |n: usize| if n == 1 { "arg" } else { "args" };
+        match *self {
+            Arity::Exact(n) => write!(f, "{} {}", n, args(n)),
+            Arity::Range(lo, hi) => write!(f, "{} to {} {}", lo, hi, args(hi)),
+            Arity::AtLeast(n) => write!(f, "at least {} {}", n, args(n)),
+            Arity::OddAtLeast(n) => write!(f, "an odd number of args, at least {}", n),
+        }
+    }
+}
+
+/// An `Error`, together with the part of the source string that caused it.
+///
+/// Returned by [`Parser::parse_spanned()`](../parser/struct.Parser.html#method.parse_spanned),
+/// [`Parser::parse_all_errors()`](../parser/struct.Parser.html#method.parse_all_errors)
+/// [`Expression::validate()`](../parser/struct.Expression.html#method.validate),
+/// [`Expression::check_allowed()`](../parser/struct.Expression.html#method.check_allowed)
+/// and [`Expression::eval_spanned()`](../parser/struct.Expression.html#method.eval_spanned).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedError {
+    /// The error.
+    pub error: Error,
+    /// The byte range of the source string that caused the error.
+    pub span: Range<usize>,
+}
+
+impl SpannedError {
+    /// Returns the line and column (both counting from 1) where the span
+    /// starts in `src`.  Columns count characters, not bytes.
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let start = floor_char_boundary(src, self.span.start);
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        (
+            src[..start].matches('\n').count() + 1,
+            src[line_start..start].chars().count() + 1,
+        )
+    }
+
+    /// Renders the error for terminal output, with the source line that
+    /// contains the start of the span, and the span underlined:
+    ///
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    /// let src = "x +\n  sin(1, 2)";
+    /// let err = Parser::new().parse_spanned(src, &mut Slab::new().ps).unwrap_err();
+    /// assert_eq!(
+    ///     err.render(src),
+    ///     "error: WrongArgCount { name: \"sin\", expected: Exact(1), got: 2 }\n --> 2:3\n  |\n2 |   sin(1, 2)\n  |   ^^^^^^^^^"
+    /// );
+    /// ```
+    ///
+    /// A span that continues on later lines is underlined up to the end of
+    /// its first line, and an empty span is shown as a single `^`.
+    pub fn render(&self, src: &str) -> String {
+        let start = floor_char_boundary(src, self.span.start);
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let line = src[line_start..line_end].trim_end_matches('\r');
+        let line_end = line_start + line.len();
+        let end = floor_char_boundary(src, self.span.end.min(line_end).max(start));
+        let (line_no, col) = self.line_col(src);
+
+        // Keep tabs, so that the underline lines up with the source line:
+        let indent: String = src[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = src[start..end].chars().count().max(1);
+        let pad = " ".repeat(line_no.to_string().len());
+        format!(
+            "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.error,
+            pad,
+            line_no,
+            col,
+            pad,
+            line_no,
+            line,
+            pad,
+            indent,
+            "^".repeat(width)
+        )
+    }
+}
+
+impl core::error::Error for SpannedError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl fmt::Display for SpannedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.error, self.span.start, self.span.end
+        )
+    }
+}
+
+impl From<SpannedError> for Error {
+    fn from(err: SpannedError) -> Self {
+        err.error
+    }
+}
+
+// The largest char boundary of `s` that is at most `i`:
+fn floor_char_boundary(s: &str, i: usize) -> usize {
+    let mut i = i.min(s.len());
+    while !s.is_char_boundary(i) {
+        i -= 1;
     }
+    i
 }
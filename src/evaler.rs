@@ -9,41 +9,57 @@ use crate as fasteval2;
 #[cfg(feature = "unsafe-vars")]
 use crate::compiler::Instruction::IUnsafeVar;
 use crate::compiler::{
-    log,
+    coalesce, interp, log,
     Instruction::{
-        self, IAdd, IConst, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH, IFuncATan,
-        IFuncATanH, IFuncAbs, IFuncCeil, IFuncCos, IFuncCosH, IFuncFloor, IFuncInt, IFuncLog,
-        IFuncMax, IFuncMin, IFuncRound, IFuncSign, IFuncSin, IFuncSinH, IFuncSqrt, IFuncTan,
-        IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE,
-        INE, IOR,
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
     },
-    IC,
+    InstructionI, SumSq, IC,
 };
-use crate::error::Error;
-use crate::evalns::EvalNamespace;
+use crate::deep;
+use crate::error::{Error, SpannedError};
+use crate::evalns::{
+    EvalNS, EvalNamespace, GridNamespace, ModMode, OverrideNamespace, ScopeNamespace,
+    StableNamespace, StrToF64PairsNamespace, TempNamespace, TempVals, ZeroPowZero,
+};
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
 #[cfg(feature = "unsafe-vars")]
 use crate::parser::StdFunc::EUnsafeVar;
 use crate::parser::{
-    remove_no_panic,
     BinaryOp::{
         self, EAdd, EDiv, EExp, EMod, EMul, ESub, EAND, EEQ, EGT, EGTE, ELT, ELTE, ENE, EOR,
     },
-    Expression,
+    Expression, ExpressionI,
     ExpressionOrString::{EExpr, EStr},
     PrintFunc,
     StdFunc::{
         self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
-        EFuncCeil, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncInt, EFuncLog, EFuncMax, EFuncMin,
-        EFuncPi, EFuncRound, EFuncSign, EFuncSin, EFuncSinH, EFuncSqrt, EFuncTan, EFuncTanH, EVar,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
     },
     UnaryOp::{self, ENeg, ENot, EParentheses, EPos},
-    Value::{self, EConstant, EPrintFunc, EStdFunc, EUnaryOp},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
 };
 use crate::slab::Slab;
+use crate::validate;
+use crate::vars;
 
-use std::collections::BTreeSet;
-use std::f64::consts;
-use std::fmt;
+use alloc::collections::BTreeSet;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::f64::consts;
+use core::fmt;
 
 /// The same as `evaler.eval(&slab, &mut ns)`, but more efficient for common cases.
 ///
@@ -57,16 +73,22 @@ use std::fmt;
 /// Since evaluation is a performance-critical operation, saving some function
 /// calls actually makes a huge performance difference.
 ///
+/// It helps most when the whole expression was constant-folded by the compiler
+/// (about 7x faster than `eval()` in the `shape_const_*` benchmarks), or when
+/// it is a single Unsafe Variable.  For anything else -- a normal variable,
+/// or a compound expression -- the macro falls back to `eval()`, so the
+/// difference is a wash (see the `shape_*` benchmarks in `benches/eval.rs`).
+///
 #[macro_export]
 macro_rules! eval_compiled {
     ($evaler:ident, $slab_ref:expr, $ns_mut:expr) => {
         if let fasteval2::IConst(c) = $evaler {
-            c
+            fasteval2::EvalNamespace::check_result($ns_mut, c)?
         } else {
             #[cfg(feature = "unsafe-vars")]
             {
                 if let fasteval2::IUnsafeVar { ptr, .. } = $evaler {
-                    unsafe { *ptr }
+                    fasteval2::EvalNamespace::check_result($ns_mut, unsafe { *ptr })?
                 } else {
                     $evaler.eval($slab_ref, $ns_mut)?
                 }
@@ -94,16 +116,18 @@ macro_rules! eval_compiled {
 /// Since evaluation is a performance-critical operation, saving some function
 /// calls actually makes a huge performance difference.
 ///
+/// [`eval_compiled!()`](macro.eval_compiled.html) explains when this helps.
+///
 #[macro_export]
 macro_rules! eval_compiled_ref {
     ($evaler:ident, $slab_ref:expr, $ns_mut:expr) => {
         if let fasteval2::IConst(c) = $evaler {
-            *c
+            fasteval2::EvalNamespace::check_result($ns_mut, *c)?
         } else {
             #[cfg(feature = "unsafe-vars")]
             {
                 if let fasteval2::IUnsafeVar { ptr, .. } = $evaler {
-                    unsafe { **ptr }
+                    fasteval2::EvalNamespace::check_result($ns_mut, unsafe { **ptr })?
                 } else {
                     $evaler.eval($slab_ref, $ns_mut)?
                 }
@@ -119,6 +143,29 @@ macro_rules! eval_compiled_ref {
     }};
 }
 
+// Like `eval_compiled_ref!()`, for the operands inside of an `Instruction`,
+// whose values aren't final results:
+macro_rules! eval_nested_ref {
+    ($evaler:expr, $slab_ref:ident, $ns_mut:expr) => {{
+        let instr_ref = $evaler;
+        if let IConst(c) = instr_ref {
+            *c
+        } else {
+            #[cfg(feature = "unsafe-vars")]
+            {
+                if let IUnsafeVar { ptr, .. } = instr_ref {
+                    unsafe { **ptr }
+                } else {
+                    instr_ref.eval_nested($slab_ref, $ns_mut)?
+                }
+            }
+
+            #[cfg(not(feature = "unsafe-vars"))]
+            instr_ref.eval_nested($slab_ref, $ns_mut)?
+        }
+    }};
+}
+
 macro_rules! eval_ic_ref {
     ($ic:ident, $slab_ref:ident, $ns_mut:expr) => {
         match $ic {
@@ -131,12 +178,12 @@ macro_rules! eval_ic_ref {
                     if let fasteval2::IUnsafeVar { ptr, .. } = instr_ref {
                         unsafe { **ptr }
                     } else {
-                        instr_ref.eval($slab_ref, $ns_mut)?
+                        instr_ref.eval_nested($slab_ref, $ns_mut)?
                     }
                 }
 
                 #[cfg(not(feature = "unsafe-vars"))]
-                instr_ref.eval($slab_ref, $ns_mut)?
+                instr_ref.eval_nested($slab_ref, $ns_mut)?
             }
         }
     };
@@ -149,6 +196,16 @@ pub trait Evaler: fmt::Debug {
     /// Returns a `fasteval2::Error` if there are any problems, such as undefined variables.
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error>;
 
+    /// Evaluate with variables supplied as a slice of `(name, value)` pairs.
+    ///
+    /// This is a convenience for tiny variable sets, where building a map or
+    /// a closure would be overkill.  Each lookup is a linear scan of `vars`.
+    /// An `UndefinedVariable` error is returned if a variable is missing.
+    fn eval_pairs(&self, slab: &Slab, vars: &[(&str, f64)]) -> Result<f64, Error> {
+        let mut ns: StrToF64PairsNamespace = vars;
+        self.eval(slab, &mut ns)
+    }
+
     /// Don't call this directly.  Use `var_names()` instead.
     ///
     /// This exists because of ternary short-circuits; they prevent us from
@@ -157,6 +214,9 @@ pub trait Evaler: fmt::Debug {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>);
 
     /// Returns a list of variables and custom functions that are used by this `Expression`/`Instruction`.
+    ///
+    /// [`Expression::for_each_var()`](../parser/struct.Expression.html#method.for_each_var)
+    /// visits the same names without collecting them, and can stop early.
     fn var_names(&self, slab: &Slab) -> BTreeSet<String> {
         let mut set = BTreeSet::new();
         self._var_names(slab, &mut set);
@@ -164,14 +224,157 @@ pub trait Evaler: fmt::Debug {
     }
 }
 
+// With the `log` feature, reports a step of the interpreter as a `trace!`
+// event of the `fasteval2::eval` target.  Without it, this expands to nothing.
+macro_rules! log_step {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::trace!(target: "fasteval2::eval", $($arg)*);
+    };
+}
+
+/// One binary operation performed during an
+/// [`Expression::eval_trace()`](../parser/struct.Expression.html#method.eval_trace).
+///
+/// The `Display` form looks like `3 * 4 = 12`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct TraceStep {
+    pub op: BinaryOp,
+    pub left: f64,
+    pub right: f64,
+    pub result: f64,
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} = {}",
+            self.left,
+            self.op.symbol(),
+            self.right,
+            self.result
+        )
+    }
+}
+
+/// The result of an
+/// [`Expression::eval_typed()`](../parser/struct.Expression.html#method.eval_typed):
+/// a number, or the truth value of a comparison or logical operation.
+///
+/// The `Display` form is the number, or `true` / `false`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum EvalResult {
+    Number(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for EvalResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalResult::Number(val) => write!(f, "{}", val),
+            EvalResult::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+// Returns `true` if `val` is the `EvalNamespace::null()` sentinel:
+#[inline(always)]
+pub(crate) fn is_null(ns: &impl EvalNamespace, val: f64) -> bool {
+    matches!(ns.null(), Some(null) if null.to_bits() == val.to_bits())
+}
+
+// The result of an operation on `val`, or null if `val` is null:
+#[inline(always)]
+pub(crate) fn null_or(ns: &impl EvalNamespace, val: f64, f: impl FnOnce() -> f64) -> f64 {
+    if is_null(ns, val) {
+        val
+    } else {
+        f()
+    }
+}
+
+// The result of an operation on `left` and `right`, or null if either is null:
+#[inline(always)]
+pub(crate) fn null_or2(
+    ns: &impl EvalNamespace,
+    left: f64,
+    right: f64,
+    f: impl FnOnce() -> f64,
+) -> f64 {
+    if is_null(ns, left) {
+        left
+    } else if is_null(ns, right) {
+        right
+    } else {
+        f()
+    }
+}
+
+// Evaluates a Value, tracing through parenthesized sub-expressions and unary operators:
+fn eval_value_traced(
+    val: &Value,
+    slab: &Slab,
+    ns: &mut impl EvalNamespace,
+    trace: Option<&mut Vec<TraceStep>>,
+) -> Result<f64, Error> {
+    let trace = match trace {
+        Some(trace) => trace,
+        None => return val.eval(slab, ns),
+    };
+    match val {
+        EUnaryOp(EParentheses(expr_i)) => {
+            get_expr!(slab.ps, expr_i).eval_traced(slab, ns, Some(trace))
+        }
+        EUnaryOp(EPos(val_i)) => eval_value_traced(get_val!(slab.ps, val_i), slab, ns, Some(trace)),
+        EUnaryOp(ENeg(val_i)) => {
+            let val = eval_value_traced(get_val!(slab.ps, val_i), slab, ns, Some(trace))?;
+            Ok(null_or(ns, val, || -val))
+        }
+        EUnaryOp(ENot(val_i)) => {
+            let val = eval_value_traced(get_val!(slab.ps, val_i), slab, ns, Some(trace))?;
+            Ok(null_or(ns, val, || bool_to_f64!(f64_eq!(val, 0.0))))
+        }
+        _ => val.eval(slab, ns),
+    }
+}
+
 impl Evaler for Expression {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>) {
-        self.first._var_names(slab, dst);
-        for pair in &self.pairs {
-            pair.1._var_names(slab, dst);
-        }
+        let _ = vars::collect(slab, dst).expr(self);
     }
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
+        // Tuples have several values, which only eval_vec() returns:
+        if !self.tuple.is_empty() {
+            return Err(Error::NotAllowed("tuple".to_string()));
+        }
+        let val = self.eval_nested(slab, ns)?;
+        ns.check_result(val)
+    }
+}
+
+impl Expression {
+    // Evaluates a sub-expression, which is never a tuple, without the final
+    // `check_result()` of eval():
+    #[inline]
+    pub(crate) fn eval_nested(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<f64, Error> {
+        // A stable Namespace gets a cache for the variables, once:
+        if ns.is_stable() {
+            return self.eval_traced(slab, &mut StableNamespace::new(ns), None);
+        }
+        self.eval_traced(slab, ns, None)
+    }
+
+    fn eval_traced(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+        mut trace: Option<&mut Vec<TraceStep>>,
+    ) -> Result<f64, Error> {
         // Order of operations: 1) ^  2) */  3) +-
         // Exponentiation should be processed right-to-left.  Think of what 2^3^4 should mean:
         //     2^(3^4)=2417851639229258349412352   <--- I choose this one.  https://codeplea.com/exponentiation-associativity-options
@@ -181,148 +384,649 @@ impl Evaler for Expression {
         // ...But Subtraction and Division must be processed left-to-right:
         //     (((6-5)-4)-3)!=(6-(5-(4-3))), (((6/5)/4)/3)!=(6/(5/(4/3)))
 
-        // // ---- Go code, for comparison ----
-        // // vals,ops:=make([]float64, len(e)/2+1),make([]BinaryOp, len(e)/2)
-        // // for i:=0; i<len(e); i+=2 {
-        // //     vals[i/2]=ns.EvalBubble(e[i].(evaler))
-        // //     if i<len(e)-1 { ops[i/2]=e[i+1].(BinaryOp) }
-        // // }
-
-        // if self.0.len()%2!=1 { return Err(KErr::new("Expression len should always be odd")) }
-        // let mut vals : Vec<f64>      = Vec::with_capacity(self.0.len()/2+1);
-        // let mut ops  : Vec<BinaryOp> = Vec::with_capacity(self.0.len()/2  );
-        // for (i,tok) in self.0.iter().enumerate() {
-        //     match tok {
-        //         EValue(val) => {
-        //             if i%2==1 { return Err(KErr::new("Found value at odd index")) }
-        //             match ns.eval_bubble(val) {
-        //                 Ok(f) => vals.push(f),
-        //                 Err(e) => return Err(e.pre(&format!("eval_bubble({:?})",val))),
-        //             }
-        //         }
-        //         EBinaryOp(bop) => {
-        //             if i%2==0 { return Err(KErr::new("Found binaryop at even index")) }
-        //             ops.push(*bop);
-        //         }
-        //     }
-        // }
-
-        // Code for new Expression data structure:
-        let mut vals = Vec::<f64>::with_capacity(self.pairs.len() + 1);
-        let mut ops = Vec::<BinaryOp>::with_capacity(self.pairs.len());
-        vals.push(self.first.eval(slab, ns)?);
+        // Every entry of OP_PASSES is a level of precedence, and the passes are
+        // replayed with a shunting-yard over two stacks: an operator is applied
+        // as soon as the next operator binds less tightly, or equally tightly
+        // in a left-to-right pass.  That groups the operands exactly like the
+        // passes would, without moving values around.  Only the order of the
+        // steps differs, when operations are independent of each other.
+        let first = eval_value_traced(&self.first, slab, ns, trace.as_deref_mut())?;
+        log_step!("value {}", first);
+        if self.pairs.is_empty() {
+            return Ok(first);
+        }
+
+        let null = ns.null();
+        let zpz = ns.zero_pow_zero();
+        let mm = ns.mod_mode();
+        let mut vals = InlineStack::<f64, { INLINE_OPS + 1 }>::new(0.0);
+        let mut ops = InlineStack::<BinaryOp, INLINE_OPS>::new(EOR);
+        // Values are all evaluated before any operation can fail, like they
+        // are by the passes:
+        let mut op_err = None;
+        vals.push(first);
         for pair in self.pairs.iter() {
+            ns.tick()?;
+            let val = eval_value_traced(&pair.1, slab, ns, trace.as_deref_mut())?;
+            log_step!("value {}", val);
+            let (pass, rtol) = op_pass(pair.0);
+            while let Some(top) = ops.last() {
+                let top_pass = op_pass(top).0;
+                if top_pass > pass || top_pass == pass && rtol {
+                    break;
+                }
+                apply_top(&mut vals, &mut ops, null, zpz, mm, &mut op_err, &mut trace);
+            }
             ops.push(pair.0);
-            vals.push(pair.1.eval(slab, ns)?);
-        }
-
-        // ---- Go code, for comparison ----
-        // evalOp:=func(i int) {
-        //     result:=ops[i]._Eval(vals[i], vals[i+1])
-        //     vals=append(append(vals[:i], result), vals[i+2:]...)
-        //     ops=append(ops[:i], ops[i+1:]...)
-        // }
-        // rtol:=func(s BinaryOp) { for i:=len(ops)-1; i>=0; i-- { if ops[i]==s { evalOp(i) } } }
-        // ltor:=func(s BinaryOp) {
-        //     loop:
-        //     for i:=0; i<len(ops); i++ { if ops[i]==s { evalOp(i); goto loop } }  // Need to restart processing when modifying from the left.
-        // }
-
-        #[inline(always)]
-        fn rtol(vals: &mut Vec<f64>, ops: &mut Vec<BinaryOp>, search: BinaryOp) {
-            for i in (0..ops.len()).rev() {
-                let op = match ops.get(i) {
-                    Some(op) => *op,
-                    None => EOR, // unreachable
+            vals.push(val);
+        }
+        while !ops.is_empty() {
+            apply_top(&mut vals, &mut ops, null, zpz, mm, &mut op_err, &mut trace);
+        }
+
+        if let Some(err) = op_err {
+            return Err(err);
+        }
+        if vals.len() != 1 {
+            return Err(Error::Unreachable);
+        }
+        vals.pop().ok_or(Error::Unreachable)
+    }
+
+    /// Evaluates this `Expression` like `eval()`, and also returns every
+    /// binary operation that was performed, in the order they were reduced.
+    ///
+    /// Operations inside parentheses and unary operators are traced too, so
+    /// `(1+2)*3` produces `1 + 2 = 3` followed by `3 * 3 = 9`.  The arguments
+    /// of function calls are evaluated normally, without tracing.
+    ///
+    /// This is slower than `eval()`, so use it for debugging, not in hot loops.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{EmptyNamespace, Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let expr_ref = Parser::new().parse("2+3*4", &mut slab.ps).unwrap().from(&slab.ps);
+    /// let (val, trace) = expr_ref.eval_trace(&slab, &mut EmptyNamespace).unwrap();
+    /// assert_eq!(val, 14.0);
+    /// let steps: Vec<String> = trace.iter().map(|step| step.to_string()).collect();
+    /// assert_eq!(steps, vec!["3 * 4 = 12", "2 + 12 = 14"]);
+    /// ```
+    pub fn eval_trace(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<(f64, Vec<TraceStep>), Error> {
+        if !self.tuple.is_empty() {
+            return Err(Error::NotAllowed("tuple".to_string()));
+        }
+        let mut trace = Vec::with_capacity(self.pairs.len());
+        let val = self.eval_traced(slab, ns, Some(&mut trace))?;
+        Ok((ns.check_result(val)?, trace))
+    }
+
+    /// Evaluates this `Expression` like `eval()`, but an error also reports
+    /// where the problem is in the source string, as a
+    /// [`SpannedError`](../error/struct.SpannedError.html).
+    ///
+    /// `UndefinedVariable`, `UndefinedFunction`, `WrongArgCount` and
+    /// `NotFinite` errors span the first variable or function call that they
+    /// name, and error placeholders from
+    /// [`parse_with_recovery()`](struct.Parser.html#method.parse_with_recovery)
+    /// span themselves.  Other errors, like a `DivideByZero`, span the whole
+    /// expression.  The spans are empty (`0..0`) if the expression was parsed
+    /// without [`with_spans()`](struct.Parser.html#method.with_spans).
+    ///
+    /// The span is only looked for after a failure, so a successful
+    /// evaluation is as fast as `eval()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Error, Parser, Slab};
+    ///
+    /// let src = "x * 2 +\n  scale(x, y)";
+    /// let mut slab = Slab::new();
+    /// let expr_ref = Parser::new().with_spans().parse(src, &mut slab.ps).unwrap().from(&slab.ps);
+    /// let mut ns = |name: &str, _: Vec<f64>| if name == "x" { Some(1.0) } else { None };
+    /// let err = expr_ref.eval_spanned(&slab, &mut ns).unwrap_err();
+    /// assert_eq!(
+    ///     err.error,
+    ///     Error::UndefinedVariable { name: "y".to_string() }
+    /// );
+    /// assert_eq!(&src[err.span.clone()], "y");
+    /// assert_eq!(err.line_col(src), (2, 12));
+    /// ```
+    pub fn eval_spanned(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<f64, SpannedError> {
+        self.eval(slab, ns).map_err(|error| SpannedError {
+            span: validate::failing_span(self, slab, &error),
+            error,
+        })
+    }
+
+    /// Evaluates every item of a tuple, like the `x * 2, y * 2` that a
+    /// `Parser` with [`tuples`](../parser/struct.ParserBuilder.html#method.tuples)
+    /// reads, and returns their values in order.  Any other `Expression` is a
+    /// tuple of one item.
+    ///
+    /// The items are evaluated from left to right with the same Namespace,
+    /// and the first error stops the evaluation.  `eval()` can't evaluate a
+    /// tuple of several items: it returns a `NotAllowed("tuple")` error, and so
+    /// do `compile()`d tuples.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let parser = Parser::builder().tuples(true).build()?;
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = parser.parse("r * cos(t), r * sin(t)", &mut slab.ps)?.from(&slab.ps);
+    ///     let mut ns = |name: &str, _: Vec<f64>| match name {
+    ///         "r" => Some(2.0),
+    ///         "t" => Some(0.0),
+    ///         _ => None,
+    ///     };
+    ///     assert_eq!(expr_ref.eval_vec(&slab, &mut ns)?, vec![2.0, 0.0]);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn eval_vec(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<Vec<f64>, Error> {
+        // Like eval(), with one cache for all of the items:
+        let vals = if ns.is_stable() {
+            self.eval_items(slab, &mut StableNamespace::new(ns))?
+        } else {
+            self.eval_items(slab, ns)?
+        };
+        vals.into_iter().map(|val| ns.check_result(val)).collect()
+    }
+
+    fn eval_items(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<Vec<f64>, Error> {
+        let mut vals = Vec::with_capacity(self.tuple.len() + 1);
+        vals.push(self.eval_traced(slab, ns, None)?);
+        for item in &self.tuple {
+            vals.push(get_expr!(slab.ps, item).eval_traced(slab, ns, None)?);
+        }
+        Ok(vals)
+    }
+
+    /// Evaluates this `Expression` like `eval()`, and tells whether the result
+    /// is a number or a truth value.
+    ///
+    /// The type comes from the operator that is applied last, like the `>` of
+    /// `x + 1 > 3`: comparisons and `!` produce a `Bool`, and arithmetic,
+    /// numbers, variables and function calls produce a `Number`.  `&&` and `||`
+    /// return one of their operands, so they produce a `Bool` only if both
+    /// operands are truth values, and `piecewise()` only if all of its values
+    /// are.  Parentheses don't change the type.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{EmptyNamespace, EvalResult, Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let mut eval = |s: &str| {
+    ///     let expr_ref = Parser::new().parse(s, &mut slab.ps).unwrap().from(&slab.ps);
+    ///     expr_ref.eval_typed(&slab, &mut EmptyNamespace).unwrap()
+    /// };
+    /// assert_eq!(eval("5 > 3"), EvalResult::Bool(true));
+    /// assert_eq!(eval("5 + 3"), EvalResult::Number(8.0));
+    /// assert_eq!(eval("1 < 2 && 2 < 1").to_string(), "false");
+    /// ```
+    pub fn eval_typed(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<EvalResult, Error> {
+        let val = self.eval(slab, ns)?;
+        if expr_is_bool(self, slab) {
+            Ok(EvalResult::Bool(f64_ne!(val, 0.0)))
+        } else {
+            Ok(EvalResult::Number(val))
+        }
+    }
+
+    /// Evaluates this `Expression` with some variables replaced by
+    /// `overrides`, for just this call.
+    ///
+    /// A variable is looked up in `overrides` first (the first pair with its
+    /// name wins), and then in `ns`, so `ns` keeps its own values.  Functions
+    /// and the limits of `ns` work as usual, and like `ez_eval()`, the result
+    /// goes through `ns.check_result()`.
+    ///
+    /// `ns` is borrowed mutably because lookups can change its state, like
+    /// the used budget or the random number generator, but its variables are
+    /// never modified.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{EvalNSBuilder, Parser, Slab};
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = Parser::new().parse("x * y", &mut slab.ps)?.from(&slab.ps);
+    ///     let mut ns = EvalNSBuilder::new().var("x", 2.0).var("y", 3.0).build();
+    ///     assert_eq!(expr_ref.eval_with(&slab, &mut ns, &[("x", 10.0)])?, 30.0);
+    ///     assert_eq!(expr_ref.eval_with(&slab, &mut ns, &[])?, 6.0);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn eval_with<NS: EvalNamespace>(
+        &self,
+        slab: &Slab,
+        ns: &mut EvalNS<NS>,
+        overrides: &[(&str, f64)],
+    ) -> Result<f64, Error> {
+        let mut layered = OverrideNamespace {
+            overrides,
+            inner: ns,
+        };
+        self.eval(slab, &mut layered)
+    }
+
+    /// Returns the number of distinct variables and custom functions that are
+    /// used by this `Expression`.
+    ///
+    /// This is a static walk of the parsed expression; nothing is evaluated.
+    /// A name that is used several times is only counted once.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let expr_ref = Parser::new().parse("a + a*b", &mut slab.ps).unwrap().from(&slab.ps);
+    /// assert_eq!(expr_ref.var_count(&slab), 2);
+    /// ```
+    pub fn var_count(&self, slab: &Slab) -> usize {
+        self.var_names(slab).len()
+    }
+
+    /// Returns a `TooManyVars` error if this `Expression` uses more than
+    /// `limit` distinct variables and custom functions.
+    ///
+    /// Use this to reject expensive inputs before evaluating them.
+    pub fn validate_var_limit(&self, slab: &Slab, limit: usize) -> Result<(), Error> {
+        let count = self.var_count(slab);
+        if count > limit {
+            return Err(Error::TooManyVars(count));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if this `Expression` uses no variables or custom
+    /// functions, so it evaluates to the same result every time and can be
+    /// cached.
+    ///
+    /// Like `var_count()`, this is a static check.  `rand()` and `randint()`
+    /// are not constant, and neither are named constants like `phi`, because
+    /// a namespace can override them.  `print()` is constant if its arguments
+    /// are; caching it skips the output.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let mut is_constant = |s: &str| {
+    ///     Parser::new().parse(s, &mut slab.ps).unwrap().from(&slab.ps).is_constant(&slab)
+    /// };
+    /// assert!(is_constant("2+3"));
+    /// assert!(!is_constant("x+1"));
+    /// assert!(!is_constant("rand()"));
+    /// ```
+    pub fn is_constant(&self, slab: &Slab) -> bool {
+        self.var_count(slab) == 0
+    }
+
+    /// Returns the peak number of operands that are live while this
+    /// `Expression` is evaluated on a stack machine.
+    ///
+    /// Every number, variable and function result is one operand, and a
+    /// binary operator replaces its two operands with the result.  Operators
+    /// are grouped the way that `eval()` groups them (the order of
+    /// `eval_trace()`), so precedence matters: `a*b + c` needs 2 operands,
+    /// but `a + b*c` needs 3.  Function arguments stay live until the call,
+    /// `piecewise()` only evaluates one condition or value at a time, and
+    /// while a `sum()` body runs, the upper bound and the running total are
+    /// live too.
+    ///
+    /// This is a static walk of the parsed expression; nothing is evaluated.
+    /// To size the buffer of
+    /// [`CompiledExpression::eval_with_stack()`](../bytecode/struct.CompiledExpression.html#method.eval_with_stack),
+    /// use its `stack_depth()` instead.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let expr_ref = Parser::new().parse("(a+b)*(c+d)", &mut slab.ps).unwrap().from(&slab.ps);
+    /// assert_eq!(expr_ref.max_depth(&slab), 3);
+    /// ```
+    pub fn max_depth(&self, slab: &Slab) -> usize {
+        expr_depth(self, slab)
+    }
+}
+
+// The operator passes of `Expression::eval()`, in order: (operators, right-to-left)
+pub(crate) const OP_PASSES: [(&[BinaryOp], bool); 9] = [
+    (&[EExp], true),
+    (&[EMod], false),
+    (&[EDiv], false),
+    (&[EMul], true),
+    (&[ESub], false),
+    (&[EAdd], true),
+    (&[ELT, EGT, ELTE, EGTE, EEQ, ENE], false),
+    (&[EAND], false),
+    (&[EOR], false),
+];
+
+// The operator pass (counting from 1) that evaluates `op`.  The printers
+// group operations by these levels:
+pub(crate) fn level_of(op: BinaryOp) -> usize {
+    OP_PASSES
+        .iter()
+        .position(|(search, _)| search.contains(&op))
+        .map_or(0, |pass| pass + 1)
+}
+
+// The index of the entry of OP_PASSES that contains `op`, and its direction.
+// This is a match because `Expression::eval()` needs it for every operator:
+#[inline(always)]
+fn op_pass(op: BinaryOp) -> (u8, bool) {
+    match op {
+        EExp => (0, true),
+        EMod => (1, false),
+        EDiv => (2, false),
+        EMul => (3, true),
+        ESub => (4, false),
+        EAdd => (5, true),
+        ELT | EGT | ELTE | EGTE | EEQ | ENE => (6, false),
+        EAND => (7, false),
+        EOR => (8, false),
+    }
+}
+
+// How many operators `Expression::eval()` can keep waiting without allocating.
+// Operators only wait for ones that bind more tightly, or for the rest of a
+// right-to-left chain like `a + b + c`, so this covers most expressions:
+const INLINE_OPS: usize = 32;
+
+// A stack that keeps its first `N` items in place, and only allocates for the
+// items after those:
+struct InlineStack<T, const N: usize> {
+    inline: [T; N],
+    len: usize,
+    spilled: Vec<T>,
+}
+
+impl<T: Copy, const N: usize> InlineStack<T, N> {
+    #[inline(always)]
+    fn new(fill: T) -> Self {
+        Self {
+            inline: [fill; N],
+            len: 0,
+            spilled: Vec::new(),
+        }
+    }
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.len
+    }
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    #[inline(always)]
+    fn push(&mut self, item: T) {
+        match self.inline.get_mut(self.len) {
+            Some(slot) => *slot = item,
+            None => self.spilled.push(item),
+        }
+        self.len += 1;
+    }
+    #[inline(always)]
+    fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        match self.inline.get(self.len) {
+            Some(item) => Some(*item),
+            None => self.spilled.pop(),
+        }
+    }
+    #[inline(always)]
+    fn last(&self) -> Option<T> {
+        let i = self.len.checked_sub(1)?;
+        match self.inline.get(i) {
+            Some(item) => Some(*item),
+            None => self.spilled.last().copied(),
+        }
+    }
+}
+
+// Applies the operator on top of `ops` to the two values on top of `vals`.  A
+// failure is kept in `err`, and the result is NaN then:
+#[inline(always)]
+fn apply_top(
+    vals: &mut InlineStack<f64, { INLINE_OPS + 1 }>,
+    ops: &mut InlineStack<BinaryOp, INLINE_OPS>,
+    null: Option<f64>,
+    zero_pow_zero: ZeroPowZero,
+    mod_mode: ModMode,
+    err: &mut Option<Error>,
+    trace: &mut Option<&mut Vec<TraceStep>>,
+) {
+    let op = ops.pop().unwrap_or(EOR); // unreachable
+    let right = vals.pop();
+    let left = vals.pop();
+    let mut res = op.binaryop_eval_null(left.as_ref(), right.as_ref(), null);
+    // `binaryop_eval()` follows `powf()`, where `0^0` is 1, and the remainder
+    // of `f64`:
+    if op == EExp && zero_pow_zero != ZeroPowZero::One || op == EMod && mod_mode != ModMode::Float {
+        let is_null = |v: f64| null.is_some_and(|n| n.to_bits() == v.to_bits());
+        if let (Some(l), Some(r)) = (left, right) {
+            if !is_null(l) && !is_null(r) {
+                let checked = if op == EExp {
+                    zero_pow_zero.pow(l, r)
+                } else {
+                    mod_mode.rem(l, r)
                 };
-                if op == search {
-                    let res = op.binaryop_eval(vals.get(i), vals.get(i + 1));
-                    match vals.get_mut(i) {
-                        Some(val_ref) => *val_ref = res,
-                        None => (), // unreachable
-                    };
-                    remove_no_panic(vals, i + 1);
-                    remove_no_panic(ops, i);
-                }
+                res = checked.unwrap_or_else(|e| {
+                    err.get_or_insert(e);
+                    f64::NAN
+                });
             }
         }
-        #[inline(always)]
-        fn ltor(vals: &mut Vec<f64>, ops: &mut Vec<BinaryOp>, search: BinaryOp) {
-            let mut i = 0;
-            loop {
-                match ops.get(i) {
-                    None => break,
-                    Some(op) => {
-                        if *op == search {
-                            let res = op.binaryop_eval(vals.get(i), vals.get(i + 1));
-                            match vals.get_mut(i) {
-                                Some(val_ref) => *val_ref = res,
-                                None => (), // unreachable
-                            };
-                            remove_no_panic(vals, i + 1);
-                            remove_no_panic(ops, i);
-                        } else {
-                            i = i + 1;
-                        }
-                    }
+    }
+    let step = || TraceStep {
+        op,
+        left: left.unwrap_or(f64::NAN),
+        right: right.unwrap_or(f64::NAN),
+        result: res,
+    };
+    log_step!("op {}", step());
+    if let Some(trace) = trace {
+        trace.push(step());
+    }
+    vals.push(res);
+}
+
+fn expr_depth(expr: &Expression, slab: &Slab) -> usize {
+    let mut depths = Vec::with_capacity(expr.pairs.len() + 1);
+    let mut ops = Vec::with_capacity(expr.pairs.len());
+    depths.push(value_depth(&expr.first, slab));
+    for pair in &expr.pairs {
+        ops.push(pair.0);
+        depths.push(value_depth(&pair.1, slab));
+    }
+
+    // The left operand is computed first, and stays live while the right one is computed:
+    let reduce = |depths: &mut Vec<usize>, ops: &mut Vec<BinaryOp>, i: usize| {
+        depths[i] = depths[i].max(1 + depths[i + 1]);
+        depths.remove(i + 1);
+        ops.remove(i);
+    };
+    for (search, rtol) in OP_PASSES {
+        if rtol {
+            for i in (0..ops.len()).rev() {
+                if search.contains(&ops[i]) {
+                    reduce(&mut depths, &mut ops, i);
                 }
             }
-        }
-        #[inline(always)]
-        fn ltor_multi(vals: &mut Vec<f64>, ops: &mut Vec<BinaryOp>, search: &[BinaryOp]) {
+        } else {
             let mut i = 0;
-            loop {
-                match ops.get(i) {
-                    None => break,
-                    Some(op) => {
-                        if search.contains(op) {
-                            let res = op.binaryop_eval(vals.get(i), vals.get(i + 1));
-                            match vals.get_mut(i) {
-                                Some(val_ref) => *val_ref = res,
-                                None => (), // unreachable
-                            };
-                            remove_no_panic(vals, i + 1);
-                            remove_no_panic(ops, i);
-                        } else {
-                            i = i + 1;
-                        }
-                    }
+            while i < ops.len() {
+                if search.contains(&ops[i]) {
+                    reduce(&mut depths, &mut ops, i);
+                } else {
+                    i += 1;
                 }
             }
         }
+    }
+    depths[0]
+}
 
-        // Keep the order of these statements in-sync with parser.rs BinaryOp priority values:
-        rtol(&mut vals, &mut ops, EExp); // https://codeplea.com/exponentiation-associativity-options
-        ltor(&mut vals, &mut ops, EMod);
-        ltor(&mut vals, &mut ops, EDiv);
-        rtol(&mut vals, &mut ops, EMul);
-        ltor(&mut vals, &mut ops, ESub);
-        rtol(&mut vals, &mut ops, EAdd);
-        ltor_multi(&mut vals, &mut ops, &[ELT, EGT, ELTE, EGTE, EEQ, ENE]); // TODO: Implement Python-style a<b<c ternary comparison... might as well generalize to N comparisons.
-        ltor(&mut vals, &mut ops, EAND);
-        ltor(&mut vals, &mut ops, EOR);
+// Whether `expr` always evaluates to a truth value.  See `Expression::eval_typed()`.
+fn expr_is_bool(expr: &Expression, slab: &Slab) -> bool {
+    let mut bools = Vec::with_capacity(expr.pairs.len() + 1);
+    let mut ops = Vec::with_capacity(expr.pairs.len());
+    bools.push(value_is_bool(&expr.first, slab));
+    for pair in &expr.pairs {
+        ops.push(pair.0);
+        bools.push(value_is_bool(&pair.1, slab));
+    }
 
-        if !ops.is_empty() {
-            return Err(Error::Unreachable);
+    let reduce = |bools: &mut Vec<bool>, ops: &mut Vec<BinaryOp>, i: usize| {
+        bools[i] = match ops[i] {
+            ELT | EGT | ELTE | EGTE | EEQ | ENE => true,
+            EAND | EOR => bools[i] && bools[i + 1],
+            EAdd | ESub | EMul | EDiv | EMod | EExp => false,
+        };
+        bools.remove(i + 1);
+        ops.remove(i);
+    };
+    for (search, rtol) in OP_PASSES {
+        if rtol {
+            for i in (0..ops.len()).rev() {
+                if search.contains(&ops[i]) {
+                    reduce(&mut bools, &mut ops, i);
+                }
+            }
+        } else {
+            let mut i = 0;
+            while i < ops.len() {
+                if search.contains(&ops[i]) {
+                    reduce(&mut bools, &mut ops, i);
+                } else {
+                    i += 1;
+                }
+            }
         }
-        if vals.len() != 1 {
-            return Err(Error::Unreachable);
+    }
+    bools[0]
+}
+
+fn value_is_bool(value: &Value, slab: &Slab) -> bool {
+    let expr = |xi: &ExpressionI| expr_is_bool(get_expr!(slab.ps, xi), slab);
+    match value {
+        EUnaryOp(ENot(_)) => true,
+        EUnaryOp(EParentheses(xi)) => expr(xi),
+        EStdFunc(EFuncPiecewise { branches, default }) => {
+            branches.iter().all(|(_, val_i)| expr(val_i)) && expr(default)
         }
-        match vals.first() {
-            Some(val) => Ok(*val),
-            None => Err(Error::Unreachable),
+        EStdFunc(EFuncLet { expr: body, .. }) => expr(body),
+        EStdFunc(EFuncIn { .. }) => true,
+        _ => false,
+    }
+}
+
+fn value_depth(value: &Value, slab: &Slab) -> usize {
+    let expr = |xi: &ExpressionI| expr_depth(get_expr!(slab.ps, xi), slab);
+    // Operands that are all live until the call:
+    let args = |depths: &mut dyn Iterator<Item = usize>| {
+        depths
+            .enumerate()
+            .map(|(k, depth)| k + depth)
+            .max()
+            .unwrap_or(1)
+    };
+    match value {
+        EConstant(_) | EError(_) => 1,
+        EUnaryOp(EPos(vi)) | EUnaryOp(ENeg(vi)) | EUnaryOp(ENot(vi)) => {
+            value_depth(get_val!(slab.ps, vi), slab)
         }
+        EUnaryOp(EParentheses(xi)) => expr(xi),
+        EPrintFunc(pf) => args(&mut pf.0.iter().filter_map(|x_or_s| match x_or_s {
+            EExpr(xi) => Some(expr(xi)),
+            EStr(_) => None,
+        })),
+        EStdFunc(f) => match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { .. } => 1,
+            EVar(_) | EFuncE | EFuncPi => 1,
+            EFunc { args: xis, .. } | EFuncNorm { args: xis, .. } => {
+                args(&mut xis.iter().map(expr))
+            }
+
+            EFuncInt(xi) | EFuncCeil(xi) | EFuncFloor(xi) | EFuncAbs(xi) | EFuncSign(xi)
+            | EFuncSin(xi) | EFuncCos(xi) | EFuncTan(xi) | EFuncASin(xi) | EFuncACos(xi)
+            | EFuncATan(xi) | EFuncSinH(xi) | EFuncCosH(xi) | EFuncTanH(xi) | EFuncASinH(xi)
+            | EFuncACosH(xi) | EFuncATanH(xi) | EFuncSqrt(xi) => expr(xi),
+            EFuncLog {
+                base: opt,
+                expr: xi,
+            }
+            | EFuncRound {
+                modulus: opt,
+                expr: xi,
+            } => args(&mut opt.iter().chain(Some(xi)).map(expr)),
+            EFuncFma { a, b, c } => args(&mut [a, b, c].into_iter().map(expr)),
+            EFuncCoalesce { value, fallback } => args(&mut [value, fallback].into_iter().map(expr)),
+            EFuncQuotient { dividend, divisor } | EFuncRemainder { dividend, divisor } => {
+                args(&mut [dividend, divisor].into_iter().map(expr))
+            }
+            EFuncInterp { x, points, .. } => args(
+                &mut Some(x)
+                    .into_iter()
+                    .chain(points.iter().flat_map(|(px, py)| [px, py]))
+                    .map(expr),
+            ),
+            // A running minimum/maximum:
+            EFuncMin { first, rest } | EFuncMax { first, rest } => rest
+                .iter()
+                .map(|xi| 1 + expr(xi))
+                .fold(expr(first), usize::max),
+            EFuncPiecewise { branches, default } => branches
+                .iter()
+                .flat_map(|(cond, val)| [cond, val])
+                .map(expr)
+                .fold(expr(default), usize::max),
+            // The value is live while the list is evaluated:
+            EFuncIn { value, list, .. } => list
+                .iter()
+                .map(|xi| 1 + expr(xi))
+                .fold(expr(value), usize::max),
+            EFuncSum {
+                from,
+                to,
+                expr: body,
+                ..
+            } => expr(from).max(1 + expr(to)).max(2 + expr(body)),
+            EFuncLet {
+                value, expr: body, ..
+            } => expr(value).max(1 + expr(body)),
+        },
     }
 }
 
 impl Evaler for Value {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>) {
-        match self {
-            EConstant(_) => (),
-            EUnaryOp(u) => u._var_names(slab, dst),
-            EStdFunc(f) => f._var_names(slab, dst),
-            EPrintFunc(f) => f._var_names(slab, dst),
-        };
+        let _ = vars::collect(slab, dst).value(self);
     }
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
         match self {
@@ -330,43 +1034,81 @@ impl Evaler for Value {
             EUnaryOp(u) => u.eval(slab, ns),
             EStdFunc(f) => f.eval(slab, ns),
             EPrintFunc(f) => f.eval(slab, ns),
+            EError(err) => Err(err.clone()),
         }
     }
 }
 
 impl Evaler for UnaryOp {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>) {
-        match self {
-            EPos(val_i) | ENeg(val_i) | ENot(val_i) => {
-                get_val!(slab.ps, val_i)._var_names(slab, dst)
-            }
-            EParentheses(expr_i) => get_expr!(slab.ps, expr_i)._var_names(slab, dst),
-        }
+        let _ = vars::collect(slab, dst).unary(self);
     }
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
         match self {
             EPos(val_i) => get_val!(slab.ps, val_i).eval(slab, ns),
-            ENeg(val_i) => Ok(-get_val!(slab.ps, val_i).eval(slab, ns)?),
-            ENot(val_i) => Ok(bool_to_f64!(f64_eq!(
-                get_val!(slab.ps, val_i).eval(slab, ns)?,
-                0.0
-            ))),
-            EParentheses(expr_i) => get_expr!(slab.ps, expr_i).eval(slab, ns),
+            ENeg(val_i) => {
+                let val = get_val!(slab.ps, val_i).eval(slab, ns)?;
+                Ok(null_or(ns, val, || -val))
+            }
+            ENot(val_i) => {
+                let val = get_val!(slab.ps, val_i).eval(slab, ns)?;
+                Ok(null_or(ns, val, || bool_to_f64!(f64_eq!(val, 0.0))))
+            }
+            EParentheses(expr_i) => get_expr!(slab.ps, expr_i).eval_nested(slab, ns),
         }
     }
 }
 
 impl BinaryOp {
+    // The operator as it is written in expressions:
+    pub(crate) fn symbol(self) -> &'static str {
+        match self {
+            EOR => "||",
+            EAND => "&&",
+            ENE => "!=",
+            EEQ => "==",
+            EGTE => ">=",
+            ELTE => "<=",
+            EGT => ">",
+            ELT => "<",
+            EAdd => "+",
+            ESub => "-",
+            EMul => "*",
+            EDiv => "/",
+            EMod => "%",
+            EExp => "^",
+        }
+    }
+
     // Non-standard eval interface (not generalized yet):
-    fn binaryop_eval(self, left_opt: Option<&f64>, right_opt: Option<&f64>) -> f64 {
+    // Like `binaryop_eval()`, but spreads the `EvalNamespace::null()` sentinel.
+    // `&&` and `||` pick one of their operands without a null check, like the
+    // compiled evaluator, which may not evaluate (or even compile) the other one.
+    #[inline(always)]
+    fn binaryop_eval_null(
+        self,
+        left_opt: Option<&f64>,
+        right_opt: Option<&f64>,
+        null: Option<f64>,
+    ) -> f64 {
+        if let Some(null) = null {
+            let is_null = |val: Option<&f64>| val.is_some_and(|v| v.to_bits() == null.to_bits());
+            if !matches!(self, EAND | EOR) && (is_null(left_opt) || is_null(right_opt)) {
+                return null;
+            }
+        }
+        self.binaryop_eval(left_opt, right_opt)
+    }
+
+    pub(crate) fn binaryop_eval(self, left_opt: Option<&f64>, right_opt: Option<&f64>) -> f64 {
         // Passing 'self' by value is more efficient than pass-by-reference.
         let left = match left_opt {
             Some(l) => *l,
-            None => return std::f64::NAN,
+            None => return f64::NAN,
         };
         let right = match right_opt {
             Some(r) => *r,
-            None => return std::f64::NAN,
+            None => return f64::NAN,
         };
         match self {
             EAdd => left + right, // Floats don't overflow.
@@ -399,77 +1141,68 @@ impl BinaryOp {
     }
 }
 
+/// Returns the value of a named constant like `phi` or `true`.
+///
+/// Named constants are used as fallbacks for variables that are not defined
+/// by the namespace, so a namespace can override them.
+pub(crate) fn named_constant(name: &str) -> Option<f64> {
+    match name {
+        "phi" => Some(1.618033988749895),
+        "sqrt2" => Some(consts::SQRT_2),
+        "ln2" => Some(consts::LN_2),
+        "ln10" => Some(consts::LN_10),
+        // The same values that comparisons evaluate to:
+        "true" => Some(1.0),
+        "false" => Some(0.0),
+        _ => None,
+    }
+}
+
 #[macro_export]
 macro_rules! eval_var {
-    ($ns:ident, $name:ident, $args:expr, $keybuf:expr) => {
-        match $ns.lookup($name, $args, $keybuf) {
-            Some(f) => Ok(f),
-            None => Err(Error::Undefined($name.to_string())),
+    ($ns:ident, $name:ident, $keybuf:expr) => {
+        match $ns.try_lookup($name, Vec::new(), $keybuf) {
+            Ok(Some(f)) => Ok(f),
+            Ok(None) => Err(Error::UndefinedVariable {
+                name: $name.to_string(),
+            }),
+            Err(err) => Err(err),
         }
     };
+    ($ns:ident, $name:ident, $args:expr, $keybuf:expr) => {{
+        let args: Vec<f64> = $args;
+        let arg_count = args.len();
+        match $ns.try_lookup($name, args, $keybuf) {
+            Ok(Some(f)) => Ok(f),
+            Ok(None) => Err(Error::UndefinedFunction {
+                name: $name.to_string(),
+                arg_count,
+            }),
+            Err(err) => Err(err),
+        }
+    }};
 }
 
 impl Evaler for StdFunc {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>) {
-        match self {
-            #[cfg(feature = "unsafe-vars")]
-            EUnsafeVar { name, .. } => {
-                dst.insert(name.clone());
-            }
-
-            EVar(s) => {
-                dst.insert(s.clone());
-            }
-            EFunc { name, args } => {
-                dst.insert(name.clone());
-                for arg in args {
-                    get_expr!(slab.ps, arg)._var_names(slab, dst);
-                }
-            }
-
-            EFuncInt(xi) | EFuncCeil(xi) | EFuncFloor(xi) | EFuncAbs(xi) | EFuncSign(xi)
-            | EFuncSin(xi) | EFuncCos(xi) | EFuncTan(xi) | EFuncASin(xi) | EFuncACos(xi)
-            | EFuncATan(xi) | EFuncSinH(xi) | EFuncCosH(xi) | EFuncTanH(xi) | EFuncASinH(xi)
-            | EFuncACosH(xi) | EFuncATanH(xi) | EFuncSqrt(xi) => {
-                get_expr!(slab.ps, xi)._var_names(slab, dst)
-            }
-
-            EFuncE | EFuncPi => (),
-            EFuncLog { base: opt, expr } | EFuncRound { modulus: opt, expr } => {
-                match opt {
-                    Some(xi) => get_expr!(slab.ps, xi)._var_names(slab, dst),
-                    None => (),
-                }
-                get_expr!(slab.ps, expr)._var_names(slab, dst);
-            }
-            EFuncMin { first, rest } | EFuncMax { first, rest } => {
-                get_expr!(slab.ps, first)._var_names(slab, dst);
-                for xi in rest {
-                    get_expr!(slab.ps, xi)._var_names(slab, dst);
-                }
-            }
-        };
+        let _ = vars::collect(slab, dst).stdfunc(self);
     }
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
+        ns.tick()?;
         match self {
             // These match arms are ordered in a way that I feel should deliver good performance.
             // (I don't think this ordering actually affects the generated code, though.)
             #[cfg(feature = "unsafe-vars")]
             EUnsafeVar { ptr, .. } => unsafe { Ok(**ptr) },
 
-            EVar(name) => eval_var!(ns, name, Vec::new(), unsafe {
-                #[allow(invalid_reference_casting)]
-                &mut *(&slab.ps.char_buf as *const _ as *mut _)
-            }),
+            EVar(name) => eval_var!(ns, name, &mut String::new())
+                .or_else(|err| named_constant(name).ok_or(err)),
             EFunc { name, args: xis } => {
                 let mut args = Vec::with_capacity(xis.len());
                 for xi in xis {
-                    args.push(get_expr!(slab.ps, xi).eval(slab, ns)?)
+                    args.push(get_expr!(slab.ps, xi).eval_nested(slab, ns)?)
                 }
-                eval_var!(ns, name, args, unsafe {
-                    #[allow(invalid_reference_casting)]
-                    &mut *(&slab.ps.char_buf as *const _ as *mut _)
-                })
+                eval_var!(ns, name, args, &mut String::new())
             }
 
             EFuncLog {
@@ -477,55 +1210,92 @@ impl Evaler for StdFunc {
                 expr: expr_i,
             } => {
                 let base = match base_opt {
-                    Some(b_expr_i) => get_expr!(slab.ps, b_expr_i).eval(slab, ns)?,
+                    Some(b_expr_i) => get_expr!(slab.ps, b_expr_i).eval_nested(slab, ns)?,
                     None => 10.0,
                 };
-                let n = get_expr!(slab.ps, expr_i).eval(slab, ns)?;
+                let n = get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?;
                 Ok(log(base, n))
             }
 
-            EFuncSin(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.sin()),
-            EFuncCos(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.cos()),
-            EFuncTan(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.tan()),
-            EFuncASin(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.asin()),
-            EFuncACos(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.acos()),
-            EFuncATan(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.atan()),
-            EFuncSinH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.sinh()),
-            EFuncCosH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.cosh()),
-            EFuncTanH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.tanh()),
-            EFuncASinH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.asinh()),
-            EFuncACosH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.acosh()),
-            EFuncATanH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.atanh()),
-            EFuncSqrt(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.sqrt()),
+            EFuncSin(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.sin()),
+            EFuncCos(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.cos()),
+            EFuncTan(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.tan()),
+            EFuncASin(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.asin()),
+            EFuncACos(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.acos()),
+            EFuncATan(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.atan()),
+            EFuncSinH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.sinh()),
+            EFuncCosH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.cosh()),
+            EFuncTanH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.tanh()),
+            EFuncASinH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.asinh()),
+            EFuncACosH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.acosh()),
+            EFuncATanH(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.atanh()),
+            EFuncSqrt(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.sqrt()),
+            EFuncFma { a, b, c } => Ok(get_expr!(slab.ps, a).eval_nested(slab, ns)?.mul_add(
+                get_expr!(slab.ps, b).eval_nested(slab, ns)?,
+                get_expr!(slab.ps, c).eval_nested(slab, ns)?,
+            )),
+            EFuncCoalesce { value, fallback } => Ok(coalesce(
+                get_expr!(slab.ps, value).eval_nested(slab, ns)?,
+                get_expr!(slab.ps, fallback).eval_nested(slab, ns)?,
+            )),
+            EFuncQuotient { dividend, divisor } => {
+                let dividend = get_expr!(slab.ps, dividend).eval_nested(slab, ns)?;
+                let divisor = get_expr!(slab.ps, divisor).eval_nested(slab, ns)?;
+                if is_null(ns, dividend) {
+                    Ok(dividend)
+                } else if is_null(ns, divisor) {
+                    Ok(divisor)
+                } else {
+                    ns.mod_mode().quotient(dividend, divisor)
+                }
+            }
+            EFuncRemainder { dividend, divisor } => {
+                let dividend = get_expr!(slab.ps, dividend).eval_nested(slab, ns)?;
+                let divisor = get_expr!(slab.ps, divisor).eval_nested(slab, ns)?;
+                if is_null(ns, dividend) {
+                    Ok(dividend)
+                } else if is_null(ns, divisor) {
+                    Ok(divisor)
+                } else {
+                    ns.mod_mode().rem(dividend, divisor)
+                }
+            }
+            EFuncNorm { squared, args } => {
+                let mut acc = SumSq::default();
+                for xi in args {
+                    acc.add(get_expr!(slab.ps, xi).eval_nested(slab, ns)?);
+                }
+                Ok(acc.finish(*squared))
+            }
 
             EFuncRound {
                 modulus: modulus_opt,
                 expr: expr_i,
             } => {
                 let modulus = match modulus_opt {
-                    Some(m_expr_i) => get_expr!(slab.ps, m_expr_i).eval(slab, ns)?,
+                    Some(m_expr_i) => get_expr!(slab.ps, m_expr_i).eval_nested(slab, ns)?,
                     None => 1.0,
                 };
-                Ok((get_expr!(slab.ps, expr_i).eval(slab, ns)? / modulus).round() * modulus)
+                Ok((get_expr!(slab.ps, expr_i).eval_nested(slab, ns)? / modulus).round() * modulus)
             }
 
-            EFuncAbs(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.abs()),
-            EFuncSign(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.signum()),
-            EFuncInt(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.trunc()),
-            EFuncCeil(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.ceil()),
-            EFuncFloor(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval(slab, ns)?.floor()),
+            EFuncAbs(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.abs()),
+            EFuncSign(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.signum()),
+            EFuncInt(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.trunc()),
+            EFuncCeil(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.ceil()),
+            EFuncFloor(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_nested(slab, ns)?.floor()),
             EFuncMin {
                 first: first_i,
                 rest,
             } => {
-                let mut min = get_expr!(slab.ps, first_i).eval(slab, ns)?;
+                let mut min = get_expr!(slab.ps, first_i).eval_nested(slab, ns)?;
                 let mut saw_nan = min.is_nan();
                 for x_i in rest.iter() {
-                    min = min.min(get_expr!(slab.ps, x_i).eval(slab, ns)?);
+                    min = min.min(get_expr!(slab.ps, x_i).eval_nested(slab, ns)?);
                     saw_nan = saw_nan || min.is_nan();
                 }
                 if saw_nan {
-                    Ok(std::f64::NAN)
+                    Ok(f64::NAN)
                 } else {
                     Ok(min)
                 }
@@ -534,18 +1304,77 @@ impl Evaler for StdFunc {
                 first: first_i,
                 rest,
             } => {
-                let mut max = get_expr!(slab.ps, first_i).eval(slab, ns)?;
+                let mut max = get_expr!(slab.ps, first_i).eval_nested(slab, ns)?;
                 let mut saw_nan = max.is_nan();
                 for x_i in rest.iter() {
-                    max = max.max(get_expr!(slab.ps, x_i).eval(slab, ns)?);
+                    max = max.max(get_expr!(slab.ps, x_i).eval_nested(slab, ns)?);
                     saw_nan = saw_nan || max.is_nan();
                 }
                 if saw_nan {
-                    Ok(std::f64::NAN)
+                    Ok(f64::NAN)
                 } else {
                     Ok(max)
                 }
             }
+            EFuncPiecewise { branches, default } => {
+                // Only the value of the taken branch is evaluated:
+                for (cond_i, val_i) in branches {
+                    if f64_ne!(get_expr!(slab.ps, cond_i).eval_nested(slab, ns)?, 0.0) {
+                        return get_expr!(slab.ps, val_i).eval_nested(slab, ns);
+                    }
+                }
+                get_expr!(slab.ps, default).eval_nested(slab, ns)
+            }
+            EFuncIn {
+                negated,
+                value,
+                list,
+            } => {
+                let value = get_expr!(slab.ps, value).eval_nested(slab, ns)?;
+                if is_null(ns, value) {
+                    return Ok(value);
+                }
+                // Only the items up to the first match are evaluated:
+                for xi in list {
+                    if f64_eq!(value, get_expr!(slab.ps, xi).eval_nested(slab, ns)?) {
+                        return Ok(bool_to_f64!(!negated));
+                    }
+                }
+                Ok(bool_to_f64!(*negated))
+            }
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                let from = get_expr!(slab.ps, from).eval_nested(slab, ns)?;
+                let to = get_expr!(slab.ps, to).eval_nested(slab, ns)?;
+                let body = get_expr!(slab.ps, expr);
+                eval_sum(var, from, to, ns, |loop_ns| body.eval(slab, loop_ns))
+            }
+            EFuncLet { var, value, expr } => {
+                let val = get_expr!(slab.ps, value).eval_nested(slab, ns)?;
+                let mut scope_ns = ScopeNamespace {
+                    name: var,
+                    val,
+                    inner: ns,
+                };
+                get_expr!(slab.ps, expr).eval_nested(slab, &mut scope_ns)
+            }
+            EFuncInterp {
+                extrapolate,
+                x: x_i,
+                points: point_is,
+            } => {
+                let x = get_expr!(slab.ps, x_i).eval_nested(slab, ns)?;
+                let mut points = Vec::with_capacity(2 * point_is.len());
+                for (px_i, py_i) in point_is {
+                    points.push(get_expr!(slab.ps, px_i).eval_nested(slab, ns)?);
+                    points.push(get_expr!(slab.ps, py_i).eval_nested(slab, ns)?);
+                }
+                interp(x, &points, *extrapolate)
+            }
 
             EFuncE => Ok(consts::E),
             EFuncPi => Ok(consts::PI),
@@ -553,14 +1382,44 @@ impl Evaler for StdFunc {
     }
 }
 
+/// Sums `body` for `var` = `from`, `from+1`, ... while `var <= to`.
+fn eval_sum(
+    var: &str,
+    from: f64,
+    to: f64,
+    ns: &mut impl EvalNamespace,
+    mut body: impl FnMut(&mut ScopeNamespace) -> Result<f64, Error>,
+) -> Result<f64, Error> {
+    check_sum_bounds(from, to)?;
+    let mut loop_ns = ScopeNamespace {
+        name: var,
+        val: from,
+        inner: ns,
+    };
+    let mut sum = 0.0;
+    while loop_ns.val <= to {
+        loop_ns.tick()?;
+        sum += body(&mut loop_ns)?;
+        loop_ns.val += 1.0;
+    }
+    Ok(sum)
+}
+
+/// Rejects the bounds of a `sum()` that would loop forever.
+pub(crate) fn check_sum_bounds(from: f64, to: f64) -> Result<(), Error> {
+    // Beyond 2^53, adding 1 no longer changes an f64, so the loop would never end:
+    const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+    if from <= to && (from.abs() >= MAX_EXACT || to.abs() >= MAX_EXACT) {
+        return Err(Error::WrongArgs(
+            "sum: bounds must be smaller than 2^53".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 impl Evaler for PrintFunc {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>) {
-        for x_or_s in &self.0 {
-            match x_or_s {
-                EExpr(xi) => get_expr!(slab.ps, xi)._var_names(slab, dst),
-                EStr(_) => (),
-            };
-        }
+        let _ = vars::collect(slab, dst).print(self);
     }
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
         let mut val = 0f64;
@@ -591,12 +1450,13 @@ impl Evaler for PrintFunc {
             }
             match a {
                 EExpr(e_i) => {
-                    val = get_expr!(slab.ps, e_i).eval(slab, ns)?;
+                    val = get_expr!(slab.ps, e_i).eval_nested(slab, ns)?;
                     out.push_str(&val.to_string());
                 }
                 EStr(s) => out.push_str(&process_str(s)),
             }
         }
+        #[cfg(feature = "trace")]
         eprintln!("{}", out);
 
         Ok(val)
@@ -605,102 +1465,148 @@ impl Evaler for PrintFunc {
 
 impl Evaler for Instruction {
     fn _var_names(&self, slab: &Slab, dst: &mut BTreeSet<String>) {
-        match self {
-            #[cfg(feature = "unsafe-vars")]
-            IUnsafeVar { name, .. } => {
-                dst.insert(name.clone());
-            }
-
-            IVar(s) => {
-                dst.insert(s.clone());
-            }
-            IFunc { name, args } => {
-                dst.insert(name.clone());
-                for ic in args {
-                    let iconst: Instruction;
-                    ic_to_instr!(slab.cs, iconst, ic)._var_names(slab, dst);
+        // Walks the Instructions with an explicit stack, so that deep trees
+        // can't overflow the native one.  `bound` holds the variables of the
+        // enclosing `sum()`s and `let`s, which are skipped in their bodies.
+        enum Step<'a> {
+            Instr(&'a Instruction),
+            Bind(&'a str),
+            Unbind,
+        }
+        let mut bound = Vec::<&str>::new();
+        let mut work = vec![Step::Instr(self)];
+        macro_rules! push_ic {
+            ($ic:expr) => {
+                if let IC::I(i) = $ic {
+                    work.push(Step::Instr(get_instr!(slab.cs, i)));
+                }
+            };
+        }
+        while let Some(step) = work.pop() {
+            let instr = match step {
+                Step::Instr(instr) => instr,
+                Step::Bind(name) => {
+                    bound.push(name);
+                    continue;
+                }
+                Step::Unbind => {
+                    bound.pop();
+                    continue;
+                }
+            };
+            match instr {
+                #[cfg(feature = "unsafe-vars")]
+                IUnsafeVar { name, .. } => {
+                    if !bound.contains(&name.as_str()) {
+                        dst.insert(name.clone());
+                    }
+                }
+                IVar(name) => {
+                    if !bound.contains(&name.as_str()) {
+                        dst.insert(name.clone());
+                    }
+                }
+                IFunc { name, args } => {
+                    if !bound.contains(&name.as_str()) {
+                        dst.insert(name.clone());
+                    }
+                    for ic in args.iter().rev() {
+                        push_ic!(ic);
+                    }
+                }
+                IFuncSum {
+                    var,
+                    from,
+                    to,
+                    expr,
+                } => {
+                    work.push(Step::Unbind);
+                    push_ic!(expr);
+                    work.push(Step::Bind(var));
+                    push_ic!(to);
+                    push_ic!(from);
+                }
+                IFuncLet { var, value, expr } => {
+                    work.push(Step::Unbind);
+                    push_ic!(expr);
+                    work.push(Step::Bind(var));
+                    push_ic!(value);
+                }
+                IPrintFunc(pf) => {
+                    let mut names = BTreeSet::new();
+                    pf._var_names(slab, &mut names);
+                    names.retain(|name| !bound.contains(&name.as_str()));
+                    dst.append(&mut names);
+                }
+                _ => {
+                    for i in instr.children().into_iter().rev() {
+                        work.push(Step::Instr(get_instr!(slab.cs, i)));
+                    }
                 }
             }
-
-            IConst(_) => (),
-
-            INeg(ii) | INot(ii) | IInv(ii) | IFuncInt(ii) | IFuncCeil(ii) | IFuncFloor(ii)
-            | IFuncAbs(ii) | IFuncSign(ii) | IFuncSin(ii) | IFuncCos(ii) | IFuncTan(ii)
-            | IFuncASin(ii) | IFuncACos(ii) | IFuncATan(ii) | IFuncSinH(ii) | IFuncCosH(ii)
-            | IFuncTanH(ii) | IFuncASinH(ii) | IFuncACosH(ii) | IFuncATanH(ii) | IFuncSqrt(ii) => {
-                get_instr!(slab.cs, ii)._var_names(slab, dst)
-            }
-
-            ILT(lic, ric)
-            | ILTE(lic, ric)
-            | IEQ(lic, ric)
-            | INE(lic, ric)
-            | IGTE(lic, ric)
-            | IGT(lic, ric)
-            | IMod {
-                dividend: lic,
-                divisor: ric,
-            }
-            | IExp {
-                base: lic,
-                power: ric,
-            }
-            | IFuncLog { base: lic, of: ric }
-            | IFuncRound {
-                modulus: lic,
-                of: ric,
-            } => {
-                let mut iconst: Instruction;
-                ic_to_instr!(slab.cs, iconst, lic)._var_names(slab, dst);
-                ic_to_instr!(slab.cs, iconst, ric)._var_names(slab, dst);
-            }
-
-            IAdd(li, ric)
-            | IMul(li, ric)
-            | IOR(li, ric)
-            | IAND(li, ric)
-            | IFuncMin(li, ric)
-            | IFuncMax(li, ric) => {
-                get_instr!(slab.cs, li)._var_names(slab, dst);
-                let iconst: Instruction;
-                ic_to_instr!(slab.cs, iconst, ric)._var_names(slab, dst);
-            }
-
-            IPrintFunc(pf) => pf._var_names(slab, dst),
         }
     }
     fn eval(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<f64, Error> {
+        let val = self.eval_nested(slab, ns)?;
+        ns.check_result(val)
+    }
+}
+
+impl Instruction {
+    // Evaluates an operand, without the final `check_result()` of eval():
+    pub(crate) fn eval_nested(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<f64, Error> {
+        // Only shallow slabs are safe to evaluate recursively, and stable
+        // Namespaces are cached by the other evaluator:
+        if slab.cs.depth() > deep::MAX_RECURSIVE || ns.is_stable() {
+            return deep::eval(self, slab, ns);
+        }
+        ns.tick()?;
         match self {
             // I have manually ordered these match arms in a way that I feel should deliver good performance.
             // (I don't think this ordering actually affects the generated code, though.)
             IMul(li, ric) => {
-                Ok(eval_compiled_ref!(get_instr!(slab.cs, li), slab, ns)
-                    * eval_ic_ref!(ric, slab, ns))
+                let left = eval_nested_ref!(get_instr!(slab.cs, li), slab, ns);
+                let right = eval_ic_ref!(ric, slab, ns);
+                Ok(null_or2(ns, left, right, || left * right))
             }
             IAdd(li, ric) => {
-                Ok(eval_compiled_ref!(get_instr!(slab.cs, li), slab, ns)
-                    + eval_ic_ref!(ric, slab, ns))
+                let left = eval_nested_ref!(get_instr!(slab.cs, li), slab, ns);
+                let right = eval_ic_ref!(ric, slab, ns);
+                Ok(null_or2(ns, left, right, || left + right))
             }
             IExp { base, power } => {
-                Ok(eval_ic_ref!(base, slab, ns).powf(eval_ic_ref!(power, slab, ns)))
+                let base = eval_ic_ref!(base, slab, ns);
+                let power = eval_ic_ref!(power, slab, ns);
+                if is_null(ns, base) {
+                    Ok(base)
+                } else if is_null(ns, power) {
+                    Ok(power)
+                } else {
+                    ns.zero_pow_zero().pow(base, power)
+                }
             }
 
-            INeg(i) => Ok(-eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns)),
-            IInv(i) => Ok(1.0 / eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns)),
+            INeg(i) => {
+                let val = eval_nested_ref!(get_instr!(slab.cs, i), slab, ns);
+                Ok(null_or(ns, val, || -val))
+            }
+            IInv(i) => {
+                let val = eval_nested_ref!(get_instr!(slab.cs, i), slab, ns);
+                Ok(null_or(ns, val, || 1.0 / val))
+            }
 
-            IVar(name) => eval_var!(ns, name, Vec::new(), unsafe {
-                #[allow(invalid_reference_casting)]
-                &mut *(&slab.ps.char_buf as *const _ as *mut _)
-            }),
+            IVar(name) => eval_var!(ns, name, &mut String::new())
+                .or_else(|err| named_constant(name).ok_or(err)),
             IFunc { name, args: ics } => {
                 let mut args = Vec::with_capacity(ics.len());
                 for ic in ics {
                     args.push(eval_ic_ref!(ic, slab, ns));
                 }
-                eval_var!(ns, name, args, unsafe {
-                    #[allow(invalid_reference_casting)]
-                    &mut *(&slab.ps.char_buf as *const _ as *mut _)
-                })
+                eval_var!(ns, name, args, &mut String::new())
             }
 
             IFuncLog {
@@ -712,19 +1618,32 @@ impl Evaler for Instruction {
                 Ok(log(base, of))
             }
 
-            IFuncSin(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).sin()),
-            IFuncCos(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).cos()),
-            IFuncTan(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).tan()),
-            IFuncASin(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).asin()),
-            IFuncACos(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).acos()),
-            IFuncATan(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).atan()),
-            IFuncSinH(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).sinh()),
-            IFuncCosH(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).cosh()),
-            IFuncTanH(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).tanh()),
-            IFuncASinH(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).asinh()),
-            IFuncACosH(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).acosh()),
-            IFuncATanH(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).atanh()),
-            IFuncSqrt(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).sqrt()),
+            IFuncSin(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).sin()),
+            IFuncCos(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).cos()),
+            IFuncTan(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).tan()),
+            IFuncASin(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).asin()),
+            IFuncACos(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).acos()),
+            IFuncATan(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).atan()),
+            IFuncSinH(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).sinh()),
+            IFuncCosH(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).cosh()),
+            IFuncTanH(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).tanh()),
+            IFuncASinH(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).asinh()),
+            IFuncACosH(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).acosh()),
+            IFuncATanH(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).atanh()),
+            IFuncSqrt(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).sqrt()),
+            IFuncFma { a, b, c } => Ok(eval_ic_ref!(a, slab, ns)
+                .mul_add(eval_ic_ref!(b, slab, ns), eval_ic_ref!(c, slab, ns))),
+            IFuncCoalesce { value, fallback } => Ok(coalesce(
+                eval_ic_ref!(value, slab, ns),
+                eval_ic_ref!(fallback, slab, ns),
+            )),
+            IFuncNorm { squared, args } => {
+                let mut acc = SumSq::default();
+                for ic in args {
+                    acc.add(eval_ic_ref!(ic, slab, ns));
+                }
+                Ok(acc.finish(*squared))
+            }
 
             IFuncRound {
                 modulus: modic,
@@ -735,19 +1654,38 @@ impl Evaler for Instruction {
                 Ok((of / modulus).round() * modulus)
             }
             IMod { dividend, divisor } => {
-                Ok(eval_ic_ref!(dividend, slab, ns) % eval_ic_ref!(divisor, slab, ns))
+                let dividend = eval_ic_ref!(dividend, slab, ns);
+                let divisor = eval_ic_ref!(divisor, slab, ns);
+                if is_null(ns, dividend) {
+                    Ok(dividend)
+                } else if is_null(ns, divisor) {
+                    Ok(divisor)
+                } else {
+                    ns.mod_mode().rem(dividend, divisor)
+                }
+            }
+            IFuncQuotient { dividend, divisor } => {
+                let dividend = eval_ic_ref!(dividend, slab, ns);
+                let divisor = eval_ic_ref!(divisor, slab, ns);
+                if is_null(ns, dividend) {
+                    Ok(dividend)
+                } else if is_null(ns, divisor) {
+                    Ok(divisor)
+                } else {
+                    ns.mod_mode().quotient(dividend, divisor)
+                }
             }
 
-            IFuncAbs(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).abs()),
-            IFuncSign(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).signum()),
-            IFuncInt(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).trunc()),
-            IFuncCeil(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).ceil()),
-            IFuncFloor(i) => Ok(eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns).floor()),
+            IFuncAbs(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).abs()),
+            IFuncSign(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).signum()),
+            IFuncInt(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).trunc()),
+            IFuncCeil(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).ceil()),
+            IFuncFloor(i) => Ok(eval_nested_ref!(get_instr!(slab.cs, i), slab, ns).floor()),
             IFuncMin(li, ric) => {
-                let left = eval_compiled_ref!(get_instr!(slab.cs, li), slab, ns);
+                let left = eval_nested_ref!(get_instr!(slab.cs, li), slab, ns);
                 let right = eval_ic_ref!(ric, slab, ns);
                 if left.is_nan() || right.is_nan() {
-                    return Ok(std::f64::NAN);
+                    return Ok(f64::NAN);
                 } // I need to implement NAN checks myself because the f64.min() function says that if one number is NaN, the other will be returned.
                 if left < right {
                     Ok(left)
@@ -756,10 +1694,10 @@ impl Evaler for Instruction {
                 }
             }
             IFuncMax(li, ric) => {
-                let left = eval_compiled_ref!(get_instr!(slab.cs, li), slab, ns);
+                let left = eval_nested_ref!(get_instr!(slab.cs, li), slab, ns);
                 let right = eval_ic_ref!(ric, slab, ns);
                 if left.is_nan() || right.is_nan() {
-                    return Ok(std::f64::NAN);
+                    return Ok(f64::NAN);
                 }
                 if left > right {
                     Ok(left)
@@ -767,34 +1705,115 @@ impl Evaler for Instruction {
                     Ok(right)
                 }
             }
+            IFuncPiecewise { branches, default } => {
+                for (cond_ic, val_ic) in branches {
+                    if f64_ne!(eval_ic_ref!(cond_ic, slab, ns), 0.0) {
+                        return Ok(eval_ic_ref!(val_ic, slab, ns));
+                    }
+                }
+                Ok(eval_ic_ref!(default, slab, ns))
+            }
+            IFuncIn {
+                negated,
+                value,
+                list,
+            } => {
+                let value = eval_ic_ref!(value, slab, ns);
+                if is_null(ns, value) {
+                    return Ok(value);
+                }
+                for ic in list {
+                    if f64_eq!(value, eval_ic_ref!(ic, slab, ns)) {
+                        return Ok(bool_to_f64!(!negated));
+                    }
+                }
+                Ok(bool_to_f64!(*negated))
+            }
+            IFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                let from = eval_ic_ref!(from, slab, ns);
+                let to = eval_ic_ref!(to, slab, ns);
+                eval_sum(var, from, to, ns, |loop_ns| {
+                    Ok(eval_ic_ref!(expr, slab, loop_ns))
+                })
+            }
+            IFuncLet { var, value, expr } => {
+                let val = eval_ic_ref!(value, slab, ns);
+                let mut scope_ns = ScopeNamespace {
+                    name: var,
+                    val,
+                    inner: ns,
+                };
+                Ok(eval_ic_ref!(expr, slab, &mut scope_ns))
+            }
+            IFuncInterp {
+                extrapolate,
+                x,
+                points: point_ics,
+            } => {
+                let x = eval_ic_ref!(x, slab, ns);
+                // Keep the breakpoints on the working stack, if there is one:
+                let base = ns.stack().map(|stack| stack.len());
+                let mut points = match base {
+                    Some(_) => Vec::new(),
+                    None => Vec::with_capacity(2 * point_ics.len()),
+                };
+                for (px_ic, py_ic) in point_ics {
+                    let point = [eval_ic_ref!(px_ic, slab, ns), eval_ic_ref!(py_ic, slab, ns)];
+                    match ns.stack() {
+                        Some(stack) => stack.extend(point),
+                        None => points.extend(point),
+                    }
+                }
+                match (base, ns.stack()) {
+                    (Some(base), Some(stack)) => {
+                        let val = interp(x, &stack[base..], *extrapolate);
+                        stack.truncate(base);
+                        val
+                    }
+                    _ => interp(x, &points, *extrapolate),
+                }
+            }
 
-            IEQ(left, right) => Ok(bool_to_f64!(f64_eq!(
-                eval_ic_ref!(left, slab, ns),
-                eval_ic_ref!(right, slab, ns)
-            ))),
-            INE(left, right) => Ok(bool_to_f64!(f64_ne!(
-                eval_ic_ref!(left, slab, ns),
-                eval_ic_ref!(right, slab, ns)
-            ))),
-            ILT(left, right) => Ok(bool_to_f64!(
-                eval_ic_ref!(left, slab, ns) < eval_ic_ref!(right, slab, ns)
-            )),
-            ILTE(left, right) => Ok(bool_to_f64!(
-                eval_ic_ref!(left, slab, ns) <= eval_ic_ref!(right, slab, ns)
-            )),
-            IGTE(left, right) => Ok(bool_to_f64!(
-                eval_ic_ref!(left, slab, ns) >= eval_ic_ref!(right, slab, ns)
-            )),
-            IGT(left, right) => Ok(bool_to_f64!(
-                eval_ic_ref!(left, slab, ns) > eval_ic_ref!(right, slab, ns)
-            )),
+            IEQ(left, right) => {
+                let (left, right) = (eval_ic_ref!(left, slab, ns), eval_ic_ref!(right, slab, ns));
+                Ok(null_or2(ns, left, right, || {
+                    bool_to_f64!(f64_eq!(left, right))
+                }))
+            }
+            INE(left, right) => {
+                let (left, right) = (eval_ic_ref!(left, slab, ns), eval_ic_ref!(right, slab, ns));
+                Ok(null_or2(ns, left, right, || {
+                    bool_to_f64!(f64_ne!(left, right))
+                }))
+            }
+            ILT(left, right) => {
+                let (left, right) = (eval_ic_ref!(left, slab, ns), eval_ic_ref!(right, slab, ns));
+                Ok(null_or2(ns, left, right, || bool_to_f64!(left < right)))
+            }
+            ILTE(left, right) => {
+                let (left, right) = (eval_ic_ref!(left, slab, ns), eval_ic_ref!(right, slab, ns));
+                Ok(null_or2(ns, left, right, || bool_to_f64!(left <= right)))
+            }
+            IGTE(left, right) => {
+                let (left, right) = (eval_ic_ref!(left, slab, ns), eval_ic_ref!(right, slab, ns));
+                Ok(null_or2(ns, left, right, || bool_to_f64!(left >= right)))
+            }
+            IGT(left, right) => {
+                let (left, right) = (eval_ic_ref!(left, slab, ns), eval_ic_ref!(right, slab, ns));
+                Ok(null_or2(ns, left, right, || bool_to_f64!(left > right)))
+            }
 
-            INot(i) => Ok(bool_to_f64!(f64_eq!(
-                eval_compiled_ref!(get_instr!(slab.cs, i), slab, ns),
-                0.0
-            ))),
+            INot(i) => {
+                let val = eval_nested_ref!(get_instr!(slab.cs, i), slab, ns);
+                Ok(null_or(ns, val, || bool_to_f64!(f64_eq!(val, 0.0))))
+            }
             IAND(lefti, rightic) => {
-                let left = eval_compiled_ref!(get_instr!(slab.cs, lefti), slab, ns);
+                let left = eval_nested_ref!(get_instr!(slab.cs, lefti), slab, ns);
                 if f64_eq!(left, 0.0) {
                     Ok(left)
                 } else {
@@ -802,7 +1821,7 @@ impl Evaler for Instruction {
                 }
             }
             IOR(lefti, rightic) => {
-                let left = eval_compiled_ref!(get_instr!(slab.cs, lefti), slab, ns);
+                let left = eval_nested_ref!(get_instr!(slab.cs, lefti), slab, ns);
                 if f64_ne!(left, 0.0) {
                     Ok(left)
                 } else {
@@ -810,7 +1829,34 @@ impl Evaler for Instruction {
                 }
             }
 
+            ITemp { slot, instr } => match ns.temp(*slot) {
+                Some(val) => Ok(val),
+                None => {
+                    let val = eval_nested_ref!(get_instr!(slab.cs, instr), slab, ns);
+                    ns.set_temp(*slot, val);
+                    Ok(val)
+                }
+            },
+            ITemps { slots, body } => {
+                // A legitimate `slots` can't exceed the number of Instructions, so
+                // limit the allocation in case this was deserialized from bad data:
+                let slots = (*slots).min(slab.cs.instrs.len());
+                let vals = match ns.stack() {
+                    Some(stack) => {
+                        let base = stack.len();
+                        stack.resize(base + 2 * slots, 0.0);
+                        TempVals::Stack { base, slots }
+                    }
+                    None => TempVals::Heap(vec![None; slots]),
+                };
+                let mut temp_ns = TempNamespace { vals, inner: ns };
+                let val = get_instr!(slab.cs, body).eval_nested(slab, &mut temp_ns);
+                temp_ns.release();
+                val
+            }
+
             IPrintFunc(pf) => pf.eval(slab, ns),
+            IError(err) => Err(err.clone()),
 
             // Put these last because you should be using the eval_compiled*!() macros to eliminate function calls.
             IConst(c) => Ok(*c),
@@ -819,3 +1865,115 @@ impl Evaler for Instruction {
         }
     }
 }
+
+impl Instruction {
+    /// Evaluates this compiled `Instruction` for every point of a 2D grid.
+    ///
+    /// The variable `x_name` takes each value of `xs` and `y_name` takes each
+    /// value of `ys`.  The results are written to `out` in row-major order:
+    /// `out[yi * xs.len() + xi]` is the result at `(xs[xi], ys[yi])`.  All
+    /// other variables and functions are looked up in `ns`.
+    ///
+    /// This is much faster than evaluating each cell with a fresh Namespace.
+    ///
+    /// # Errors
+    ///
+    /// If `out.len() != xs.len() * ys.len()`, a `WrongArgs` error is returned
+    /// and `out` is not modified.  Evaluation errors are returned immediately,
+    /// leaving the remaining cells unmodified.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::Compiler;  // use this trait so we can call compile().
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let mut slab = fasteval2::Slab::new();
+    ///     let mut ns = fasteval2::EmptyNamespace;
+    ///     let instr = fasteval2::Parser::new().parse("x * 10 + y", &mut slab.ps)?
+    ///                     .from(&slab.ps).compile(&slab.ps, &mut slab.cs, &mut ns);
+    ///
+    ///     let mut out = [0.0; 6];
+    ///     instr.eval_grid(&slab, &mut ns, "x", &[1.0, 2.0, 3.0], "y", &[0.1, 0.2], &mut out)?;
+    ///     assert_eq!(out, [10.1, 20.1, 30.1, 10.2, 20.2, 30.2]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn eval_grid(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+        x_name: &str,
+        xs: &[f64],
+        y_name: &str,
+        ys: &[f64],
+        out: &mut [f64],
+    ) -> Result<(), Error> {
+        if xs.len().checked_mul(ys.len()) != Some(out.len()) {
+            return Err(Error::WrongArgs(format!(
+                "eval_grid: expected an output of {}*{} values, but got {}",
+                xs.len(),
+                ys.len(),
+                out.len()
+            )));
+        }
+        if xs.is_empty() {
+            return Ok(());
+        }
+        let mut grid_ns = GridNamespace {
+            x_name,
+            x: 0.0,
+            y_name,
+            y: 0.0,
+            inner: ns,
+        };
+        for (&y, row) in ys.iter().zip(out.chunks_mut(xs.len())) {
+            grid_ns.y = y;
+            for (&x, cell) in xs.iter().zip(row.iter_mut()) {
+                grid_ns.x = x;
+                *cell = eval_compiled_ref!(self, slab, &mut grid_ns);
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of working-stack entries that `eval()` uses for this
+    /// `Instruction`.  See `CompiledExpression::stack_depth()`.
+    pub(crate) fn stack_depth(&self, slab: &Slab) -> usize {
+        // Deep slabs are evaluated without the working stack (and they may be
+        // too deep to measure recursively):
+        if slab.cs.depth() > deep::MAX_RECURSIVE {
+            return 0;
+        }
+        // Shared sub-expressions are only measured once:
+        fn depth(instr: &Instruction, slab: &Slab, memo: &mut Vec<Option<usize>>) -> usize {
+            let mut child = |i: &InstructionI| match memo.get(i.0).copied().flatten() {
+                Some(d) => d,
+                None => {
+                    let d = depth(get_instr!(slab.cs, i), slab, memo);
+                    if let Some(m) = memo.get_mut(i.0) {
+                        *m = Some(d);
+                    }
+                    d
+                }
+            };
+            match instr {
+                ITemps { slots, body } => 2 * (*slots).min(slab.cs.instrs.len()) + child(body),
+                // The breakpoints are pushed one pair at a time:
+                IFuncInterp { x, points, .. } => {
+                    let mut ic = |ic: &IC| match ic {
+                        IC::I(i) => child(i),
+                        IC::C(_) => 0,
+                    };
+                    let mut max = ic(x).max(2 * points.len());
+                    for (k, (px, py)) in points.iter().enumerate() {
+                        max = max.max(2 * k + ic(px).max(ic(py)));
+                    }
+                    max
+                }
+                _ => instr.children().into_iter().map(child).max().unwrap_or(0),
+            }
+        }
+        depth(self, slab, &mut vec![None; slab.cs.instrs.len()])
+    }
+}
@@ -17,6 +17,15 @@
 //!   Each layer is a separate 'scope'.  Higher layers take precedence
 //!   over lower layers.  Very useful for creating scoped higher-level-languages.
 //!   Type alias: [LayeredStringToF64Namespace](#layeredstringtof64namespace)
+//! * &[(&str,f64)] -- Define a handful of variables with a slice of pairs.
+//!   Lookups are a linear scan, so this is best for very small sets of variables.
+//!   Type alias: [StrToF64PairsNamespace](#strtof64pairsnamespace)
+//! * [`ContextNamespace`](#context) -- Resolve variables and custom functions
+//!   with methods on your own type, by implementing the `Context` trait.
+//! * [`EnvNamespace`](struct.EnvNamespace.html) -- Read variables from
+//!   environment variables, for configuration and deployment scripts.
+//! * [`EvalNSBuilder`](struct.EvalNSBuilder.html) -- Define variables, custom
+//!   functions and evaluation-time limits with one builder expression.
 //!
 //! # Examples
 //!
@@ -84,7 +93,7 @@
 //!     let mut ns = fasteval2::StringToCallbackNamespace::new();
 //!     ns.insert("x".to_string(), Box::new(|_args| 2.0));
 //!     ns.insert("double".to_string(), Box::new(|args| {
-//!         args.get(0).map(|arg0| arg0*2.0).unwrap_or(std::f64::NAN)
+//!         args.get(0).map(|arg0| arg0*2.0).unwrap_or(f64::NAN)
 //!     }));
 //!
 //!     let val = fasteval2::ez_eval("double(x + 1) + 1", &mut ns)?;
@@ -100,7 +109,7 @@
 //!     let mut ns = fasteval2::StrToCallbackNamespace::new();
 //!     ns.insert("x", Box::new(|_args| 2.0));
 //!     ns.insert("double", Box::new(|args| {
-//!         args.get(0).map(|arg0| arg0*2.0).unwrap_or(std::f64::NAN)
+//!         args.get(0).map(|arg0| arg0*2.0).unwrap_or(f64::NAN)
 //!     }));
 //!
 //!     let val = fasteval2::ez_eval("double(x + 1) + 1", &mut ns)?;
@@ -169,6 +178,67 @@
 //! }
 //! ```
 //!
+//! ## StrToF64PairsNamespace
+//! ```
+//! use fasteval2::Evaler;  // use this trait so we can call eval_pairs().
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = fasteval2::Slab::new();
+//!     let expr_ref = fasteval2::Parser::new().parse("x * (y + 1)", &mut slab.ps)?.from(&slab.ps);
+//!
+//!     let val = expr_ref.eval_pairs(&slab, &[("x", 2.0), ("y", 3.0)])?;
+//!     assert_eq!(val, 8.0);
+//!
+//!     // The slice can also be used directly as a Namespace:
+//!     let mut ns : fasteval2::StrToF64PairsNamespace = &[("x", 2.0), ("y", 3.0)];
+//!     let val = fasteval2::ez_eval("x * (y + 1)", &mut ns)?;
+//!     assert_eq!(val, 8.0);
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## Context
+//! ```
+//! use fasteval2::{Context, EvalNS, Error};
+//!
+//! // Your own type can hold whatever state it needs, without any closure lifetimes:
+//! struct Dice {
+//!     seed: u64,
+//!     rolls: usize,
+//! }
+//! impl Context for Dice {
+//!     fn get(&mut self, name: &str) -> Option<f64> {
+//!         match name {
+//!             "rolls" => Some(self.rolls as f64),
+//!             _ => None,
+//!         }
+//!     }
+//!     fn call(&mut self, name: &str, args: &[f64]) -> Option<Result<f64, Error>> {
+//!         match (name, args) {
+//!             ("roll", [sides]) if *sides >= 1.0 => {
+//!                 self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+//!                 self.rolls += 1;
+//!                 Some(Ok(((self.seed >> 33) % *sides as u64) as f64 + 1.0))
+//!             }
+//!             ("roll", _) => Some(Err(Error::WrongArgs("roll: expected a number of sides".to_string()))),
+//!             _ => None,
+//!         }
+//!     }
+//! }
+//!
+//! fn main() -> Result<(), Error> {
+//!     let mut ns = EvalNS::from_context(Dice { seed: 42, rolls: 0 });
+//!     let val = fasteval2::ez_eval("roll(6) + roll(6)", &mut ns)?;
+//!     assert!(val >= 2.0 && val <= 12.0);
+//!     assert_eq!(fasteval2::ez_eval("rolls", &mut ns), Ok(2.0));
+//!
+//!     // Errors from call() abort the evaluation:
+//!     assert!(fasteval2::ez_eval("roll(0)", &mut ns).is_err());
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
 //! ## Custom Namespace Types
 //!
 //! If the pre-defined Namespace types aren't perfect for your application, you
@@ -185,18 +255,149 @@
 //! * CachedCallbacksNamespace  --  Same as above, but with a cache for each
 //!   layer.  Good for expensive look-ups.
 
-use crate::error::Error;
+use crate::compiler::quotient;
+use crate::error::{Arity, Error};
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+use crate::symbol::Interner;
 
-use std::collections::BTreeMap;
+use alloc::collections::BTreeMap;
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::mem;
+#[cfg(feature = "std")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 //---- Types:
 
 /// All `fasteval` Namespaces must implement the `EvalNamespace` trait.
+// The crate's wrapper Namespaces (like `ScopeNamespace`) forward each hook
+// below to the Namespace that they wrap.  A new hook must be added to them
+// and to `wrapper_tests`.
 pub trait EvalNamespace {
     /// Perform a variable/function lookup.
     ///
     /// May return cached values.
     fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64>;
+
+    /// Like `lookup()`, but the Namespace can also report an `Error`, which
+    /// aborts the evaluation.
+    ///
+    /// This is what the evaluators call.  The default delegates to `lookup()`,
+    /// so only Namespaces that can fail need to implement it.
+    #[inline(always)]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        Ok(self.lookup(name, args, keybuf))
+    }
+
+    /// Called once for every operation, function call, and loop iteration
+    /// during evaluation.
+    ///
+    /// Return an `Error` to abort the evaluation.  The default does nothing,
+    /// which gets optimized-away at compile time.  See [`EvalNS`](struct.EvalNS.html)
+    /// for a Namespace wrapper that uses this to enforce an operation budget.
+    #[inline(always)]
+    fn tick(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Returns the value of a temporary that was stored with `set_temp()`.
+    ///
+    /// Temporaries hold shared sub-expressions after
+    /// [`eliminate_common_subexpressions()`](../compiler/enum.Instruction.html#method.eliminate_common_subexpressions).
+    /// The evaluator provides its own storage for them, so Namespaces don't
+    /// need to implement this.  Without storage, shared sub-expressions are
+    /// just evaluated every time.
+    #[inline(always)]
+    fn temp(&mut self, _slot: usize) -> Option<f64> {
+        None
+    }
+
+    /// Stores the value of a temporary.  See `temp()`.
+    #[inline(always)]
+    fn set_temp(&mut self, _slot: usize, _val: f64) {}
+
+    /// Returns the working stack that was passed to
+    /// [`CompiledExpression::eval_with_stack()`](../bytecode/struct.CompiledExpression.html#method.eval_with_stack),
+    /// if any.
+    ///
+    /// The evaluator keeps temporaries and `interp()` breakpoints on it instead
+    /// of allocating.  The evaluator provides it itself, so Namespaces don't
+    /// need to implement this.
+    #[inline(always)]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        None
+    }
+
+    /// Returns the value that stands for a missing ("null") value, if any.
+    ///
+    /// The evaluators compare operands with it bit-for-bit: an arithmetic
+    /// operation, comparison, `-` or `!` with a null operand evaluates to null.
+    /// The default returns `None`, which gets optimized-away at compile time.  See
+    /// [`EvalNS::with_null()`](struct.EvalNS.html#method.with_null).
+    #[inline(always)]
+    fn null(&self) -> Option<f64> {
+        None
+    }
+
+    /// Returns what `0^0` evaluates to.
+    ///
+    /// The default is [`ZeroPowZero::One`](enum.ZeroPowZero.html#variant.One),
+    /// like `f64::powf()`.  See
+    /// [`EvalNS::with_zero_pow_zero()`](struct.EvalNS.html#method.with_zero_pow_zero).
+    #[inline(always)]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        ZeroPowZero::One
+    }
+
+    /// Returns how `%` and `remainder()` treat their operands.
+    ///
+    /// The default is [`ModMode::Float`](enum.ModMode.html#variant.Float), the
+    /// remainder of `f64`.  See
+    /// [`EvalNS::with_mod_mode()`](struct.EvalNS.html#method.with_mod_mode).
+    #[inline(always)]
+    fn mod_mode(&self) -> ModMode {
+        ModMode::Float
+    }
+
+    /// Checks the final result of an evaluation, and can replace it, or
+    /// reject it with an `Error`.
+    ///
+    /// `Evaler::eval()` of an `Expression` or an `Instruction` calls this
+    /// once, with the final result, and so do `ez_eval()`, the
+    /// `eval_compiled*!()` macros and the `eval` methods of
+    /// [`CompiledExpression`](../bytecode/struct.CompiledExpression.html).
+    /// The sub-expressions that they evaluate aren't checked, and neither are
+    /// the results of `eval()` on the parts of an `Expression`, like a
+    /// `Value`.  The default accepts every value.  See
+    /// [`EvalNS::with_result_range()`](struct.EvalNS.html#method.with_result_range).
+    #[inline(always)]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        Ok(val)
+    }
+
+    /// Returns `true` if variables and custom functions return the same value
+    /// for the same arguments throughout an evaluation.
+    ///
+    /// The evaluators can then look up each variable once, and compiled
+    /// expressions compute each distinct pure sub-expression once, even where
+    /// [`eliminate_common_subexpressions()`](../compiler/enum.Instruction.html#method.eliminate_common_subexpressions)
+    /// doesn't share it.  `rand()`, `randint()`, `now()` and `print()` are
+    /// never cached.  The default returns `false`, which gets optimized-away
+    /// at compile time.  See [`EvalNS::with_stable()`](struct.EvalNS.html#method.with_stable).
+    #[inline(always)]
+    fn is_stable(&self) -> bool {
+        false
+    }
 }
 
 /// Cache operations for `EvalNamespace`s.
@@ -216,7 +417,28 @@ pub trait Cached {
     fn cache_clear(&mut self);
 }
 
-//// I don't want to put this into the public API until it is needed.
+/// Resolves variables and custom functions for a
+/// [`ContextNamespace`](struct.ContextNamespace.html).
+///
+/// Implement this on your own type when variable and function resolution
+/// needs state (caches, an RNG, handles, ...).  Closures with the usual
+/// `FnMut(&str,Vec<f64>) -> Option<f64>` signature implement it too.
+///
+/// [See module-level documentation for example.](index.html#context)
+///
+pub trait Context {
+    /// Returns the value of the variable `name`, or `None` if it is undefined.
+    fn get(&mut self, name: &str) -> Option<f64>;
+
+    /// Calls the custom function `name`.
+    ///
+    /// Returns `None` if the function is undefined, or `Some(Err(...))` to
+    /// abort the evaluation.  Calls without arguments, like `rand()`, come
+    /// here when `get()` returns `None`.
+    fn call(&mut self, name: &str, args: &[f64]) -> Option<Result<f64, Error>>;
+}
+
+// I don't want to put this into the public API until it is needed.
 // pub trait Layered {
 //     fn push(&mut self);
 //     fn pop(&mut self);
@@ -239,11 +461,273 @@ pub struct EmptyNamespace;
 ///
 pub struct CachedCallbackNamespace<'a> {
     cache: BTreeMap<String, f64>,
+    #[allow(clippy::type_complexity)]
     cb: Box<dyn FnMut(&str, Vec<f64>) -> Option<f64> + 'a>, // I think a reference would be more efficient than a Box, but then I would need to use a funky 'let cb=|n|{}; Namespace::new(&cb)' syntax.  The Box results in a super convenient pass-the-cb-by-value API interface.
 }
 
-//// I am commenting these out until I need them in real-life.
-//// (I don't want to add things to the public API until necessary.)
+/// `EvalNS` wraps another Namespace and adds evaluation-time limits.
+///
+/// Lookups are delegated to the wrapped Namespace.  By default there are no
+/// limits, and the wrapper behaves exactly like the wrapped Namespace.
+///
+/// # Examples
+/// ```
+/// use fasteval2::{EvalNS, Error};
+/// let mut ns = EvalNS::new(fasteval2::EmptyNamespace).with_budget(10);
+/// assert_eq!(fasteval2::ez_eval("1+2+3", &mut ns), Ok(6.0));
+/// assert_eq!(fasteval2::ez_eval("sum(i, 1, 1e9, i)", &mut ns), Err(Error::BudgetExceeded(10)));
+/// ```
+pub struct EvalNS<NS> {
+    ns: NS,
+    budget: Option<u64>,
+    used: u64,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    rng: Option<Rng>,
+    clock: Option<Box<dyn Clock + Send>>,
+    strict: bool,
+    null: Option<f64>,
+    range: Option<(f64, f64)>,
+    zero_pow_zero: Option<ZeroPowZero>,
+    mod_mode: Option<ModMode>,
+    stable: bool,
+}
+
+/// `EvalNSBuilder` configures an [`EvalNS`](struct.EvalNS.html) with variables,
+/// custom functions and evaluation-time limits, in one expression.
+///
+/// # Examples
+/// ```
+/// use fasteval2::{EvalNSBuilder, Error};
+/// let mut ns = EvalNSBuilder::new()
+///     .var("x", 2.0)
+///     .var("y", 3.0)
+///     .func("hypot", |args: Vec<f64>| match *args {
+///         [a, b] => Some(a.hypot(b)),
+///         _ => None,
+///     })
+///     .strict()
+///     .budget(100)
+///     .build();
+/// assert_eq!(fasteval2::ez_eval("hypot(x + 1, y + 1)", &mut ns), Ok(5.0));
+/// assert_eq!(fasteval2::ez_eval("z", &mut ns), Err(Error::UndefinedVariable { name: "z".to_string() }));
+/// ```
+pub struct EvalNSBuilder<'a> {
+    ns: MapNamespace<'a>,
+    budget: Option<u64>,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    rng: Option<u64>,
+    clock: Option<Box<dyn Clock + Send>>,
+    strict: bool,
+    null: Option<f64>,
+    range: Option<(f64, f64)>,
+    zero_pow_zero: Option<ZeroPowZero>,
+    mod_mode: Option<ModMode>,
+    stable: bool,
+    fold_funcs: bool,
+}
+
+/// The Namespace that [`EvalNSBuilder`](struct.EvalNSBuilder.html) builds.
+///
+/// A name without arguments is looked up in the variables first, and then
+/// in the functions.  A name with arguments is only looked up in the functions.
+pub struct MapNamespace<'a> {
+    vars: BTreeMap<String, f64>,
+    #[allow(clippy::type_complexity)]
+    funcs: BTreeMap<String, Box<dyn FnMut(Vec<f64>) -> Option<f64> + 'a>>,
+}
+
+/// What `0^0` evaluates to, see
+/// [`EvalNS::with_zero_pow_zero()`](struct.EvalNS.html#method.with_zero_pow_zero).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZeroPowZero {
+    /// `1`, like `f64::powf()`.  This is the default.
+    #[default]
+    One,
+    /// `NaN`.
+    NaN,
+    /// An [`Error::ZeroPowZero`](../error/enum.Error.html#variant.ZeroPowZero).
+    Error,
+}
+
+/// How `%`, `remainder()` and `quotient()` treat their operands, see
+/// [`EvalNS::with_mod_mode()`](struct.EvalNS.html#method.with_mod_mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModMode {
+    /// The remainder of `f64`, so `5.5 % 2` is `1.5`.  This is the default.
+    #[default]
+    Float,
+    /// Integer modulo: both operands must be integers, otherwise the result
+    /// is an [`Error::NotAnInteger`](../error/enum.Error.html#variant.NotAnInteger).
+    /// A zero divisor is an [`Error::DivideByZero`](../error/enum.Error.html#variant.DivideByZero).
+    Integer,
+}
+
+/// The random number generator behind `rand()` and `randint()` (SplitMix64).
+///
+/// It is tiny and fast, and its output only depends on the seed, so results
+/// are reproducible on every platform.
+struct Rng(u64);
+
+/// The source of the current time for `now()`, see
+/// [`EvalNS::with_clock()`](struct.EvalNS.html#method.with_clock).
+///
+/// [`SystemClock`](struct.SystemClock.html) reads the real time, and is the
+/// default with the `std` feature.  Tests and
+/// replays can freeze time with a [`FixedClock`](struct.FixedClock.html), or
+/// use a closure `FnMut() -> f64`.
+pub trait Clock {
+    /// Returns the current time, in seconds since 1970-01-01 00:00 UTC.
+    fn now(&mut self) -> f64;
+}
+
+/// A [`Clock`](trait.Clock.html) that reads the system time.  Only available
+/// with the `std` feature.
+///
+/// `wasm32-unknown-unknown` has no system time, so there this panics; use a
+/// closure that calls JavaScript's `Date.now()` instead.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+/// A [`Clock`](trait.Clock.html) that always returns the same time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedClock(pub f64);
+
+/// `EnvNamespace` reads variables from the environment variables of the
+/// process.  Only available with the `std` feature.
+///
+/// A variable's value is its environment variable, parsed as an `f64` after
+/// trimming whitespace.  Unset variables are undefined, and a value that
+/// isn't a number is an `Error::ParseF64` with that value (or an
+/// `Error::Utf8ErrorWhileParsing` if it isn't UTF-8).  Calls with arguments
+/// are never defined.  See [`EvalNS::from_env()`](struct.EvalNS.html#method.from_env).
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvNamespace;
+
+/// `ContextNamespace` adapts a [`Context`](trait.Context.html) into a Namespace.
+///
+/// [See module-level documentation for example.](index.html#context)
+///
+pub struct ContextNamespace<C> {
+    ctx: C,
+}
+
+/// Binds the loop variable of a `sum()`, or the variable of a `let`, and
+/// delegates everything else.
+pub(crate) struct ScopeNamespace<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) val: f64,
+    pub(crate) inner: &'a mut dyn EvalNamespace,
+}
+
+/// Stores the temporaries of an `ITemps` evaluation and delegates everything else.
+///
+/// `ScopeNamespace` doesn't forward the temporaries, so shared values are never
+/// reused across `sum()` iterations.  Slots beyond its own are forwarded to
+/// `inner`.
+pub(crate) struct TempNamespace<'a> {
+    pub(crate) vals: TempVals,
+    pub(crate) inner: &'a mut dyn EvalNamespace,
+}
+
+/// Where a `TempNamespace` keeps its temporaries.
+pub(crate) enum TempVals {
+    Heap(Vec<Option<f64>>),
+    /// Two entries per slot on the working stack, starting at `base`: a
+    /// "stored" flag and the value.
+    Stack {
+        base: usize,
+        slots: usize,
+    },
+}
+
+/// The scopes of an evaluation that uses an explicit stack instead of
+/// recursion: the `sum()` and `let` bindings and the `ITemps` temporaries,
+/// innermost last.  It behaves like the equivalent nesting of
+/// `ScopeNamespace`s and `TempNamespace`s, and delegates everything else.
+pub(crate) struct LayerNamespace<'s, 'a> {
+    pub(crate) layers: Vec<Layer<'s>>,
+    pub(crate) inner: &'a mut dyn EvalNamespace,
+}
+
+/// One scope of a `LayerNamespace`.
+pub(crate) enum Layer<'s> {
+    /// A bound name and its value.  Like `ScopeNamespace`, it hides the
+    /// temporaries of the outer layers.
+    Scope(&'s str, f64),
+    Temps(Vec<Option<f64>>),
+}
+
+/// Caches the variables that an evaluation looks up in a stable Namespace (see
+/// `EvalNamespace::is_stable()`), and delegates everything else.  It reports
+/// itself as unstable, so that the evaluators only add it once.
+pub(crate) struct StableNamespace<'a> {
+    vars: BTreeMap<String, f64>,
+    inner: &'a mut dyn EvalNamespace,
+}
+
+/// Looks up the variables of `Expression::eval_with()` in `overrides` first,
+/// and delegates everything else.
+pub(crate) struct OverrideNamespace<'a, NS> {
+    pub(crate) overrides: &'a [(&'a str, f64)],
+    pub(crate) inner: &'a mut NS,
+}
+
+impl<NS> OverrideNamespace<'_, NS> {
+    #[inline]
+    fn get(&self, name: &str, args: &[f64]) -> Option<f64> {
+        if !args.is_empty() {
+            return None;
+        }
+        self.overrides
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|&(_, val)| val)
+    }
+}
+
+/// Provides the working stack of `CompiledExpression::eval_with_stack()` and delegates everything else.
+pub(crate) struct StackNamespace<'a, NS> {
+    pub(crate) stack: &'a mut Vec<f64>,
+    pub(crate) inner: &'a mut NS,
+}
+
+/// Keeps the values of `CompiledExpression::eval_incremental()` between
+/// evaluations and delegates everything else.
+///
+/// The slots below `base` belong to `ITemps` temporaries, so they are passed on.
+#[cfg(feature = "std")]
+pub(crate) struct CacheNamespace<'a, NS> {
+    pub(crate) vals: &'a mut [Option<f64>],
+    pub(crate) base: usize,
+    pub(crate) inner: &'a mut NS,
+}
+
+/// Provides the variables of `BoundExpression::eval()` and delegates everything else.
+///
+/// Each symbol has a slot, starting at `base`.  Inside `sum()` bodies, where
+/// the temporaries aren't passed on, the variables are found by name instead.
+pub(crate) struct SymbolNamespace<'a, NS> {
+    pub(crate) vals: &'a [f64],
+    pub(crate) symbols: &'a Interner,
+    pub(crate) base: usize,
+    pub(crate) inner: &'a mut NS,
+}
+
+/// Binds the two grid variables during `Instruction::eval_grid()` and delegates everything else.
+pub(crate) struct GridNamespace<'a, NS> {
+    pub(crate) x_name: &'a str,
+    pub(crate) x: f64,
+    pub(crate) y_name: &'a str,
+    pub(crate) y: f64,
+    pub(crate) inner: &'a mut NS,
+}
+
+// I am commenting these out until I need them in real-life.
+// (I don't want to add things to the public API until necessary.)
 // pub struct CachedLayeredNamespace<'a> {
 //     caches:Vec<BTreeMap<String,f64>>,
 //     cb    :Box<dyn FnMut(&str, Vec<f64>)->Option<f64> + 'a>,
@@ -300,11 +784,7 @@ pub type StringToCallbackNamespace<'a> = BTreeMap<String, Box<dyn FnMut(Vec<f64>
 impl EvalNamespace for StringToCallbackNamespace<'_> {
     #[inline]
     fn lookup(&mut self, name: &str, args: Vec<f64>, _keybuf: &mut String) -> Option<f64> {
-        if let Some(f) = self.get_mut(name) {
-            Some(f(args))
-        } else {
-            None
-        }
+        self.get_mut(name).map(|f| f(args))
     }
 }
 
@@ -317,11 +797,7 @@ pub type StrToCallbackNamespace<'a> = BTreeMap<&'static str, Box<dyn FnMut(Vec<f
 impl EvalNamespace for StrToCallbackNamespace<'_> {
     #[inline]
     fn lookup(&mut self, name: &str, args: Vec<f64>, _keybuf: &mut String) -> Option<f64> {
-        if let Some(f) = self.get_mut(name) {
-            Some(f(args))
-        } else {
-            None
-        }
+        self.get_mut(name).map(|f| f(args))
     }
 }
 
@@ -341,6 +817,19 @@ impl EvalNamespace for LayeredStringToF64Namespace {
     }
 }
 
+/// Type alias for `&[(&str,f64)]`
+///
+/// Each lookup is a linear scan of the slice, which is fine (and avoids
+/// building a map) when you only have a few variables.
+pub type StrToF64PairsNamespace<'a> = &'a [(&'a str, f64)];
+impl EvalNamespace for StrToF64PairsNamespace<'_> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        let key = key_from_nameargs(keybuf, name, &args);
+        self.iter().find(|(k, _)| *k == key).map(|&(_, val)| val)
+    }
+}
+
 // I'm not making a type alias for this because of the un-name-ability of closures:
 impl<F> EvalNamespace for F
 where
@@ -352,6 +841,90 @@ where
     }
 }
 
+impl<F> Context for F
+where
+    F: FnMut(&str, Vec<f64>) -> Option<f64>,
+{
+    #[inline]
+    fn get(&mut self, name: &str) -> Option<f64> {
+        self(name, Vec::new())
+    }
+    #[inline]
+    fn call(&mut self, name: &str, args: &[f64]) -> Option<Result<f64, Error>> {
+        self(name, args.to_vec()).map(Ok)
+    }
+}
+
+impl<C: Context> ContextNamespace<C> {
+    #[inline]
+    pub fn new(ctx: C) -> Self {
+        ContextNamespace { ctx }
+    }
+
+    /// Returns a reference to the wrapped `Context`.
+    #[inline]
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.ctx
+    }
+
+    /// Unwraps the wrapped `Context`.
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.ctx
+    }
+}
+impl<C: Context> EvalNamespace for ContextNamespace<C> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        self.try_lookup(name, args, keybuf).unwrap_or(None)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        _keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        if args.is_empty() {
+            if let Some(val) = self.ctx.get(name) {
+                return Ok(Some(val));
+            }
+        }
+        self.ctx.call(name, &args).transpose()
+    }
+}
+
+#[cfg(feature = "std")]
+impl EvalNamespace for EnvNamespace {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        self.try_lookup(name, args, keybuf).unwrap_or(None)
+    }
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        _keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        if !args.is_empty() {
+            return Ok(None);
+        }
+        match std::env::var(name) {
+            Ok(val) => {
+                let val = val.trim();
+                val.parse()
+                    .map(Some)
+                    .map_err(|_| Error::ParseF64(val.to_string()))
+            }
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(std::env::VarError::NotUnicode(_)) => Err(Error::Utf8ErrorWhileParsing(format!(
+                "environment variable {}",
+                name
+            ))),
+        }
+    }
+}
+
 impl EvalNamespace for EmptyNamespace {
     /// Always returns `None`, indicating that the variable is undefined.
     #[inline]
@@ -406,6 +979,1579 @@ impl<'a> CachedCallbackNamespace<'a> {
     }
 }
 
+impl<NS> EvalNS<NS> {
+    /// Wraps `ns` without any limits.
+    #[inline]
+    pub fn new(ns: NS) -> Self {
+        EvalNS {
+            ns,
+            budget: None,
+            used: 0,
+            #[cfg(feature = "std")]
+            deadline: None,
+            rng: None,
+            clock: default_clock(),
+            strict: false,
+            null: None,
+            range: None,
+            zero_pow_zero: None,
+            mod_mode: None,
+            stable: false,
+        }
+    }
+
+    /// Limits the number of operations that evaluations may perform.
+    ///
+    /// Every binary operation, function call, and loop iteration consumes one
+    /// unit of the budget.  Once the budget is used up, evaluation fails with
+    /// [`Error::BudgetExceeded`](../error/enum.Error.html#variant.BudgetExceeded).
+    /// The budget is shared by all evaluations that use this Namespace; use
+    /// `reset_budget()` to start counting again.
+    #[inline]
+    pub fn with_budget(mut self, budget: u64) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Limits the wall-clock time that evaluations may take.
+    ///
+    /// The clock is checked at the same points where the operation budget is
+    /// counted, so it is checked before every function call, and a custom function
+    /// that blocks is caught as soon as it returns.  Once `deadline` has
+    /// passed, evaluation fails with
+    /// [`Error::Timeout`](../error/enum.Error.html#variant.Timeout).  Only
+    /// available with the `std` feature.
+    ///
+    /// ```
+    /// use fasteval2::{EvalNS, Error};
+    /// use std::time::{Duration, Instant};
+    /// let mut ns = EvalNS::new(fasteval2::EmptyNamespace)
+    ///     .with_deadline(Instant::now() + Duration::from_millis(50));
+    /// assert_eq!(fasteval2::ez_eval("sum(i, 1, 1e12, i)", &mut ns), Err(Error::Timeout));
+    /// ```
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Defines the `rand()` and `randint(a, b)` functions, which draw from a
+    /// random number generator that is seeded with `seed`.
+    ///
+    /// `rand()` returns a uniformly-distributed number in `[0, 1)`, and
+    /// `randint(a, b)` returns a uniformly-distributed integer in `[a, b]`.
+    /// Evaluations that use the same seed produce the same sequence of
+    /// numbers.  These names take precedence over the wrapped Namespace.
+    ///
+    /// ```
+    /// use fasteval2::EvalNS;
+    /// let mut ns = EvalNS::new(fasteval2::EmptyNamespace).with_rng(42);
+    /// let roll = fasteval2::ez_eval("randint(1, 6)", &mut ns).unwrap();
+    /// assert!((1.0..=6.0).contains(&roll) && roll == roll.trunc());
+    /// ```
+    #[inline]
+    pub fn with_rng(mut self, seed: u64) -> Self {
+        self.rng = Some(Rng(seed));
+        self
+    }
+
+    /// Sets the clock that `now()` reads, instead of the default
+    /// [`SystemClock`](struct.SystemClock.html).
+    ///
+    /// Every `EvalNS` defines the `now()`, `hour(t)`, `minute(t)`, `dow(t)`
+    /// and `date(y, m, d)` functions.  Times are numbers of seconds since
+    /// 1970-01-01 00:00, and everything is in UTC; there are no time zones or
+    /// leap seconds.
+    ///
+    /// * `now()` returns `clock.now()`.  It is never constant-folded or cached.
+    ///   Without the `std` feature there is no default clock, so until one is
+    ///   set, `now()` is looked up in the wrapped Namespace like any other
+    ///   function.
+    /// * `hour(t)` (0 to 23) and `minute(t)` (0 to 59) return the time of day of `t`.
+    /// * `dow(t)` returns the day of the week of `t`, from 0 for Sunday to 6 for Saturday.
+    /// * `date(y, m, d)` returns the start of day `d` of month `m` (1 to 12) of year `y`.
+    ///   Arguments that aren't integers, or aren't a valid date (like
+    ///   `date(2023, 2, 29)`), make the evaluation fail with
+    ///   [`Error::WrongArgs`](../error/enum.Error.html#variant.WrongArgs).
+    ///
+    /// These names take precedence over the wrapped Namespace.
+    ///
+    /// ```
+    /// use fasteval2::{EvalNS, FixedClock};
+    /// // Monday, 2024-03-04 09:30 UTC:
+    /// let mut ns = EvalNS::new(fasteval2::EmptyNamespace).with_clock(FixedClock(1709544600.0));
+    /// assert_eq!(fasteval2::ez_eval("now() - date(2024, 3, 4)", &mut ns), Ok(9.5 * 3600.0));
+    /// assert_eq!(fasteval2::ez_eval("hour(now()) >= 9 && dow(now()) == 1", &mut ns), Ok(1.0));
+    /// ```
+    #[inline]
+    pub fn with_clock(mut self, clock: impl Clock + Send + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Rejects NaN and infinite values from the wrapped Namespace.
+    ///
+    /// A variable or custom function that produces a non-finite value makes
+    /// the evaluation fail with
+    /// [`Error::NotFinite`](../error/enum.Error.html#variant.NotFinite),
+    /// instead of silently spreading through the result.  Only the values
+    /// that the Namespace returns are checked: `1/0` still evaluates to `inf`.
+    ///
+    /// ```
+    /// use fasteval2::{EvalNS, Error};
+    /// let mut ns = EvalNS::new(|_: &str, _: Vec<f64>| Some(f64::NAN)).with_strict();
+    /// assert_eq!(fasteval2::ez_eval("x + 1", &mut ns), Err(Error::NotFinite("x".to_string())));
+    /// ```
+    #[inline]
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Treats `sentinel` as a missing ("null") value, which spreads through
+    /// the operations that use it.
+    ///
+    /// Arithmetic operations, comparisons, `-` and `!` with a null operand
+    /// evaluate to null, so `none + 5` stays null instead of becoming a number.
+    /// `&&` and `||` are unchanged: they return one of their operands, and
+    /// treat null like any other value.  Values are
+    /// compared bit-for-bit, so a NaN with a custom payload can be used as the
+    /// sentinel without matching the NaN of `0/0`.  Functions receive null
+    /// arguments like any other value, and the `jit` backend ignores the
+    /// sentinel.
+    ///
+    /// ```
+    /// use fasteval2::EvalNS;
+    /// let none = f64::from_bits(0x7ff8_0000_0000_dead);
+    /// let mut ns = EvalNS::new(|name: &str, _: Vec<f64>| match name {
+    ///     "none" => Some(none),
+    ///     _ => Some(1.0),
+    /// })
+    /// .with_null(none);
+    /// let val = fasteval2::ez_eval("none + 5", &mut ns).unwrap();
+    /// assert!(ns.is_null(val));
+    /// let val = fasteval2::ez_eval("x < none", &mut ns).unwrap();
+    /// assert!(ns.is_null(val));
+    /// assert_eq!(fasteval2::ez_eval("x + 5", &mut ns), Ok(6.0));
+    /// ```
+    #[inline]
+    pub fn with_null(mut self, sentinel: f64) -> Self {
+        self.null = Some(sentinel);
+        self
+    }
+
+    /// Rejects results outside of `[min, max]`, like negative masses or counts.
+    ///
+    /// After an evaluation, a result below `min`, above `max`, or NaN makes it
+    /// fail with
+    /// [`Error::OutOfRange`](../error/enum.Error.html#variant.OutOfRange).
+    /// Only final results are checked, by the evaluation calls that
+    /// [`check_result()`](trait.EvalNamespace.html#method.check_result) lists,
+    /// so intermediate values may still leave the range.
+    ///
+    /// ```
+    /// use fasteval2::{EvalNS, Error};
+    /// let mut ns = EvalNS::new(fasteval2::EmptyNamespace).with_result_range(0.0, f64::INFINITY);
+    /// assert_eq!(fasteval2::ez_eval("3 - 1", &mut ns), Ok(2.0));
+    /// assert_eq!(
+    ///     fasteval2::ez_eval("1 - 3", &mut ns),
+    ///     Err(Error::OutOfRange { value: -2.0, min: 0.0, max: f64::INFINITY })
+    /// );
+    /// ```
+    #[inline]
+    pub fn with_result_range(mut self, min: f64, max: f64) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    /// Chooses what `0^0` evaluates to: `1` (the default, like `f64::powf()`),
+    /// `NaN`, or an error.
+    ///
+    /// Only an exact zero raised to an exact zero is affected (either of them
+    /// may be `-0`); `0^x` and `x^0` are unchanged for every other `x`.  The
+    /// interpreter and compiled expressions follow this setting, but
+    /// [`Expression::to_fn()`](../parser/struct.Expression.html#method.to_fn)
+    /// and the `jit` backend always use `1`.
+    ///
+    /// ```
+    /// use fasteval2::{EvalNS, Error, ZeroPowZero};
+    /// let mut ns = EvalNS::new(|_: &str, _: Vec<f64>| Some(0.0)).with_zero_pow_zero(ZeroPowZero::Error);
+    /// assert_eq!(fasteval2::ez_eval("x ^ 0", &mut ns), Err(Error::ZeroPowZero));
+    /// assert_eq!(fasteval2::ez_eval("x ^ 2", &mut ns), Ok(0.0));
+    /// ```
+    #[inline]
+    pub fn with_zero_pow_zero(mut self, mode: ZeroPowZero) -> Self {
+        self.zero_pow_zero = Some(mode);
+        self
+    }
+
+    /// Chooses how `%`, `remainder()` and `quotient()` treat their operands:
+    /// as floats (the default, so `5.5 % 2` is `1.5`), or as integers, which
+    /// makes a non-integral operand or a zero divisor an error.
+    ///
+    /// The result of an integer modulo has the sign of the dividend, like the
+    /// float remainder and `eval_i64()`, and the quotient is rounded toward
+    /// zero to match.  A null operand stays null.  The
+    /// interpreter and compiled expressions follow this setting, but
+    /// [`Expression::to_fn()`](../parser/struct.Expression.html#method.to_fn)
+    /// and the `jit` backend always use floats.
+    ///
+    /// ```
+    /// use fasteval2::{EvalNS, Error, ModMode};
+    /// let mut ns = EvalNS::new(|_: &str, _: Vec<f64>| Some(5.5)).with_mod_mode(ModMode::Integer);
+    /// assert_eq!(fasteval2::ez_eval("x % 2", &mut ns), Err(Error::NotAnInteger("5.5".to_string())));
+    /// assert_eq!(fasteval2::ez_eval("(x - 0.5) % 2", &mut ns), Ok(1.0));
+    /// ```
+    #[inline]
+    pub fn with_mod_mode(mut self, mode: ModMode) -> Self {
+        self.mod_mode = Some(mode);
+        self
+    }
+
+    /// Declares that the wrapped Namespace doesn't change during an
+    /// evaluation, so that evaluations can cache what they look up and
+    /// compute.
+    ///
+    /// Each variable is then looked up at most once per evaluation, and a
+    /// compiled expression computes each of its distinct pure sub-expressions
+    /// at most once: a repeated sub-expression is only evaluated where it is
+    /// first reached.  Results don't change, but `tick()`s are only counted for
+    /// the work that is done, and the caches are built again for every
+    /// evaluation (for a compiled expression, by comparing all of its
+    /// sub-expressions), so this pays off when lookups or repeated
+    /// sub-expressions are expensive.  See [`EvalNamespace::is_stable()`](trait.EvalNamespace.html#method.is_stable).
+    ///
+    /// ```
+    /// use fasteval2::EvalNS;
+    /// let mut lookups = 0;
+    /// let mut ns = EvalNS::new(|_: &str, _: Vec<f64>| {
+    ///     lookups += 1;
+    ///     Some(2.0)
+    /// })
+    /// .with_stable();
+    /// assert_eq!(fasteval2::ez_eval("x*x + piecewise(x > 1, x^3, x)", &mut ns), Ok(12.0));
+    /// drop(ns);
+    /// assert_eq!(lookups, 1);
+    /// ```
+    #[inline]
+    pub fn with_stable(mut self) -> Self {
+        self.stable = true;
+        self
+    }
+
+    /// Returns `true` if `val` is the sentinel of `with_null()`.
+    #[inline]
+    pub fn is_null(&self, val: f64) -> bool {
+        matches!(self.null, Some(null) if null.to_bits() == val.to_bits())
+    }
+
+    /// Returns the number of operations consumed so far.
+    #[inline]
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Resets the consumed-operations counter to zero.
+    #[inline]
+    pub fn reset_budget(&mut self) {
+        self.used = 0;
+    }
+
+    /// Returns a reference to the wrapped Namespace.
+    #[inline]
+    pub fn inner(&mut self) -> &mut NS {
+        &mut self.ns
+    }
+
+    /// Unwraps the wrapped Namespace.
+    #[inline]
+    pub fn into_inner(self) -> NS {
+        self.ns
+    }
+}
+fn wrong_arg_count(name: &str, n: usize, args: &[f64]) -> Error {
+    Error::WrongArgCount {
+        name: name.to_string(),
+        expected: Arity::Exact(n),
+        got: args.len(),
+    }
+}
+
+impl<NS> EvalNS<NS> {
+    // Handles `rand()` and `randint()`, or returns `None` for other names.
+    fn random(&mut self, name: &str, args: &[f64]) -> Option<Result<f64, Error>> {
+        let rng = self.rng.as_mut()?;
+        match name {
+            "rand" => Some(match *args {
+                [] => Ok(rng.next_f64()),
+                _ => Err(wrong_arg_count(name, 0, args)),
+            }),
+            "randint" => Some(match *args {
+                [a, b] => rng.next_int(a, b),
+                _ => Err(wrong_arg_count(name, 2, args)),
+            }),
+            _ => None,
+        }
+    }
+
+    // Handles the time functions of `with_clock()`, or returns `None` for other
+    // names.  Only `now()` needs the clock; without one, it is left to `ns`.
+    fn time(&mut self, name: &str, args: &[f64]) -> Option<Result<f64, Error>> {
+        let of_day = |t: f64, unit: f64, n: f64| ((t / unit).floor() % n + n) % n;
+        let f = match (name, args) {
+            ("now", []) => self.clock.as_mut()?.now(),
+            ("hour", &[t]) => of_day(t, 3600.0, 24.0),
+            ("minute", &[t]) => of_day(t, 60.0, 60.0),
+            // 1970-01-01 was a Thursday:
+            ("dow", &[t]) => of_day(t + 4.0 * 86400.0, 86400.0, 7.0),
+            ("date", &[y, m, d]) => return Some(date(y, m, d)),
+            ("now", _) => {
+                self.clock.as_ref()?;
+                return Some(Err(wrong_arg_count(name, 0, args)));
+            }
+            ("hour" | "minute" | "dow", _) => return Some(Err(wrong_arg_count(name, 1, args))),
+            ("date", _) => return Some(Err(wrong_arg_count(name, 3, args))),
+            _ => return None,
+        };
+        Some(Ok(f))
+    }
+
+    // Applies the `with_strict()` check to a lookup result.
+    #[inline]
+    fn check(&self, name: &str, val: Option<f64>) -> Result<Option<f64>, Error> {
+        match val {
+            Some(f) if self.strict && !f.is_finite() => Err(Error::NotFinite(name.to_string())),
+            _ => Ok(val),
+        }
+    }
+}
+impl<C: Context> EvalNS<ContextNamespace<C>> {
+    /// Wraps a `Context` (in a `ContextNamespace`) without any limits.
+    #[inline]
+    pub fn from_context(ctx: C) -> Self {
+        EvalNS::new(ContextNamespace::new(ctx))
+    }
+}
+#[cfg(feature = "std")]
+impl EvalNS<EnvNamespace> {
+    /// Reads variables from the environment variables of the process, through
+    /// an [`EnvNamespace`](struct.EnvNamespace.html), without any limits.
+    /// Only available with the `std` feature.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{ez_eval, Error, EvalNS};
+    ///
+    /// std::env::set_var("FE_DOC_WORKERS", "4");
+    /// assert_eq!(ez_eval("FE_DOC_WORKERS * 2", &mut EvalNS::from_env()), Ok(8.0));
+    ///
+    /// std::env::set_var("FE_DOC_WORKERS", "four");
+    /// assert_eq!(
+    ///     ez_eval("FE_DOC_WORKERS * 2", &mut EvalNS::from_env()),
+    ///     Err(Error::ParseF64("four".to_string()))
+    /// );
+    /// ```
+    #[inline]
+    pub fn from_env() -> Self {
+        EvalNS::new(EnvNamespace)
+    }
+}
+impl<NS: EvalNamespace> EvalNamespace for EvalNS<NS> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        let val = match self.random(name, &args).or_else(|| self.time(name, &args)) {
+            Some(res) => res.ok(),
+            None => self.ns.lookup(name, args, keybuf),
+        };
+        self.check(name, val).unwrap_or(None)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        let val = match self.random(name, &args).or_else(|| self.time(name, &args)) {
+            Some(res) => res.map(Some),
+            None => self.ns.try_lookup(name, args, keybuf),
+        }?;
+        self.check(name, val)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        if let Some(budget) = self.budget {
+            if self.used >= budget {
+                return Err(Error::BudgetExceeded(self.used));
+            }
+            self.used += 1;
+        }
+        #[cfg(feature = "std")]
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+        }
+        self.ns.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.null.or_else(|| self.ns.null())
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.zero_pow_zero
+            .unwrap_or_else(|| self.ns.zero_pow_zero())
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.mod_mode.unwrap_or_else(|| self.ns.mod_mode())
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.stable || self.ns.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        if let Some((min, max)) = self.range {
+            if !(min..=max).contains(&val) {
+                return Err(Error::OutOfRange {
+                    value: val,
+                    min,
+                    max,
+                });
+            }
+        }
+        self.ns.check_result(val)
+    }
+}
+impl<NS: Cached> Cached for EvalNS<NS> {
+    fn cache_create(&mut self, name: String, val: f64) -> Result<(), Error> {
+        self.ns.cache_create(name, val)
+    }
+    fn cache_set(&mut self, name: String, val: f64) {
+        self.ns.cache_set(name, val)
+    }
+    fn cache_clear(&mut self) {
+        self.ns.cache_clear()
+    }
+}
+
+impl Default for EvalNSBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<'a> EvalNSBuilder<'a> {
+    /// Starts without any variables, functions or limits.
+    #[inline]
+    pub fn new() -> Self {
+        EvalNSBuilder {
+            ns: MapNamespace {
+                vars: BTreeMap::new(),
+                funcs: BTreeMap::new(),
+            },
+            budget: None,
+            #[cfg(feature = "std")]
+            deadline: None,
+            rng: None,
+            clock: None,
+            strict: false,
+            null: None,
+            range: None,
+            zero_pow_zero: None,
+            mod_mode: None,
+            stable: false,
+            fold_funcs: false,
+        }
+    }
+
+    /// Defines a variable.  Defining a name again replaces the old value.
+    #[inline]
+    pub fn var(mut self, name: &str, val: f64) -> Self {
+        self.ns.vars.insert(name.to_string(), val);
+        self
+    }
+
+    /// Defines a custom function.  `f` gets the arguments, and returns `None`
+    /// to report the function as undefined (for example, when it got the
+    /// wrong number of arguments).
+    #[inline]
+    pub fn func(mut self, name: &str, f: impl FnMut(Vec<f64>) -> Option<f64> + 'a) -> Self {
+        self.ns.funcs.insert(name.to_string(), Box::new(f));
+        self
+    }
+
+    /// Defines the functions by their lowercase names, so a function `Foo` can be called as `FOO()` or `foo()` by a
+    /// [`Parser`](../parser/struct.Parser.html) with
+    /// [`case_insensitive_funcs`](../parser/struct.ParserBuilder.html#method.case_insensitive_funcs),
+    /// which calls functions by their lowercase name.  Variables keep their case.
+    #[inline]
+    pub fn case_insensitive_funcs(mut self) -> Self {
+        self.fold_funcs = true;
+        self
+    }
+
+    /// See [`EvalNS::with_strict()`](struct.EvalNS.html#method.with_strict).
+    #[inline]
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// See [`EvalNS::with_budget()`](struct.EvalNS.html#method.with_budget).
+    #[inline]
+    pub fn budget(mut self, budget: u64) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// See [`EvalNS::with_deadline()`](struct.EvalNS.html#method.with_deadline).
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// See [`EvalNS::with_rng()`](struct.EvalNS.html#method.with_rng).
+    #[inline]
+    pub fn rng(mut self, seed: u64) -> Self {
+        self.rng = Some(seed);
+        self
+    }
+
+    /// See [`EvalNS::with_clock()`](struct.EvalNS.html#method.with_clock).
+    #[inline]
+    pub fn clock(mut self, clock: impl Clock + Send + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// See [`EvalNS::with_result_range()`](struct.EvalNS.html#method.with_result_range).
+    #[inline]
+    pub fn result_range(mut self, min: f64, max: f64) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    /// See [`EvalNS::with_zero_pow_zero()`](struct.EvalNS.html#method.with_zero_pow_zero).
+    #[inline]
+    pub fn zero_pow_zero(mut self, mode: ZeroPowZero) -> Self {
+        self.zero_pow_zero = Some(mode);
+        self
+    }
+
+    /// See [`EvalNS::with_mod_mode()`](struct.EvalNS.html#method.with_mod_mode).
+    #[inline]
+    pub fn mod_mode(mut self, mode: ModMode) -> Self {
+        self.mod_mode = Some(mode);
+        self
+    }
+
+    /// See [`EvalNS::with_stable()`](struct.EvalNS.html#method.with_stable).
+    #[inline]
+    pub fn stable(mut self) -> Self {
+        self.stable = true;
+        self
+    }
+
+    /// See [`EvalNS::with_null()`](struct.EvalNS.html#method.with_null).
+    #[inline]
+    pub fn null(mut self, sentinel: f64) -> Self {
+        self.null = Some(sentinel);
+        self
+    }
+
+    /// Creates the configured `EvalNS`.
+    pub fn build(mut self) -> EvalNS<MapNamespace<'a>> {
+        if self.fold_funcs {
+            let funcs = mem::take(&mut self.ns.funcs);
+            self.ns.funcs = funcs
+                .into_iter()
+                .map(|(name, f)| (name.to_ascii_lowercase(), f))
+                .collect();
+        }
+        let mut ns = EvalNS::new(self.ns);
+        if let Some(budget) = self.budget {
+            ns = ns.with_budget(budget);
+        }
+        #[cfg(feature = "std")]
+        if let Some(deadline) = self.deadline {
+            ns = ns.with_deadline(deadline);
+        }
+        if let Some(seed) = self.rng {
+            ns = ns.with_rng(seed);
+        }
+        // Keeps the default clock, unless one was set:
+        if self.clock.is_some() {
+            ns.clock = self.clock;
+        }
+        if self.strict {
+            ns = ns.with_strict();
+        }
+        if let Some(sentinel) = self.null {
+            ns = ns.with_null(sentinel);
+        }
+        if let Some((min, max)) = self.range {
+            ns = ns.with_result_range(min, max);
+        }
+        if self.stable {
+            ns = ns.with_stable();
+        }
+        if let Some(mode) = self.zero_pow_zero {
+            ns = ns.with_zero_pow_zero(mode);
+        }
+        if let Some(mode) = self.mod_mode {
+            ns = ns.with_mod_mode(mode);
+        }
+        ns
+    }
+}
+
+impl EvalNamespace for MapNamespace<'_> {
+    fn lookup(&mut self, name: &str, args: Vec<f64>, _keybuf: &mut String) -> Option<f64> {
+        if args.is_empty() {
+            if let Some(val) = self.vars.get(name) {
+                return Some(*val);
+            }
+        }
+        self.funcs.get_mut(name).and_then(|f| f(args))
+    }
+}
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa exactly:
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_int(&mut self, a: f64, b: f64) -> Result<f64, Error> {
+        let (lo, hi) = (a.ceil(), b.floor());
+        if lo.is_nan() || hi.is_nan() || lo > hi {
+            return Err(Error::WrongArgs("randint: the range is empty".to_string()));
+        }
+        if lo.is_infinite() || hi.is_infinite() || hi - lo >= (1u64 << 53) as f64 {
+            return Err(Error::WrongArgs(
+                "randint: the range is too large".to_string(),
+            ));
+        }
+        // Reject the top of the u64 range so that every integer is equally likely:
+        let span = (hi - lo) as u64 + 1;
+        let limit = u64::MAX - u64::MAX % span;
+        loop {
+            let r = self.next_u64();
+            if r < limit {
+                return Ok(lo + (r % span) as f64);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&mut self) -> f64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        }
+    }
+}
+impl Clock for FixedClock {
+    #[inline]
+    fn now(&mut self) -> f64 {
+        self.0
+    }
+}
+impl<F: FnMut() -> f64> Clock for F {
+    #[inline]
+    fn now(&mut self) -> f64 {
+        self()
+    }
+}
+
+// The clock of a new `EvalNS`: the system time, if there is one.
+#[inline]
+fn default_clock() -> Option<Box<dyn Clock + Send>> {
+    #[cfg(feature = "std")]
+    return Some(Box::new(SystemClock));
+    #[cfg(not(feature = "std"))]
+    None
+}
+
+// The start of a day, for the `date()` function of `with_clock()`.
+fn date(y: f64, m: f64, d: f64) -> Result<f64, Error> {
+    let int = |x: f64| x == x.trunc() && x.abs() <= 1e9;
+    if !int(y) || !int(m) || !int(d) {
+        return Err(Error::WrongArgs(
+            "date: the year, month and day must be integers".to_string(),
+        ));
+    }
+    let (y, m, d) = (y as i64, m as i64, d as i64);
+    let leap = y % 4 == 0 && (y % 100 != 0 || y % 400 == 0);
+    let month_len = match m {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&m) || !(1..=month_len).contains(&d) {
+        return Err(Error::WrongArgs(format!(
+            "date: {}-{:02}-{:02} is not a valid date",
+            y, m, d
+        )));
+    }
+    // Days since 1970-01-01, counting years from March so that the leap day
+    // comes last (Howard Hinnant's days_from_civil):
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Ok((era * 146_097 + doe - 719_468) as f64 * 86400.0)
+}
+
+impl ZeroPowZero {
+    // `base ^ power`, with this treatment of `0^0`:
+    #[inline]
+    pub(crate) fn pow(self, base: f64, power: f64) -> Result<f64, Error> {
+        if base == 0.0 && power == 0.0 {
+            match self {
+                ZeroPowZero::One => Ok(1.0),
+                ZeroPowZero::NaN => Ok(f64::NAN),
+                ZeroPowZero::Error => Err(Error::ZeroPowZero),
+            }
+        } else {
+            Ok(base.powf(power))
+        }
+    }
+}
+
+impl ModMode {
+    // `dividend % divisor`, with this treatment of the operands:
+    #[inline]
+    pub(crate) fn rem(self, dividend: f64, divisor: f64) -> Result<f64, Error> {
+        self.check(dividend, divisor)?;
+        Ok(dividend % divisor)
+    }
+
+    // `quotient(dividend, divisor)`, with the same treatment as `%`:
+    #[inline]
+    pub(crate) fn quotient(self, dividend: f64, divisor: f64) -> Result<f64, Error> {
+        self.check(dividend, divisor)?;
+        Ok(quotient(dividend, divisor))
+    }
+
+    #[inline]
+    fn check(self, dividend: f64, divisor: f64) -> Result<(), Error> {
+        if self == ModMode::Integer {
+            for val in [dividend, divisor] {
+                if !val.is_finite() || val.fract() != 0.0 {
+                    return Err(Error::NotAnInteger(val.to_string()));
+                }
+            }
+            if divisor == 0.0 {
+                return Err(Error::DivideByZero);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns `true` if `dividend % divisor` is the same in every mode, so that
+    // it can be constant-folded:
+    #[inline]
+    pub(crate) fn is_foldable(dividend: f64, divisor: f64) -> bool {
+        ModMode::Integer.rem(dividend, divisor).is_ok()
+    }
+}
+
+/// Returns `true` for the functions that don't always return the same value
+/// for the same arguments, which must never be constant-folded or cached.
+pub(crate) fn is_impure(name: &str) -> bool {
+    name == "rand" || name == "randint" || name == "now"
+}
+
+impl EvalNamespace for ScopeNamespace<'_> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        if args.is_empty() && name == self.name {
+            Some(self.val)
+        } else {
+            self.inner.lookup(name, args, keybuf)
+        }
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        if args.is_empty() && name == self.name {
+            Ok(Some(self.val))
+        } else {
+            self.inner.try_lookup(name, args, keybuf)
+        }
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+impl TempNamespace<'_> {
+    /// Gives the temporaries' space on the working stack back.
+    pub(crate) fn release(self) {
+        if let TempVals::Stack { base, .. } = self.vals {
+            if let Some(stack) = self.inner.stack() {
+                stack.truncate(base);
+            }
+        }
+    }
+}
+
+impl EvalNamespace for TempNamespace<'_> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        self.inner.lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        self.inner.try_lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        match self.vals {
+            TempVals::Heap(ref vals) if slot < vals.len() => vals.get(slot).copied().flatten(),
+            TempVals::Stack { base, slots } if slot < slots => {
+                let stack = self.inner.stack()?;
+                let i = base + 2 * slot;
+                if stack[i] != 0.0 {
+                    Some(stack[i + 1])
+                } else {
+                    None
+                }
+            }
+            _ => self.inner.temp(slot),
+        }
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        match self.vals {
+            TempVals::Heap(ref mut vals) if slot < vals.len() => {
+                if let Some(v) = vals.get_mut(slot) {
+                    *v = Some(val);
+                }
+            }
+            TempVals::Stack { base, slots } if slot < slots => {
+                if let Some(stack) = self.inner.stack() {
+                    let i = base + 2 * slot;
+                    stack[i] = 1.0;
+                    stack[i + 1] = val;
+                }
+            }
+            _ => self.inner.set_temp(slot, val),
+        }
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+impl LayerNamespace<'_, '_> {
+    #[inline]
+    fn scoped(&self, name: &str, args: &[f64]) -> Option<f64> {
+        if !args.is_empty() {
+            return None;
+        }
+        self.layers.iter().rev().find_map(|layer| match layer {
+            Layer::Scope(n, val) if *n == name => Some(*val),
+            _ => None,
+        })
+    }
+}
+
+impl EvalNamespace for LayerNamespace<'_, '_> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        match self.scoped(name, &args) {
+            Some(val) => Some(val),
+            None => self.inner.lookup(name, args, keybuf),
+        }
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        match self.scoped(name, &args) {
+            Some(val) => Ok(Some(val)),
+            None => self.inner.try_lookup(name, args, keybuf),
+        }
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        for layer in self.layers.iter().rev() {
+            match layer {
+                Layer::Scope(..) => return None,
+                Layer::Temps(vals) if slot < vals.len() => return vals[slot],
+                Layer::Temps(_) => (),
+            }
+        }
+        self.inner.temp(slot)
+    }
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        for layer in self.layers.iter_mut().rev() {
+            match layer {
+                Layer::Scope(..) => return,
+                Layer::Temps(vals) if slot < vals.len() => {
+                    vals[slot] = Some(val);
+                    return;
+                }
+                Layer::Temps(_) => (),
+            }
+        }
+        self.inner.set_temp(slot, val)
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+impl<'a> StableNamespace<'a> {
+    #[inline]
+    pub(crate) fn new(inner: &'a mut dyn EvalNamespace) -> Self {
+        StableNamespace {
+            vars: BTreeMap::new(),
+            inner,
+        }
+    }
+}
+
+impl EvalNamespace for StableNamespace<'_> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        self.try_lookup(name, args, keybuf).unwrap_or(None)
+    }
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        if !args.is_empty() || is_impure(name) {
+            return self.inner.try_lookup(name, args, keybuf);
+        }
+        if let Some(&val) = self.vars.get(name) {
+            return Ok(Some(val));
+        }
+        let val = self.inner.try_lookup(name, args, keybuf)?;
+        if let Some(val) = val {
+            self.vars.insert(name.to_string(), val);
+        }
+        Ok(val)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        self.inner.temp(slot)
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        self.inner.set_temp(slot, val)
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+}
+
+impl<NS: EvalNamespace> EvalNamespace for OverrideNamespace<'_, NS> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        match self.get(name, &args) {
+            Some(val) => Some(val),
+            None => self.inner.lookup(name, args, keybuf),
+        }
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        match self.get(name, &args) {
+            Some(val) => Ok(Some(val)),
+            None => self.inner.try_lookup(name, args, keybuf),
+        }
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        self.inner.temp(slot)
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        self.inner.set_temp(slot, val)
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+impl<NS: EvalNamespace> EvalNamespace for StackNamespace<'_, NS> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        self.inner.lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        self.inner.try_lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        self.inner.temp(slot)
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        self.inner.set_temp(slot, val)
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        Some(self.stack)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<NS: EvalNamespace> EvalNamespace for CacheNamespace<'_, NS> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        self.inner.lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        self.inner.try_lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        match slot.checked_sub(self.base) {
+            Some(i) => self.vals.get(i).copied().flatten(),
+            None => self.inner.temp(slot),
+        }
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        match slot.checked_sub(self.base) {
+            Some(i) => {
+                if let Some(v) = self.vals.get_mut(i) {
+                    *v = Some(val);
+                }
+            }
+            None => self.inner.set_temp(slot, val),
+        }
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+impl<NS> SymbolNamespace<'_, NS> {
+    #[inline]
+    fn get(&self, name: &str) -> Option<f64> {
+        let sym = self.symbols.get(name)?;
+        self.vals.get(sym.index()).copied()
+    }
+}
+
+impl<NS: EvalNamespace> EvalNamespace for SymbolNamespace<'_, NS> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        if args.is_empty() {
+            if let Some(val) = self.get(name) {
+                return Some(val);
+            }
+        }
+        self.inner.lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        if args.is_empty() {
+            if let Some(val) = self.get(name) {
+                return Ok(Some(val));
+            }
+        }
+        self.inner.try_lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        match slot.checked_sub(self.base) {
+            Some(i) => self.vals.get(i).copied(),
+            None => self.inner.temp(slot),
+        }
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        // The slots of the symbols are only read:
+        if slot < self.base {
+            self.inner.set_temp(slot, val);
+        }
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+impl<NS: EvalNamespace> EvalNamespace for GridNamespace<'_, NS> {
+    #[inline]
+    fn lookup(&mut self, name: &str, args: Vec<f64>, keybuf: &mut String) -> Option<f64> {
+        if args.is_empty() {
+            if name == self.x_name {
+                return Some(self.x);
+            }
+            if name == self.y_name {
+                return Some(self.y);
+            }
+        }
+        self.inner.lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn try_lookup(
+        &mut self,
+        name: &str,
+        args: Vec<f64>,
+        keybuf: &mut String,
+    ) -> Result<Option<f64>, Error> {
+        if args.is_empty() {
+            if name == self.x_name {
+                return Ok(Some(self.x));
+            }
+            if name == self.y_name {
+                return Ok(Some(self.y));
+            }
+        }
+        self.inner.try_lookup(name, args, keybuf)
+    }
+    #[inline]
+    fn tick(&mut self) -> Result<(), Error> {
+        self.inner.tick()
+    }
+    #[inline]
+    fn null(&self) -> Option<f64> {
+        self.inner.null()
+    }
+    #[inline]
+    fn zero_pow_zero(&self) -> ZeroPowZero {
+        self.inner.zero_pow_zero()
+    }
+    #[inline]
+    fn mod_mode(&self) -> ModMode {
+        self.inner.mod_mode()
+    }
+    #[inline]
+    fn is_stable(&self) -> bool {
+        self.inner.is_stable()
+    }
+    #[inline]
+    fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+        self.inner.check_result(val)
+    }
+    #[inline]
+    fn temp(&mut self, slot: usize) -> Option<f64> {
+        self.inner.temp(slot)
+    }
+    #[inline]
+    fn set_temp(&mut self, slot: usize, val: f64) {
+        self.inner.set_temp(slot, val)
+    }
+    #[inline]
+    fn stack(&mut self) -> Option<&mut Vec<f64>> {
+        self.inner.stack()
+    }
+}
+
+#[cfg(test)]
+mod wrapper_tests {
+    use super::*;
+    use alloc::vec;
+
+    // Answers every hook with a value that no default has.
+    struct Probe {
+        temps: Vec<f64>,
+        stack: Vec<f64>,
+    }
+
+    impl EvalNamespace for Probe {
+        fn lookup(&mut self, _name: &str, _args: Vec<f64>, _keybuf: &mut String) -> Option<f64> {
+            None
+        }
+        fn tick(&mut self) -> Result<(), Error> {
+            Err(Error::BudgetExceeded(7))
+        }
+        fn temp(&mut self, slot: usize) -> Option<f64> {
+            self.temps.get(slot).copied()
+        }
+        fn set_temp(&mut self, slot: usize, val: f64) {
+            self.temps[slot] = val;
+        }
+        fn stack(&mut self) -> Option<&mut Vec<f64>> {
+            Some(&mut self.stack)
+        }
+        fn null(&self) -> Option<f64> {
+            Some(-7.0)
+        }
+        fn zero_pow_zero(&self) -> ZeroPowZero {
+            ZeroPowZero::Error
+        }
+        fn mod_mode(&self) -> ModMode {
+            ModMode::Integer
+        }
+        fn check_result(&mut self, val: f64) -> Result<f64, Error> {
+            Ok(val + 1.0)
+        }
+        fn is_stable(&self) -> bool {
+            true
+        }
+    }
+
+    fn probe() -> Probe {
+        Probe {
+            temps: vec![0.5; 4],
+            stack: vec![7.0],
+        }
+    }
+
+    // Asserts that `ns` passes every hook on to its `Probe`, except for the
+    // hooks in `own`, which the wrapper answers itself.  Slot 3 isn't one of
+    // the wrappers' own temporaries.
+    fn assert_forwards(ns: &mut dyn EvalNamespace, own: &[&str]) {
+        let hooks = [
+            "tick",
+            "temp",
+            "set_temp",
+            "stack",
+            "null",
+            "zero_pow_zero",
+            "mod_mode",
+            "check_result",
+            "is_stable",
+        ];
+        for hook in hooks.iter().filter(|hook| !own.contains(hook)) {
+            match *hook {
+                "tick" => assert_eq!(ns.tick(), Err(Error::BudgetExceeded(7))),
+                "temp" => assert_eq!(ns.temp(3), Some(0.5)),
+                "set_temp" => {
+                    ns.set_temp(3, 2.5);
+                    assert_eq!(ns.temp(3), Some(2.5));
+                }
+                "stack" => assert_eq!(ns.stack().cloned(), Some(vec![7.0])),
+                "null" => assert_eq!(ns.null(), Some(-7.0)),
+                "zero_pow_zero" => assert_eq!(ns.zero_pow_zero(), ZeroPowZero::Error),
+                "mod_mode" => assert_eq!(ns.mod_mode(), ModMode::Integer),
+                "check_result" => assert_eq!(ns.check_result(1.0), Ok(2.0)),
+                "is_stable" => assert!(ns.is_stable()),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn every_hook_is_forwarded() {
+        // A `sum()` or `let` hides the temporaries of the outer scopes:
+        assert_forwards(
+            &mut ScopeNamespace {
+                name: "i",
+                val: 1.0,
+                inner: &mut probe(),
+            },
+            &["temp", "set_temp"],
+        );
+        assert_forwards(
+            &mut TempNamespace {
+                vals: TempVals::Heap(vec![None]),
+                inner: &mut probe(),
+            },
+            &[],
+        );
+        assert_forwards(
+            &mut LayerNamespace {
+                layers: vec![Layer::Temps(vec![None])],
+                inner: &mut probe(),
+            },
+            &[],
+        );
+        // It reports itself as unstable, so that it's only added once:
+        let mut inner = probe();
+        let mut stable = StableNamespace::new(&mut inner);
+        assert!(!stable.is_stable());
+        assert_forwards(&mut stable, &["is_stable"]);
+        assert_forwards(
+            &mut OverrideNamespace {
+                overrides: &[("x", 1.0)],
+                inner: &mut probe(),
+            },
+            &[],
+        );
+        let mut stack = Vec::new();
+        assert_forwards(
+            &mut StackNamespace {
+                stack: &mut stack,
+                inner: &mut probe(),
+            },
+            &["stack"],
+        );
+        #[cfg(feature = "std")]
+        assert_forwards(
+            &mut CacheNamespace {
+                vals: &mut [None],
+                base: 10,
+                inner: &mut probe(),
+            },
+            &[],
+        );
+        assert_forwards(
+            &mut SymbolNamespace {
+                vals: &[1.0],
+                symbols: &Interner::new(),
+                base: 10,
+                inner: &mut probe(),
+            },
+            &[],
+        );
+        assert_forwards(
+            &mut GridNamespace {
+                x_name: "x",
+                x: 1.0,
+                y_name: "y",
+                y: 2.0,
+                inner: &mut probe(),
+            },
+            &[],
+        );
+    }
+}
+
 //// I am not ready to make this part of the public API yet.
 // impl EvalNamespace for CachedLayeredNamespace<'_> {
 //     fn lookup(&mut self, name:&str, args:Vec<f64>, keybuf:&mut String) -> Option<f64> {
@@ -0,0 +1,264 @@
+//! A C ABI, for evaluating formulas from C and other languages.
+//!
+//! With the `ffi` feature, the crate exports four `extern "C"` functions.
+//! `include/fasteval2.h` declares them (it is generated by
+//! `cbindgen --config cbindgen.toml --output include/fasteval2.h src/ffi.rs`):
+//!
+//! ```text
+//! FeExpr *expr = NULL;
+//! if (fe_parse("x * 2 + y", &expr) != FE_OK) { ... }
+//!
+//! FeVar vars[] = { { "x", 3.0 }, { "y", 1.0 } };
+//! double out;
+//! FeStatus status = fe_eval(expr, vars, 2, &out);     // out == 7.0
+//! if (status != FE_OK) {
+//!     char msg[256];
+//!     fe_error_message(status, msg, sizeof msg);
+//!     ...
+//! }
+//! fe_expr_free(expr);
+//! ```
+//!
+//! Every function returns an [`FeStatus`](type.FeStatus.html) instead of
+//! unwinding: null pointers and strings that aren't UTF-8 are reported as such,
+//! and a panic inside the crate becomes [`FE_PANIC`](constant.FE_PANIC.html).
+//! Pointers that are not null but don't point to what they should can't be
+//! detected, and are undefined behavior, as usual in C.  The ownership rules
+//! are listed on each function.
+//!
+//! The crate is built as an `rlib`, so that it keeps working for `no_std`
+//! targets.  A shared or static library for linking from C is built with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib` (or
+//! `staticlib`).  `tests/ffi.rs` builds the static library and runs
+//! `tests/ffi/main.c` against it.
+
+use crate::bytecode::CompiledExpression;
+use crate::evalns::EmptyNamespace;
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// The result of an `fe_*` function.  `FE_OK` is zero, errors are positive.
+pub type FeStatus = c_int;
+
+/// Success.
+pub const FE_OK: FeStatus = 0;
+/// A required pointer argument was null.
+pub const FE_NULL_POINTER: FeStatus = 1;
+/// A string argument was not valid UTF-8.
+pub const FE_INVALID_UTF8: FeStatus = 2;
+/// The formula could not be parsed or compiled.
+pub const FE_PARSE_ERROR: FeStatus = 3;
+/// The formula could not be evaluated, for example because a variable is missing.
+pub const FE_EVAL_ERROR: FeStatus = 4;
+/// The crate panicked.  The panic was caught, but its message is only
+/// available through `fe_error_message()`.
+pub const FE_PANIC: FeStatus = 5;
+
+/// A parsed and compiled formula, created by `fe_parse()` and destroyed by
+/// `fe_expr_free()`.
+///
+/// C only sees it behind a pointer.  It can be evaluated from several threads
+/// at the same time.
+#[derive(Debug)]
+pub struct FeExpr {
+    compiled: CompiledExpression,
+}
+
+/// A variable for `fe_eval()`: a NUL-terminated name and its value.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FeVar {
+    pub name: *const c_char,
+    pub value: f64,
+}
+
+thread_local! {
+    // The status and message of the last failure on this thread.
+    static LAST_ERROR: RefCell<(FeStatus, String)> = const { RefCell::new((FE_OK, String::new())) };
+}
+
+/// Parses and compiles the NUL-terminated formula `src`, and stores a new
+/// `FeExpr` in `*out`.
+///
+/// `src` is only borrowed during the call.  On success the caller owns
+/// `*out`, and must release it with `fe_expr_free()`.  On failure `*out` is
+/// set to null, unless `out` itself is null.
+///
+/// # Safety
+/// `src` must be null or point to a NUL-terminated string, and `out` must be
+/// null or valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn fe_parse(src: *const c_char, out: *mut *mut FeExpr) -> FeStatus {
+    guard(|| {
+        if out.is_null() {
+            return fail(FE_NULL_POINTER, "out is null");
+        }
+        *out = ptr::null_mut();
+        if src.is_null() {
+            return fail(FE_NULL_POINTER, "src is null");
+        }
+        let src = match CStr::from_ptr(src).to_str() {
+            Ok(src) => src,
+            Err(err) => return fail(FE_INVALID_UTF8, &format!("src: {}", err)),
+        };
+        match CompiledExpression::compile(src, &mut EmptyNamespace) {
+            Ok(compiled) => {
+                *out = Box::into_raw(Box::new(FeExpr { compiled }));
+                FE_OK
+            }
+            Err(err) => fail(FE_PARSE_ERROR, &err.to_string()),
+        }
+    })
+}
+
+/// Evaluates `expr` with the `n` variables in `vars`, and stores the result
+/// in `*out`.
+///
+/// Everything is only borrowed during the call; the caller keeps ownership of
+/// `expr`, `vars` and the names.  `vars` may be null when `n` is zero.  If a
+/// name appears more than once, the first one wins.  Formulas can use the
+/// built-in functions, but not custom ones.  `*out` is left unchanged on
+/// failure.
+///
+/// # Safety
+/// `expr` must be null or come from `fe_parse()` and not be freed yet.
+/// `vars` must be null or point to `n` `FeVar`s, whose names are null or
+/// NUL-terminated strings.  `out` must be null or valid for writing a `double`.
+#[no_mangle]
+pub unsafe extern "C" fn fe_eval(
+    expr: *const FeExpr,
+    vars: *const FeVar,
+    n: usize,
+    out: *mut f64,
+) -> FeStatus {
+    guard(|| {
+        if expr.is_null() {
+            return fail(FE_NULL_POINTER, "expr is null");
+        }
+        if out.is_null() {
+            return fail(FE_NULL_POINTER, "out is null");
+        }
+        let vars: &[FeVar] = if n == 0 {
+            &[]
+        } else if vars.is_null() {
+            return fail(FE_NULL_POINTER, "vars is null");
+        } else {
+            std::slice::from_raw_parts(vars, n)
+        };
+        let mut names = Vec::with_capacity(vars.len());
+        for (i, var) in vars.iter().enumerate() {
+            if var.name.is_null() {
+                return fail(FE_NULL_POINTER, &format!("vars[{}].name is null", i));
+            }
+            let name = CStr::from_ptr(var.name);
+            if let Err(err) = name.to_str() {
+                return fail(FE_INVALID_UTF8, &format!("vars[{}].name: {}", i, err));
+            }
+            names.push(name.to_bytes());
+        }
+
+        let mut ns = |name: &str, args: Vec<f64>| -> Option<f64> {
+            if !args.is_empty() {
+                return None;
+            }
+            let i = names.iter().position(|n| *n == name.as_bytes())?;
+            Some(vars[i].value)
+        };
+        match (*expr).compiled.eval(&mut ns) {
+            Ok(val) => {
+                *out = val;
+                FE_OK
+            }
+            Err(err) => fail(FE_EVAL_ERROR, &err.to_string()),
+        }
+    })
+}
+
+/// Destroys an `FeExpr` from `fe_parse()`.  Passing null does nothing.
+///
+/// # Safety
+/// `expr` must be null or come from `fe_parse()`, and must not be used (or
+/// freed) again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fe_expr_free(expr: *mut FeExpr) {
+    if !expr.is_null() {
+        // Nothing to report if dropping panics, but it must not unwind into C.
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(expr))));
+    }
+}
+
+/// Writes a NUL-terminated description of `status` into `buf`, and returns
+/// the length of the whole description in bytes, without the NUL (like
+/// `snprintf()`).
+///
+/// If `status` is the status of the last failed `fe_*` call on this thread,
+/// the description includes the details of that failure, like the name of a
+/// missing variable.  At most `len` bytes are written, so the message is cut
+/// off (at a UTF-8 character boundary) when the return value is `len` or more.
+/// With a null `buf` or a `len` of zero nothing is written, which can be used
+/// to find out the size that is needed.  The caller owns `buf`.
+///
+/// # Safety
+/// `buf` must be null or valid for writing `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fe_error_message(status: FeStatus, buf: *mut c_char, len: usize) -> usize {
+    catch_unwind(AssertUnwindSafe(|| {
+        let mut msg = String::from(describe(status));
+        LAST_ERROR.with(|last| {
+            let last = last.borrow();
+            if status != FE_OK && last.0 == status {
+                msg.push_str(": ");
+                msg.push_str(&last.1);
+            }
+        });
+        if !buf.is_null() && len > 0 {
+            let mut n = msg.len().min(len - 1);
+            while !msg.is_char_boundary(n) {
+                n -= 1;
+            }
+            ptr::copy_nonoverlapping(msg.as_ptr(), buf.cast::<u8>(), n);
+            *buf.add(n) = 0;
+        }
+        msg.len()
+    }))
+    .unwrap_or(0)
+}
+
+fn describe(status: FeStatus) -> &'static str {
+    match status {
+        FE_OK => "ok",
+        FE_NULL_POINTER => "null pointer",
+        FE_INVALID_UTF8 => "invalid UTF-8",
+        FE_PARSE_ERROR => "parse error",
+        FE_EVAL_ERROR => "eval error",
+        FE_PANIC => "panic",
+        _ => "unknown status",
+    }
+}
+
+fn fail(status: FeStatus, msg: &str) -> FeStatus {
+    LAST_ERROR.with(|last| {
+        let mut last = last.borrow_mut();
+        last.0 = status;
+        last.1.clear();
+        last.1.push_str(msg);
+    });
+    status
+}
+
+// Runs the body of an `fe_*` function, converting a panic into FE_PANIC.
+fn guard(f: impl FnOnce() -> FeStatus) -> FeStatus {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.as_str()
+        } else {
+            "unknown panic payload"
+        };
+        fail(FE_PANIC, msg)
+    })
+}
@@ -0,0 +1,350 @@
+//! This module evaluates parsed `Expression`s using integer-only semantics.
+//!
+//! The normal [`Evaler`](../evaler/trait.Evaler.html) performs all
+//! calculations with `f64`, which loses precision for integers larger than
+//! 2^53.  The [`IntEvaler`](trait.IntEvaler.html) trait provides an
+//! `eval_i64()` method that performs all intermediate arithmetic with `i64`
+//! using checked operations instead:
+//!
+//! * Overflow produces `Error::IntegerOverflow`.
+//! * Division or modulo by zero produces `Error::DivideByZero`.
+//! * Division truncates towards zero, like Rust's `/` operator.
+//! * Constants and variables must be integral, otherwise `Error::NotAnInteger` is produced.
+//!   (Literals are still parsed as `f64`, so literals larger than 2^53 lose precision
+//!   before they reach the integer evaluator.)
+//! * Built-in functions without an integer definition (such as `log()`, `sqrt()`
+//!   and the trig functions) are calculated with `f64`, and their result must be integral.
+//!
+//! Only parsed `Expression`s can be evaluated this way, because the compiler
+//! performs its constant-folding with `f64`.
+//!
+//! # Examples
+//! ```
+//! use fasteval2::IntEvaler;  // use this trait so we can call eval_i64().
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = fasteval2::Slab::new();
+//!     let mut ns = fasteval2::EmptyNamespace;
+//!
+//!     let expr_ref = fasteval2::Parser::new().parse("2^62 + (2^62 - 1)", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(expr_ref.eval_i64(&slab, &mut ns)?, i64::MAX);
+//!
+//!     let expr_ref = fasteval2::Parser::new().parse("2^63", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(expr_ref.eval_i64(&slab, &mut ns), Err(fasteval2::Error::IntegerOverflow));
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use crate::error::Error;
+use crate::evaler::{named_constant, Evaler};
+use crate::evalns::EvalNamespace;
+#[cfg(not(feature = "std"))]
+use crate::math::F64Ext;
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    remove_no_panic,
+    BinaryOp::{
+        self, EAdd, EDiv, EExp, EMod, EMul, ESub, EAND, EEQ, EGT, EGTE, ELT, ELTE, ENE, EOR,
+    },
+    Expression,
+    StdFunc::{
+        self, EFunc, EFuncAbs, EFuncCeil, EFuncE, EFuncFloor, EFuncInt, EFuncMax, EFuncMin,
+        EFuncPi, EFuncPiecewise, EFuncRound, EFuncSign, EVar,
+    },
+    UnaryOp::{self, ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// You must `use` this trait so you can call `.eval_i64()`.
+pub trait IntEvaler {
+    /// Evaluate this `Expression` with integer-only semantics and return an `i64`.
+    ///
+    /// Returns a `fasteval2::Error` if there are any problems, such as
+    /// undefined variables, overflow, or non-integral values.
+    fn eval_i64(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<i64, Error>;
+}
+
+/// Converts an `f64` to an `i64`, but only if it can be represented exactly.
+fn to_i64(f: f64) -> Result<i64, Error> {
+    if !f.is_finite() || f.fract() != 0.0 {
+        return Err(Error::NotAnInteger(f.to_string()));
+    }
+    // 2^63 is exactly representable, but i64::MAX is not:
+    if !(-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&f) {
+        return Err(Error::IntegerOverflow);
+    }
+    Ok(f as i64)
+}
+
+#[inline]
+fn bool_to_i64(b: bool) -> i64 {
+    if b {
+        1
+    } else {
+        0
+    }
+}
+
+impl BinaryOp {
+    fn binaryop_eval_i64(self, left: i64, right: i64) -> Result<i64, Error> {
+        match self {
+            EAdd => left.checked_add(right).ok_or(Error::IntegerOverflow),
+            ESub => left.checked_sub(right).ok_or(Error::IntegerOverflow),
+            EMul => left.checked_mul(right).ok_or(Error::IntegerOverflow),
+            EDiv | EMod => {
+                if right == 0 {
+                    return Err(Error::DivideByZero);
+                }
+                if self == EDiv {
+                    left.checked_div(right).ok_or(Error::IntegerOverflow)
+                } else {
+                    left.checked_rem(right).ok_or(Error::IntegerOverflow)
+                }
+            }
+            EExp => {
+                if right < 0 {
+                    // Only 1 and -1 have integral negative powers:
+                    return match left {
+                        1 => Ok(1),
+                        -1 => Ok(if right % 2 == 0 { 1 } else { -1 }),
+                        _ => Err(Error::NotAnInteger(format!("{}^{}", left, right))),
+                    };
+                }
+                match u32::try_from(right) {
+                    Ok(power) => left.checked_pow(power).ok_or(Error::IntegerOverflow),
+                    // Any base other than -1, 0, and 1 would overflow:
+                    Err(_) => match left {
+                        0 | 1 => Ok(left),
+                        -1 => Ok(if right % 2 == 0 { 1 } else { -1 }),
+                        _ => Err(Error::IntegerOverflow),
+                    },
+                }
+            }
+            ELT => Ok(bool_to_i64(left < right)),
+            ELTE => Ok(bool_to_i64(left <= right)),
+            EEQ => Ok(bool_to_i64(left == right)),
+            ENE => Ok(bool_to_i64(left != right)),
+            EGTE => Ok(bool_to_i64(left >= right)),
+            EGT => Ok(bool_to_i64(left > right)),
+            EOR => Ok(if left != 0 { left } else { right }),
+            EAND => Ok(if left == 0 { left } else { right }),
+        }
+    }
+}
+
+impl IntEvaler for Expression {
+    fn eval_i64(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<i64, Error> {
+        // This follows the same order-of-operations as the f64 evaler.
+        // See Expression::eval() for details.
+        if !self.tuple.is_empty() {
+            return Err(Error::NotAllowed("tuple".to_string()));
+        }
+        let mut vals = Vec::<i64>::with_capacity(self.pairs.len() + 1);
+        let mut ops = Vec::<BinaryOp>::with_capacity(self.pairs.len());
+        vals.push(self.first.eval_i64(slab, ns)?);
+        for pair in self.pairs.iter() {
+            ns.tick()?;
+            ops.push(pair.0);
+            vals.push(pair.1.eval_i64(slab, ns)?);
+        }
+
+        fn eval_op(vals: &mut Vec<i64>, ops: &mut Vec<BinaryOp>, i: usize) -> Result<(), Error> {
+            let (op, left, right) = match (ops.get(i), vals.get(i), vals.get(i + 1)) {
+                (Some(op), Some(left), Some(right)) => (*op, *left, *right),
+                _ => return Err(Error::Unreachable),
+            };
+            let res = op.binaryop_eval_i64(left, right)?;
+            if let Some(val_ref) = vals.get_mut(i) {
+                *val_ref = res;
+            }
+            remove_no_panic(vals, i + 1);
+            remove_no_panic(ops, i);
+            Ok(())
+        }
+        fn rtol(
+            vals: &mut Vec<i64>,
+            ops: &mut Vec<BinaryOp>,
+            search: BinaryOp,
+        ) -> Result<(), Error> {
+            for i in (0..ops.len()).rev() {
+                if ops.get(i) == Some(&search) {
+                    eval_op(vals, ops, i)?;
+                }
+            }
+            Ok(())
+        }
+        fn ltor(
+            vals: &mut Vec<i64>,
+            ops: &mut Vec<BinaryOp>,
+            search: &[BinaryOp],
+        ) -> Result<(), Error> {
+            let mut i = 0;
+            while let Some(op) = ops.get(i) {
+                if search.contains(op) {
+                    eval_op(vals, ops, i)?;
+                } else {
+                    i += 1;
+                }
+            }
+            Ok(())
+        }
+
+        // Keep the order of these statements in-sync with parser.rs BinaryOp priority values:
+        rtol(&mut vals, &mut ops, EExp)?;
+        ltor(&mut vals, &mut ops, &[EMod])?;
+        ltor(&mut vals, &mut ops, &[EDiv])?;
+        rtol(&mut vals, &mut ops, EMul)?;
+        ltor(&mut vals, &mut ops, &[ESub])?;
+        rtol(&mut vals, &mut ops, EAdd)?;
+        ltor(&mut vals, &mut ops, &[ELT, EGT, ELTE, EGTE, EEQ, ENE])?;
+        ltor(&mut vals, &mut ops, &[EAND])?;
+        ltor(&mut vals, &mut ops, &[EOR])?;
+
+        if !ops.is_empty() || vals.len() != 1 {
+            return Err(Error::Unreachable);
+        }
+        match vals.first() {
+            Some(val) => Ok(*val),
+            None => Err(Error::Unreachable),
+        }
+    }
+}
+
+impl IntEvaler for Value {
+    fn eval_i64(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<i64, Error> {
+        match self {
+            EConstant(c) => to_i64(*c),
+            EUnaryOp(u) => u.eval_i64(slab, ns),
+            EStdFunc(f) => f.eval_i64(slab, ns),
+            EPrintFunc(f) => to_i64(f.eval(slab, ns)?),
+            EError(err) => Err(err.clone()),
+        }
+    }
+}
+
+impl IntEvaler for UnaryOp {
+    fn eval_i64(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<i64, Error> {
+        match self {
+            EPos(val_i) => get_val!(slab.ps, val_i).eval_i64(slab, ns),
+            ENeg(val_i) => get_val!(slab.ps, val_i)
+                .eval_i64(slab, ns)?
+                .checked_neg()
+                .ok_or(Error::IntegerOverflow),
+            ENot(val_i) => Ok(bool_to_i64(
+                get_val!(slab.ps, val_i).eval_i64(slab, ns)? == 0,
+            )),
+            EParentheses(expr_i) => get_expr!(slab.ps, expr_i).eval_i64(slab, ns),
+        }
+    }
+}
+
+impl IntEvaler for StdFunc {
+    fn eval_i64(&self, slab: &Slab, ns: &mut impl EvalNamespace) -> Result<i64, Error> {
+        ns.tick()?;
+        match self {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { ptr, .. } => to_i64(unsafe { **ptr }),
+
+            EVar(name) => match ns.try_lookup(name, Vec::new(), &mut String::new())? {
+                Some(f) => to_i64(f),
+                None => match named_constant(name) {
+                    Some(f) => to_i64(f),
+                    None => Err(Error::UndefinedVariable {
+                        name: name.to_string(),
+                    }),
+                },
+            },
+            EFunc { name, args: xis } => {
+                let mut args = Vec::with_capacity(xis.len());
+                for xi in xis {
+                    args.push(get_expr!(slab.ps, xi).eval_i64(slab, ns)? as f64)
+                }
+                match ns.try_lookup(name, args, &mut String::new())? {
+                    Some(f) => to_i64(f),
+                    None => Err(Error::UndefinedFunction {
+                        name: name.to_string(),
+                        arg_count: xis.len(),
+                    }),
+                }
+            }
+
+            // Integers are already rounded:
+            EFuncInt(expr_i) | EFuncCeil(expr_i) | EFuncFloor(expr_i) => {
+                get_expr!(slab.ps, expr_i).eval_i64(slab, ns)
+            }
+            EFuncRound {
+                modulus: None,
+                expr: expr_i,
+            } => get_expr!(slab.ps, expr_i).eval_i64(slab, ns),
+            EFuncRound {
+                modulus: Some(m_expr_i),
+                expr: expr_i,
+            } => {
+                let modulus = get_expr!(slab.ps, m_expr_i).eval_i64(slab, ns)?;
+                let x = get_expr!(slab.ps, expr_i).eval_i64(slab, ns)?;
+                if modulus == 0 {
+                    return Err(Error::DivideByZero);
+                }
+                // Round half away from zero, like f64::round():
+                let mut quotient = x.checked_div(modulus).ok_or(Error::IntegerOverflow)?;
+                let rem = x % modulus;
+                if rem.unsigned_abs() >= modulus.unsigned_abs() - rem.unsigned_abs() {
+                    if (rem < 0) != (modulus < 0) {
+                        quotient -= 1;
+                    } else {
+                        quotient += 1;
+                    }
+                }
+                quotient.checked_mul(modulus).ok_or(Error::IntegerOverflow)
+            }
+
+            EFuncAbs(expr_i) => get_expr!(slab.ps, expr_i)
+                .eval_i64(slab, ns)?
+                .checked_abs()
+                .ok_or(Error::IntegerOverflow),
+            EFuncSign(expr_i) => Ok(get_expr!(slab.ps, expr_i).eval_i64(slab, ns)?.signum()),
+            EFuncMin {
+                first: first_i,
+                rest,
+            } => {
+                let mut min = get_expr!(slab.ps, first_i).eval_i64(slab, ns)?;
+                for x_i in rest.iter() {
+                    min = min.min(get_expr!(slab.ps, x_i).eval_i64(slab, ns)?);
+                }
+                Ok(min)
+            }
+            EFuncMax {
+                first: first_i,
+                rest,
+            } => {
+                let mut max = get_expr!(slab.ps, first_i).eval_i64(slab, ns)?;
+                for x_i in rest.iter() {
+                    max = max.max(get_expr!(slab.ps, x_i).eval_i64(slab, ns)?);
+                }
+                Ok(max)
+            }
+            EFuncPiecewise { branches, default } => {
+                for (cond_i, val_i) in branches {
+                    if get_expr!(slab.ps, cond_i).eval_i64(slab, ns)? != 0 {
+                        return get_expr!(slab.ps, val_i).eval_i64(slab, ns);
+                    }
+                }
+                get_expr!(slab.ps, default).eval_i64(slab, ns)
+            }
+
+            EFuncE => Err(Error::NotAnInteger("e()".to_string())),
+            EFuncPi => Err(Error::NotAnInteger("pi()".to_string())),
+
+            // Everything else has no integer definition, so calculate with f64:
+            _ => to_i64(self.eval(slab, ns)?),
+        }
+    }
+}
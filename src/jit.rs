@@ -0,0 +1,716 @@
+//! Native code generation for compiled expressions, using Cranelift.
+//!
+//! This module only exists if the `jit` feature is enabled (`cargo build
+//! --features jit`).
+//!
+//! A [`JitExpression`](struct.JitExpression.html) lowers a compiled
+//! `Instruction` into machine code for the host CPU.  Variables are bound to
+//! slots of a caller-provided `&[f64]` when the expression is built, so
+//! evaluation doesn't need a Namespace at all: it is a single call to native
+//! code that reads the slots that it needs.  Arithmetic, comparisons, logic,
+//! `int`/`ceil`/`floor`/`abs`/`sign`/`sqrt`, `min`/`max`, `sumsq`/`norm`,
+//! `coalesce()`, `piecewise()`, `in` and `let` bindings are emitted inline; `^`, `%`,
+//! `quotient()`, `log`, `round`, `fma` and the trigonometric functions call
+//! small Rust shims, so the results are bit-for-bit the same as the
+//! interpreter's.
+//!
+//! A few features can't be lowered (`sum()`, `interp()`, `print()`, error
+//! placeholders and Unsafe Variables).  Expressions that use them still work:
+//! they are evaluated by the normal interpreter, with a Namespace that reads the
+//! same slots.  Use [`is_native()`](struct.JitExpression.html#method.is_native)
+//! and [`fallback_reason()`](struct.JitExpression.html#method.fallback_reason)
+//! to find out which path an expression takes.
+//!
+//! JIT compilation costs tens of microseconds per expression, so it only pays
+//! off for expressions that are evaluated many times.
+//!
+//! # Examples
+//!
+//! ```
+//! use fasteval2::JitExpression;
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let jit = JitExpression::compile("x * x + y", &["x", "y"])?;
+//!     assert!(jit.is_native());
+//!     assert_eq!(jit.eval(&[3.0, 1.0]), 10.0);
+//!     assert_eq!(jit.eval(&[4.0, 0.5]), 16.5);
+//!
+//!     // Names that aren't bound to a slot are an error at build time:
+//!     assert!(JitExpression::compile("x + z", &["x"]).is_err());
+//!
+//!     // Unsupported features fall back to the interpreter:
+//!     let jit = JitExpression::compile("sum(i, 1, n, i)", &["n"])?;
+//!     assert_eq!(jit.fallback_reason(), Some("sum() is not supported by the JIT"));
+//!     assert_eq!(jit.eval(&[4.0]), 10.0);
+//!     Ok(())
+//! }
+//! ```
+
+#[cfg(feature = "unsafe-vars")]
+use crate::compiler::Instruction::IUnsafeVar;
+use crate::compiler::{
+    Compiler,
+    Instruction::{
+        self, IAdd, IConst, IError, IExp, IFunc, IFuncACos, IFuncACosH, IFuncASin, IFuncASinH,
+        IFuncATan, IFuncATanH, IFuncAbs, IFuncCeil, IFuncCoalesce, IFuncCos, IFuncCosH, IFuncFloor,
+        IFuncFma, IFuncIn, IFuncInt, IFuncInterp, IFuncLet, IFuncLog, IFuncMax, IFuncMin,
+        IFuncNorm, IFuncPiecewise, IFuncQuotient, IFuncRound, IFuncSign, IFuncSin, IFuncSinH,
+        IFuncSqrt, IFuncSum, IFuncTan, IFuncTanH, IInv, IMod, IMul, INeg, INot, IPrintFunc, ITemp,
+        ITemps, IVar, IAND, IEQ, IGT, IGTE, ILT, ILTE, INE, IOR,
+    },
+    InstructionI, IC,
+};
+use crate::error::Error;
+use crate::evaler::{named_constant, Evaler};
+use crate::evalns::EmptyNamespace;
+use crate::parser::Parser;
+use crate::slab::{CompileSlab, Slab};
+
+use cranelift_codegen::ir::{
+    condcodes::FloatCC, types, AbiParam, InstBuilder, MemFlags, SigRef, Signature, Type, Value,
+};
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+type NativeFn = extern "C" fn(*const f64) -> f64;
+
+/// A compiled expression that has been lowered to native code.
+///
+/// [See the `jit module` documentation.](index.html)
+pub struct JitExpression {
+    slab: Slab,
+    instr: Instruction,
+    slots: Vec<String>,
+    native: Option<Native>,
+    fallback_reason: Option<String>,
+}
+
+struct Native {
+    func: NativeFn,
+    // Owns the memory that `func` points into:
+    module: Option<JITModule>,
+}
+
+impl Drop for Native {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: `func` is dropped together with the module, so nothing
+            // can call into the freed code.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+impl fmt::Debug for JitExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JitExpression")
+            .field("instr", &self.instr)
+            .field("slots", &self.slots)
+            .field("native", &self.native.is_some())
+            .field("fallback_reason", &self.fallback_reason)
+            .finish()
+    }
+}
+
+impl JitExpression {
+    /// Parses, compiles and JIT-compiles `expr_str`.
+    ///
+    /// Variable `vars[i]` is read from slot `i` of the slice passed to
+    /// [`eval()`](#method.eval).  Using any other name (except for the named
+    /// constants like `phi`) is an `UndefinedVariable` error.  Custom
+    /// functions can't be used, because there is no Namespace to call them, so
+    /// calling one is an `UndefinedFunction` error.
+    pub fn compile(expr_str: &str, vars: &[&str]) -> Result<Self, Error> {
+        let mut slab = Slab::new();
+        let instr = Parser::new()
+            .parse(expr_str, &mut slab.ps)?
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        Self::new(instr, slab, vars)
+    }
+
+    /// JIT-compiles an `Instruction` that was compiled into `slab`.
+    ///
+    /// [See `compile()`](#method.compile) for how `vars` is used.
+    pub fn new(instr: Instruction, slab: Slab, vars: &[&str]) -> Result<Self, Error> {
+        let slots: Vec<String> = vars.iter().map(|v| v.to_string()).collect();
+        // `var_names()` also sees into the parts that aren't lowered, like `sum()` bodies:
+        for name in instr.var_names(&slab) {
+            if !slots.contains(&name) && named_constant(&name).is_none() {
+                return Err(undefined(name, &instr, &slab.cs));
+            }
+        }
+
+        let (native, fallback_reason) = match lower(&instr, &slab.cs, &slots)? {
+            Ok(native) => (Some(native), None),
+            Err(reason) => (None, Some(reason)),
+        };
+        Ok(Self {
+            slab,
+            instr,
+            slots,
+            native,
+            fallback_reason,
+        })
+    }
+
+    /// Evaluates the expression.  `vars[i]` is the value of the `i`th variable
+    /// that was passed at build time.
+    ///
+    /// Returns NaN if `vars` has fewer slots than were bound, or if the
+    /// interpreter fallback produces an error.
+    #[inline]
+    pub fn eval(&self, vars: &[f64]) -> f64 {
+        if vars.len() < self.slots.len() {
+            return f64::NAN;
+        }
+        match &self.native {
+            // The native code only reads slots below `self.slots.len()`:
+            Some(native) => (native.func)(vars.as_ptr()),
+            None => self.interpret(vars).unwrap_or(f64::NAN),
+        }
+    }
+
+    fn interpret(&self, vars: &[f64]) -> Result<f64, Error> {
+        let mut ns = |name: &str, args: Vec<f64>| {
+            if !args.is_empty() {
+                return None;
+            }
+            self.slots.iter().position(|s| s == name).map(|i| vars[i])
+        };
+        self.instr.eval(&self.slab, &mut ns)
+    }
+
+    /// Returns `true` if the expression runs as native code, or `false` if it
+    /// falls back to the interpreter.
+    #[inline]
+    pub fn is_native(&self) -> bool {
+        self.native.is_some()
+    }
+
+    /// Explains why the expression falls back to the interpreter, or returns
+    /// `None` if it runs as native code.
+    #[inline]
+    pub fn fallback_reason(&self) -> Option<&str> {
+        self.fallback_reason.as_deref()
+    }
+
+    /// Returns the variable names, in slot order.
+    #[inline]
+    pub fn vars(&self) -> &[String] {
+        &self.slots
+    }
+}
+
+// The error for a name that isn't a slot, depending on whether it's called with arguments:
+fn undefined(name: String, instr: &Instruction, cs: &CompileSlab) -> Error {
+    let call = cs.instrs.iter().chain(Some(instr)).find_map(|i| match i {
+        IFunc { name: n, args } if *n == name && !args.is_empty() => Some(args.len()),
+        _ => None,
+    });
+    match call {
+        Some(arg_count) => Error::UndefinedFunction { name, arg_count },
+        None => Error::UndefinedVariable { name },
+    }
+}
+
+// Returns the native code, or the reason why the interpreter must be used.
+// Errors in the expression itself (the outer Result) are reported either way.
+fn lower(
+    instr: &Instruction,
+    cs: &CompileSlab,
+    slots: &[String],
+) -> Result<Result<Native, String>, Error> {
+    let mut module = match new_module() {
+        Ok(module) => module,
+        Err(reason) => {
+            // Still check the expression, so errors don't depend on the host:
+            let mut ctx = cranelift_codegen::Context::new();
+            emit(
+                &mut ctx,
+                instr,
+                cs,
+                slots,
+                types::I64,
+                Signature::new(CallConv::SystemV),
+            )?;
+            return Ok(Err(reason));
+        }
+    };
+
+    let ptr_ty = module.target_config().pointer_type();
+    let mut ctx = module.make_context();
+    let shim_sig = Signature::new(module.isa().default_call_conv());
+    if let Some(reason) = emit(&mut ctx, instr, cs, slots, ptr_ty, shim_sig)? {
+        return Ok(Err(format!("{} is not supported by the JIT", reason)));
+    }
+
+    let id = match module.declare_anonymous_function(&ctx.func.signature) {
+        Ok(id) => id,
+        Err(err) => return Ok(Err(format!("cranelift: {}", err))),
+    };
+    if let Err(err) = module.define_function(id, &mut ctx) {
+        return Ok(Err(format!("cranelift: {}", err)));
+    }
+    module.clear_context(&mut ctx);
+    if let Err(err) = module.finalize_definitions() {
+        return Ok(Err(format!("cranelift: {}", err)));
+    }
+    let code = module.get_finalized_function(id);
+    // SAFETY: the function was built with the `(pointer) -> f64` signature
+    // of the host's default calling convention, which is "C".
+    let func = unsafe { std::mem::transmute::<*const u8, NativeFn>(code) };
+    Ok(Ok(Native {
+        func,
+        module: Some(module),
+    }))
+}
+
+fn new_module() -> Result<JITModule, String> {
+    let mut flags = settings::builder();
+    for (name, value) in [
+        ("opt_level", "speed"),
+        ("use_colocated_libcalls", "false"),
+        ("is_pic", "false"),
+    ] {
+        flags
+            .set(name, value)
+            .map_err(|err| format!("cranelift: {}", err))?;
+    }
+    let isa = cranelift_native::builder()
+        .map_err(|msg| format!("cranelift: {}", msg))?
+        .finish(settings::Flags::new(flags))
+        .map_err(|err| format!("cranelift: {}", err))?;
+    Ok(JITModule::new(JITBuilder::with_isa(
+        isa,
+        default_libcall_names(),
+    )))
+}
+
+// Builds `fn(vars: *const f64) -> f64` into `ctx.func`.  Returns the first
+// unsupported feature, if any.
+fn emit(
+    ctx: &mut cranelift_codegen::Context,
+    instr: &Instruction,
+    cs: &CompileSlab,
+    slots: &[String],
+    ptr_ty: Type,
+    shim_sig: Signature,
+) -> Result<Option<&'static str>, Error> {
+    ctx.func.signature.params.push(AbiParam::new(ptr_ty));
+    ctx.func.signature.returns.push(AbiParam::new(types::F64));
+    let mut fbc = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut fbc);
+    let block = b.create_block();
+    b.append_block_params_for_function_params(block);
+    b.switch_to_block(block);
+    b.seal_block(block);
+    let vars = b.block_params(block)[0];
+
+    let mut unary = shim_sig.clone();
+    unary.params.push(AbiParam::new(types::F64));
+    unary.returns.push(AbiParam::new(types::F64));
+    let mut binary = unary.clone();
+    binary.params.push(AbiParam::new(types::F64));
+    let mut ternary = binary.clone();
+    ternary.params.push(AbiParam::new(types::F64));
+    let unary = b.import_signature(unary);
+    let binary = b.import_signature(binary);
+    let ternary = b.import_signature(ternary);
+
+    let mut lw = Lowerer {
+        b,
+        cs,
+        slots,
+        vars,
+        ptr_ty,
+        unary,
+        binary,
+        ternary,
+        temps: BTreeMap::new(),
+        lets: Vec::new(),
+        unsupported: None,
+    };
+    let result = lw.instr(instr)?;
+    lw.b.ins().return_(&[result]);
+    lw.b.finalize();
+    Ok(lw.unsupported)
+}
+
+struct Lowerer<'a, 'f> {
+    b: FunctionBuilder<'f>,
+    cs: &'a CompileSlab,
+    slots: &'a [String],
+    vars: Value,
+    ptr_ty: Type,
+    unary: SigRef,
+    binary: SigRef,
+    ternary: SigRef,
+    // The values of shared sub-expressions, by Instruction index:
+    temps: BTreeMap<usize, Value>,
+    // The values of the enclosing `let` bindings, innermost last:
+    lets: Vec<(String, Value)>,
+    unsupported: Option<&'static str>,
+}
+
+impl Lowerer<'_, '_> {
+    fn ic(&mut self, ic: &IC) -> Result<Value, Error> {
+        match ic {
+            IC::C(c) => Ok(self.b.ins().f64const(*c)),
+            IC::I(i) => self.ii(*i),
+        }
+    }
+
+    fn ii(&mut self, i: InstructionI) -> Result<Value, Error> {
+        let cs = self.cs;
+        self.instr(cs.get_instr(i))
+    }
+
+    fn instr(&mut self, instr: &Instruction) -> Result<Value, Error> {
+        Ok(match instr {
+            IConst(c) => self.b.ins().f64const(*c),
+
+            INeg(i) => {
+                let x = self.ii(*i)?;
+                self.b.ins().fneg(x)
+            }
+            INot(i) => {
+                let x = self.ii(*i)?;
+                let c = self.is_zero(x);
+                self.bool_to_f64(c)
+            }
+            IInv(i) => {
+                let x = self.ii(*i)?;
+                let one = self.b.ins().f64const(1.0);
+                self.b.ins().fdiv(one, x)
+            }
+
+            IAdd(li, ric) => {
+                let (l, r) = (self.ii(*li)?, self.ic(ric)?);
+                self.b.ins().fadd(l, r)
+            }
+            IMul(li, ric) => {
+                let (l, r) = (self.ii(*li)?, self.ic(ric)?);
+                self.b.ins().fmul(l, r)
+            }
+            IMod { dividend, divisor } => {
+                let (l, r) = (self.ic(dividend)?, self.ic(divisor)?);
+                self.call2(shims::fmod, l, r)
+            }
+            IFuncQuotient { dividend, divisor } => {
+                let (l, r) = (self.ic(dividend)?, self.ic(divisor)?);
+                self.call2(shims::quotient, l, r)
+            }
+            IExp { base, power } => {
+                let (l, r) = (self.ic(base)?, self.ic(power)?);
+                self.call2(shims::powf, l, r)
+            }
+
+            ILT(lic, ric) => self.compare(FloatCC::LessThan, lic, ric)?,
+            ILTE(lic, ric) => self.compare(FloatCC::LessThanOrEqual, lic, ric)?,
+            IGTE(lic, ric) => self.compare(FloatCC::GreaterThanOrEqual, lic, ric)?,
+            IGT(lic, ric) => self.compare(FloatCC::GreaterThan, lic, ric)?,
+            IEQ(lic, ric) | INE(lic, ric) => {
+                let (l, r) = (self.ic(lic)?, self.ic(ric)?);
+                let diff = self.b.ins().fsub(l, r);
+                let c = if let IEQ(..) = instr {
+                    self.is_zero(diff)
+                } else {
+                    self.is_nonzero(diff)
+                };
+                self.bool_to_f64(c)
+            }
+
+            IOR(li, ric) | IAND(li, ric) => {
+                let (l, r) = (self.ii(*li)?, self.ic(ric)?);
+                let keep_left = if let IOR(..) = instr {
+                    self.is_nonzero(l)
+                } else {
+                    self.is_zero(l)
+                };
+                self.b.ins().select(keep_left, l, r)
+            }
+
+            IVar(name) => self.var(name)?,
+            IFunc { name, args } => {
+                if !args.is_empty() {
+                    return Err(Error::UndefinedFunction {
+                        name: name.clone(),
+                        arg_count: args.len(),
+                    });
+                }
+                self.var(name)?
+            }
+
+            IFuncInt(i) => {
+                let x = self.ii(*i)?;
+                self.b.ins().trunc(x)
+            }
+            IFuncCeil(i) => {
+                let x = self.ii(*i)?;
+                self.b.ins().ceil(x)
+            }
+            IFuncFloor(i) => {
+                let x = self.ii(*i)?;
+                self.b.ins().floor(x)
+            }
+            IFuncAbs(i) => {
+                let x = self.ii(*i)?;
+                self.b.ins().fabs(x)
+            }
+            IFuncSign(i) => {
+                // Like f64::signum(): NaN stays NaN, and zeros keep their sign.
+                let x = self.ii(*i)?;
+                let one = self.b.ins().f64const(1.0);
+                let sign = self.b.ins().fcopysign(one, x);
+                let nan = self.b.ins().fcmp(FloatCC::Unordered, x, x);
+                self.b.ins().select(nan, x, sign)
+            }
+            IFuncSqrt(i) => {
+                let x = self.ii(*i)?;
+                self.b.ins().sqrt(x)
+            }
+            IFuncLog { base, of } => {
+                let (l, r) = (self.ic(base)?, self.ic(of)?);
+                self.call2(shims::log, l, r)
+            }
+            IFuncRound { modulus, of } => {
+                let (l, r) = (self.ic(modulus)?, self.ic(of)?);
+                self.call2(shims::round, l, r)
+            }
+            IFuncFma { a, b, c } => {
+                let (a, b, c) = (self.ic(a)?, self.ic(b)?, self.ic(c)?);
+                let callee = self.shim_addr(shims::fma as *const ());
+                let call = self.b.ins().call_indirect(self.ternary, callee, &[a, b, c]);
+                self.b.inst_results(call)[0]
+            }
+            IFuncCoalesce { value, fallback } => {
+                // |value| < inf is false for both infinities and NaN:
+                let (v, f) = (self.ic(value)?, self.ic(fallback)?);
+                let abs = self.b.ins().fabs(v);
+                let inf = self.b.ins().f64const(f64::INFINITY);
+                let finite = self.b.ins().fcmp(FloatCC::LessThan, abs, inf);
+                self.b.ins().select(finite, v, f)
+            }
+            IFuncNorm { squared, args } => {
+                // The same sum as SumSq, so the results are identical:
+                let mut sum = self.b.ins().f64const(0.0);
+                let mut last = sum;
+                for arg in args {
+                    last = self.ic(arg)?;
+                    let sq = self.b.ins().fmul(last, last);
+                    sum = self.b.ins().fadd(sum, sq);
+                }
+                match (*squared, args.len()) {
+                    (true, _) => sum,
+                    (false, 1) => self.b.ins().fabs(last),
+                    (false, _) => self.b.ins().sqrt(sum),
+                }
+            }
+            IFuncIn {
+                negated,
+                value,
+                list,
+            } => {
+                // Like piecewise(), every item is evaluated:
+                let v = self.ic(value)?;
+                let (hit, miss) = if *negated { (0.0, 1.0) } else { (1.0, 0.0) };
+                let hit = self.b.ins().f64const(hit);
+                let mut out = self.b.ins().f64const(miss);
+                for item in list {
+                    let x = self.ic(item)?;
+                    let diff = self.b.ins().fsub(v, x);
+                    let c = self.is_zero(diff);
+                    out = self.b.ins().select(c, hit, out);
+                }
+                out
+            }
+            IFuncMin(li, ric) | IFuncMax(li, ric) => {
+                let (l, r) = (self.ii(*li)?, self.ic(ric)?);
+                let cc = if let IFuncMin(..) = instr {
+                    FloatCC::LessThan
+                } else {
+                    FloatCC::GreaterThan
+                };
+                let pick_left = self.b.ins().fcmp(cc, l, r);
+                let picked = self.b.ins().select(pick_left, l, r);
+                let nan = self.b.ins().fcmp(FloatCC::Unordered, l, r);
+                let nan_val = self.b.ins().f64const(f64::NAN);
+                self.b.ins().select(nan, nan_val, picked)
+            }
+            IFuncPiecewise { branches, default } => {
+                // Every branch is pure, so it's fine to evaluate them all and
+                // select from the last branch back to the first:
+                let mut out = self.ic(default)?;
+                let mut vals = Vec::with_capacity(branches.len());
+                for (cond_ic, val_ic) in branches {
+                    vals.push((self.ic(cond_ic)?, self.ic(val_ic)?));
+                }
+                for (cond, val) in vals.into_iter().rev() {
+                    let c = self.is_nonzero(cond);
+                    out = self.b.ins().select(c, val, out);
+                }
+                out
+            }
+
+            IFuncSin(i) => self.call1(shims::sin, *i)?,
+            IFuncCos(i) => self.call1(shims::cos, *i)?,
+            IFuncTan(i) => self.call1(shims::tan, *i)?,
+            IFuncASin(i) => self.call1(shims::asin, *i)?,
+            IFuncACos(i) => self.call1(shims::acos, *i)?,
+            IFuncATan(i) => self.call1(shims::atan, *i)?,
+            IFuncSinH(i) => self.call1(shims::sinh, *i)?,
+            IFuncCosH(i) => self.call1(shims::cosh, *i)?,
+            IFuncTanH(i) => self.call1(shims::tanh, *i)?,
+            IFuncASinH(i) => self.call1(shims::asinh, *i)?,
+            IFuncACosH(i) => self.call1(shims::acosh, *i)?,
+            IFuncATanH(i) => self.call1(shims::atanh, *i)?,
+
+            // The body of a sum() uses the loop variable, which has no slot.
+            // Its names were already checked by the caller.
+            IFuncSum { from, to, .. } => {
+                self.ic(from)?;
+                self.ic(to)?;
+                self.unsupported("sum()")
+            }
+            IFuncLet { var, value, expr } => {
+                let value = self.ic(value)?;
+                self.lets.push((var.clone(), value));
+                let result = self.ic(expr);
+                self.lets.pop();
+                result?
+            }
+            IFuncInterp { x, points, .. } => {
+                self.ic(x)?;
+                for (px, py) in points {
+                    self.ic(px)?;
+                    self.ic(py)?;
+                }
+                self.unsupported("interp()")
+            }
+            // Everything is emitted into a single block, so a shared value
+            // is available to every use after the first one:
+            ITemps { body, .. } => self.ii(*body)?,
+            ITemp { instr, .. } => match self.temps.get(&instr.0) {
+                Some(val) => *val,
+                None => {
+                    let val = self.ii(*instr)?;
+                    self.temps.insert(instr.0, val);
+                    val
+                }
+            },
+            IPrintFunc(_) => self.unsupported("print()"),
+            IError(_) => self.unsupported("an error placeholder"),
+            #[cfg(feature = "unsafe-vars")]
+            IUnsafeVar { .. } => self.unsupported("an Unsafe Variable"),
+        })
+    }
+
+    fn var(&mut self, name: &str) -> Result<Value, Error> {
+        if let Some((_, val)) = self.lets.iter().rev().find(|(var, _)| var == name) {
+            return Ok(*val);
+        }
+        if let Some(slot) = self.slots.iter().position(|s| s == name) {
+            let offset = i32::try_from(slot * std::mem::size_of::<f64>())
+                .map_err(|_| Error::TooManyVars(self.slots.len()))?;
+            return Ok(self
+                .b
+                .ins()
+                .load(types::F64, MemFlags::trusted(), self.vars, offset));
+        }
+        match named_constant(name) {
+            Some(c) => Ok(self.b.ins().f64const(c)),
+            None => Err(Error::UndefinedVariable {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    fn compare(&mut self, cc: FloatCC, lic: &IC, ric: &IC) -> Result<Value, Error> {
+        let (l, r) = (self.ic(lic)?, self.ic(ric)?);
+        let c = self.b.ins().fcmp(cc, l, r);
+        Ok(self.bool_to_f64(c))
+    }
+
+    // `f64_eq!(x, 0.0)`.  False for NaN.
+    fn is_zero(&mut self, x: Value) -> Value {
+        let abs = self.b.ins().fabs(x);
+        let eps = self.b.ins().f64const(8.0 * f64::EPSILON);
+        self.b.ins().fcmp(FloatCC::LessThanOrEqual, abs, eps)
+    }
+
+    // `f64_ne!(x, 0.0)`.  Also false for NaN.
+    fn is_nonzero(&mut self, x: Value) -> Value {
+        let abs = self.b.ins().fabs(x);
+        let eps = self.b.ins().f64const(8.0 * f64::EPSILON);
+        self.b.ins().fcmp(FloatCC::GreaterThan, abs, eps)
+    }
+
+    fn bool_to_f64(&mut self, c: Value) -> Value {
+        let one = self.b.ins().f64const(1.0);
+        let zero = self.b.ins().f64const(0.0);
+        self.b.ins().select(c, one, zero)
+    }
+
+    fn call1(&mut self, f: extern "C" fn(f64) -> f64, i: InstructionI) -> Result<Value, Error> {
+        let x = self.ii(i)?;
+        let callee = self.shim_addr(f as *const ());
+        let call = self.b.ins().call_indirect(self.unary, callee, &[x]);
+        Ok(self.b.inst_results(call)[0])
+    }
+
+    fn call2(&mut self, f: extern "C" fn(f64, f64) -> f64, l: Value, r: Value) -> Value {
+        let callee = self.shim_addr(f as *const ());
+        let call = self.b.ins().call_indirect(self.binary, callee, &[l, r]);
+        self.b.inst_results(call)[0]
+    }
+
+    fn shim_addr(&mut self, f: *const ()) -> Value {
+        self.b.ins().iconst(self.ptr_ty, f as usize as i64)
+    }
+
+    // Records the first unsupported feature and emits a dummy value, so the
+    // rest of the expression is still checked for errors.
+    fn unsupported(&mut self, what: &'static str) -> Value {
+        self.unsupported.get_or_insert(what);
+        self.b.ins().f64const(f64::NAN)
+    }
+}
+
+// The native code calls these for operations that Cranelift doesn't have
+// instructions for.  They must match the interpreter exactly.
+mod shims {
+    macro_rules! unary_shims {
+        ($($name:ident),*) => {
+            $(pub(super) extern "C" fn $name(x: f64) -> f64 {
+                x.$name()
+            })*
+        };
+    }
+    unary_shims!(sin, cos, tan, asin, acos, atan, sinh, cosh, tanh, asinh, acosh, atanh);
+
+    pub(super) extern "C" fn powf(base: f64, power: f64) -> f64 {
+        base.powf(power)
+    }
+    pub(super) extern "C" fn fmod(dividend: f64, divisor: f64) -> f64 {
+        dividend % divisor
+    }
+    pub(super) extern "C" fn quotient(dividend: f64, divisor: f64) -> f64 {
+        crate::compiler::quotient(dividend, divisor)
+    }
+    pub(super) extern "C" fn log(base: f64, of: f64) -> f64 {
+        crate::compiler::log(base, of)
+    }
+    pub(super) extern "C" fn round(modulus: f64, of: f64) -> f64 {
+        (of / modulus).round() * modulus
+    }
+    pub(super) extern "C" fn fma(a: f64, b: f64, c: f64) -> f64 {
+        a.mul_add(b, c)
+    }
+}
@@ -0,0 +1,503 @@
+//! Renders parsed expressions as LaTeX math.
+//!
+//! [`Expression::to_latex()`](../parser/struct.Expression.html#method.to_latex)
+//! typesets an expression for documents and reports:
+//!
+//! ```
+//! use fasteval2::{Latex, Parser, Slab};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = Slab::new();
+//!     let expr = Parser::new().parse("a/(b+c)^2 * sin(x)", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(expr.to_latex(&slab), r"\frac{a}{(b + c)^{2}} \cdot \sin(x)");
+//!     assert_eq!(Latex::new().implicit_mul().render(expr, &slab), r"\frac{a}{(b + c)^{2}} \sin(x)");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! `/` becomes a `\frac`, `^` a superscript, `%` a `\bmod`, and `*` a `\cdot`,
+//! or juxtaposition with [`Latex::implicit_mul()`](struct.Latex.html#method.implicit_mul).
+//! Comparisons and logical operators become `<`, `\le`, `=`, `\ne`, `\land`,
+//! `\lnot`, etc., and `x in (1, 2)` becomes `x \in \{1, 2\}`.  Functions that
+//! LaTeX knows, like `sin` and `log`, become `\sin` and `\log` (`asin` becomes
+//! `\arcsin`), `sqrt`, `abs`, `floor` and `ceil` get their usual notation,
+//! `sum()` becomes a `\sum`, `piecewise()` becomes a `cases` environment, and
+//! `let r = v; body` becomes `body \quad \text{where } r = v`.  Other
+//! functions are written with `\operatorname`.  Variables with one letter are
+//! written as-is, and longer names with `\mathit`.
+//!
+//! Operators are grouped the way that evaluation groups them, like
+//! [`to_simplified_string()`](../simplify/index.html) groups them, and
+//! parentheses are added wherever the typeset form would otherwise be
+//! ambiguous.  Note that unary operators bind tighter than `^`, so `-x^2` is
+//! `(-x)^{2}`.
+
+use crate::evaler::{level_of, OP_PASSES};
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    BinaryOp::{
+        self, EAdd, EDiv, EExp, EMod, EMul, ESub, EAND, EEQ, EGT, EGTE, ELT, ELTE, ENE, EOR,
+    },
+    Expression, ExpressionI,
+    ExpressionOrString::{EExpr, EStr},
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::mem;
+
+/// LaTeX rendering options.
+///
+/// [See the `latex module` documentation.](index.html)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Latex {
+    implicit_mul: bool,
+}
+
+impl Latex {
+    /// Renders `*` as `\cdot`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders `*` by juxtaposition, like `2 x` instead of `2 \cdot x`.  A
+    /// `\cdot` is still used before a number or a sign, where juxtaposition
+    /// would change the meaning.
+    pub fn implicit_mul(mut self) -> Self {
+        self.implicit_mul = true;
+        self
+    }
+
+    /// Renders `expr` as LaTeX math, without the surrounding `$`s.
+    ///
+    /// Invalid values from
+    /// [`parse_with_recovery()`](../parser/struct.Parser.html#method.parse_with_recovery)
+    /// are rendered as `\text{?}`.
+    pub fn render(&self, expr: &Expression, slab: &Slab) -> String {
+        let renderer = Renderer { opts: self, slab };
+        let mut text = renderer.expr(expr).text;
+        // The items of a tuple:
+        for item in &expr.tuple {
+            text.push_str(", ");
+            text.push_str(&renderer.expr(get_expr!(slab.ps, item)).text);
+        }
+        text
+    }
+}
+
+impl Expression {
+    /// Renders this `Expression` as LaTeX math, with the default options.
+    /// [See the `latex module` documentation.](../latex/index.html)
+    pub fn to_latex(&self, slab: &Slab) -> String {
+        Latex::new().render(self, slab)
+    }
+}
+
+// How a rendered operand binds, for deciding where parentheses are needed.
+#[derive(Clone, Copy, PartialEq)]
+enum Prec {
+    Atom,      // Variables, numbers, calls, and anything else that is delimited.
+    Frac,      // A `\frac`, which is delimited, unless it is raised to a power.
+    Pow,       // A superscript.
+    Unary,     // A sign or `\lnot`, which bind tighter than `^` in expressions.
+    Mod,       // A `\bmod`, which reads as binding loosely in math.
+    Big,       // A `\sum` or `cases`, which extend to the right.
+    Op(usize), // Any other operation, by operator pass (counting from 1).
+}
+
+struct Tex {
+    text: String,
+    prec: Prec,
+}
+
+impl Tex {
+    fn atom(text: String) -> Self {
+        Tex {
+            text,
+            prec: Prec::Atom,
+        }
+    }
+
+    fn wrap(self, wrap: bool) -> String {
+        if wrap {
+            format!("({})", self.text)
+        } else {
+            self.text
+        }
+    }
+}
+
+struct Renderer<'a> {
+    opts: &'a Latex,
+    slab: &'a Slab,
+}
+
+impl Renderer<'_> {
+    fn expr_i(&self, xi: &ExpressionI) -> Tex {
+        self.expr(get_expr!(self.slab.ps, xi))
+    }
+
+    fn exprs<'x>(&self, xis: impl IntoIterator<Item = &'x ExpressionI>) -> Vec<String> {
+        xis.into_iter().map(|xi| self.expr_i(xi).text).collect()
+    }
+
+    fn expr(&self, expr: &Expression) -> Tex {
+        let mut terms = Vec::with_capacity(expr.pairs.len() + 1);
+        let mut ops = Vec::with_capacity(expr.pairs.len());
+        terms.push(self.value(&expr.first));
+        for pair in &expr.pairs {
+            ops.push(pair.0);
+            terms.push(self.value(&pair.1));
+        }
+
+        // Group the operators the way that eval() does:
+        let reduce = |terms: &mut Vec<Tex>, ops: &mut Vec<BinaryOp>, i, level, rtol| {
+            let right = terms.remove(i + 1);
+            let left = mem::replace(&mut terms[i], Tex::atom(String::new()));
+            terms[i] = self.binary(ops.remove(i), level, rtol, left, right);
+        };
+        for (pass, (search, rtol)) in OP_PASSES.iter().enumerate() {
+            let level = pass + 1;
+            if *rtol {
+                for i in (0..ops.len()).rev() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut terms, &mut ops, i, level, true);
+                    }
+                }
+            } else {
+                let mut i = 0;
+                while i < ops.len() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut terms, &mut ops, i, level, false);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+        terms
+            .pop()
+            .unwrap_or_else(|| Tex::atom(r"\text{?}".to_string()))
+    }
+
+    // Combines two operands of `op`, which is in operator pass `level`:
+    fn binary(&self, op: BinaryOp, level: usize, rtol: bool, left: Tex, right: Tex) -> Tex {
+        match op {
+            EDiv => {
+                return Tex {
+                    text: format!(r"\frac{{{}}}{{{}}}", left.text, right.text),
+                    prec: Prec::Frac,
+                }
+            }
+            EExp => {
+                let base = left.prec != Prec::Atom;
+                return Tex {
+                    text: format!("{}^{{{}}}", left.wrap(base), right.text),
+                    prec: Prec::Pow,
+                };
+            }
+            _ => {}
+        }
+
+        // Operations of the same pass are grouped from one side:
+        let wrap = |prec: Prec, same: bool| match prec {
+            Prec::Atom | Prec::Frac | Prec::Pow | Prec::Unary => false,
+            Prec::Big => true,
+            Prec::Mod => op != EMod || same,
+            Prec::Op(l) => l > level || (l == level && same),
+        };
+        let (left_wrap, right_wrap) = (wrap(left.prec, rtol), wrap(right.prec, !rtol));
+        let (left, right) = (left.wrap(left_wrap), right.wrap(right_wrap));
+        let sym = match op {
+            EMul if self.opts.implicit_mul && !right.starts_with(starts_number) => "",
+            EMul => r" \cdot",
+            EMod => r" \bmod",
+            EAdd => " +",
+            ESub => " -",
+            ELT => " <",
+            ELTE => r" \le",
+            EEQ => " =",
+            ENE => r" \ne",
+            EGTE => r" \ge",
+            EGT => " >",
+            EAND => r" \land",
+            EOR => r" \lor",
+            EDiv | EExp => "",
+        };
+        Tex {
+            text: format!("{}{} {}", left, sym, right),
+            prec: if op == EMod {
+                Prec::Mod
+            } else {
+                Prec::Op(level)
+            },
+        }
+    }
+
+    fn value(&self, value: &Value) -> Tex {
+        // Renders a unary operator:
+        let unary = |sym: &str, tex: Tex| {
+            let wrap = !matches!(tex.prec, Prec::Atom | Prec::Frac | Prec::Pow | Prec::Unary);
+            Tex {
+                text: format!("{}{}", sym, tex.wrap(wrap)),
+                prec: Prec::Unary,
+            }
+        };
+        match value {
+            EConstant(c) => number(*c),
+            EError(_) => Tex::atom(r"\text{?}".to_string()),
+            EUnaryOp(EPos(vi)) => self.value(get_val!(self.slab.ps, vi)),
+            EUnaryOp(ENeg(vi)) => unary("-", self.value(get_val!(self.slab.ps, vi))),
+            EUnaryOp(ENot(vi)) => unary(r"\lnot ", self.value(get_val!(self.slab.ps, vi))),
+            EUnaryOp(EParentheses(xi)) => self.expr_i(xi),
+            EPrintFunc(pf) => {
+                let args =
+                    pf.0.iter()
+                        .map(|x_or_s| match x_or_s {
+                            EExpr(xi) => self.expr_i(xi).text,
+                            EStr(s) => format!(r#"\text{{"{}"}}"#, escape(s)),
+                        })
+                        .collect();
+                call("print", args)
+            }
+            EStdFunc(f) => self.std_func(f),
+        }
+    }
+
+    fn std_func(&self, f: &StdFunc) -> Tex {
+        let (name, args) = match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { name, .. } => return var(name),
+            EVar(name) => return var(name),
+            EFunc { name, args } => (name.as_str(), self.exprs(args)),
+            EFuncSum {
+                var: v,
+                from,
+                to,
+                expr,
+            } => {
+                let body = self.expr_i(expr);
+                // The body extends to the right, so it needs parentheses if it
+                // would read as a sum:
+                let wrap = match body.prec {
+                    Prec::Big | Prec::Mod => true,
+                    Prec::Op(l) => l >= level_of(ESub),
+                    _ => false,
+                };
+                return Tex {
+                    text: format!(
+                        r"\sum_{{{}={}}}^{{{}}} {}",
+                        var(v).text,
+                        self.expr_i(from).text,
+                        self.expr_i(to).text,
+                        body.wrap(wrap)
+                    ),
+                    prec: Prec::Big,
+                };
+            }
+            EFuncLet {
+                var: v,
+                value,
+                expr,
+            } => {
+                return Tex {
+                    text: format!(
+                        r"{} \quad \text{{where }} {} = {}",
+                        self.expr_i(expr).text,
+                        var(v).text,
+                        self.expr_i(value).text
+                    ),
+                    prec: Prec::Big,
+                };
+            }
+            EFuncIn {
+                negated,
+                value,
+                list,
+            } => {
+                let level = level_of(EEQ);
+                let value = self.expr_i(value);
+                let wrap = match value.prec {
+                    Prec::Big | Prec::Mod => true,
+                    Prec::Op(l) => l >= level,
+                    _ => false,
+                };
+                return Tex {
+                    text: format!(
+                        r"{} {} \{{{}\}}",
+                        value.wrap(wrap),
+                        if *negated { r"\notin" } else { r"\in" },
+                        self.exprs(list).join(", ")
+                    ),
+                    prec: Prec::Op(level),
+                };
+            }
+            EFuncPiecewise { branches, default } => {
+                let mut text = r"\begin{cases} ".to_string();
+                for (cond, val) in branches {
+                    text.push_str(&format!(
+                        r"{} & \text{{if }} {} \\ ",
+                        self.expr_i(val).text,
+                        self.expr_i(cond).text
+                    ));
+                }
+                text.push_str(&format!(
+                    r"{} & \text{{otherwise}} \end{{cases}}",
+                    self.expr_i(default).text
+                ));
+                return Tex {
+                    text,
+                    prec: Prec::Big,
+                };
+            }
+
+            EFuncE => return Tex::atom("e".to_string()),
+            EFuncPi => return Tex::atom(r"\pi".to_string()),
+            EFuncSqrt(xi) => return Tex::atom(format!(r"\sqrt{{{}}}", self.expr_i(xi).text)),
+            EFuncAbs(xi) => {
+                return Tex::atom(format!(r"\left|{}\right|", self.expr_i(xi).text));
+            }
+            EFuncFloor(xi) => {
+                return Tex::atom(format!(
+                    r"\left\lfloor {} \right\rfloor",
+                    self.expr_i(xi).text
+                ));
+            }
+            EFuncCeil(xi) => {
+                return Tex::atom(format!(
+                    r"\left\lceil {} \right\rceil",
+                    self.expr_i(xi).text
+                ));
+            }
+            EFuncLog {
+                base: Some(base),
+                expr,
+            } => {
+                return Tex::atom(format!(
+                    r"\log_{{{}}}({})",
+                    self.expr_i(base).text,
+                    self.expr_i(expr).text
+                ));
+            }
+            EFuncLog { base: None, expr } => ("log", self.exprs([expr])),
+
+            EFuncInt(xi) => ("int", self.exprs([xi])),
+            EFuncSign(xi) => ("sgn", self.exprs([xi])),
+            EFuncRound { modulus, expr } => ("round", self.exprs(modulus.iter().chain([expr]))),
+            EFuncMin { first, rest } => ("min", self.exprs([first].into_iter().chain(rest))),
+            EFuncMax { first, rest } => ("max", self.exprs([first].into_iter().chain(rest))),
+            EFuncInterp {
+                extrapolate,
+                x,
+                points,
+            } => (
+                if *extrapolate { "interp_ext" } else { "interp" },
+                self.exprs(
+                    [x].into_iter()
+                        .chain(points.iter().flat_map(|(px, py)| [px, py])),
+                ),
+            ),
+            EFuncFma { a, b, c } => ("fma", self.exprs([a, b, c])),
+            EFuncCoalesce { value, fallback } => ("coalesce", self.exprs([value, fallback])),
+            EFuncQuotient { dividend, divisor } => ("quotient", self.exprs([dividend, divisor])),
+            EFuncRemainder { dividend, divisor } => ("remainder", self.exprs([dividend, divisor])),
+            EFuncNorm { squared, args } => {
+                (if *squared { "sumsq" } else { "norm" }, self.exprs(args))
+            }
+
+            EFuncSin(xi) => ("sin", self.exprs([xi])),
+            EFuncCos(xi) => ("cos", self.exprs([xi])),
+            EFuncTan(xi) => ("tan", self.exprs([xi])),
+            EFuncASin(xi) => ("asin", self.exprs([xi])),
+            EFuncACos(xi) => ("acos", self.exprs([xi])),
+            EFuncATan(xi) => ("atan", self.exprs([xi])),
+            EFuncSinH(xi) => ("sinh", self.exprs([xi])),
+            EFuncCosH(xi) => ("cosh", self.exprs([xi])),
+            EFuncTanH(xi) => ("tanh", self.exprs([xi])),
+            EFuncASinH(xi) => ("asinh", self.exprs([xi])),
+            EFuncACosH(xi) => ("acosh", self.exprs([xi])),
+            EFuncATanH(xi) => ("atanh", self.exprs([xi])),
+        };
+        call(name, args)
+    }
+}
+
+// Juxtaposition before these would merge with the left operand, or read as a
+// subtraction:
+fn starts_number(ch: char) -> bool {
+    ch.is_ascii_digit() || ch == '.' || ch == '-'
+}
+
+fn number(c: f64) -> Tex {
+    let text = if c.is_nan() {
+        r"\mathrm{NaN}".to_string()
+    } else if c.is_infinite() {
+        if c > 0.0 { r"\infty" } else { r"-\infty" }.to_string()
+    } else {
+        c.to_string()
+    };
+    Tex {
+        prec: if text.starts_with('-') {
+            Prec::Unary
+        } else {
+            Prec::Atom
+        },
+        text,
+    }
+}
+
+fn var(name: &str) -> Tex {
+    if name.len() == 1 && name.bytes().all(|b| b.is_ascii_alphabetic()) {
+        Tex::atom(name.to_string())
+    } else {
+        Tex::atom(format!(r"\mathit{{{}}}", escape(name)))
+    }
+}
+
+// Renders a function call, with LaTeX's own command for the functions that it
+// knows:
+fn call(name: &str, args: Vec<String>) -> Tex {
+    let op = match name {
+        "asin" => r"\arcsin".to_string(),
+        "acos" => r"\arccos".to_string(),
+        "atan" => r"\arctan".to_string(),
+        "arcsin" | "arccos" | "arctan" | "cos" | "cosh" | "cot" | "coth" | "csc" | "det"
+        | "exp" | "gcd" | "lg" | "ln" | "log" | "max" | "min" | "sec" | "sin" | "sinh" | "tan"
+        | "tanh" => format!(r"\{}", name),
+        _ if name.len() == 1 => escape(name),
+        _ => format!(r"\operatorname{{{}}}", escape(name)),
+    };
+    Tex::atom(format!("{}({})", op, args.join(", ")))
+}
+
+// Escapes the characters that are special in LaTeX text:
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '\\' => out.push_str(r"\textbackslash{}"),
+            '^' => out.push_str(r"\^{}"),
+            '~' => out.push_str(r"\~{}"),
+            '{' | '}' | '_' | '%' | '&' | '#' | '$' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            _ => out.push(ch),
+        }
+    }
+    out
+}
@@ -1,7 +1,7 @@
 //! Fast evaluation of algebraic expressions
 //!
 //! # Features
-//! * No dependencies.
+//! * No dependencies.  (The optional `serde`, `libm` and `jit` features add some.)
 //! * Safe execution of untrusted expressions.
 //! * Works with stable Rust.
 //! * Supports interpretation (i.e. parse & eval) as well as compiled execution (i.e. parse, compile, eval).
@@ -24,6 +24,7 @@
 //!
 //! ```text
 //!   * print(...strings and values...) -- Prints to stderr.  Very useful to 'probe' an expression.
+//!                                        Only prints with the `trace` feature.
 //!                                        Evaluates to the last value.
 //!                                        Example: `print("x is", x, "and y is", y)`
 //!                                        Example: `x + print("y:", y) + z == x+y+z`
@@ -35,6 +36,16 @@
 //!   * e()  -- Euler's number (2.718281828459045)
 //!   * pi() -- π (3.141592653589793)
 //!
+//!   * phi   -- The golden ratio (1.618033988749895)
+//!   * sqrt2 -- √2 (1.4142135623730951)
+//!   * ln2   -- ln(2) (0.6931471805599453)
+//!   * ln10  -- ln(10) (2.302585092994046)
+//!   * true  -- 1
+//!   * false -- 0 (the same values that comparisons evaluate to)
+//!              These are written without parentheses, like variables.  They are
+//!              only used if your namespace doesn't define a variable with that name.
+//!              Example: `(x > 1) == true  &&  !false`
+//!
 //!   * int(val)
 //!   * ceil(val)
 //!   * floor(val)
@@ -47,18 +58,101 @@
 //!   * min(val, ...) -- Example: `min(1, -2, 3, -4) == -4`
 //!   * max(val, ...) -- Example: `max(1, -2, 3, -4) == 3`
 //!
+//!   * piecewise(cond1, val1, cond2, val2, ..., default)
+//!                   -- Returns the value paired with the first nonzero condition,
+//!                      or 'default' if none match.  Only the chosen value is evaluated.
+//!                      Example: `piecewise(x < 10, 1, x < 100, 2, 3)`
+//!   * if(cond, then, else)
+//!                   -- Spreadsheet-style spelling of `piecewise(cond, then, else)`.
+//!                      Only the chosen value is evaluated.
+//!                      Example: `if(x > 0, sqrt(x), 0)`
+//!
+//!   * sum(var, from, to, expr)
+//!                   -- Sums 'expr' for var = from, from+1, from+2, ... while var <= to.
+//!                      'var' must be a plain variable name, and it is only defined inside 'expr'.
+//!                      (Calls to 'sum' with any other shape are treated as custom functions.)
+//!                      Example: `sum(i, 1, 4, i^2) == 30`
+//!
+//!   * let var = val; expr
+//!                   -- Evaluates 'val' once, and then 'expr' with 'var' bound to it.
+//!                      'var' is only defined inside 'expr', which extends as far right as it can.
+//!                      Example: `let r = sqrt(x*x + y*y); r * 2`
+//!
+//!   * interp(x, x1,y1, x2,y2, ...)
+//!                   -- Linear interpolation through a table of at least two (x,y) breakpoints.
+//!                      The x-breakpoints must be strictly increasing.  Probes outside of
+//!                      the table are clamped to the end values.
+//!                      Example: `interp(15, 0,0, 10,5, 20,30) == 17.5`
+//!   * interp_ext(x, x1,y1, x2,y2, ...)
+//!                   -- Like interp(), but extrapolates beyond the ends of the table.
+//!
 //!   * sin(radians)    * asin(val)
 //!   * cos(radians)    * acos(val)
 //!   * tan(radians)    * atan(val)
 //!   * sinh(val)       * asinh(val)
 //!   * cosh(val)       * acosh(val)
 //!   * tanh(val)       * atanh(val)
+//!
+//!   * sqrt(val)
+//!
+//!   * fma(a, b, c)  -- Fused multiply-add: a*b+c with a single rounding, which is more
+//!                      accurate than writing `a*b+c`.  Uses the CPU's FMA instruction
+//!                      where available (and a slower exact software routine otherwise).
+//!                      Example: `fma(2, 3, 4) == 10`
+//!
+//!   * coalesce(val, fallback)
+//!                   -- Returns 'val' if it is finite, or 'fallback' if it is NaN or infinite.
+//!                      Both arguments are always evaluated.
+//!                      Example: `coalesce(sqrt(-1), 5) == 5  &&  coalesce(3, 5) == 3`
+//!
+//!   * quotient(a, b)
+//!                   -- 'a / b' rounded toward zero: the quotient that goes with '%',
+//!                      so that `quotient(a, b) * b + remainder(a, b) == a`.
+//!                      It is truncated rather than floored, because '%' is the
+//!                      truncated remainder in every ModMode.
+//!   * remainder(a, b)
+//!                   -- The same as `a % b`, which has the sign of 'a'.
+//!                      Example: `quotient(7, 2) == 3  &&  remainder(-7, 2) == -1`
+//!
+//!   * sumsq(val1, val2, ...)
+//!                   -- The sum of the squares of its arguments.
+//!   * norm(val1, val2, ...)
+//!                   -- The Euclidean norm: the square root of sumsq().  `norm(x)` is `abs(x)`.
+//!                      Example: `sumsq(3, 4) == 25  &&  norm(3, 4) == 5`
+//!
+//!   * rand()        -- A uniformly-distributed random number in [0, 1).
+//!   * randint(a, b) -- A uniformly-distributed random integer in [a, b].
+//!                      Example: `randint(1, 6) + randint(1, 6)`
+//!                      These are defined by an `EvalNS::with_rng(seed)` Namespace, so the
+//!                      sequence is reproducible.  They are never constant-folded.
+//!
+//!   * now()         -- The current time, in seconds since 1970-01-01 00:00 UTC.
+//!   * hour(t), minute(t)
+//!                   -- The hour (0-23) and minute (0-59) of time 't', in UTC.
+//!   * dow(t)        -- The day of the week of time 't', from 0 (Sunday) to 6 (Saturday), in UTC.
+//!   * date(y, m, d) -- The time at the start of a day, in UTC.
+//!                      Example: `now() - last_run > 3600  &&  hour(now()) >= 9`
+//!                      These are defined by an `EvalNS` Namespace.  `now()` reads the system
+//!                      time, or the clock of `EvalNS::with_clock(clock)`, so tests can use a
+//!                      `FixedClock`.  `now()` is never constant-folded.
+//!
+//!   Arguments outside of a function's domain evaluate to NaN (for example `sqrt(-1)` or `asin(2)`).
 //! ```
 //!
+//! There are no bitwise operators, and nothing truncates a fraction silently,
+//! except that `randint()` rounds its bounds inwards.  `quotient()` rounds its
+//! result toward zero, but an
+//! [`EvalNS::with_mod_mode()`](evalns/struct.EvalNS.html#method.with_mod_mode)
+//! Namespace makes fractional operands an error, like for `%`.
+//! `date()` rejects fractional arguments, and
+//! [`eval_i64()`](trait.IntEvaler.html#tymethod.eval_i64) returns
+//! [`Error::NotAnInteger`](error/enum.Error.html#variant.NotAnInteger) instead
+//! of truncating.
+//!
 //! ## Operators
 //!
-//! The `and` and `or` operators are enabled by default, but if your
-//! application wants to use those words for something else, they can be
+//! The `and`, `or`, `in` and `not in` operators are enabled by default, but if
+//! your application wants to use those words for something else, they can be
 //! disabled by turning off the `alpha-keywords` feature (`cargo build --no-default-features`).
 //!
 //! ```text
@@ -71,11 +165,19 @@
 //!                          -               Subtraction
 //!                          +               Addition
 //!                          == != < <= >= > Comparisons (all have equal precedence)
+//!                          in  not in      Membership, as a comparison: `x in (1, 2)`
 //!                          && and          Logical AND with short-circuit
 //!     (Lowest Precedence)  || or           Logical OR with short-circuit
 //!
 //! ```
 //!
+//! `0^0` is `1`, like `f64::powf()`.  An
+//! [`EvalNS::with_zero_pow_zero()`](evalns/struct.EvalNS.html#method.with_zero_pow_zero)
+//! Namespace can make it `NaN` or an error instead.  Likewise, `%` is the
+//! remainder of `f64`, so `5.5 % 2` is `1.5`, and an
+//! [`EvalNS::with_mod_mode()`](evalns/struct.EvalNS.html#method.with_mod_mode)
+//! Namespace can restrict it, `remainder()` and `quotient()` to integers.
+//!
 //! ## Numeric Literals
 //!
 //! ```text
@@ -146,7 +248,8 @@
 //!
 //!     let val = fasteval2::ez_eval(r#"x + print("y:",y) + z"#,    &mut map)?;
 //!     //                                 |
-//!     //                                 prints "y: 2" to stderr and then evaluates to 2.0
+//!     //                                 prints "y: 2" to stderr (with the `trace`
+//!     //                                 feature) and then evaluates to 2.0
 //!
 //!     assert_eq!(val, 6.0);
 //!
@@ -205,7 +308,7 @@
 //!     //       Here are some examples:
 //!     //           pi        -- Uses the custom 'pi' variable, NOT the builtin 'pi' function.  
 //!     //           pi()      -- Uses the builtin 'pi' function even if a custom variable is defined.
-//!     //           pi(1,2,3) -- Uses the builtin 'pi' function, and produces a WrongArgs error
+//!     //           pi(1,2,3) -- Uses the builtin 'pi' function, and produces a WrongArgCount error
 //!     //                        during parse because the builtin does not expect any arguments.
 //!     //           x         -- Uses the custom 'x' variable.
 //!     //           x()       -- Uses the custom 'x' variable because there is no 'x' builtin.
@@ -333,6 +436,96 @@
 //! }
 //! ```
 //!
+//! ## Serialization
+//! With the `serde` feature (`cargo build --features serde`), the `Slab` and
+//! all of the parsed and compiled types implement `serde::Serialize` and
+//! `serde::Deserialize`.  This lets you parse (and compile) expressions ahead
+//! of time and ship the result, rather than re-parsing the expression strings:
+//! serialize the `Slab` together with the top-level `ExpressionI` or
+//! `Instruction`.
+//!
+//! The names of the types, fields and variants (like `EAdd` or `EFuncLog`) are
+//! part of the format, so renaming one is a breaking change.
+//!
+//! Constants round-trip exactly, including NaN and infinities.  (JSON can't
+//! represent those, so they are written as the strings `"NaN"`, `"inf"` and
+//! `"-inf"`.)  Deserializing data that contains an unknown variant (for example,
+//! a function from a newer version of `fasteval`) produces a normal serde error.
+//! Unsafe Variables contain raw pointers, so trying to serialize them produces an error.
+//!
+//! For a compact binary encoding of compiled expressions that doesn't need
+//! `serde`, see [`CompiledExpression`](bytecode/struct.CompiledExpression.html).
+//!
+//! ## JIT compilation
+//! With the `jit` feature (`cargo build --features jit`), a
+//! [`JitExpression`](jit/struct.JitExpression.html) lowers an expression to
+//! native machine code with [Cranelift](https://cranelift.dev/).  Variables
+//! are bound to slots of a `&[f64]` at build time, so evaluation is a single
+//! call without any Namespace lookups.  This is worthwhile when the same
+//! expression is evaluated millions of times, like in a Monte Carlo
+//! simulation.  Expressions that use features which the JIT doesn't support
+//! (like `sum()`) transparently fall back to the interpreter.
+//!
+//! Without any extra dependencies,
+//! [`Expression::to_fn()`](parser/struct.Expression.html#method.to_fn) converts
+//! an expression into a tree of closures that read their variables from a
+//! `&[f64]` in the same way.  It is slower than the JIT, but usually several
+//! times faster than evaluating with a Namespace.
+//! [`CompiledExpression::bind()`](bytecode/struct.CompiledExpression.html#method.bind)
+//! also reads the variables from a `&[f64]`, indexed by
+//! [`Symbol`](symbol/struct.Symbol.html), but keeps the interpreter, its
+//! error messages and a Namespace for custom functions.
+//!
+//! ## Without `std`
+//! The default `std` feature can be turned off for embedded targets, like
+//! `cargo build --no-default-features --features libm --target thumbv7em-none-eabihf`.
+//! The crate then only needs `core` and `alloc`, and the `libm` feature provides
+//! the math functions.  (One of `std` or `libm` is required.)  A few things
+//! need the standard library and are left out:
+//!
+//! * [`EvalNS::with_deadline()`](evalns/struct.EvalNS.html#method.with_deadline), which reads the clock.
+//! * [`CompiledExpression::eval_incremental()`](bytecode/struct.CompiledExpression.html#method.eval_incremental), which takes a `HashSet`.
+//! * The `ParseFloatError` source of `ParseF64` errors.
+//! * The `trace` and `jit` features, which turn `std` on.
+//!
+//! `tests/no_std.rs` checks that the crate builds this way.
+//!
+//! ## WebAssembly
+//! The crate builds for `wasm32-unknown-unknown` as it is.  The `wasm` feature
+//! adds [`wasm-bindgen`](https://rustwasm.github.io/docs/wasm-bindgen/)
+//! bindings for evaluating formulas from JavaScript, see the
+//! [`wasm` module](wasm/index.html).
+//!
+//! ## Many expressions at once
+//! With the `rayon` feature, [`par_eval_all()`](par/fn.par_eval_all.html)
+//! evaluates a batch of independent expressions on all cores, each with its own
+//! [`EvalNS`](evalns/struct.EvalNS.html).
+//!
+//! ## C and other languages
+//! The `ffi` feature exports a small C ABI (`fe_parse()`, `fe_eval()`,
+//! `fe_expr_free()` and `fe_error_message()`), declared in
+//! `include/fasteval2.h`.  See the [`ffi` module](ffi/index.html) for the
+//! ownership rules and how to build a library to link against.
+//!
+//! ## Output
+//! `fasteval` never writes to stdout or stderr unless the `trace` feature is
+//! enabled (`cargo build --features trace`).  With it, the `print()` built-in
+//! prints its arguments to stderr, which is handy for debugging expressions.
+//! Without it, `print()` still evaluates its arguments and returns the last
+//! value, but prints nothing, so untrusted expressions can't write to your
+//! logs.
+//!
+//! To follow an evaluation step by step, enable the `log` feature.  The
+//! interpreter (`Evaler::eval()` on an `Expression`) then reports every value
+//! that it computes and every binary operation that it performs as a
+//! [`log`](https://docs.rs/log) `trace!()` event with the `fasteval2::eval`
+//! target: `1+2*3` produces `value 1`, `value 2`, `value 3`, `op 2 * 3 = 6`
+//! and `op 1 + 6 = 7`, in that order.  The operations are formatted like a
+//! [`TraceStep`](evaler/struct.TraceStep.html), and values inside parentheses
+//! are reported too.  Without the feature, none of this code is compiled in.
+//! [`Expression::eval_trace()`](parser/struct.Expression.html#method.eval_trace)
+//! returns the same operations without a logger.
+//!
 //! ## Let's Develop an Intuition of `fasteval` Internals
 //! In this advanced example, we peek into the Slab to see how expressions are
 //! represented after the 'parse' and 'compile' phases.
@@ -399,6 +592,13 @@
 //! [`parse()`](https://docs.rs/fasteval/latest/fasteval/parser/struct.Parser.html#method.parse) will return an
 //! [Error](https://docs.rs/fasteval/latest/fasteval/error/enum.Error.html).
 //!
+//! Built-in iteration (like `sum(i, 1, 1e9, i)`) can still consume a lot of
+//! CPU time.  If that is a concern, wrap your Namespace in an
+//! [`EvalNS`](evalns/struct.EvalNS.html) with an operation budget, and
+//! evaluation will stop with `Error::BudgetExceeded` once the budget is used up.
+//! A deadline (`EvalNS::with_deadline()`) limits the wall-clock time instead,
+//! which also covers custom functions that block.
+//!
 //! Note that it *is* possible for you (the developer) to define custom functions
 //! which might perform dangerous operations.  It is your responsibility to make
 //! sure that all custom functionality is safe.
@@ -542,6 +742,12 @@
 //! I am running Ubuntu 18.04 on an Asus G55V (a 2012 laptop with Intel Core i7-3610QM CPU @ 2.3GHz - 3.3GHz).
 //!
 //! All numeric results can be found in `fasteval/benches/bench.rs`.
+//! `cargo bench --bench eval` measures the interpreter on a 30-term
+//! expression and on a batch of short ones with
+//! [Criterion](https://docs.rs/criterion/), and prints how many heap
+//! allocations each `parse()` and `eval()` makes.  `eval()` makes none, and
+//! `parse()` only allocates for names, for the arguments of functions, and for
+//! expressions with more than 5 operands.
 //!
 //! See the [detailed post about my benchmarking methology]{http://likebike.com/posts/How_To_Write_Fast_Rust_Code.html#how-to-measure}
 //! on my blog.
@@ -550,7 +756,8 @@
 //!
 //! A variety of techniques are used to optimize performance:
 //!   * [Minimization of memory allocations/deallocations](http://likebike.com/posts/How_To_Write_Fast_Rust_Code.html#reduce-redundancy-mem);
-//!     I just pre-allocate a large `Slab` during initialization.
+//!     I just pre-allocate a large `Slab` during initialization, and the
+//!     interpreter resolves operator precedence on the stack.
 //!   * Elimination of redundant work, [especially when parsing](http://likebike.com/posts/How_To_Write_Fast_Rust_Code.html#reduce-redundancy-logic).
 //!   * Designed using ["Infallible Data Structures"](http://likebike.com/posts/How_To_Write_Fast_Rust_Code.html#reduce-redundancy-data), which eliminate all corner cases.
 //!   * Compilation: Constant Folding and Expression Simplification.
@@ -607,22 +814,62 @@
 //! * [openpinescript](#coming-soon)
 //! * [The Texas Instruments MW-83 Plus Scientific Microwave Oven](https://raw.githubusercontent.com/likebike/fasteval/master/examples/scientific-microwave-ti-mw-83-plus.jpg)
 
+#![cfg_attr(not(feature = "std"), no_std)]
 //#![feature(test)]
 //#![warn(missing_docs)]
 
 //// Keeping for reference:
 // #![cfg_attr(feature="nightly", feature(slice_index_methods))]
 
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("fasteval2 needs either the `std` or the `libm` feature for its math functions");
+
+extern crate alloc;
+
 pub mod error;
 #[macro_use]
 pub mod slab;
 pub mod parser;
 #[macro_use]
 pub mod compiler;
+pub mod bytecode;
+pub mod cache;
+mod closure;
+pub mod compat;
+pub mod complexevaler;
+pub mod cost;
+pub mod cse;
+mod deep;
+pub mod disasm;
 pub mod evaler;
 pub mod evalns;
 pub mod ez;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod intevaler;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod latex;
+#[cfg(not(feature = "std"))]
+mod math;
+mod name;
+pub mod optimizer;
+#[cfg(feature = "rayon")]
+pub mod par;
+pub mod printer;
+pub mod rpn;
+#[cfg(feature = "serde")]
+mod serde_f64;
+pub mod simplify;
+pub mod symbol;
+pub mod template;
+pub mod validate;
+mod vars;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+pub use self::bytecode::CompiledExpression;
+pub use self::cache::{CachingExpr, ExpressionCache};
 #[cfg(feature = "unsafe-vars")]
 pub use self::compiler::Instruction::IUnsafeVar;
 pub use self::compiler::{
@@ -630,14 +877,37 @@ pub use self::compiler::{
     Instruction::{self, IConst},
     InstructionI,
 };
-pub use self::error::Error;
-pub use self::evaler::Evaler;
+pub use self::complexevaler::ComplexEvaler;
+pub use self::cost::CostReport;
+pub use self::cse::CseReport;
+pub use self::disasm::{InstructionView, Operand};
+pub use self::error::{Arity, Error, SpannedError};
+pub use self::evaler::{EvalResult, Evaler, TraceStep};
 pub use self::evalns::{
-    Cached, CachedCallbackNamespace, EmptyNamespace, EvalNamespace, LayeredStringToF64Namespace,
-    StrToCallbackNamespace, StrToF64Namespace, StringToCallbackNamespace, StringToF64Namespace,
+    Cached, CachedCallbackNamespace, Clock, Context, ContextNamespace, EmptyNamespace, EvalNS,
+    EvalNSBuilder, EvalNamespace, FixedClock, LayeredStringToF64Namespace, MapNamespace, ModMode,
+    StrToCallbackNamespace, StrToF64Namespace, StrToF64PairsNamespace, StringToCallbackNamespace,
+    StringToF64Namespace, ZeroPowZero,
 };
+#[cfg(feature = "std")]
+pub use self::evalns::{EnvNamespace, SystemClock};
 pub use self::ez::ez_eval;
-pub use self::parser::{Expression, ExpressionI, Parser, Value, ValueI};
+pub use self::intevaler::IntEvaler;
+#[cfg(feature = "jit")]
+pub use self::jit::JitExpression;
+pub use self::latex::Latex;
+pub use self::optimizer::{OptReport, OptRule, Optimizer};
+#[cfg(feature = "rayon")]
+pub use self::par::par_eval_all;
+pub use self::parser::{
+    Expression, ExpressionI, Parser, ParserBuilder, Token, TokenKind, TokenizerConfig, Tokens,
+    Value, ValueI,
+};
+pub use self::printer::Printer;
+pub use self::rpn::RpnToken;
 pub use self::slab::Slab;
+pub use self::symbol::{BoundExpression, Interner, Symbol};
+pub use self::template::{interpolate, Template};
+pub use self::validate::ValidationSchema;
 
 // TODO: Convert `match`es to `if let`s for performance boost.
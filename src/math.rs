@@ -0,0 +1,147 @@
+//! `f64` math methods for `no_std` builds.
+//!
+//! Without `std`, `f64` has no `sin()`, `sqrt()`, etc.  This trait provides
+//! them with the same names, on top of `libm`, so the evaluation code reads the
+//! same with and without `std`.  Modules that need the methods import it with
+//! `#[cfg(not(feature = "std"))]`.
+
+pub(crate) trait F64Ext {
+    fn floor(self) -> f64;
+    fn ceil(self) -> f64;
+    fn round(self) -> f64;
+    fn trunc(self) -> f64;
+    fn fract(self) -> f64;
+    fn sqrt(self) -> f64;
+    fn hypot(self, other: f64) -> f64;
+    fn mul_add(self, a: f64, b: f64) -> f64;
+    fn powf(self, n: f64) -> f64;
+    fn exp(self) -> f64;
+    fn ln(self) -> f64;
+    fn log(self, base: f64) -> f64;
+    fn log2(self) -> f64;
+    fn log10(self) -> f64;
+    fn sin(self) -> f64;
+    fn cos(self) -> f64;
+    fn tan(self) -> f64;
+    fn asin(self) -> f64;
+    fn acos(self) -> f64;
+    fn atan(self) -> f64;
+    fn atan2(self, other: f64) -> f64;
+    fn sinh(self) -> f64;
+    fn cosh(self) -> f64;
+    fn tanh(self) -> f64;
+    fn asinh(self) -> f64;
+    fn acosh(self) -> f64;
+    fn atanh(self) -> f64;
+}
+
+impl F64Ext for f64 {
+    #[inline]
+    fn floor(self) -> f64 {
+        libm::floor(self)
+    }
+    #[inline]
+    fn ceil(self) -> f64 {
+        libm::ceil(self)
+    }
+    #[inline]
+    fn round(self) -> f64 {
+        libm::round(self)
+    }
+    #[inline]
+    fn trunc(self) -> f64 {
+        libm::trunc(self)
+    }
+    #[inline]
+    fn fract(self) -> f64 {
+        self - libm::trunc(self)
+    }
+    #[inline]
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+    #[inline]
+    fn hypot(self, other: f64) -> f64 {
+        libm::hypot(self, other)
+    }
+    #[inline]
+    fn mul_add(self, a: f64, b: f64) -> f64 {
+        libm::fma(self, a, b)
+    }
+    #[inline]
+    fn powf(self, n: f64) -> f64 {
+        libm::pow(self, n)
+    }
+    #[inline]
+    fn exp(self) -> f64 {
+        libm::exp(self)
+    }
+    #[inline]
+    fn ln(self) -> f64 {
+        libm::log(self)
+    }
+    #[inline]
+    fn log(self, base: f64) -> f64 {
+        libm::log(self) / libm::log(base)
+    }
+    #[inline]
+    fn log2(self) -> f64 {
+        libm::log2(self)
+    }
+    #[inline]
+    fn log10(self) -> f64 {
+        libm::log10(self)
+    }
+    #[inline]
+    fn sin(self) -> f64 {
+        libm::sin(self)
+    }
+    #[inline]
+    fn cos(self) -> f64 {
+        libm::cos(self)
+    }
+    #[inline]
+    fn tan(self) -> f64 {
+        libm::tan(self)
+    }
+    #[inline]
+    fn asin(self) -> f64 {
+        libm::asin(self)
+    }
+    #[inline]
+    fn acos(self) -> f64 {
+        libm::acos(self)
+    }
+    #[inline]
+    fn atan(self) -> f64 {
+        libm::atan(self)
+    }
+    #[inline]
+    fn atan2(self, other: f64) -> f64 {
+        libm::atan2(self, other)
+    }
+    #[inline]
+    fn sinh(self) -> f64 {
+        libm::sinh(self)
+    }
+    #[inline]
+    fn cosh(self) -> f64 {
+        libm::cosh(self)
+    }
+    #[inline]
+    fn tanh(self) -> f64 {
+        libm::tanh(self)
+    }
+    #[inline]
+    fn asinh(self) -> f64 {
+        libm::asinh(self)
+    }
+    #[inline]
+    fn acosh(self) -> f64 {
+        libm::acosh(self)
+    }
+    #[inline]
+    fn atanh(self) -> f64 {
+        libm::atanh(self)
+    }
+}
@@ -0,0 +1,208 @@
+//! `Name`, the string type of the identifiers in parsed expressions.
+
+use alloc::{boxed::Box, string::String};
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use core::str;
+
+// The longest name that is stored without allocating.  This keeps a `Name` as
+// big as a `String` (24 bytes on 64-bit targets), and covers nearly every
+// variable and function name that people write.
+const INLINE_LEN: usize = 22;
+
+/// The name of a variable or function in a parsed expression, like the `x` of
+/// `EVar("x")`.
+///
+/// Names of up to 22 bytes are stored inline, so parsing expressions with
+/// short identifiers like `x`, `dt` or `hp` doesn't allocate for them.  Longer
+/// names are stored on the heap.  Either way, a `Name` owns its text and
+/// doesn't borrow from the parsed string, and it derefs to a `&str`:
+///
+/// ```
+/// use fasteval2::parser::Name;
+///
+/// let dt = Name::from("dt");
+/// assert_eq!(dt, "dt");
+/// assert!(!dt.is_heap());
+///
+/// let long = Name::from("acceleration_due_to_gravity");
+/// assert_eq!(long.len(), 27);
+/// assert!(long.is_heap());
+/// ```
+#[derive(Clone)]
+pub struct Name(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    // The first `len` bytes are UTF-8, because they are copied from a `&str`:
+    Inline { len: u8, bytes: [u8; INLINE_LEN] },
+    Heap(Box<str>),
+}
+
+impl Name {
+    /// Returns the name as a string slice.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Inline { len, bytes } => {
+                // SAFETY: `bytes[..len]` was copied from a `&str` by `From<&str>`.
+                unsafe { str::from_utf8_unchecked(&bytes[..*len as usize]) }
+            }
+            Repr::Heap(s) => s,
+        }
+    }
+
+    /// Returns `true` if the name is stored on the heap, because it is longer
+    /// than the inline capacity.
+    #[inline]
+    pub fn is_heap(&self) -> bool {
+        matches!(self.0, Repr::Heap(_))
+    }
+}
+
+impl From<&str> for Name {
+    #[inline]
+    fn from(s: &str) -> Self {
+        if s.len() > INLINE_LEN {
+            return Name(Repr::Heap(s.into()));
+        }
+        let mut bytes = [0; INLINE_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Name(Repr::Inline {
+            len: s.len() as u8,
+            bytes,
+        })
+    }
+}
+
+impl From<String> for Name {
+    #[inline]
+    fn from(s: String) -> Self {
+        if s.len() > INLINE_LEN {
+            return Name(Repr::Heap(s.into_boxed_str()));
+        }
+        Name::from(s.as_str())
+    }
+}
+
+impl From<Name> for String {
+    #[inline]
+    fn from(name: Name) -> Self {
+        match name.0 {
+            Repr::Heap(s) => s.into_string(),
+            Repr::Inline { .. } => String::from(name.as_str()),
+        }
+    }
+}
+
+impl Default for Name {
+    #[inline]
+    fn default() -> Self {
+        Name::from("")
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Name {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for Name {
+    #[inline]
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// Like a `String`, which these replace:
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Name {
+    #[inline]
+    fn eq(&self, other: &Name) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Name {}
+
+impl PartialEq<str> for Name {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    #[inline]
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Name {
+    #[inline]
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialOrd for Name {
+    #[inline]
+    fn partial_cmp(&self, other: &Name) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    #[inline]
+    fn cmp(&self, other: &Name) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for Name {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+// Names are written as strings, like the `String`s that they replace, so the
+// format doesn't change:
+#[cfg(feature = "serde")]
+impl serde::Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Name::from)
+    }
+}
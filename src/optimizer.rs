@@ -0,0 +1,298 @@
+//! A peephole pass over compiled `Instruction`s.
+//!
+//! `compile()` already folds constants and simplifies flat chains of
+//! operators, but some patterns only show up after sub-expressions have been
+//! compiled separately.  For example, `-x * 3` compiles to `(-x) * 3`, and
+//! `y - 2*x` compiles to `y + -(x * 2)`.  An [`Optimizer`](struct.Optimizer.html)
+//! rewrites those with a small set of local rules:
+//!
+//! | Rule                  | Rewrite                              |
+//! |-----------------------|--------------------------------------|
+//! | [`DoubleNeg`]         | `-(-x)` ⟶ `x`                        |
+//! | [`NegFusion`]         | `(-x) * c` and `-(x * c)` ⟶ `x * -c` |
+//! | [`MulIdentity`]       | `x * 1` ⟶ `x`, `x * -1` ⟶ `-x`       |
+//! | [`Square`]            | `v ^ 2` ⟶ `v * v`, `x ^ 1` ⟶ `x`     |
+//! | [`MulConstChain`] (*) | `(x * a) * b` ⟶ `x * (a*b)`          |
+//! | [`AddConstChain`] (*) | `(x + a) + b` ⟶ `x + (a+b)`          |
+//!
+//! (`c`, `a` and `b` are constants, and `v` is a variable.)
+//!
+//! Every rule can be disabled individually.  The rules marked with (*)
+//! reassociate floating-point operations, which can change the rounding of
+//! the result (or overflow where the original didn't), so they only run when
+//! fast-math is enabled.  The other rules give exactly the same results as
+//! the unoptimized `Instruction`, except possibly for the sign bit of a NaN.
+//!
+//! [`DoubleNeg`]: enum.OptRule.html#variant.DoubleNeg
+//! [`NegFusion`]: enum.OptRule.html#variant.NegFusion
+//! [`MulIdentity`]: enum.OptRule.html#variant.MulIdentity
+//! [`Square`]: enum.OptRule.html#variant.Square
+//! [`MulConstChain`]: enum.OptRule.html#variant.MulConstChain
+//! [`AddConstChain`]: enum.OptRule.html#variant.AddConstChain
+//!
+//! # Example
+//! ```
+//! use fasteval2::{Compiler, Evaler, OptRule, Optimizer};
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = fasteval2::Slab::new();
+//!     let mut ns = fasteval2::EmptyNamespace;
+//!     let instr = fasteval2::Parser::new()
+//!         .parse("y - 2*x + x^2", &mut slab.ps)?
+//!         .from(&slab.ps)
+//!         .compile(&slab.ps, &mut slab.cs, &mut ns);
+//!
+//!     let (instr, report) = Optimizer::new().optimize_with_report(instr, &mut slab.cs);
+//!     assert_eq!(report.count(OptRule::NegFusion), 1);
+//!     assert_eq!(report.count(OptRule::Square), 1);
+//!     assert_eq!((report.before, report.after), (8, 8));
+//!
+//!     let mut ns = |name: &str, _: Vec<f64>| match name {
+//!         "x" => Some(3.0),
+//!         "y" => Some(1.0),
+//!         _ => None,
+//!     };
+//!     assert_eq!(instr.eval(&slab, &mut ns)?, 4.0);
+//!     Ok(())
+//! }
+//! ```
+
+use crate::compiler::{
+    Instruction::{self, IAdd, IExp, IMul, INeg, IVar},
+    InstructionI, IC,
+};
+use crate::evalns::is_impure;
+use crate::slab::CompileSlab;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem;
+
+/// The rewrite rules of the [`Optimizer`](struct.Optimizer.html).
+///
+/// See the [module documentation](index.html) for an overview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OptRule {
+    /// `-(-x)` ⟶ `x`
+    DoubleNeg,
+    /// `(-x) * c` ⟶ `x * -c` and `-(x * c)` ⟶ `x * -c`, for a constant `c`.
+    ///
+    /// This removes the negation from subtractions like `y - 2*x`.
+    NegFusion,
+    /// `x * 1` ⟶ `x` and `x * -1` ⟶ `-x`
+    MulIdentity,
+    /// `v ^ 2` ⟶ `v * v` for a variable `v`, and `x ^ 1` ⟶ `x`.
+    ///
+    /// The variable is looked up twice, so this assumes that the Namespace
+    /// returns the same value each time it is asked (`CachedCallbackNamespace`
+    /// guarantees that).  `rand` is left alone.
+    Square,
+    /// `(x * a) * b` ⟶ `x * (a*b)`, for constants `a` and `b`.  Fast-math only.
+    MulConstChain,
+    /// `(x + a) + b` ⟶ `x + (a+b)`, for constants `a` and `b`.  Fast-math only.
+    AddConstChain,
+}
+
+impl OptRule {
+    /// All rules, in the order that they are documented.
+    pub const ALL: [OptRule; 6] = [
+        OptRule::DoubleNeg,
+        OptRule::NegFusion,
+        OptRule::MulIdentity,
+        OptRule::Square,
+        OptRule::MulConstChain,
+        OptRule::AddConstChain,
+    ];
+
+    /// Returns `true` if this rule can change results, and therefore only
+    /// runs when fast-math is enabled.
+    pub fn requires_fast_math(self) -> bool {
+        matches!(self, OptRule::MulConstChain | OptRule::AddConstChain)
+    }
+}
+
+/// What an [`Optimizer`](struct.Optimizer.html) did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptReport {
+    /// How many times each rule fired.  Rules that didn't fire are absent.
+    pub fired: BTreeMap<OptRule, usize>,
+    /// The number of `Instruction`s reachable from the root, before optimization.
+    pub before: usize,
+    /// The number of `Instruction`s reachable from the root, after optimization.
+    pub after: usize,
+}
+
+impl OptReport {
+    /// How many times `rule` fired.
+    pub fn count(&self, rule: OptRule) -> usize {
+        self.fired.get(&rule).copied().unwrap_or(0)
+    }
+
+    /// The number of `Instruction`s that were eliminated.
+    ///
+    /// This is zero if the optimized form is larger (the `Square` rule adds a
+    /// variable lookup to remove a `pow()` call).
+    pub fn eliminated(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+/// A peephole optimizer for compiled `Instruction`s.
+///
+/// `Optimizer::new()` enables all of the rules that preserve results.  Use
+/// [`without()`](#method.without) to disable a rule, and
+/// [`fast_math()`](#method.fast_math) to allow the rules that don't.
+#[derive(Debug, Clone, Default)]
+pub struct Optimizer {
+    disabled: Vec<OptRule>,
+    fast_math: bool,
+}
+
+impl Optimizer {
+    /// Creates an `Optimizer` with all of the result-preserving rules enabled.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disables `rule`.
+    pub fn without(mut self, rule: OptRule) -> Self {
+        if !self.disabled.contains(&rule) {
+            self.disabled.push(rule);
+        }
+        self
+    }
+
+    /// Enables or disables the rules that reassociate floating-point
+    /// operations.  These can change the rounding of results.
+    pub fn fast_math(mut self, on: bool) -> Self {
+        self.fast_math = on;
+        self
+    }
+
+    /// Returns `true` if `rule` will run.
+    pub fn is_enabled(&self, rule: OptRule) -> bool {
+        !self.disabled.contains(&rule) && (self.fast_math || !rule.requires_fast_math())
+    }
+
+    /// Rewrites `instr`, whose sub-instructions are stored in `cslab`.
+    ///
+    /// Replaced sub-instructions are left behind in `cslab`, unreferenced.
+    pub fn optimize(&self, instr: Instruction, cslab: &mut CompileSlab) -> Instruction {
+        self.optimize_with_report(instr, cslab).0
+    }
+
+    /// Like [`optimize()`](#method.optimize), but also reports which rules
+    /// fired and how many `Instruction`s were eliminated.
+    pub fn optimize_with_report(
+        &self,
+        instr: Instruction,
+        cslab: &mut CompileSlab,
+    ) -> (Instruction, OptReport) {
+        let mut report = OptReport {
+            before: count(&instr, cslab),
+            ..OptReport::default()
+        };
+        let instr = self.opt(instr, cslab, &mut report);
+        report.after = count(&instr, cslab);
+        (instr, report)
+    }
+
+    fn opt(
+        &self,
+        mut instr: Instruction,
+        cslab: &mut CompileSlab,
+        report: &mut OptReport,
+    ) -> Instruction {
+        // Bottom-up, so that each rule only needs to look one level down:
+        let children: Vec<InstructionI> = instr.children().into_iter().copied().collect();
+        for i in children {
+            let child = mem::take(&mut cslab.instrs_mut()[i.0]);
+            cslab.instrs_mut()[i.0] = self.opt(child, cslab, report);
+        }
+        loop {
+            match self.rewrite(instr, cslab) {
+                Ok((rule, new)) => {
+                    *report.fired.entry(rule).or_insert(0) += 1;
+                    instr = new;
+                }
+                Err(instr) => return instr,
+            }
+        }
+    }
+
+    /// Applies one rule to `instr`, or gives it back unchanged.
+    fn rewrite(
+        &self,
+        instr: Instruction,
+        cslab: &mut CompileSlab,
+    ) -> Result<(OptRule, Instruction), Instruction> {
+        let on = |rule| self.is_enabled(rule);
+        match instr {
+            INeg(i) => match *cslab.get_instr(i) {
+                INeg(x) if on(OptRule::DoubleNeg) => Ok((OptRule::DoubleNeg, take(cslab, x))),
+                IMul(x, IC::C(c)) if on(OptRule::NegFusion) => {
+                    Ok((OptRule::NegFusion, IMul(x, IC::C(-c))))
+                }
+                _ => Err(INeg(i)),
+            },
+            IMul(li, IC::C(c)) if on(OptRule::MulIdentity) && c == 1.0 => {
+                Ok((OptRule::MulIdentity, take(cslab, li)))
+            }
+            IMul(li, IC::C(c)) if on(OptRule::MulIdentity) && c == -1.0 => {
+                Ok((OptRule::MulIdentity, INeg(li)))
+            }
+            IMul(li, IC::C(c)) => match *cslab.get_instr(li) {
+                INeg(x) if on(OptRule::NegFusion) => Ok((OptRule::NegFusion, IMul(x, IC::C(-c)))),
+                IMul(x, IC::C(a)) if on(OptRule::MulConstChain) => {
+                    Ok((OptRule::MulConstChain, IMul(x, IC::C(a * c))))
+                }
+                _ => Err(IMul(li, IC::C(c))),
+            },
+            IAdd(li, IC::C(c)) => match *cslab.get_instr(li) {
+                IAdd(x, IC::C(a)) if on(OptRule::AddConstChain) => {
+                    Ok((OptRule::AddConstChain, IAdd(x, IC::C(a + c))))
+                }
+                _ => Err(IAdd(li, IC::C(c))),
+            },
+            IExp {
+                base: IC::I(b),
+                power: IC::C(p),
+            } if on(OptRule::Square) && p == 1.0 => Ok((OptRule::Square, take(cslab, b))),
+            IExp {
+                base: IC::I(b),
+                power: IC::C(p),
+            } if on(OptRule::Square) && p == 2.0 => match cslab.get_instr(b) {
+                IVar(name) if !is_impure(name) => {
+                    let other = IVar(name.clone());
+                    Ok((OptRule::Square, IMul(b, IC::I(cslab.push_instr(other)))))
+                }
+                _ => Err(IExp {
+                    base: IC::I(b),
+                    power: IC::C(p),
+                }),
+            },
+            instr => Err(instr),
+        }
+    }
+}
+
+impl Instruction {
+    /// Runs the default [`Optimizer`](optimizer/struct.Optimizer.html) over
+    /// this `Instruction`.  The results of evaluation don't change.
+    pub fn optimize(self, cslab: &mut CompileSlab) -> Instruction {
+        Optimizer::new().optimize(self, cslab)
+    }
+}
+
+fn take(cslab: &mut CompileSlab, i: InstructionI) -> Instruction {
+    // The slot stays unreferenced, so the indices of the others don't change:
+    mem::take(&mut cslab.instrs_mut()[i.0])
+}
+
+fn count(instr: &Instruction, cslab: &CompileSlab) -> usize {
+    1 + instr
+        .children()
+        .into_iter()
+        .map(|i| count(cslab.get_instr(*i), cslab))
+        .sum::<usize>()
+}
@@ -0,0 +1,82 @@
+//! Evaluation of many expressions on all cores, with `rayon`.
+
+use crate::error::Error;
+use crate::evaler::Evaler;
+use crate::evalns::{EvalNS, EvalNamespace};
+use crate::parser::ExpressionI;
+use crate::slab::Slab;
+
+use rayon::prelude::*;
+
+/// Evaluates every expression in `exprs` (indexes into `slab`), spreading the work over rayon's
+/// thread pool, and returns their results in the same order.
+///
+/// The expressions are independent: each one gets a fresh `EvalNS` from
+/// `make_ns`, so budgets, random number generators and the like start over for
+/// every expression, and the results are the same as those of a sequential
+/// loop, whichever thread each expression runs on.  `slab` is only read, so
+/// it is shared by all threads.
+///
+/// This needs the `rayon` feature.  With `unsafe-vars`, a `slab` that has
+/// Unsafe Variables is rejected, because their pointers can't be shared
+/// between threads: every result is an `Error::NotAllowed` with the name of
+/// one of them.
+///
+/// # Examples
+/// ```
+/// use fasteval2::{par_eval_all, EvalNS, Parser, Slab};
+/// use std::collections::BTreeMap;
+///
+/// let mut slab = Slab::with_capacity(1000);
+/// let exprs: Vec<_> = (0..100)
+///     .map(|i| {
+///         Parser::new()
+///             .parse_noclear(&format!("x * {}", i), &mut slab.ps)
+///             .unwrap()
+///     })
+///     .collect();
+///
+/// let make_ns = || EvalNS::new(BTreeMap::from([("x".to_string(), 2.0)]));
+/// let results = par_eval_all(&slab, &exprs, make_ns);
+/// assert_eq!(results[21], Ok(42.0));
+/// ```
+pub fn par_eval_all<NS: EvalNamespace>(
+    slab: &Slab,
+    exprs: &[ExpressionI],
+    make_ns: impl Fn() -> EvalNS<NS> + Sync,
+) -> Vec<Result<f64, Error>> {
+    #[cfg(feature = "unsafe-vars")]
+    if let Some(name) = slab.ps.unsafe_vars.keys().next() {
+        return exprs
+            .iter()
+            .map(|_| Err(Error::NotAllowed(name.clone())))
+            .collect();
+    }
+    let shared = SharedSlab(slab);
+    exprs
+        .par_iter()
+        .map(|&expr_i| {
+            let slab = shared.get();
+            let mut ns = make_ns();
+            slab.ps.get_expr(expr_i).eval(slab, &mut ns)
+        })
+        .collect()
+}
+
+// Shares a `Slab` between rayon's threads.  Without `unsafe-vars` it is `Sync`
+// anyway.  With `unsafe-vars`, a `Slab` holds pointers, but only a `ParseSlab`
+// that has Unsafe Variables can have expressions that read them (and
+// expressions never read the `CompileSlab`), so `par_eval_all()` only shares
+// the other ones.
+struct SharedSlab<'a>(&'a Slab);
+
+#[cfg(feature = "unsafe-vars")]
+unsafe impl Sync for SharedSlab<'_> {}
+
+impl SharedSlab<'_> {
+    // A method, so that closures capture the whole `SharedSlab`, not its field:
+    #[inline]
+    fn get(&self) -> &Slab {
+        self.0
+    }
+}
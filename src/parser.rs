@@ -4,7 +4,7 @@
 //! ```text
 //! Expression: Value (BinaryOp Value)*
 //!
-//! Value: Constant || UnaryOp || PrintFunc || StdFunc
+//! Value: Constant || UnaryOp || PrintFunc || Let || StdFunc
 //!
 //! Constant: [+-]?[0-9]*(\.[0-9]+)?( ([eE][+-]?[0-9]+) || [pnuµmkKMGT] )?  || [+-]?(NaN || inf)
 //!
@@ -16,24 +16,70 @@
 //!
 //! StdFunc: VarName((Expression,)*)?  ||  VarName[(Expression,)*]?
 //!
+//! Let: let VarName = Expression ; Expression
+//!
+//! Tuple: Expression ((, || ;) Expression)*
+//!
 //! PrintFunc: print(ExpressionOrString,*)
 //!
 //! ExpressionOrString: Expression || String
 //!
 //! String: ".*"
 //! ```
+//!
+//! `let r = sqrt(x*x + y*y); r * 2` evaluates the first expression once, and
+//! then the second one with `r` bound to its value.  The binding can't be
+//! changed, and it ends with the second expression, which extends as far to
+//! the right as possible (up to a `,` or a closing parenthesis).  Where `let`
+//! isn't followed by `VarName =`, it is an ordinary variable name.
+//!
+//! Spaces, tabs and newlines can appear between tokens.  A backslash at the
+//! end of a line is also whitespace, so a long expression can be continued on
+//! the next line with an explicit marker:
+//! ```text
+//! 2 + \
+//!     3
+//! ```
+//!
+//! `x in (1, 2, 3)` is 1 if `x` equals one of the listed values (like `==`
+//! compares them), and 0 otherwise.  `x not in (1, 2, 3)` is the opposite.
+//! The list is evaluated from left to right, only until a value matches, and
+//! it can't be empty.  `in` groups like a comparison, so `a && x + 1 in (2, 3)`
+//! tests `x + 1`.  `in` and `not` are only keywords before such a list, and
+//! only with the `alpha-keywords` feature, like `and` and `or`.
+//!
+//! A `Tuple` is only read at the top level, and only by a `Parser` with
+//! [`tuples`](struct.ParserBuilder.html#method.tuples) enabled.  Its items are
+//! evaluated with [`Expression::eval_vec()`](struct.Expression.html#method.eval_vec).
+//!
+//! The characters of `VarName`s and operators can be changed with a
+//! [`TokenizerConfig`](trait.TokenizerConfig.html).
 
-use crate::error::Error;
+use crate::error::{Arity, Error, SpannedError};
+use crate::evaler::OP_PASSES;
+pub use crate::name::Name;
 use crate::slab::ParseSlab;
 
-use std::ptr;
-use std::str::{from_utf8, from_utf8_unchecked};
+use alloc::sync::Arc;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use core::mem;
+use core::ops::Range;
+use core::ptr;
+use core::str::{from_utf8, from_utf8_unchecked};
+use smallvec::{smallvec, SmallVec};
 
 /// An `ExpressionI` represents an index into `Slab.ps.exprs`.
 ///
 /// It behaves much like a pointer or reference, but it is 'safe' (unlike a raw
 /// pointer) and is not managed by the Rust borrow checker (unlike a reference).
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExpressionI(pub usize);
 
 /// A `ValueI` represents an index into `Slab.ps.vals`.
@@ -41,32 +87,112 @@ pub struct ExpressionI(pub usize);
 /// It behaves much like a pointer or reference, but it is 'safe' (unlike a raw
 /// pointer) and is not managed by the Rust borrow checker (unlike a reference).
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValueI(pub usize);
 
 /// An `Expression` is the top node of a parsed AST.
 ///
 /// It can be `compile()`d or `eval()`d.
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
 pub struct Expression {
     pub(crate) first: Value,
-    pub(crate) pairs: Vec<ExprPair>, // cap=8
+    pub(crate) pairs: Pairs,
+    // The items of a top-level tuple after this one, which only a `Parser`
+    // with `tuples` reads.  Empty everywhere else.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) tuple: Vec<ExpressionI>,
+}
+
+// By hand, so that only tuples show their `tuple`:
+impl fmt::Debug for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("Expression");
+        s.field("first", &self.first).field("pairs", &self.pairs);
+        if !self.tuple.is_empty() {
+            s.field("tuple", &self.tuple);
+        }
+        s.finish()
+    }
+}
+
+// By hand too, so that human-readable formats (like JSON) only write the
+// `tuple` of tuples, and keep the format of everything else.  Binary formats
+// (like bincode) read the fields in order, so they always write it:
+#[cfg(feature = "serde")]
+impl serde::Serialize for Expression {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let skip_tuple = self.tuple.is_empty() && serializer.is_human_readable();
+        let mut s = serializer.serialize_struct("Expression", if skip_tuple { 2 } else { 3 })?;
+        s.serialize_field("first", &self.first)?;
+        s.serialize_field("pairs", &self.pairs)?;
+        if skip_tuple {
+            s.skip_field("tuple")?;
+        } else {
+            s.serialize_field("tuple", &self.tuple)?;
+        }
+        s.end()
+    }
+}
+
+impl Expression {
+    /// Returns the number if this `Expression` is nothing but a number, like
+    /// `42`, `-1.5e3` or `NaN`, so literals can skip the Namespace setup.
+    ///
+    /// Operators, variables, parentheses and function calls make it `None`,
+    /// even where they add up to a constant, like `2 * 3` or `pi()`, and so
+    /// does a tuple of several items, like `1, 2`.
+    ///
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let expr_i = Parser::new().parse("-42", &mut slab.ps).unwrap();
+    /// assert_eq!(expr_i.from(&slab.ps).as_const(), Some(-42.0));
+    /// ```
+    #[inline]
+    pub fn as_const(&self) -> Option<f64> {
+        match (&self.first, self.pairs.is_empty() && self.tuple.is_empty()) {
+            (EConstant(c), true) => Some(*c),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct ExprPair(pub BinaryOp, pub Value);
 
+// The operators and operands of an `Expression` after the first operand.  Up
+// to `INLINE_PAIRS` of them are stored in the `Expression` itself, so that
+// parsing short expressions doesn't allocate for them.  A pair is 72 bytes on
+// 64-bit targets.  In the `short_batch` benchmark of `benches/eval.rs` (3 to 7
+// tokens), 1 inline pair still allocates for a third of the expressions and
+// is slower than a Vec; 2 and 4 are about 5% faster than a Vec, and 4 also
+// covers chains of 5 operands:
+pub(crate) const INLINE_PAIRS: usize = 4;
+pub(crate) type Pairs = SmallVec<[ExprPair; INLINE_PAIRS]>;
+
 /// A `Value` can be a Constant, a UnaryOp, a StdFunc, or a PrintFunc.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
-    EConstant(f64),
+    EConstant(#[cfg_attr(feature = "serde", serde(with = "crate::serde_f64"))] f64),
     EUnaryOp(UnaryOp),
     EStdFunc(StdFunc),
     EPrintFunc(PrintFunc),
+
+    /// A placeholder for an invalid value, inserted by
+    /// [`parse_with_recovery()`](struct.Parser.html#method.parse_with_recovery).
+    /// Evaluating it returns the contained `Error`.
+    EError(Error),
 }
-use Value::{EConstant, EPrintFunc, EStdFunc, EUnaryOp};
+use Value::{EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp};
 
 /// Unary Operators
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOp {
     EPos(ValueI),
     ENeg(ValueI),
@@ -77,6 +203,7 @@ use UnaryOp::{ENeg, ENot, EParentheses, EPos};
 
 /// Binary Operators
 #[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOp {
     // Sorted in order of precedence (low-priority to high-priority):
     // Keep this order in-sync with evaler.rs.  (Search for 'rtol' and 'ltor'.)
@@ -99,15 +226,11 @@ use BinaryOp::{EAdd, EDiv, EExp, EMod, EMul, ESub, EAND, EEQ, EGT, EGTE, ELT, EL
 
 /// A Function Call with Standard Syntax.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StdFunc {
-    EVar(String),
-    #[cfg(feature = "unsafe-vars")]
-    EUnsafeVar {
-        name: String,
-        ptr: *const f64,
-    },
+    EVar(Name),
     EFunc {
-        name: String,
+        name: Name,
         args: Vec<ExpressionI>,
     }, // cap=4
 
@@ -132,6 +255,21 @@ pub enum StdFunc {
         first: ExpressionI,
         rest: Vec<ExpressionI>,
     }, // cap=4
+    EFuncPiecewise {
+        branches: Vec<(ExpressionI, ExpressionI)>,
+        default: ExpressionI,
+    }, // cap=4
+    EFuncSum {
+        var: Name,
+        from: ExpressionI,
+        to: ExpressionI,
+        expr: ExpressionI,
+    },
+    EFuncInterp {
+        extrapolate: bool,
+        x: ExpressionI,
+        points: Vec<(ExpressionI, ExpressionI)>,
+    }, // cap=4
 
     EFuncE,
     EFuncPi,
@@ -149,21 +287,65 @@ pub enum StdFunc {
     EFuncACosH(ExpressionI),
     EFuncATanH(ExpressionI),
     EFuncSqrt(ExpressionI),
+    EFuncFma {
+        a: ExpressionI,
+        b: ExpressionI,
+        c: ExpressionI,
+    },
+    EFuncCoalesce {
+        value: ExpressionI,
+        fallback: ExpressionI,
+    },
+    EFuncNorm {
+        squared: bool,
+        args: Vec<ExpressionI>,
+    }, // cap=4
+    EFuncLet {
+        var: Name,
+        value: ExpressionI,
+        expr: ExpressionI,
+    },
+    EFuncQuotient {
+        dividend: ExpressionI,
+        divisor: ExpressionI,
+    },
+    EFuncRemainder {
+        dividend: ExpressionI,
+        divisor: ExpressionI,
+    },
+    /// `value in (list...)`, or `value not in (list...)` if `negated`.
+    EFuncIn {
+        negated: bool,
+        value: ExpressionI,
+        list: Vec<ExpressionI>,
+    }, // cap=4
+
+    // Kept last so that the other variants have the same index with or without the feature:
+    #[cfg(feature = "unsafe-vars")]
+    #[cfg_attr(feature = "serde", serde(skip))] // Pointers can't be serialized.
+    EUnsafeVar {
+        name: Name,
+        ptr: *const f64,
+    },
 }
 #[cfg(feature = "unsafe-vars")]
 use StdFunc::EUnsafeVar;
 use StdFunc::{
     EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
-    EFuncCeil, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncInt, EFuncLog, EFuncMax, EFuncMin,
-    EFuncPi, EFuncRound, EFuncSign, EFuncSin, EFuncSinH, EFuncSqrt, EFuncTan, EFuncTanH, EVar,
+    EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn, EFuncInt,
+    EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi, EFuncPiecewise,
+    EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH, EFuncSqrt, EFuncSum,
+    EFuncTan, EFuncTanH, EVar,
 };
 
 /// Represents a `print()` function call in the `fasteval` expression AST.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrintFunc(pub Vec<ExpressionOrString>); // cap=8
 
 /// Used by the `print()` function.  Can hold an `Expression` or a `String`.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExpressionOrString {
     EExpr(ExpressionI),
     EStr(String), // cap=64
@@ -183,11 +365,67 @@ impl Clone for PrintFunc {
     }
 }
 
-enum Token<T> {
+/// A token of an expression string, from [`Parser::tokens()`](struct.Parser.html#method.tokens).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The byte range of the token in the source string.
+    pub span: Range<usize>,
+}
+
+/// What a [`Token`](struct.Token.html) is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// A number, after its sign, separators and suffix have been applied.
+    /// `-3` is one `Number`, `- 3` is a `Neg` and a `Number`.
+    Number(f64),
+    /// A variable or function name.
+    Ident(String),
+    /// The contents of a string argument of `print()`, without the quotes.
+    Str(String),
+    /// A binary operator, also when it is written as an alias.
+    BinaryOp(BinaryOp),
+    /// The unary `-`.
+    Neg,
+    /// The unary `+`.
+    Pos,
+    /// The unary `!`.
+    Not,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+    Semicolon,
+    /// The `let` of a [`let` binding](index.html#fasteval-algebra-grammar).
+    Let,
+    /// The `=` of a `let` binding.
+    Assign,
+    /// The `in` of a membership test, like `x in (1, 2)`.
+    In,
+    /// The `not in` of a membership test, both words.
+    NotIn,
+}
+
+/// The iterator of [`Parser::tokens()`](struct.Parser.html#method.tokens).
+#[derive(Debug)]
+pub struct Tokens<'a> {
+    parser: &'a Parser,
+    src: &'a str,
+    bs: &'a [u8],
+    want_value: bool,
+    after_name: bool,
+    after_let: bool,
+    after_let_name: bool,
+    char_buf: String,
+    done: bool,
+}
+
+enum Lex<T> {
     Pass,
     Bite(T),
 }
-use Token::{Bite, Pass};
+use Lex::{Bite, Pass};
 
 macro_rules! peek {
     ($bs:ident) => {
@@ -261,34 +499,432 @@ macro_rules! is_space {
 macro_rules! spaces {
     ($bs:ident) => {
         while let Some(b) = peek!($bs) {
-            if !is_space!(b) { break }
-            skip!($bs);  // We normally don't have long strings of whitespace, so it is more efficient to put this single-skip inside this loop rather than a skip_n afterwards.
+            if is_space!(b) {
+                skip!($bs);  // We normally don't have long strings of whitespace, so it is more efficient to put this single-skip inside this loop rather than a skip_n afterwards.
+            } else if b == b'\\' && peek_is!($bs, 1, b'\n') {
+                skip_n!($bs, 2);  // A line continuation.
+            } else if b == b'\\' && peek_is!($bs, 1, b'\r') && peek_is!($bs, 2, b'\n') {
+                skip_n!($bs, 3);
+            } else {
+                break;
+            }
         }
     };
 }
 
+// A number token of parse_rpn(), in the form that RpnToken displays it:
+fn rpn_number(tok: &str) -> Option<f64> {
+    let unsigned = tok.strip_prefix(['-', '+']).unwrap_or(tok);
+    let numeric = match unsigned.as_bytes().first() {
+        Some(b) => b.is_ascii_digit() || *b == b'.' || unsigned == "inf" || unsigned == "NaN",
+        None => false,
+    };
+    if numeric {
+        tok.parse().ok()
+    } else {
+        None
+    }
+}
+
+// Returns the BinaryOp that is written as `symbol`, like `EMod` for "%":
+fn binaryop_from_symbol(symbol: &str) -> Option<BinaryOp> {
+    OP_PASSES
+        .iter()
+        .flat_map(|(ops, _)| ops.iter().copied())
+        .find(|op| op.symbol() == symbol)
+}
+
+// Decodes the char at the start of `bs`, or returns None at the end or at bytes that aren't a char.
+fn peek_char(bs: &[u8]) -> Option<char> {
+    let len = match *bs.first()? {
+        b if b < 0x80 => 1,
+        b if b >= 0xf0 => 4,
+        b if b >= 0xe0 => 3,
+        _ => 2,
+    };
+    from_utf8(bs.get(..len)?).ok()?.chars().next()
+}
+
 pub const DEFAULT_EXPR_LEN_LIMIT: usize = 4096;
 pub const DEFAULT_EXPR_DEPTH_LIMIT: usize = 32;
 
+/// Parses expression strings into a `ParseSlab`.
+///
+/// `Parser::new()` has the default options.  To change them, use
+/// [`Parser::builder()`](#method.builder), which checks that the options can
+/// be used together:
+///
+/// ```
+/// use fasteval2::{Error, Evaler, Parser, Slab};
+///
+/// fn main() -> Result<(), Error> {
+///     let parser = Parser::builder().max_len(100).decimal_comma(true).build()?;
+///     let mut slab = Slab::new();
+///     let expr_ref = parser.parse("max(1,5; 2) * 2", &mut slab.ps)?.from(&slab.ps);
+///     assert_eq!(expr_ref.eval(&slab, &mut fasteval2::EmptyNamespace)?, 4.0);
+///
+///     assert!(matches!(
+///         Parser::builder().decimal_comma(true).thousands_separators(true).build(),
+///         Err(Error::InvalidConfig(_))
+///     ));
+///     Ok(())
+/// }
+/// ```
+///
+/// A `Parser` is immutable while parsing, so one can be shared by many threads.
+///
+/// The two public limits are deprecated: setting them directly skips the
+/// checks of [`ParserBuilder::build()`](struct.ParserBuilder.html#method.build).
+/// The other options are private, so they are only set through the builder.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Parser {
+    #[deprecated(note = "use `Parser::builder().max_len()`")]
     pub expr_len_limit: usize,
+    #[deprecated(note = "use `Parser::builder().max_depth()`")]
     pub expr_depth_limit: usize,
+
+    // Set by `ParserBuilder::thousands_separators()`:
+    thousands_separators: bool,
+
+    // Set by `ParserBuilder::decimal_comma()`:
+    decimal_comma: bool,
+
+    // Set by `ParserBuilder::record_spans()` or `Parser::with_spans()`:
+    record_spans: bool,
+
+    // Set by `ParserBuilder::tokenizer()`, or `None` for the defaults of
+    // `TokenizerConfig`:
+    tokenizer: Option<Arc<dyn TokenizerConfig>>,
+
+    // Set by `ParserBuilder::add_alias()`, as `(word, operator)` pairs:
+    aliases: Vec<(String, String)>,
+
+    // Set by `ParserBuilder::case_insensitive_funcs()`:
+    case_insensitive_funcs: bool,
+
+    // Set by `ParserBuilder::percent()`:
+    percent: bool,
+
+    // Set by `ParserBuilder::equals_is_comparison()`:
+    equals_is_comparison: bool,
+
+    // Set by `ParserBuilder::tuples()`:
+    tuples: bool,
+}
+
+/// Decides which characters make up identifiers (variable and function names)
+/// and operators.  Set it with
+/// [`ParserBuilder::tokenizer()`](struct.ParserBuilder.html#method.tokenizer).
+///
+/// Every method has a default that matches the `Parser::new()` grammar, so an
+/// implementation only overrides what it changes.  Because the start and the
+/// rest of an identifier are checked separately, a character can be allowed
+/// inside of identifiers while still being an operator everywhere else:
+///
+/// ```
+/// use fasteval2::{Error, Parser, Slab, TokenizerConfig};
+///
+/// struct Kebab;
+/// impl TokenizerConfig for Kebab {
+///     fn is_ident_continue(&self, c: char) -> bool {
+///         c == '-' || c.is_ascii_alphanumeric() || c == '_'
+///     }
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let parser = Parser::builder().tokenizer(Kebab).build()?;
+///     let mut ns = |name: &str, _: Vec<f64>| match name {
+///         "max-width" => Some(80.0),
+///         _ => None,
+///     };
+///     let mut slab = Slab::new();
+///     let expr_ref = parser.parse("-max-width - 2", &mut slab.ps)?.from(&slab.ps);
+///     assert_eq!(fasteval2::Evaler::eval(expr_ref, &slab, &mut ns)?, -82.0);
+///     Ok(())
+/// }
+/// ```
+///
+/// Numbers, string literals and the `or`/`and` keywords aren't affected.
+pub trait TokenizerConfig: Send + Sync {
+    /// Whether `c` can be the first character of an identifier.  The default
+    /// is `[a-zA-Z_]`.
+    fn is_ident_start(&self, c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_'
+    }
+
+    /// Whether `c` can be any character of an identifier after the first.  The
+    /// default is `[a-zA-Z_0-9]`.
+    fn is_ident_continue(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+
+    /// Whether `c` can start an operator where one is expected, like the `-` of
+    /// `-x` or `x - 1`.  The default allows all of `+-*/%^<>=!|&`.  Operators
+    /// that are turned off fail to parse, like any other unknown character.
+    fn is_operator(&self, c: char) -> bool {
+        "+-*/%^<>=!|&".contains(c)
+    }
+}
+
+// A TokenizerConfig has no state that can be shown or compared, so a Parser
+// just shows that it has one, and compares them by identity:
+impl fmt::Debug for dyn TokenizerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TokenizerConfig")
+    }
 }
+impl PartialEq for dyn TokenizerConfig {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(
+            self as *const Self as *const u8,
+            other as *const Self as *const u8,
+        )
+    }
+}
+
+/// Configures a [`Parser`](struct.Parser.html).  Created by
+/// [`Parser::builder()`](struct.Parser.html#method.builder).
+///
+/// Each setter returns the builder, and [`build()`](#method.build) returns the
+/// finished `Parser`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParserBuilder {
+    parser: Parser,
+}
+
+#[allow(deprecated)]
+impl ParserBuilder {
+    /// Sets the longest expression string, in bytes, that can be parsed.
+    /// Longer strings produce a `TooLong` error.  The default is
+    /// [`DEFAULT_EXPR_LEN_LIMIT`](constant.DEFAULT_EXPR_LEN_LIMIT.html).
+    #[inline]
+    pub fn max_len(mut self, n: usize) -> Self {
+        self.parser.expr_len_limit = n;
+        self
+    }
+
+    /// Sets the deepest nesting of parentheses, unary operators and function
+    /// calls that can be parsed.  Deeper expressions produce a `TooDeep` error.
+    /// The default is [`DEFAULT_EXPR_DEPTH_LIMIT`](constant.DEFAULT_EXPR_DEPTH_LIMIT.html).
+    #[inline]
+    pub fn max_depth(mut self, n: usize) -> Self {
+        self.parser.expr_depth_limit = n;
+        self
+    }
 
+    /// Accept numbers with thousands separators, like `1,234.56`.
+    ///
+    /// Digit groups after the first must contain exactly three digits, so
+    /// `1,23` is a `ParseF64` error.  Since ',' is then part of numbers, it can
+    /// no longer be used to separate function arguments -- use ';' instead:
+    /// `max(1,000; 2,000)`.
+    #[inline]
+    pub fn thousands_separators(mut self, on: bool) -> Self {
+        self.parser.thousands_separators = on;
+        self
+    }
+
+    /// Accept numbers with a decimal comma, like `1,5`, instead of a decimal
+    /// point.  Function arguments are then separated with ';': `max(1,5; 2,5)`.
+    ///
+    /// This can't be combined with `thousands_separators`.
+    #[inline]
+    pub fn decimal_comma(mut self, on: bool) -> Self {
+        self.parser.decimal_comma = on;
+        self
+    }
+
+    /// Record the byte range of the source string that each node was parsed
+    /// from, for [`ExpressionI::span()`](../slab/struct.ExpressionI.html#method.span)
+    /// and [`ExpressionI::node_at()`](../slab/struct.ExpressionI.html#method.node_at).
+    #[inline]
+    pub fn record_spans(mut self, on: bool) -> Self {
+        self.parser.record_spans = on;
+        self
+    }
+
+    /// Read the word `word` as the operator `operator`, which is one of the
+    /// binary operators like `"%"` or `"&&"`, or `"!"`:
+    ///
+    /// ```
+    /// use fasteval2::{Error, Evaler, Parser, Slab};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let parser = Parser::builder().add_alias("mod", "%").add_alias("not", "!").build()?;
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = parser.parse("not 0 + 5 mod 3", &mut slab.ps)?.from(&slab.ps);
+    ///     assert_eq!(expr_ref.eval(&slab, &mut fasteval2::EmptyNamespace)?, 3.0);
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// Only whole words are aliases, so `modulus` is still a variable.  An
+    /// alias is only read as an operator where that operator can be, so an
+    /// alias of a binary operator is still a name where a value is expected.
+    /// `word` must be a valid `VarName` and `operator` a known operator, or
+    /// `build()` fails.
+    #[inline]
+    pub fn add_alias(mut self, word: impl Into<String>, operator: impl Into<String>) -> Self {
+        self.parser.aliases.push((word.into(), operator.into()));
+        self
+    }
+
+    /// Match the names of function calls case-insensitively (ASCII only), for
+    /// formulas like `SIN(X) + MAX(A, B)` from spreadsheets.
+    ///
+    /// The names of calls are stored in lowercase, which is the spelling that
+    /// errors, `var_names()` and the Namespace get.  Custom functions must be
+    /// defined in lowercase, or with
+    /// [`EvalNSBuilder::case_insensitive_funcs()`](../evalns/struct.EvalNSBuilder.html#method.case_insensitive_funcs).
+    /// Variables (names without parentheses) are left alone, so their case
+    /// is up to the Namespace, and spans still point at the original spelling.
+    #[inline]
+    pub fn case_insensitive_funcs(mut self, on: bool) -> Self {
+        self.parser.case_insensitive_funcs = on;
+        self
+    }
+
+    /// Read a `%` right after a number as a percent sign, so `20%` is `0.2`.
+    /// Together with an alias of `*`, this reads phrases like `20% of 100`:
+    ///
+    /// ```
+    /// use fasteval2::{Error, Evaler, Parser, Slab};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let parser = Parser::builder().percent(true).add_alias("of", "*").build()?;
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = parser.parse("20% of 100 + 50%", &mut slab.ps)?.from(&slab.ps);
+    ///     assert_eq!(expr_ref.eval(&slab, &mut fasteval2::EmptyNamespace)?, 20.5);
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// The `%` is only a percent sign if an operator, a closing bracket, a
+    /// separator or the end follows it, so `20%3` and `x % 3` are still
+    /// remainders.  Write a space in front of a negative divisor, like
+    /// `20 % -3`, because `20%-3` is `20% - 3`.
+    #[inline]
+    pub fn percent(mut self, on: bool) -> Self {
+        self.parser.percent = on;
+        self
+    }
+
+    /// Read a single `=` as the `==` comparison, for spreadsheet formulas like
+    /// `A1 = 1`.  `==` keeps working, and `<=`, `>=` and `!=` are unchanged.
+    /// Without this option, a single `=` is a parse error.
+    #[inline]
+    pub fn equals_is_comparison(mut self, on: bool) -> Self {
+        self.parser.equals_is_comparison = on;
+        self
+    }
+
+    /// Read items that are separated by `,` or `;` at the top level as a
+    /// tuple, like the `x * 2, y * 2` of a point.  Evaluate tuples with
+    /// [`Expression::eval_vec()`](struct.Expression.html#method.eval_vec):
+    ///
+    /// ```
+    /// use fasteval2::{Error, Parser, Slab};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let parser = Parser::builder().tuples(true).build()?;
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = parser.parse("3, max(4, 1)", &mut slab.ps)?.from(&slab.ps);
+    ///     assert_eq!(expr_ref.eval_vec(&slab, &mut fasteval2::EmptyNamespace)?, vec![3.0, 4.0]);
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// Separators inside of parentheses still separate function arguments,
+    /// so a tuple can't be nested.  With a `,` in numbers, only `;` separates
+    /// items.  Without this option, a top-level `,` is a parse error.
+    #[inline]
+    pub fn tuples(mut self, on: bool) -> Self {
+        self.parser.tuples = on;
+        self
+    }
+
+    /// Use `config` to decide which characters make up identifiers and
+    /// operators.  See [`TokenizerConfig`](trait.TokenizerConfig.html).
+    #[inline]
+    pub fn tokenizer(mut self, config: impl TokenizerConfig + 'static) -> Self {
+        self.parser.tokenizer = Some(Arc::new(config));
+        self
+    }
+
+    /// Returns the configured `Parser`, or an
+    /// [`InvalidConfig`](../error/enum.Error.html#variant.InvalidConfig) error
+    /// if the options can't be used together.
+    pub fn build(self) -> Result<Parser, Error> {
+        let parser = self.parser;
+        if parser.decimal_comma && parser.thousands_separators {
+            return Err(Error::InvalidConfig(
+                "decimal_comma and thousands_separators both use ','".to_string(),
+            ));
+        }
+        for (word, op) in &parser.aliases {
+            if word.is_empty() || parser.varname_len(word.as_bytes()) != word.len() {
+                return Err(Error::InvalidConfig(format!(
+                    "alias {:?} is not a name",
+                    word
+                )));
+            }
+            if binaryop_from_symbol(op).is_none() && op != "!" {
+                return Err(Error::InvalidConfig(format!(
+                    "alias {:?}: {:?} is not an operator",
+                    word, op
+                )));
+            }
+        }
+        if parser.expr_len_limit == 0 || parser.expr_depth_limit == 0 {
+            return Err(Error::InvalidConfig(
+                "max_len and max_depth must be at least 1".to_string(),
+            ));
+        }
+        Ok(parser)
+    }
+}
+
+#[allow(deprecated)]
 impl Parser {
     #[inline]
     pub const fn new() -> Self {
         Self {
             expr_len_limit: DEFAULT_EXPR_LEN_LIMIT,
             expr_depth_limit: DEFAULT_EXPR_DEPTH_LIMIT,
+            thousands_separators: false,
+            decimal_comma: false,
+            record_spans: false,
+            tokenizer: None,
+            aliases: Vec::new(),
+            case_insensitive_funcs: false,
+            percent: false,
+            equals_is_comparison: false,
+            tuples: false,
         }
     }
 
+    /// Returns a [`ParserBuilder`](struct.ParserBuilder.html) that starts with
+    /// the options of `Parser::new()`.
+    #[inline]
+    pub const fn builder() -> ParserBuilder {
+        ParserBuilder {
+            parser: Self::new(),
+        }
+    }
+
+    /// Returns this `Parser` with [`record_spans`](struct.ParserBuilder.html#method.record_spans)
+    /// enabled.
+    #[inline]
+    pub fn with_spans(mut self) -> Self {
+        self.record_spans = true;
+        self
+    }
+
     fn is_varname_byte(b: u8, i: usize) -> bool {
-        (b'A' <= b && b <= b'Z')
-            || (b'a' <= b && b <= b'z')
+        b.is_ascii_uppercase()
+            || b.is_ascii_lowercase()
             || b == b'_'
-            || (i > 0 && (b'0' <= b && b <= b'9'))
+            || (i > 0 && b.is_ascii_digit())
     }
     fn is_varname_byte_opt(bo: Option<u8>, i: usize) -> bool {
         match bo {
@@ -297,7 +933,36 @@ impl Parser {
         }
     }
 
+    // The length in bytes of the identifier at the start of `bs`, as decided by `config`:
+    fn ident_len(config: &dyn TokenizerConfig, bs: &[u8]) -> usize {
+        let mut toklen = 0;
+        while let Some(c) = peek_char(&bs[toklen..]) {
+            let ok = if toklen == 0 {
+                config.is_ident_start(c)
+            } else {
+                config.is_ident_continue(c)
+            };
+            if !ok {
+                break;
+            }
+            toklen += c.len_utf8();
+        }
+        toklen
+    }
+
+    // Whether `b` can start an operator.  Only symbols are checked, so the
+    // `or`/`and` keywords stay operators:
+    fn is_operator_byte(&self, b: u8) -> bool {
+        match &self.tokenizer {
+            None => true,
+            Some(config) => !b.is_ascii_punctuation() || config.is_operator(b as char),
+        }
+    }
+
     /// Use this function to parse an expression String.  The `Slab` will be cleared first.
+    ///
+    /// Empty and whitespace-only strings produce an
+    /// [`EmptyExpression`](../error/enum.Error.html#variant.EmptyExpression) error.
     #[inline]
     pub fn parse(&self, expr_str: &str, slab: &mut ParseSlab) -> Result<ExpressionI, Error> {
         slab.clear();
@@ -315,12 +980,530 @@ impl Parser {
         &self,
         expr_str: &str,
         slab: &mut ParseSlab,
+    ) -> Result<ExpressionI, Error> {
+        self.parse_bs(expr_str, slab, &mut expr_str.as_bytes(), true)
+    }
+
+    /// Parses the expression at the start of `expr_str`, and returns it with
+    /// the number of bytes that it takes up.  The rest of `expr_str` is left
+    /// for the caller, which is useful for expressions embedded in a larger
+    /// syntax.  The `Slab` will be cleared first.
+    ///
+    /// Parsing stops at the first thing that can't continue the expression,
+    /// like a `}` or a `,` outside of parentheses.  A binary operator that
+    /// isn't followed by a value isn't consumed either, so `1 + }` consumes
+    /// `1`.  Whitespace before the expression is counted, but whitespace after
+    /// it isn't.
+    ///
+    /// Anything that would make `parse()` fail inside of the expression is
+    /// still an error, like the unclosed parenthesis of `1 + (2 }`, and so is
+    /// input that doesn't start with an expression at all.  `parse()` is the
+    /// same as `parse_partial()`, except that it fails if `parse_partial()`
+    /// would stop before the end of the input.  The length limit applies to
+    /// all of `expr_str`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Error, Evaler, Parser, Slab};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut slab = Slab::new();
+    ///     let template = "{{ 1 + 2 * 3 }} apples";
+    ///     let (expr_i, len) = Parser::new().parse_partial(&template[2..], &mut slab.ps)?;
+    ///     assert_eq!(&template[2 + len..], " }} apples");
+    ///     assert_eq!(expr_i.from(&slab.ps).eval(&slab, &mut fasteval2::EmptyNamespace)?, 7.0);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn parse_partial(
+        &self,
+        expr_str: &str,
+        slab: &mut ParseSlab,
+    ) -> Result<(ExpressionI, usize), Error> {
+        slab.clear();
+        let mut bs = expr_str.as_bytes();
+        let expr_i = self.parse_bs(expr_str, slab, &mut bs, false)?;
+        let consumed = &expr_str.as_bytes()[..expr_str.len() - bs.len()];
+        Ok((expr_i, trim_blank_end(consumed).len()))
+    }
+
+    // Parses `expr_str`, leaving `bs` where the parser stopped.  Without
+    // `expect_eof`, it stops at the end of the leading expression:
+    #[inline]
+    fn parse_bs(
+        &self,
+        expr_str: &str,
+        slab: &mut ParseSlab,
+        bs: &mut &[u8],
+        expect_eof: bool,
     ) -> Result<ExpressionI, Error> {
         if expr_str.len() > self.expr_len_limit {
             return Err(Error::TooLong);
         } // Restrict length for safety
+        spaces!(bs);
+        if bs.is_empty() {
+            return Err(Error::EmptyExpression);
+        }
+        slab.spans.src_len = expr_str.len();
+        let res = self.read_expression(slab, bs, 0, expect_eof);
+        if let Ok(expr_i) = &res {
+            debug_assert!(
+                well_formed(slab, *expr_i),
+                "malformed parse of {:?}",
+                expr_str
+            );
+        }
+        res
+    }
+
+    /// Like `parse()`, but a failure also reports where the problem is in
+    /// `expr_str`, as a [`SpannedError`](../error/struct.SpannedError.html).
+    ///
+    /// The span covers the innermost value that failed to parse (like a
+    /// function call with the wrong number of arguments), or the offending
+    /// character if the problem is in between values.  Errors at the end of
+    /// the input have an empty span at `expr_str.len()`, and `TooLong` and
+    /// `EmptyExpression` errors span the whole input.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Arity, Error, Parser, Slab};
+    /// let mut slab = Slab::new();
+    /// let err = Parser::new().parse_spanned("1 + sin(1, 2)", &mut slab.ps).unwrap_err();
+    /// assert_eq!(
+    ///     err.error,
+    ///     Error::WrongArgCount { name: "sin".to_string(), expected: Arity::Exact(1), got: 2 }
+    /// );
+    /// assert_eq!(err.span, 4..13);
+    /// ```
+    pub fn parse_spanned(
+        &self,
+        expr_str: &str,
+        slab: &mut ParseSlab,
+    ) -> Result<ExpressionI, SpannedError> {
+        slab.clear();
+        slab.err_span = None;
         let mut bs = expr_str.as_bytes();
-        self.read_expression(slab, &mut bs, 0, true)
+        match self.parse_bs(expr_str, slab, &mut bs, true) {
+            Ok(expr_i) => Ok(expr_i),
+            Err(err) => Err(spanned(
+                expr_str,
+                err,
+                slab.err_span.unwrap_or((bs.len(), bs.len())),
+            )),
+        }
+    }
+
+    /// Parses a list of expressions separated by `sep`, like
+    /// `"x+1, y*2, max(z, 1)"`.  The `Slab` will be cleared first, and it holds
+    /// all of the expressions afterwards.
+    ///
+    /// Only the separators outside of parentheses, brackets and strings split
+    /// the list, so `sep` can be `,` even though argument lists use it too.
+    /// Whitespace around the items is ignored.  An empty item, like the one
+    /// after a trailing separator, is an `EmptyExpression` error that spans
+    /// the space between its separators.  The other errors are reported like
+    /// `parse_spanned()` reports them, at their position in `s`, and the length
+    /// limit applies to all of `s`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Error, Evaler, Parser, Slab};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut slab = Slab::new();
+    ///     let exprs = Parser::new().parse_list("1 + 2, max(3, 4) * 2", ',', &mut slab.ps)?;
+    ///     let vals = exprs
+    ///         .iter()
+    ///         .map(|expr_i| expr_i.from(&slab.ps).eval(&slab, &mut fasteval2::EmptyNamespace))
+    ///         .collect::<Result<Vec<_>, _>>()?;
+    ///     assert_eq!(vals, vec![3.0, 8.0]);
+    ///
+    ///     let err = Parser::new().parse_list("1 + 2, , 3", ',', &mut slab.ps).unwrap_err();
+    ///     assert_eq!((err.error, err.span), (Error::EmptyExpression, 6..7));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn parse_list(
+        &self,
+        s: &str,
+        sep: char,
+        slab: &mut ParseSlab,
+    ) -> Result<Vec<ExpressionI>, SpannedError> {
+        slab.clear();
+        if s.len() > self.expr_len_limit {
+            return Err(spanned(s, Error::TooLong, (0, 0)));
+        }
+        let mut exprs = Vec::new();
+        for (start, end) in split_list(s, sep) {
+            let mut bs = &s.as_bytes()[start..end];
+            let rest = &mut &bs[..];
+            spaces!(rest);
+            if rest.is_empty() {
+                return Err(SpannedError {
+                    error: Error::EmptyExpression,
+                    span: start..end,
+                });
+            }
+            // Parse the item as the end of `s[..end]`, so that the spans are
+            // positions in `s`:
+            slab.err_span = None;
+            match self.parse_bs(&s[..end], slab, &mut bs, true) {
+                Ok(expr_i) => exprs.push(expr_i),
+                Err(err) => {
+                    return Err(spanned(
+                        &s[..end],
+                        err,
+                        slab.err_span.unwrap_or((bs.len(), bs.len())),
+                    ))
+                }
+            }
+        }
+        Ok(exprs)
+    }
+
+    /// Parses whitespace-separated postfix (Reverse Polish Notation) tokens,
+    /// like `"3 4 + 5 *"`, into a normal `Expression` that evaluates like the
+    /// infix form, `(3 + 4) * 5`.  The `Slab` will be cleared first.
+    ///
+    /// The tokens are the ones that
+    /// [`Expression::to_rpn()`](struct.Expression.html#method.to_rpn) produces,
+    /// written like [`RpnToken`](../rpn/enum.RpnToken.html)s are displayed:
+    ///
+    /// * Numbers, like `2`, `-0.5`, `1e-3`, `inf` and `NaN`.
+    /// * Variable names.
+    /// * The binary operators `+ - * / % ^ < <= == != >= > || &&`.
+    /// * Calls, written as `name/argc`, like `max/3`.  `neg/1` and `not/1` are
+    ///   the unary `-` and `!` operators.
+    ///
+    /// An operator or call without enough operands on the stack is an error
+    /// that spans the token, and so is a token that isn't one of the above.  If
+    /// more than one value is left on the stack at the end, the error spans the
+    /// extra values.  `print()` can't be written in postfix, and the length
+    /// and depth limits apply like they apply to `parse()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Error, Evaler, Parser, Slab};
+    /// let mut slab = Slab::new();
+    /// let parser = Parser::new();
+    ///
+    /// let expr_i = parser.parse_rpn("3 x + 5 * 2 sqrt/1 max/2", &mut slab.ps).unwrap();
+    /// let mut ns = |_: &str, _: Vec<f64>| Some(4.0);
+    /// assert_eq!(expr_i.from(&slab.ps).eval(&slab, &mut ns), Ok(35.0));
+    ///
+    /// let err = parser.parse_rpn("1 2 + *", &mut slab.ps).unwrap_err();
+    /// assert_eq!((err.error, err.span), (Error::Expected("2 values".to_string()), 6..7));
+    /// ```
+    /// Splits `src` into tokens, without building an expression.
+    ///
+    /// Numbers, operators and names are read the way that `parse()` reads
+    /// them, with the same options, so `-` is a binary operator after a value
+    /// and a sign or unary operator before one.  The structure isn't checked:
+    /// `(1 +` yields three tokens.  Whitespace isn't a token.
+    ///
+    /// The iterator stops after the first error, which is `InvalidValue` for
+    /// something that isn't a value where one is expected, and
+    /// `UnparsedTokensRemaining` for something that isn't an operator after
+    /// a value.  Inputs that are longer than the length limit only yield
+    /// `TooLong`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::parser::{BinaryOp, TokenKind};
+    /// use fasteval2::Parser;
+    ///
+    /// let tokens: Vec<_> = Parser::new().tokens("max(x, -2)").map(|t| t.unwrap().kind).collect();
+    /// assert_eq!(
+    ///     tokens,
+    ///     vec![
+    ///         TokenKind::Ident("max".to_string()),
+    ///         TokenKind::OpenParen,
+    ///         TokenKind::Ident("x".to_string()),
+    ///         TokenKind::Comma,
+    ///         TokenKind::Number(-2.0),
+    ///         TokenKind::CloseParen,
+    ///     ]
+    /// );
+    ///
+    /// let spans: Vec<_> = Parser::new().tokens("1 <= 2").map(|t| t.unwrap().span).collect();
+    /// assert_eq!(spans, vec![0..1, 2..4, 5..6]);
+    /// ```
+    pub fn tokens<'a>(&'a self, src: &'a str) -> Tokens<'a> {
+        Tokens {
+            parser: self,
+            src,
+            bs: src.as_bytes(),
+            want_value: true,
+            after_name: false,
+            after_let: false,
+            after_let_name: false,
+            char_buf: String::new(),
+            done: false,
+        }
+    }
+
+    pub fn parse_rpn(&self, s: &str, slab: &mut ParseSlab) -> Result<ExpressionI, SpannedError> {
+        slab.clear();
+        let whole = |error| SpannedError {
+            error,
+            span: 0..s.len(),
+        };
+        if s.len() > self.expr_len_limit {
+            return Err(whole(Error::TooLong));
+        }
+        // The operands, with their spans and depths:
+        let mut stack = Vec::<(ExpressionI, Range<usize>, usize)>::new();
+        let mut pos = 0;
+        for tok in s.split(|c: char| c.is_ascii_whitespace()) {
+            let span = pos..pos + tok.len();
+            pos = span.end + 1;
+            if tok.is_empty() {
+                continue;
+            }
+            self.rpn_token(tok, span.clone(), slab, &mut stack)
+                .map_err(|error| SpannedError { error, span })?;
+        }
+        match &stack[..] {
+            [] => Err(whole(Error::EmptyExpression)),
+            [(expr_i, ..)] => {
+                debug_assert!(well_formed(slab, *expr_i), "malformed parse of {:?}", s);
+                Ok(*expr_i)
+            }
+            [_, extra @ ..] => {
+                let start = extra.first().map_or(0, |(_, span, _)| span.start);
+                let end = extra.last().map_or(0, |(_, span, _)| span.end);
+                Err(SpannedError {
+                    error: Error::Expected("an operator".to_string()),
+                    span: start..end,
+                })
+            }
+        }
+    }
+
+    fn rpn_token(
+        &self,
+        tok: &str,
+        span: Range<usize>,
+        slab: &mut ParseSlab,
+        stack: &mut Vec<(ExpressionI, Range<usize>, usize)>,
+    ) -> Result<(), Error> {
+        let is_name =
+            |name: &str| !name.is_empty() && self.varname_len(name.as_bytes()) == name.len();
+        let call = tok
+            .rsplit_once('/')
+            .filter(|(name, _)| is_name(name))
+            .and_then(|(name, argc)| Some((name, argc.parse::<usize>().ok()?)));
+        let argc = match (binaryop_from_symbol(tok), call) {
+            (Some(_), _) => 2,
+            (None, Some((_, argc))) => argc,
+            (None, None) => {
+                let val = match rpn_number(tok) {
+                    Some(f) => EConstant(f),
+                    None if is_name(tok) => {
+                        #[cfg(feature = "unsafe-vars")]
+                        let var = match slab.unsafe_vars.get(tok) {
+                            None => EVar(Name::from(tok)),
+                            Some(&ptr) => EUnsafeVar {
+                                name: Name::from(tok),
+                                ptr,
+                            },
+                        };
+                        #[cfg(not(feature = "unsafe-vars"))]
+                        let var = EVar(Name::from(tok));
+                        EStdFunc(var)
+                    }
+                    None => return Err(Error::InvalidValue),
+                };
+                let expr_i = slab.push_expr(Expression {
+                    first: val,
+                    pairs: Pairs::new(),
+                    tuple: Vec::new(),
+                })?;
+                stack.push((expr_i, span, 1));
+                return Ok(());
+            }
+        };
+        if stack.len() < argc {
+            return Err(Error::Expected(if argc == 1 {
+                "1 value".to_string()
+            } else {
+                format!("{} values", argc)
+            }));
+        }
+        let args = stack.split_off(stack.len() - argc);
+        let depth = 1 + args.iter().map(|(_, _, depth)| *depth).max().unwrap_or(0);
+        if depth > self.expr_depth_limit {
+            return Err(Error::TooDeep);
+        }
+        let start = args.first().map_or(span.start, |(_, span, _)| span.start);
+        let mut xis = args.into_iter().map(|(expr_i, ..)| expr_i);
+        let paren =
+            |expr_i: Option<ExpressionI>| expr_i.map(EParentheses).ok_or(Error::Unreachable);
+
+        let expr = match (binaryop_from_symbol(tok), call) {
+            (Some(op), _) => Expression {
+                first: EUnaryOp(paren(xis.next())?),
+                pairs: smallvec![ExprPair(op, EUnaryOp(paren(xis.next())?))],
+                tuple: Vec::new(),
+            },
+            (None, Some((name, _))) => {
+                let name = if self.case_insensitive_funcs {
+                    Name::from(name.to_ascii_lowercase())
+                } else {
+                    Name::from(name)
+                };
+                let first = match (name.as_str(), argc) {
+                    ("neg", 1) => EUnaryOp(ENeg(slab.push_val(EUnaryOp(paren(xis.next())?))?)),
+                    ("not", 1) => EUnaryOp(ENot(slab.push_val(EUnaryOp(paren(xis.next())?))?)),
+                    ("print", _) => return Err(Error::NotAllowed("print".to_string())),
+                    _ => EStdFunc(Self::build_func(name, xis.collect(), slab)?),
+                };
+                Expression {
+                    first,
+                    pairs: Pairs::new(),
+                    tuple: Vec::new(),
+                }
+            }
+            (None, None) => return Err(Error::Unreachable),
+        };
+        stack.push((slab.push_expr(expr)?, start..span.end, depth));
+        Ok(())
+    }
+
+    /// Like `parse()`, but continues past recoverable syntax errors and
+    /// collects all of them, rather than stopping at the first one.
+    ///
+    /// Invalid values are replaced by [`EError`](enum.Value.html#variant.EError)
+    /// placeholders, so the returned expression can still be inspected, but
+    /// evaluating it will return the first error that it contains.  If an
+    /// unrecoverable error occurs (for example, an unbalanced parenthesis or
+    /// the expression is too long or too deep), no expression is returned.
+    ///
+    /// Unexpected input inside of parentheses and argument lists is skipped, up
+    /// to the next `,`, `;` or closing parenthesis, and replaced by a
+    /// placeholder too.  For example, `max(1 2, 3)` reports a missing `,` and then
+    /// continues with the `3`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Error, Evaler, Parser, Slab};
+    /// let mut slab = Slab::new();
+    /// let (expr_opt, errs) = Parser::new().parse_with_recovery("1 + * 2 * * 3", &mut slab.ps);
+    /// assert_eq!(errs, vec![Error::InvalidValue, Error::InvalidValue]);
+    /// let expr_ref = expr_opt.unwrap().from(&slab.ps);
+    /// assert_eq!(expr_ref.eval(&slab, &mut fasteval2::EmptyNamespace), Err(Error::InvalidValue));
+    /// ```
+    pub fn parse_with_recovery(
+        &self,
+        expr_str: &str,
+        slab: &mut ParseSlab,
+    ) -> (Option<ExpressionI>, Vec<Error>) {
+        slab.clear();
+        slab.recovered_errs = Some(Vec::new());
+        let res = self.parse_noclear(expr_str, slab);
+        let mut errs: Vec<Error> = slab
+            .recovered_errs
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(err, _)| err)
+            .collect();
+        match res {
+            Ok(expr_i) => (Some(expr_i), errs),
+            Err(err) => {
+                errs.push(err);
+                (None, errs)
+            }
+        }
+    }
+
+    /// Like [`parse_with_recovery()`](#method.parse_with_recovery), but returns
+    /// every error with its span, as
+    /// [`SpannedError`](../error/struct.SpannedError.html)s ordered by where
+    /// they start in `expr_str`.  An expression is only returned if there are
+    /// no errors at all.
+    ///
+    /// This is useful for editors, which can show all of the problems at once.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Error, Parser, Slab};
+    /// let mut slab = Slab::new();
+    /// let errs = Parser::new().parse_all_errors("max(1 2, 3) + * 4 + (5 6)", &mut slab.ps).unwrap_err();
+    /// let spans: Vec<_> = errs.iter().map(|err| err.span.clone()).collect();
+    /// assert_eq!(spans, vec![6..7, 14..15, 23..24]);
+    /// assert_eq!(errs[1].error, Error::InvalidValue);
+    /// ```
+    pub fn parse_all_errors(
+        &self,
+        expr_str: &str,
+        slab: &mut ParseSlab,
+    ) -> Result<ExpressionI, Vec<SpannedError>> {
+        slab.clear();
+        slab.err_span = None;
+        slab.recovered_errs = Some(Vec::new());
+        let mut bs = expr_str.as_bytes();
+        let res = self.parse_bs(expr_str, slab, &mut bs, true);
+        let mut errs: Vec<SpannedError> = slab
+            .recovered_errs
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(err, rem)| spanned(expr_str, err, rem))
+            .collect();
+        match res {
+            Ok(expr_i) if errs.is_empty() => return Ok(expr_i),
+            Ok(_) => {}
+            Err(err) => errs.push(spanned(
+                expr_str,
+                err,
+                slab.err_span.unwrap_or((bs.len(), bs.len())),
+            )),
+        }
+        // Enclosing values are reported after the errors inside of them:
+        errs.sort_by_key(|err| err.span.start);
+        Err(errs)
+    }
+
+    // In recovery mode, records `err` and skips the input from `from` up to the
+    // next byte in `stops` that isn't nested in brackets, and returns a
+    // placeholder for the skipped input.  Otherwise, or if there is nowhere to
+    // skip to, returns `err` and leaves `bs` alone.
+    fn resync<'a>(
+        &self,
+        slab: &mut ParseSlab,
+        bs: &mut &'a [u8],
+        from: &'a [u8],
+        err: Error,
+        stops: &[u8],
+    ) -> Result<ExpressionI, Error> {
+        let errs = match &mut slab.recovered_errs {
+            Some(errs) => errs,
+            None => return Err(err),
+        };
+        let mut nesting = 0usize;
+        let mut i = 0;
+        loop {
+            match from.get(i) {
+                None => return Err(err),
+                Some(b) if nesting == 0 && stops.contains(b) => break,
+                Some(b'(' | b'[') => nesting += 1,
+                // It closes an enclosing group:
+                Some(b')' | b']') if nesting == 0 => return Err(err),
+                Some(b')' | b']') => nesting -= 1,
+                Some(_) => {}
+            }
+            i += 1;
+        }
+        let skipped = from[..i].trim_ascii_end().len();
+        errs.push((err.clone(), (from.len(), from.len() - skipped)));
+        *bs = &from[i..];
+        slab.push_expr(Expression {
+            first: EError(err),
+            pairs: Pairs::new(),
+            tuple: Vec::new(),
+        })
     }
 
     fn read_expression(
@@ -334,26 +1517,123 @@ impl Parser {
             return Err(Error::TooDeep);
         }
 
-        let first = self.read_value(slab, bs, depth)?;
-        let mut pairs = Vec::<ExprPair>::with_capacity(8);
+        let mut spans = Vec::new();
+        let from = *bs;
+        let mut first = self.read_value(slab, bs, depth)?;
+        if self.record_spans {
+            spans.push(consumed_span(slab, from, bs));
+        }
+        // The top level of parse_partial() doesn't expect EOF:
+        let partial = depth == 0 && !expect_eof;
+        let mut pairs = Pairs::new();
         loop {
+            let before_op = *bs;
+            spaces!(bs);
+            if let Some((toklen, negated)) = self.membership_len(bs) {
+                skip_n!(bs, toklen);
+                // The tested value is everything back to the last `&&` or `||`:
+                let k = pairs
+                    .iter()
+                    .rposition(|pair| matches!(pair.0, EAND | EOR))
+                    .map_or(0, |i| i + 1);
+                let rest: Pairs = pairs.drain(k..).collect();
+                let slot = match k {
+                    0 => &mut first,
+                    _ => &mut pairs[k - 1].1,
+                };
+                let value = slab.push_expr(Expression {
+                    first: mem::replace(slot, EConstant(0.0)),
+                    pairs: rest,
+                    tuple: Vec::new(),
+                })?;
+                let mut start = 0;
+                if self.record_spans {
+                    let value_spans = spans.split_off(k);
+                    start = value_spans.first().map_or(0, |span| span.0);
+                    slab.spans.set_expr(value, value_spans);
+                }
+                let list = self.read_membership_list(slab, bs, depth)?;
+                *slot = EStdFunc(EFuncIn {
+                    negated,
+                    value,
+                    list,
+                });
+                if self.record_spans {
+                    spans.push((start, slab.spans.src_len - bs.len()));
+                }
+                continue;
+            }
             match self.read_binaryop(bs)? {
                 Pass => break,
                 Bite(bop) => {
-                    let val = self.read_value(slab, bs, depth)?;
+                    let from = *bs;
+                    let val = match self.read_value(slab, bs, depth) {
+                        // A trailing operator is left for the caller:
+                        Err(_) if partial && only_spaces(from, bs) => {
+                            *bs = before_op;
+                            slab.err_span = None;
+                            break;
+                        }
+                        res => res?,
+                    };
+                    if self.record_spans {
+                        spans.push(consumed_span(slab, from, bs));
+                    }
                     pairs.push(ExprPair(bop, val));
                 }
             }
         }
+        // The other items of a top-level tuple:
+        let mut tuple = Vec::new();
+        if depth == 0 && self.tuples {
+            loop {
+                let mut look = *bs;
+                let look = &mut look;
+                spaces!(look);
+                match peek!(look) {
+                    Some(b',') if !self.comma_in_numbers() => {}
+                    Some(b';') => {}
+                    _ => break,
+                }
+                skip!(look);
+                *bs = look;
+                tuple.push(self.read_expression(slab, bs, 1, false)?);
+            }
+        }
         spaces!(bs);
         if expect_eof && !bs.is_empty() {
-            let bs_str = match from_utf8(bs) {
-                Ok(s) => s,
-                Err(..) => "Utf8Error while handling UnparsedTokensRemaining error",
-            };
-            return Err(Error::UnparsedTokensRemaining(bs_str.to_string()));
+            let bs_str =
+                from_utf8(bs).unwrap_or("Utf8Error while handling UnparsedTokensRemaining error");
+            let err = Error::UnparsedTokensRemaining(bs_str.to_string());
+            match &mut slab.recovered_errs {
+                Some(errs) => errs.push((err, (bs.len(), 0))),
+                None => return Err(err),
+            }
+        }
+        let expr_i = slab.push_expr(Expression {
+            first,
+            pairs,
+            tuple,
+        })?;
+        if self.record_spans {
+            slab.spans.set_expr(expr_i, spans);
         }
-        Ok(slab.push_expr(Expression { first, pairs })?)
+        Ok(expr_i)
+    }
+
+    fn push_val(
+        &self,
+        slab: &mut ParseSlab,
+        val: Value,
+        from: &[u8],
+        bs: &[u8],
+    ) -> Result<ValueI, Error> {
+        let val_i = slab.push_val(val)?;
+        if self.record_spans {
+            let span = consumed_span(slab, from, bs);
+            slab.spans.set_val(val_i, span);
+        }
+        Ok(val_i)
     }
 
     fn read_value(
@@ -361,12 +1641,51 @@ impl Parser {
         slab: &mut ParseSlab,
         bs: &mut &[u8],
         depth: usize,
+    ) -> Result<Value, Error> {
+        let start = bs.len();
+        let err = match self.read_value_norecover(slab, bs, depth) {
+            Ok(val) => return Ok(val),
+            Err(err) => err,
+        };
+        // The innermost value that fails is the most precise location:
+        if slab.err_span.is_none() {
+            slab.err_span = Some((start, bs.len()));
+        }
+        if slab.recovered_errs.is_none() {
+            return Err(err);
+        }
+        match err {
+            Error::InvalidValue => {
+                // Skip over the garbage, up to something that could follow a value:
+                while let Some(b) = peek!(bs) {
+                    if b"+-*/%^<>=!|&)],;".contains(&b) {
+                        break;
+                    }
+                    skip!(bs);
+                }
+            }
+            // These are detected after the entire value has been consumed:
+            Error::WrongArgs(_) | Error::WrongArgCount { .. } | Error::ParseF64(_) => {}
+            _ => return Err(err),
+        }
+        let rem = slab.err_span.take().unwrap_or((start, start));
+        if let Some(errs) = &mut slab.recovered_errs {
+            errs.push((err.clone(), rem));
+        }
+        Ok(EError(err))
+    }
+
+    fn read_value_norecover(
+        &self,
+        slab: &mut ParseSlab,
+        bs: &mut &[u8],
+        depth: usize,
     ) -> Result<Value, Error> {
         if depth > self.expr_depth_limit {
             return Err(Error::TooDeep);
         }
 
-        match Self::read_const(slab, bs)? {
+        match self.read_const(&mut slab.char_buf, bs)? {
             Pass => {}
             Bite(c) => return Ok(EConstant(c)),
         }
@@ -387,7 +1706,7 @@ impl Parser {
         Err(Error::InvalidValue)
     }
 
-    fn read_const(slab: &mut ParseSlab, bs: &mut &[u8]) -> Result<Token<f64>, Error> {
+    fn read_const(&self, char_buf: &mut String, bs: &mut &[u8]) -> Result<Lex<f64>, Error> {
         spaces!(bs);
 
         let mut toklen = 0;
@@ -395,22 +1714,32 @@ impl Parser {
         let mut specials_ok = true;
         let mut suffix_ok = true;
         let mut saw_val = false;
+        let point = if self.decimal_comma { b',' } else { b'.' };
+        let mut commas_ok = self.thousands_separators;
+        let mut saw_comma = false;
         loop {
             match peek_n!(bs, toklen) {
                 None => break,
                 Some(b) => {
-                    if b'0' <= b && b <= b'9' || b == b'.' {
+                    if b.is_ascii_digit() || b == point {
                         saw_val = true;
                         sign_ok = false;
                         specials_ok = false;
-                        toklen = toklen + 1;
+                        if b == b'.' {
+                            commas_ok = false;
+                        }
+                        toklen += 1;
+                    } else if commas_ok && saw_val && b == b',' {
+                        saw_comma = true;
+                        toklen += 1;
                     } else if sign_ok && (b == b'-' || b == b'+') {
                         sign_ok = false;
-                        toklen = toklen + 1;
+                        toklen += 1;
                     } else if saw_val && (b == b'e' || b == b'E') {
                         suffix_ok = false;
                         sign_ok = true;
-                        toklen = toklen + 1;
+                        commas_ok = false;
+                        toklen += 1;
                     } else if specials_ok
                         && (b == b'N'
                             && peek_is!(bs, toklen + 1, b'a')
@@ -423,7 +1752,7 @@ impl Parser {
                         {
                             saw_val = true;
                             suffix_ok = false;
-                            toklen = toklen + 3;
+                            toklen += 3;
                         }
                         break;
                     } else {
@@ -438,6 +1767,15 @@ impl Parser {
         }
 
         let mut tok = unsafe { from_utf8_unchecked(&bs[..toklen]) };
+        let ungrouped;
+        if saw_comma {
+            ungrouped = Self::remove_thousands_separators(tok)
+                .ok_or_else(|| Error::ParseF64(tok.to_string()))?;
+            tok = &ungrouped;
+        } else if self.decimal_comma {
+            ungrouped = tok.replace(',', ".");
+            tok = &ungrouped;
+        }
         if suffix_ok {
             match peek_n!(bs, toklen) {
                 None => (),
@@ -455,26 +1793,58 @@ impl Parser {
                         _ => (0, 0),
                     };
                     if exp != 0 {
-                        slab.char_buf.clear();
-                        slab.char_buf.push_str(tok);
-                        slab.char_buf.push('e');
-                        slab.char_buf.push_str(&exp.to_string());
-                        tok = &slab.char_buf;
+                        char_buf.clear();
+                        char_buf.push_str(tok);
+                        char_buf.push('e');
+                        char_buf.push_str(&exp.to_string());
+                        tok = char_buf;
 
-                        toklen = toklen + suffixlen;
+                        toklen += suffixlen;
                     }
                 }
             }
         }
 
-        let val = tok
+        let mut val = tok
             .parse::<f64>()
             .map_err(|_| Error::ParseF64(tok.to_string()))?;
+        if self.percent && peek_is!(bs, toklen, b'%') && self.percent_sign_at(&bs[toklen + 1..])? {
+            val /= 100.0;
+            toklen += 1;
+        }
         skip_n!(bs, toklen);
 
         Ok(Bite(val))
     }
 
+    // Whether a `%` that is followed by `rest` is a percent sign, instead of
+    // the remainder operator: nothing that could be a divisor may follow it.
+    fn percent_sign_at(&self, mut rest: &[u8]) -> Result<bool, Error> {
+        if let Bite(_) = self.read_binaryop(&mut rest)? {
+            return Ok(true);
+        }
+        Ok(matches!(
+            peek!(rest),
+            None | Some(b')' | b']' | b',' | b';')
+        ))
+    }
+
+    // Validates the digit groups of a number like "-1,234,567.89" and returns it without the commas.
+    fn remove_thousands_separators(tok: &str) -> Option<String> {
+        let int_end = tok.find(['.', 'e', 'E']).unwrap_or(tok.len());
+        let int_part = tok[..int_end].trim_start_matches(['-', '+']);
+        for (i, group) in int_part.split(',').enumerate() {
+            let ok = match i {
+                0 => (1..=3).contains(&group.len()),
+                _ => group.len() == 3,
+            };
+            if !ok {
+                return None;
+            }
+        }
+        Some(tok.replace(',', ""))
+    }
+
     // // This implementation is beautiful and correct, but it is slow due to the fact that I am first parsing everything,
     // // and then I'm calling parse::<f64> which repeats the entire process.
     // // I wish I could just call dec2flt::convert() ( https://doc.rust-lang.org/src/core/num/dec2flt/mod.rs.html#247 )
@@ -486,7 +1856,7 @@ impl Parser {
     // //
     // // As a side-note, It's surprising how similar these algorithms are (which I created from scratch at 3am with no reference),
     // // compared to the dec2flt::parse module.
-    // fn read_const(&mut self, bs:&mut &[u8]) -> Result<Token<f64>, KErr> {
+    // fn read_const(&mut self, bs:&mut &[u8]) -> Result<Lex<f64>, KErr> {
     //     spaces!(bs);
     //
     //     // Grammar: [+-]?[0-9]*(\.[0-9]+)?( ([eE][+-]?[0-9]+) || [pnuµmkKMGT] )?
@@ -567,27 +1937,48 @@ impl Parser {
         slab: &mut ParseSlab,
         bs: &mut &[u8],
         depth: usize,
-    ) -> Result<Token<UnaryOp>, Error> {
+    ) -> Result<Lex<UnaryOp>, Error> {
         spaces!(bs);
         match peek!(bs) {
             None => Ok(Pass), // Err(KErr::new("EOF at UnaryOp position")), -- Instead of erroring, let the higher level decide what to do.
+            Some(_) if self.alias_at(bs).is_some() => {
+                let (op, toklen) = self.alias_at(bs).ok_or(Error::Unreachable)?;
+                let unaryop: fn(ValueI) -> UnaryOp = match op {
+                    "+" => EPos,
+                    "-" => ENeg,
+                    "!" => ENot,
+                    // Aliases of binary operators are names here:
+                    _ => return Ok(Pass),
+                };
+                skip_n!(bs, toklen);
+                let from = *bs;
+                let v = self.read_value(slab, bs, depth + 1)?;
+                Ok(Bite(unaryop(self.push_val(slab, v, from, bs)?)))
+            }
             Some(b) => match b {
+                b'+' | b'-' | b'!' if !self.is_operator_byte(b) => Ok(Pass),
                 b'+' => {
                     skip!(bs);
+                    let from = *bs;
                     let v = self.read_value(slab, bs, depth + 1)?;
-                    Ok(Bite(EPos(slab.push_val(v)?)))
+                    Ok(Bite(EPos(self.push_val(slab, v, from, bs)?)))
                 }
                 b'-' => {
                     skip!(bs);
+                    let from = *bs;
                     let v = self.read_value(slab, bs, depth + 1)?;
-                    Ok(Bite(ENeg(slab.push_val(v)?)))
+                    Ok(Bite(ENeg(self.push_val(slab, v, from, bs)?)))
                 }
                 b'(' => {
                     skip!(bs);
                     let xi = self.read_expression(slab, bs, depth + 1, false)?;
                     spaces!(bs);
+                    let from = *bs;
                     if read!(bs, "parentheses")? != b')' {
-                        return Err(Error::Expected(")".to_string()));
+                        let err = Error::Expected(")".to_string());
+                        let xi = self.resync(slab, bs, from, err, b")")?;
+                        skip!(bs);
+                        return Ok(Bite(EParentheses(xi)));
                     }
                     Ok(Bite(EParentheses(xi)))
                 }
@@ -595,25 +1986,41 @@ impl Parser {
                     skip!(bs);
                     let xi = self.read_expression(slab, bs, depth + 1, false)?;
                     spaces!(bs);
+                    let from = *bs;
                     if read!(bs, "square brackets")? != b']' {
-                        return Err(Error::Expected("]".to_string()));
+                        let err = Error::Expected("]".to_string());
+                        let xi = self.resync(slab, bs, from, err, b"]")?;
+                        skip!(bs);
+                        return Ok(Bite(EParentheses(xi)));
                     }
                     Ok(Bite(EParentheses(xi)))
                 }
                 b'!' => {
                     skip!(bs);
+                    let from = *bs;
                     let v = self.read_value(slab, bs, depth + 1)?;
-                    Ok(Bite(ENot(slab.push_val(v)?)))
+                    Ok(Bite(ENot(self.push_val(slab, v, from, bs)?)))
                 }
                 _ => Ok(Pass),
             },
         }
     }
 
-    fn read_binaryop(&self, bs: &mut &[u8]) -> Result<Token<BinaryOp>, Error> {
+    fn read_binaryop(&self, bs: &mut &[u8]) -> Result<Lex<BinaryOp>, Error> {
         spaces!(bs);
         match peek!(bs) {
             None => Ok(Pass), // Err(KErr::new("EOF")), -- EOF is usually OK in a BinaryOp position.
+            Some(_) if self.alias_at(bs).is_some() => {
+                let (op, toklen) = self.alias_at(bs).ok_or(Error::Unreachable)?;
+                match binaryop_from_symbol(op) {
+                    Some(op) => {
+                        skip_n!(bs, toklen);
+                        Ok(Bite(op))
+                    }
+                    None => Ok(Pass),
+                }
+            }
+            Some(b) if !self.is_operator_byte(b) => Ok(Pass),
             Some(b) => match b {
                 b'+' => {
                     skip!(bs);
@@ -661,6 +2068,10 @@ impl Parser {
                     skip_n!(bs, 2);
                     Ok(Bite(EEQ))
                 }
+                b'=' if self.equals_is_comparison => {
+                    skip!(bs);
+                    Ok(Bite(EEQ))
+                }
                 b'!' if peek_is!(bs, 1, b'=') => {
                     skip_n!(bs, 2);
                     Ok(Bite(ENE))
@@ -693,8 +2104,13 @@ impl Parser {
         slab: &mut ParseSlab,
         bs: &mut &[u8],
         depth: usize,
-    ) -> Result<Token<Value>, Error> {
-        match Self::read_varname(bs)? {
+    ) -> Result<Lex<Value>, Error> {
+        spaces!(bs);
+        if self.is_let_binding(bs) {
+            skip_n!(bs, 3);
+            return Ok(Bite(EStdFunc(self.read_let(slab, bs, depth)?)));
+        }
+        match self.read_varname(bs)? {
             Pass => Ok(Pass),
             Bite(varname) => {
                 match Self::read_open_parenthesis(bs)? {
@@ -702,7 +2118,7 @@ impl Parser {
                         // VarNames without Parenthesis are always treated as custom 0-arg functions.
 
                         #[cfg(feature = "unsafe-vars")]
-                        match slab.unsafe_vars.get(&varname) {
+                        match slab.unsafe_vars.get(varname.as_str()) {
                             None => Ok(Bite(EStdFunc(EVar(varname)))),
                             Some(&ptr) => Ok(Bite(EStdFunc(EUnsafeVar { name: varname, ptr }))),
                         }
@@ -712,6 +2128,11 @@ impl Parser {
                     }
                     Bite(open_parenth) => {
                         // VarNames with Parenthesis are first matched against builtins, then custom.
+                        let varname = if self.case_insensitive_funcs {
+                            lowercase_name(varname)
+                        } else {
+                            varname
+                        };
                         match varname.as_ref() {
                             "print" => Ok(Bite(EPrintFunc(self.read_printfunc(
                                 slab,
@@ -733,24 +2154,114 @@ impl Parser {
         }
     }
 
-    fn read_varname(bs: &mut &[u8]) -> Result<Token<String>, Error> {
+    // Whether `bs` starts with `let VarName =`.  Anything else that starts
+    // with `let` is left alone, so `let` is still a valid variable name:
+    fn is_let_binding(&self, bs: &[u8]) -> bool {
+        if self.varname_len(bs) != 3 || !bs.starts_with(b"let") {
+            return false;
+        }
+        let look: &mut &[u8] = &mut &bs[3..];
+        spaces!(look);
+        let toklen = self.varname_len(look);
+        if toklen == 0 {
+            return false;
+        }
+        skip_n!(look, toklen);
+        spaces!(look);
+        peek!(look) == Some(b'=') && !peek_is!(look, 1, b'=')
+    }
+
+    // The length of the `in` or `not in` at the start of `bs`, up to the `(`
+    // of the list, and whether it is `not in`.  They are only keywords before
+    // a `(`, so `in` stays a valid name:
+    fn membership_len(&self, bs: &[u8]) -> Option<(usize, bool)> {
+        if !cfg!(feature = "alpha-keywords") {
+            return None;
+        }
+        let look: &mut &[u8] = &mut &bs[..];
+        let negated = self.varname_len(look) == 3 && look.starts_with(b"not");
+        if negated {
+            skip_n!(look, 3);
+            spaces!(look);
+        }
+        if self.varname_len(look) != 2 || !look.starts_with(b"in") {
+            return None;
+        }
+        skip_n!(look, 2);
+        let toklen = bs.len() - look.len();
+        spaces!(look);
+        match peek!(look) {
+            Some(b'(') => Some((toklen, negated)),
+            _ => None,
+        }
+    }
+
+    // The rest of a `let` binding, after the `let`:
+    fn read_let(
+        &self,
+        slab: &mut ParseSlab,
+        bs: &mut &[u8],
+        depth: usize,
+    ) -> Result<StdFunc, Error> {
+        let var = match self.read_varname(bs)? {
+            Bite(var) => var,
+            Pass => return Err(Error::Unreachable),
+        };
+        spaces!(bs);
+        skip!(bs); // The '=', which is_let_binding() checked.
+        let value = self.read_expression(slab, bs, depth + 1, false)?;
         spaces!(bs);
+        match read!(bs) {
+            Ok(b';') => {}
+            Ok(_) => return Err(Error::Expected("';'".to_string())),
+            Err(_) => return Err(Error::EofWhileParsing("let".to_string())),
+        }
+        let expr = self.read_expression(slab, bs, depth + 1, false)?;
+        Ok(EFuncLet { var, value, expr })
+    }
 
-        let mut toklen = 0;
-        while Self::is_varname_byte_opt(peek_n!(bs, toklen), toklen) {
-            toklen = toklen + 1;
+    // The length in bytes of the VarName at the start of `bs`:
+    fn varname_len(&self, bs: &[u8]) -> usize {
+        match &self.tokenizer {
+            None => {
+                let mut toklen = 0;
+                while Self::is_varname_byte_opt(peek_n!(bs, toklen), toklen) {
+                    toklen += 1;
+                }
+                toklen
+            }
+            Some(config) => Self::ident_len(&**config, bs),
+        }
+    }
+
+    // The operator that the alias at the start of `bs` stands for, and the
+    // length of the alias.  Only whole words match, so `mod` doesn't match
+    // the start of `modulus`:
+    fn alias_at(&self, bs: &[u8]) -> Option<(&str, usize)> {
+        if self.aliases.is_empty() {
+            return None;
         }
+        let toklen = self.varname_len(bs);
+        self.aliases
+            .iter()
+            .find(|(word, _)| word.as_bytes() == &bs[..toklen])
+            .map(|(_, op)| (op.as_str(), toklen))
+    }
+
+    fn read_varname(&self, bs: &mut &[u8]) -> Result<Lex<Name>, Error> {
+        spaces!(bs);
 
+        let toklen = self.varname_len(bs);
         if toklen == 0 {
             return Ok(Pass);
         }
 
-        let out = unsafe { from_utf8_unchecked(&bs[..toklen]) }.to_string();
+        let out = Name::from(unsafe { from_utf8_unchecked(&bs[..toklen]) });
         skip_n!(bs, toklen);
         Ok(Bite(out))
     }
 
-    fn read_open_parenthesis(bs: &mut &[u8]) -> Result<Token<u8>, Error> {
+    fn read_open_parenthesis(bs: &mut &[u8]) -> Result<Lex<u8>, Error> {
         spaces!(bs);
 
         match peek!(bs) {
@@ -762,9 +2273,31 @@ impl Parser {
         }
     }
 
+    // Whether ',' is part of numbers, and so can't separate arguments:
+    fn comma_in_numbers(&self) -> bool {
+        self.thousands_separators || self.decimal_comma
+    }
+
+    fn expected_arg_separator(&self) -> Error {
+        if self.comma_in_numbers() {
+            Error::Expected("';'".to_string())
+        } else {
+            Error::Expected("',' or ';'".to_string())
+        }
+    }
+
+    // The bytes that can follow an argument:
+    fn arg_stops(&self, close_parenth: u8) -> Vec<u8> {
+        if self.comma_in_numbers() {
+            vec![b';', close_parenth]
+        } else {
+            vec![b',', b';', close_parenth]
+        }
+    }
+
     fn read_func(
         &self,
-        fname: String,
+        fname: Name,
         slab: &mut ParseSlab,
         bs: &mut &[u8],
         depth: usize,
@@ -775,6 +2308,35 @@ impl Parser {
             b'[' => b']',
             _ => return Err(Error::Expected("'(' or '['".to_string())),
         };
+        let args = self.read_args(fname.as_str(), slab, bs, depth, close_parenth)?;
+        Self::build_func(fname, args, slab)
+    }
+
+    // The list of a membership test, after `in` or `not in`:
+    fn read_membership_list(
+        &self,
+        slab: &mut ParseSlab,
+        bs: &mut &[u8],
+        depth: usize,
+    ) -> Result<Vec<ExpressionI>, Error> {
+        spaces!(bs);
+        skip!(bs); // The '(' that membership_len() found.
+        let list = self.read_args("in", slab, bs, depth, b')')?;
+        if list.is_empty() {
+            return Err(Error::Expected("a value in the list of 'in'".to_string()));
+        }
+        Ok(list)
+    }
+
+    // The comma-separated arguments up to `close_parenth`, which is consumed:
+    fn read_args(
+        &self,
+        what: &str,
+        slab: &mut ParseSlab,
+        bs: &mut &[u8],
+        depth: usize,
+        close_parenth: u8,
+    ) -> Result<Vec<ExpressionI>, Error> {
         let mut args = Vec::<ExpressionI>::with_capacity(4);
         loop {
             spaces!(bs);
@@ -785,293 +2347,163 @@ impl Parser {
                         break;
                     }
                 }
-                None => return Err(Error::EofWhileParsing(fname)),
+                None => return Err(Error::EofWhileParsing(what.to_string())),
             }
             if !args.is_empty() {
+                let from = *bs;
                 match read!(bs) {
                     // I accept ',' or ';' because the TV API disallows the ',' char in symbols... so I'm using ';' as a compromise.
-                    Ok(b',') | Ok(b';') => {}
-                    _ => return Err(Error::Expected("',' or ';'".to_string())),
+                    Ok(b',') if !self.comma_in_numbers() => {}
+                    Ok(b';') => {}
+                    _ => {
+                        let err = self.expected_arg_separator();
+                        let stops = self.arg_stops(close_parenth);
+                        let xi = self.resync(slab, bs, from, err, &stops)?;
+                        // The garbage replaces the argument that it follows:
+                        if let Some(arg) = args.last_mut() {
+                            *arg = xi;
+                        }
+                        continue;
+                    }
                 }
             }
             args.push(self.read_expression(slab, bs, depth + 1, false)?);
         }
+        Ok(args)
+    }
 
+    // The built-in function `fname`, or a custom function call:
+    fn build_func(
+        fname: Name,
+        mut args: Vec<ExpressionI>,
+        slab: &ParseSlab,
+    ) -> Result<StdFunc, Error> {
         let fname_str = fname.as_str();
-        match fname_str {
-            "int" => {
-                if args.len() == 1 {
-                    Ok(EFuncInt(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("int: expected one arg".to_string()))
-                }
-            }
-            "ceil" => {
-                if args.len() == 1 {
-                    Ok(EFuncCeil(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("ceil: expected one arg".to_string()))
-                }
-            }
-            "floor" => {
-                if args.len() == 1 {
-                    Ok(EFuncFloor(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("floor: expected one arg".to_string()))
-                }
-            }
-            "abs" => {
-                if args.len() == 1 {
-                    Ok(EFuncAbs(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("abs: expected one arg".to_string()))
-                }
-            }
-            "sign" => {
-                if args.len() == 1 {
-                    Ok(EFuncSign(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("sign: expected one arg".to_string()))
-                }
+        if let Some(arity) = builtin_arity(fname_str) {
+            if !arity.accepts(args.len()) {
+                return Err(Error::WrongArgCount {
+                    name: fname.into(),
+                    expected: arity,
+                    got: args.len(),
+                });
             }
+        }
+        // The number of args of the built-ins was checked above:
+        match fname_str {
+            "int" => Ok(EFuncInt(args.pop().ok_or(Error::Unreachable)?)),
+            "ceil" => Ok(EFuncCeil(args.pop().ok_or(Error::Unreachable)?)),
+            "floor" => Ok(EFuncFloor(args.pop().ok_or(Error::Unreachable)?)),
+            "abs" => Ok(EFuncAbs(args.pop().ok_or(Error::Unreachable)?)),
+            "sign" => Ok(EFuncSign(args.pop().ok_or(Error::Unreachable)?)),
             "log" => {
-                if args.len() == 1 {
-                    Ok(EFuncLog {
-                        base: None,
-                        expr: match args.pop() {
-                            Some(xi) => xi,
-                            None => return Err(Error::Unreachable),
-                        },
-                    })
-                } else if args.len() == 2 {
-                    let expr = match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    };
-                    Ok(EFuncLog {
-                        base: Some(match args.pop() {
-                            Some(xi) => xi,
-                            None => return Err(Error::Unreachable),
-                        }),
-                        expr,
-                    })
-                } else {
-                    Err(Error::WrongArgs(
-                        "expected log(x) or log(base,x)".to_string(),
-                    ))
-                }
+                let expr = args.pop().ok_or(Error::Unreachable)?;
+                Ok(EFuncLog {
+                    base: args.pop(),
+                    expr,
+                })
             }
             "round" => {
-                if args.len() == 1 {
-                    Ok(EFuncRound {
-                        modulus: None,
-                        expr: match args.pop() {
-                            Some(xi) => xi,
-                            None => return Err(Error::Unreachable),
-                        },
-                    })
-                } else if args.len() == 2 {
-                    let expr = match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    };
-                    Ok(EFuncRound {
-                        modulus: Some(match args.pop() {
-                            Some(xi) => xi,
-                            None => return Err(Error::Unreachable),
-                        }),
-                        expr,
-                    })
-                } else {
-                    Err(Error::WrongArgs(
-                        "round: expected round(x) or round(modulus,x)".to_string(),
-                    ))
-                }
+                let expr = args.pop().ok_or(Error::Unreachable)?;
+                Ok(EFuncRound {
+                    modulus: args.pop(),
+                    expr,
+                })
             }
             "min" => {
-                if !args.is_empty() {
-                    match remove_no_panic(&mut args, 0) {
-                        Some(first) => Ok(EFuncMin { first, rest: args }),
-                        None => Err(Error::Unreachable),
-                    }
-                } else {
-                    Err(Error::WrongArgs(
-                        "min: expected one or more args".to_string(),
-                    ))
-                }
+                let first = remove_no_panic(&mut args, 0).ok_or(Error::Unreachable)?;
+                Ok(EFuncMin { first, rest: args })
             }
             "max" => {
-                if !args.is_empty() {
-                    match remove_no_panic(&mut args, 0) {
-                        Some(first) => Ok(EFuncMax { first, rest: args }),
-                        None => Err(Error::Unreachable),
-                    }
-                } else {
-                    Err(Error::WrongArgs(
-                        "max: expected one or more args".to_string(),
-                    ))
-                }
-            }
-
-            "e" => {
-                if args.is_empty() {
-                    Ok(EFuncE)
-                } else {
-                    Err(Error::WrongArgs("e: expected no args".to_string()))
-                }
-            }
-            "pi" => {
-                if args.is_empty() {
-                    Ok(EFuncPi)
-                } else {
-                    Err(Error::WrongArgs("pi: expected no args".to_string()))
-                }
-            }
-
-            "sin" => {
-                if args.len() == 1 {
-                    Ok(EFuncSin(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("sin: expected one arg".to_string()))
-                }
-            }
-            "cos" => {
-                if args.len() == 1 {
-                    Ok(EFuncCos(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("cos: expected one arg".to_string()))
-                }
-            }
-            "tan" => {
-                if args.len() == 1 {
-                    Ok(EFuncTan(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("tan: expected one arg".to_string()))
-                }
-            }
-            "asin" => {
-                if args.len() == 1 {
-                    Ok(EFuncASin(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("asin: expected one arg".to_string()))
-                }
-            }
-            "acos" => {
-                if args.len() == 1 {
-                    Ok(EFuncACos(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("acos: expected one arg".to_string()))
-                }
-            }
-            "atan" => {
-                if args.len() == 1 {
-                    Ok(EFuncATan(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("atan: expected one arg".to_string()))
-                }
+                let first = remove_no_panic(&mut args, 0).ok_or(Error::Unreachable)?;
+                Ok(EFuncMax { first, rest: args })
             }
-            "sinh" => {
-                if args.len() == 1 {
-                    Ok(EFuncSinH(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("sinh: expected one arg".to_string()))
+            "piecewise" => {
+                let default = args.pop().ok_or(Error::Unreachable)?;
+                let mut branches = Vec::with_capacity(args.len() / 2);
+                let mut it = args.into_iter();
+                while let (Some(cond), Some(val)) = (it.next(), it.next()) {
+                    branches.push((cond, val));
                 }
+                Ok(EFuncPiecewise { branches, default })
             }
-            "cosh" => {
-                if args.len() == 1 {
-                    Ok(EFuncCosH(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("cosh: expected one arg".to_string()))
-                }
+            // `if(cond, then, else)` is a piecewise() with one branch, so it
+            // short-circuits in the same way everywhere:
+            "if" => {
+                let default = args.pop().ok_or(Error::Unreachable)?;
+                let val = args.pop().ok_or(Error::Unreachable)?;
+                let cond = args.pop().ok_or(Error::Unreachable)?;
+                Ok(EFuncPiecewise {
+                    branches: vec![(cond, val)],
+                    default,
+                })
             }
-            "tanh" => {
-                if args.len() == 1 {
-                    Ok(EFuncTanH(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("tanh: expected one arg".to_string()))
+            "interp" | "interp_ext" => {
+                let x = remove_no_panic(&mut args, 0).ok_or(Error::Unreachable)?;
+                let mut points = Vec::with_capacity(args.len() / 2);
+                let mut it = args.into_iter();
+                while let (Some(px), Some(py)) = (it.next(), it.next()) {
+                    points.push((px, py));
                 }
+                Ok(EFuncInterp {
+                    extrapolate: fname_str == "interp_ext",
+                    x,
+                    points,
+                })
             }
-            "asinh" => {
-                if args.len() == 1 {
-                    Ok(EFuncASinH(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("asinh: expected one arg".to_string()))
-                }
+
+            "e" => Ok(EFuncE),
+            "pi" => Ok(EFuncPi),
+
+            "sin" => Ok(EFuncSin(args.pop().ok_or(Error::Unreachable)?)),
+            "cos" => Ok(EFuncCos(args.pop().ok_or(Error::Unreachable)?)),
+            "tan" => Ok(EFuncTan(args.pop().ok_or(Error::Unreachable)?)),
+            "asin" => Ok(EFuncASin(args.pop().ok_or(Error::Unreachable)?)),
+            "acos" => Ok(EFuncACos(args.pop().ok_or(Error::Unreachable)?)),
+            "atan" => Ok(EFuncATan(args.pop().ok_or(Error::Unreachable)?)),
+            "sinh" => Ok(EFuncSinH(args.pop().ok_or(Error::Unreachable)?)),
+            "cosh" => Ok(EFuncCosH(args.pop().ok_or(Error::Unreachable)?)),
+            "tanh" => Ok(EFuncTanH(args.pop().ok_or(Error::Unreachable)?)),
+            "asinh" => Ok(EFuncASinH(args.pop().ok_or(Error::Unreachable)?)),
+            "acosh" => Ok(EFuncACosH(args.pop().ok_or(Error::Unreachable)?)),
+            "atanh" => Ok(EFuncATanH(args.pop().ok_or(Error::Unreachable)?)),
+            "sqrt" => Ok(EFuncSqrt(args.pop().ok_or(Error::Unreachable)?)),
+            "fma" => {
+                let c = args.pop().ok_or(Error::Unreachable)?;
+                let b = args.pop().ok_or(Error::Unreachable)?;
+                let a = args.pop().ok_or(Error::Unreachable)?;
+                Ok(EFuncFma { a, b, c })
             }
-            "acosh" => {
-                if args.len() == 1 {
-                    Ok(EFuncACosH(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("acosh: expected one arg".to_string()))
-                }
+            "coalesce" => {
+                let fallback = args.pop().ok_or(Error::Unreachable)?;
+                let value = args.pop().ok_or(Error::Unreachable)?;
+                Ok(EFuncCoalesce { value, fallback })
             }
-            "atanh" => {
-                if args.len() == 1 {
-                    Ok(EFuncATanH(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
+            "quotient" | "remainder" => {
+                let divisor = args.pop().ok_or(Error::Unreachable)?;
+                let dividend = args.pop().ok_or(Error::Unreachable)?;
+                if fname_str == "quotient" {
+                    Ok(EFuncQuotient { dividend, divisor })
                 } else {
-                    Err(Error::WrongArgs("atanh: expected one arg".to_string()))
+                    Ok(EFuncRemainder { dividend, divisor })
                 }
             }
-            "sqrt" => {
-                if args.len() == 1 {
-                    Ok(EFuncSqrt(match args.pop() {
-                        Some(xi) => xi,
-                        None => return Err(Error::Unreachable),
-                    }))
-                } else {
-                    Err(Error::WrongArgs("sqrt: expected one arg".to_string()))
-                }
+            "sumsq" | "norm" => Ok(EFuncNorm {
+                squared: fname_str == "sumsq",
+                args,
+            }),
+
+            // A 'sum' with any other shape is left for custom functions:
+            "sum" if args.len() == 4 && loop_var_name(slab, args[0]).is_some() => {
+                let var = loop_var_name(slab, args[0]).ok_or(Error::Unreachable)?;
+                let expr = args.pop().ok_or(Error::Unreachable)?;
+                let to = args.pop().ok_or(Error::Unreachable)?;
+                let from = args.pop().ok_or(Error::Unreachable)?;
+                Ok(EFuncSum {
+                    var,
+                    from,
+                    to,
+                    expr,
+                })
             }
 
             _ => {
@@ -1114,10 +2546,18 @@ impl Parser {
                 }
             }
             if !args.is_empty() {
+                let from = *bs;
                 match read!(bs) {
-                    Ok(b',') | Ok(b';') => {}
+                    Ok(b',') if !self.comma_in_numbers() => {}
+                    Ok(b';') => {}
                     _ => {
-                        return Err(Error::Expected("',' or ';'".to_string()));
+                        let err = self.expected_arg_separator();
+                        let stops = self.arg_stops(close_parenth);
+                        let xi = self.resync(slab, bs, from, err, &stops)?;
+                        if let Some(arg) = args.last_mut() {
+                            *arg = EExpr(xi);
+                        }
+                        continue;
                     }
                 }
             }
@@ -1141,7 +2581,7 @@ impl Parser {
     }
 
     // TODO: Improve this logic, especially to handle embedded quotes:
-    fn read_string(bs: &mut &[u8]) -> Result<Token<String>, Error> {
+    fn read_string(bs: &mut &[u8]) -> Result<Lex<String>, Error> {
         spaces!(bs);
 
         match peek!(bs) {
@@ -1162,7 +2602,7 @@ impl Parser {
             Some(b'"') => false,
             Some(_) => true,
         } {
-            toklen = toklen + 1;
+            toklen += 1;
         }
 
         let out = from_utf8(&bs[..toklen])
@@ -1177,22 +2617,491 @@ impl Parser {
     }
 }
 
+#[allow(deprecated)]
+impl<'a> Tokens<'a> {
+    fn read_token(&mut self) -> Result<Option<Token>, Error> {
+        let parser = self.parser;
+        if self.src.len() > parser.expr_len_limit {
+            return Err(Error::TooLong);
+        }
+        let bs = &mut self.bs;
+        spaces!(bs);
+        if bs.is_empty() {
+            return Ok(None);
+        }
+        let start = self.src.len() - bs.len();
+
+        let kind = match peek!(bs) {
+            // Also where a value is expected, because of empty argument lists:
+            Some(b')') => {
+                skip!(bs);
+                TokenKind::CloseParen
+            }
+            Some(b']') => {
+                skip!(bs);
+                TokenKind::CloseBracket
+            }
+            Some(b) if self.want_value => {
+                let unary_alias = parser.alias_at(bs).and_then(|(op, toklen)| match op {
+                    "+" => Some((TokenKind::Pos, toklen)),
+                    "-" => Some((TokenKind::Neg, toklen)),
+                    "!" => Some((TokenKind::Not, toklen)),
+                    _ => None,
+                });
+                let punct = match b {
+                    b'+' | b'-' | b'!' if !parser.is_operator_byte(b) => None,
+                    b'+' => Some(TokenKind::Pos),
+                    b'-' => Some(TokenKind::Neg),
+                    b'!' => Some(TokenKind::Not),
+                    b'(' => Some(TokenKind::OpenParen),
+                    b'[' => Some(TokenKind::OpenBracket),
+                    _ => None,
+                };
+                if let Bite(val) = parser.read_const(&mut self.char_buf, bs)? {
+                    TokenKind::Number(val)
+                } else if let Some((kind, toklen)) = unary_alias {
+                    skip_n!(bs, toklen);
+                    kind
+                } else if let Some(kind) = punct {
+                    skip!(bs);
+                    kind
+                } else if parser.is_let_binding(bs) {
+                    skip_n!(bs, 3);
+                    TokenKind::Let
+                } else if let Bite(s) = Parser::read_string(bs)? {
+                    TokenKind::Str(s)
+                } else if let Bite(name) = parser.read_varname(bs)? {
+                    TokenKind::Ident(name.into())
+                } else {
+                    return Err(Error::InvalidValue);
+                }
+            }
+            // The arguments of a function:
+            Some(b'(') if self.after_name => {
+                skip!(bs);
+                TokenKind::OpenParen
+            }
+            Some(b'[') if self.after_name => {
+                skip!(bs);
+                TokenKind::OpenBracket
+            }
+            Some(b',') => {
+                skip!(bs);
+                TokenKind::Comma
+            }
+            Some(b';') => {
+                skip!(bs);
+                TokenKind::Semicolon
+            }
+            Some(b'=') if self.after_let_name && !peek_is!(bs, 1, b'=') => {
+                skip!(bs);
+                TokenKind::Assign
+            }
+            Some(_) if parser.membership_len(bs).is_some() => {
+                let (toklen, negated) = parser.membership_len(bs).ok_or(Error::Unreachable)?;
+                skip_n!(bs, toklen);
+                if negated {
+                    TokenKind::NotIn
+                } else {
+                    TokenKind::In
+                }
+            }
+            _ => match parser.read_binaryop(bs)? {
+                Bite(op) => TokenKind::BinaryOp(op),
+                Pass => {
+                    let bs_str = from_utf8(bs)
+                        .unwrap_or("Utf8Error while handling UnparsedTokensRemaining error");
+                    return Err(Error::UnparsedTokensRemaining(bs_str.to_string()));
+                }
+            },
+        };
+        self.after_let_name = self.after_let && matches!(kind, TokenKind::Ident(_));
+        self.after_let = matches!(kind, TokenKind::Let);
+        self.after_name = matches!(kind, TokenKind::Ident(_)) && !self.after_let_name;
+        self.want_value = !matches!(
+            kind,
+            TokenKind::Number(_)
+                | TokenKind::Ident(_)
+                | TokenKind::Str(_)
+                | TokenKind::CloseParen
+                | TokenKind::CloseBracket
+        );
+        let end = self.src.len() - bs.len();
+        Ok(Some(Token {
+            kind,
+            span: start..end,
+        }))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.read_token();
+        if !matches!(res, Ok(Some(_))) {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
+
 impl Default for Parser {
     fn default() -> Self {
         Self::new()
     }
 }
-impl Default for Expression {
+impl Default for Value {
     fn default() -> Self {
-        Expression {
-            first: Default::default(),
-            pairs: Vec::new(),
+        EConstant(f64::NAN)
+    }
+}
+
+/// The built-in functions, and the number of arguments that each accepts.
+///
+/// `print()` accepts any number of arguments and strings, and
+/// `sum(var, from, to, expr)` is only built in when `var` is a plain variable
+/// (otherwise it's a custom function), so they aren't listed.
+pub const BUILTIN_FUNCS: &[(&str, Arity)] = &[
+    ("int", Arity::Exact(1)),
+    ("ceil", Arity::Exact(1)),
+    ("floor", Arity::Exact(1)),
+    ("abs", Arity::Exact(1)),
+    ("sign", Arity::Exact(1)),
+    ("log", Arity::Range(1, 2)),
+    ("round", Arity::Range(1, 2)),
+    ("min", Arity::AtLeast(1)),
+    ("max", Arity::AtLeast(1)),
+    ("piecewise", Arity::OddAtLeast(1)),
+    ("if", Arity::Exact(3)),
+    ("interp", Arity::OddAtLeast(5)),
+    ("interp_ext", Arity::OddAtLeast(5)),
+    ("e", Arity::Exact(0)),
+    ("pi", Arity::Exact(0)),
+    ("sin", Arity::Exact(1)),
+    ("cos", Arity::Exact(1)),
+    ("tan", Arity::Exact(1)),
+    ("asin", Arity::Exact(1)),
+    ("acos", Arity::Exact(1)),
+    ("atan", Arity::Exact(1)),
+    ("sinh", Arity::Exact(1)),
+    ("cosh", Arity::Exact(1)),
+    ("tanh", Arity::Exact(1)),
+    ("asinh", Arity::Exact(1)),
+    ("acosh", Arity::Exact(1)),
+    ("atanh", Arity::Exact(1)),
+    ("sqrt", Arity::Exact(1)),
+    ("fma", Arity::Exact(3)),
+    ("coalesce", Arity::Exact(2)),
+    ("quotient", Arity::Exact(2)),
+    ("remainder", Arity::Exact(2)),
+    ("sumsq", Arity::AtLeast(1)),
+    ("norm", Arity::AtLeast(1)),
+];
+
+/// Returns the number of arguments that the built-in function `name`
+/// accepts, or `None` if there is no such built-in.
+///
+/// # Examples
+/// ```
+/// use fasteval2::parser::builtin_arity;
+/// use fasteval2::Arity;
+///
+/// assert_eq!(builtin_arity("fma"), Some(Arity::Exact(3)));
+/// assert_eq!(builtin_arity("max"), Some(Arity::AtLeast(1)));
+/// assert_eq!(builtin_arity("clamp"), None);
+/// ```
+pub fn builtin_arity(name: &str) -> Option<Arity> {
+    BUILTIN_FUNCS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, arity)| *arity)
+}
+
+/// Returns the variable name if `expr_i` is nothing but a plain variable.
+// The span of the input that was consumed between `from` and `bs`, without
+// surrounding whitespace.
+fn consumed_span(slab: &ParseSlab, from: &[u8], bs: &[u8]) -> (usize, usize) {
+    let rest = &mut &from[..from.len() - bs.len()];
+    spaces!(rest);
+    let start = slab.spans.src_len - rest.len() - bs.len();
+    (start, start + trim_blank_end(rest).len())
+}
+
+// Whether only whitespace was read between `from` and `bs`:
+fn only_spaces(mut from: &[u8], bs: &[u8]) -> bool {
+    let rest = &mut from;
+    spaces!(rest);
+    rest.len() == bs.len()
+}
+
+// Removes the whitespace at the end of `consumed`.  A value never ends with a
+// '\\', so one at the end is a line continuation:
+fn trim_blank_end(mut consumed: &[u8]) -> &[u8] {
+    while let Some(trimmed) = consumed.trim_ascii_end().strip_suffix(b"\\") {
+        consumed = trimmed;
+    }
+    consumed.trim_ascii_end()
+}
+
+// Finds the smallest node around `pos` in the recorded spans.  See
+// `ExpressionI::node_at()`.
+pub(crate) fn node_at(ps: &ParseSlab, expr_i: ExpressionI, pos: usize) -> Option<(usize, usize)> {
+    let expr = ps.exprs.get(expr_i.0)?;
+    let spans = ps.spans.exprs.get(expr_i.0)?;
+    if spans.len() != expr.pairs.len() + 1 {
+        return None;
+    }
+    let covers = |(start, end): (usize, usize)| start <= pos && pos < end;
+    let values = Some(&expr.first)
+        .into_iter()
+        .chain(expr.pairs.iter().map(|pair| &pair.1));
+    for (value, span) in values.zip(spans) {
+        if covers(*span) {
+            return Some(value_node_at(ps, value, *span, pos));
+        }
+    }
+    if let Some(span) = (expr.tuple.iter()).find_map(|item| node_at(ps, *item, pos)) {
+        return Some(span);
+    }
+
+    // `pos` is in between values, so group the operators the way that eval()
+    // does.  Groups are built from the inside out, so the first one around
+    // `pos` is the smallest:
+    let mut groups = spans.clone();
+    let mut ops: Vec<BinaryOp> = expr.pairs.iter().map(|pair| pair.0).collect();
+    let reduce = |groups: &mut Vec<(usize, usize)>, ops: &mut Vec<BinaryOp>, i: usize| {
+        let (_, end) = groups.remove(i + 1);
+        groups[i].1 = end;
+        ops.remove(i);
+        covers(groups[i])
+    };
+    for (search, rtol) in OP_PASSES {
+        if rtol {
+            for i in (0..ops.len()).rev() {
+                if search.contains(&ops[i]) && reduce(&mut groups, &mut ops, i) {
+                    return Some(groups[i]);
+                }
+            }
+        } else {
+            let mut i = 0;
+            while i < ops.len() {
+                if search.contains(&ops[i]) {
+                    if reduce(&mut groups, &mut ops, i) {
+                        return Some(groups[i]);
+                    }
+                } else {
+                    i += 1;
+                }
+            }
         }
     }
+    None
 }
-impl Default for Value {
-    fn default() -> Self {
-        EConstant(std::f64::NAN)
+
+fn value_node_at(
+    ps: &ParseSlab,
+    value: &Value,
+    span: (usize, usize),
+    pos: usize,
+) -> (usize, usize) {
+    let mut xis = Vec::new();
+    let val_i = value_refs(value, &mut xis);
+    if let Some(node) = xis.into_iter().find_map(|xi| node_at(ps, xi, pos)) {
+        return node;
+    }
+    if let Some(val_i) = val_i {
+        if let (Some(val), Some(Some(val_span))) =
+            (ps.vals.get(val_i.0), ps.spans.vals.get(val_i.0))
+        {
+            if val_span.0 <= pos && pos < val_span.1 {
+                return value_node_at(ps, val, *val_span, pos);
+            }
+        }
+    }
+    span
+}
+
+// `name` in ASCII lowercase, which only allocates for long names that have
+// uppercase letters:
+fn lowercase_name(name: Name) -> Name {
+    if name.bytes().any(|b| b.is_ascii_uppercase()) {
+        Name::from(name.to_ascii_lowercase())
+    } else {
+        name
+    }
+}
+
+// Attaches a span to `error`.  `rem` is the remaining input at the start and
+// at the end of the part of `expr_str` that caused it.
+// Splits `s` at the occurrences of `sep` that aren't nested in brackets or
+// strings, and returns the byte ranges of the items:
+fn split_list(s: &str, sep: char) -> Vec<(usize, usize)> {
+    let mut items = Vec::new();
+    let (mut start, mut nesting, mut quoted) = (0, 0usize, false);
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' | '[' => nesting += 1,
+            ')' | ']' => nesting = nesting.saturating_sub(1),
+            _ if c == sep && nesting == 0 => {
+                items.push((start, i));
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    items.push((start, s.len()));
+    items
+}
+
+fn spanned(expr_str: &str, error: Error, rem: (usize, usize)) -> SpannedError {
+    let len = expr_str.len();
+    if let Error::TooLong | Error::EmptyExpression = error {
+        return SpannedError {
+            error,
+            span: 0..len,
+        };
+    }
+    let (start, end) = match &error {
+        Error::UnparsedTokensRemaining(rest) => (len - rest.len().min(len), len),
+        _ => (len - rem.0.min(len), len - rem.1.min(len)),
+    };
+    // Skip leading whitespace, and point at one character if nothing was consumed:
+    let rest = &mut &expr_str.as_bytes()[start..end];
+    spaces!(rest);
+    let start = end - rest.len();
+    let end = if start < end {
+        end
+    } else {
+        expr_str[start..]
+            .chars()
+            .next()
+            .map_or(start, |c| start + c.len_utf8())
+    };
+    SpannedError {
+        error,
+        span: start..end,
+    }
+}
+
+fn loop_var_name(slab: &ParseSlab, expr_i: ExpressionI) -> Option<Name> {
+    let expr = get_expr!(slab, expr_i);
+    match &expr.first {
+        EStdFunc(EVar(name)) if expr.pairs.is_empty() => Some(name.clone()),
+        _ => None,
+    }
+}
+
+// Checks the structure that the parser builds: every index is in bounds, an
+// expression only refers to expressions that were pushed before it, and a
+// value only refers to values that were pushed before it.  Walking the AST
+// can't loop forever on a slab that passes.
+pub(crate) fn well_formed(slab: &ParseSlab, root: ExpressionI) -> bool {
+    if root.0 >= slab.exprs.len() {
+        return false;
+    }
+    let mut seen = vec![false; slab.exprs.len()];
+    let mut todo = vec![root];
+    seen[root.0] = true;
+    let mut xis = Vec::<ExpressionI>::new();
+    while let Some(expr_i) = todo.pop() {
+        let expr = &slab.exprs[expr_i.0];
+        for value in Some(&expr.first)
+            .into_iter()
+            .chain(expr.pairs.iter().map(|p| &p.1))
+        {
+            let mut val_i = value_refs(value, &mut xis);
+            while let Some(vi) = val_i {
+                match slab.vals.get(vi.0) {
+                    Some(val) => {
+                        val_i = value_refs(val, &mut xis);
+                        if val_i.is_some_and(|next| next.0 >= vi.0) {
+                            return false;
+                        }
+                    }
+                    None => return false,
+                }
+            }
+        }
+        xis.extend(&expr.tuple);
+        for xi in xis.drain(..) {
+            if xi.0 >= expr_i.0 {
+                return false;
+            }
+            if !seen[xi.0] {
+                seen[xi.0] = true;
+                todo.push(xi);
+            }
+        }
+    }
+    true
+}
+
+// Adds the expressions that `value` refers to to `xis`, and returns the value it refers to:
+fn value_refs(value: &Value, xis: &mut Vec<ExpressionI>) -> Option<ValueI> {
+    match value {
+        EConstant(_) | EError(_) => None,
+        EUnaryOp(EPos(vi)) | EUnaryOp(ENeg(vi)) | EUnaryOp(ENot(vi)) => Some(*vi),
+        EUnaryOp(EParentheses(xi)) => {
+            xis.push(*xi);
+            None
+        }
+        EPrintFunc(pf) => {
+            xis.extend(pf.0.iter().filter_map(|x_or_s| match x_or_s {
+                EExpr(xi) => Some(*xi),
+                EStr(_) => None,
+            }));
+            None
+        }
+        EStdFunc(f) => {
+            match f {
+                #[cfg(feature = "unsafe-vars")]
+                EUnsafeVar { .. } => (),
+                EVar(_) | EFuncE | EFuncPi => (),
+                EFunc { args, .. } => xis.extend(args),
+                EFuncInt(xi) | EFuncCeil(xi) | EFuncFloor(xi) | EFuncAbs(xi) | EFuncSign(xi)
+                | EFuncSin(xi) | EFuncCos(xi) | EFuncTan(xi) | EFuncASin(xi) | EFuncACos(xi)
+                | EFuncATan(xi) | EFuncSinH(xi) | EFuncCosH(xi) | EFuncTanH(xi)
+                | EFuncASinH(xi) | EFuncACosH(xi) | EFuncATanH(xi) | EFuncSqrt(xi) => xis.push(*xi),
+                EFuncLog {
+                    base: opt,
+                    expr: xi,
+                }
+                | EFuncRound {
+                    modulus: opt,
+                    expr: xi,
+                } => xis.extend(opt.iter().chain(Some(xi))),
+                EFuncMin { first, rest } | EFuncMax { first, rest } => {
+                    xis.extend(Some(first).into_iter().chain(rest))
+                }
+                EFuncPiecewise { branches, default } => xis.extend(
+                    branches
+                        .iter()
+                        .flat_map(|(cond, val)| [cond, val])
+                        .chain(Some(default)),
+                ),
+                EFuncSum { from, to, expr, .. } => xis.extend([from, to, expr]),
+                EFuncLet { value, expr, .. } => xis.extend([value, expr]),
+                EFuncInterp { x, points, .. } => xis.extend(
+                    Some(x)
+                        .into_iter()
+                        .chain(points.iter().flat_map(|(px, py)| [px, py])),
+                ),
+                EFuncFma { a, b, c } => xis.extend([a, b, c]),
+                EFuncCoalesce { value, fallback } => xis.extend([value, fallback]),
+                EFuncIn { value, list, .. } => xis.extend(Some(value).into_iter().chain(list)),
+                EFuncQuotient { dividend, divisor } | EFuncRemainder { dividend, divisor } => {
+                    xis.extend([dividend, divisor])
+                }
+                EFuncNorm { args, .. } => xis.extend(args),
+            }
+            None
+        }
     }
 }
 
@@ -1226,7 +3135,7 @@ mod internal_tests {
     use super::*;
     use crate::slab::Slab;
 
-    //// Commented so I can compile with stable Rust.
+    // Commented so I can compile with stable Rust.
     // extern crate test;
     // use test::{Bencher, black_box};
 
@@ -1240,6 +3149,7 @@ mod internal_tests {
     }
 
     #[test]
+    #[allow(clippy::const_is_empty, clippy::needless_borrow)]
     fn util() {
         match (|| -> Result<(), Error> {
             let bsarr = [1, 2, 3];
@@ -0,0 +1,395 @@
+//! Formats parsed expressions back into expression strings.
+//!
+//! A [`Printer`](struct.Printer.html) writes an
+//! [`Expression`](../parser/struct.Expression.html) in the normalized form of
+//! [`to_simplified_string()`](../simplify/index.html), without simplifying
+//! anything: operators are surrounded by spaces, function arguments are
+//! separated by `", "`, and parentheses are only kept where the grouping needs
+//! them.  The options change the formatting:
+//!
+//! ```
+//! use fasteval2::{Parser, Printer, Slab};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = Slab::new();
+//!     let expr = Parser::new().parse("((x*2)) + (y-1)*0.30000000000000004", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(Printer::new().print(expr, &slab), "x * 2 + (y - 1) * 0.30000000000000004");
+//!     assert_eq!(
+//!         Printer::new().spaces(false).significant_digits(15).print(expr, &slab),
+//!         "x*2+(y-1)*0.3"
+//!     );
+//!     assert_eq!(Printer::new().keep_parens(true).print(expr, &slab), "((x * 2)) + (y - 1) * 0.30000000000000004");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The output parses to an expression that evaluates exactly like the original,
+//! unless `significant_digits()` rounds a constant, or the multiplication
+//! symbol is a word that the `Parser` doesn't know as an
+//! [alias](../parser/struct.ParserBuilder.html#method.add_alias).  Unary `+`
+//! is dropped.
+
+use crate::evaler::{level_of, OP_PASSES};
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    BinaryOp::{self, EMul, EEQ},
+    Expression, ExpressionI,
+    ExpressionOrString::{EExpr, EStr},
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::mem;
+
+/// Formatting options for expression strings.
+///
+/// [See the `printer module` documentation.](index.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Printer {
+    spaces: bool,
+    digits: Option<usize>,
+    mul: String,
+    keep_parens: bool,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Printer {
+    /// Starts with the normalized format: spaces around operators, all the
+    /// digits that constants need, `*`, and only the necessary parentheses.
+    pub fn new() -> Self {
+        Printer {
+            spaces: true,
+            digits: None,
+            mul: "*".to_string(),
+            keep_parens: false,
+        }
+    }
+
+    /// Surround binary operators with spaces, like `x + 1`, or not, like
+    /// `x+1`.  A multiplication symbol that is a word is always surrounded
+    /// with spaces.
+    pub fn spaces(mut self, on: bool) -> Self {
+        self.spaces = on;
+        self
+    }
+
+    /// Round constants to `digits` significant digits (at least 1), and write
+    /// them in their shortest form, so `0.30000000000000004` becomes `0.3`
+    /// with 15 digits.  Constants with fewer digits aren't changed.
+    pub fn significant_digits(mut self, digits: usize) -> Self {
+        self.digits = Some(digits.max(1));
+        self
+    }
+
+    /// Write multiplications with `symbol` instead of `*`, like `"times"`.
+    /// The output can only be parsed by a `Parser` that has `symbol` as an
+    /// [alias](../parser/struct.ParserBuilder.html#method.add_alias) of `*`.
+    pub fn mul_symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.mul = symbol.into();
+        self
+    }
+
+    /// Keep the parentheses of the source, even where they are redundant,
+    /// like in `((x)) + (y * 2)`.  By default, parentheses are dropped and
+    /// added where the grouping needs them.  Either way, the output groups
+    /// operators like the source does.
+    pub fn keep_parens(mut self, on: bool) -> Self {
+        self.keep_parens = on;
+        self
+    }
+
+    /// Formats `expr`.
+    ///
+    /// Invalid values from
+    /// [`parse_with_recovery()`](../parser/struct.Parser.html#method.parse_with_recovery)
+    /// are written as `?`, which doesn't parse.
+    pub fn print(&self, expr: &Expression, slab: &Slab) -> String {
+        let writer = Writer { opts: self, slab };
+        let mut text = writer.expr(expr).text;
+        // The items of a tuple:
+        for item in writer.exprs(&expr.tuple) {
+            text.push_str(", ");
+            text.push_str(&item);
+        }
+        text
+    }
+}
+
+// A formatted operand.
+struct Term {
+    text: String,
+    level: usize, // 0 for atoms, or the operator pass (counting from 1) for operations.
+}
+
+impl Term {
+    fn atom(text: String) -> Self {
+        Term { text, level: 0 }
+    }
+
+    // Renders the operand of an operation, in parentheses if `wrap` says that
+    // its own level needs them:
+    fn operand(self, wrap: impl Fn(usize) -> bool) -> String {
+        if self.level > 0 && wrap(self.level) {
+            format!("({})", self.text)
+        } else {
+            self.text
+        }
+    }
+}
+
+struct Writer<'a> {
+    opts: &'a Printer,
+    slab: &'a Slab,
+}
+
+impl Writer<'_> {
+    fn expr_i(&self, xi: &ExpressionI) -> Term {
+        self.expr(get_expr!(self.slab.ps, xi))
+    }
+
+    fn exprs<'x>(&self, xis: impl IntoIterator<Item = &'x ExpressionI>) -> Vec<String> {
+        xis.into_iter().map(|xi| self.expr_i(xi).text).collect()
+    }
+
+    fn op(&self, op: BinaryOp) -> String {
+        let sym = match op {
+            EMul => self.opts.mul.as_str(),
+            _ => op.symbol(),
+        };
+        let word = sym.bytes().any(|b| b.is_ascii_alphanumeric() || b == b'_');
+        if self.opts.spaces || word {
+            format!(" {} ", sym)
+        } else {
+            sym.to_string()
+        }
+    }
+
+    fn expr(&self, expr: &Expression) -> Term {
+        // The source's own parentheses already group everything:
+        if self.opts.keep_parens {
+            let mut text = self.value(&expr.first).text;
+            for pair in &expr.pairs {
+                text.push_str(&self.op(pair.0));
+                text.push_str(&self.value(&pair.1).text);
+            }
+            return Term::atom(text);
+        }
+
+        let mut terms = Vec::with_capacity(expr.pairs.len() + 1);
+        let mut ops = Vec::with_capacity(expr.pairs.len());
+        terms.push(self.value(&expr.first));
+        for pair in &expr.pairs {
+            ops.push(pair.0);
+            terms.push(self.value(&pair.1));
+        }
+
+        // Group the operators the way that eval() does:
+        let reduce = |terms: &mut Vec<Term>, ops: &mut Vec<BinaryOp>, i, level, rtol| {
+            let right = terms.remove(i + 1);
+            let left = mem::replace(&mut terms[i], Term::atom(String::new()));
+            // Operations of the same pass are grouped from one side:
+            let left = left.operand(|l| l > level || (l == level && rtol));
+            let right = right.operand(|l| l > level || (l == level && !rtol));
+            terms[i] = Term {
+                text: format!("{}{}{}", left, self.op(ops.remove(i)), right),
+                level,
+            };
+        };
+        for (pass, (search, rtol)) in OP_PASSES.iter().enumerate() {
+            let level = pass + 1;
+            if *rtol {
+                for i in (0..ops.len()).rev() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut terms, &mut ops, i, level, true);
+                    }
+                }
+            } else {
+                let mut i = 0;
+                while i < ops.len() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut terms, &mut ops, i, level, false);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+        terms.pop().unwrap_or_else(|| Term::atom("?".to_string()))
+    }
+
+    fn value(&self, value: &Value) -> Term {
+        // Renders a unary operator:
+        let unary =
+            |sym: &str, term: Term| Term::atom(format!("{}{}", sym, term.operand(|_| true)));
+        match value {
+            EConstant(c) => Term::atom(self.number(*c)),
+            EError(_) => Term::atom("?".to_string()),
+            EUnaryOp(EPos(vi)) => self.value(get_val!(self.slab.ps, vi)),
+            EUnaryOp(ENeg(vi)) => unary("-", self.value(get_val!(self.slab.ps, vi))),
+            EUnaryOp(ENot(vi)) => unary("!", self.value(get_val!(self.slab.ps, vi))),
+            EUnaryOp(EParentheses(xi)) if self.opts.keep_parens => {
+                Term::atom(format!("({})", self.expr_i(xi).text))
+            }
+            EUnaryOp(EParentheses(xi)) => self.expr_i(xi),
+            EPrintFunc(pf) => {
+                let args =
+                    pf.0.iter()
+                        .map(|x_or_s| match x_or_s {
+                            EExpr(xi) => self.expr_i(xi).text,
+                            EStr(s) => format!("\"{}\"", s),
+                        })
+                        .collect();
+                call("print", args)
+            }
+            EStdFunc(f) => self.std_func(f),
+        }
+    }
+
+    fn number(&self, c: f64) -> String {
+        match self.opts.digits {
+            Some(digits) if c.is_finite() => {
+                // Round in scientific notation, then write the shortest form:
+                let rounded = format!("{:.*e}", digits - 1, c);
+                rounded.parse::<f64>().unwrap_or(c).to_string()
+            }
+            _ => c.to_string(),
+        }
+    }
+
+    fn std_func(&self, f: &StdFunc) -> Term {
+        let (name, args) = match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { name, .. } => return Term::atom(name.to_string()),
+            EVar(name) => return Term::atom(name.to_string()),
+            EFunc { name, args } => (name.as_str(), self.exprs(args)),
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => (
+                "sum",
+                [var.to_string()]
+                    .into_iter()
+                    .chain(self.exprs([from, to, expr]))
+                    .collect(),
+            ),
+            EFuncLet { var, value, expr } => {
+                let (eq, semi) = if self.opts.spaces {
+                    (" = ", "; ")
+                } else {
+                    ("=", ";")
+                };
+                // The body extends to the right, so the binding is wrapped
+                // whenever it is an operand:
+                return Term {
+                    text: format!(
+                        "let {}{}{}{}{}",
+                        var,
+                        eq,
+                        self.expr_i(value).text,
+                        semi,
+                        self.expr_i(expr).text
+                    ),
+                    level: OP_PASSES.len() + 1,
+                };
+            }
+
+            EFuncIn {
+                negated,
+                value,
+                list,
+            } => {
+                // `in` groups like a comparison:
+                let level = level_of(EEQ);
+                return Term {
+                    text: format!(
+                        "{} {} ({})",
+                        self.expr_i(value).operand(|l| l > level),
+                        if *negated { "not in" } else { "in" },
+                        self.exprs(list).join(", ")
+                    ),
+                    level,
+                };
+            }
+
+            EFuncInt(xi) => ("int", self.exprs([xi])),
+            EFuncCeil(xi) => ("ceil", self.exprs([xi])),
+            EFuncFloor(xi) => ("floor", self.exprs([xi])),
+            EFuncAbs(xi) => ("abs", self.exprs([xi])),
+            EFuncSign(xi) => ("sign", self.exprs([xi])),
+            EFuncLog { base, expr } => ("log", self.exprs(base.iter().chain([expr]))),
+            EFuncRound { modulus, expr } => ("round", self.exprs(modulus.iter().chain([expr]))),
+            EFuncMin { first, rest } => ("min", self.exprs([first].into_iter().chain(rest))),
+            EFuncMax { first, rest } => ("max", self.exprs([first].into_iter().chain(rest))),
+            EFuncPiecewise { branches, default } => (
+                "piecewise",
+                self.exprs(
+                    branches
+                        .iter()
+                        .flat_map(|(cond, val)| [cond, val])
+                        .chain([default]),
+                ),
+            ),
+            EFuncInterp {
+                extrapolate,
+                x,
+                points,
+            } => (
+                if *extrapolate { "interp_ext" } else { "interp" },
+                self.exprs(
+                    [x].into_iter()
+                        .chain(points.iter().flat_map(|(px, py)| [px, py])),
+                ),
+            ),
+            EFuncFma { a, b, c } => ("fma", self.exprs([a, b, c])),
+            EFuncCoalesce { value, fallback } => ("coalesce", self.exprs([value, fallback])),
+            EFuncQuotient { dividend, divisor } => ("quotient", self.exprs([dividend, divisor])),
+            EFuncRemainder { dividend, divisor } => ("remainder", self.exprs([dividend, divisor])),
+            EFuncNorm { squared, args } => {
+                (if *squared { "sumsq" } else { "norm" }, self.exprs(args))
+            }
+            EFuncE => ("e", Vec::new()),
+            EFuncPi => ("pi", Vec::new()),
+
+            EFuncSin(xi) => ("sin", self.exprs([xi])),
+            EFuncCos(xi) => ("cos", self.exprs([xi])),
+            EFuncTan(xi) => ("tan", self.exprs([xi])),
+            EFuncASin(xi) => ("asin", self.exprs([xi])),
+            EFuncACos(xi) => ("acos", self.exprs([xi])),
+            EFuncATan(xi) => ("atan", self.exprs([xi])),
+            EFuncSinH(xi) => ("sinh", self.exprs([xi])),
+            EFuncCosH(xi) => ("cosh", self.exprs([xi])),
+            EFuncTanH(xi) => ("tanh", self.exprs([xi])),
+            EFuncASinH(xi) => ("asinh", self.exprs([xi])),
+            EFuncACosH(xi) => ("acosh", self.exprs([xi])),
+            EFuncATanH(xi) => ("atanh", self.exprs([xi])),
+            EFuncSqrt(xi) => ("sqrt", self.exprs([xi])),
+        };
+        call(name, args)
+    }
+}
+
+// Renders a function call:
+fn call(name: &str, args: Vec<String>) -> Term {
+    Term::atom(format!("{}({})", name, args.join(", ")))
+}
@@ -0,0 +1,275 @@
+//! Exports parsed expressions as postfix (Reverse Polish Notation) tokens,
+//! for stack machines.
+//!
+//! [`Expression::to_rpn()`](../parser/struct.Expression.html#method.to_rpn)
+//! lists the operands of every operation before the operation itself.  A stack
+//! machine evaluates the tokens in order:
+//!
+//! * `Const` and `Var` push a value.  A `Var` is looked up like `eval()` looks
+//!   it up, so it can also name a constant like `phi`, if the Namespace doesn't
+//!   define it.
+//! * `Op` pops the right operand, then the left one, and pushes the result.
+//! * `Call` pops `argc` arguments (the last argument is on top) and pushes the
+//!   result.  `name` is the name of a built-in function (`sin`, `log`,
+//!   `interp_ext`, ...), `neg` or `not` for the unary `-` and `!` operators,
+//!   or the name of a custom function.  The arguments are in the order that
+//!   they are written, like `log(base, x)`.
+//!
+//! Operators are grouped exactly like `eval()` groups them: `^` is
+//! right-associative, and `-`, `/` and `%` are evaluated from left to right,
+//! so `2^3^2` is `2 3 2 ^ ^`, and `8-3-2` is `8 3 - 2 -`.  `e()` and `pi()`
+//! become constants, parentheses and unary `+` disappear.
+//!
+//! A stack machine evaluates every operand, so `piecewise()` and `coalesce()`
+//! evaluate all of their arguments, instead of only the ones that `eval()`
+//! needs.  The results are the same unless an argument fails to evaluate.
+//!
+//! # Example
+//! ```
+//! use fasteval2::parser::BinaryOp;
+//! use fasteval2::{Parser, RpnToken, Slab};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = Slab::new();
+//!     let expr = Parser::new().parse("-x + max(2, y) ^ 2", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(
+//!         expr.to_rpn(&slab)?,
+//!         vec![
+//!             RpnToken::Var("x".to_string()),
+//!             RpnToken::Call { name: "neg".to_string(), argc: 1 },
+//!             RpnToken::Const(2.0),
+//!             RpnToken::Var("y".to_string()),
+//!             RpnToken::Call { name: "max".to_string(), argc: 2 },
+//!             RpnToken::Const(2.0),
+//!             RpnToken::Op(BinaryOp::EExp),
+//!             RpnToken::Op(BinaryOp::EAdd),
+//!         ]
+//!     );
+//!     Ok(())
+//! }
+//! ```
+//!
+//! `sum()` loops, `let` bindings, `in`, `print()` and tuples can't be expressed
+//! as tokens, and produce `Error::NotAllowed`.
+//!
+//! The `Display` form of the tokens, joined with spaces, can be read back with
+//! [`Parser::parse_rpn()`](../parser/struct.Parser.html#method.parse_rpn).
+
+use crate::error::Error;
+use crate::evaler::OP_PASSES;
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    BinaryOp, Expression, ExpressionI,
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::f64::consts;
+use core::fmt;
+
+/// One token of a postfix expression.
+///
+/// [See the `rpn module` documentation.](index.html)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RpnToken {
+    Const(f64),
+    Var(String),
+    Op(BinaryOp),
+    Call { name: String, argc: usize },
+}
+
+/// Tokens are displayed in the form that
+/// [`Parser::parse_rpn()`](../parser/struct.Parser.html#method.parse_rpn)
+/// reads: numbers, names, operator symbols like `^`, and calls as `name/argc`.
+impl fmt::Display for RpnToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpnToken::Const(c) => write!(f, "{}", c),
+            RpnToken::Var(name) => write!(f, "{}", name),
+            RpnToken::Op(op) => write!(f, "{}", op.symbol()),
+            RpnToken::Call { name, argc } => write!(f, "{}/{}", name, argc),
+        }
+    }
+}
+
+impl Expression {
+    /// Converts this `Expression` to postfix tokens.
+    /// [See the `rpn module` documentation.](../rpn/index.html)
+    pub fn to_rpn(&self, slab: &Slab) -> Result<Vec<RpnToken>, Error> {
+        // The tokens have a single result:
+        if !self.tuple.is_empty() {
+            return Err(Error::NotAllowed("tuple".to_string()));
+        }
+        let mut out = Vec::new();
+        expr(self, slab, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn expr(expr: &Expression, slab: &Slab, out: &mut Vec<RpnToken>) -> Result<(), Error> {
+    // Each group holds the tokens of one operand.  Neighbouring groups are
+    // joined in the same order that eval() reduces its values:
+    let mut groups = Vec::with_capacity(expr.pairs.len() + 1);
+    let mut ops: Vec<BinaryOp> = expr.pairs.iter().map(|pair| pair.0).collect();
+    for val in Some(&expr.first)
+        .into_iter()
+        .chain(expr.pairs.iter().map(|pair| &pair.1))
+    {
+        let mut group = Vec::new();
+        value(val, slab, &mut group)?;
+        groups.push(group);
+    }
+
+    let reduce = |groups: &mut Vec<Vec<RpnToken>>, ops: &mut Vec<BinaryOp>, i: usize| {
+        let right = groups.remove(i + 1);
+        groups[i].extend(right);
+        groups[i].push(RpnToken::Op(ops.remove(i)));
+    };
+    for (search, rtol) in OP_PASSES {
+        if rtol {
+            for i in (0..ops.len()).rev() {
+                if search.contains(&ops[i]) {
+                    reduce(&mut groups, &mut ops, i);
+                }
+            }
+        } else {
+            let mut i = 0;
+            while i < ops.len() {
+                if search.contains(&ops[i]) {
+                    reduce(&mut groups, &mut ops, i);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+    if groups.len() != 1 {
+        return Err(Error::Unreachable);
+    }
+    out.extend(groups.into_iter().flatten());
+    Ok(())
+}
+
+fn value(val: &Value, slab: &Slab, out: &mut Vec<RpnToken>) -> Result<(), Error> {
+    match val {
+        EConstant(c) => out.push(RpnToken::Const(*c)),
+        EUnaryOp(EPos(val_i)) => value(get_val!(slab.ps, val_i), slab, out)?,
+        EUnaryOp(ENeg(val_i)) => {
+            value(get_val!(slab.ps, val_i), slab, out)?;
+            out.push(call("neg", 1));
+        }
+        EUnaryOp(ENot(val_i)) => {
+            value(get_val!(slab.ps, val_i), slab, out)?;
+            out.push(call("not", 1));
+        }
+        EUnaryOp(EParentheses(expr_i)) => expr(get_expr!(slab.ps, expr_i), slab, out)?,
+        EStdFunc(f) => stdfunc(f, slab, out)?,
+        EPrintFunc(_) => return Err(Error::NotAllowed("print".to_string())),
+        EError(err) => return Err(err.clone()),
+    }
+    Ok(())
+}
+
+fn stdfunc(f: &StdFunc, slab: &Slab, out: &mut Vec<RpnToken>) -> Result<(), Error> {
+    let (name, args): (&str, Vec<&ExpressionI>) = match f {
+        #[cfg(feature = "unsafe-vars")]
+        EUnsafeVar { name, .. } => {
+            out.push(RpnToken::Var(name.to_string()));
+            return Ok(());
+        }
+        EVar(name) => {
+            out.push(RpnToken::Var(name.to_string()));
+            return Ok(());
+        }
+        EFunc { name, args } => (name, args.iter().collect()),
+        EFuncSum { .. } => return Err(Error::NotAllowed("sum".to_string())),
+        EFuncLet { .. } => return Err(Error::NotAllowed("let".to_string())),
+        EFuncIn { .. } => return Err(Error::NotAllowed("in".to_string())),
+
+        EFuncE => {
+            out.push(RpnToken::Const(consts::E));
+            return Ok(());
+        }
+        EFuncPi => {
+            out.push(RpnToken::Const(consts::PI));
+            return Ok(());
+        }
+
+        EFuncInt(xi) => ("int", vec![xi]),
+        EFuncCeil(xi) => ("ceil", vec![xi]),
+        EFuncFloor(xi) => ("floor", vec![xi]),
+        EFuncAbs(xi) => ("abs", vec![xi]),
+        EFuncSign(xi) => ("sign", vec![xi]),
+        EFuncLog { base, expr } => ("log", base.iter().chain([expr]).collect()),
+        EFuncRound { modulus, expr } => ("round", modulus.iter().chain([expr]).collect()),
+        EFuncMin { first, rest } => ("min", [first].into_iter().chain(rest).collect()),
+        EFuncMax { first, rest } => ("max", [first].into_iter().chain(rest).collect()),
+        EFuncPiecewise { branches, default } => (
+            "piecewise",
+            branches
+                .iter()
+                .flat_map(|(c, v)| [c, v])
+                .chain([default])
+                .collect(),
+        ),
+        EFuncInterp {
+            extrapolate,
+            x,
+            points,
+        } => (
+            if *extrapolate { "interp_ext" } else { "interp" },
+            [x].into_iter()
+                .chain(points.iter().flat_map(|(px, py)| [px, py]))
+                .collect(),
+        ),
+        EFuncFma { a, b, c } => ("fma", vec![a, b, c]),
+        EFuncCoalesce { value, fallback } => ("coalesce", vec![value, fallback]),
+        EFuncQuotient { dividend, divisor } => ("quotient", vec![dividend, divisor]),
+        EFuncRemainder { dividend, divisor } => ("remainder", vec![dividend, divisor]),
+        EFuncNorm { squared, args } => (
+            if *squared { "sumsq" } else { "norm" },
+            args.iter().collect(),
+        ),
+
+        EFuncSin(xi) => ("sin", vec![xi]),
+        EFuncCos(xi) => ("cos", vec![xi]),
+        EFuncTan(xi) => ("tan", vec![xi]),
+        EFuncASin(xi) => ("asin", vec![xi]),
+        EFuncACos(xi) => ("acos", vec![xi]),
+        EFuncATan(xi) => ("atan", vec![xi]),
+        EFuncSinH(xi) => ("sinh", vec![xi]),
+        EFuncCosH(xi) => ("cosh", vec![xi]),
+        EFuncTanH(xi) => ("tanh", vec![xi]),
+        EFuncASinH(xi) => ("asinh", vec![xi]),
+        EFuncACosH(xi) => ("acosh", vec![xi]),
+        EFuncATanH(xi) => ("atanh", vec![xi]),
+        EFuncSqrt(xi) => ("sqrt", vec![xi]),
+    };
+    for xi in &args {
+        expr(get_expr!(slab.ps, xi), slab, out)?;
+    }
+    out.push(call(name, args.len()));
+    Ok(())
+}
+
+fn call(name: &str, argc: usize) -> RpnToken {
+    RpnToken::Call {
+        name: name.to_string(),
+        argc,
+    }
+}
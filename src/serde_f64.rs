@@ -0,0 +1,59 @@
+//! Exact (de)serialization of `f64` constants for the `serde` feature.
+//!
+//! Binary formats (like bincode) store the raw `f64` bits, so every value
+//! round-trips exactly.  Human-readable formats (like JSON) write finite
+//! values as normal numbers, but most of them cannot represent NaN or
+//! infinity, so those are written as the strings `"NaN"`, `"inf"` and `"-inf"`.
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::Serializer;
+
+use core::fmt;
+
+pub(crate) fn serialize<S: Serializer>(x: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if !serializer.is_human_readable() || x.is_finite() {
+        serializer.serialize_f64(*x)
+    } else if x.is_nan() {
+        serializer.serialize_str("NaN")
+    } else if *x > 0.0 {
+        serializer.serialize_str("inf")
+    } else {
+        serializer.serialize_str("-inf")
+    }
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(F64Visitor)
+    } else {
+        deserializer.deserialize_f64(F64Visitor)
+    }
+}
+
+struct F64Visitor;
+
+impl Visitor<'_> for F64Visitor {
+    type Value = f64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number, \"NaN\", \"inf\" or \"-inf\"")
+    }
+
+    fn visit_f64<E: de::Error>(self, x: f64) -> Result<f64, E> {
+        Ok(x)
+    }
+    fn visit_i64<E: de::Error>(self, x: i64) -> Result<f64, E> {
+        Ok(x as f64)
+    }
+    fn visit_u64<E: de::Error>(self, x: u64) -> Result<f64, E> {
+        Ok(x as f64)
+    }
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<f64, E> {
+        match s {
+            "NaN" => Ok(f64::NAN),
+            "inf" => Ok(f64::INFINITY),
+            "-inf" => Ok(f64::NEG_INFINITY),
+            _ => Err(E::invalid_value(de::Unexpected::Str(s), &self)),
+        }
+    }
+}
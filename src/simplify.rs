@@ -0,0 +1,483 @@
+//! Partial evaluation of parsed expressions into readable strings.
+//!
+//! [`Expression::to_simplified_string()`](../parser/struct.Expression.html#method.to_simplified_string)
+//! evaluates every part of an expression that doesn't depend on an unknown
+//! variable, and renders what is left as an expression string:
+//!
+//! ```
+//! use fasteval2::{EmptyNamespace, Parser, Slab};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let mut slab = Slab::new();
+//!     let expr_ref = Parser::new().parse("2*3 + x*1", &mut slab.ps)?.from(&slab.ps);
+//!     assert_eq!(expr_ref.to_simplified_string(&slab, &mut EmptyNamespace)?, "6 + x");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Variables and custom functions that the Namespace defines are replaced by
+//! their values, named constants like `phi` are replaced when the Namespace
+//! doesn't define them, and everything else stays symbolic.  Multiplications
+//! and divisions by 1, additions and subtractions of 0 and powers of 1 are
+//! dropped.  Operators are grouped the way that evaluation groups them, and
+//! nothing is rearranged: `x + 1 + 2` becomes `x + 3` (additions are grouped
+//! from the right), but `x - 1 - 2` stays, because `(x - 1) - 2` isn't always
+//! the same floating-point number as `x - 3`.
+//!
+//! The output is normalized: operators are surrounded by spaces, function
+//! arguments are separated by `", "`, and parentheses are only kept where the
+//! grouping needs them.  Parsing the output gives an expression that evaluates
+//! to the same value as the original (except for `-0` versus `0`, because of
+//! the dropped `+ 0`s).
+//!
+//! `print()`, `rand()`/`randint()` and `now()` calls are never evaluated, because that
+//! would happen only once, at simplification time.
+
+use crate::error::Error;
+use crate::evaler::{level_of, named_constant, Evaler, OP_PASSES};
+use crate::evalns::{is_impure, EvalNamespace, ModMode};
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    BinaryOp::{self, EAdd, EDiv, EExp, EMod, EMul, ESub, EEQ},
+    Expression, ExpressionI,
+    ExpressionOrString::{EExpr, EStr},
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::mem;
+
+impl Expression {
+    /// Evaluates the constant parts of this `Expression`, and renders the rest
+    /// as an expression string.
+    ///
+    /// Errors from evaluating the constant parts (like a `BudgetExceeded` from
+    /// an [`EvalNS`](../evalns/struct.EvalNS.html)) are returned as-is.
+    ///
+    /// [See the `simplify module` documentation.](../simplify/index.html)
+    pub fn to_simplified_string(
+        &self,
+        slab: &Slab,
+        ns: &mut impl EvalNamespace,
+    ) -> Result<String, Error> {
+        let mut s = Simplifier {
+            slab,
+            ns,
+            bound: Vec::new(),
+        };
+        let mut text = s.expr(self)?.render();
+        // The items of a tuple:
+        for item in &self.tuple {
+            text.push_str(", ");
+            text.push_str(&s.expr_i(item)?.render());
+        }
+        Ok(text)
+    }
+}
+
+// A simplified operand.
+enum Term {
+    Const(f64),
+    Sym {
+        text: String,
+        level: usize, // 0 for atoms, or the operator pass (counting from 1) for operations.
+        free: bool,   // Depends on something unknown, other than `sum()` loop variables.
+    },
+}
+
+impl Term {
+    fn render(self) -> String {
+        match self {
+            Term::Const(c) => c.to_string(),
+            Term::Sym { text, .. } => text,
+        }
+    }
+
+    // Renders the operand of an operation at `level`, in parentheses if
+    // `wrap` says that its own level needs them:
+    fn operand(self, wrap: impl Fn(usize) -> bool) -> String {
+        match self {
+            Term::Sym { text, level, .. } if level > 0 && wrap(level) => format!("({})", text),
+            term => term.render(),
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        match self {
+            Term::Const(_) => false,
+            Term::Sym { free, .. } => *free,
+        }
+    }
+}
+
+// Combines two operands of `op`, which is in operator pass `level`:
+fn combine(op: BinaryOp, level: usize, rtol: bool, left: Term, right: Term) -> Term {
+    match (op, &left, &right) {
+        // `0^0` is left for the Namespace of the evaluation:
+        (EExp, Term::Const(l), Term::Const(r)) if *l == 0.0 && *r == 0.0 => {
+            sym(op, level, rtol, left, right)
+        }
+        // So are the non-integers of `%`:
+        (EMod, Term::Const(l), Term::Const(r)) if !ModMode::is_foldable(*l, *r) => {
+            sym(op, level, rtol, left, right)
+        }
+        (_, Term::Const(l), Term::Const(r)) => Term::Const(op.binaryop_eval(Some(l), Some(r))),
+        (EMul, Term::Const(c), _) | (EAdd, Term::Const(c), _) if *c == identity(op) => right,
+        (EMul, _, Term::Const(c))
+        | (EAdd, _, Term::Const(c))
+        | (ESub, _, Term::Const(c))
+        | (EDiv, _, Term::Const(c))
+        | (EExp, _, Term::Const(c))
+            if *c == identity(op) =>
+        {
+            left
+        }
+        _ => sym(op, level, rtol, left, right),
+    }
+}
+
+// Writes `left op right` out, without simplifying it:
+fn sym(op: BinaryOp, level: usize, rtol: bool, left: Term, right: Term) -> Term {
+    let free = left.is_free() || right.is_free();
+    // Operations of the same pass are grouped from one side:
+    let left = left.operand(|l| l > level || (l == level && rtol));
+    let right = right.operand(|l| l > level || (l == level && !rtol));
+    Term::Sym {
+        text: format!("{} {} {}", left, op.symbol(), right),
+        level,
+        free,
+    }
+}
+
+// The right operand that leaves the left one unchanged:
+fn identity(op: BinaryOp) -> f64 {
+    match op {
+        EAdd | ESub => 0.0,
+        _ => 1.0,
+    }
+}
+
+// Renders a function call:
+fn call(name: &str, args: Vec<Term>) -> Term {
+    let free = args.iter().any(Term::is_free);
+    let args: Vec<String> = args.into_iter().map(Term::render).collect();
+    Term::Sym {
+        text: format!("{}({})", name, args.join(", ")),
+        level: 0,
+        free,
+    }
+}
+
+struct Simplifier<'a, NS> {
+    slab: &'a Slab,
+    ns: &'a mut NS,
+    bound: Vec<&'a str>, // The variables of the enclosing sum() loops.
+}
+
+impl<'a, NS: EvalNamespace> Simplifier<'a, NS> {
+    fn expr_i(&mut self, xi: &ExpressionI) -> Result<Term, Error> {
+        self.expr(get_expr!(self.slab.ps, xi))
+    }
+
+    fn exprs<'x>(
+        &mut self,
+        xis: impl IntoIterator<Item = &'x ExpressionI>,
+    ) -> Result<Vec<Term>, Error> {
+        xis.into_iter().map(|xi| self.expr_i(xi)).collect()
+    }
+
+    fn expr(&mut self, expr: &'a Expression) -> Result<Term, Error> {
+        let mut terms = Vec::with_capacity(expr.pairs.len() + 1);
+        let mut ops = Vec::with_capacity(expr.pairs.len());
+        terms.push(self.value(&expr.first)?);
+        for pair in &expr.pairs {
+            ops.push(pair.0);
+            terms.push(self.value(&pair.1)?);
+        }
+
+        // Group the operators the way that eval() does:
+        let reduce = |terms: &mut Vec<Term>, ops: &mut Vec<BinaryOp>, i, level, rtol| {
+            let right = terms.remove(i + 1);
+            let left = mem::replace(&mut terms[i], Term::Const(0.0));
+            terms[i] = combine(ops.remove(i), level, rtol, left, right);
+        };
+        for (pass, (search, rtol)) in OP_PASSES.iter().enumerate() {
+            let level = pass + 1;
+            if *rtol {
+                for i in (0..ops.len()).rev() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut terms, &mut ops, i, level, true);
+                    }
+                }
+            } else {
+                let mut i = 0;
+                while i < ops.len() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut terms, &mut ops, i, level, false);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+        terms.pop().ok_or(Error::Unreachable)
+    }
+
+    fn value(&mut self, value: &'a Value) -> Result<Term, Error> {
+        // Renders a unary operator:
+        let unary = |sym: &str, term: Term| {
+            let free = term.is_free();
+            Term::Sym {
+                text: format!("{}{}", sym, term.operand(|_| true)),
+                level: 0,
+                free,
+            }
+        };
+        Ok(match value {
+            EConstant(c) => Term::Const(*c),
+            EError(err) => return Err(err.clone()),
+            EUnaryOp(EPos(vi)) => self.value(get_val!(self.slab.ps, vi))?,
+            EUnaryOp(ENeg(vi)) => match self.value(get_val!(self.slab.ps, vi))? {
+                Term::Const(c) => Term::Const(-c),
+                term => unary("-", term),
+            },
+            EUnaryOp(ENot(vi)) => match self.value(get_val!(self.slab.ps, vi))? {
+                Term::Const(c) => Term::Const(bool_to_f64!(f64_eq!(c, 0.0))),
+                term => unary("!", term),
+            },
+            EUnaryOp(EParentheses(xi)) => self.expr_i(xi)?,
+            EPrintFunc(pf) => {
+                let mut parts = Vec::with_capacity(pf.0.len());
+                for x_or_s in &pf.0 {
+                    parts.push(match x_or_s {
+                        EExpr(xi) => self.expr_i(xi)?.render(),
+                        EStr(s) => format!("\"{}\"", s),
+                    });
+                }
+                Term::Sym {
+                    text: format!("print({})", parts.join(", ")),
+                    level: 0,
+                    free: true,
+                }
+            }
+            EStdFunc(f) => self.std_func(value, f)?,
+        })
+    }
+
+    fn std_func(&mut self, value: &'a Value, f: &'a StdFunc) -> Result<Term, Error> {
+        let args = match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { name, .. } => {
+                return Ok(Term::Sym {
+                    text: name.to_string(),
+                    level: 0,
+                    free: true,
+                })
+            }
+            EVar(name) => {
+                if self.bound.contains(&name.as_str()) {
+                    return Ok(Term::Sym {
+                        text: name.to_string(),
+                        level: 0,
+                        free: false,
+                    });
+                }
+                let val = self.ns.try_lookup(name, Vec::new(), &mut String::new())?;
+                return Ok(match val.or_else(|| named_constant(name)) {
+                    Some(c) => Term::Const(c),
+                    None => Term::Sym {
+                        text: name.to_string(),
+                        level: 0,
+                        free: true,
+                    },
+                });
+            }
+            EFunc { name, args: xis } => {
+                let args = self.exprs(xis)?;
+                let consts: Option<Vec<f64>> = args
+                    .iter()
+                    .map(|term| match term {
+                        Term::Const(c) => Some(*c),
+                        Term::Sym { .. } => None,
+                    })
+                    .collect();
+                let mut term = call(name, args);
+                if let Some(consts) = consts {
+                    if !is_impure(name) {
+                        if let Some(c) = self.ns.try_lookup(name, consts, &mut String::new())? {
+                            return Ok(Term::Const(c));
+                        }
+                    }
+                    if let Term::Sym { ref mut free, .. } = term {
+                        *free = true;
+                    }
+                }
+                return Ok(term);
+            }
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                let from = self.expr_i(from)?;
+                let to = self.expr_i(to)?;
+                self.bound.push(var);
+                let body = self.expr_i(expr)?;
+                self.bound.pop();
+                let foldable =
+                    matches!((&from, &to), (Term::Const(_), Term::Const(_))) && !body.is_free();
+                if foldable {
+                    match value.eval(self.slab, self.ns) {
+                        Ok(c) => return Ok(Term::Const(c)),
+                        Err(Error::UndefinedVariable { .. } | Error::UndefinedFunction { .. }) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                let var = Term::Sym {
+                    text: var.to_string(),
+                    level: 0,
+                    free: false,
+                };
+                let mut term = call("sum", vec![var, from, to, body]);
+                if foldable {
+                    if let Term::Sym { ref mut free, .. } = term {
+                        *free = true;
+                    }
+                }
+                return Ok(term);
+            }
+            EFuncLet {
+                var,
+                value: bound,
+                expr,
+            } => {
+                let bound = self.expr_i(bound)?;
+                self.bound.push(var);
+                let body = self.expr_i(expr)?;
+                self.bound.pop();
+                let foldable = matches!(bound, Term::Const(_)) && !body.is_free();
+                if foldable {
+                    match value.eval(self.slab, self.ns) {
+                        Ok(c) => return Ok(Term::Const(c)),
+                        Err(Error::UndefinedVariable { .. } | Error::UndefinedFunction { .. }) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                // The body extends to the right, so the binding is wrapped
+                // whenever it is an operand:
+                return Ok(Term::Sym {
+                    free: foldable || bound.is_free() || body.is_free(),
+                    text: format!("let {} = {}; {}", var, bound.render(), body.render()),
+                    level: OP_PASSES.len() + 1,
+                });
+            }
+
+            EFuncIn {
+                negated,
+                value: tested,
+                list,
+            } => {
+                let tested = self.expr_i(tested)?;
+                let items = self.exprs(list)?;
+                if matches!(tested, Term::Const(_))
+                    && items.iter().all(|item| matches!(item, Term::Const(_)))
+                {
+                    return Ok(Term::Const(value.eval(self.slab, self.ns)?));
+                }
+                // `in` groups like a comparison:
+                let level = level_of(EEQ);
+                let free = tested.is_free() || items.iter().any(Term::is_free);
+                let items: Vec<String> = items.into_iter().map(Term::render).collect();
+                return Ok(Term::Sym {
+                    text: format!(
+                        "{} {} ({})",
+                        tested.operand(|l| l > level),
+                        if *negated { "not in" } else { "in" },
+                        items.join(", ")
+                    ),
+                    level,
+                    free,
+                });
+            }
+
+            EFuncInt(xi) => ("int", vec![self.expr_i(xi)?]),
+            EFuncCeil(xi) => ("ceil", vec![self.expr_i(xi)?]),
+            EFuncFloor(xi) => ("floor", vec![self.expr_i(xi)?]),
+            EFuncAbs(xi) => ("abs", vec![self.expr_i(xi)?]),
+            EFuncSign(xi) => ("sign", vec![self.expr_i(xi)?]),
+            EFuncLog { base, expr } => ("log", self.exprs(base.iter().chain(Some(expr)))?),
+            EFuncRound { modulus, expr } => {
+                ("round", self.exprs(modulus.iter().chain(Some(expr)))?)
+            }
+            EFuncMin { first, rest } => ("min", self.exprs(Some(first).into_iter().chain(rest))?),
+            EFuncMax { first, rest } => ("max", self.exprs(Some(first).into_iter().chain(rest))?),
+            EFuncPiecewise { branches, default } => (
+                "piecewise",
+                self.exprs(
+                    branches
+                        .iter()
+                        .flat_map(|(cond, val)| [cond, val])
+                        .chain(Some(default)),
+                )?,
+            ),
+            EFuncInterp {
+                extrapolate,
+                x,
+                points,
+            } => (
+                if *extrapolate { "interp_ext" } else { "interp" },
+                self.exprs(
+                    Some(x)
+                        .into_iter()
+                        .chain(points.iter().flat_map(|(px, py)| [px, py])),
+                )?,
+            ),
+            EFuncFma { a, b, c } => ("fma", self.exprs([a, b, c])?),
+            EFuncCoalesce { value, fallback } => ("coalesce", self.exprs([value, fallback])?),
+            EFuncQuotient { dividend, divisor } => ("quotient", self.exprs([dividend, divisor])?),
+            EFuncRemainder { dividend, divisor } => ("remainder", self.exprs([dividend, divisor])?),
+            EFuncNorm { squared, args } => {
+                (if *squared { "sumsq" } else { "norm" }, self.exprs(args)?)
+            }
+            EFuncE => ("e", Vec::new()),
+            EFuncPi => ("pi", Vec::new()),
+
+            EFuncSin(xi) => ("sin", vec![self.expr_i(xi)?]),
+            EFuncCos(xi) => ("cos", vec![self.expr_i(xi)?]),
+            EFuncTan(xi) => ("tan", vec![self.expr_i(xi)?]),
+            EFuncASin(xi) => ("asin", vec![self.expr_i(xi)?]),
+            EFuncACos(xi) => ("acos", vec![self.expr_i(xi)?]),
+            EFuncATan(xi) => ("atan", vec![self.expr_i(xi)?]),
+            EFuncSinH(xi) => ("sinh", vec![self.expr_i(xi)?]),
+            EFuncCosH(xi) => ("cosh", vec![self.expr_i(xi)?]),
+            EFuncTanH(xi) => ("tanh", vec![self.expr_i(xi)?]),
+            EFuncASinH(xi) => ("asinh", vec![self.expr_i(xi)?]),
+            EFuncACosH(xi) => ("acosh", vec![self.expr_i(xi)?]),
+            EFuncATanH(xi) => ("atanh", vec![self.expr_i(xi)?]),
+            EFuncSqrt(xi) => ("sqrt", vec![self.expr_i(xi)?]),
+        };
+
+        // A built-in function with constant arguments is evaluated:
+        let (name, args) = args;
+        if args.iter().all(|term| matches!(term, Term::Const(_))) {
+            return Ok(Term::Const(value.eval(self.slab, self.ns)?));
+        }
+        Ok(call(name, args))
+    }
+}
@@ -58,11 +58,14 @@ use crate::compiler::{
 use crate::error::Error;
 use crate::parser::{Expression, ExpressionI, Value, ValueI};
 
-use std::fmt;
-use std::mem;
+use core::fmt;
+use core::mem;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[cfg(feature = "unsafe-vars")]
-use std::collections::BTreeMap;
+use alloc::collections::BTreeMap;
+use alloc::{string::String, vec, vec::Vec};
 
 // Eliminate function call overhead:
 macro_rules! get_expr {
@@ -104,6 +107,79 @@ impl ExpressionI {
     pub fn from(self, ps: &ParseSlab) -> &Expression {
         get_expr!(ps, self)
     }
+
+    /// Checks that this expression and everything it refers to is in `ps`,
+    /// and that they can't refer back to each other in a loop.
+    ///
+    /// The parser always produces well-formed expressions (debug builds assert
+    /// it), so this is only useful for a `ParseSlab` that came from somewhere
+    /// else, like a deserialized one.  Evaluating a malformed expression can
+    /// recurse until the stack overflows.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let expr_i = Parser::new().parse("-(a + 1) * max(b, 2)", &mut slab.ps).unwrap();
+    /// assert!(expr_i.is_well_formed(&slab.ps));
+    ///
+    /// slab.ps.clear();
+    /// assert!(!expr_i.is_well_formed(&slab.ps));
+    /// ```
+    #[inline]
+    pub fn is_well_formed(self, ps: &ParseSlab) -> bool {
+        crate::parser::well_formed(ps, self)
+    }
+
+    /// Returns the byte range of the source string that this expression was
+    /// parsed from, without surrounding whitespace.
+    ///
+    /// Spans are only recorded by a [`Parser`](../parser/struct.Parser.html)
+    /// with [`record_spans`](../parser/struct.ParserBuilder.html#method.record_spans)
+    /// enabled, so this returns `None` otherwise.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let expr_i = Parser::new().with_spans().parse(" 2 + 3*4 ", &mut slab.ps).unwrap();
+    /// assert_eq!(expr_i.span(&slab.ps), Some(1..8));
+    /// ```
+    #[inline]
+    pub fn span(self, ps: &ParseSlab) -> Option<Range<usize>> {
+        let spans = ps.spans.exprs.get(self.0)?;
+        // A tuple ends with its last item:
+        match ps.exprs.get(self.0)?.tuple.last() {
+            Some(item) => Some(spans.first()?.0..item.span(ps)?.end),
+            None => Some(spans.first()?.0..spans.last()?.1),
+        }
+    }
+
+    /// Returns the span of the smallest node of this expression that covers
+    /// the byte offset `pos` of the source string, or `None` if `pos` is
+    /// outside of the expression or spans weren't recorded.
+    ///
+    /// Nodes are values (like numbers, variables, function calls and
+    /// parenthesized groups), and binary operations, grouped by precedence the
+    /// way that `eval()` groups them.  This is useful for hover and selection
+    /// in editors.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    ///
+    /// let mut slab = Slab::new();
+    /// let src = "2 + 3*4";
+    /// let expr_i = Parser::new().with_spans().parse(src, &mut slab.ps).unwrap();
+    /// let node = expr_i.node_at(&slab.ps, src.find('*').unwrap()).unwrap();
+    /// assert_eq!(&src[node], "3*4");
+    /// ```
+    #[inline]
+    pub fn node_at(self, ps: &ParseSlab, pos: usize) -> Option<Range<usize>> {
+        crate::parser::node_at(ps, self, pos).map(|(start, end)| start..end)
+    }
 }
 impl ValueI {
     /// Gets a Value reference from the ParseSlab.
@@ -117,6 +193,7 @@ impl ValueI {
 }
 
 /// [See the `slab module` documentation.](index.html)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Slab {
     pub ps: ParseSlab,
     pub cs: CompileSlab,
@@ -132,10 +209,12 @@ pub struct Slab {
 /// build --features unsafe-vars`).  I want this documentation to appear
 /// regardless of the build mode, so I'm putting it here.)
 ///
-/// Here is the function signature of the `add_unsafe_var()` method:
+/// Here is the function signature of the `add_unsafe_var()` method, and of
+/// `add_unsafe_var_ptr()`, which takes a raw pointer instead:
 ///
 /// ```text
 /// pub unsafe fn add_unsafe_var(&mut self, name: String, ptr: &f64)
+/// pub unsafe fn add_unsafe_var_ptr(&mut self, name: String, ptr: *const f64)
 /// ```
 ///
 /// If you are using [Unsafe Variables](../index.html#unsafe-variables), you
@@ -147,7 +226,7 @@ pub struct Slab {
 ///
 /// If you forget to pre-register an unsafe variable before `parse()`, the
 /// variable will be treated like a Normal Variable, and you'll probably get an
-/// [`Undefined`](../error/enum.Error.html#variant.Undefined) error during evaluation.
+/// [`UndefinedVariable`](../error/enum.Error.html#variant.UndefinedVariable) error during evaluation.
 ///
 /// ## Safety
 ///
@@ -213,20 +292,67 @@ pub struct Slab {
 /// }
 ///
 /// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParseSlab {
     pub(crate) exprs: Vec<Expression>,
     pub(crate) vals: Vec<Value>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) def_expr: Expression,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) def_val: Value,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) char_buf: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) recovered_errs: Option<Vec<(Error, (usize, usize))>>, // Only used in recovery mode, with the remaining input around each error.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) err_span: Option<(usize, usize)>, // The remaining input around the innermost value that failed to parse.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) spans: SourceSpans,
+    pub(crate) limit: usize, // The maximum number of exprs (and vals).
     #[cfg(feature = "unsafe-vars")]
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) unsafe_vars: BTreeMap<String, *const f64>,
 }
 
+// The parts of the source string that the nodes in a `ParseSlab` were parsed
+// from, recorded when `Parser.record_spans` is set.  They are kept apart from
+// the nodes, so that parsing without spans doesn't pay for them.
+#[derive(Default)]
+pub(crate) struct SourceSpans {
+    pub(crate) src_len: usize, // The length of the string that is being parsed.
+    pub(crate) exprs: Vec<Vec<(usize, usize)>>, // The spans of the values of each Expression.
+    pub(crate) vals: Vec<Option<(usize, usize)>>, // The span of each Value in ParseSlab.vals.
+}
+
+impl SourceSpans {
+    pub(crate) fn set_expr(&mut self, expr_i: ExpressionI, spans: Vec<(usize, usize)>) {
+        if self.exprs.len() <= expr_i.0 {
+            self.exprs.resize(expr_i.0 + 1, Vec::new());
+        }
+        self.exprs[expr_i.0] = spans;
+    }
+    pub(crate) fn set_val(&mut self, val_i: ValueI, span: (usize, usize)) {
+        if self.vals.len() <= val_i.0 {
+            self.vals.resize(val_i.0 + 1, None);
+        }
+        self.vals[val_i.0] = Some(span);
+    }
+    fn clear(&mut self) {
+        self.exprs.clear();
+        self.vals.clear();
+    }
+}
+
 /// `CompileSlab` is where `compile()` results are stored, located at `Slab.cs`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompileSlab {
+    // Changed through `instrs_mut()`, so that `depth` is measured again:
     pub(crate) instrs: Vec<Instruction>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) def_instr: Instruction,
+    // 0 until `depth()` measures it, then 1 + the depth:
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) depth: AtomicUsize,
 }
 
 impl ParseSlab {
@@ -266,7 +392,7 @@ impl ParseSlab {
     #[inline]
     pub(crate) fn push_expr(&mut self, expr: Expression) -> Result<ExpressionI, Error> {
         let i = self.exprs.len();
-        if i >= self.exprs.capacity() {
+        if i >= self.limit {
             return Err(Error::SlabOverflow);
         }
         self.exprs.push(expr);
@@ -282,7 +408,7 @@ impl ParseSlab {
     #[inline]
     pub(crate) fn push_val(&mut self, val: Value) -> Result<ValueI, Error> {
         let i = self.vals.len();
-        if i >= self.vals.capacity() {
+        if i >= self.limit {
             return Err(Error::SlabOverflow);
         }
         self.vals.push(val);
@@ -290,17 +416,50 @@ impl ParseSlab {
     }
 
     /// Clears all data from `ParseSlab.exprs` and `ParseSlab.vals`.
+    ///
+    /// The allocated memory is retained, so the `ParseSlab` can be re-used
+    /// without allocating again.
     #[inline]
     pub fn clear(&mut self) {
         self.exprs.clear();
         self.vals.clear();
+        self.spans.clear();
     }
 
     /// [See the `add_unsafe_var()` documentation above.](#unsafe-variable-registration-with-add_unsafe_var)
+    ///
+    /// # Safety
+    ///
+    /// The same as [`add_unsafe_var_ptr()`](#method.add_unsafe_var_ptr).
     #[cfg(feature = "unsafe-vars")]
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub unsafe fn add_unsafe_var(&mut self, name: String, ptr: &f64) {
-        self.unsafe_vars.insert(name, ptr as *const f64);
+        self.add_unsafe_var_ptr(name, ptr);
+    }
+
+    /// Like [`add_unsafe_var()`](#method.add_unsafe_var), but takes a raw
+    /// pointer.
+    ///
+    /// This is useful when the value is written through another pointer while
+    /// the expression is in use, for example with `Cell::as_ptr()` or a slot
+    /// of a `Vec` that is refilled for every sample.  Expressions can mix
+    /// Unsafe Variables with Normal Variables and custom functions from the
+    /// `EvalNamespace`; Unsafe Variables are read directly, without consulting
+    /// the namespace.
+    ///
+    /// # Safety
+    ///
+    /// For as long as an `Expression` or `Instruction` that was parsed after
+    /// this call is evaluated, `ptr` must:
+    ///
+    /// * be non-null, aligned, and point to an initialized `f64`;
+    /// * stay valid -- the pointee must not be moved or dropped;
+    /// * not be written to *during* an `eval()` call.  Writes between calls
+    ///   are fine, as long as they don't invalidate `ptr` (so don't write
+    ///   through a `&mut` that was created after `ptr`).
+    #[cfg(feature = "unsafe-vars")]
+    pub unsafe fn add_unsafe_var_ptr(&mut self, name: String, ptr: *const f64) {
+        self.unsafe_vars.insert(name, ptr);
     }
 }
 
@@ -318,27 +477,31 @@ impl CompileSlab {
         }
     }
 
-    /// Appends an `Instruction` to `CompileSlab.instrs`.
-    pub(crate) fn push_instr(&mut self, instr: Instruction) -> InstructionI {
+    /// Appends an `Instruction` to `CompileSlab.instrs`, and returns its index.
+    ///
+    /// This is for building compiled expressions directly, without the parser
+    /// and the compiler.  An `Instruction` must only refer to Instructions
+    /// that were pushed before it.
+    pub fn push_instr(&mut self, instr: Instruction) -> InstructionI {
         if self.instrs.capacity() == 0 {
             self.instrs.reserve(32);
         }
         let i = self.instrs.len();
-        self.instrs.push(instr);
+        self.instrs_mut().push(instr);
         InstructionI(i)
     }
 
     /// Removes an `Instruction` from `CompileSlab.instrs` as efficiently as possible.
     pub(crate) fn take_instr(&mut self, i: InstructionI) -> Instruction {
         if i.0 == self.instrs.len() - 1 {
-            match self.instrs.pop() {
+            match self.instrs_mut().pop() {
                 Some(instr) => instr,
-                None => IConst(std::f64::NAN),
+                None => IConst(f64::NAN),
             }
         } else {
-            match self.instrs.get_mut(i.0) {
-                Some(instr_ref) => mem::replace(instr_ref, IConst(std::f64::NAN)), // Replace with a conspicuous value in case we use it by accident.
-                None => IConst(std::f64::NAN),
+            match self.instrs_mut().get_mut(i.0) {
+                Some(instr_ref) => mem::replace(instr_ref, IConst(f64::NAN)), // Replace with a conspicuous value in case we use it by accident.
+                None => IConst(f64::NAN),
             }
         }
     }
@@ -346,7 +509,50 @@ impl CompileSlab {
     /// Clears all data from `CompileSlab.instrs`.
     #[inline]
     pub fn clear(&mut self) {
-        self.instrs.clear();
+        self.instrs_mut().clear();
+    }
+
+    /// `CompileSlab.instrs`, for changing it.  The depth is measured again
+    /// the next time that it's needed.
+    #[inline]
+    pub(crate) fn instrs_mut(&mut self) -> &mut Vec<Instruction> {
+        *self.depth.get_mut() = 0;
+        &mut self.instrs
+    }
+
+    /// The most levels of Instructions that are nested in
+    /// `CompileSlab.instrs`.  An Instruction without sub-instructions is 1
+    /// level deep.
+    ///
+    /// It is measured once, after the slab has changed, and without recursion,
+    /// because the slab may be nested too deeply for that.
+    pub(crate) fn depth(&self) -> usize {
+        let known = self.depth.load(Ordering::Relaxed);
+        if known > 0 {
+            return known - 1;
+        }
+        let n = self.instrs.len();
+        // 0 until measured:
+        let mut depths = vec![0; n];
+        let mut work = Vec::new();
+        for root in 0..n {
+            work.push((root, false));
+            while let Some((i, measure)) = work.pop() {
+                if depths[i] > 0 {
+                    continue;
+                }
+                let children = self.instrs[i].children().into_iter().filter(|c| c.0 < n);
+                if measure {
+                    depths[i] = 1 + children.map(|c| depths[c.0]).max().unwrap_or(0);
+                } else {
+                    work.push((i, true));
+                    work.extend(children.map(|c| (c.0, false)));
+                }
+            }
+        }
+        let depth = depths.into_iter().max().unwrap_or(0);
+        self.depth.store(depth + 1, Ordering::Relaxed);
+        depth
     }
 }
 
@@ -358,8 +564,35 @@ impl Slab {
     }
 
     /// Creates a new `Slab` with the given capacity.
+    ///
+    /// The capacity is pre-allocated, and it is also the limit: parsing an
+    /// expression that needs more room produces a `SlabOverflow` error.
     #[inline]
     pub fn with_capacity(cap: usize) -> Self {
+        Self::with_capacity_and_limit(cap, cap)
+    }
+
+    /// Creates a new `Slab` that pre-allocates `cap` items, but is allowed to
+    /// grow up to `limit` items.
+    ///
+    /// This is useful when most expressions are small, but you still want to
+    /// accept some large ones without pre-allocating for the worst case.
+    /// The `limit` protects against malicious inputs that would consume a lot
+    /// of memory; exceeding it produces a `SlabOverflow` error.
+    /// (If `limit` is smaller than `cap`, `cap` is used as the limit.)
+    ///
+    /// # Examples
+    /// ```
+    /// let mut slab = fasteval2::Slab::with_capacity_and_limit(2, 1000);
+    /// let expr_str = "(((((1)))))";
+    /// assert!(fasteval2::Parser::new().parse(expr_str, &mut slab.ps).is_ok());
+    ///
+    /// let mut slab = fasteval2::Slab::with_capacity(2);
+    /// assert_eq!(fasteval2::Parser::new().parse(expr_str, &mut slab.ps),
+    ///            Err(fasteval2::Error::SlabOverflow));
+    /// ```
+    #[inline]
+    pub fn with_capacity_and_limit(cap: usize, limit: usize) -> Self {
         Self {
             ps: ParseSlab {
                 exprs: Vec::with_capacity(cap),
@@ -367,12 +600,17 @@ impl Slab {
                 def_expr: Default::default(),
                 def_val: Default::default(),
                 char_buf: String::with_capacity(64),
+                recovered_errs: None,
+                err_span: None,
+                spans: SourceSpans::default(),
+                limit: limit.max(cap),
                 #[cfg(feature = "unsafe-vars")]
                 unsafe_vars: BTreeMap::new(),
             },
             cs: CompileSlab {
                 instrs: Vec::new(), // Don't pre-allocate for compilation.
                 def_instr: Default::default(),
+                depth: AtomicUsize::new(0),
             },
         }
     }
@@ -380,9 +618,8 @@ impl Slab {
     /// Clears all data from [`Slab.ps`](struct.ParseSlab.html) and [`Slab.cs`](struct.CompileSlab.html).
     #[inline]
     pub fn clear(&mut self) {
-        self.ps.exprs.clear();
-        self.ps.vals.clear();
-        self.cs.instrs.clear();
+        self.ps.clear();
+        self.cs.clear();
     }
 }
 
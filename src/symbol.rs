@@ -0,0 +1,217 @@
+//! Variables resolved to numbered `Symbol`s, for evaluating without name lookups.
+//!
+//! Evaluating an `Instruction` asks the Namespace for every variable by name,
+//! so a Namespace like a `BTreeMap<String, f64>` compares strings on every
+//! evaluation.  [`CompiledExpression::bind()`](../bytecode/struct.CompiledExpression.html#method.bind)
+//! does that work once: it gives each distinct variable name a [`Symbol`](struct.Symbol.html),
+//! and returns a [`BoundExpression`](struct.BoundExpression.html) that takes the
+//! values of the variables as a slice indexed by `Symbol`.  After that, a
+//! variable lookup is an array index.
+//!
+//! The names stay available through the [`Interner`](struct.Interner.html),
+//! and errors still report them, like `UndefinedVariable { name: "x" }`.
+//!
+//! The loop variable of `sum()`, the names bound by `let` and custom functions
+//! are still looked up by name, and so are the variables inside the body of a
+//! `sum()` or `let` (through the same slice).  [`Expression::to_fn()`](../parser/struct.Expression.html#method.to_fn)
+//! also resolves names to positions ahead of time, but it has no Namespace
+//! for custom functions, and it returns NaN instead of errors.
+//!
+//! # Examples
+//!
+//! ```
+//! use fasteval2::{CompiledExpression, EmptyNamespace};
+//!
+//! fn main() -> Result<(), fasteval2::Error> {
+//!     let compiled = CompiledExpression::compile("a*x^2 + b*x + c", &mut EmptyNamespace)?;
+//!     let bound = compiled.bind()?;
+//!
+//!     let syms = bound.symbols();
+//!     let mut vals = vec![0.0; syms.len()];
+//!     for (name, val) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+//!         vals[syms.get(name).unwrap().index()] = val;
+//!     }
+//!
+//!     let x = syms.get("x").unwrap();
+//!     vals[x.index()] = 2.0;
+//!     assert_eq!(bound.eval(&vals, &mut EmptyNamespace)?, 11.0);
+//!     vals[x.index()] = 3.0;
+//!     assert_eq!(bound.eval(&vals, &mut EmptyNamespace)?, 18.0);
+//!     Ok(())
+//! }
+//! ```
+
+use crate::bytecode::CompiledExpression;
+use crate::compiler::{
+    Instruction::{ITemp, IVar},
+    InstructionI,
+};
+use crate::error::Error;
+use crate::evaler::Evaler;
+use crate::evalns::{EvalNamespace, SymbolNamespace};
+use crate::slab::Slab;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{string::String, vec::Vec};
+use core::mem;
+
+/// A variable name, interned by an [`Interner`](struct.Interner.html).
+///
+/// Symbols are numbered from zero in the order in which their names were
+/// first interned, so [`index()`](#method.index) can be used as an index
+/// into a slice of values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The position of this symbol, starting at zero.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Maps names to [`Symbol`](struct.Symbol.html)s and back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interner {
+    names: Vec<String>,
+    ids: BTreeMap<String, Symbol>,
+}
+
+impl Interner {
+    /// Creates an empty `Interner`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the symbol of `name`, adding it if it is new.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.get(name) {
+            return sym;
+        }
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name.into());
+        self.ids.insert(name.into(), sym);
+        sym
+    }
+
+    /// Returns the symbol of `name`, if it has one.
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the name of `sym`, or `None` if it belongs to another `Interner`.
+    #[inline]
+    pub fn name(&self, sym: Symbol) -> Option<&str> {
+        self.names.get(sym.index()).map(String::as_str)
+    }
+
+    /// Returns the number of symbols.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns `true` if there are no symbols.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Iterates over the names, in the order of their symbols.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+}
+
+/// A compiled expression whose variables take their values from a slice,
+/// created by [`CompiledExpression::bind()`](../bytecode/struct.CompiledExpression.html#method.bind).
+///
+/// [See the `symbol module` documentation.](index.html)
+#[derive(Debug)]
+pub struct BoundExpression {
+    slab: Slab,
+    root: InstructionI,
+    /// The slot of symbol 0, after the ones that are used by `ITemps`.
+    base: usize,
+    symbols: Interner,
+}
+
+impl CompiledExpression {
+    /// Gives every variable of the expression a [`Symbol`](../symbol/struct.Symbol.html),
+    /// and returns a copy that reads the variables from a slice.
+    ///
+    /// The symbols are numbered in the alphabetical order of the names.  The
+    /// loop variables of `sum()` and the names bound by `let` get symbols too,
+    /// but their values are ignored.
+    ///
+    /// [See the `symbol module` documentation.](../symbol/index.html)
+    pub fn bind(&self) -> Result<BoundExpression, Error> {
+        let (mut slab, root, base) = self.copy_into_slab()?;
+
+        // Move every variable to the end of the slab and leave an ITemp in its
+        // place, whose slot is the symbol's:
+        let mut symbols = Interner::new();
+        let names: BTreeSet<&str> = (slab.cs.instrs.iter())
+            .filter_map(|instr| match instr {
+                IVar(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        for name in names {
+            symbols.intern(name);
+        }
+        for i in 0..slab.cs.instrs.len() {
+            let sym = match slab.cs.get_instr(InstructionI(i)) {
+                IVar(name) => symbols.intern(name),
+                _ => continue,
+            };
+            let moved_i = InstructionI(slab.cs.instrs.len());
+            if let Some(instr) = slab.cs.instrs_mut().get_mut(i) {
+                let moved = mem::replace(
+                    instr,
+                    ITemp {
+                        slot: base + sym.index(),
+                        instr: moved_i,
+                    },
+                );
+                slab.cs.push_instr(moved);
+            }
+        }
+
+        Ok(BoundExpression {
+            slab,
+            root,
+            base,
+            symbols,
+        })
+    }
+}
+
+impl BoundExpression {
+    /// Returns the symbols of the variables.
+    #[inline]
+    pub fn symbols(&self) -> &Interner {
+        &self.symbols
+    }
+
+    /// Evaluates the expression, with `vals[sym.index()]` as the value of
+    /// each variable.
+    ///
+    /// Variables whose symbol is past the end of `vals`, and custom
+    /// functions, are looked up in `ns` by name.
+    pub fn eval(&self, vals: &[f64], ns: &mut impl EvalNamespace) -> Result<f64, Error> {
+        let mut sym_ns = SymbolNamespace {
+            vals,
+            symbols: &self.symbols,
+            base: self.base,
+            inner: ns,
+        };
+        self.slab
+            .cs
+            .get_instr(self.root)
+            .eval(&self.slab, &mut sym_ns)
+    }
+}
@@ -0,0 +1,196 @@
+//! Substitutes the values of expressions into text.
+//!
+//! A template is text with embedded `${...}` expressions, like
+//! `"Total: ${subtotal * (1 + tax)} (${items} items)"`.
+//! [`interpolate()`](fn.interpolate.html) renders a template once, and a
+//! [`Template`](struct.Template.html) parses and compiles the expressions once,
+//! so it can be rendered many times, with different Namespaces:
+//!
+//! ```
+//! use fasteval2::{Parser, Template};
+//! use std::collections::BTreeMap;
+//!
+//! fn main() -> Result<(), fasteval2::SpannedError> {
+//!     let template = Template::new("Total: ${subtotal * (1 + tax)} (${items} items)", &Parser::new())?
+//!         .precision(2);
+//!
+//!     let mut ns = BTreeMap::new();
+//!     ns.insert("subtotal".to_string(), 40.0);
+//!     ns.insert("tax".to_string(), 0.25);
+//!     ns.insert("items".to_string(), 3.0);
+//!     assert_eq!(template.render(&mut ns)?, "Total: 50.00 (3.00 items)");
+//!
+//!     ns.insert("items".to_string(), 4.0);
+//!     assert_eq!(template.render(&mut ns)?, "Total: 50.00 (4.00 items)");
+//!
+//!     // Without a precision, values are formatted like f64's Display:
+//!     assert_eq!(fasteval2::interpolate("${items / 8} of $${x}", &mut ns)?, "0.5 of ${x}");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! `$${` is a literal `${`.  An expression ends at the first `}` that isn't
+//! inside of a string.  Errors are [`SpannedError`](../error/struct.SpannedError.html)s
+//! with spans in the template: parse errors point into the expression, and an
+//! unterminated `${` or a failed evaluation spans the whole placeholder.
+//!
+//! Like the [`ExpressionCache`](../cache/struct.ExpressionCache.html), a
+//! `Template` compiles with an
+//! [`EmptyNamespace`](../evalns/struct.EmptyNamespace.html), and it can't hold
+//! `print()` calls.
+
+use crate::bytecode::CompiledExpression;
+use crate::compiler::Compiler;
+use crate::error::{Error, SpannedError};
+use crate::evalns::{EmptyNamespace, EvalNamespace};
+use crate::parser::Parser;
+use crate::slab::Slab;
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
+use core::ops::Range;
+
+/// A template whose `${...}` expressions are parsed and compiled once.
+///
+/// [See the `template module` documentation.](index.html)
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+    precision: Option<usize>,
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Expr {
+        expr: Box<CompiledExpression>,
+        span: Range<usize>, // The whole placeholder, including `${` and `}`.
+    },
+}
+
+impl Template {
+    /// Parses `template`, with `parser` for the embedded expressions.
+    pub fn new(template: &str, parser: &Parser) -> Result<Self, SpannedError> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let (mut i, mut last) = (0, 0);
+        while let Some(off) = template[i..].find("${") {
+            let start = i + off;
+            i = start + 2;
+            // `$${` is an escaped `${`:
+            if template[last..start].ends_with('$') {
+                text.push_str(&template[last..start - 1]);
+                text.push_str("${");
+                last = i;
+                continue;
+            }
+            text.push_str(&template[last..start]);
+
+            let end = match closing_brace(&template[i..]) {
+                Some(len) => i + len,
+                None => {
+                    return Err(SpannedError {
+                        error: Error::Expected("}".to_string()),
+                        span: start..template.len(),
+                    })
+                }
+            };
+            let span = start..end + 1;
+            let expr = compile(&template[i..end], parser).map_err(|err| match err {
+                Compiled::Parse(err) => SpannedError {
+                    error: err.error,
+                    span: i + err.span.start..i + err.span.end,
+                },
+                Compiled::Other(error) => SpannedError {
+                    error,
+                    span: span.clone(),
+                },
+            })?;
+            if !text.is_empty() {
+                parts.push(Part::Text(core::mem::take(&mut text)));
+            }
+            parts.push(Part::Expr { expr, span });
+            i = end + 1;
+            last = i;
+        }
+        text.push_str(&template[last..]);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self {
+            parts,
+            precision: None,
+        })
+    }
+
+    /// Formats every value with `digits` decimal places.  By default, values
+    /// are formatted like `f64`'s `Display`, with as many digits as needed.
+    pub fn precision(mut self, digits: usize) -> Self {
+        self.precision = Some(digits);
+        self
+    }
+
+    /// Evaluates the expressions with `ns` and returns the rendered text.
+    ///
+    /// An evaluation error is returned with the span of its placeholder.
+    pub fn render(&self, ns: &mut impl EvalNamespace) -> Result<String, SpannedError> {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Expr { expr, span } => {
+                    let val = expr.eval(ns).map_err(|error| SpannedError {
+                        error,
+                        span: span.clone(),
+                    })?;
+                    // Writing to a String can't fail:
+                    let _ = match self.precision {
+                        Some(digits) => write!(out, "{:.*}", digits, val),
+                        None => write!(out, "{}", val),
+                    };
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Renders `template` once.  See [`Template`](struct.Template.html) for
+/// templates that are rendered many times, or that need a precision.
+pub fn interpolate(template: &str, ns: &mut impl EvalNamespace) -> Result<String, SpannedError> {
+    Template::new(template, &Parser::new())?.render(ns)
+}
+
+enum Compiled {
+    Parse(SpannedError),
+    Other(Error),
+}
+
+fn compile(expr_str: &str, parser: &Parser) -> Result<Box<CompiledExpression>, Compiled> {
+    let mut slab = Slab::new();
+    let instr = parser
+        .parse_spanned(expr_str, &mut slab.ps)
+        .map_err(Compiled::Parse)?
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    CompiledExpression::new(instr, slab)
+        .map(Box::new)
+        .map_err(Compiled::Other)
+}
+
+// Returns the position of the `}` that ends an expression, skipping strings:
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut quoted = false;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            b'"' => quoted = !quoted,
+            b'}' if !quoted => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,618 @@
+//! Pre-flight validation of parsed expressions.
+//!
+//! [`Expression::validate()`](../parser/struct.Expression.html#method.validate)
+//! checks an expression against a [`ValidationSchema`](struct.ValidationSchema.html)
+//! of known variables and custom functions, without evaluating anything.  Use
+//! it to reject bad formulas when they are saved, instead of when they are
+//! evaluated.  Every problem is reported, not just the first:
+//!
+//! ```
+//! use fasteval2::{Arity, Error, Parser, Slab, ValidationSchema};
+//!
+//! let schema = ValidationSchema::new()
+//!     .with_var("x")
+//!     .with_func("clip", 3);
+//!
+//! let mut slab = Slab::new();
+//! let expr_ref = Parser::new()
+//!     .parse("clip(x, 0) + y", &mut slab.ps)
+//!     .unwrap()
+//!     .from(&slab.ps);
+//! let errors = expr_ref.validate(&slab, &schema).unwrap_err();
+//! assert_eq!(
+//!     errors.into_iter().map(|err| err.error).collect::<Vec<_>>(),
+//!     vec![
+//!         Error::WrongArgCount {
+//!             name: "clip".to_string(),
+//!             expected: Arity::Exact(3),
+//!             got: 2,
+//!         },
+//!         Error::UndefinedVariable { name: "y".to_string() },
+//!     ]
+//! );
+//! ```
+//!
+//! Built-in functions like `sin()`, named constants like `phi` and `sum()`
+//! loop variables are always known.  `rand()` and `randint()` are custom
+//! functions (see [`EvalNS::with_rng()`](../evalns/struct.EvalNS.html#method.with_rng)),
+//! and so are `now()` and the other time functions of
+//! [`EvalNS::with_clock()`](../evalns/struct.EvalNS.html#method.with_clock),
+//! so add them to the schema if you use them.
+//!
+//! The errors name the offending variable, function or operator, and carry the
+//! span of the variable or the whole function call if the expression was
+//! parsed [`with_spans()`](../parser/struct.Parser.html#method.with_spans).
+//!
+//! # Allow-lists
+//!
+//! [`Expression::check_allowed()`](../parser/struct.Expression.html#method.check_allowed)
+//! is stricter, for formulas from untrusted sources: nothing is allowed
+//! unless it is listed, including the built-in functions and named constants.
+//! Every violation is reported with its span, so parse with
+//! [`with_spans()`](../parser/struct.Parser.html#method.with_spans):
+//!
+//! ```
+//! use fasteval2::{Arity, Error, Parser, Slab};
+//! use std::collections::{HashMap, HashSet};
+//!
+//! let allowed_vars = HashSet::from(["x"]);
+//! let allowed_funcs = HashMap::from([("max", Arity::AtLeast(1))]);
+//!
+//! let src = "max(x, 1) + sin(x)";
+//! let mut slab = Slab::new();
+//! let expr_ref = Parser::new()
+//!     .with_spans()
+//!     .parse(src, &mut slab.ps)
+//!     .unwrap()
+//!     .from(&slab.ps);
+//! let errors = expr_ref.check_allowed(&slab, &allowed_vars, &allowed_funcs).unwrap_err();
+//! assert_eq!(
+//!     errors[0].error,
+//!     Error::UndefinedFunction { name: "sin".to_string(), arg_count: 1 }
+//! );
+//! assert_eq!(&src[errors[0].span.clone()], "sin(x)");
+//! ```
+
+use crate::error::{Arity, Error, SpannedError};
+use crate::evaler::named_constant;
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    BinaryOp::{EEQ, EGT, EGTE, ELT, ELTE, ENE},
+    Expression, ExpressionI,
+    ExpressionOrString::{EExpr, EStr},
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::ops::Range;
+use core::ptr;
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+
+/// The variables and custom functions that an expression may use.
+///
+/// [See the `validate module` documentation.](index.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationSchema {
+    /// The known variables.
+    pub vars: BTreeSet<String>,
+    /// The known custom functions, with their number of arguments.  `None`
+    /// accepts any number of arguments.
+    pub funcs: BTreeMap<String, Option<usize>>,
+    /// Allow `<`, `<=`, `==`, `!=`, `>=` and `>`.  Defaults to `true`.
+    pub comparisons: bool,
+    /// Allow `print()`.  Defaults to `true`.
+    pub print: bool,
+}
+
+impl Default for ValidationSchema {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValidationSchema {
+    /// Creates a schema without any variables or custom functions.
+    #[inline]
+    pub fn new() -> Self {
+        ValidationSchema {
+            vars: BTreeSet::new(),
+            funcs: BTreeMap::new(),
+            comparisons: true,
+            print: true,
+        }
+    }
+
+    /// Creates a schema from the keys of a Namespace.
+    ///
+    /// Namespaces look up variables and custom functions by name alone, so
+    /// each name is accepted as a variable, and as a function with any number
+    /// of arguments:
+    ///
+    /// ```
+    /// use fasteval2::{StringToF64Namespace, ValidationSchema};
+    ///
+    /// let mut ns = StringToF64Namespace::new();
+    /// ns.insert("x".to_string(), 1.0);
+    /// let schema = ValidationSchema::from_names(ns.keys());
+    /// assert!(schema.vars.contains("x"));
+    /// ```
+    pub fn from_names<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> Self {
+        let mut schema = Self::new();
+        for name in names {
+            let name = name.as_ref();
+            schema.vars.insert(name.to_string());
+            schema.funcs.insert(name.to_string(), None);
+        }
+        schema
+    }
+
+    /// Adds a known variable.
+    #[inline]
+    pub fn with_var(mut self, name: &str) -> Self {
+        self.vars.insert(name.to_string());
+        self
+    }
+
+    /// Adds a known custom function that takes exactly `arity` arguments.
+    #[inline]
+    pub fn with_func(mut self, name: &str, arity: usize) -> Self {
+        self.funcs.insert(name.to_string(), Some(arity));
+        self
+    }
+}
+
+impl Expression {
+    /// Checks this `Expression` against a `ValidationSchema`, without
+    /// evaluating anything.
+    ///
+    /// Returns every problem that was found, in order, with its span.  A
+    /// problem that occurs several times is only reported once, with the span
+    /// of its first occurrence.  The spans are empty (`0..0`) for disallowed
+    /// comparisons, and if the expression was parsed without
+    /// [`with_spans()`](struct.Parser.html#method.with_spans):
+    ///
+    /// * An unknown variable is an `UndefinedVariable` error, and an unknown
+    ///   custom function is an `UndefinedFunction` error.
+    /// * A custom function with the wrong number of arguments is a `WrongArgCount` error.
+    /// * A comparison or `print()` that the schema doesn't allow is a `NotAllowed` error.
+    /// * Error placeholders from
+    ///   [`parse_with_recovery()`](struct.Parser.html#method.parse_with_recovery)
+    ///   are reported as-is.
+    ///
+    /// [See the `validate module` documentation.](../validate/index.html)
+    pub fn validate(
+        &self,
+        slab: &Slab,
+        schema: &ValidationSchema,
+    ) -> Result<(), Vec<SpannedError>> {
+        let mut errors: Vec<SpannedError> = Vec::new();
+        for err in Validator::run(self, slab, Policy::Schema(schema)) {
+            if !errors.iter().any(|seen| seen.error == err.error) {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks that this `Expression` only uses the variables in
+    /// `allowed_vars`, and the functions in `allowed_funcs` with the numbers
+    /// of arguments that they allow, without evaluating anything.
+    ///
+    /// Unlike [`validate()`](#method.validate), nothing is allowed by default:
+    /// built-in functions like `sin()`, `sum()` and `print()` must be in
+    /// `allowed_funcs` too, and named constants like `phi` and `true` must be
+    /// in `allowed_vars`.  Only the loop variables of `sum()` and the names
+    /// bound by `let` are always allowed, inside their bodies.  Every branch
+    /// of `piecewise()`, `coalesce()`, `&&` and `||`, and every item of an `in`
+    /// list, is checked, whether or not it would be evaluated.
+    ///
+    /// Returns every violation, in order, with the span of the variable or the
+    /// whole function call.  The spans are empty (`0..0`) if the expression
+    /// was parsed without [`record_spans`](struct.ParserBuilder.html#method.record_spans):
+    ///
+    /// * A variable that isn't allowed is an `UndefinedVariable` error.
+    /// * A function that isn't allowed is an `UndefinedFunction` error.
+    /// * A function with a number of arguments that its `Arity` doesn't
+    ///   accept is a `WrongArgCount` error.
+    /// * Error placeholders from
+    ///   [`parse_with_recovery()`](struct.Parser.html#method.parse_with_recovery)
+    ///   are reported as-is.
+    ///
+    /// Only available with the `std` feature.
+    /// [See the `validate module` documentation.](../validate/index.html#allow-lists)
+    #[cfg(feature = "std")]
+    pub fn check_allowed(
+        &self,
+        slab: &Slab,
+        allowed_vars: &HashSet<&str>,
+        allowed_funcs: &HashMap<&str, Arity>,
+    ) -> Result<(), Vec<SpannedError>> {
+        let policy = Policy::AllowList {
+            vars: &|name| allowed_vars.contains(name),
+            funcs: &|name| allowed_funcs.get(name).copied(),
+        };
+        let errors = Validator::run(self, slab, policy);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Policy<'a> {
+    // Only checks the custom functions and variables:
+    Schema(&'a ValidationSchema),
+    // Checks everything, and allows nothing by default:
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    AllowList {
+        vars: &'a dyn Fn(&str) -> bool,
+        funcs: &'a dyn Fn(&str) -> Option<Arity>,
+    },
+    // Only finds the variables and function calls that an evaluation error names:
+    Failing(&'a Error),
+}
+
+// Returns the span of the first variable or function call of `expr` that `err`
+// names, for eval_spanned().  Other errors span the whole expression:
+pub(crate) fn failing_span(expr: &Expression, slab: &Slab, err: &Error) -> Range<usize> {
+    if let Some(found) = Validator::run(expr, slab, Policy::Failing(err))
+        .into_iter()
+        .next()
+    {
+        return found.span;
+    }
+    slab.ps
+        .exprs
+        .iter()
+        .position(|x| ptr::eq(x, expr))
+        .and_then(|i| ExpressionI(i).span(&slab.ps))
+        .unwrap_or(0..0)
+}
+
+// Returns whether `err` is about the variable `name` (if `arg_count` is `None`),
+// or about a call to the function `name` with `arg_count` arguments:
+fn names(err: &Error, name: &str, arg_count: Option<usize>) -> bool {
+    match err {
+        Error::UndefinedVariable { name: n } => arg_count.is_none() && n == name,
+        Error::UndefinedFunction {
+            name: n,
+            arg_count: c,
+        } => arg_count == Some(*c) && n == name,
+        Error::WrongArgCount { name: n, got, .. } => arg_count == Some(*got) && n == name,
+        Error::NotFinite(n) => n == name,
+        _ => false,
+    }
+}
+
+type Span = Option<(usize, usize)>;
+
+struct Validator<'a> {
+    slab: &'a Slab,
+    policy: Policy<'a>,
+    bound: Vec<&'a str>, // The variables of the enclosing sum() and let.
+    errors: Vec<SpannedError>,
+}
+
+impl<'a> Validator<'a> {
+    fn run(expr: &'a Expression, slab: &'a Slab, policy: Policy<'a>) -> Vec<SpannedError> {
+        let mut v = Validator {
+            slab,
+            policy,
+            bound: Vec::new(),
+            errors: Vec::new(),
+        };
+        // The spans are kept by ExpressionI, so find the root in the slab:
+        let root_i = slab.ps.exprs.iter().position(|x| ptr::eq(x, expr));
+        v.expr(expr, root_i.and_then(|i| slab.ps.spans.exprs.get(i)));
+        v.errors
+    }
+
+    fn report(&mut self, error: Error, span: Span) {
+        // Error placeholders can be the evaluation error too:
+        if let Policy::Failing(target) = self.policy {
+            if error != *target {
+                return;
+            }
+        }
+        let err = SpannedError {
+            error,
+            span: span.map_or(0..0, |(start, end)| start..end),
+        };
+        if !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+    }
+
+    fn var(&mut self, name: &str, span: Span) {
+        if let Policy::Failing(err) = self.policy {
+            if !self.bound.contains(&name) && names(err, name, None) {
+                self.report(err.clone(), span);
+            }
+            return;
+        }
+        let known = self.bound.contains(&name)
+            || match self.policy {
+                Policy::Schema(schema) => {
+                    schema.vars.contains(name) || named_constant(name).is_some()
+                }
+                Policy::AllowList { vars, .. } => vars(name),
+                Policy::Failing(_) => true,
+            };
+        if !known {
+            self.report(
+                Error::UndefinedVariable {
+                    name: name.to_string(),
+                },
+                span,
+            );
+        }
+    }
+
+    fn func(&mut self, name: &str, arg_count: usize, span: Span) {
+        let arity = match self.policy {
+            Policy::Schema(schema) => schema.funcs.get(name).map(|arity| arity.map(Arity::Exact)),
+            Policy::AllowList { funcs, .. } => funcs(name).map(Some),
+            Policy::Failing(err) => {
+                if names(err, name, Some(arg_count)) {
+                    self.report(err.clone(), span);
+                }
+                return;
+            }
+        };
+        match arity {
+            None => self.report(
+                Error::UndefinedFunction {
+                    name: name.to_string(),
+                    arg_count,
+                },
+                span,
+            ),
+            Some(Some(arity)) if !arity.accepts(arg_count) => self.report(
+                Error::WrongArgCount {
+                    name: name.to_string(),
+                    expected: arity,
+                    got: arg_count,
+                },
+                span,
+            ),
+            Some(_) => (),
+        }
+    }
+
+    fn expr_i(&mut self, xi: &ExpressionI) {
+        self.expr(
+            get_expr!(self.slab.ps, xi),
+            self.slab.ps.spans.exprs.get(xi.0),
+        );
+    }
+
+    fn expr(&mut self, expr: &'a Expression, spans: Option<&Vec<(usize, usize)>>) {
+        let span = |k: usize| spans.and_then(|spans| spans.get(k)).copied();
+        self.value(&expr.first, span(0));
+        for (k, pair) in expr.pairs.iter().enumerate() {
+            if let Policy::Schema(schema) = self.policy {
+                if !schema.comparisons {
+                    let op = match pair.0 {
+                        ELT => Some("<"),
+                        ELTE => Some("<="),
+                        EEQ => Some("=="),
+                        ENE => Some("!="),
+                        EGTE => Some(">="),
+                        EGT => Some(">"),
+                        _ => None,
+                    };
+                    if let Some(op) = op {
+                        self.report(Error::NotAllowed(op.to_string()), None);
+                    }
+                }
+            }
+            self.value(&pair.1, span(k + 1));
+        }
+        for xi in &expr.tuple {
+            self.expr_i(xi);
+        }
+    }
+
+    fn value(&mut self, value: &'a Value, span: Span) {
+        match value {
+            EConstant(_) => (),
+            EError(err) => self.report(err.clone(), span),
+            EUnaryOp(EPos(vi)) | EUnaryOp(ENeg(vi)) | EUnaryOp(ENot(vi)) => self.value(
+                get_val!(self.slab.ps, vi),
+                self.slab.ps.spans.vals.get(vi.0).copied().flatten(),
+            ),
+            EUnaryOp(EParentheses(xi)) => self.expr_i(xi),
+            EPrintFunc(pf) => {
+                match self.policy {
+                    Policy::Schema(schema) => {
+                        if !schema.print {
+                            self.report(Error::NotAllowed("print".to_string()), span);
+                        }
+                    }
+                    Policy::AllowList { .. } => self.func("print", pf.0.len(), span),
+                    Policy::Failing(_) => (),
+                }
+                for x_or_s in &pf.0 {
+                    match x_or_s {
+                        EExpr(xi) => self.expr_i(xi),
+                        EStr(_) => (),
+                    }
+                }
+            }
+            EStdFunc(f) => self.stdfunc(f, span),
+        }
+    }
+
+    fn stdfunc(&mut self, f: &'a StdFunc, span: Span) {
+        // A schema allows every built-in function:
+        if let (Policy::AllowList { .. }, Some((name, arg_count))) = (self.policy, builtin(f)) {
+            self.func(name, arg_count, span);
+        }
+        match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { name, .. } => {
+                // Unsafe Variables were registered, so a schema knows them:
+                if let Policy::AllowList { .. } = self.policy {
+                    self.var(name, span);
+                }
+            }
+            EVar(name) => self.var(name, span),
+            EFunc { name, args } => {
+                self.func(name, args.len(), span);
+                for xi in args {
+                    self.expr_i(xi);
+                }
+            }
+
+            EFuncInt(xi) | EFuncCeil(xi) | EFuncFloor(xi) | EFuncAbs(xi) | EFuncSign(xi)
+            | EFuncSin(xi) | EFuncCos(xi) | EFuncTan(xi) | EFuncASin(xi) | EFuncACos(xi)
+            | EFuncATan(xi) | EFuncSinH(xi) | EFuncCosH(xi) | EFuncTanH(xi) | EFuncASinH(xi)
+            | EFuncACosH(xi) | EFuncATanH(xi) | EFuncSqrt(xi) => self.expr_i(xi),
+            EFuncE | EFuncPi => (),
+            EFuncFma { a, b, c } => {
+                self.expr_i(a);
+                self.expr_i(b);
+                self.expr_i(c);
+            }
+            EFuncCoalesce { value, fallback } => {
+                self.expr_i(value);
+                self.expr_i(fallback);
+            }
+            EFuncQuotient { dividend, divisor } | EFuncRemainder { dividend, divisor } => {
+                self.expr_i(dividend);
+                self.expr_i(divisor);
+            }
+            EFuncNorm { args, .. } => {
+                for xi in args {
+                    self.expr_i(xi);
+                }
+            }
+            EFuncIn { value, list, .. } => {
+                self.expr_i(value);
+                for xi in list {
+                    self.expr_i(xi);
+                }
+            }
+            EFuncLog { base: opt, expr } | EFuncRound { modulus: opt, expr } => {
+                if let Some(xi) = opt {
+                    self.expr_i(xi);
+                }
+                self.expr_i(expr);
+            }
+            EFuncMin { first, rest } | EFuncMax { first, rest } => {
+                self.expr_i(first);
+                for xi in rest {
+                    self.expr_i(xi);
+                }
+            }
+            EFuncPiecewise { branches, default } => {
+                for (cond, val) in branches {
+                    self.expr_i(cond);
+                    self.expr_i(val);
+                }
+                self.expr_i(default);
+            }
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                self.expr_i(from);
+                self.expr_i(to);
+                self.bound.push(var);
+                self.expr_i(expr);
+                self.bound.pop();
+            }
+            EFuncLet { var, value, expr } => {
+                self.expr_i(value);
+                self.bound.push(var);
+                self.expr_i(expr);
+                self.bound.pop();
+            }
+            EFuncInterp { x, points, .. } => {
+                self.expr_i(x);
+                for (px, py) in points {
+                    self.expr_i(px);
+                    self.expr_i(py);
+                }
+            }
+        }
+    }
+}
+
+// Returns the name and the number of arguments of a call to a built-in
+// function, as they were written:
+fn builtin(f: &StdFunc) -> Option<(&'static str, usize)> {
+    let call = match f {
+        #[cfg(feature = "unsafe-vars")]
+        EUnsafeVar { .. } => return None,
+        EVar(_) | EFunc { .. } => return None,
+        // An operator, like `==`:
+        EFuncIn { .. } => return None,
+
+        EFuncE => ("e", 0),
+        EFuncPi => ("pi", 0),
+        EFuncInt(_) => ("int", 1),
+        EFuncCeil(_) => ("ceil", 1),
+        EFuncFloor(_) => ("floor", 1),
+        EFuncAbs(_) => ("abs", 1),
+        EFuncSign(_) => ("sign", 1),
+        EFuncLog { base, .. } => ("log", 1 + usize::from(base.is_some())),
+        EFuncRound { modulus, .. } => ("round", 1 + usize::from(modulus.is_some())),
+        EFuncMin { rest, .. } => ("min", 1 + rest.len()),
+        EFuncMax { rest, .. } => ("max", 1 + rest.len()),
+        EFuncPiecewise { branches, .. } => ("piecewise", 2 * branches.len() + 1),
+        EFuncInterp {
+            extrapolate,
+            points,
+            ..
+        } => (
+            if *extrapolate { "interp_ext" } else { "interp" },
+            1 + 2 * points.len(),
+        ),
+        EFuncFma { .. } => ("fma", 3),
+        EFuncCoalesce { .. } => ("coalesce", 2),
+        EFuncQuotient { .. } => ("quotient", 2),
+        EFuncRemainder { .. } => ("remainder", 2),
+        EFuncNorm { squared, args } => (if *squared { "sumsq" } else { "norm" }, args.len()),
+        EFuncSum { .. } => ("sum", 4),
+        EFuncLet { .. } => ("let", 3),
+
+        EFuncSin(_) => ("sin", 1),
+        EFuncCos(_) => ("cos", 1),
+        EFuncTan(_) => ("tan", 1),
+        EFuncASin(_) => ("asin", 1),
+        EFuncACos(_) => ("acos", 1),
+        EFuncATan(_) => ("atan", 1),
+        EFuncSinH(_) => ("sinh", 1),
+        EFuncCosH(_) => ("cosh", 1),
+        EFuncTanH(_) => ("tanh", 1),
+        EFuncASinH(_) => ("asinh", 1),
+        EFuncACosH(_) => ("acosh", 1),
+        EFuncATanH(_) => ("atanh", 1),
+        EFuncSqrt(_) => ("sqrt", 1),
+    };
+    Some(call)
+}
@@ -0,0 +1,268 @@
+//! Visits the variables of an `Expression` without collecting them.
+//!
+//! See [`Expression::for_each_var()`](../parser/struct.Expression.html#method.for_each_var).
+
+#[cfg(feature = "unsafe-vars")]
+use crate::parser::StdFunc::EUnsafeVar;
+use crate::parser::{
+    Expression, ExpressionI,
+    ExpressionOrString::{EExpr, EStr},
+    PrintFunc,
+    StdFunc::{
+        self, EFunc, EFuncACos, EFuncACosH, EFuncASin, EFuncASinH, EFuncATan, EFuncATanH, EFuncAbs,
+        EFuncCeil, EFuncCoalesce, EFuncCos, EFuncCosH, EFuncE, EFuncFloor, EFuncFma, EFuncIn,
+        EFuncInt, EFuncInterp, EFuncLet, EFuncLog, EFuncMax, EFuncMin, EFuncNorm, EFuncPi,
+        EFuncPiecewise, EFuncQuotient, EFuncRemainder, EFuncRound, EFuncSign, EFuncSin, EFuncSinH,
+        EFuncSqrt, EFuncSum, EFuncTan, EFuncTanH, EVar,
+    },
+    UnaryOp::{self, ENeg, ENot, EParentheses, EPos},
+    Value::{self, EConstant, EError, EPrintFunc, EStdFunc, EUnaryOp},
+};
+use crate::slab::Slab;
+
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::{vec, vec::Vec};
+use core::ops::ControlFlow;
+
+impl Expression {
+    /// Calls `f` with the name of every variable and custom function that
+    /// this `Expression` uses, in source order.
+    ///
+    /// This visits the same names as [`var_names()`](../evaler/trait.Evaler.html#method.var_names),
+    /// but without collecting them: a name is visited every time that it
+    /// appears, and only the walk's own stack is allocated (so that deep
+    /// expressions can't overflow the native stack).  The loop variables of `sum()` and
+    /// the names bound by `let` are skipped inside their bodies.  If `f`
+    /// returns `ControlFlow::Break(())`, the walk stops there, and this returns
+    /// `Break(())` too.
+    ///
+    /// # Examples
+    /// ```
+    /// use fasteval2::{Parser, Slab};
+    /// use std::ops::ControlFlow;
+    ///
+    /// fn main() -> Result<(), fasteval2::Error> {
+    ///     let mut slab = Slab::new();
+    ///     let expr_ref = Parser::new().parse("x * y + sum(i, 1, 3, i * z)", &mut slab.ps)?.from(&slab.ps);
+    ///
+    ///     let allowed = ["x", "y"];
+    ///     let check = expr_ref.for_each_var(&slab, &mut |name| {
+    ///         if allowed.contains(&name) {
+    ///             ControlFlow::Continue(())
+    ///         } else {
+    ///             ControlFlow::Break(())
+    ///         }
+    ///     });
+    ///     assert_eq!(check, ControlFlow::Break(())); // Because of 'z'.
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn for_each_var(
+        &self,
+        slab: &Slab,
+        f: &mut impl FnMut(&str) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        Walk { slab, f }.expr(self)
+    }
+}
+
+// Returns a walk that collects the names into `dst`, for `var_names()`.
+pub(crate) fn collect<'s>(
+    slab: &'s Slab,
+    dst: &'s mut BTreeSet<String>,
+) -> Walk<'s, impl FnMut(&str) -> ControlFlow<()> + 's> {
+    Walk {
+        slab,
+        f: move |name: &str| {
+            if !dst.contains(name) {
+                dst.insert(name.to_string());
+            }
+            ControlFlow::Continue(())
+        },
+    }
+}
+
+pub(crate) struct Walk<'s, F> {
+    slab: &'s Slab,
+    f: F,
+}
+
+// The pending work of a walk.
+enum Step<'x> {
+    Expr(&'x Expression),
+    Value(&'x Value),
+    // The start and the end of the body of a `sum()` or `let`.
+    Bind(&'x str),
+    Unbind,
+}
+
+// Schedules the `Expression`s at `xis`, so that they are walked from left to
+// right.
+fn push_exprs<'x>(
+    work: &mut Vec<Step<'x>>,
+    slab: &'x Slab,
+    xis: impl DoubleEndedIterator<Item = &'x ExpressionI>,
+) {
+    work.extend(xis.rev().map(|xi| Step::Expr(get_expr!(slab.ps, xi))));
+}
+
+impl<'s, F: FnMut(&str) -> ControlFlow<()>> Walk<'s, F> {
+    pub(crate) fn expr(&mut self, expr: &Expression) -> ControlFlow<()> {
+        self.run(vec![Step::Expr(expr)], Vec::new())
+    }
+
+    pub(crate) fn value(&mut self, val: &Value) -> ControlFlow<()> {
+        self.run(vec![Step::Value(val)], Vec::new())
+    }
+
+    pub(crate) fn unary(&mut self, u: &UnaryOp) -> ControlFlow<()> {
+        let mut work = Vec::new();
+        schedule_unary(&mut work, self.slab, u);
+        self.run(work, Vec::new())
+    }
+
+    pub(crate) fn print(&mut self, pf: &PrintFunc) -> ControlFlow<()> {
+        let mut work = Vec::new();
+        schedule_print(&mut work, self.slab, pf);
+        self.run(work, Vec::new())
+    }
+
+    pub(crate) fn stdfunc(&mut self, f: &StdFunc) -> ControlFlow<()> {
+        let (mut work, mut bound) = (Vec::new(), Vec::new());
+        self.stdfunc_step(&mut work, &mut bound, f)?;
+        self.run(work, bound)
+    }
+
+    // Runs the steps with an explicit stack, so that deep trees can't overflow
+    // the native one.  `bound` holds the names of the enclosing `sum()`s and
+    // `let`s.
+    fn run<'x>(&mut self, mut work: Vec<Step<'x>>, mut bound: Vec<&'x str>) -> ControlFlow<()>
+    where
+        's: 'x,
+    {
+        let slab = self.slab;
+        while let Some(step) = work.pop() {
+            match step {
+                Step::Expr(expr) => {
+                    push_exprs(&mut work, slab, expr.tuple.iter());
+                    work.extend(expr.pairs.iter().rev().map(|pair| Step::Value(&pair.1)));
+                    work.push(Step::Value(&expr.first));
+                }
+                Step::Value(val) => match val {
+                    EConstant(_) | EError(_) => (),
+                    EUnaryOp(u) => schedule_unary(&mut work, slab, u),
+                    EStdFunc(f) => self.stdfunc_step(&mut work, &mut bound, f)?,
+                    EPrintFunc(pf) => schedule_print(&mut work, slab, pf),
+                },
+                Step::Bind(name) => bound.push(name),
+                Step::Unbind => {
+                    bound.pop();
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    // Visits the name of `f`, if it has one, and schedules its arguments:
+    fn stdfunc_step<'x>(
+        &mut self,
+        work: &mut Vec<Step<'x>>,
+        bound: &mut Vec<&'x str>,
+        f: &'x StdFunc,
+    ) -> ControlFlow<()>
+    where
+        's: 'x,
+    {
+        let slab = self.slab;
+        match f {
+            #[cfg(feature = "unsafe-vars")]
+            EUnsafeVar { name, .. } => self.name(name, bound)?,
+            EVar(name) => self.name(name, bound)?,
+            EFunc { name, args } => {
+                self.name(name, bound)?;
+                push_exprs(work, slab, args.iter());
+            }
+
+            EFuncInt(xi) | EFuncCeil(xi) | EFuncFloor(xi) | EFuncAbs(xi) | EFuncSign(xi)
+            | EFuncSin(xi) | EFuncCos(xi) | EFuncTan(xi) | EFuncASin(xi) | EFuncACos(xi)
+            | EFuncATan(xi) | EFuncSinH(xi) | EFuncCosH(xi) | EFuncTanH(xi) | EFuncASinH(xi)
+            | EFuncACosH(xi) | EFuncATanH(xi) | EFuncSqrt(xi) => {
+                push_exprs(work, slab, [xi].into_iter())
+            }
+
+            EFuncE | EFuncPi => (),
+            EFuncFma { a, b, c } => push_exprs(work, slab, [a, b, c].into_iter()),
+            EFuncCoalesce { value, fallback } => {
+                push_exprs(work, slab, [value, fallback].into_iter())
+            }
+            EFuncQuotient { dividend, divisor } | EFuncRemainder { dividend, divisor } => {
+                push_exprs(work, slab, [dividend, divisor].into_iter())
+            }
+            EFuncNorm { args, .. } => push_exprs(work, slab, args.iter()),
+            EFuncIn { value, list, .. } => push_exprs(work, slab, [value].into_iter().chain(list)),
+            EFuncLog { base: opt, expr } | EFuncRound { modulus: opt, expr } => {
+                push_exprs(work, slab, opt.iter().chain([expr]))
+            }
+            EFuncMin { first, rest } | EFuncMax { first, rest } => {
+                push_exprs(work, slab, [first].into_iter().chain(rest))
+            }
+            EFuncPiecewise { branches, default } => {
+                let conds_vals = branches.iter().flat_map(|(cond_i, val_i)| [cond_i, val_i]);
+                push_exprs(work, slab, conds_vals.chain([default]))
+            }
+            EFuncSum {
+                var,
+                from,
+                to,
+                expr,
+            } => {
+                schedule_scope(work, slab, var, expr);
+                push_exprs(work, slab, [from, to].into_iter());
+            }
+            EFuncLet { var, value, expr } => {
+                schedule_scope(work, slab, var, expr);
+                push_exprs(work, slab, [value].into_iter());
+            }
+            EFuncInterp { x, points, .. } => {
+                let pxs_pys = points.iter().flat_map(|(px_i, py_i)| [px_i, py_i]);
+                push_exprs(work, slab, [x].into_iter().chain(pxs_pys))
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn name(&mut self, name: &str, bound: &[&str]) -> ControlFlow<()> {
+        if bound.contains(&name) {
+            return ControlFlow::Continue(());
+        }
+        (self.f)(name)
+    }
+}
+
+fn schedule_unary<'x>(work: &mut Vec<Step<'x>>, slab: &'x Slab, u: &'x UnaryOp) {
+    match u {
+        EPos(vi) | ENeg(vi) | ENot(vi) => work.push(Step::Value(get_val!(slab.ps, vi))),
+        EParentheses(xi) => push_exprs(work, slab, [xi].into_iter()),
+    }
+}
+
+fn schedule_print<'x>(work: &mut Vec<Step<'x>>, slab: &'x Slab, pf: &'x PrintFunc) {
+    let xis = pf.0.iter().filter_map(|x_or_s| match x_or_s {
+        EExpr(xi) => Some(xi),
+        EStr(_) => None,
+    });
+    push_exprs(work, slab, xis);
+}
+
+// Walks `body` with `name` bound:
+fn schedule_scope<'x>(
+    work: &mut Vec<Step<'x>>,
+    slab: &'x Slab,
+    name: &'x str,
+    body: &'x ExpressionI,
+) {
+    work.push(Step::Unbind);
+    push_exprs(work, slab, [body].into_iter());
+    work.push(Step::Bind(name));
+}
@@ -0,0 +1,153 @@
+//! JavaScript bindings, for running formulas in the browser.
+//!
+//! With the `wasm` feature, a build for `wasm32-unknown-unknown` with
+//! [`wasm-bindgen`](https://rustwasm.github.io/docs/wasm-bindgen/) exports
+//! three functions to JavaScript:
+//!
+//! ```text
+//! import { parse, eval, last_error } from "./fasteval2.js";
+//!
+//! const formula = parse("x * 2 + y");                // Throws on syntax errors.
+//! eval(formula, { x: 3, y: 1 });                     // 7
+//! eval(formula, new Map([["x", 1], ["y", 0]]));      // 2
+//! formula.free();
+//! ```
+//!
+//! `parse()` compiles the formula once and returns a [`Formula`](struct.Formula.html)
+//! handle, which can be evaluated any number of times.  The variables are the
+//! properties of a plain object, or the entries of a `Map`, and must be
+//! numbers.  Formulas can use the built-in functions, but not custom ones.
+//!
+//! Failures are thrown as JavaScript `Error`s, with the message of the
+//! [`Error`](../error/enum.Error.html).  Parse errors also have `start` and
+//! `end` properties: the span of the problem in the source string, in UTF-16
+//! code units, so that `src.slice(err.start, err.end)` is the offending part.
+//! [`last_error()`](fn.last_error.html) returns the message of the last
+//! failure again, for callers that don't catch exceptions.
+//!
+//! Nothing in here is used unless the feature is enabled, and the rest of the
+//! crate doesn't need it to run in the browser: it never reads the clock or
+//! starts threads, unless an [`EvalNS`](../evalns/struct.EvalNS.html) is given
+//! a deadline or a `SystemClock`, and `rand()` uses the seed of
+//! [`EvalNS::with_rng()`](../evalns/struct.EvalNS.html#method.with_rng) instead
+//! of the operating system.
+//!
+//! `cargo test --target wasm32-unknown-unknown --features wasm --test wasm`
+//! runs `tests/wasm.rs` in Node.js, with the `wasm-bindgen-test-runner` from
+//! `cargo install wasm-bindgen-cli`.
+
+use crate::bytecode::CompiledExpression;
+use crate::compiler::Compiler;
+use crate::error::{Error, SpannedError};
+use crate::evalns::EmptyNamespace;
+use crate::parser::Parser;
+use crate::slab::Slab;
+
+use js_sys::{Map, Object, Reflect};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// A parsed and compiled formula, returned by [`parse()`](fn.parse.html).
+///
+/// In JavaScript, call `free()` on it when it is no longer needed.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Formula {
+    compiled: CompiledExpression,
+}
+
+/// Parses and compiles `src`.
+#[wasm_bindgen]
+pub fn parse(src: &str) -> Result<Formula, JsValue> {
+    let compiled = parse_spanned(src).map_err(|err| {
+        let js_err = throw(&err.error);
+        let utf16_len = |i: usize| src.get(..i).map_or(0, |s| s.encode_utf16().count());
+        let _ = Reflect::set(&js_err, &"start".into(), &utf16_len(err.span.start).into());
+        let _ = Reflect::set(&js_err, &"end".into(), &utf16_len(err.span.end).into());
+        js_err
+    })?;
+    set_last_error("");
+    Ok(Formula { compiled })
+}
+
+/// Evaluates `formula` with the variables in `vars`, a plain object or a `Map`.
+#[wasm_bindgen]
+pub fn eval(formula: &Formula, vars: &JsValue) -> Result<f64, JsValue> {
+    let mut ns = read_vars(vars).map_err(|err| throw(&err))?;
+    let val = formula.compiled.eval(&mut ns).map_err(|err| throw(&err))?;
+    set_last_error("");
+    Ok(val)
+}
+
+/// Returns the message of the error from the last `parse()` or `eval()`, or
+/// an empty string if it succeeded.
+#[wasm_bindgen]
+pub fn last_error() -> String {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+fn parse_spanned(src: &str) -> Result<CompiledExpression, SpannedError> {
+    let mut slab = Slab::new();
+    let expr_i = Parser::new().parse_spanned(src, &mut slab.ps)?;
+    let instr = expr_i
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    CompiledExpression::new(instr, slab).map_err(|error| SpannedError {
+        error,
+        span: 0..src.len(),
+    })
+}
+
+fn read_vars(vars: &JsValue) -> Result<BTreeMap<String, f64>, Error> {
+    let entries: Vec<(JsValue, JsValue)> = if let Some(map) = vars.dyn_ref::<Map>() {
+        let mut entries = Vec::with_capacity(map.size() as usize);
+        map.for_each(&mut |val, key| entries.push((key, val)));
+        entries
+    } else if vars.is_object() {
+        Object::entries(vars.unchecked_ref())
+            .iter()
+            .map(|entry| {
+                let entry: js_sys::Array = entry.unchecked_into();
+                (entry.get(0), entry.get(1))
+            })
+            .collect()
+    } else if vars.is_undefined() || vars.is_null() {
+        Vec::new()
+    } else {
+        return Err(Error::Expected(
+            "an object or a Map of variables".to_string(),
+        ));
+    };
+
+    let mut ns = BTreeMap::new();
+    for (key, val) in entries {
+        let name = key
+            .as_string()
+            .ok_or_else(|| Error::Expected("variable names to be strings".to_string()))?;
+        match val.as_f64() {
+            Some(val) => ns.insert(name, val),
+            None => return Err(Error::Expected(format!("a number for {:?}", name))),
+        };
+    }
+    Ok(ns)
+}
+
+fn set_last_error(msg: &str) {
+    LAST_ERROR.with(|last| {
+        let mut last = last.borrow_mut();
+        last.clear();
+        last.push_str(msg);
+    });
+}
+
+// Records `err` for last_error(), and converts it into a JavaScript Error.
+fn throw(err: &Error) -> JsValue {
+    let msg = err.to_string();
+    set_last_error(&msg);
+    js_sys::Error::new(&msg).into()
+}
@@ -0,0 +1,397 @@
+mod common;
+
+use fasteval2::{
+    BoundExpression, CompiledExpression, Compiler, EmptyNamespace, Error, Evaler, Parser, Slab,
+};
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashSet};
+
+// Uses every opcode of the first release of format version 1:
+static FIXTURE_EXPR: &str = "x + 2*y - z/3 ^ 2 % 4 < 1 || f(x, 2) && !(x >= y) \
+    + (x <= y) + (x == y) + (x != y) + (x > y) + log(2, y) + round(0.5, y) \
+    + min(x, y, 1) + max(x, 3) + int(x) + ceil(y) + floor(x) + abs(-y) + sign(x) \
+    + piecewise(x < 0, -1, x > 1, 1, 0) + sum(i, 1, 3, i*x) + interp_ext(x, 0,0, 10,100) \
+    + sin(x) + cos(x) + tan(x) + asin(x) + acos(x) + atan(x) \
+    + sinh(x) + cosh(x) + tanh(x) + asinh(x) + acosh(y) + atanh(x) + sqrt(y)";
+static FIXTURE_V1: &[u8] = include_bytes!("fixtures/bytecode_v1.bin");
+
+fn evalns_cb(name: &str, args: Vec<f64>) -> Option<f64> {
+    match name {
+        "x" => Some(0.5),
+        "y" => Some(2.0),
+        "z" => Some(3.0),
+        "f" => Some(args.iter().sum()),
+        _ => None,
+    }
+}
+
+fn interpreted(expr_str: &str) -> Result<f64, Error> {
+    let mut slab = fasteval2::Slab::new();
+    fasteval2::Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .eval(&slab, &mut evalns_cb)
+}
+
+#[test]
+fn roundtrip() {
+    for expr_str in [
+        FIXTURE_EXPR,
+        "1",
+        "NaN",
+        "-inf",
+        "-0.0",
+        "5e-324",
+        "x",
+        "g(1)",
+        "1 + 2 * 3",
+        "fma(x, y, z) + fma(2, x, 1)",
+        "coalesce(x / y, 2) + coalesce(NaN, x)",
+        "quotient(x, y) + remainder(x, 3) + quotient(7, 2)",
+        "sumsq(x, y, 2) + norm(x, z) - norm(y)",
+        "x + x + x + x + x + x + x + x + x + x + x + x + x + x + x + x",
+    ] {
+        let compiled = CompiledExpression::compile(expr_str, &mut EmptyNamespace).unwrap();
+        let bs = compiled.to_bytes().unwrap();
+        assert_eq!(&bs[..5], b"FEVB\x01");
+
+        let loaded = CompiledExpression::from_bytes(&bs).unwrap();
+        assert_eq!(loaded.to_bytes().unwrap(), bs);
+        let want = format!("{:?}", interpreted(expr_str).map(f64::to_bits));
+        assert_eq!(
+            format!("{:?}", compiled.eval(&mut evalns_cb).map(f64::to_bits)),
+            want
+        );
+        assert_eq!(
+            format!("{:?}", loaded.eval(&mut evalns_cb).map(f64::to_bits)),
+            want
+        );
+    }
+}
+
+#[test]
+fn fixture() {
+    // If this fails, the encoding has changed.  That breaks previously-exported
+    // bytecode, so bump BYTECODE_VERSION and keep loading the old format instead.
+    let compiled = CompiledExpression::compile(FIXTURE_EXPR, &mut EmptyNamespace).unwrap();
+    assert_eq!(compiled.to_bytes().unwrap(), FIXTURE_V1);
+
+    let loaded = CompiledExpression::from_bytes(FIXTURE_V1).unwrap();
+    assert_eq!(loaded.eval(&mut evalns_cb), interpreted(FIXTURE_EXPR));
+}
+
+#[test]
+fn unencodable() {
+    assert_eq!(
+        CompiledExpression::compile("print(1)", &mut EmptyNamespace).unwrap_err(),
+        Error::Bytecode("print() can't be encoded".to_string())
+    );
+}
+
+#[test]
+fn invalid() {
+    let bs = CompiledExpression::compile("x + f(1, 2)", &mut EmptyNamespace)
+        .unwrap()
+        .to_bytes()
+        .unwrap();
+    let load = |bs: &[u8]| CompiledExpression::from_bytes(bs).map(|_| ()).unwrap_err();
+    let err = |s: &str| Error::Bytecode(s.to_string());
+    let c = b"\x00\x00\x00\x00\x00\x00\xf0\x3f"; // 1.0
+
+    assert_eq!(load(b""), err("unexpected end of input"));
+    assert_eq!(load(b"FEVA\x01"), err("bad magic header"));
+    assert_eq!(load(b"FEVB\x02"), err("unsupported format version 2"));
+    assert_eq!(load(b"FEVB\x01"), err("no instructions"));
+    assert_eq!(load(b"FEVB\x01\xff"), err("unknown opcode 255"));
+    assert_eq!(
+        load(b"FEVB\x01\x01\x00\x00"),
+        err("unexpected end of input")
+    );
+    assert_eq!(
+        load(&[&b"FEVB\x01\x00"[..], c].concat()),
+        err("the result must be an instruction")
+    );
+    assert_eq!(
+        load(&[&bs[..], b"\x00", c].concat()),
+        err("1 unused operands")
+    );
+    assert_eq!(load(&bs[..bs.len() - 1]), err("1 unused operands"));
+    assert_eq!(
+        load(b"FEVB\x01\x05"),
+        err("opcode 5 needs 2 operands, but only 0 are available")
+    );
+    assert_eq!(
+        load(&[&b"FEVB\x01\x00"[..], c, b"\x02"].concat()),
+        err("opcode 2 needs an instruction operand, not a constant")
+    );
+    assert_eq!(
+        load(b"FEVB\x01\x12\x01f\xff\xff\xff\xff\x0f"),
+        err("opcode 18 needs 4294967295 operands, but only 0 are available")
+    );
+    assert_eq!(
+        load(b"FEVB\x01\x11\x02\xff\xfe"),
+        err("invalid utf8 in string")
+    );
+
+    // A deeply-nested chain of negations:
+    let mut deep = [&b"FEVB\x01\x01"[..], c].concat();
+    deep.resize(deep.len() + 100_000, 2u8);
+    assert_eq!(load(&deep), err("too deep"));
+    deep.truncate(deep.len() - 100_000 + 1000);
+    assert!(CompiledExpression::from_bytes(&deep).is_ok());
+}
+
+#[test]
+fn fuzz() {
+    let mut rand = common::rand_gen(0x2545_f491_4f6c_dd1d);
+
+    let seeds: Vec<Vec<u8>> = [FIXTURE_EXPR, "piecewise(x<0, -x, x)", "interp(x, 0,0, 1,1)"]
+        .iter()
+        .map(|s| {
+            CompiledExpression::compile(s, &mut EmptyNamespace)
+                .unwrap()
+                .to_bytes()
+                .unwrap()
+        })
+        .collect();
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 0.5);
+
+    for _ in 0..20_000 {
+        let mut bs = seeds[rand() as usize % seeds.len()].clone();
+        for _ in 0..1 + rand() % 4 {
+            let i = rand() as usize % bs.len();
+            match rand() % 4 {
+                0 => bs[i] = rand() as u8,
+                1 => bs[i] ^= 1 << (rand() % 8),
+                2 => bs.truncate(i.max(1)),
+                _ => bs.insert(i, rand() as u8),
+            }
+        }
+        // Must not panic; valid results must be evaluatable and re-encodable:
+        if let Ok(loaded) = CompiledExpression::from_bytes(&bs) {
+            let _ = loaded.eval(&mut ns);
+            assert_eq!(
+                CompiledExpression::from_bytes(&loaded.to_bytes().unwrap())
+                    .unwrap()
+                    .to_bytes()
+                    .unwrap(),
+                loaded.to_bytes().unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn reduce_rows() {
+    let xs = [1.0, 2.0, 3.0, 4.0];
+    let ys = [10.0, 20.0, 30.0, 40.0];
+    let rows: Vec<[f64; 2]> = xs.iter().zip(&ys).map(|(x, y)| [*x, *y]).collect();
+    let rows = || rows.iter().map(|row| &row[..]);
+
+    let compiled = CompiledExpression::compile("x * y", &mut EmptyNamespace).unwrap();
+    assert_eq!(compiled.sum_rows(&["x", "y"], rows()), Ok(300.0));
+    assert_eq!(
+        compiled.reduce_rows(&["x", "y"], rows(), 1.0, |a, b| a * b),
+        Ok(10.0 * 40.0 * 90.0 * 160.0)
+    );
+    // The names are matched by position, and extra values are ignored:
+    let compiled =
+        CompiledExpression::compile("sum(i, 1, n, i) - k + phi * 0", &mut EmptyNamespace).unwrap();
+    let flat = [3.0, 1.0, 99.0, 4.0, 2.0, 99.0];
+    assert_eq!(compiled.sum_rows(&["n", "k"], flat.chunks(3)), Ok(13.0));
+    assert_eq!(
+        compiled.sum_rows(&["n", "k"], flat.chunks(3).take(0)),
+        Ok(0.0)
+    );
+
+    // Errors stop the fold:
+    assert_eq!(
+        compiled.sum_rows(&["n"], flat.chunks(3)),
+        Err(Error::UndefinedVariable {
+            name: "k".to_string()
+        })
+    );
+    assert_eq!(
+        compiled.sum_rows(&["n", "k"], flat.chunks(5)),
+        Err(Error::Expected("2 values in row 1, got 1".to_string()))
+    );
+}
+
+#[test]
+fn eval_incremental() {
+    let calls = Cell::new(0);
+    let (x, y) = (Cell::new(1.0), Cell::new(2.0));
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "x" => Some(x.get()),
+        "y" => Some(y.get()),
+        "slow" => {
+            calls.set(calls.get() + 1);
+            Some(args.iter().sum::<f64>() * 10.0)
+        }
+        _ => None,
+    };
+    let changed = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<HashSet<_>>();
+
+    let expr_str =
+        "slow(3) + slow(y) * x + sum(i, 1, 3, slow(i) * x) + piecewise(x > 2, slow(x), 0)";
+    let mut compiled = CompiledExpression::compile(expr_str, &mut EmptyNamespace).unwrap();
+    assert_eq!(compiled.eval_incremental(&changed(&[]), &mut ns), Ok(110.0));
+    assert_eq!(calls.replace(0), 5);
+
+    // The constant slow(3) and slow(y) are reused, but sum() depends on x:
+    x.set(3.0);
+    assert_eq!(
+        compiled.eval_incremental(&changed(&["x"]), &mut ns),
+        Ok(300.0)
+    );
+    assert_eq!(calls.replace(0), 4);
+    assert_eq!(compiled.eval(&mut ns), Ok(300.0));
+    calls.set(0);
+
+    y.set(1.0);
+    assert_eq!(
+        compiled.eval_incremental(&changed(&["y"]), &mut ns),
+        Ok(270.0)
+    );
+    assert_eq!(calls.replace(0), 1);
+    // Nothing changed:
+    assert_eq!(
+        compiled.eval_incremental(&changed(&["z"]), &mut ns),
+        Ok(270.0)
+    );
+    assert_eq!(calls.replace(0), 0);
+    // Changing a function recomputes its calls:
+    assert_eq!(
+        compiled.eval_incremental(&changed(&["slow"]), &mut ns),
+        Ok(270.0)
+    );
+    assert_eq!(calls.replace(0), 6);
+
+    // Shared sub-expressions still work:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("slow(y) * slow(y) + x", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    let mut compiled = CompiledExpression::new(instr, slab).unwrap();
+    assert_eq!(compiled.eval_incremental(&changed(&[]), &mut ns), Ok(103.0));
+    assert_eq!(calls.replace(0), 1);
+    x.set(4.0);
+    assert_eq!(
+        compiled.eval_incremental(&changed(&["x"]), &mut ns),
+        Ok(104.0)
+    );
+    assert_eq!(calls.replace(0), 0);
+    y.set(2.0);
+    assert_eq!(
+        compiled.eval_incremental(&changed(&["y"]), &mut ns),
+        Ok(404.0)
+    );
+    assert_eq!(calls.replace(0), 1);
+
+    // The cache's slots don't overlap the temporaries of a top-level ITemps:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("(x - y) * (x - y) + y", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    let mut compiled = CompiledExpression::new(instr, slab).unwrap();
+    assert_eq!(compiled.eval_incremental(&changed(&[]), &mut ns), Ok(6.0));
+    assert_eq!(compiled.eval(&mut ns), Ok(6.0));
+}
+
+#[test]
+fn bind() {
+    let values = |bound: &BoundExpression| -> Vec<f64> {
+        let syms = bound.symbols();
+        syms.names()
+            .map(|name| evalns_cb(name, Vec::new()).unwrap_or(f64::NAN))
+            .collect()
+    };
+    for expr_str in [
+        FIXTURE_EXPR,
+        "x",
+        "x * y - z + x",
+        "z + sum(i, 1, 3, i * x + sum(j, 1, i, j * y))",
+        "piecewise(x > 1, y / 0, z) + f(x, y, f(z))",
+    ] {
+        let compiled = CompiledExpression::compile(expr_str, &mut EmptyNamespace).unwrap();
+        let bound = compiled.bind().unwrap();
+        let vals = values(&bound);
+        assert_eq!(
+            bound.eval(&vals, &mut evalns_cb),
+            compiled.eval(&mut evalns_cb),
+            "{}",
+            expr_str
+        );
+    }
+
+    // Only custom functions reach the Namespace, and a distinct name has one symbol:
+    let compiled = CompiledExpression::compile(
+        "x * y + x + f(x) + sum(i, 1, 3, i * y)",
+        &mut EmptyNamespace,
+    )
+    .unwrap();
+    let bound = compiled.bind().unwrap();
+    let syms = bound.symbols();
+    assert_eq!(syms.names().collect::<Vec<_>>().len(), syms.len());
+    assert_eq!(syms.len(), 3);
+    for name in ["x", "y", "i"] {
+        assert_eq!(syms.name(syms.get(name).unwrap()), Some(name));
+    }
+    assert_eq!(syms.get("f"), None);
+    let mut vals = vec![0.0; syms.len()];
+    vals[syms.get("x").unwrap().index()] = 2.0;
+    vals[syms.get("y").unwrap().index()] = 3.0;
+    // The loop variable shadows a value for `i`:
+    vals[syms.get("i").unwrap().index()] = 100.0;
+    let mut names = Vec::new();
+    let mut ns = |name: &str, args: Vec<f64>| {
+        names.push(name.to_string());
+        evalns_cb(name, args)
+    };
+    assert_eq!(bound.eval(&vals, &mut ns), Ok(6.0 + 2.0 + 2.0 + 18.0));
+    assert_eq!(names, ["f"]);
+
+    // Shared sub-expressions still work:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("(x - y) * (x - y) + y", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    let bound = CompiledExpression::new(instr, slab)
+        .unwrap()
+        .bind()
+        .unwrap();
+    let mut vals = vec![0.0; 2];
+    vals[bound.symbols().get("x").unwrap().index()] = 5.0;
+    vals[bound.symbols().get("y").unwrap().index()] = 1.0;
+    assert_eq!(bound.eval(&vals, &mut EmptyNamespace), Ok(17.0));
+
+    // Missing values are looked up by name, and errors name the variable:
+    let bound = CompiledExpression::compile("a + b * sum(i, 1, 2, b)", &mut EmptyNamespace)
+        .unwrap()
+        .bind()
+        .unwrap();
+    assert_eq!(bound.symbols().names().collect::<Vec<_>>(), ["a", "b"]);
+    assert_eq!(
+        bound.eval(&[1.0], &mut EmptyNamespace),
+        Err(Error::UndefinedVariable {
+            name: "b".to_string()
+        })
+    );
+    assert_eq!(
+        bound.eval(&[1.0], &mut |name: &str, _: Vec<f64>| (name == "b")
+            .then_some(3.0)),
+        Ok(19.0)
+    );
+    assert_eq!(bound.eval(&[1.0, 3.0], &mut EmptyNamespace), Ok(19.0));
+}
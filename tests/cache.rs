@@ -0,0 +1,161 @@
+use fasteval2::{CachingExpr, CompiledExpression, EmptyNamespace, Error, ExpressionCache, Parser};
+
+use std::cell::Cell;
+use std::sync::Arc;
+
+#[test]
+fn lru() {
+    let parser = Parser::new();
+    let mut cache = ExpressionCache::new(2);
+
+    let a = cache.get_or_parse("a + 1", &parser).unwrap();
+    cache.get_or_parse("b + 1", &parser).unwrap();
+    assert!(Arc::ptr_eq(
+        &a,
+        &cache.get_or_parse("a + 1", &parser).unwrap()
+    ));
+
+    // "b + 1" is the least recently used:
+    cache.get_or_parse("c + 1", &parser).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert!(cache.contains("a + 1"));
+    assert!(!cache.contains("b + 1"));
+    assert!(cache.contains("c + 1"));
+    assert_eq!((cache.hits(), cache.misses(), cache.evictions()), (1, 3, 1));
+
+    // An evicted handle still works:
+    cache.get_or_parse("b + 1", &parser).unwrap();
+    cache.get_or_parse("d + 1", &parser).unwrap();
+    assert!(!cache.contains("a + 1"));
+    let mut ns = |_: &str, _: Vec<f64>| Some(1.0);
+    assert_eq!(a.eval(&mut ns), Ok(2.0));
+    assert_eq!(cache.evictions(), 3);
+}
+
+#[test]
+fn errors() {
+    let parser = Parser::new();
+    let mut cache = ExpressionCache::new(2);
+    assert_eq!(
+        cache.get_or_parse("1 +", &parser).unwrap_err(),
+        Error::EofWhileParsing("value".to_string())
+    );
+    assert!(cache.get_or_parse(r#"print("x")"#, &parser).is_err());
+    assert!(cache.is_empty());
+    assert_eq!(cache.misses(), 2);
+
+    let max_len = Parser::builder().max_len(3).build().unwrap();
+    assert!(cache.get_or_parse("1 + 2 + 3", &max_len).is_err());
+
+    let mut uncached = ExpressionCache::new(0);
+    uncached.get_or_parse("x", &parser).unwrap();
+    uncached.get_or_parse("x", &parser).unwrap();
+    assert_eq!((uncached.len(), uncached.misses()), (0, 2));
+}
+
+#[test]
+fn caching_expr() {
+    let compiled = CompiledExpression::compile("slow(x) * y + z", &mut EmptyNamespace).unwrap();
+    let mut caching = CachingExpr::new(compiled);
+    assert_eq!(caching.var_names(), ["x", "y", "z"]);
+
+    // Counts the evaluations of the wrapped expression:
+    let calls = Cell::new(0);
+    let eval = |caching: &mut CachingExpr, vars: &[(&str, f64)]| {
+        let mut ns = |name: &str, args: Vec<f64>| {
+            if name == "slow" {
+                calls.set(calls.get() + 1);
+                return Some(args[0] * 10.0);
+            }
+            vars.iter().find(|(n, _)| *n == name).map(|(_, val)| *val)
+        };
+        caching.eval(&mut ns)
+    };
+
+    // Errors are not cached:
+    let undefined = Err(Error::UndefinedVariable {
+        name: "z".to_string(),
+    });
+    assert_eq!(eval(&mut caching, &[("x", 2.0), ("y", 3.0)]), undefined);
+    assert_eq!(eval(&mut caching, &[("x", 2.0), ("y", 3.0)]), undefined);
+    assert_eq!(calls.get(), 2);
+
+    let vars = [("x", 2.0), ("y", 3.0), ("z", 1.0)];
+    assert_eq!(eval(&mut caching, &vars), Ok(61.0));
+    assert_eq!(calls.get(), 3);
+    assert_eq!(eval(&mut caching, &vars), Ok(61.0));
+    assert_eq!(eval(&mut caching, &vars), Ok(61.0));
+    assert_eq!(calls.get(), 3);
+    assert_eq!((caching.hits(), caching.misses()), (2, 3));
+
+    assert_eq!(
+        eval(&mut caching, &[("x", 2.0), ("y", 3.0), ("z", 2.0)]),
+        Ok(62.0)
+    );
+    assert_eq!(calls.get(), 4);
+    caching.invalidate();
+    assert_eq!(
+        eval(&mut caching, &[("x", 2.0), ("y", 3.0), ("z", 2.0)]),
+        Ok(62.0)
+    );
+    assert_eq!(calls.get(), 5);
+
+    // NaN is the same input as NaN:
+    let nan = [("x", f64::NAN), ("y", 3.0), ("z", 2.0)];
+    assert!(eval(&mut caching, &nan).unwrap().is_nan());
+    assert!(eval(&mut caching, &nan).unwrap().is_nan());
+    assert_eq!(calls.get(), 6);
+
+    // Impure functions are called every time:
+    let compiled = CompiledExpression::compile("x + rand() * 0", &mut EmptyNamespace).unwrap();
+    let mut caching = CachingExpr::new(compiled);
+    let mut ns = |name: &str, _: Vec<f64>| match name {
+        "x" => Some(1.0),
+        "rand" => Some(0.5),
+        _ => None,
+    };
+    assert_eq!(caching.eval(&mut ns), Ok(1.0));
+    assert_eq!(caching.eval(&mut ns), Ok(1.0));
+    assert_eq!((caching.hits(), caching.misses()), (0, 2));
+}
+
+#[cfg(not(feature = "unsafe-vars"))]
+#[test]
+fn threads() {
+    use std::sync::Mutex;
+    use std::thread;
+
+    let cache = Arc::new(Mutex::new(ExpressionCache::new(3)));
+    let parser = Arc::new(Parser::new());
+
+    let workers: Vec<_> = (0..8)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            let parser = Arc::clone(&parser);
+            thread::spawn(move || {
+                for i in 0..200 {
+                    let k = (i * 7 + t) % 6;
+                    let src = format!("x * {} + f(x, {})", k, k);
+                    let expr = cache.lock().unwrap().get_or_parse(&src, &parser).unwrap();
+                    // The lock is released while evaluating:
+                    let x = (t * 1000 + i) as f64;
+                    let mut ns = |name: &str, args: Vec<f64>| match name {
+                        "x" => Some(x),
+                        "f" => Some(args[0] - args[1]),
+                        _ => None,
+                    };
+                    assert_eq!(expr.eval(&mut ns), Ok(x * k as f64 + x - k as f64));
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    let cache = cache.lock().unwrap();
+    assert!(cache.len() <= 3);
+    assert_eq!(cache.hits() + cache.misses(), 8 * 200);
+    assert!(cache.evictions() > 0);
+    assert_eq!(cache.misses(), cache.evictions() + cache.len() as u64);
+}
@@ -0,0 +1,12 @@
+// Helpers shared by the integration tests.  Each test file that needs them
+// declares `mod common;`.
+
+// A small deterministic PRNG (xorshift64), so failures are reproducible:
+pub fn rand_gen(mut state: u64) -> impl FnMut() -> u64 {
+    move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    }
+}
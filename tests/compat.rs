@@ -0,0 +1,68 @@
+use fasteval2::compat::meval::{eval_str, eval_str_with_context, ArgGuard, Context};
+use fasteval2::{ez_eval, Arity, Error, EvalNS};
+
+#[test]
+fn meval_context() {
+    // The usual meval one-liner:
+    assert_eq!(
+        eval_str_with_context("x*2", Context::new().var("x", 3.0)),
+        Ok(6.0)
+    );
+
+    let y = 10.0;
+    let mut ctx = Context::empty();
+    ctx.var("x", 3.0)
+        .func("plus_y", |x| x + y)
+        .func2("sub", |a, b| a - b)
+        .func3("mix", |a, b, c| a * b + c)
+        .funcn("count", |xs| xs.len() as f64, 2..);
+    assert_eq!(
+        eval_str_with_context("plus_y(x) + sub(5, 1) + mix(2, 3, 4)", &ctx),
+        Ok(27.0)
+    );
+    assert_eq!(eval_str_with_context("count(1, 2, 3)", &mut ctx), Ok(3.0));
+
+    // Argument counts are checked:
+    assert_eq!(
+        eval_str_with_context("sub(1)", &ctx),
+        Err(Error::WrongArgCount {
+            name: "sub".to_string(),
+            expected: Arity::Exact(2),
+            got: 1
+        })
+    );
+    assert_eq!(
+        eval_str_with_context("count(1)", &ctx),
+        Err(Error::WrongArgCount {
+            name: "count".to_string(),
+            expected: Arity::AtLeast(2),
+            got: 1
+        })
+    );
+    assert_eq!(
+        eval_str_with_context("pi", &ctx),
+        Err(Error::UndefinedVariable {
+            name: "pi".to_string()
+        })
+    );
+    assert_eq!(ArgGuard::from(3), ArgGuard::Exact(3));
+
+    // It's a Namespace, so it works with the rest of the crate:
+    assert_eq!(ez_eval("x + 1", &mut ctx), Ok(4.0));
+    let mut ns = EvalNS::new(ctx).with_budget(1);
+    assert_eq!(ez_eval("x + x + x", &mut ns), Err(Error::BudgetExceeded(1)));
+}
+
+#[test]
+fn meval_builtins() {
+    assert_eq!(eval_str("pi"), Ok(std::f64::consts::PI));
+    assert_eq!(eval_str("ln(e) + exp(0) + signum(-5)"), Ok(1.0));
+    assert_eq!(eval_str("atan2(1, 1) * 4"), Ok(std::f64::consts::PI));
+    // fasteval2 built-ins are still there:
+    assert_eq!(eval_str("sqrt(16) + max(1, 2, 3)"), Ok(7.0));
+    // Variables can override the meval built-ins:
+    assert_eq!(
+        eval_str_with_context("e", Context::new().var("e", 2.0)),
+        Ok(2.0)
+    );
+}
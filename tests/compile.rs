@@ -129,7 +129,7 @@ fn unsafe_comp_chk(expr_str: &str, expect_fmt: &str, expect_eval: f64) {
                         match v.get(start) {
                             None => break,
                             Some(&b) => {
-                                if (b'0' <= b && b <= b'9') || (b'a' <= b && b <= b'f') {
+                                if b.is_ascii_digit() || (b'a'..=b'f').contains(&b) {
                                     v[start] = b'?';
                                     start += 1;
                                 } else {
@@ -303,6 +303,8 @@ fn double_neg() {
 }
 
 #[test]
+// The expected results of folded constants are written out:
+#[allow(clippy::approx_constant)]
 fn all_instrs() {
     // IConst:
     comp_chk("1", IConst(1.0), "CompileSlab{ instrs:{} }", 1.0);
@@ -482,7 +484,7 @@ fn all_instrs() {
         "-4 ^ 0.5",
         "IConst(NaN)",
         "CompileSlab{ instrs:{} }",
-        std::f64::NAN,
+        f64::NAN,
     );
     comp_chk(
         "y ^ 0.5",
@@ -1111,13 +1113,13 @@ fn all_instrs() {
         "min(NaN, y7, 4.7)",
         "IFuncMin(InstructionI(0), C(NaN))",
         "CompileSlab{ instrs:{ 0:IVar(\"y7\") } }",
-        std::f64::NAN,
+        f64::NAN,
     );
     comp_chk_str(
         "min(NaN, 4.7)",
         "IConst(NaN)",
         "CompileSlab{ instrs:{} }",
-        std::f64::NAN,
+        f64::NAN,
     );
     comp_chk_str(
         "min(inf, y7, 4.7)",
@@ -1135,13 +1137,13 @@ fn all_instrs() {
         "min(-inf, y7, 4.7)",
         "IFuncMin(InstructionI(0), C(-inf))",
         "CompileSlab{ instrs:{ 0:IVar(\"y7\") } }",
-        std::f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
     );
     comp_chk_str(
         "min(-inf, 4.7)",
         "IConst(-inf)",
         "CompileSlab{ instrs:{} }",
-        std::f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
     );
 
     // IFuncMax
@@ -1180,25 +1182,25 @@ fn all_instrs() {
         "max(NaN, y7, 0.7)",
         "IFuncMax(InstructionI(0), C(NaN))",
         "CompileSlab{ instrs:{ 0:IVar(\"y7\") } }",
-        std::f64::NAN,
+        f64::NAN,
     );
     comp_chk_str(
         "max(NaN, 0.7)",
         "IConst(NaN)",
         "CompileSlab{ instrs:{} }",
-        std::f64::NAN,
+        f64::NAN,
     );
     comp_chk_str(
         "max(inf, y7, 4.7)",
         "IFuncMax(InstructionI(0), C(inf))",
         "CompileSlab{ instrs:{ 0:IVar(\"y7\") } }",
-        std::f64::INFINITY,
+        f64::INFINITY,
     );
     comp_chk_str(
         "max(inf, 4.7)",
         "IConst(inf)",
         "CompileSlab{ instrs:{} }",
-        std::f64::INFINITY,
+        f64::INFINITY,
     );
     comp_chk_str(
         "max(-inf, y7, 4.7)",
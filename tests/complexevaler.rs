@@ -0,0 +1,99 @@
+use fasteval2::{ComplexEvaler, EmptyNamespace, Error, EvalNS, Evaler, Parser, Slab};
+
+use std::collections::BTreeMap;
+
+fn eval_complex(expr_str: &str) -> Result<(f64, f64), Error> {
+    let mut slab = Slab::new();
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 3.0);
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .eval_complex(&slab, &mut ns)
+}
+
+fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+    (a.0 - b.0).abs() < 1e-12 && (a.1 - b.1).abs() < 1e-12
+}
+
+#[test]
+fn basics() {
+    assert_eq!(eval_complex("i*i"), Ok((-1.0, 0.0)));
+    assert_eq!(eval_complex("(1+i)*(1-i)"), Ok((2.0, 0.0)));
+    assert_eq!(eval_complex("i^2"), Ok((-1.0, 0.0)));
+    assert_eq!(eval_complex("i^-1"), Ok((0.0, -1.0)));
+    assert_eq!(eval_complex("(3 + 4*i) / (1 + 2*i)"), Ok((2.2, -0.4)));
+    assert_eq!(eval_complex("-(x - 2*i)"), Ok((-3.0, 2.0)));
+    assert_eq!(eval_complex("abs(3 + 4*i) + sqrt(-4)"), Ok((5.0, 2.0)));
+    assert_eq!(eval_complex("sqrt(2*i)"), Ok((1.0, 1.0)));
+    assert_eq!(eval_complex("piecewise(x > 2, i, 1)"), Ok((0.0, 1.0)));
+    assert_eq!(eval_complex("2 * i^3^2"), Ok((0.0, 2.0)));
+
+    // e^(i*pi) = -1:
+    let euler = eval_complex("e()^(i*pi())").unwrap();
+    assert!(close(euler, (-1.0, 0.0)), "{:?}", euler);
+    let root = eval_complex("(-8)^(1/3)").unwrap();
+    assert!(close(root, (1.0, 3f64.sqrt())), "{:?}", root);
+}
+
+#[test]
+fn real_only() {
+    // Real expressions give the same results as eval(), with no imaginary part:
+    for expr_str in [
+        "1 + 2*3 - 4",
+        "2^0.5 + (-2)^3",
+        "7 % 3 + (x > 2) + !x",
+        "x || 0 && 5",
+        "sin(x) * log(100) + max(1, x, 2)",
+    ] {
+        let mut slab = Slab::new();
+        let expr_ref = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        let ns = || {
+            let mut ns = BTreeMap::<String, f64>::new();
+            ns.insert("x".to_string(), 3.0);
+            ns
+        };
+        let real = expr_ref.eval(&slab, &mut ns()).unwrap();
+        assert_eq!(
+            expr_ref.eval_complex(&slab, &mut ns()),
+            Ok((real, 0.0)),
+            "{}",
+            expr_str
+        );
+    }
+}
+
+#[test]
+fn errors() {
+    let not_real = |s: &str| Err(Error::NotReal(s.to_string()));
+    assert_eq!(eval_complex("i < 1"), not_real("0+1i"));
+    assert_eq!(eval_complex("(2 - i) % 2"), not_real("2-1i"));
+    assert_eq!(eval_complex("!i"), not_real("0+1i"));
+    assert_eq!(eval_complex("piecewise(i, 1, 2)"), not_real("0+1i"));
+    assert_eq!(eval_complex("f(i)"), not_real("0+1i"));
+    assert_eq!(
+        eval_complex("y + i"),
+        Err(Error::UndefinedVariable {
+            name: "y".to_string()
+        })
+    );
+
+    // A Namespace can define its own i:
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse("i * 2", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut ns = |name: &str, _: Vec<f64>| (name == "i").then_some(5.0);
+    assert_eq!(expr_ref.eval_complex(&slab, &mut ns), Ok((10.0, 0.0)));
+
+    // Budgets are enforced:
+    let mut ns = EvalNS::new(EmptyNamespace).with_budget(1);
+    assert_eq!(
+        expr_ref.eval_complex(&slab, &mut ns),
+        Err(Error::BudgetExceeded(1))
+    );
+}
@@ -0,0 +1,143 @@
+use fasteval2::{Compiler, CostReport, EmptyNamespace, Parser, Slab};
+
+use std::collections::BTreeMap;
+
+fn cost(expr_str: &str) -> CostReport {
+    let mut slab = Slab::new();
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .cost(&slab)
+}
+
+fn compiled_cost(expr_str: &str) -> CostReport {
+    let mut slab = Slab::new();
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace)
+        .cost(&slab)
+}
+
+fn calls(pairs: &[(&str, usize)]) -> BTreeMap<String, usize> {
+    pairs.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+}
+
+#[test]
+fn counts() {
+    assert_eq!(
+        cost("x + y*2 - 3"),
+        CostReport {
+            ops: 3,
+            vars: 2,
+            calls: calls(&[]),
+            loops: 0,
+            max_trips: Some(0),
+            score: 7.0,
+        }
+    );
+    assert_eq!(
+        cost("1.5"),
+        CostReport {
+            max_trips: Some(0),
+            ..CostReport::default()
+        }
+    );
+    assert_eq!(cost("-(x) * !y").ops, 3);
+    assert_eq!(cost("-(x) * !y").score, 7.0);
+
+    let c = cost("sin(x)^2 + cos(x)^2");
+    assert_eq!((c.ops, c.vars), (3, 1));
+    assert_eq!(c.calls, calls(&[("cos", 1), ("sin", 1)]));
+    assert_eq!(c.score, 21.0);
+
+    // Custom functions and print() are the most expensive calls:
+    let c = cost(r#"max(a, b, c) + f(a, 2) + print("v", a)"#);
+    assert_eq!((c.ops, c.vars), (2, 3));
+    assert_eq!(c.calls, calls(&[("f", 1), ("max", 1), ("print", 1)]));
+    assert_eq!(c.call_count(), 3);
+    assert_eq!(c.score, 33.0);
+    assert_eq!(
+        cost("rand() + randint(1, 6)").calls,
+        calls(&[("rand", 1), ("randint", 1)])
+    );
+    assert_eq!(cost("pi() * e()").calls, calls(&[("e", 1), ("pi", 1)]));
+}
+
+#[test]
+fn loops() {
+    // Nested loops multiply:
+    let c = cost("sum(i, 1, 10, sum(j, 0, 4, i*j))");
+    assert_eq!((c.ops, c.vars, c.loops), (1, 0, 2));
+    assert_eq!(c.max_trips, Some(50));
+    assert_eq!(c.score, 10.0 + 50.0 + 50.0 + 2.0 * 2.0 * 50.0);
+
+    // Sibling loops don't:
+    let c = cost("sum(i, 1, 10, i) + sum(i, -(2), 2, x)");
+    assert_eq!((c.vars, c.loops, c.max_trips), (1, 2, Some(10)));
+
+    // Fractional and empty ranges:
+    assert_eq!(cost("sum(i, 0.5, 3, i)").max_trips, Some(3));
+    let c = cost("sum(i, 5, 1, f(i))");
+    assert_eq!((c.max_trips, c.score), (Some(0), 0.0));
+
+    // Unknown bounds assume 100 iterations:
+    let c = cost("sum(i, 1, n, i)");
+    assert_eq!((c.vars, c.loops, c.max_trips), (1, 1, None));
+    assert_eq!(c.score, 2.0 + 100.0 + 2.0 * 100.0);
+    assert_eq!(cost("sum(i, 1, 10, sum(j, 1, 2*i, j))").max_trips, None);
+    assert_eq!(cost("sum(i, 1, 2+3, i)").max_trips, None);
+
+    // The loop variable is only bound inside the body:
+    assert_eq!(cost("sum(i, 1, 3, i) + i").vars, 1);
+}
+
+#[test]
+fn compiled() {
+    // Division becomes a multiplication by an inverse, and constants are folded:
+    let c = compiled_cost("x/y + 2*3");
+    assert_eq!((c.ops, c.vars, c.score), (3, 2, 7.0));
+    assert_eq!(cost("x/y + 2*3").ops, 3);
+    assert_eq!(
+        compiled_cost("1 + 2*3"),
+        CostReport {
+            max_trips: Some(0),
+            ..CostReport::default()
+        }
+    );
+
+    assert_eq!(compiled_cost("max(a, b, c)").calls, calls(&[("max", 2)]));
+    assert_eq!(compiled_cost("sum(i, 1, 2+3, i)").max_trips, Some(5));
+    assert_eq!(
+        compiled_cost(r#"print("v", x) + log(x)"#).calls,
+        calls(&[("log", 1), ("print", 1)])
+    );
+
+    // Shared sub-expressions only count once:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("(x-y)*(x-y)", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let before = instr.cost(&slab);
+    assert_eq!((before.ops, before.score), (5, 13.0));
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    let after = instr.cost(&slab);
+    assert_eq!((after.ops, after.vars, after.score), (3, 2, 7.0));
+}
+
+#[test]
+fn ordering() {
+    let terms = (0..200)
+        .map(|i| format!("x{}*{}", i % 7, i))
+        .collect::<Vec<_>>()
+        .join(" + ");
+    let big = cost(&terms);
+    assert_eq!((big.ops, big.vars), (399, 7));
+    assert!(cost("x + 1").score < big.score);
+    assert!(compiled_cost("x + 1").score < compiled_cost(&terms).score);
+    assert!(cost("sin(x)").score < cost("sum(i, 1, 1000, i)").score);
+}
@@ -0,0 +1,315 @@
+use fasteval2::{
+    CompiledExpression, Compiler, CseReport, EmptyNamespace, Error, EvalNS, Evaler, Instruction,
+    Parser, Slab,
+};
+
+use std::collections::BTreeMap;
+
+static VARS: [&str; 3] = ["x", "y", "z"];
+
+static CORPUS: &[&str] = &[
+    "(x-y)*(x-y)*z",
+    "sin(x)^2 + cos(x)^2 + sin(x)",
+    "(x+1)/(x+1)",
+    "max(x*y, x*y + 1, abs(x*y))",
+    "piecewise(x < y, (x+z)*2, (x+z)*3)",
+    "(x > 0 && y/x > 1) || y/x < -1",
+    "sum(i, 1, 3, i*x) + sum(i, 1, 3, i*x)",
+    "sum(i, 1, 3, (i+x)*(i+x)) + (y+x)*(y+x)",
+    "fma(x*y, z, x*y) - sqrt(x*y)",
+    "log(2, abs(x)+1) * log(2, abs(x)+1) + log(abs(x)+1)",
+    "x^y + x^y^y",
+    "x * y + z",
+];
+
+fn rand_inputs(n: usize) -> Vec<[f64; 3]> {
+    // xorshift64, so failures are reproducible:
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut rand = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let special = [0.0, -0.0, 1.0, -1.0, f64::NAN, f64::INFINITY];
+    let mut val = move || {
+        let r = rand();
+        match r % 4 {
+            0 => special[(r >> 8) as usize % special.len()],
+            1 => ((r >> 8) % 21) as f64 - 10.0,
+            _ => ((r >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * 20.0,
+        }
+    };
+    (0..n).map(|_| [val(), val(), val()]).collect()
+}
+
+fn compile(expr_str: &str, slab: &mut Slab) -> Result<Instruction, Error> {
+    Ok(Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace))
+}
+
+fn cse(expr_str: &str, slab: &mut Slab) -> (Instruction, CseReport) {
+    compile(expr_str, slab)
+        .unwrap()
+        .eliminate_common_subexpressions(&mut slab.cs)
+}
+
+fn eval(instr: &Instruction, slab: &Slab, vars: &[f64; 3]) -> Result<f64, Error> {
+    let mut ns = |name: &str, _: Vec<f64>| VARS.iter().position(|v| *v == name).map(|i| vars[i]);
+    instr.eval(slab, &mut ns)
+}
+
+/// Evaluates `instr` and counts the lookups of each name.
+fn lookups(instr: &Instruction, slab: &Slab, vars: &[f64; 3]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    let mut ns = |name: &str, _: Vec<f64>| {
+        *counts.entry(name.to_string()).or_insert(0) += 1;
+        VARS.iter().position(|v| *v == name).map(|i| vars[i])
+    };
+    instr.eval(slab, &mut ns).unwrap();
+    counts
+}
+
+#[test]
+fn preserves_results() {
+    let inputs = rand_inputs(500);
+    let mut total = CseReport::default();
+    for expr_str in CORPUS {
+        let mut slab = Slab::new();
+        let plain = compile(expr_str, &mut slab).unwrap();
+        let mut cse_slab = Slab::new();
+        let (instr, report) = cse(expr_str, &mut cse_slab);
+        for vars in &inputs {
+            let want = eval(&plain, &slab, vars).unwrap();
+            let got = eval(&instr, &cse_slab, vars).unwrap();
+            assert!(
+                got.to_bits() == want.to_bits() || (got.is_nan() && want.is_nan()),
+                "{} with {:?}: cse={:?} plain={:?}",
+                expr_str,
+                vars,
+                got,
+                want
+            );
+        }
+        total.temps += report.temps;
+        total.eliminated += report.eliminated;
+    }
+    assert!(total.temps > 10);
+    assert!(total.eliminated > total.temps);
+}
+
+#[test]
+fn fewer_lookups() {
+    let vars = [5.0, 3.0, 0.5];
+    let mut slab = Slab::new();
+    let plain = compile("(x-y)*(x-y)*z", &mut slab).unwrap();
+    let counts = lookups(&plain, &slab, &vars);
+    assert_eq!(counts.values().sum::<usize>(), 5);
+
+    let mut slab = Slab::new();
+    let (instr, report) = cse("(x-y)*(x-y)*z", &mut slab);
+    assert_eq!(
+        report,
+        CseReport {
+            temps: 1,
+            eliminated: 4
+        }
+    );
+    let counts = lookups(&instr, &slab, &vars);
+    assert_eq!(
+        counts.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("x".to_string(), 1),
+            ("y".to_string(), 1),
+            ("z".to_string(), 1)
+        ]
+    );
+    assert_eq!(eval(&instr, &slab, &vars).unwrap(), 2.0);
+
+    // Custom functions are called once for each distinct set of arguments:
+    let mut slab = Slab::new();
+    let (instr, _) = cse("f(x) + f(x)*f(x) + f(y)", &mut slab);
+    let mut calls = Vec::new();
+    let mut ns = |name: &str, args: Vec<f64>| {
+        calls.push(args.clone());
+        match name {
+            "f" => Some(args[0] * 10.0),
+            _ => VARS.iter().position(|v| *v == name).map(|i| vars[i]),
+        }
+    };
+    assert_eq!(instr.eval(&slab, &mut ns).unwrap(), 2580.0);
+    assert_eq!(calls, vec![vec![], vec![5.0], vec![], vec![3.0]]);
+}
+
+#[test]
+fn report() {
+    // Nothing to share:
+    let mut slab = Slab::new();
+    let (instr, report) = cse("x * y + z", &mut slab);
+    assert_eq!(report, CseReport::default());
+    assert_eq!(
+        format!("{:?}", instr),
+        "IAdd(InstructionI(2), I(InstructionI(3)))"
+    );
+    let mut slab = Slab::new();
+    let (instr, report) = cse("3", &mut slab);
+    assert_eq!(report, CseReport::default());
+    assert_eq!(instr, Instruction::IConst(3.0));
+
+    // Nested duplicates are shared at the outermost level only:
+    let mut slab = Slab::new();
+    let (_, report) = cse("sin(x*y) + sin(x*y)", &mut slab);
+    assert_eq!(
+        report,
+        CseReport {
+            temps: 1,
+            eliminated: 4
+        }
+    );
+
+    // ...unless they are used on their own, too:
+    let mut slab = Slab::new();
+    let (_, report) = cse("sin(x*y) + sin(x*y) + x*y", &mut slab);
+    assert_eq!(
+        report,
+        CseReport {
+            temps: 2,
+            eliminated: 7
+        }
+    );
+
+    // Constants are never worth a temporary:
+    let mut slab = Slab::new();
+    let (_, report) = cse("x*2 + y*2", &mut slab);
+    assert_eq!(report, CseReport::default());
+}
+
+#[test]
+fn lazy() {
+    // A shared sub-expression is computed at its first use, so nothing is
+    // looked up in a branch that isn't taken:
+    let mut slab = Slab::new();
+    let (instr, report) = cse("piecewise(x < 0, sqrt(w) + sqrt(w), x)", &mut slab);
+    assert_eq!(report.temps, 2);
+    assert_eq!(eval(&instr, &slab, &[1.0, 0.0, 0.0]).unwrap(), 1.0);
+    assert_eq!(
+        eval(&instr, &slab, &[-1.0, 0.0, 0.0]),
+        Err(Error::UndefinedVariable {
+            name: "w".to_string()
+        })
+    );
+
+    let mut slab = Slab::new();
+    let (instr, _) = cse("x > 0 && w/x + w/x", &mut slab);
+    assert_eq!(eval(&instr, &slab, &[0.0, 0.0, 0.0]).unwrap(), 0.0);
+    assert_eq!(lookups(&instr, &slab, &[0.0, 0.0, 0.0]).len(), 1);
+}
+
+#[test]
+fn impure() {
+    // Each rand() is a different value, so only `x` is shared:
+    let mut slab = Slab::new();
+    let (instr, report) = cse("rand() - rand() + (x+rand())*(x+rand())", &mut slab);
+    assert_eq!(
+        report,
+        CseReport {
+            temps: 1,
+            eliminated: 1
+        }
+    );
+    let mut plain_slab = Slab::new();
+    let plain = compile("rand() - rand() + (x+rand())*(x+rand())", &mut plain_slab).unwrap();
+    let x = |name: &str, _: Vec<f64>| match name {
+        "x" => Some(1.0),
+        _ => None,
+    };
+    for seed in 0..20 {
+        let got = instr.eval(&slab, &mut EvalNS::new(x).with_rng(seed));
+        let want = plain.eval(&plain_slab, &mut EvalNS::new(x).with_rng(seed));
+        assert_eq!(got, want);
+    }
+
+    // ...but the pure parts around it can still be shared:
+    let mut slab = Slab::new();
+    let (_, report) = cse("rand()*(x+y) + randint(1, 6)*(x+y)", &mut slab);
+    assert_eq!(report.temps, 1);
+
+    // print() is called for every use:
+    let mut slab = Slab::new();
+    let (_, report) = cse(r#"print("a", x) + print("a", x)"#, &mut slab);
+    assert_eq!(report, CseReport::default());
+}
+
+#[test]
+fn loops() {
+    // The body of a sum() depends on the loop variable:
+    let mut slab = Slab::new();
+    let (instr, report) = cse("sum(i, 1, 3, (i+x)*(i+x))", &mut slab);
+    assert_eq!(report, CseReport::default());
+    assert_eq!(eval(&instr, &slab, &[1.0, 0.0, 0.0]).unwrap(), 29.0);
+
+    // An outer `i` is a different variable:
+    let mut slab = Slab::new();
+    let (instr, report) = cse("sum(i, 1, 3, i*2) + i*2 + i*2", &mut slab);
+    assert_eq!(report.temps, 1);
+    let mut ns = |name: &str, _: Vec<f64>| match name {
+        "i" => Some(10.0),
+        _ => None,
+    };
+    assert_eq!(instr.eval(&slab, &mut ns).unwrap(), 52.0);
+
+    // Whole loops can be shared:
+    let mut slab = Slab::new();
+    let (instr, report) = cse("sum(i, 1, x, i) * sum(i, 1, x, i)", &mut slab);
+    assert_eq!(report.temps, 1);
+    let counts = lookups(&instr, &slab, &[4.0, 0.0, 0.0]);
+    assert_eq!(counts["x"], 1);
+    assert_eq!(eval(&instr, &slab, &[4.0, 0.0, 0.0]).unwrap(), 100.0);
+}
+
+#[test]
+fn bytecode() {
+    let vars = [5.0, 3.0, 0.5];
+    let mut slab = Slab::new();
+    let (instr, _) = cse("(x-y)*(x-y)*z + sin(x-y)", &mut slab);
+    let want = eval(&instr, &slab, &vars).unwrap();
+    let compiled = CompiledExpression::new(instr, slab).unwrap();
+    let decoded = CompiledExpression::from_bytes(&compiled.to_bytes().unwrap()).unwrap();
+    let got = eval(decoded.instr(), decoded.slab(), &vars).unwrap();
+    assert_eq!(got, want);
+    assert_eq!(lookups(decoded.instr(), decoded.slab(), &vars).len(), 3);
+    assert_eq!(
+        lookups(decoded.instr(), decoded.slab(), &vars)
+            .values()
+            .sum::<usize>(),
+        3
+    );
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit() {
+    let inputs = rand_inputs(100);
+    for expr_str in CORPUS {
+        let mut slab = Slab::new();
+        let (instr, _) = cse(expr_str, &mut slab);
+        let want = inputs
+            .iter()
+            .map(|vars| eval(&instr, &slab, vars).unwrap())
+            .collect::<Vec<_>>();
+        let jit = fasteval2::JitExpression::new(instr, slab, &VARS).unwrap();
+        for (vars, want) in inputs.iter().zip(want) {
+            let got = jit.eval(vars);
+            assert!(
+                got == want || (got.is_nan() && want.is_nan()),
+                "{} with {:?}: jit={:?} eval={:?}",
+                expr_str,
+                vars,
+                got,
+                want
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use fasteval2::compiler::Instruction::{self, IAdd, IConst, IFuncLet, INeg, IVar};
+use fasteval2::compiler::IC;
+use fasteval2::{
+    CompiledExpression, Compiler, EmptyNamespace, Error, EvalNS, Evaler, Parser, Slab,
+};
+
+use std::collections::BTreeMap;
+
+// Builds an expression that is nested `levels` deep, which the Parser would
+// reject (and the recursive evaluator would overflow the stack on).  Every 4
+// levels negate the value, so the result is `x` when `levels` is a multiple of 8.
+fn deep(levels: usize) -> (Instruction, Slab) {
+    let mut slab = Slab::new();
+    let mut instr = IVar("x".to_string());
+    for level in 0..levels {
+        let i = slab.cs.push_instr(instr);
+        instr = match level % 4 {
+            0 => IAdd(i, IC::C(1.0)),
+            1 => INeg(i),
+            2 => IAdd(i, IC::C(-1.0)),
+            _ => IFuncLet {
+                var: "t".to_string(),
+                value: IC::I(i),
+                expr: IC::I(slab.cs.push_instr(IVar("t".to_string()))),
+            },
+        };
+    }
+    (instr, slab)
+}
+
+#[test]
+fn deep_instructions() {
+    let (instr, slab) = deep(100_000);
+    let mut ns = BTreeMap::from([("x".to_string(), 2.5)]);
+    assert_eq!(instr.eval(&slab, &mut ns), Ok(2.5));
+    assert_eq!(
+        instr.var_names(&slab).into_iter().collect::<Vec<_>>(),
+        ["x"]
+    );
+
+    let compiled = CompiledExpression::new(instr, slab).unwrap();
+    assert_eq!(compiled.eval(&mut ns), Ok(2.5));
+    assert_eq!(compiled.stack_depth(), 0);
+    let mut stack = Vec::new();
+    assert_eq!(compiled.eval_with_stack(&mut stack, &mut ns), Ok(2.5));
+    assert_eq!(
+        compiled.eval(&mut EmptyNamespace),
+        Err(Error::UndefinedVariable {
+            name: "x".to_string()
+        })
+    );
+
+    // One line per Instruction:
+    let listing = compiled.to_string();
+    assert_eq!(listing.lines().count(), 125_001);
+
+    // Loading still limits the depth, because the compiler's passes recurse:
+    assert_eq!(
+        CompiledExpression::from_bytes(&compiled.to_bytes().unwrap()).err(),
+        Some(Error::Bytecode("too deep".to_string()))
+    );
+}
+
+#[test]
+fn small_stack() {
+    // Few Instructions, but too deep to recurse on a small stack in an
+    // unoptimized build:
+    let val = std::thread::Builder::new()
+        .stack_size(1024 * 1024)
+        .spawn(|| {
+            let (instr, slab) = deep(400);
+            let mut ns = BTreeMap::from([("x".to_string(), 2.5)]);
+            instr.eval(&slab, &mut ns)
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(val, Ok(2.5));
+}
+
+// Compiles `expr_str`, after an unused chain of `padding` nested Instructions,
+// so that deep paddings take the non-recursive evaluator.
+fn compile(expr_str: &str, padding: usize, cse: bool) -> (Instruction, Slab) {
+    let mut slab = Slab::new();
+    let mut i = slab.cs.push_instr(IConst(0.0));
+    for _ in 1..padding {
+        i = slab.cs.push_instr(INeg(i));
+    }
+    let instr = Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    if cse {
+        let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+        (instr, slab)
+    } else {
+        (instr, slab)
+    }
+}
+
+// `y` is null, with `with_null()`:
+const NULL: f64 = -4.0;
+
+fn ns_cb(name: &str, args: Vec<f64>) -> Option<f64> {
+    match (name, args.as_slice()) {
+        ("x", []) => Some(2.5),
+        ("y", []) => Some(-4.0),
+        ("n", []) => Some(f64::NAN),
+        ("f", [a, b]) => Some(a * 10.0 + b),
+        _ => None,
+    }
+}
+
+#[test]
+fn same_as_recursive() {
+    for expr_str in [
+        "x + y * 2 - 3 / x % 2 ^ 3",
+        "-x + !y - +(x)",
+        "x < y || x >= 1 && y != 2 || x == y",
+        "0 && undefined || 1 || undefined",
+        "(x-y)*(x-y) + sqrt(x)*sqrt(x) + x*x",
+        "sin(x) + cos(y) + tan(x) + asin(0.5) + acos(0.5) + atan(x) + abs(y) + sign(y)",
+        "sinh(x) + cosh(x) + tanh(x) + asinh(x) + acosh(x) + atanh(0.5) + sqrt(x)",
+        "int(x) + ceil(x) + floor(y) + round(x) + round(0.5, x) + log(x) + log(2, x) + e() + pi()",
+        "min(x, y, n) + max(x, n, y) + min(n) + fma(x, y, 2)",
+        "piecewise(x < 0, 1, y < 0, 2, 3) + piecewise(x > 10, 1, 4)",
+        "sum(i, 1, 10, i * x) + sum(i, 1, 3, sum(j, i, 3, i * j + x))",
+        "sum(i, 10, 1, i) + sum(i, 1, n, i)",
+        "(let t = x * 2; t + t * y) + (let t = 1; let u = t + 1; t * u)",
+        "interp(x, 0,0, 10,100) + interp(y, 0,1, 1,interp(x, 0,0, 5,5))",
+        "norm(x, y) + coalesce(n, x) + f(x, y) + f(1, 2) * f(x, 2)",
+        "quotient(x, y) + remainder(y, x) + quotient(7, n)",
+        "undefined + 1",
+        "g(1) * 2",
+        "x ^ 0 + 0 ^ 0 + (1 ^ 0.5) % 0",
+        "sum(i, 1, 1000000000, i)",
+    ] {
+        for cse in [false, true] {
+            let (want_instr, want_slab) = compile(expr_str, 0, cse);
+            let (got_instr, got_slab) = compile(expr_str, 2000, cse);
+            for null in [false, true] {
+                let run = |instr: &Instruction, slab: &Slab, stable: bool| {
+                    let mut ns = EvalNS::new(ns_cb).with_budget(10_000);
+                    if null {
+                        ns = ns.with_null(NULL);
+                    }
+                    if stable {
+                        ns = ns.with_stable();
+                    }
+                    let val = instr.eval(slab, &mut ns).map(f64::to_bits);
+                    (val, ns.used())
+                };
+                assert_eq!(
+                    run(&got_instr, &got_slab, false),
+                    run(&want_instr, &want_slab, false),
+                    "{} (cse: {}, null: {})",
+                    expr_str,
+                    cse,
+                    null
+                );
+                // Stable Namespaces memoize, which saves `tick()`s:
+                let (want, _) = run(&want_instr, &want_slab, false);
+                assert_eq!(run(&want_instr, &want_slab, true).0, want, "{}", expr_str);
+            }
+            assert_eq!(
+                got_instr.var_names(&got_slab),
+                want_instr.var_names(&want_slab),
+                "{}",
+                expr_str
+            );
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use fasteval2::{CompiledExpression, Compiler, EmptyNamespace, Operand, Parser, Slab};
+
+fn compile(expr_str: &str) -> CompiledExpression {
+    CompiledExpression::compile(expr_str, &mut EmptyNamespace).unwrap()
+}
+
+fn compile_cse(expr_str: &str) -> CompiledExpression {
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    CompiledExpression::new(instr, slab).unwrap()
+}
+
+#[test]
+fn listing() {
+    assert_eq!(
+        compile("sin(x) * (1 + 1) + y").to_string(),
+        "0: var x\n1: sin %0\n2: mul %1, 2\n3: var y\n4: add %2, %3"
+    );
+    assert_eq!(compile("1 + 2*3").to_string(), "0: const 7");
+    assert_eq!(
+        compile("f(x, 2) - sum(i, 1, 3, i) ^ 0.5").to_string(),
+        "0: var x\n1: call f, %0, 2\n2: var i\n3: sum i, 1, 3, %2\n4: pow %3, 0.5\n5: neg %4\n6: add %1, %5"
+    );
+    assert_eq!(
+        compile("interp_ext(x, 0,0, 1,10) < NaN").to_string(),
+        "0: var x\n1: interp_ext %0, 0, 0, 1, 10\n2: lt %1, NaN"
+    );
+
+    // Shared sub-expressions are listed once, and each use is a `temp`:
+    assert_eq!(
+        compile_cse("(x-y)*(x-y)").to_string(),
+        "0: var x\n1: var y\n2: neg %1\n3: add %0, %2\n4: temp $0, %3\n5: temp $0, %3\n6: mul %4, %5\n7: temps %6"
+    );
+}
+
+#[test]
+fn views() {
+    let compiled = compile("piecewise(x < 0, -x, x > 1, 1, x)");
+    let views: Vec<_> = compiled.instructions().collect();
+    for (i, view) in views.iter().enumerate() {
+        assert_eq!(view.index, i);
+        // Operands always come first:
+        for operand in &view.operands {
+            if let Operand::Child(c) = operand {
+                assert!(*c < i);
+            }
+        }
+    }
+    let root = views.last().unwrap();
+    assert_eq!(root.op, "piecewise");
+    assert_eq!(root.operands.len(), 5);
+    assert_eq!(root.operands[3], Operand::Const(1.0));
+    match root.operands[4] {
+        Operand::Child(c) => assert_eq!(views[c].operands, vec![Operand::Name("x")]),
+        ref operand => panic!("unexpected operand {:?}", operand),
+    }
+    assert_eq!(views[0].to_string(), "0: var x");
+
+    // Views survive a bytecode roundtrip:
+    let loaded = CompiledExpression::from_bytes(&compiled.to_bytes().unwrap()).unwrap();
+    assert_eq!(loaded.to_string(), compiled.to_string());
+}
+
+// Regression guards for constant folding and common subexpression elimination:
+#[test]
+fn counts() {
+    for (expr_str, max) in [
+        ("1 + 2 * 3 - 4 / 5", 1),
+        ("x + 2 * 3", 2),
+        ("sin(pi() / 2) * x", 2),
+        ("x * 2 * 3 * 4", 2),
+        ("max(1, 2, x)", 2),
+        ("interp(2, 0,0, 10,100) + x", 2),
+        ("sum(i, 1, 3, i) + x", 4),
+    ] {
+        let n = compile(expr_str).instructions().count();
+        assert!(n <= max, "{} compiled into {} instructions", expr_str, n);
+    }
+
+    let expr_str = "sqrt((x-y)*(x-y) + (x-y)) / sqrt((x-y)*(x-y) + (x-y))";
+    let plain = compile(expr_str).instructions().count();
+    let shared = compile_cse(expr_str).instructions().count();
+    assert!(shared < plain);
+    assert!(shared <= 19, "{}", compile_cse(expr_str));
+}
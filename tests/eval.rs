@@ -1,8 +1,12 @@
 use fasteval2::bool_to_f64;
-use fasteval2::{Cached, CachedCallbackNamespace, EmptyNamespace, Error, Evaler, Parser, Slab};
+use fasteval2::{
+    Arity, Cached, CachedCallbackNamespace, Compiler, EmptyNamespace, Error, EvalResult, Evaler,
+    Parser, Slab,
+};
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
+use std::ops::ControlFlow;
 
 #[test]
 fn eval() {
@@ -39,7 +43,9 @@ fn eval() {
             .unwrap()
             .from(&slab.ps)
             .eval(&slab, &mut ns),
-        Err(Error::Undefined("a".to_string()))
+        Err(Error::UndefinedVariable {
+            name: "a".to_string()
+        })
     );
 }
 
@@ -71,6 +77,8 @@ fn aaa_aab_single() {
 }
 
 #[test]
+// The expected values of e() and pi() are written out:
+#[allow(clippy::approx_constant)]
 fn aaa_basics() {
     let mut slab = Slab::new();
 
@@ -245,7 +253,9 @@ fn aaa_basics() {
             .unwrap()
             .from(&slab.ps)
             .eval(&slab, &mut ns),
-        Err(Error::Undefined("x".to_string()))
+        Err(Error::UndefinedVariable {
+            name: "x".to_string()
+        })
     );
 
     let mut ns = CachedCallbackNamespace::new(|_, _| Some(3.0));
@@ -312,7 +322,7 @@ fn aaa_basics() {
             .unwrap()
             .from(&slab.ps)
             .eval(&slab, &mut ns),
-        Ok(std::f64::NEG_INFINITY)
+        Ok(f64::NEG_INFINITY)
     );
     assert!(Parser::new()
         .parse("1.2 + log(-1)", &mut slab.ps)
@@ -484,9 +494,23 @@ fn aaa_basics() {
             .eval(&slab, &mut ns),
         Ok(0.6557942026326724)
     );
+
+    let ez = |s: &str| fasteval2::ez_eval(s, &mut EmptyNamespace);
+    assert_eq!(ez("tanh(0)"), Ok(0.0));
+    assert_eq!(ez("asin(1) == pi()/2"), Ok(1.0));
+    assert_eq!(ez("asinh(sinh(1.5))"), Ok(1.5));
+    assert_eq!(ez("acosh(cosh(1.5))"), Ok(1.5));
+    assert_eq!(ez("atanh(tanh(0.5))"), Ok(0.5));
+
+    // Arguments outside of a function's domain produce NaN, like sqrt(-1):
+    assert!(ez("sqrt(-1)").unwrap().is_nan());
+    assert!(ez("asin(2)").unwrap().is_nan());
+    assert!(ez("acos(-2)").unwrap().is_nan());
+    assert!(ez("acosh(0.5)").unwrap().is_nan());
+    assert!(ez("atanh(2)").unwrap().is_nan());
 }
 
-//// Commented out until we bring CachedLayeredNamespace back.
+// Commented out until we bring CachedLayeredNamespace back.
 // #[derive(Debug)]
 // struct TestEvaler;
 // impl Evaler for TestEvaler {
@@ -566,10 +590,8 @@ fn custom_func() {
             "x" => Some(1.0),
             "y" => Some(2.0),
             "z" => Some(3.0),
-            "foo" => Some(args.get(0).unwrap_or(&std::f64::NAN) * 10.0),
-            "bar" => {
-                Some(args.get(0).unwrap_or(&std::f64::NAN) + args.get(1).unwrap_or(&std::f64::NAN))
-            }
+            "foo" => Some(args.first().unwrap_or(&f64::NAN) * 10.0),
+            "bar" => Some(args.first().unwrap_or(&f64::NAN) + args.get(1).unwrap_or(&f64::NAN)),
             _ => None,
         }
     });
@@ -690,6 +712,1228 @@ fn custom_func() {
     );
 }
 
+#[test]
+fn unary_plus() {
+    let ez = |s: &str| fasteval2::ez_eval(s, &mut EmptyNamespace);
+    assert_eq!(ez("+3"), Ok(3.0));
+    assert_eq!(ez("+(-3)"), Ok(-3.0));
+    assert_eq!(ez("3 + +4"), Ok(7.0));
+    assert_eq!(ez("3 - +4"), Ok(-1.0));
+    assert_eq!(ez("+ + 3"), Ok(3.0));
+    assert_eq!(ez("-+3"), Ok(-3.0));
+    assert_eq!(
+        ez("+x"),
+        Err(Error::UndefinedVariable {
+            name: "x".to_string()
+        })
+    );
+
+    // Compiled unary plus is a no-op:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("+(1+2)", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(instr, fasteval2::IConst(3.0));
+}
+
+#[test]
+fn piecewise() {
+    fn price(q: f64) -> (Result<f64, Error>, Result<f64, Error>) {
+        let mut slab = Slab::new();
+        // 'boom' is undefined, so evaluating it would produce an error:
+        let mut ns = |name: &str, _: Vec<f64>| match name {
+            "q" => Some(q),
+            _ => None,
+        };
+        let expr = Parser::new()
+            .parse(
+                "piecewise(q < 10, q*5, q < 100, q*4, q < 1000, boom, q*3)",
+                &mut slab.ps,
+            )
+            .unwrap()
+            .from(&slab.ps);
+        let interpreted = expr.eval(&slab, &mut ns);
+        let compiled = expr.compile(&slab.ps, &mut slab.cs, &mut ns);
+        (interpreted, compiled.eval(&slab, &mut ns))
+    }
+
+    assert_eq!(price(2.0), (Ok(10.0), Ok(10.0)));
+    assert_eq!(price(50.0), (Ok(200.0), Ok(200.0)));
+    assert_eq!(price(2000.0), (Ok(6000.0), Ok(6000.0)));
+    assert_eq!(
+        price(500.0),
+        (
+            Err(Error::UndefinedVariable {
+                name: "boom".to_string()
+            }),
+            Err(Error::UndefinedVariable {
+                name: "boom".to_string()
+            })
+        )
+    );
+
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("piecewise(1, 2)", &mut slab.ps),
+        Err(Error::WrongArgCount {
+            name: "piecewise".to_string(),
+            expected: Arity::OddAtLeast(1),
+            got: 2
+        })
+    );
+    assert!(Parser::new().parse("piecewise()", &mut slab.ps).is_err());
+    assert_eq!(
+        fasteval2::ez_eval("piecewise(0, 1, 0, 2, 3)", &mut EmptyNamespace),
+        Ok(3.0)
+    );
+}
+
+#[test]
+fn if_func() {
+    // Counts how often each variable is read:
+    let reads = std::cell::RefCell::new(BTreeMap::<String, usize>::new());
+    let mut ns = |name: &str, _: Vec<f64>| {
+        *reads.borrow_mut().entry(name.to_string()).or_default() += 1;
+        match name {
+            "x" => Some(4.0),
+            "a" => Some(10.0),
+            "b" => Some(20.0),
+            _ => None,
+        }
+    };
+
+    for (expr_str, want, unread) in [
+        ("if(x > 2, a, b)", 10.0, "b"),
+        ("if(x > 5, a, b)", 20.0, "a"),
+        ("if(x, a + 1, boom)", 11.0, "boom"),
+        ("IF(0, boom, b * 2)", 40.0, "boom"),
+    ] {
+        let mut slab = Slab::new();
+        let parser = Parser::builder()
+            .case_insensitive_funcs(true)
+            .build()
+            .unwrap();
+        let expr = parser.parse(expr_str, &mut slab.ps).unwrap().from(&slab.ps);
+        let instr = expr.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        reads.borrow_mut().clear();
+        assert_eq!(expr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+        assert_eq!(instr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+        assert_eq!(reads.borrow().get(unread), None, "{}", expr_str);
+    }
+
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("if(1, 2)", &mut slab.ps),
+        Err(Error::WrongArgCount {
+            name: "if".to_string(),
+            expected: Arity::Exact(3),
+            got: 2
+        })
+    );
+}
+
+#[test]
+fn interp() {
+    fn chk(expr_str: &str, expect: Result<f64, Error>) {
+        let mut slab = Slab::new();
+        let mut ns = |name: &str, _: Vec<f64>| match name {
+            "x" => Some(15.0),
+            "bp" => Some(10.0),
+            _ => None,
+        };
+        let expr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        assert_eq!(expr.eval(&slab, &mut ns), expect, "{}", expr_str);
+        let instr = expr.compile(&slab.ps, &mut slab.cs, &mut ns);
+        assert_eq!(
+            instr.eval(&slab, &mut ns),
+            expect,
+            "{} (compiled)",
+            expr_str
+        );
+    }
+
+    // Exact breakpoints:
+    chk("interp(0, 0,0, 10,5, 20,30)", Ok(0.0));
+    chk("interp(10, 0,0, 10,5, 20,30)", Ok(5.0));
+    chk("interp(20, 0,0, 10,5, 20,30)", Ok(30.0));
+    // Midpoints:
+    chk("interp(5, 0,0, 10,5, 20,30)", Ok(2.5));
+    chk("interp(x, 0,0, 10,5, 20,30)", Ok(17.5));
+    chk("interp(x, 0,0, bp,5, 20,30)", Ok(17.5));
+    // Clamping and extrapolation:
+    chk("interp(-5, 0,0, 10,5, 20,30)", Ok(0.0));
+    chk("interp(25, 0,0, 10,5, 20,30)", Ok(30.0));
+    chk("interp_ext(-5, 0,0, 10,5, 20,30)", Ok(-2.5));
+    chk("interp_ext(25, 0,0, 10,5, 20,30)", Ok(42.5));
+    // Unsorted tables:
+    let unsorted = Err(Error::WrongArgs(
+        "interp: x-breakpoints must be strictly increasing".to_string(),
+    ));
+    chk("interp(5, 0,0, 20,30, 10,5)", unsorted.clone());
+    chk("interp(5, 0,0, 0,1)", unsorted.clone());
+    chk("interp(x, 0,0, bp,5, 5,30)", unsorted);
+    chk(
+        "interp_ext(5, 0,0, 0,1)",
+        Err(Error::WrongArgs(
+            "interp_ext: x-breakpoints must be strictly increasing".to_string(),
+        )),
+    );
+
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("interp(1, 0,0)", &mut slab.ps),
+        Err(Error::WrongArgCount {
+            name: "interp".to_string(),
+            expected: Arity::OddAtLeast(5),
+            got: 3
+        })
+    );
+    assert!(Parser::new()
+        .parse("interp_ext(1, 0,0, 1)", &mut slab.ps)
+        .is_err());
+}
+
+#[test]
+fn sum() {
+    let mut slab = Slab::new();
+    let mut ns = |name: &str, args: Vec<f64>| match (name, args.as_slice()) {
+        ("n", []) => Some(4.0),
+        ("i", []) => Some(100.0),
+        ("sum", [a, b]) => Some(a + b), // A custom function with a different shape.
+        _ => None,
+    };
+    for (expr_str, expect) in [
+        ("sum(i, 1, 4, i^2)", 30.0),
+        ("sum(i, 1, n, i) + i", 110.0),
+        ("sum(i, 1, 3, sum(j, 1, i, j))", 10.0),
+        ("sum(i, 5, 1, i)", 0.0),
+        ("sum(i, 0.5, 2, i)", 2.0),
+        ("sum(1, 2)", 3.0),
+    ] {
+        let expr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        assert_eq!(expr.eval(&slab, &mut ns), Ok(expect), "{}", expr_str);
+        let instr = expr.compile(&slab.ps, &mut slab.cs, &mut ns);
+        assert_eq!(
+            instr.eval(&slab, &mut ns),
+            Ok(expect),
+            "{} (compiled)",
+            expr_str
+        );
+    }
+
+    let expr = Parser::new()
+        .parse("sum(i, a, 10, i*b) + i", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr.var_names(&slab),
+        ["a", "b", "i"].iter().map(|s| s.to_string()).collect()
+    );
+    assert_eq!(
+        fasteval2::ez_eval("sum(i, 0, 1e16, i)", &mut ns),
+        Err(Error::WrongArgs(
+            "sum: bounds must be smaller than 2^53".to_string()
+        ))
+    );
+}
+
+#[test]
+fn let_bindings() {
+    let mut slab = Slab::new();
+    let mut ns = |name: &str, args: Vec<f64>| match (name, args.as_slice()) {
+        ("x", []) => Some(3.0),
+        ("y", []) => Some(4.0),
+        ("r", []) => Some(100.0),
+        ("let", []) => Some(5.0),
+        _ => None,
+    };
+    for (expr_str, expect) in [
+        ("let r = sqrt(x*x + y*y); r * 2", 10.0),
+        ("let x = x + 1; x * y", 16.0),
+        ("let a = 2; let b = a * 3; a + b", 8.0),
+        ("let r = 1; let r = r + 1; r", 2.0),
+        ("(let r = 2; r) + r", 102.0),
+        ("1 + let r = y; r * 2", 9.0),
+        ("max(let r = x; r * r, 1)", 9.0),
+        ("sum(i, 1, 3, let j = i * 2; j)", 12.0),
+        ("let + 1", 6.0), // Still a variable name.
+        ("let==5", 1.0),
+    ] {
+        let expr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        assert_eq!(expr.eval(&slab, &mut ns), Ok(expect), "{}", expr_str);
+        let instr = expr.compile(&slab.ps, &mut slab.cs, &mut ns);
+        assert_eq!(
+            instr.eval(&slab, &mut ns),
+            Ok(expect),
+            "{} (compiled)",
+            expr_str
+        );
+    }
+
+    let expr = Parser::new()
+        .parse("let r = a; r * b + x", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr.var_names(&slab),
+        ["a", "b", "x"].iter().map(|s| s.to_string()).collect()
+    );
+    for (expr_str, err) in [
+        ("let r = 1 r", Error::Expected("';'".to_string())),
+        ("let r = 1", Error::EofWhileParsing("let".to_string())),
+        ("let r = 1;", Error::EofWhileParsing("value".to_string())),
+        (
+            "let 2 = 3; 1",
+            Error::UnparsedTokensRemaining("2 = 3; 1".to_string()),
+        ),
+    ] {
+        assert_eq!(
+            Parser::new().parse(expr_str, &mut slab.ps),
+            Err(err),
+            "{}",
+            expr_str
+        );
+    }
+}
+
+#[test]
+fn for_each_var() {
+    let mut slab = Slab::new();
+    let expr = Parser::new()
+        .parse(
+            "a + f(b, -c) * sum(i, a, 3, i * d + let e = i; e + g) - print(\"x\", h) + a",
+            &mut slab.ps,
+        )
+        .unwrap()
+        .from(&slab.ps);
+
+    let mut visited = Vec::new();
+    let flow = expr.for_each_var(&slab, &mut |name| {
+        visited.push(name.to_string());
+        ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    assert_eq!(visited, ["a", "f", "b", "c", "a", "d", "g", "h", "a"]);
+    assert_eq!(
+        visited.into_iter().collect::<BTreeSet<_>>(),
+        expr.var_names(&slab)
+    );
+
+    // Breaking stops the walk:
+    let mut count = 0;
+    let flow = expr.for_each_var(&slab, &mut |name| {
+        count += 1;
+        if name == "c" {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!((flow, count), (ControlFlow::Break(()), 4));
+}
+
+#[test]
+fn eval_grid() {
+    let mut slab = Slab::new();
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "k" => Some(100.0),
+        "f" => args.first().map(|a| a * 2.0),
+        _ => None,
+    };
+    let instr = Parser::new()
+        .parse("k*y + f(x)", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut ns);
+
+    let (xs, ys) = ([0.0, 1.0, 2.0], [1.0, 2.0]);
+    let mut out = [0.0; 6];
+    instr
+        .eval_grid(&slab, &mut ns, "x", &xs, "y", &ys, &mut out)
+        .unwrap();
+    assert_eq!(out, [100.0, 102.0, 104.0, 200.0, 202.0, 204.0]);
+
+    // Same as the naive double loop:
+    for (yi, &y) in ys.iter().enumerate() {
+        for (xi, &x) in xs.iter().enumerate() {
+            let mut cell_ns = |name: &str, args: Vec<f64>| match name {
+                "x" => Some(x),
+                "y" => Some(y),
+                _ => ns(name, args),
+            };
+            assert_eq!(instr.eval(&slab, &mut cell_ns), Ok(out[yi * xs.len() + xi]));
+        }
+    }
+
+    // Constant expressions fill the whole grid:
+    let instr = Parser::new()
+        .parse("1+2", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut ns);
+    instr
+        .eval_grid(&slab, &mut ns, "x", &xs, "y", &ys, &mut out)
+        .unwrap();
+    assert_eq!(out, [3.0; 6]);
+
+    // Size mismatches and evaluation errors:
+    let mut short = [0.0; 5];
+    assert_eq!(
+        instr.eval_grid(&slab, &mut ns, "x", &xs, "y", &ys, &mut short),
+        Err(Error::WrongArgs(
+            "eval_grid: expected an output of 3*2 values, but got 5".to_string()
+        ))
+    );
+    assert_eq!(short, [0.0; 5]);
+    let instr = Parser::new()
+        .parse("x + z", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut ns);
+    assert_eq!(
+        instr.eval_grid(&slab, &mut ns, "x", &xs, "y", &ys, &mut out),
+        Err(Error::UndefinedVariable {
+            name: "z".to_string()
+        })
+    );
+    assert_eq!(
+        instr.eval_grid(&slab, &mut ns, "x", &[], "y", &ys, &mut []),
+        Ok(())
+    );
+}
+
+#[test]
+fn var_count() {
+    let mut slab = Slab::new();
+    let mut count = |s: &str| {
+        Parser::new()
+            .parse(s, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .var_count(&slab)
+    };
+    assert_eq!(count("a+b+c"), 3);
+    assert_eq!(count("a+a"), 1);
+    assert_eq!(count("1+2"), 0);
+    assert_eq!(count("f(a, b) * -(a + c)"), 4);
+    assert_eq!(count("sum(i, 1, n, i*x)"), 2);
+
+    let expr_ref = Parser::new()
+        .parse("a+b+c", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(expr_ref.validate_var_limit(&slab, 3), Ok(()));
+    assert_eq!(
+        expr_ref.validate_var_limit(&slab, 2),
+        Err(Error::TooManyVars(3))
+    );
+}
+
+#[test]
+fn is_constant() {
+    let mut slab = Slab::new();
+    let mut is_constant = |s: &str| {
+        Parser::new()
+            .parse(s, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .is_constant(&slab)
+    };
+    assert!(is_constant("2+3"));
+    assert!(is_constant("-(1.5 * pi()) ^ 2 > abs(-3) || e()"));
+    assert!(is_constant("max(1, 2, 3) + piecewise(1 < 2, 4, 5)"));
+    assert!(is_constant("sum(i, 1, 10, i^2)"));
+    assert!(is_constant(r#"print("a", 1)"#));
+
+    assert!(!is_constant("x+1"));
+    assert!(!is_constant("2 + 3*x"));
+    assert!(!is_constant("phi"));
+    assert!(!is_constant("f(1)"));
+    assert!(!is_constant("sum(i, 1, n, i)"));
+    assert!(!is_constant("sum(i, 1, 10, i*x)"));
+    assert!(!is_constant("piecewise(1 < 2, 4, x)"));
+    assert!(!is_constant("rand()"));
+    assert!(!is_constant("randint(1, 6)"));
+    assert!(!is_constant("1 + rand()*0"));
+}
+
+#[test]
+fn max_depth() {
+    let mut slab = Slab::new();
+    let mut max_depth = |s: &str| {
+        Parser::new()
+            .parse(s, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .max_depth(&slab)
+    };
+    assert_eq!(max_depth("(a+b)*(c+d)"), 3);
+    assert_eq!(max_depth("((a+b)*(c+d)) * ((e+f)*(g+h))"), 4);
+    assert_eq!(max_depth("1"), 1);
+    assert_eq!(max_depth("-(-a)"), 1);
+    assert_eq!(max_depth("sin(-(a+b))"), 2);
+
+    // Precedence and grouping:
+    assert_eq!(max_depth("a*b + c"), 2);
+    assert_eq!(max_depth("a + b*c"), 3);
+    assert_eq!(max_depth("a - b - c"), 2);
+    assert_eq!(max_depth("a + b + c"), 3); // a + (b + c), like eval().
+    assert_eq!(max_depth("2^3^4"), 3);
+    assert_eq!(max_depth("a < b && c"), 2);
+
+    // Functions:
+    assert_eq!(max_depth("f()"), 1);
+    assert_eq!(max_depth("f(a, b+c, d)"), 3);
+    assert_eq!(max_depth("max(a, b+c) + log(2, a)"), 3);
+    assert_eq!(max_depth("interp(x, 0,0, 1,1)"), 5);
+    assert_eq!(max_depth("piecewise(a < b, c, d)"), 2);
+    assert_eq!(max_depth("sum(i, 1, 10, i*x)"), 4);
+    assert_eq!(max_depth(r#"print("v", a, b*c)"#), 3);
+}
+
+#[test]
+fn eval_trace() {
+    let mut slab = Slab::new();
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 3.0);
+    let mut trace = |s: &str| -> (f64, Vec<String>) {
+        let expr_ref = Parser::new().parse(s, &mut slab.ps).unwrap().from(&slab.ps);
+        let (val, steps) = expr_ref.eval_trace(&slab, &mut ns).unwrap();
+        assert_eq!(Ok(val), expr_ref.eval(&slab, &mut ns));
+        (val, steps.iter().map(|step| step.to_string()).collect())
+    };
+
+    assert_eq!(
+        trace("2+3*4"),
+        (
+            14.0,
+            vec!["3 * 4 = 12".to_string(), "2 + 12 = 14".to_string()]
+        )
+    );
+    assert_eq!(
+        trace("-(1+2)*x ^ 2 < 0"),
+        (
+            1.0,
+            vec![
+                "1 + 2 = 3".to_string(),
+                "3 ^ 2 = 9".to_string(),
+                "-3 * 9 = -27".to_string(),
+                "-27 < 0 = 1".to_string(),
+            ]
+        )
+    );
+    assert_eq!(trace("max(1+1, x)"), (3.0, vec![]));
+    assert_eq!(trace("5"), (5.0, vec![]));
+
+    let expr_ref = Parser::new()
+        .parse("1 + y", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr_ref.eval_trace(&slab, &mut ns),
+        Err(Error::UndefinedVariable {
+            name: "y".to_string()
+        })
+    );
+    let expr_ref = Parser::new()
+        .parse("2 * 3", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr_ref.eval_trace(&slab, &mut ns),
+        Ok((
+            6.0,
+            vec![fasteval2::TraceStep {
+                op: fasteval2::parser::BinaryOp::EMul,
+                left: 2.0,
+                right: 3.0,
+                result: 6.0
+            }]
+        ))
+    );
+}
+
+#[test]
+fn operator_grouping() {
+    // Groups a chain of operators with parentheses, one pass per precedence
+    // level like the original evaluator did, so that eval() of the result
+    // doesn't depend on how eval() resolves precedence:
+    const PASSES: [(&[&str], bool); 9] = [
+        (&["^"], true),
+        (&["%"], false),
+        (&["/"], false),
+        (&["*"], true),
+        (&["-"], false),
+        (&["+"], true),
+        (&["<", "<=", "==", "!=", ">=", ">"], false),
+        (&["&&"], false),
+        (&["||"], false),
+    ];
+    fn reduce(vals: &mut Vec<String>, ops: &mut Vec<&str>, i: usize) {
+        vals[i] = format!("({} {} {})", vals[i], ops[i], vals[i + 1]);
+        vals.remove(i + 1);
+        ops.remove(i);
+    }
+    fn grouped(mut vals: Vec<String>, mut ops: Vec<&str>) -> String {
+        for (search, rtol) in PASSES {
+            if rtol {
+                for i in (0..ops.len()).rev() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut vals, &mut ops, i);
+                    }
+                }
+            } else {
+                let mut i = 0;
+                while i < ops.len() {
+                    if search.contains(&ops[i]) {
+                        reduce(&mut vals, &mut ops, i);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+        vals.remove(0)
+    }
+
+    let parser = Parser::builder()
+        .max_depth(1000)
+        .max_len(100_000)
+        .build()
+        .unwrap();
+    let mut slab = Slab::with_capacity(1000);
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 0.1);
+    ns.insert("y".to_string(), -3.7);
+    let mut eval = |s: &str| -> f64 {
+        let expr_ref = parser.parse(s, &mut slab.ps).unwrap().from(&slab.ps);
+        let (val, steps) = expr_ref.eval_trace(&slab, &mut ns).unwrap();
+        assert_eq!(
+            expr_ref.eval(&slab, &mut ns).map(f64::to_bits),
+            Ok(val.to_bits())
+        );
+        assert_eq!(steps.len(), s.matches(' ').count() / 2);
+        val
+    };
+
+    // Mostly arithmetic, which is where rounding shows the grouping:
+    let all_ops = [
+        "^", "%", "/", "*", "-", "+", "%", "/", "*", "-", "+", "/", "*", "-", "+", "<", "<=", "==",
+        "!=", ">=", ">", "&&", "||",
+    ];
+    let terms = ["x", "y", "2", "0.3", "1.7", "3.3", "7", "1e3", "0"];
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut rand = |n: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % n as u64) as usize
+    };
+    // Chains where the grouping changes the rounding, then random ones with up
+    // to 80 operators, more than eval() keeps without allocating:
+    let long_sum = vec!["x"; 40].join(" + ");
+    let fixed = [
+        long_sum.as_str(),
+        "x * x * y",
+        "x + x + 1.7",
+        "x * x * y / 0.3 / 1.7",
+        "1.7 - x - x + 0.3 * 3.3 * y",
+        "2 ^ 0.3 ^ 1.7 ^ x",
+    ];
+    let random = (0..500).map(|i| {
+        let len = i % 81;
+        let mut flat = terms[rand(terms.len())].to_string();
+        for _ in 0..len {
+            let op = all_ops[rand(all_ops.len())];
+            flat = format!("{} {} {}", flat, op, terms[rand(terms.len())]);
+        }
+        flat
+    });
+    for flat in fixed.iter().map(|s| s.to_string()).chain(random) {
+        let toks: Vec<&str> = flat.split(' ').collect();
+        let vals = toks.iter().step_by(2).map(|s| s.to_string()).collect();
+        let ops = toks.iter().skip(1).step_by(2).copied().collect();
+        let want = eval(&grouped(vals, ops));
+        let got = eval(&flat);
+        assert!(
+            got.to_bits() == want.to_bits() || got.is_nan() && want.is_nan(),
+            "{}: {} != {}",
+            flat,
+            got,
+            want
+        );
+    }
+}
+
+#[test]
+fn named_constants() {
+    assert!((fasteval2::ez_eval("phi", &mut EmptyNamespace).unwrap() - 1.618).abs() < 1e-3);
+    assert_eq!(
+        fasteval2::ez_eval("phi^2 - phi - 1", &mut EmptyNamespace).map(|x| x.abs() < 1e-15),
+        Ok(true)
+    );
+    assert!(
+        (fasteval2::ez_eval("sqrt2 * sqrt2", &mut EmptyNamespace).unwrap() - 2.0).abs() < 1e-15
+    );
+    assert_eq!(
+        fasteval2::ez_eval("ln2", &mut EmptyNamespace),
+        Ok(std::f64::consts::LN_2)
+    );
+    assert_eq!(
+        fasteval2::ez_eval("ln10", &mut EmptyNamespace),
+        Ok(std::f64::consts::LN_10)
+    );
+
+    // The namespace overrides named constants:
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("phi".to_string(), 0.5);
+    assert_eq!(
+        fasteval2::ez_eval("phi + ln2", &mut ns),
+        Ok(0.5 + std::f64::consts::LN_2)
+    );
+
+    // Compiled, too:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("phi * sqrt2", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(
+        instr.eval(&slab, &mut ns),
+        Ok(0.5 * std::f64::consts::SQRT_2)
+    );
+
+    // Only the bare names are constants:
+    assert_eq!(
+        fasteval2::ez_eval("phi()", &mut EmptyNamespace),
+        Err(Error::UndefinedFunction {
+            name: "phi".to_string(),
+            arg_count: 0
+        })
+    );
+    assert_eq!(
+        fasteval2::ez_eval("sqrt3", &mut EmptyNamespace),
+        Err(Error::UndefinedVariable {
+            name: "sqrt3".to_string()
+        })
+    );
+}
+
+#[test]
+fn bool_literals() {
+    assert_eq!(
+        fasteval2::ez_eval("true && false", &mut EmptyNamespace),
+        Ok(0.0)
+    );
+    assert_eq!(
+        fasteval2::ez_eval("true || false", &mut EmptyNamespace),
+        Ok(1.0)
+    );
+    assert_eq!(fasteval2::ez_eval("true", &mut EmptyNamespace), Ok(1.0));
+    assert_eq!(
+        fasteval2::ez_eval("!false + !true", &mut EmptyNamespace),
+        Ok(1.0)
+    );
+    assert_eq!(
+        fasteval2::ez_eval("(3 > 1) == true && (3 < 1) == false", &mut EmptyNamespace),
+        Ok(1.0)
+    );
+    assert_eq!(
+        fasteval2::ez_eval("piecewise(false, 10, true, 20, 30)", &mut EmptyNamespace),
+        Ok(20.0)
+    );
+
+    // Compiled, too:
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("true && false || true", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(instr.eval(&slab, &mut EmptyNamespace), Ok(1.0));
+
+    // The namespace overrides them:
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("true".to_string(), 5.0);
+    assert_eq!(fasteval2::ez_eval("true + false", &mut ns), Ok(5.0));
+    assert_eq!(instr.eval(&slab, &mut ns), Ok(5.0));
+
+    assert_eq!(
+        fasteval2::ez_eval("true()", &mut EmptyNamespace),
+        Err(Error::UndefinedFunction {
+            name: "true".to_string(),
+            arg_count: 0
+        })
+    );
+}
+
+#[test]
+fn fma() {
+    // (1 + 2^-52) * (1 - 2^-52) == 1 - 2^-104, which rounds to 1 before the addition:
+    let (a, b) = ("1.0000000000000002", "0.9999999999999998");
+    let naive = format!("{} * {} - 1", a, b);
+    let fused = format!("fma({}, {}, -1)", a, b);
+    assert_eq!(fasteval2::ez_eval(&naive, &mut EmptyNamespace), Ok(0.0));
+    assert_eq!(
+        fasteval2::ez_eval(&fused, &mut EmptyNamespace),
+        Ok(-(2.0f64.powi(-104)))
+    );
+
+    // Compiled, both constant-folded and with variables:
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("a".to_string(), 1.0 + f64::EPSILON);
+    ns.insert("b".to_string(), 1.0 - f64::EPSILON);
+    for expr_str in [fused.as_str(), "fma(a, b, -1)", "fma(a, 1 - 2^-52, -1)"] {
+        let mut slab = Slab::new();
+        let instr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        assert_eq!(instr.eval(&slab, &mut ns), Ok(-(2.0f64.powi(-104))));
+    }
+
+    assert_eq!(
+        fasteval2::ez_eval("fma(2, 3, 4) + fma(-1, 1e308, inf)", &mut EmptyNamespace),
+        Ok(f64::INFINITY)
+    );
+    assert_eq!(
+        fasteval2::ez_eval("fma(1, 2)", &mut EmptyNamespace),
+        Err(Error::WrongArgCount {
+            name: "fma".to_string(),
+            expected: Arity::Exact(3),
+            got: 2
+        })
+    );
+}
+
+#[test]
+fn coalesce() {
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 3.0);
+    ns.insert("nan".to_string(), f64::NAN);
+    for (expr_str, want) in [
+        ("coalesce(NaN, 5)", 5.0),
+        ("coalesce(nan, 5)", 5.0),
+        ("coalesce(3, 5)", 3.0),
+        ("coalesce(x, 5)", 3.0),
+        ("coalesce(inf, 5) + coalesce(-inf, 5)", 10.0),
+        ("coalesce(sqrt(-1), x) + coalesce(-0.0, x)", 3.0),
+        ("coalesce(x / 0, coalesce(nan, -1))", -1.0),
+    ] {
+        assert_eq!(
+            fasteval2::ez_eval(expr_str, &mut ns),
+            Ok(want),
+            "{}",
+            expr_str
+        );
+
+        // Compiled, both constant-folded and with variables:
+        let mut slab = Slab::new();
+        let instr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        assert_eq!(instr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+    }
+
+    // The fallback can itself be NaN:
+    assert!(fasteval2::ez_eval("coalesce(nan, nan)", &mut ns)
+        .unwrap()
+        .is_nan());
+    assert_eq!(
+        fasteval2::ez_eval("coalesce(1)", &mut ns),
+        Err(Error::WrongArgCount {
+            name: "coalesce".to_string(),
+            expected: Arity::Exact(2),
+            got: 1
+        })
+    );
+}
+
+#[test]
+fn quotient_remainder() {
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 7.0);
+    ns.insert("y".to_string(), 2.0);
+    for (expr_str, want) in [
+        ("quotient(7, 2)", 3.0),
+        ("remainder(7, 2)", 1.0),
+        ("quotient(x, y)", 3.0),
+        ("remainder(x, y)", 1.0),
+        ("quotient(-7, 2)", -3.0),
+        ("remainder(-7, 2)", -1.0),
+        ("quotient(7, -2)", -3.0),
+        ("remainder(7, -2)", 1.0),
+        ("quotient(x, 0.5) + remainder(x, 0.5)", 14.0),
+        ("quotient(5.5, y) + remainder(5.5, y)", 3.5),
+        ("quotient(x, inf) + remainder(x, inf)", 7.0),
+    ] {
+        let mut slab = Slab::new();
+        let expr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        assert_eq!(expr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+
+        // Compiled, both constant-folded and with variables:
+        let instr = expr.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        assert_eq!(instr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+    }
+
+    // They agree with '%', and with each other:
+    for (a, b) in [
+        (7.5, 2.0),
+        (-7.5, 2.0),
+        (7.5, -2.0),
+        (-7.5, -2.0),
+        (1e300, 3.0),
+    ] {
+        ns.insert("x".to_string(), a);
+        ns.insert("y".to_string(), b);
+        assert_eq!(
+            fasteval2::ez_eval("remainder(x, y)", &mut ns),
+            fasteval2::ez_eval("x % y", &mut ns)
+        );
+        let q = fasteval2::ez_eval("quotient(x, y)", &mut ns).unwrap();
+        assert_eq!(q, (a / b).trunc(), "{} {}", a, b);
+        assert_eq!(q * b + a % b, a, "{} {}", a, b);
+    }
+
+    for expr_str in ["quotient(x, 0)", "remainder(x, 0)", "quotient(inf, y)"] {
+        assert!(fasteval2::ez_eval(expr_str, &mut ns).unwrap().is_nan());
+    }
+    assert_eq!(
+        fasteval2::ez_eval("quotient(1)", &mut ns),
+        Err(Error::WrongArgCount {
+            name: "quotient".to_string(),
+            expected: Arity::Exact(2),
+            got: 1
+        })
+    );
+}
+
+#[test]
+fn sumsq_norm() {
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 3.0);
+    ns.insert("y".to_string(), -4.0);
+    for (expr_str, want) in [
+        ("sumsq(3, 4)", 25.0),
+        ("norm(3, 4)", 5.0),
+        ("sumsq(x, y) + sumsq(x)", 34.0),
+        ("norm(x, y) * norm(1, 2, 2)", 15.0),
+        ("norm(y)", 4.0),
+        ("norm(-1e200)", 1e200),
+        ("sumsq(x, 2 * y, norm(x, y))", 98.0),
+    ] {
+        assert_eq!(
+            fasteval2::ez_eval(expr_str, &mut ns),
+            Ok(want),
+            "{}",
+            expr_str
+        );
+
+        // Compiled, both constant-folded and with variables:
+        let mut slab = Slab::new();
+        let instr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        assert_eq!(instr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+    }
+
+    for name in ["sumsq", "norm"] {
+        assert_eq!(
+            fasteval2::ez_eval(&format!("{}()", name), &mut ns),
+            Err(Error::WrongArgCount {
+                name: name.to_string(),
+                expected: Arity::AtLeast(1),
+                got: 0
+            })
+        );
+    }
+}
+
+#[test]
+fn membership() {
+    // Counts how often each variable is read:
+    let reads = std::cell::RefCell::new(BTreeMap::<String, usize>::new());
+    let mut ns = |name: &str, _: Vec<f64>| {
+        *reads.borrow_mut().entry(name.to_string()).or_default() += 1;
+        match name {
+            "x" => Some(4.0),
+            "a" => Some(2.0),
+            "b" => Some(4.0),
+            _ => None,
+        }
+    };
+
+    for (expr_str, want, unread) in [
+        ("x in (1, 2, 4)", 1.0, ""),
+        ("x in (1, 2, 3)", 0.0, ""),
+        ("x not in (1, 2, 3)", 1.0, ""),
+        ("x not in (a, b, boom)", 0.0, "boom"),
+        ("x in (a * 2, boom)", 1.0, "boom"),
+        ("x + 1 in (5) && x in (a)", 0.0, ""),
+        ("0 && x in (0)", 0.0, ""),
+        ("1 || x in (3)", 1.0, ""),
+        ("x in (3) or x in (4)", 1.0, ""),
+        ("(x in (4)) * 10 + 1", 11.0, ""),
+    ] {
+        let mut slab = Slab::new();
+        let expr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        let instr = expr.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        reads.borrow_mut().clear();
+        assert_eq!(expr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+        assert_eq!(instr.eval(&slab, &mut ns), Ok(want), "{}", expr_str);
+        assert_eq!(reads.borrow().get(unread), None, "{}", expr_str);
+    }
+
+    // 'in' is only a keyword before a list:
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("in".to_string(), 3.0);
+    assert_eq!(fasteval2::ez_eval("in + 1", &mut ns), Ok(4.0));
+
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("x in ()", &mut slab.ps),
+        Err(Error::Expected("a value in the list of 'in'".to_string()))
+    );
+}
+
+#[test]
+fn undefined_names() {
+    fn undefined_fn(name: &str, arg_count: usize) -> Error {
+        Error::UndefinedFunction {
+            name: name.to_string(),
+            arg_count,
+        }
+    }
+    let var = Error::UndefinedVariable {
+        name: "f".to_string(),
+    };
+
+    // The same name as a variable and as a function, in either order:
+    let mut slab = Slab::new();
+    for (expr_str, want) in [
+        ("f + f(1, 2)", var.clone()),
+        ("f(1, 2) + f", undefined_fn("f", 2)),
+        ("1 + f()", undefined_fn("f", 0)),
+    ] {
+        let expr_i = Parser::new().parse(expr_str, &mut slab.ps).unwrap();
+        let expr_ref = expr_i.from(&slab.ps);
+        assert_eq!(
+            expr_ref.eval(&slab, &mut EmptyNamespace),
+            Err(want.clone()),
+            "{}",
+            expr_str
+        );
+        let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        assert_eq!(
+            instr.eval(&slab, &mut EmptyNamespace),
+            Err(want.clone()),
+            "{}",
+            expr_str
+        );
+        assert_eq!(
+            fasteval2::IntEvaler::eval_i64(expr_i.from(&slab.ps), &slab, &mut EmptyNamespace),
+            Err(want),
+            "{}",
+            expr_str
+        );
+    }
+
+    // The name is owned by the error, so it outlives the source string:
+    let err = {
+        let expr_str = String::from("sqrt(2) * typo_d(3)");
+        fasteval2::ez_eval(&expr_str, &mut EmptyNamespace).unwrap_err()
+    };
+    match err {
+        Error::UndefinedFunction { name, arg_count } => {
+            assert_eq!((name.as_str(), arg_count), ("typo_d", 1))
+        }
+        other => panic!("{:?}", other),
+    }
+
+    // A namespace that only has variables still reports functions as functions:
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("f".to_string(), 1.0);
+    assert_eq!(
+        fasteval2::ez_eval("f + f(1, 2)", &mut ns),
+        Err(undefined_fn("f", 2))
+    );
+}
+
+// Compile-time check that the closures can be shared between threads:
+fn assert_send_sync<T: Send + Sync + 'static>(_: &T) {}
+
+#[test]
+fn to_fn() {
+    let vars = ["x", "y", "z"];
+    let inputs = [
+        [0.0, 0.0, 0.0],
+        [-0.0, 1.0, -1.0],
+        [0.5, 2.0, 3.0],
+        [-7.25, 3.5, 1e10],
+        [f64::NAN, 1.0, 2.0],
+        [f64::INFINITY, -1.0, 0.25],
+    ];
+    for expr_str in [
+        "x + y * z - 3",
+        "x / y % z ^ 2",
+        "-x + !y",
+        "x < y || y >= z && x != z",
+        "x == y or y > z and x <= 1",
+        "int(x) + ceil(y) + floor(z) + abs(x) + sign(y)",
+        "log(y) + log(2, z) + round(x) + round(0.5, y)",
+        "min(x, y, z) + max(x, 1)",
+        "piecewise(x < 0, -1, x > y, 1, z)",
+        "sum(i, 1, 4, i * x) + sum(i, y, min(z, 5), sum(j, 1, i, j))",
+        "sum(x, 1, 3, x) + x",
+        "interp(x, 0,0, 10,y) + interp_ext(y, 0,z, 1,1)",
+        "sin(x) + cos(y) + tan(z) + asin(x) + acos(y) + atan(z)",
+        "sinh(x) + cosh(y) + tanh(z) + asinh(x) + acosh(y) + atanh(z)",
+        "sqrt(z) + fma(x, y, z) + phi * pi() + e()",
+        "x()",
+        "1 + 2",
+    ] {
+        let mut slab = Slab::new();
+        let expr_ref = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        let f = expr_ref.to_fn(&slab, &vars).unwrap();
+        assert_send_sync(&f);
+        let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        for vals in &inputs {
+            let mut ns =
+                |name: &str, _: Vec<f64>| vars.iter().position(|v| *v == name).map(|i| vals[i]);
+            // The closure is built from the compiled form, which can round differently
+            // from the parsed form (for example, `x/y` is compiled to `x * 1/y`):
+            let want = instr.eval(&slab, &mut ns).unwrap();
+            let got = f(vals);
+            assert!(
+                got.to_bits() == want.to_bits() || (got.is_nan() && want.is_nan()),
+                "{} with {:?}: {} != {}",
+                expr_str,
+                vals,
+                got,
+                want
+            );
+        }
+    }
+
+    let mut slab = Slab::new();
+    let mut build = |expr_str: &str, vars: &[&str]| {
+        let expr_ref = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        expr_ref.to_fn(&slab, vars)
+    };
+
+    // Slots are positional, and missing values produce NaN:
+    let f = build("a - b", &["b", "a"]).unwrap();
+    assert_eq!(f(&[1.0, 10.0]), 9.0);
+    assert_eq!(f(&[1.0, 10.0, 100.0]), 9.0);
+    assert!(f(&[1.0]).is_nan());
+
+    // Unknown names fail at build time, not at call time:
+    assert_eq!(
+        build("x + w", &vars).err(),
+        Some(Error::UndefinedVariable {
+            name: "w".to_string()
+        })
+    );
+    assert_eq!(
+        build("f(x)", &vars).err(),
+        Some(Error::UndefinedFunction {
+            name: "f".to_string(),
+            arg_count: 1
+        })
+    );
+    assert_eq!(
+        build("sum(i, 1, 3, w)", &vars).err(),
+        Some(Error::UndefinedVariable {
+            name: "w".to_string()
+        })
+    );
+    assert!(build(r#"print("x")"#, &vars).is_err());
+
+    // The closure can be moved to other threads:
+    let f = build("x * y", &vars).unwrap();
+    let results: Vec<f64> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let f = &f;
+                s.spawn(move || f(&[i as f64, 2.0, 0.0]))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(results, [0.0, 2.0, 4.0, 6.0]);
+}
+
+#[test]
+fn eval_typed() {
+    let mut slab = Slab::new();
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 2.0);
+    let mut eval = |s: &str| {
+        let expr_ref = Parser::new().parse(s, &mut slab.ps).unwrap().from(&slab.ps);
+        expr_ref.eval_typed(&slab, &mut ns)
+    };
+
+    assert_eq!(eval("5 > 3"), Ok(EvalResult::Bool(true)));
+    assert_eq!(eval("5 + 3"), Ok(EvalResult::Number(8.0)));
+    assert_eq!(eval("x + 1 == 3"), Ok(EvalResult::Bool(true)));
+    assert_eq!(eval("(x < 1)"), Ok(EvalResult::Bool(false)));
+    assert_eq!(eval("!x"), Ok(EvalResult::Bool(false)));
+    assert_eq!(eval("x > 1 && x < 3"), Ok(EvalResult::Bool(true)));
+    assert_eq!(eval("1 > 2 || !0"), Ok(EvalResult::Bool(true)));
+    assert_eq!(
+        eval("piecewise(x > 1, x < 1, 1 == 1)"),
+        Ok(EvalResult::Bool(false))
+    );
+    // Comparisons that are only operands are numbers:
+    assert_eq!(eval("(5 > 3) + 1"), Ok(EvalResult::Number(2.0)));
+    assert_eq!(eval("-(5 > 3)"), Ok(EvalResult::Number(-1.0)));
+    assert_eq!(eval("0 || x"), Ok(EvalResult::Number(2.0)));
+    assert_eq!(
+        eval("piecewise(x > 1, 1 < 2, 5)"),
+        Ok(EvalResult::Number(1.0))
+    );
+    assert_eq!(eval("max(1 < 2, 0)"), Ok(EvalResult::Number(1.0)));
+    assert_eq!(eval("1"), Ok(EvalResult::Number(1.0)));
+    assert_eq!(
+        eval("y > 1"),
+        Err(Error::UndefinedVariable {
+            name: "y".to_string()
+        })
+    );
+
+    assert_eq!(EvalResult::Bool(true).to_string(), "true");
+    assert_eq!(EvalResult::Number(1.5).to_string(), "1.5");
+}
+
 #[test]
 #[cfg(feature = "unsafe-vars")]
 fn unsafe_var() {
@@ -726,3 +1970,48 @@ fn unsafe_var() {
 
     let _ = (ua, ub); // Silence compiler warnings about variables not being read.
 }
+
+#[test]
+#[cfg(feature = "unsafe-vars")]
+fn unsafe_var_ptr() {
+    use std::cell::{Cell, RefCell};
+
+    // A frame of samples that is rewritten between evaluations:
+    let frame = [Cell::new(0.0), Cell::new(0.0)];
+    let mut slab = Slab::new();
+    unsafe {
+        slab.ps
+            .add_unsafe_var_ptr("l".to_string(), frame[0].as_ptr());
+        slab.ps
+            .add_unsafe_var_ptr("r".to_string(), frame[1].as_ptr());
+    }
+
+    // Unsafe Variables can be mixed with namespace variables and functions,
+    // and are never looked up in the namespace:
+    let lookups = RefCell::new(Vec::new());
+    let mut ns = |name: &str, args: Vec<f64>| {
+        lookups.borrow_mut().push(name.to_string());
+        match name {
+            "gain" => Some(0.5),
+            "clip" => Some(args[0].clamp(-1.0, 1.0)),
+            _ => None,
+        }
+    };
+    let expr_ref = Parser::new()
+        .parse("clip(gain * (l + r)) + l*0", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+
+    let mut out = Vec::new();
+    for (l, r) in [(0.25, 0.75), (2.0, 3.0), (-4.0, 1.0)] {
+        frame[0].set(l);
+        frame[1].set(r);
+        out.push(instr.eval(&slab, &mut ns).unwrap());
+        assert_eq!(expr_ref.eval(&slab, &mut ns), Ok(*out.last().unwrap()));
+    }
+    assert_eq!(out, vec![0.5, 1.0, -1.0]);
+    let lookups = lookups.into_inner();
+    assert_eq!(lookups.len(), 12);
+    assert!(lookups.iter().all(|name| name == "gain" || name == "clip"));
+}
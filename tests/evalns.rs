@@ -1,4 +1,12 @@
-use fasteval2::ez_eval;
+use fasteval2::{
+    eval_compiled_ref, ez_eval, Arity, Compiler, Context, ContextNamespace, EmptyNamespace, Error,
+    EvalNS, EvalNSBuilder, Evaler, FixedClock, IntEvaler, ModMode, Optimizer, Parser, Slab,
+    ZeroPowZero,
+};
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 #[test]
 fn empty() {
@@ -74,6 +82,29 @@ fn layered_str_to_f64() {
     assert_eq!(val, 4.33);
 }
 
+#[test]
+fn str_to_f64_pairs() {
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse("a*b", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr_ref.eval_pairs(&slab, &[("a", 2.0), ("b", 3.0)]),
+        Ok(6.0)
+    );
+    assert_eq!(
+        expr_ref.eval_pairs(&slab, &[("a", 2.0)]),
+        Err(Error::UndefinedVariable {
+            name: "b".to_string()
+        })
+    );
+
+    let mut ns: fasteval2::StrToF64PairsNamespace = &[("a", 1.11), ("b", 2.22)];
+    let val = ez_eval("a + b + 1", &mut ns).unwrap();
+    assert_eq!(val, 4.33);
+}
+
 #[test]
 fn cb() {
     let mut ns = |name: &str, args: Vec<f64>| match name {
@@ -128,15 +159,945 @@ fn custom_vector_funcs() {
     ns.insert(
         "vec_sum",
         Box::new(|args| {
-            if let Some(index) = args.get(0) {
+            if let Some(index) = args.first() {
                 if let Some(v) = vecs_cell.borrow().get(*index as usize) {
                     return v.iter().sum();
                 }
             }
-            std::f64::NAN
+            f64::NAN
         }),
     );
 
     let val = ez_eval("vec_sum(vec_store(1.1, x, 3.3)) + vec_sum(0)", &mut ns).unwrap();
     assert_eq!(val, 12.8);
 }
+
+#[test]
+fn budget() {
+    let mut slab = Slab::new();
+    let mut ns = EvalNS::new(EmptyNamespace).with_budget(10_000);
+
+    let start = std::time::Instant::now();
+    let expr_ref = Parser::new()
+        .parse("sum(i, 1, 1e9, i)", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr_ref.eval(&slab, &mut ns),
+        Err(Error::BudgetExceeded(10_000))
+    );
+    ns.reset_budget();
+    let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut ns);
+    assert_eq!(
+        instr.eval(&slab, &mut ns),
+        Err(Error::BudgetExceeded(10_000))
+    );
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+    // Small evaluations fit within the budget, and the usage is reported:
+    ns.reset_budget();
+    assert_eq!(ez_eval("sum(i, 1, 10, i) + 1", &mut ns), Ok(56.0));
+    assert!(ns.used() > 10 && ns.used() < 100);
+
+    // Lookups are delegated:
+    let mut ns = EvalNS::new(|name: &str, _: Vec<f64>| match name {
+        "x" => Some(2.0),
+        _ => None,
+    })
+    .with_budget(3);
+    assert_eq!(ez_eval("x + x", &mut ns), Ok(4.0));
+    assert_eq!(ez_eval("x + x", &mut ns), Err(Error::BudgetExceeded(3)));
+
+    // Without a budget, there is no limit:
+    let mut ns = EvalNS::new(EmptyNamespace);
+    assert_eq!(ez_eval("sum(i, 1, 100000, 1)", &mut ns), Ok(100000.0));
+}
+
+#[test]
+fn deadline() {
+    // A deadline in the past trips immediately:
+    let mut ns = EvalNS::new(|_: &str, _: Vec<f64>| Some(1.0)).with_deadline(Instant::now());
+    assert_eq!(ez_eval("x + 1", &mut ns), Err(Error::Timeout));
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("x * 2", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(instr.eval(&slab, &mut ns), Err(Error::Timeout));
+
+    // A custom function that blocks is caught when it returns:
+    let mut ns = fasteval2::EvalNSBuilder::new()
+        .func("slow", |_| {
+            std::thread::sleep(Duration::from_millis(20));
+            Some(1.0)
+        })
+        .deadline(Instant::now() + Duration::from_millis(10))
+        .build();
+    assert_eq!(ez_eval("slow(1) + slow(2)", &mut ns), Err(Error::Timeout));
+
+    // Evaluations that finish in time aren't affected:
+    let mut ns =
+        EvalNS::new(EmptyNamespace).with_deadline(Instant::now() + Duration::from_secs(60));
+    assert_eq!(ez_eval("sum(i, 1, 10, i) + 1", &mut ns), Ok(56.0));
+}
+
+#[test]
+fn null() {
+    let none = f64::from_bits(0x7ff8_0000_0000_dead);
+    let mut ns = EvalNS::new(move |name: &str, _: Vec<f64>| match name {
+        "none" => Some(none),
+        "x" => Some(2.0),
+        _ => None,
+    })
+    .with_null(none);
+
+    let eval_both = |expr_str: &str, ns: &mut EvalNS<_>| {
+        let mut slab = Slab::new();
+        let expr = Parser::new().parse(expr_str, &mut slab.ps).unwrap();
+        let interpreted = expr.from(&slab.ps).eval(&slab, ns).unwrap();
+        let instr = expr
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        let compiled = instr.eval(&slab, ns).unwrap();
+        assert_eq!(
+            interpreted.to_bits(),
+            compiled.to_bits(),
+            "{}: {} != {}",
+            expr_str,
+            interpreted,
+            compiled
+        );
+        interpreted
+    };
+
+    for expr_str in [
+        "none + 5",
+        "5 - none",
+        "x * none / 2",
+        "2 ^ none % 3",
+        "-none",
+        "!none",
+        "none < 1",
+        "x == none",
+        "(none + 1) * 0",
+        "1 && none",
+        "0 || none",
+    ] {
+        let val = eval_both(expr_str, &mut ns);
+        assert!(ns.is_null(val), "{}", expr_str);
+    }
+    // `&&` and `||` just pick an operand, like they do with NaN:
+    assert_eq!(eval_both("0 && none", &mut ns), 0.0);
+    assert_eq!(eval_both("x || none", &mut ns), 2.0);
+    assert_eq!(eval_both("none && x", &mut ns), 2.0);
+    assert_eq!(eval_both("x + 5 < 10", &mut ns), 1.0);
+    // Other NaNs aren't null:
+    let val = eval_both("sqrt(x - 3) + 1", &mut ns);
+    assert!(val.is_nan() && !ns.is_null(val));
+    assert!(!ns.is_null(f64::NAN));
+
+    // Without a sentinel, the same value is an ordinary NaN:
+    let mut plain = EvalNS::new(move |_: &str, _: Vec<f64>| Some(none));
+    assert!(!plain.is_null(none));
+    assert_eq!(ez_eval("none < 1", &mut plain), Ok(0.0));
+
+    let mut ns = fasteval2::EvalNSBuilder::new()
+        .var("none", -1.0)
+        .null(-1.0)
+        .build();
+    assert_eq!(ez_eval("none * 3 + 4", &mut ns), Ok(-1.0));
+    assert_eq!(ez_eval("abs(none) * 3", &mut ns), Ok(3.0));
+}
+
+#[test]
+fn result_range() {
+    let mut ns = EvalNS::new(EmptyNamespace).with_result_range(0.0, f64::INFINITY);
+    assert_eq!(
+        ez_eval("-1", &mut ns),
+        Err(Error::OutOfRange {
+            value: -1.0,
+            min: 0.0,
+            max: f64::INFINITY
+        })
+    );
+    assert_eq!(ez_eval("0", &mut ns), Ok(0.0));
+    assert_eq!(ez_eval("1/0", &mut ns), Ok(f64::INFINITY));
+    // Only the result is checked, not the steps to it:
+    assert_eq!(ez_eval("(1 - 3) * -2", &mut ns), Ok(4.0));
+    assert!(matches!(
+        ez_eval("sqrt(-1)", &mut ns),
+        Err(Error::OutOfRange { value, .. }) if value.is_nan()
+    ));
+
+    let compiled = fasteval2::CompiledExpression::compile("x - 5", &mut EmptyNamespace).unwrap();
+    let mut ns = fasteval2::EvalNSBuilder::new()
+        .var("x", 7.0)
+        .result_range(0.0, 10.0)
+        .build();
+    assert_eq!(compiled.eval(&mut ns), Ok(2.0));
+    let mut ns = fasteval2::EvalNSBuilder::new()
+        .var("x", 20.0)
+        .result_range(0.0, 10.0)
+        .build();
+    assert_eq!(
+        compiled.eval(&mut ns),
+        Err(Error::OutOfRange {
+            value: 15.0,
+            min: 0.0,
+            max: 10.0
+        })
+    );
+    assert_eq!(
+        compiled.eval_with_stack(&mut Vec::new(), &mut ns),
+        Err(Error::OutOfRange {
+            value: 15.0,
+            min: 0.0,
+            max: 10.0
+        })
+    );
+
+    // Evaler::eval() checks the result of the whole Expression or Instruction,
+    // but not the sub-expressions on the way:
+    let out_of_range = |value: f64| {
+        Err(Error::OutOfRange {
+            value,
+            min: 0.0,
+            max: 10.0,
+        })
+    };
+    let mut slab = Slab::new();
+    let mut ns = EvalNSBuilder::new()
+        .var("x", 20.0)
+        .result_range(0.0, 10.0)
+        .build();
+    for (expr_str, result) in [
+        ("x - 15", Ok(5.0)),
+        ("(x - 30) * -1 - 8", Ok(2.0)),
+        ("max(x, 50) / 10", Ok(5.0)),
+        ("x", out_of_range(20.0)),
+        ("-(x - 15)", out_of_range(-5.0)),
+    ] {
+        let expr_ref = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        assert_eq!(expr_ref.eval(&slab, &mut ns), result, "{}", expr_str);
+        let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut ns);
+        assert_eq!(instr.eval(&slab, &mut ns), result, "{}", expr_str);
+    }
+    // Constant-folded Instructions too, with or without the macros:
+    let instr = Parser::new()
+        .parse("3 * 5", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut ns);
+    assert_eq!(instr.eval(&slab, &mut ns), out_of_range(15.0));
+    let mut macro_eval =
+        || -> Result<f64, Error> { Ok(eval_compiled_ref!(&instr, &slab, &mut ns)) };
+    assert_eq!(macro_eval(), out_of_range(15.0));
+
+    // Every cell of a grid:
+    let instr = Parser::new()
+        .parse("x - y", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let mut ns = EvalNS::new(EmptyNamespace).with_result_range(0.0, f64::INFINITY);
+    let mut out = [0.0; 2];
+    assert_eq!(
+        instr.eval_grid(&slab, &mut ns, "x", &[1.0, 2.0], "y", &[0.0], &mut out),
+        Ok(())
+    );
+    assert_eq!(out, [1.0, 2.0]);
+    assert_eq!(
+        instr.eval_grid(&slab, &mut ns, "x", &[1.0, 2.0], "y", &[5.0], &mut out),
+        Err(Error::OutOfRange {
+            value: -4.0,
+            min: 0.0,
+            max: f64::INFINITY
+        })
+    );
+    assert_eq!(out, [1.0, 2.0]);
+}
+
+// A Context with its own state: a memo table and a call counter.
+struct Memo {
+    cache: std::collections::BTreeMap<u64, f64>,
+    calls: usize,
+}
+impl Context for Memo {
+    fn get(&mut self, name: &str) -> Option<f64> {
+        match name {
+            "calls" => Some(self.calls as f64),
+            "x" => Some(3.0),
+            _ => None,
+        }
+    }
+    fn call(&mut self, name: &str, args: &[f64]) -> Option<Result<f64, Error>> {
+        match (name, args) {
+            ("slow_sq", [v]) => {
+                let calls = &mut self.calls;
+                Some(Ok(*self.cache.entry(v.to_bits()).or_insert_with(|| {
+                    *calls += 1;
+                    v * v
+                })))
+            }
+            ("slow_sq", _) => Some(Err(Error::WrongArgs(
+                "slow_sq: expected one arg".to_string(),
+            ))),
+            ("zero", []) => Some(Ok(0.0)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn zero_pow_zero() {
+    let vars = |name: &str, _: Vec<f64>| match name {
+        "zero" => Some(0.0),
+        "two" => Some(2.0),
+        _ => None,
+    };
+    for (mode, want) in [
+        (None, Ok(1.0)),
+        (Some(ZeroPowZero::One), Ok(1.0)),
+        (Some(ZeroPowZero::NaN), Ok(f64::NAN)),
+        (Some(ZeroPowZero::Error), Err(Error::ZeroPowZero)),
+    ] {
+        let ns = || match mode {
+            Some(mode) => EvalNS::new(vars).with_zero_pow_zero(mode),
+            None => EvalNS::new(vars),
+        };
+        for expr_str in [
+            "0^0",
+            "zero^zero",
+            "zero^0",
+            "-0^0",
+            "0^-0",
+            "2^0^0",
+            "1 + zero^(two-2)",
+        ] {
+            let want_here = match expr_str {
+                "2^0^0" => want.clone().map(|v| 2f64.powf(v)),
+                "1 + zero^(two-2)" => want.clone().map(|v| 1.0 + v),
+                _ => want.clone(),
+            };
+            let same = |got: Result<f64, Error>| match (&got, &want_here) {
+                (Ok(a), Ok(b)) => a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+                _ => got == want_here,
+            };
+            // Interpreted:
+            assert!(
+                same(ez_eval(expr_str, &mut ns())),
+                "{:?} {}",
+                mode,
+                expr_str
+            );
+
+            // Compiled (and optimized) with a Namespace that doesn't know the mode:
+            let mut slab = Slab::new();
+            let instr = Parser::new()
+                .parse(expr_str, &mut slab.ps)
+                .unwrap()
+                .from(&slab.ps)
+                .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+            let instr = Optimizer::new().optimize(instr, &mut slab.cs);
+            assert!(
+                same(instr.eval(&slab, &mut ns())),
+                "{:?} {}",
+                mode,
+                expr_str
+            );
+        }
+
+        // Other powers of zero, and zero powers, are unchanged:
+        assert_eq!(ez_eval("zero^2 + two^0 + 0^1", &mut ns()), Ok(1.0));
+        assert!(ez_eval("zero^-1", &mut ns()).unwrap().is_infinite());
+    }
+
+    let mut ns = EvalNSBuilder::new()
+        .var("x", 0.0)
+        .zero_pow_zero(ZeroPowZero::Error)
+        .build();
+    assert_eq!(ez_eval("x^x", &mut ns), Err(Error::ZeroPowZero));
+    assert_eq!(ez_eval("x^2", &mut ns), Ok(0.0));
+}
+
+#[test]
+fn mod_mode() {
+    let vars = |name: &str, _: Vec<f64>| match name {
+        "x" => Some(5.5),
+        "seven" => Some(7.0),
+        "zero" => Some(0.0),
+        _ => None,
+    };
+    let not_an_integer = |val: &str| Err(Error::NotAnInteger(val.to_string()));
+    for (expr_str, float, integer) in [
+        ("5.5 % 2", Ok(1.5), not_an_integer("5.5")),
+        ("x % 2", Ok(1.5), not_an_integer("5.5")),
+        ("seven % 2.5", Ok(2.0), not_an_integer("2.5")),
+        ("remainder(x, 2)", Ok(1.5), not_an_integer("5.5")),
+        ("7 % 2", Ok(1.0), Ok(1.0)),
+        ("-seven % 2", Ok(-1.0), Ok(-1.0)),
+        ("remainder(seven, -4)", Ok(3.0), Ok(3.0)),
+        ("(x + 0.5) % 4 % 4", Ok(2.0), Ok(2.0)),
+        ("seven % zero", Ok(f64::NAN), Err(Error::DivideByZero)),
+        ("quotient(5.5, 2)", Ok(2.0), not_an_integer("5.5")),
+        ("quotient(seven, x)", Ok(1.0), not_an_integer("5.5")),
+        ("quotient(7, 2)", Ok(3.0), Ok(3.0)),
+        ("quotient(-seven, 2)", Ok(-3.0), Ok(-3.0)),
+        ("quotient(7, 0)", Ok(f64::NAN), Err(Error::DivideByZero)),
+        (
+            "quotient(seven, zero)",
+            Ok(f64::NAN),
+            Err(Error::DivideByZero),
+        ),
+    ] {
+        for (mode, want) in [
+            (None, float.clone()),
+            (Some(ModMode::Float), float),
+            (Some(ModMode::Integer), integer),
+        ] {
+            let ns = || match mode {
+                Some(mode) => EvalNS::new(vars).with_mod_mode(mode),
+                None => EvalNS::new(vars),
+            };
+            let same = |got: Result<f64, Error>| match (&got, &want) {
+                (Ok(a), Ok(b)) => a == b || (a.is_nan() && b.is_nan()),
+                _ => got == want,
+            };
+            // Interpreted:
+            assert!(
+                same(ez_eval(expr_str, &mut ns())),
+                "{:?} {}",
+                mode,
+                expr_str
+            );
+
+            // Compiled with a Namespace that doesn't know the mode:
+            let mut slab = Slab::new();
+            let instr = Parser::new()
+                .parse(expr_str, &mut slab.ps)
+                .unwrap()
+                .from(&slab.ps)
+                .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+            assert!(
+                same(instr.eval(&slab, &mut ns())),
+                "{:?} {}",
+                mode,
+                expr_str
+            );
+        }
+    }
+
+    let mut ns = EvalNSBuilder::new()
+        .var("x", 5.5)
+        .mod_mode(ModMode::Integer)
+        .build();
+    assert_eq!(ez_eval("x % 2", &mut ns), not_an_integer("5.5"));
+    assert_eq!(ez_eval("int(x) % 2", &mut ns), Ok(1.0));
+
+    // quotient() is truncated like `%` in every mode, so they add up:
+    for mode in [ModMode::Float, ModMode::Integer] {
+        for (a, b) in [(7.0, 2.0), (-7.0, 2.0), (7.0, -2.0), (-7.0, -2.0)] {
+            let mut ns = EvalNSBuilder::new()
+                .var("a", a)
+                .var("b", b)
+                .mod_mode(mode)
+                .build();
+            assert_eq!(ez_eval("quotient(a, b)", &mut ns), Ok((a / b).trunc()));
+            assert_eq!(ez_eval("quotient(a, b) * b + a % b", &mut ns), Ok(a));
+        }
+    }
+}
+
+#[test]
+fn context() {
+    let mut ns = EvalNS::from_context(Memo {
+        cache: Default::default(),
+        calls: 0,
+    });
+    assert_eq!(
+        ez_eval("slow_sq(x) + slow_sq(3) + zero()", &mut ns),
+        Ok(18.0)
+    );
+    assert_eq!(ez_eval("calls", &mut ns), Ok(1.0));
+    assert_eq!(ns.inner().inner().cache.len(), 1);
+    assert_eq!(
+        ez_eval("y", &mut ns),
+        Err(Error::UndefinedVariable {
+            name: "y".to_string()
+        })
+    );
+
+    // Errors from call() abort the evaluation, in every evaluator:
+    let err = Err(Error::WrongArgs("slow_sq: expected one arg".to_string()));
+    assert_eq!(ez_eval("1 + slow_sq(1, 2)", &mut ns), err);
+    assert_eq!(ez_eval("sum(i, 1, 3, slow_sq(i, i))", &mut ns), err);
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse("slow_sq(x, x) * 2", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(expr_ref.eval(&slab, &mut ns), err);
+    assert_eq!(
+        expr_ref.eval_i64(&slab, &mut ns),
+        err.clone().map(|v: f64| v as i64)
+    );
+    let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut ns);
+    assert_eq!(instr.eval(&slab, &mut ns), err);
+    let mut out = [0.0; 2];
+    assert_eq!(
+        instr.eval_grid(&slab, &mut ns, "a", &[1.0, 2.0], "b", &[1.0], &mut out),
+        err.map(|_| ())
+    );
+
+    // Budgets still apply:
+    let mut ns = EvalNS::from_context(Memo {
+        cache: Default::default(),
+        calls: 0,
+    })
+    .with_budget(5);
+    assert_eq!(
+        ez_eval("sum(i, 1, 100, slow_sq(i))", &mut ns),
+        Err(Error::BudgetExceeded(5))
+    );
+
+    // Closures are Contexts too:
+    let mut ns = ContextNamespace::new(|name: &str, args: Vec<f64>| match name {
+        "x" => Some(2.0),
+        "double" => args.first().map(|v| v * 2.0),
+        _ => None,
+    });
+    assert_eq!(ez_eval("double(x) + x", &mut ns), Ok(6.0));
+    assert_eq!(
+        ez_eval("double()", &mut ns),
+        Err(Error::UndefinedFunction {
+            name: "double".to_string(),
+            arg_count: 0
+        })
+    );
+}
+
+#[test]
+fn rng() {
+    fn draws(seed: u64, expr_str: &str, n: usize) -> Vec<f64> {
+        let mut ns = EvalNS::new(EmptyNamespace).with_rng(seed);
+        (0..n)
+            .map(|_| ez_eval(expr_str, &mut ns).unwrap())
+            .collect()
+    }
+
+    // The same seed produces the same sequence:
+    let a = draws(7, "rand()", 1000);
+    assert_eq!(a, draws(7, "rand()", 1000));
+    assert_ne!(a, draws(8, "rand()", 1000));
+    assert!(a.iter().all(|v| (0.0..1.0).contains(v)));
+    let mean = a.iter().sum::<f64>() / a.len() as f64;
+    assert!((mean - 0.5).abs() < 0.05, "{}", mean);
+
+    let rolls = draws(7, "randint(1, 6)", 1000);
+    assert_eq!(rolls, draws(7, "randint(1, 6)", 1000));
+    for face in 1..=6 {
+        let n = rolls.iter().filter(|v| **v == face as f64).count();
+        assert!(n > 100, "{} came up {} times", face, n);
+    }
+    assert!(rolls.iter().all(|v| (1.0..=6.0).contains(v)));
+    // Bounds are rounded inwards:
+    assert!(draws(1, "randint(-2.5, 0.5)", 100)
+        .iter()
+        .all(|v| [-2.0, -1.0, 0.0].contains(v)));
+    assert_eq!(draws(1, "randint(3, 3)", 3), [3.0, 3.0, 3.0]);
+
+    // Each call advances the generator, in evaluation order:
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(7);
+    let sum = ez_eval("rand() + rand()", &mut ns).unwrap();
+    assert_eq!(sum, a[0] + a[1]);
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(7);
+    assert_eq!(
+        ez_eval("sum(i, 1, 10, rand())", &mut ns),
+        Ok(a[..10].iter().sum())
+    );
+
+    // The compiled form isn't constant-folded, even when compiled with the
+    // random Namespace itself:
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(7);
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("rand() + randint(1, 6) * 0", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut ns);
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(7);
+    let vals = (0..4)
+        .map(|_| instr.eval(&slab, &mut ns).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vals, [a[0], a[2], a[4], a[6]]);
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse("randint(1, 6)", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(7);
+    assert_eq!(expr_ref.eval_i64(&slab, &mut ns), Ok(rolls[0] as i64));
+
+    // Errors:
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(7);
+    assert_eq!(
+        ez_eval("rand(1)", &mut ns),
+        Err(Error::WrongArgCount {
+            name: "rand".to_string(),
+            expected: Arity::Exact(0),
+            got: 1
+        })
+    );
+    assert_eq!(
+        ez_eval("randint(1)", &mut ns),
+        Err(Error::WrongArgCount {
+            name: "randint".to_string(),
+            expected: Arity::Exact(2),
+            got: 1
+        })
+    );
+    assert_eq!(
+        ez_eval("randint(6, 1)", &mut ns),
+        Err(Error::WrongArgs("randint: the range is empty".to_string()))
+    );
+    assert_eq!(
+        ez_eval("randint(0.2, 0.8)", &mut ns),
+        Err(Error::WrongArgs("randint: the range is empty".to_string()))
+    );
+    assert_eq!(
+        ez_eval("randint(0, 1e300)", &mut ns),
+        Err(Error::WrongArgs(
+            "randint: the range is too large".to_string()
+        ))
+    );
+
+    // Without an RNG, the names are passed through like any other:
+    let mut ns = EvalNS::new(EmptyNamespace);
+    assert_eq!(
+        ez_eval("rand()", &mut ns),
+        Err(Error::UndefinedFunction {
+            name: "rand".to_string(),
+            arg_count: 0
+        })
+    );
+    let mut ns = EvalNS::new(|name: &str, _: Vec<f64>| match name {
+        "rand" => Some(4.0), // chosen by fair dice roll
+        _ => None,
+    });
+    assert_eq!(ez_eval("rand()", &mut ns), Ok(4.0));
+}
+
+#[test]
+fn clock() {
+    // Monday, 2024-03-04 09:30:15 UTC:
+    let t = 1709544615.0;
+    let mut ns = EvalNS::new(EmptyNamespace).with_clock(FixedClock(t));
+    let mut eval = |expr_str: &str| ez_eval(expr_str, &mut ns);
+    assert_eq!(eval("now()"), Ok(t));
+    assert_eq!(eval("hour(now())"), Ok(9.0));
+    assert_eq!(eval("minute(now())"), Ok(30.0));
+    assert_eq!(eval("dow(now())"), Ok(1.0));
+    assert_eq!(eval("date(2024, 3, 4)"), Ok(1709510400.0));
+    assert_eq!(eval("now() - date(2024, 3, 4) > 3600"), Ok(1.0));
+
+    assert_eq!(eval("date(1970, 1, 1)"), Ok(0.0));
+    assert_eq!(eval("date(2000, 2, 29)"), Ok(951782400.0));
+    assert_eq!(eval("date(2100, 12, 31)"), Ok(4133894400.0));
+    assert_eq!(eval("date(1969, 12, 31)"), Ok(-86400.0));
+    assert_eq!(eval("date(1600, 3, 1)"), Ok(-11670912000.0));
+    // Times before 1970 count backwards:
+    assert_eq!(eval("hour(-1)"), Ok(23.0));
+    assert_eq!(eval("minute(-1)"), Ok(59.0));
+    assert_eq!(eval("dow(-1)"), Ok(3.0)); // Wednesday
+    assert_eq!(eval("dow(date(2024, 3, 10))"), Ok(0.0)); // Sunday
+    assert_eq!(eval("dow(date(2024, 3, 9) + 86399)"), Ok(6.0)); // Saturday
+
+    assert_eq!(
+        eval("date(2023, 2, 29)"),
+        Err(Error::WrongArgs(
+            "date: 2023-02-29 is not a valid date".to_string()
+        ))
+    );
+    assert_eq!(
+        eval("date(2024, 13, 1)"),
+        Err(Error::WrongArgs(
+            "date: 2024-13-01 is not a valid date".to_string()
+        ))
+    );
+    assert_eq!(
+        eval("date(2024, 1.5, 1)"),
+        Err(Error::WrongArgs(
+            "date: the year, month and day must be integers".to_string()
+        ))
+    );
+    assert_eq!(
+        eval("now(1)"),
+        Err(Error::WrongArgCount {
+            name: "now".to_string(),
+            expected: Arity::Exact(0),
+            got: 1
+        })
+    );
+    assert_eq!(
+        eval("hour()"),
+        Err(Error::WrongArgCount {
+            name: "hour".to_string(),
+            expected: Arity::Exact(1),
+            got: 0
+        })
+    );
+
+    // `now()` isn't constant-folded or shared, even when compiled with the
+    // clock Namespace itself:
+    let ticking = || {
+        let mut t = 100.0;
+        move || {
+            t += 1.0;
+            t
+        }
+    };
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse("now() * 1000 + now() + hour(7200)", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(
+            &slab.ps,
+            &mut slab.cs,
+            &mut EvalNS::new(EmptyNamespace).with_clock(ticking()),
+        );
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    let mut ns = EvalNSBuilder::new().clock(ticking()).build();
+    assert_eq!(instr.eval(&slab, &mut ns), Ok(101_000.0 + 102.0 + 2.0));
+    assert_eq!(instr.eval(&slab, &mut ns), Ok(103_000.0 + 104.0 + 2.0));
+
+    // The real clock is the default, and takes precedence over the wrapped
+    // Namespace:
+    let mut ns = EvalNS::new(|name: &str, _: Vec<f64>| match name {
+        "now" => Some(5.0),
+        _ => None,
+    });
+    let now = ez_eval("now()", &mut ns).unwrap();
+    assert!(now > 1.7e9, "{}", now);
+    let mut ns = EvalNSBuilder::new().build();
+    let now = ez_eval("now()", &mut ns).unwrap();
+    assert!(now > 1.7e9, "{}", now);
+    let mut ns = EvalNS::new(EmptyNamespace).with_clock(fasteval2::SystemClock);
+    assert!(ez_eval("now()", &mut ns).unwrap() >= now);
+
+    // The other functions don't need a clock:
+    let mut ns = EvalNS::new(EmptyNamespace);
+    assert_eq!(ez_eval("hour(7200) + dow(0)", &mut ns), Ok(6.0));
+    assert_eq!(ez_eval("date(1970, 1, 2)", &mut ns), Ok(86400.0));
+}
+
+#[test]
+fn builder() {
+    let mut calls = 0;
+    {
+        let mut ns = fasteval2::EvalNSBuilder::new()
+            .var("x", 2.0)
+            .var("y", 3.0)
+            .var("bad", f64::NAN)
+            .func("twice", |args| {
+                calls += 1;
+                args.first().map(|a| a * 2.0)
+            })
+            .strict()
+            .build();
+        assert_eq!(ez_eval("x * y + twice(x)", &mut ns), Ok(10.0));
+        assert_eq!(
+            ez_eval("z", &mut ns),
+            Err(Error::UndefinedVariable {
+                name: "z".to_string()
+            })
+        );
+        // Variables don't take arguments:
+        assert_eq!(
+            ez_eval("x(1)", &mut ns),
+            Err(Error::UndefinedFunction {
+                name: "x".to_string(),
+                arg_count: 1
+            })
+        );
+        assert_eq!(
+            ez_eval("twice()", &mut ns),
+            Err(Error::UndefinedFunction {
+                name: "twice".to_string(),
+                arg_count: 0
+            })
+        );
+
+        // Strict mode rejects non-finite lookups, but not non-finite arithmetic:
+        assert_eq!(
+            ez_eval("bad * 0", &mut ns),
+            Err(Error::NotFinite("bad".to_string()))
+        );
+        assert_eq!(ez_eval("1 / (x - 2)", &mut ns), Ok(f64::INFINITY));
+
+        // The compiled form is checked too:
+        let mut slab = Slab::new();
+        let compiled = Parser::new()
+            .parse("x + twice(bad)", &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        assert_eq!(
+            compiled.eval(&slab, &mut ns),
+            Err(Error::NotFinite("bad".to_string()))
+        );
+    }
+    assert_eq!(calls, 2);
+
+    // Limits are applied, and names are still overridden by rand():
+    let mut ns = fasteval2::EvalNSBuilder::new()
+        .var("x", f64::NAN)
+        .func("rand", |_| Some(5.0))
+        .budget(3)
+        .rng(7)
+        .build();
+    assert!(ez_eval("x + 1", &mut ns).unwrap().is_nan());
+    assert!(ez_eval("rand()", &mut ns).unwrap() < 1.0);
+    assert_eq!(
+        ez_eval("sum(i, 1, 10, i)", &mut ns),
+        Err(Error::BudgetExceeded(3))
+    );
+}
+
+#[test]
+fn eval_with() {
+    let mut slab = Slab::new();
+    let expr = Parser::new()
+        .parse("x * scale(y) + z", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut ns = EvalNSBuilder::new()
+        .var("x", 2.0)
+        .var("y", 3.0)
+        .func("scale", |args| args.first().map(|a| a * 10.0))
+        .result_range(0.0, 1000.0)
+        .build();
+
+    // `z` only exists for this call, and the override of `x` wins:
+    assert_eq!(
+        expr.eval_with(&slab, &mut ns, &[("x", 5.0), ("z", 1.0), ("x", 7.0)]),
+        Ok(151.0)
+    );
+    // ...but the base is untouched:
+    assert_eq!(ez_eval("x", &mut ns), Ok(2.0));
+    assert_eq!(
+        expr.eval_with(&slab, &mut ns, &[]),
+        Err(Error::UndefinedVariable {
+            name: "z".to_string()
+        })
+    );
+    assert_eq!(expr.eval_with(&slab, &mut ns, &[("z", 0.0)]), Ok(60.0));
+
+    // Overrides are variables, not functions, and the result is checked:
+    assert_eq!(
+        expr.eval_with(&slab, &mut ns, &[("scale", 1.0), ("z", 0.0)]),
+        Ok(60.0)
+    );
+    assert_eq!(
+        expr.eval_with(&slab, &mut ns, &[("z", 1e9)]),
+        Err(Error::OutOfRange {
+            value: 1e9 + 60.0,
+            min: 0.0,
+            max: 1000.0
+        })
+    );
+}
+
+#[test]
+fn from_env() {
+    // Unique names, since the tests of this file run in parallel:
+    std::env::set_var("FASTEVAL2_TEST_RATE", " 2.5\n");
+    std::env::set_var("FASTEVAL2_TEST_BAD", "fast");
+    std::env::remove_var("FASTEVAL2_TEST_UNSET");
+
+    let mut ns = EvalNS::from_env();
+    assert_eq!(ez_eval("FASTEVAL2_TEST_RATE * 4", &mut ns), Ok(10.0));
+    assert_eq!(
+        ez_eval("FASTEVAL2_TEST_BAD + 1", &mut ns),
+        Err(Error::ParseF64("fast".to_string()))
+    );
+    assert_eq!(
+        ez_eval("FASTEVAL2_TEST_UNSET", &mut ns),
+        Err(Error::UndefinedVariable {
+            name: "FASTEVAL2_TEST_UNSET".to_string()
+        })
+    );
+    // Only variables come from the environment:
+    assert!(ez_eval("FASTEVAL2_TEST_RATE(1)", &mut ns).is_err());
+
+    // Compiled expressions look them up the same way:
+    let mut slab = Slab::new();
+    let compiled = Parser::new()
+        .parse("FASTEVAL2_TEST_RATE + 1", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(compiled.eval(&slab, &mut ns), Ok(3.5));
+}
+
+#[test]
+fn stable() {
+    let expr_str = "x*x + f(x)*f(x) + piecewise(x > 1, x^3, x) + sum(i, 1, 3, i*y) + (let t = y; t + y) + rand()*0";
+    let calls = RefCell::new(BTreeMap::<String, usize>::new());
+    let cb = |name: &str, args: Vec<f64>| {
+        *calls.borrow_mut().entry(name.to_string()).or_default() += 1;
+        match (name, args.as_slice()) {
+            ("x", []) => Some(2.0),
+            ("y", []) => Some(3.0),
+            ("f", [a]) => Some(a + 1.0),
+            ("rand", []) => Some(0.5),
+            _ => None,
+        }
+    };
+    let counts = |stable: bool, compiled: bool| {
+        calls.borrow_mut().clear();
+        let mut slab = Slab::new();
+        let expr = Parser::new()
+            .parse(expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        let mut ns = EvalNS::new(cb);
+        if stable {
+            ns = ns.with_stable();
+        }
+        let val = if compiled {
+            let instr = expr.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+            instr.eval(&slab, &mut ns)
+        } else {
+            expr.eval(&slab, &mut ns)
+        };
+        assert_eq!(val, Ok(45.0));
+        let calls = calls.borrow();
+        ["x", "y", "f", "rand"].map(|name| calls.get(name).copied().unwrap_or(0))
+    };
+
+    assert_eq!(counts(false, false), [6, 5, 2, 1]);
+    assert_eq!(counts(false, true), [6, 5, 2, 1]);
+    // Every variable is looked up once, but functions with arguments and
+    // rand() are still called every time:
+    assert_eq!(counts(true, false), [1, 1, 2, 1]);
+    // ...unless they're compiled, which also computes repeats once:
+    assert_eq!(counts(true, true), [1, 1, 1, 1]);
+
+    // The builder declares it too:
+    let mut lookups = 0;
+    let mut ns = EvalNSBuilder::new()
+        .func("g", |args| {
+            lookups += 1;
+            args.first().copied()
+        })
+        .stable()
+        .build();
+    assert_eq!(ez_eval("g(1) + g(1) + g(2)", &mut ns), Ok(4.0));
+    drop(ns);
+    assert_eq!(lookups, 3);
+}
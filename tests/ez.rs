@@ -23,3 +23,95 @@ fn ez() {
         Ok(5.0)
     );
 }
+
+#[test]
+fn error_variants() {
+    // Callers tell errors apart by their variant, not by their text:
+    fn kind(expr_str: &str) -> &'static str {
+        match ez_eval(expr_str, &mut BTreeMap::<String, f64>::new()) {
+            Ok(_) => "ok",
+            Err(Error::UndefinedVariable { .. } | Error::UndefinedFunction { .. }) => "undefined",
+            Err(Error::WrongArgs(_)) | Err(Error::WrongArgCount { .. }) => "wrong args",
+            Err(Error::TooLong) | Err(Error::TooDeep) => "too big",
+            Err(Error::EofWhileParsing(_))
+            | Err(Error::UnparsedTokensRemaining(_))
+            | Err(Error::InvalidValue)
+            | Err(Error::Expected(_)) => "parse",
+            Err(Error::Unreachable) => "internal",
+            Err(_) => "other",
+        }
+    }
+
+    assert_eq!(kind("1 + 2"), "ok");
+    assert_eq!(kind("x + 2"), "undefined");
+    assert_eq!(kind("f(1)"), "undefined");
+    assert_eq!(kind("interp(1, 2)"), "wrong args");
+    assert_eq!(kind(&"(".repeat(100)), "too big");
+    assert_eq!(kind(&"1+".repeat(3000)), "too big");
+    assert_eq!(kind("1 +"), "parse");
+    assert_eq!(kind("1 2"), "parse");
+    assert_eq!(kind("1 + * 2"), "parse");
+    assert_eq!(kind("print(1"), "parse");
+}
+
+#[test]
+fn nested_errors() {
+    // Errors don't collect context on the way out, so the innermost failure
+    // comes out unchanged:
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "f" | "g" => args.first().copied(),
+        _ => None,
+    };
+    for expr_str in [
+        "y",
+        "f(y)",
+        "f(1 + (2 * sum(i, 1, 3, g(y))))",
+        "piecewise(f(1), max(1, -g(y)), 0)",
+    ] {
+        assert_eq!(
+            ez_eval(expr_str, &mut ns),
+            Err(Error::UndefinedVariable {
+                name: "y".to_string()
+            }),
+            "{}",
+            expr_str
+        );
+    }
+}
+
+#[test]
+fn error_source() {
+    use std::error::Error as _;
+    use std::num::ParseFloatError;
+
+    let mut ns = fasteval2::EmptyNamespace;
+    for tok in ["1e", "1e+", "2.3.4", "."] {
+        let err = ez_eval(&format!("1 + {}", tok), &mut ns).unwrap_err();
+        assert_eq!(err, Error::ParseF64(tok.to_string()));
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<ParseFloatError>(),
+            tok.parse::<f64>().err().as_ref(),
+            "{}",
+            tok
+        );
+    }
+    assert!(Error::InvalidValue.source().is_none());
+
+    // The chain continues through boxed errors and spanned errors:
+    let boxed: Box<dyn std::error::Error> = ez_eval("1e", &mut ns).unwrap_err().into();
+    assert!(boxed.source().unwrap().is::<ParseFloatError>());
+    let spanned = fasteval2::Parser::new()
+        .parse_spanned("2 * 1e", &mut fasteval2::Slab::new().ps)
+        .unwrap_err();
+    assert!(spanned
+        .source()
+        .unwrap()
+        .source()
+        .unwrap()
+        .is::<ParseFloatError>());
+
+    // Display is a single line:
+    let err = ez_eval("1\n+ 2)\n", &mut ns).unwrap_err();
+    assert_eq!(err.to_string(), r#"UnparsedTokensRemaining(")\n")"#);
+}
@@ -0,0 +1,214 @@
+#![cfg(feature = "ffi")]
+
+use fasteval2::ffi::*;
+
+use std::ffi::{c_char, CStr};
+use std::process::Command;
+use std::ptr;
+
+fn parse(src: &CStr) -> Result<*mut FeExpr, FeStatus> {
+    let mut expr = ptr::null_mut();
+    match unsafe { fe_parse(src.as_ptr(), &mut expr) } {
+        FE_OK => Ok(expr),
+        status => {
+            assert!(expr.is_null());
+            Err(status)
+        }
+    }
+}
+
+fn eval(expr: *const FeExpr, vars: &[FeVar]) -> Result<f64, FeStatus> {
+    let mut out = f64::NAN;
+    match unsafe { fe_eval(expr, vars.as_ptr(), vars.len(), &mut out) } {
+        FE_OK => Ok(out),
+        status => Err(status),
+    }
+}
+
+fn message(status: FeStatus) -> String {
+    let mut buf = [0 as c_char; 256];
+    unsafe { fe_error_message(status, buf.as_mut_ptr(), buf.len()) };
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn var(name: &CStr, value: f64) -> FeVar {
+    FeVar {
+        name: name.as_ptr(),
+        value,
+    }
+}
+
+#[test]
+fn parse_and_eval() {
+    let expr = parse(c"x * 2 + max(y, 1)").unwrap();
+    let vars = [var(c"x", 3.0), var(c"y", 5.0), var(c"x", 100.0)];
+    assert_eq!(eval(expr, &vars), Ok(11.0));
+    assert_eq!(eval(expr, &vars[..2]), Ok(11.0));
+
+    assert_eq!(eval(expr, &vars[..1]), Err(FE_EVAL_ERROR));
+    assert!(message(FE_EVAL_ERROR).starts_with("eval error: "));
+    assert!(message(FE_EVAL_ERROR).contains("\"y\""));
+    unsafe { fe_expr_free(expr) };
+
+    let constant = parse(c"sin(0) + 1").unwrap();
+    let mut out = 0.0;
+    assert_eq!(
+        unsafe { fe_eval(constant, ptr::null(), 0, &mut out) },
+        FE_OK
+    );
+    assert_eq!(out, 1.0);
+    // Variables can't be called as functions:
+    let call = parse(c"f(1)").unwrap();
+    assert_eq!(eval(call, &[var(c"f", 1.0)]), Err(FE_EVAL_ERROR));
+    unsafe { fe_expr_free(call) };
+    unsafe { fe_expr_free(constant) };
+}
+
+#[test]
+fn invalid_arguments() {
+    let mut expr = ptr::null_mut();
+    let mut out = 0.0;
+    unsafe {
+        assert_eq!(fe_parse(ptr::null(), &mut expr), FE_NULL_POINTER);
+        assert_eq!(message(FE_NULL_POINTER), "null pointer: src is null");
+        assert_eq!(fe_parse(c"1".as_ptr(), ptr::null_mut()), FE_NULL_POINTER);
+        assert_eq!(fe_parse(c"\xff".as_ptr(), &mut expr), FE_INVALID_UTF8);
+        assert!(expr.is_null());
+
+        let expr = parse(c"x").unwrap();
+        assert_eq!(
+            fe_eval(ptr::null(), ptr::null(), 0, &mut out),
+            FE_NULL_POINTER
+        );
+        assert_eq!(
+            fe_eval(expr, ptr::null(), 0, ptr::null_mut()),
+            FE_NULL_POINTER
+        );
+        assert_eq!(fe_eval(expr, ptr::null(), 1, &mut out), FE_NULL_POINTER);
+        let no_name = [FeVar {
+            name: ptr::null(),
+            value: 1.0,
+        }];
+        assert_eq!(eval(expr, &no_name), Err(FE_NULL_POINTER));
+        assert_eq!(
+            message(FE_NULL_POINTER),
+            "null pointer: vars[0].name is null"
+        );
+        assert_eq!(eval(expr, &[var(c"\xff", 1.0)]), Err(FE_INVALID_UTF8));
+        assert_eq!(out, 0.0);
+        fe_expr_free(expr);
+        fe_expr_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn error_messages() {
+    assert_eq!(parse(c"1 +"), Err(FE_PARSE_ERROR));
+    let full = message(FE_PARSE_ERROR);
+    assert!(full.starts_with("parse error: "), "{}", full);
+    // Other statuses don't pick up the details of the last failure:
+    assert_eq!(message(FE_EVAL_ERROR), "eval error");
+    assert_eq!(message(FE_OK), "ok");
+    assert_eq!(message(-1), "unknown status");
+
+    unsafe {
+        assert_eq!(
+            fe_error_message(FE_PARSE_ERROR, ptr::null_mut(), 0),
+            full.len()
+        );
+        let mut buf = [b'?' as c_char; 8];
+        assert_eq!(
+            fe_error_message(FE_PARSE_ERROR, buf.as_mut_ptr(), 6),
+            full.len()
+        );
+        assert_eq!(CStr::from_ptr(buf.as_ptr()).to_bytes(), b"parse");
+        assert_eq!(buf[6], b'?' as c_char);
+    }
+
+    // Truncation doesn't split UTF-8 characters:
+    assert_eq!(parse(c"1 \xc3\xa9"), Err(FE_PARSE_ERROR));
+    let full = message(FE_PARSE_ERROR);
+    let at = full.find('é').unwrap();
+    let mut buf = [0 as c_char; 64];
+    unsafe { fe_error_message(FE_PARSE_ERROR, buf.as_mut_ptr(), at + 2) };
+    let cut = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    assert_eq!(cut.to_str(), Ok(&full[..at]));
+}
+
+#[test]
+fn shared_between_threads() {
+    struct Shared(*mut FeExpr);
+    unsafe impl Send for Shared {}
+    unsafe impl Sync for Shared {}
+
+    let expr = Shared(parse(c"x * x").unwrap());
+    std::thread::scope(|s| {
+        for i in 0..4 {
+            let expr = &expr;
+            s.spawn(move || {
+                let x = i as f64;
+                assert_eq!(eval(expr.0, &[var(c"x", x)]), Ok(x * x));
+            });
+        }
+    });
+    unsafe { fe_expr_free(expr.0) };
+}
+
+// Builds the crate as a static library, and runs tests/ffi/main.c against it,
+// through include/fasteval2.h.  A separate target directory keeps the outer
+// build unlocked.  The system libraries to link are those of Linux.
+#[cfg(target_os = "linux")]
+#[test]
+fn c_program() {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let target = concat!(env!("CARGO_MANIFEST_DIR"), "/target/ffi");
+    let out = Command::new(env!("CARGO"))
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "ffi",
+            "--crate-type",
+            "staticlib",
+        ])
+        .current_dir(dir)
+        .env("CARGO_TARGET_DIR", target)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let exe = format!("{}/c_program", target);
+    let out = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args([
+            "-std=c99",
+            "-Wall",
+            "-Werror",
+            "-Iinclude",
+            "tests/ffi/main.c",
+        ])
+        .arg(format!("{}/debug/libfasteval2.a", target))
+        .args(["-lm", "-lpthread", "-ldl", "-o", &exe])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = Command::new(&exe).output().unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(out.stdout, b"ok\n");
+}
@@ -0,0 +1,53 @@
+/* Exercises include/fasteval2.h from C.  tests/ffi.rs compiles and runs it. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "fasteval2.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                       \
+    do {                                                                  \
+        if (!(cond)) {                                                    \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,        \
+                    __LINE__, #cond);                                     \
+            failures++;                                                   \
+        }                                                                 \
+    } while (0)
+
+int main(void) {
+    FeExpr *expr = NULL;
+    double out = 0.0;
+    char msg[256];
+
+    CHECK(fe_parse("x * 2 + max(y, 1)", &expr) == FE_OK);
+    CHECK(expr != NULL);
+
+    FeVar vars[] = { { "x", 3.0 }, { "y", 5.0 } };
+    CHECK(fe_eval(expr, vars, 2, &out) == FE_OK);
+    CHECK(out == 11.0);
+
+    FeStatus status = fe_eval(expr, vars, 1, &out);
+    CHECK(status == FE_EVAL_ERROR);
+    fe_error_message(status, msg, sizeof msg);
+    CHECK(strstr(msg, "\"y\"") != NULL);
+    fe_expr_free(expr);
+
+    status = fe_parse("1 +", &expr);
+    CHECK(status == FE_PARSE_ERROR);
+    CHECK(expr == NULL);
+    size_t len = fe_error_message(status, NULL, 0);
+    CHECK(len > strlen("parse error"));
+    CHECK(fe_error_message(status, msg, 6) == len);
+    CHECK(strcmp(msg, "parse") == 0);
+
+    CHECK(fe_parse(NULL, &expr) == FE_NULL_POINTER);
+    CHECK(fe_eval(NULL, NULL, 0, &out) == FE_NULL_POINTER);
+    fe_expr_free(NULL);
+
+    if (failures == 0) {
+        printf("ok\n");
+    }
+    return failures != 0;
+}
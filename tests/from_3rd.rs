@@ -63,10 +63,15 @@ fn chk_eerr(expr_str: &str, expect_err: Error) {
 
 #[test]
 fn meval() {
-    chk_perr("", Error::EofWhileParsing("value".to_string()));
+    chk_perr("", Error::EmptyExpression);
     chk_perr("(", Error::EofWhileParsing("value".to_string()));
     chk_perr("0(", Error::UnparsedTokensRemaining("(".to_string()));
-    chk_eerr("e", Error::Undefined("e".to_string()));
+    chk_eerr(
+        "e",
+        Error::UndefinedVariable {
+            name: "e".to_string(),
+        },
+    );
     chk_perr("1E", Error::ParseF64("1E".to_string()));
     chk_perr("1e+", Error::ParseF64("1e+".to_string()));
     chk_perr("()", Error::InvalidValue);
@@ -98,6 +103,7 @@ fn meval() {
 }
 
 #[test]
+#[allow(deprecated)] // The public fields still work.
 fn overflow_stack() {
     chk_perr(
         from_utf8(&[b'('; 1]).unwrap(),
@@ -134,21 +140,17 @@ fn overflow_stack() {
     chk_perr(from_utf8(&[b'('; 8192]).unwrap(), Error::TooLong);
 
     // Test custom safety parse limits:
+    let mut parser = Parser::new();
+    parser.expr_depth_limit = 31;
     assert_eq!(
-        Parser {
-            expr_len_limit: fasteval2::parser::DEFAULT_EXPR_LEN_LIMIT,
-            expr_depth_limit: 31
-        }
-        .parse(from_utf8(&[b'('; 32]).unwrap(), &mut Slab::new().ps),
+        parser.parse(from_utf8(&[b'('; 32]).unwrap(), &mut Slab::new().ps),
         Err(Error::TooDeep)
     );
 
+    let mut parser = Parser::new();
+    parser.expr_len_limit = 8;
     assert_eq!(
-        Parser {
-            expr_len_limit: 8,
-            expr_depth_limit: fasteval2::parser::DEFAULT_EXPR_DEPTH_LIMIT
-        }
-        .parse(from_utf8(&[b'('; 32]).unwrap(), &mut Slab::new().ps),
+        parser.parse(from_utf8(&[b'('; 32]).unwrap(), &mut Slab::new().ps),
         Err(Error::TooLong)
     );
 }
@@ -0,0 +1,89 @@
+use fasteval2::{EmptyNamespace, Error, IntEvaler, Parser, Slab};
+
+use std::collections::BTreeMap;
+
+fn eval_i64(expr_str: &str) -> Result<i64, Error> {
+    let mut slab = Slab::new();
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 3.0);
+    ns.insert("half".to_string(), 0.5);
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .eval_i64(&slab, &mut ns)
+}
+
+#[test]
+fn basics() {
+    assert_eq!(eval_i64("2^10"), Ok(1024));
+    assert_eq!(eval_i64("1 + 2*3 - 4"), Ok(3));
+    assert_eq!(eval_i64("7/2"), Ok(3));
+    assert_eq!(eval_i64("-7/2"), Ok(-3));
+    assert_eq!(eval_i64("-7%2"), Ok(-1));
+    assert_eq!(eval_i64("2^3^2"), Ok(512));
+    assert_eq!(eval_i64("x*x + x"), Ok(12));
+    assert_eq!(eval_i64("x > 2 && x < 4"), Ok(1));
+    assert_eq!(eval_i64("0 || x"), Ok(3));
+    assert_eq!(eval_i64("!x"), Ok(0));
+    assert_eq!(
+        eval_i64("abs(-5) + sign(-5) + min(4, x) + max(4, x)"),
+        Ok(11)
+    );
+    assert_eq!(
+        eval_i64("round(2, 5) + round(2, -5) + round(10, 14)"),
+        Ok(10)
+    );
+    assert_eq!(eval_i64("piecewise(x < 2, 10, x < 5, 20, 30)"), Ok(20));
+    assert_eq!(eval_i64("sqrt(16)"), Ok(4));
+    assert_eq!(eval_i64("(-1)^-3 + 1^-3"), Ok(0));
+
+    // Precision is not lost like it would be with f64:
+    assert_eq!(eval_i64("2^62 + (2^62 - 1)"), Ok(i64::MAX));
+    assert_eq!(eval_i64("(2^62 - 1)*2 + 1 - 2"), Ok(i64::MAX - 2));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        eval_i64("(2^62 - 1)*2 + 1 + 1"),
+        Err(Error::IntegerOverflow)
+    );
+    assert_eq!(eval_i64("2^63"), Err(Error::IntegerOverflow));
+    assert_eq!(eval_i64("-(-2^62*2)"), Err(Error::IntegerOverflow));
+    assert_eq!(eval_i64("2^100000000000"), Err(Error::IntegerOverflow));
+    assert_eq!(eval_i64("1/0"), Err(Error::DivideByZero));
+    assert_eq!(eval_i64("1%0"), Err(Error::DivideByZero));
+    assert_eq!(eval_i64("1.5"), Err(Error::NotAnInteger("1.5".to_string())));
+    assert_eq!(
+        eval_i64("half*2"),
+        Err(Error::NotAnInteger("0.5".to_string()))
+    );
+    assert_eq!(
+        eval_i64("2^-1"),
+        Err(Error::NotAnInteger("2^-1".to_string()))
+    );
+    assert_eq!(
+        eval_i64("pi()"),
+        Err(Error::NotAnInteger("pi()".to_string()))
+    );
+    assert_eq!(
+        eval_i64("sqrt(2)"),
+        Err(Error::NotAnInteger("1.4142135623730951".to_string()))
+    );
+    assert_eq!(
+        eval_i64("y"),
+        Err(Error::UndefinedVariable {
+            name: "y".to_string()
+        })
+    );
+
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new()
+            .parse("1e300", &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+            .eval_i64(&slab, &mut EmptyNamespace),
+        Err(Error::IntegerOverflow)
+    );
+}
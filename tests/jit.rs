@@ -0,0 +1,212 @@
+#![cfg(feature = "jit")]
+
+mod common;
+
+use fasteval2::{Compiler, EmptyNamespace, Error, Evaler, JitExpression};
+
+static VARS: [&str; 3] = ["x", "y", "z"];
+
+static CORPUS: &[&str] = &[
+    "x",
+    "-x",
+    "x + y * z",
+    "x - y / z",
+    "1 / x",
+    "x ^ y",
+    "x ^ 0.5 + y ^ -2",
+    "x % y",
+    "-x % 3",
+    "x < y",
+    "x <= y",
+    "x == y",
+    "x != y",
+    "x >= y",
+    "x > y",
+    "x == x",
+    "x == 0.1 + 0.2 - 0.3 + x",
+    "!x",
+    "!(x - y)",
+    "x && y",
+    "x || y",
+    "(x > 0) && (y > 0) || z",
+    "int(x) + ceil(y) + floor(z)",
+    "abs(x) * sign(y)",
+    "sign(x - x)",
+    "sqrt(x)",
+    "log(x)",
+    "log(2, x)",
+    "log(y, x)",
+    "round(x)",
+    "round(0.25, x)",
+    "round(y, x)",
+    "min(x, y)",
+    "max(x, y, z)",
+    "min(x, 1, y, -1)",
+    "piecewise(x < 0, -1, x > 1, 1, x)",
+    "piecewise(x, y, z)",
+    "sin(x) + cos(y) + tan(z)",
+    "asin(x) + acos(y) + atan(z)",
+    "sinh(x) + cosh(y) + tanh(z)",
+    "asinh(x) + acosh(y) + atanh(z)",
+    "fma(x, y, z)",
+    "fma(x, 3, -y)",
+    "coalesce(x / y, z)",
+    "coalesce(log(x), -1) + coalesce(1 / (x - x), y)",
+    "quotient(x, y) * y + remainder(x, y)",
+    "quotient(-x, 0.3) + quotient(y, 0)",
+    "sumsq(x, y, z) + norm(x, 2) + norm(y)",
+    "pi() * e() + phi + sqrt2 * ln2 / ln10",
+    "x * (y + z * (x - y * (z + x)))",
+    "(x + 1) * (x + 2) * (x + 3) / (y - 1) / (y - 2)",
+    "3 * x ^ 2 - 2 * x * y + y ^ 3 - z % 7",
+    "1 + 2 * 3",
+    "let r = x * y; r + r * z",
+    "let x = x + 1; x * (let y = x; y + z)",
+];
+
+fn rand_inputs(n: usize) -> Vec<[f64; 3]> {
+    let mut rand = common::rand_gen(0x9e37_79b9_7f4a_7c15);
+    let special = [
+        0.0,
+        -0.0,
+        1.0,
+        -1.0,
+        0.5,
+        f64::NAN,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::EPSILON,
+        1e308,
+    ];
+    let mut val = move || {
+        let r = rand();
+        match r % 4 {
+            0 => special[(r >> 8) as usize % special.len()],
+            1 => ((r >> 8) % 21) as f64 - 10.0,
+            _ => ((r >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * 20.0,
+        }
+    };
+    (0..n).map(|_| [val(), val(), val()]).collect()
+}
+
+fn interpreted(expr_str: &str, vars: &[f64; 3]) -> Result<f64, Error> {
+    let mut slab = fasteval2::Slab::new();
+    let instr = fasteval2::Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let mut ns = |name: &str, _: Vec<f64>| VARS.iter().position(|v| *v == name).map(|i| vars[i]);
+    instr.eval(&slab, &mut ns)
+}
+
+fn same(a: f64, b: f64) -> bool {
+    a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+}
+
+#[test]
+fn matches_interpreter() {
+    let inputs = rand_inputs(2000);
+    for expr_str in CORPUS {
+        let jit = JitExpression::compile(expr_str, &VARS).unwrap();
+        assert!(jit.is_native(), "{}: {:?}", expr_str, jit.fallback_reason());
+        for vars in &inputs {
+            let want = interpreted(expr_str, vars).unwrap();
+            let got = jit.eval(vars);
+            assert!(
+                same(got, want),
+                "{} with {:?}: jit={:?} interpreter={:?}",
+                expr_str,
+                vars,
+                got,
+                want
+            );
+        }
+    }
+}
+
+#[test]
+fn fallback() {
+    let inputs = rand_inputs(200);
+    for (expr_str, reason) in [
+        (
+            "sum(i, 1, 4, i * x) + y",
+            "sum() is not supported by the JIT",
+        ),
+        (
+            "interp(x, 0,0, 10,y) * z",
+            "interp() is not supported by the JIT",
+        ),
+        (
+            "x + min(sum(i, y, z, i), 3)",
+            "sum() is not supported by the JIT",
+        ),
+    ] {
+        let jit = JitExpression::compile(expr_str, &VARS).unwrap();
+        assert!(!jit.is_native());
+        assert_eq!(jit.fallback_reason(), Some(reason));
+        for vars in &inputs {
+            let want = interpreted(expr_str, vars).unwrap_or(f64::NAN);
+            assert!(same(jit.eval(vars), want), "{} with {:?}", expr_str, vars);
+        }
+    }
+
+    // Interpreter errors become NaN:
+    let jit = JitExpression::compile("interp(x, y,0, 0,1)", &VARS).unwrap();
+    assert!(jit.eval(&[0.5, 1.0, 0.0]).is_nan());
+}
+
+#[test]
+fn slots() {
+    // Slots don't have to match the order in which the variables are used:
+    let jit = JitExpression::compile("a - b", &["unused", "b", "a"]).unwrap();
+    assert_eq!(jit.vars(), ["unused", "b", "a"]);
+    assert_eq!(jit.eval(&[100.0, 1.0, 10.0]), 9.0);
+    // Extra slots are ignored; missing slots produce NaN:
+    assert_eq!(jit.eval(&[100.0, 1.0, 10.0, 1000.0]), 9.0);
+    assert!(jit.eval(&[100.0, 1.0]).is_nan());
+
+    let jit = JitExpression::compile("2 + 3", &[]).unwrap();
+    assert!(jit.is_native());
+    assert_eq!(jit.eval(&[]), 5.0);
+}
+
+#[test]
+fn undefined() {
+    assert_eq!(
+        JitExpression::compile("x + w", &VARS).unwrap_err(),
+        Error::UndefinedVariable {
+            name: "w".to_string()
+        }
+    );
+    assert_eq!(
+        JitExpression::compile("f(x)", &VARS).unwrap_err(),
+        Error::UndefinedFunction {
+            name: "f".to_string(),
+            arg_count: 1
+        }
+    );
+    assert_eq!(
+        JitExpression::compile("x(1)", &VARS).unwrap_err(),
+        Error::UndefinedFunction {
+            name: "x".to_string(),
+            arg_count: 1
+        }
+    );
+    // Names inside a sum() are checked too, even though it isn't lowered:
+    assert_eq!(
+        JitExpression::compile("sum(i, 1, 3, i * w)", &VARS).unwrap_err(),
+        Error::UndefinedVariable {
+            name: "w".to_string()
+        }
+    );
+    assert!(JitExpression::compile("sum(i, 1, 3, i * x)", &VARS).is_ok());
+}
+
+#[test]
+fn many() {
+    // Each JitExpression owns its code, and frees it when dropped:
+    for i in 0..200 {
+        let jit = JitExpression::compile("x * y + z", &VARS).unwrap();
+        assert_eq!(jit.eval(&[i as f64, 2.0, 1.0]), i as f64 * 2.0 + 1.0);
+    }
+}
@@ -0,0 +1,84 @@
+use fasteval2::{Latex, Parser, Slab};
+
+fn latex(expr_str: &str, opts: Latex) -> String {
+    let mut slab = Slab::new();
+    let expr = Parser::new().parse(expr_str, &mut slab.ps).unwrap();
+    opts.render(expr.from(&slab.ps), &slab)
+}
+
+#[test]
+fn snapshots() {
+    for (expr_str, tex) in [
+        ("a/(b+c)^2", r"\frac{a}{(b + c)^{2}}"),
+        ("(x + 1) / (x / (y - 1))", r"\frac{x + 1}{\frac{x}{y - 1}}"),
+        ("x * y / 2", r"x \cdot \frac{y}{2}"),
+        ("(x / y) ^ 2 + 2^3^x", r"(\frac{x}{y})^{2} + 2^{3^{x}}"),
+        ("(x ^ y) ^ 2 - -x ^ 2", r"(x^{y})^{2} - (-x)^{2}"),
+        ("-(x + 1) * !y", r"-(x + 1) \cdot \lnot y"),
+        ("x - (y - 1) - 2", r"x - (y - 1) - 2"),
+        ("(x + y) % 3 * 2", r"((x + y) \bmod 3) \cdot 2"),
+        (
+            "x <= 1 && y != 2 || x >= y == 0",
+            r"x \le 1 \land y \ne 2 \lor x \ge y = 0",
+        ),
+        (
+            "sin(x)^2 + cos(x) * atan(y) - sinh(2)",
+            r"\sin(x)^{2} + \cos(x) \cdot \arctan(y) - \sinh(2)",
+        ),
+        (
+            "log(2, x) + log(x) - sqrt(abs(x)) * floor(x/2)",
+            r"\log_{2}(x) + \log(x) - \sqrt{\left|x\right|} \cdot \left\lfloor \frac{x}{2} \right\rfloor",
+        ),
+        (
+            "max(1, x, y) + f(x, 2) + clamp_to(x, 0, 1.5)",
+            r"\max(1, x, y) + f(x, 2) + \operatorname{clamp\_to}(x, 0, 1.5)",
+        ),
+        (
+            "2 * pi() * rate_1 + e()",
+            r"2 \cdot \pi \cdot \mathit{rate\_1} + e",
+        ),
+        (
+            "sum(i, 1, n, i^2 + 1) * 2",
+            r"(\sum_{i=1}^{n} (i^{2} + 1)) \cdot 2",
+        ),
+        (
+            "let r = sqrt(x) + 1; r * r",
+            r"r \cdot r \quad \text{where } r = \sqrt{x} + 1",
+        ),
+        (
+            "piecewise(x < 0, -x, x)",
+            r"\begin{cases} -x & \text{if } x < 0 \\ x & \text{otherwise} \end{cases}",
+        ),
+        (
+            r#"print("50% & more", x) + ceil(0.25)"#,
+            r#"\operatorname{print}(\text{"50\% \& more"}, x) + \left\lceil 0.25 \right\rceil"#,
+        ),
+    ] {
+        assert_eq!(latex(expr_str, Latex::new()), tex, "{}", expr_str);
+    }
+}
+
+#[test]
+fn implicit_mul() {
+    for (expr_str, tex) in [
+        ("2 * x * y", "2 x y"),
+        ("x * 2", r"x \cdot 2"),
+        ("(a + b) * (a - b)", "(a + b) (a - b)"),
+        ("x * -y", r"x \cdot -y"),
+        ("3 * sin(x) / 4", r"3 \frac{\sin(x)}{4}"),
+    ] {
+        assert_eq!(
+            latex(expr_str, Latex::new().implicit_mul()),
+            tex,
+            "{}",
+            expr_str
+        );
+    }
+
+    let mut slab = Slab::new();
+    let expr = Parser::new()
+        .parse("2*x", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(expr.to_latex(&slab), r"2 \cdot x");
+}
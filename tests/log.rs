@@ -0,0 +1,67 @@
+#![cfg(feature = "log")]
+
+use fasteval2::{ez_eval, EmptyNamespace};
+
+use std::sync::Mutex;
+
+// Keeps the messages of the interpreter's events:
+struct Recorder(Mutex<Vec<String>>);
+
+impl log::Log for Recorder {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target() == "fasteval2::eval"
+    }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            assert_eq!(record.level(), log::Level::Trace);
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+    fn flush(&self) {}
+}
+
+static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+// One test function, because the logger is global:
+#[test]
+fn events() {
+    log::set_logger(&RECORDER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    let take = || std::mem::take(&mut *RECORDER.0.lock().unwrap());
+
+    assert_eq!(ez_eval("1+2*3", &mut EmptyNamespace), Ok(7.0));
+    assert_eq!(
+        take(),
+        [
+            "value 1",
+            "value 2",
+            "value 3",
+            "op 2 * 3 = 6",
+            "op 1 + 6 = 7"
+        ]
+    );
+
+    // Parentheses are evaluated first, and negation is part of a value:
+    assert_eq!(
+        ez_eval("2^(1+1) - -x", &mut |_: &str, _| Some(0.5)),
+        Ok(4.5)
+    );
+    assert_eq!(
+        take(),
+        [
+            "value 2",
+            "value 1",
+            "value 1",
+            "op 1 + 1 = 2",
+            "value 2",
+            "value -0.5",
+            "op 2 ^ 2 = 4",
+            "op 4 - -0.5 = 4.5",
+        ]
+    );
+
+    // Nothing is reported below the trace level:
+    log::set_max_level(log::LevelFilter::Debug);
+    assert_eq!(ez_eval("1+2", &mut EmptyNamespace), Ok(3.0));
+    assert!(take().is_empty());
+}
@@ -0,0 +1,33 @@
+// Checks that the crate builds without `std`, as it does for embedded targets
+// like `thumbv7em-none-eabihf`.  The host target is used, so that no other
+// target has to be installed: `#![no_std]` rejects any use of `std` on every
+// target.  A separate target directory keeps the outer build unlocked.
+
+use std::process::Command;
+
+#[test]
+fn builds_without_std() {
+    for features in [
+        "libm",
+        "libm,alpha-keywords,serde",
+        "libm,unsafe-vars",
+        "libm,log",
+    ] {
+        let out = Command::new(env!("CARGO"))
+            .args(["check", "--lib", "--no-default-features", "--features"])
+            .arg(features)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .env(
+                "CARGO_TARGET_DIR",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/target/no_std"),
+            )
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "--features {}:\n{}",
+            features,
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+mod common;
+
+use fasteval2::{
+    Compiler, EmptyNamespace, Error, Evaler, Instruction, OptReport, OptRule, Optimizer, Parser,
+    Slab,
+};
+
+static VARS: [&str; 3] = ["x", "y", "z"];
+
+// Each rule fires at least once in here:
+static CORPUS: &[&str] = &[
+    "-(x*3)",
+    "-x * 3",
+    "y - 2*x",
+    "y - x/4",
+    "-abs(x) * 2",
+    "-(x * -1)",
+    "(-x) * -1",
+    "-(-(x+1)*-1) + 2",
+    "x^2 + y^2",
+    "z^2 - x^2 * y",
+    "(x+y)^2",
+    "x^1 - (y*z)^1",
+    "2^x * x^2",
+    "-(x*2) * 3",
+    "(x + 0.1)^1 + 0.2",
+    "(y*3)^1 * 7",
+    "(x*2) / (1/3)",
+    "(x+1) - (-2)",
+    "sum(i, 1, 3, -(i*x) * 2 + i^2)",
+    "min(-y*3, x^2) + piecewise(x < 0, -(z*0.5), y^2)",
+    "fma(-x*3, y^2, -(z*2))",
+    "x * y + z",
+];
+
+fn rand_inputs(n: usize) -> Vec<[f64; 3]> {
+    let mut rand = common::rand_gen(0x2545_f491_4f6c_dd1d);
+    let special = [
+        0.0,
+        -0.0,
+        1.0,
+        -1.0,
+        f64::NAN,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::MIN_POSITIVE,
+        1e308,
+        -1e-310,
+    ];
+    let mut val = move || {
+        let r = rand();
+        match r % 4 {
+            0 => special[(r >> 8) as usize % special.len()],
+            1 => ((r >> 8) % 21) as f64 - 10.0,
+            _ => ((r >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * 20.0,
+        }
+    };
+    (0..n).map(|_| [val(), val(), val()]).collect()
+}
+
+fn compile(expr_str: &str, slab: &mut Slab) -> Result<Instruction, Error> {
+    Ok(Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace))
+}
+
+fn eval(instr: &Instruction, slab: &Slab, vars: &[f64; 3]) -> f64 {
+    let mut ns = |name: &str, _: Vec<f64>| VARS.iter().position(|v| *v == name).map(|i| vars[i]);
+    instr.eval(slab, &mut ns).unwrap()
+}
+
+fn same(a: f64, b: f64) -> bool {
+    a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+}
+
+fn only(rule: OptRule) -> Optimizer {
+    OptRule::ALL
+        .iter()
+        .filter(|r| **r != rule)
+        .fold(Optimizer::new().fast_math(true), |opt, r| opt.without(*r))
+}
+
+/// Optimizes every expression of the corpus, and checks the results against
+/// the unoptimized `Instruction` with `check`.  Returns the combined reports.
+fn differential(opt: &Optimizer, inputs: &[[f64; 3]], check: fn(f64, f64) -> bool) -> OptReport {
+    let mut total = OptReport::default();
+    for expr_str in CORPUS {
+        let mut slab = Slab::new();
+        let plain = compile(expr_str, &mut slab).unwrap();
+        let mut opt_slab = Slab::new();
+        let instr = compile(expr_str, &mut opt_slab).unwrap();
+        let (instr, report) = opt.optimize_with_report(instr, &mut opt_slab.cs);
+        for vars in inputs {
+            let want = eval(&plain, &slab, vars);
+            let got = eval(&instr, &opt_slab, vars);
+            assert!(
+                check(got, want),
+                "{} with {:?}: optimized={:?} unoptimized={:?} ({:?})",
+                expr_str,
+                vars,
+                got,
+                want,
+                report
+            );
+        }
+        for (rule, n) in report.fired {
+            *total.fired.entry(rule).or_insert(0) += n;
+        }
+        total.before += report.before;
+        total.after += report.after;
+    }
+    total
+}
+
+#[test]
+fn preserves_results() {
+    let inputs = rand_inputs(2000);
+    let report = differential(&Optimizer::new(), &inputs, same);
+    assert!(report.eliminated() > 0);
+    for rule in OptRule::ALL {
+        assert_eq!(
+            report.count(rule) > 0,
+            !rule.requires_fast_math(),
+            "{:?}",
+            rule
+        );
+    }
+
+    // One rule at a time, so that a rule can't hide the effects of another.
+    // compile() already removes double negations, so DoubleNeg only fires
+    // after another rule has run:
+    for rule in OptRule::ALL {
+        if rule.requires_fast_math() {
+            continue;
+        }
+        let report = differential(&only(rule), &inputs, same);
+        assert!(report.fired.keys().all(|r| *r == rule), "{:?}", report);
+        assert_eq!(
+            report.count(rule) > 0,
+            rule != OptRule::DoubleNeg,
+            "{:?}",
+            rule
+        );
+    }
+}
+
+#[test]
+fn fast_math() {
+    // Reassociation only changes the rounding when nothing overflows:
+    let inputs = rand_inputs(2000)
+        .into_iter()
+        .filter(|vars| vars.iter().all(|v| v.is_finite() && v.abs() < 1e6))
+        .collect::<Vec<_>>();
+    let close = |a: f64, b: f64| same(a, b) || (a - b).abs() <= 1e-12 * a.abs().max(b.abs());
+    for rule in [OptRule::MulConstChain, OptRule::AddConstChain] {
+        assert!(!Optimizer::new().is_enabled(rule));
+        assert!(Optimizer::new().fast_math(true).is_enabled(rule));
+        let report = differential(&only(rule), &inputs, close);
+        assert!(report.count(rule) > 0, "{:?}", rule);
+    }
+    let report = differential(&Optimizer::new().fast_math(true), &inputs, close);
+    assert!(OptRule::ALL.iter().all(|rule| report.count(*rule) > 0));
+
+    // The chains really are different in the corner cases:
+    let mut slab = Slab::new();
+    let instr = compile("-(x*1e300)*1e-300", &mut slab).unwrap();
+    let instr = Optimizer::new()
+        .fast_math(true)
+        .optimize(instr, &mut slab.cs);
+    assert_eq!(eval(&instr, &slab, &[1e10, 0.0, 0.0]), -1e10);
+    let mut slab = Slab::new();
+    let instr = compile("-(x*1e300)*1e-300", &mut slab).unwrap();
+    assert_eq!(eval(&instr, &slab, &[1e10, 0.0, 0.0]), f64::NEG_INFINITY);
+}
+
+#[test]
+fn report() {
+    let mut slab = Slab::new();
+    let instr = compile("-(x*3) + -(-(y*0.5)*-1)", &mut slab).unwrap();
+    let (instr, report) = Optimizer::new().optimize_with_report(instr, &mut slab.cs);
+    assert_eq!(report.count(OptRule::NegFusion), 4);
+    assert_eq!(report.count(OptRule::MulIdentity), 1);
+    assert_eq!(report.count(OptRule::DoubleNeg), 0);
+    assert_eq!(report.fired.len(), 2);
+    assert_eq!(
+        (report.before, report.after, report.eliminated()),
+        (9, 5, 4)
+    );
+    assert_eq!(eval(&instr, &slab, &[1.0, 2.0, 0.0]), -4.0);
+
+    // Disabled rules don't fire:
+    let mut slab = Slab::new();
+    let instr = compile("-(x*3) + -(-(y*0.5)*-1)", &mut slab).unwrap();
+    let opt = Optimizer::new()
+        .without(OptRule::NegFusion)
+        .without(OptRule::NegFusion);
+    assert!(!opt.is_enabled(OptRule::NegFusion));
+    assert!(opt.is_enabled(OptRule::DoubleNeg));
+    let (_, report) = opt.optimize_with_report(instr, &mut slab.cs);
+    assert_eq!(report.count(OptRule::NegFusion), 0);
+    assert_eq!(report.count(OptRule::DoubleNeg), 1);
+
+    // Square trades a pow() call for another variable lookup:
+    let mut slab = Slab::new();
+    let instr = compile("x^2", &mut slab).unwrap();
+    let (instr, report) = Optimizer::new().optimize_with_report(instr, &mut slab.cs);
+    assert_eq!(
+        (report.before, report.after, report.eliminated()),
+        (2, 3, 0)
+    );
+    assert_eq!(eval(&instr, &slab, &[-3.0, 0.0, 0.0]), 9.0);
+
+    // Nothing to do:
+    let mut slab = Slab::new();
+    let instr = compile("x * y + z", &mut slab).unwrap();
+    let (instr, report) = Optimizer::new().optimize_with_report(instr, &mut slab.cs);
+    assert_eq!(report.fired.len(), 0);
+    assert_eq!((report.before, report.after), (5, 5));
+    assert_eq!(eval(&instr, &slab, &[2.0, 3.0, 4.0]), 10.0);
+
+    // The shorthand uses the default rules:
+    let mut slab = Slab::new();
+    let instr = compile("1 - (x*3)^1", &mut slab)
+        .unwrap()
+        .optimize(&mut slab.cs);
+    assert_eq!(format!("{:?}", instr), "IAdd(InstructionI(3), C(1.0))");
+    assert_eq!(
+        format!("{:?}", slab.cs.get_instr(fasteval2::InstructionI(3))),
+        "IMul(InstructionI(0), C(-3.0))"
+    );
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "rayon")]
+
+use fasteval2::{par_eval_all, EvalNS, Evaler, Parser, Slab};
+
+use std::collections::BTreeMap;
+
+#[test]
+fn same_as_sequential() {
+    let mut slab = Slab::with_capacity(20_000);
+    let exprs: Vec<_> = (0..5_000)
+        .map(|i| {
+            let expr_str = match i % 4 {
+                0 => format!("x * {} + y", i),
+                1 => format!("sum(j, 1, {}, j) / x", i % 50),
+                2 => "rand()".to_string(),
+                _ => format!("z{} + 1", i), // Undefined.
+            };
+            Parser::new()
+                .parse_noclear(&expr_str, &mut slab.ps)
+                .unwrap()
+        })
+        .collect();
+    let make_ns = || {
+        let vars = BTreeMap::from([("x".to_string(), 2.5), ("y".to_string(), -1.0)]);
+        EvalNS::new(vars).with_rng(7).with_budget(1_000)
+    };
+
+    let sequential: Vec<_> = exprs
+        .iter()
+        .map(|&expr_i| slab.ps.get_expr(expr_i).eval(&slab, &mut make_ns()))
+        .collect();
+    assert_eq!(par_eval_all(&slab, &exprs, make_ns), sequential);
+    assert!(sequential[3].is_err());
+    // Each expression has its own namespace, so the random numbers repeat:
+    assert_eq!(sequential[2], sequential[6]);
+
+    assert_eq!(par_eval_all(&slab, &[], make_ns), vec![]);
+}
+
+#[cfg(feature = "unsafe-vars")]
+#[test]
+fn unsafe_vars() {
+    let mut slab = Slab::new();
+    let exprs = [Parser::new().parse_noclear("x + 1", &mut slab.ps).unwrap()];
+    let make_ns = || EvalNS::new(BTreeMap::from([("x".to_string(), 2.0)]));
+    assert_eq!(par_eval_all(&slab, &exprs, make_ns), [Ok(3.0)]);
+
+    // The pointers can't be shared between threads, even for the expressions
+    // that don't read them:
+    let deg = 1.0;
+    unsafe { slab.ps.add_unsafe_var("deg".to_string(), &deg) };
+    assert_eq!(
+        par_eval_all(&slab, &exprs, make_ns),
+        [Err(fasteval2::Error::NotAllowed("deg".to_string()))]
+    );
+}
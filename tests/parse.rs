@@ -1,4 +1,8 @@
-use fasteval2::{Error, Parser, Slab};
+use fasteval2::parser::BinaryOp;
+use fasteval2::{
+    Arity, Compiler, EmptyNamespace, Error, Evaler, Parser, Printer, Slab, Token, TokenKind,
+    TokenizerConfig,
+};
 
 #[test]
 fn basics() {
@@ -218,7 +222,7 @@ fn unsafe_var() {
                         match v.get(start) {
                             None => break,
                             Some(&b) => {
-                                if (b'0' <= b && b <= b'9') || (b'a' <= b && b <= b'f') {
+                                if b.is_ascii_digit() || (b'a'..=b'f').contains(&b) {
                                     v[start] = b'?';
                                     start += 1;
                                 } else {
@@ -246,3 +250,1077 @@ fn unsafe_var() {
     assert_eq!(replace_addrs(format!("{:?}",&slab)),
 "Slab{ exprs:{ 0:Expression { first: EStdFunc(EUnsafeVar { name: \"ua\", ptr: 0x???????????? }), pairs: [ExprPair(EAdd, EStdFunc(EUnsafeVar { name: \"ub\", ptr: 0x???????????? })), ExprPair(EAdd, EConstant(5.0))] } }, vals:{}, instrs:{} }");
 }
+
+#[test]
+fn recovery() {
+    use fasteval2::{Compiler, EmptyNamespace, Evaler};
+
+    let mut slab = Slab::new();
+
+    // (`2 + + 3` is valid because of unary plus, so use binary-only operators:)
+    let (expr_opt, errs) = Parser::new().parse_with_recovery("2 + * 3 * * 4", &mut slab.ps);
+    assert_eq!(errs, vec![Error::InvalidValue, Error::InvalidValue]);
+    assert_eq!(format!("{:?}",&slab),
+"Slab{ exprs:{ 0:Expression { first: EConstant(2.0), pairs: [ExprPair(EAdd, EError(InvalidValue)), ExprPair(EMul, EConstant(3.0)), ExprPair(EMul, EError(InvalidValue)), ExprPair(EMul, EConstant(4.0))] } }, vals:{}, instrs:{} }");
+    let expr_ref = expr_opt.unwrap().from(&slab.ps);
+    assert_eq!(
+        expr_ref.eval(&slab, &mut EmptyNamespace),
+        Err(Error::InvalidValue)
+    );
+    let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(
+        instr.eval(&slab, &mut EmptyNamespace),
+        Err(Error::InvalidValue)
+    );
+
+    // Different kinds of errors, including garbage and trailing input:
+    let (expr_opt, errs) =
+        Parser::new().parse_with_recovery("sin(1, 2) + $$ 3 - -) 4", &mut slab.ps);
+    assert!(expr_opt.is_some());
+    assert_eq!(
+        errs,
+        vec![
+            Error::WrongArgCount {
+                name: "sin".to_string(),
+                expected: Arity::Exact(1),
+                got: 2
+            },
+            Error::InvalidValue,
+            Error::InvalidValue,
+            Error::UnparsedTokensRemaining(") 4".to_string()),
+        ]
+    );
+
+    // Valid input has no errors:
+    let (expr_opt, errs) = Parser::new().parse_with_recovery("1 + 2", &mut slab.ps);
+    assert_eq!(errs, vec![]);
+    assert_eq!(
+        expr_opt
+            .unwrap()
+            .from(&slab.ps)
+            .eval(&slab, &mut EmptyNamespace),
+        Ok(3.0)
+    );
+
+    // Unrecoverable errors don't produce an expression:
+    let (expr_opt, errs) = Parser::new().parse_with_recovery("1 + * (2", &mut slab.ps);
+    assert!(expr_opt.is_none());
+    assert_eq!(
+        errs,
+        vec![
+            Error::InvalidValue,
+            Error::EofWhileParsing("parentheses".to_string())
+        ]
+    );
+
+    // The normal parse() is unaffected:
+    assert_eq!(
+        Parser::new().parse("2 + * 3", &mut slab.ps),
+        Err(Error::InvalidValue)
+    );
+}
+
+#[test]
+fn empty_and_dangling() {
+    let mut slab = Slab::new();
+    for s in ["", " ", "   ", "\t\r\n ", "\\\n", " \\\r\n "] {
+        assert_eq!(
+            Parser::new().parse(s, &mut slab.ps),
+            Err(Error::EmptyExpression),
+            "{:?}",
+            s
+        );
+    }
+    assert_eq!(
+        Parser::new().parse_with_recovery("  ", &mut slab.ps),
+        (None, vec![Error::EmptyExpression])
+    );
+    assert_eq!(
+        fasteval2::ez_eval("", &mut fasteval2::EmptyNamespace),
+        Err(Error::EmptyExpression)
+    );
+    // ...and so do the entry points that compile straight from a string:
+    assert_eq!(
+        fasteval2::CompiledExpression::compile("   ", &mut fasteval2::EmptyNamespace).err(),
+        Some(Error::EmptyExpression)
+    );
+    assert_eq!(
+        fasteval2::ExpressionCache::new(4)
+            .get_or_parse("", &Parser::new())
+            .err(),
+        Some(Error::EmptyExpression)
+    );
+
+    // Dangling operators run out of input while looking for a value:
+    for s in ["1 +", "1 + ", "1 *", "-", "(1) ^ "] {
+        assert_eq!(
+            Parser::new().parse(s, &mut slab.ps),
+            Err(Error::EofWhileParsing("value".to_string())),
+            "{:?}",
+            s
+        );
+    }
+    // ...which is different from empty sub-expressions:
+    assert_eq!(
+        Parser::new().parse("()", &mut slab.ps),
+        Err(Error::InvalidValue)
+    );
+}
+
+#[test]
+fn slab_growth() {
+    let expr_str = "1 + (2 * (3 - (4 / (5))))";
+
+    let mut slab = Slab::with_capacity(2);
+    assert_eq!(
+        Parser::new().parse(expr_str, &mut slab.ps),
+        Err(Error::SlabOverflow)
+    );
+
+    let mut slab = Slab::with_capacity_and_limit(2, 5);
+    assert!(Parser::new().parse(expr_str, &mut slab.ps).is_ok());
+    assert_eq!(
+        Parser::new().parse("1 + (2 * (3 - (4 / (5 + (6)))))", &mut slab.ps),
+        Err(Error::SlabOverflow)
+    );
+
+    // The slab can be re-used after clear():
+    slab.clear();
+    assert_eq!(
+        fasteval2::Evaler::eval(
+            Parser::new()
+                .parse(expr_str, &mut slab.ps)
+                .unwrap()
+                .from(&slab.ps),
+            &slab,
+            &mut fasteval2::EmptyNamespace
+        ),
+        Ok(5.4)
+    );
+}
+
+#[test]
+fn thousands_separators() {
+    let parser = Parser::builder()
+        .thousands_separators(true)
+        .build()
+        .unwrap();
+    let eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut fasteval2::EmptyNamespace)
+    };
+
+    assert_eq!(eval("1,234 + 1"), Ok(1235.0));
+    assert_eq!(eval("1,234.56"), Ok(1234.56));
+    assert_eq!(eval("-12,345,678"), Ok(-12345678.0));
+    assert_eq!(eval("999"), Ok(999.0));
+    assert_eq!(eval("1,000k"), Ok(1000000.0));
+    assert_eq!(eval("1,000e3"), Ok(1000000.0));
+    assert_eq!(eval("max(1,000; 2,000)"), Ok(2000.0));
+
+    assert_eq!(eval("1,23"), Err(Error::ParseF64("1,23".to_string())));
+    assert_eq!(eval("1,2345"), Err(Error::ParseF64("1,2345".to_string())));
+    assert_eq!(
+        eval("1234,567"),
+        Err(Error::ParseF64("1234,567".to_string()))
+    );
+    assert_eq!(eval("1,,234"), Err(Error::ParseF64("1,,234".to_string())));
+    assert_eq!(eval("1, 234"), Err(Error::ParseF64("1,".to_string())));
+    assert_eq!(eval("max(1; 2, 3)"), Err(Error::ParseF64("2,".to_string())));
+    assert_eq!(eval("max(x, y)"), Err(Error::Expected("';'".to_string())));
+
+    // Without the flag, commas still separate arguments:
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("1,234", &mut slab.ps),
+        Err(Error::UnparsedTokensRemaining(",234".to_string()))
+    );
+    assert!(Parser::new().parse("max(1,234)", &mut slab.ps).is_ok());
+}
+
+#[test]
+fn decimal_comma() {
+    let parser = Parser::builder().decimal_comma(true).build().unwrap();
+    let eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut fasteval2::EmptyNamespace)
+    };
+
+    assert_eq!(eval("1,5 + 1"), Ok(2.5));
+    assert_eq!(eval("-0,25e2"), Ok(-25.0));
+    assert_eq!(eval(",5k"), Ok(500.0));
+    assert_eq!(eval("max(1,5; 2,5)"), Ok(2.5));
+    assert_eq!(eval("1,2,3"), Err(Error::ParseF64("1.2.3".to_string())));
+    assert_eq!(
+        eval("1.5"),
+        Err(Error::UnparsedTokensRemaining(".5".to_string()))
+    );
+    assert_eq!(eval("max(x, y)"), Err(Error::Expected("';'".to_string())));
+}
+
+#[test]
+fn tokenizer() {
+    // '$' can start and continue identifiers, and '-' can continue them:
+    struct Sheet;
+    impl TokenizerConfig for Sheet {
+        fn is_ident_start(&self, c: char) -> bool {
+            c == '$' || c.is_ascii_alphabetic()
+        }
+        fn is_ident_continue(&self, c: char) -> bool {
+            c == '$' || c == '-' || c.is_ascii_alphanumeric()
+        }
+    }
+    let parser = Parser::builder().tokenizer(Sheet).build().unwrap();
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "$A$1" => Some(10.0),
+        "unit-price" => Some(2.5),
+        "x" => Some(3.0),
+        "$sum" => Some(args.iter().sum()),
+        _ => None,
+    };
+    let mut eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut ns)
+    };
+    assert_eq!(eval("$A$1 * unit-price"), Ok(25.0));
+    assert_eq!(eval("-unit-price - x"), Ok(-5.5));
+    assert_eq!(eval("$sum(x, $A$1) - 1"), Ok(12.0));
+    assert_eq!(
+        eval("x-1"),
+        Err(Error::UndefinedVariable {
+            name: "x-1".to_string()
+        })
+    );
+    assert_eq!(eval("_x"), Err(Error::InvalidValue));
+
+    // Identifiers are made of chars, not bytes:
+    struct Greek;
+    impl TokenizerConfig for Greek {
+        fn is_ident_start(&self, c: char) -> bool {
+            c.is_alphabetic()
+        }
+        fn is_ident_continue(&self, c: char) -> bool {
+            c.is_alphanumeric()
+        }
+        fn is_operator(&self, c: char) -> bool {
+            c != '%'
+        }
+    }
+    let parser = Parser::builder().tokenizer(Greek).build().unwrap();
+    let mut slab = Slab::new();
+    let expr_ref = parser
+        .parse("2 * πr² + 1", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut ns = |name: &str, _: Vec<f64>| (name == "πr²").then_some(3.0);
+    assert_eq!(fasteval2::Evaler::eval(expr_ref, &slab, &mut ns), Ok(7.0));
+    assert_eq!(
+        parser.parse("7 % 2", &mut slab.ps),
+        Err(Error::UnparsedTokensRemaining("% 2".to_string()))
+    );
+    assert!(parser.parse("1 or 0 and 1", &mut slab.ps).is_ok());
+
+    // The default config is the same as no config:
+    struct Ascii;
+    impl TokenizerConfig for Ascii {}
+    let parser = Parser::builder().tokenizer(Ascii).build().unwrap();
+    for s in ["x_1 + _y2 * 3", "1 + é", "$x", "x-1", "a || b && !c != d"] {
+        let mut slab = Slab::new();
+        assert_eq!(
+            parser
+                .parse(s, &mut slab.ps)
+                .map(|e| format!("{:?}", e.from(&slab.ps))),
+            Parser::new()
+                .parse(s, &mut slab.ps)
+                .map(|e| format!("{:?}", e.from(&slab.ps))),
+            "{}",
+            s
+        );
+    }
+}
+
+#[test]
+fn aliases() {
+    let parser = Parser::builder()
+        .add_alias("and", "&&")
+        .add_alias("or", "||")
+        .add_alias("not", "!")
+        .add_alias("mod", "%")
+        .add_alias("AND", "&&")
+        .build()
+        .unwrap();
+    let mut ns = |name: &str, _: Vec<f64>| match name {
+        "modulus" => Some(10.0),
+        "notice" | "android" => Some(1.0),
+        _ => None,
+    };
+    let mut eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut ns)
+    };
+
+    assert_eq!(eval("1 and 0"), Ok(0.0));
+    assert_eq!(eval("5 mod 3"), Ok(2.0));
+    assert_eq!(eval("1 AND 2"), Ok(2.0));
+    assert_eq!(eval("0 or not 0"), Ok(1.0));
+    assert_eq!(eval("not(1 > 2) and 7 mod(4)"), Ok(3.0));
+    assert_eq!(eval("(5)mod 3"), Ok(2.0));
+    // Names that start with an alias are still names:
+    assert_eq!(eval("modulus mod 3"), Ok(1.0));
+    assert_eq!(eval("notice + android"), Ok(2.0));
+    assert_eq!(eval("not notice"), Ok(0.0));
+    assert_eq!(
+        eval("5 modulus"),
+        Err(Error::UnparsedTokensRemaining("modulus".to_string()))
+    );
+
+    // The words and operators are checked by build():
+    for (word, op) in [
+        ("mod", "%%"),
+        ("mod", "("),
+        ("mod 2", "%"),
+        ("", "!"),
+        ("2x", "+"),
+    ] {
+        assert!(matches!(
+            Parser::builder().add_alias(word, op).build(),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+    // ...and without aliases, the words are names:
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("5 mod 3", &mut slab.ps),
+        Err(Error::UnparsedTokensRemaining("mod 3".to_string()))
+    );
+}
+
+#[test]
+fn percent() {
+    let parser = Parser::builder()
+        .percent(true)
+        .add_alias("of", "*")
+        .build()
+        .unwrap();
+    let mut ns = |name: &str, _: Vec<f64>| match name {
+        "half" => Some(0.5),
+        "x" => Some(7.0),
+        _ => None,
+    };
+    let mut eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut ns)
+    };
+
+    assert_eq!(eval("20% of 100"), Ok(20.0));
+    assert_eq!(eval("half of 10"), Ok(5.0));
+    // `of` has the same precedence as `*`:
+    assert_eq!(eval("1 + 50% of 10 ^ 2"), Ok(51.0));
+    assert_eq!(eval("200 - 10% of 200 / 4"), Ok(195.0));
+    assert_eq!(eval("max(25%, 1%) * 4"), Ok(1.0));
+    assert_eq!(eval("(50%) + 1.5k%"), Ok(15.5));
+    assert_eq!(eval("20%-3"), Ok(-2.8));
+    // Without an operator after it, `%` is still the remainder:
+    assert_eq!(eval("20%3"), Ok(2.0));
+    assert_eq!(eval("20 % -3"), Ok(2.0));
+    assert_eq!(eval("x % 4"), Ok(3.0));
+    assert_eq!(eval("20 %(3)"), Ok(2.0));
+    assert_eq!(eval("x%"), Err(Error::EofWhileParsing("value".to_string())));
+
+    // ...and without the option, `%` is always the remainder:
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("20%", &mut slab.ps),
+        Err(Error::EofWhileParsing("value".to_string()))
+    );
+}
+
+#[test]
+fn as_const() {
+    let as_const = |s: &str| {
+        let mut slab = Slab::new();
+        let expr_i = Parser::new().parse(s, &mut slab.ps).unwrap();
+        expr_i.from(&slab.ps).as_const()
+    };
+
+    assert_eq!(as_const("42"), Some(42.0));
+    assert_eq!(as_const(" -1.5e3 "), Some(-1500.0));
+    assert_eq!(as_const("2k"), Some(2000.0));
+    assert!(as_const("NaN").unwrap().is_nan());
+    assert_eq!(as_const("x+1"), None);
+    assert_eq!(as_const("x"), None);
+    assert_eq!(as_const("1 + 1"), None);
+    assert_eq!(as_const("(42)"), None);
+    assert_eq!(as_const("- 42"), None);
+    assert_eq!(as_const("pi()"), None);
+
+    // A tuple is several values, even if the first one is a number:
+    let mut slab = Slab::new();
+    let parser = Parser::builder().tuples(true).build().unwrap();
+    let expr_i = parser.parse("1, 2", &mut slab.ps).unwrap();
+    assert_eq!(expr_i.from(&slab.ps).as_const(), None);
+    let expr_i = parser.parse("1", &mut slab.ps).unwrap();
+    assert_eq!(expr_i.from(&slab.ps).as_const(), Some(1.0));
+}
+
+#[test]
+fn equals_is_comparison() {
+    let parser = Parser::builder()
+        .equals_is_comparison(true)
+        .build()
+        .unwrap();
+    let eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut fasteval2::EmptyNamespace)
+    };
+
+    assert_eq!(eval("1 = 1"), Ok(1.0));
+    assert_eq!(eval("1=2"), Ok(0.0));
+    assert_eq!(eval("1 + 1 = 2 && 3 = 3"), Ok(1.0));
+    assert_eq!(eval("1 == 1"), Ok(1.0));
+    assert_eq!(eval("1 <= 1"), Ok(1.0));
+    assert_eq!(eval("1 >= 2"), Ok(0.0));
+    assert_eq!(eval("1 != 1"), Ok(0.0));
+    assert!(eval("1 = = 1").is_err());
+
+    let mut slab = Slab::new();
+    assert!(Parser::new().parse("1 = 1", &mut slab.ps).is_err());
+}
+
+#[test]
+fn tuples() {
+    let parser = Parser::builder().tuples(true).build().unwrap();
+    let eval_vec = |parser: &Parser, s: &str| -> Result<Vec<f64>, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        let mut ns = |name: &str, _: Vec<f64>| match name {
+            "x" => Some(2.0),
+            _ => None,
+        };
+        expr_ref.eval_vec(&slab, &mut ns)
+    };
+
+    assert_eq!(eval_vec(&parser, "2*1, 2*2"), Ok(vec![2.0, 4.0]));
+    assert_eq!(eval_vec(&parser, "3, 4"), Ok(vec![3.0, 4.0]));
+    assert_eq!(
+        eval_vec(&parser, " x ; max(x, 5), min(1, 2) * 2 "),
+        Ok(vec![2.0, 5.0, 2.0])
+    );
+    assert_eq!(eval_vec(&parser, "x + 1"), Ok(vec![3.0]));
+    assert_eq!(eval_vec(&Parser::new(), "x + 1"), Ok(vec![3.0]));
+    assert!(eval_vec(&parser, "1,").is_err());
+    assert!(eval_vec(&parser, ", 1").is_err());
+    assert!(eval_vec(&parser, "(1, 2)").is_err());
+    assert!(eval_vec(&Parser::new(), "1, 2").is_err());
+    assert_eq!(
+        eval_vec(&parser, "1, y"),
+        Err(Error::UndefinedVariable {
+            name: "y".to_string()
+        })
+    );
+
+    // With a comma in numbers, only ';' separates items:
+    let decimal = Parser::builder()
+        .decimal_comma(true)
+        .tuples(true)
+        .build()
+        .unwrap();
+    assert_eq!(eval_vec(&decimal, "1,5; 2,5"), Ok(vec![1.5, 2.5]));
+
+    // A tuple has no single value:
+    let mut slab = Slab::new();
+    let expr_ref = parser.parse("x, x*2", &mut slab.ps).unwrap().from(&slab.ps);
+    let tuple = Err(Error::NotAllowed("tuple".to_string()));
+    assert_eq!(Evaler::eval(expr_ref, &slab, &mut EmptyNamespace), tuple);
+    let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(Evaler::eval(&instr, &slab, &mut EmptyNamespace), tuple);
+    assert_eq!(
+        expr_ref.var_names(&slab).into_iter().collect::<Vec<_>>(),
+        ["x"]
+    );
+    assert_eq!(Printer::new().print(expr_ref, &slab), "x, x * 2");
+}
+
+#[test]
+fn long_names() {
+    // Names longer than the 22 bytes that are stored inline:
+    let long_var = "acceleration_due_to_gravity";
+    let long_func = "the_longest_function_name_yet";
+    let mut slab = Slab::new();
+    let expr_ref = {
+        // The parsed names don't borrow from the input:
+        let input = format!("{}(x_22_bytes_long_name_, {}) * 2", long_func, long_var);
+        Parser::new()
+            .parse(&input, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps)
+    };
+    assert_eq!(format!("{:?}", slab.ps),
+"ParseSlab{ exprs:{ 0:Expression { first: EStdFunc(EVar(\"x_22_bytes_long_name_\")), pairs: [] }, 1:Expression { first: EStdFunc(EVar(\"acceleration_due_to_gravity\")), pairs: [] }, 2:Expression { first: EStdFunc(EFunc { name: \"the_longest_function_name_yet\", args: [ExpressionI(0), ExpressionI(1)] }), pairs: [ExprPair(EMul, EConstant(2.0))] } }, vals:{} }");
+
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "x_22_bytes_long_name_" => Some(3.0),
+        "acceleration_due_to_gravity" => Some(9.81),
+        "the_longest_function_name_yet" => Some(args.iter().sum()),
+        _ => None,
+    };
+    assert_eq!(expr_ref.eval(&slab, &mut ns), Ok((3.0 + 9.81) * 2.0));
+    assert_eq!(
+        expr_ref.var_names(&slab).into_iter().collect::<Vec<_>>(),
+        [long_var, long_func, "x_22_bytes_long_name_"]
+    );
+    assert_eq!(
+        Printer::new().print(expr_ref, &slab),
+        "the_longest_function_name_yet(x_22_bytes_long_name_, acceleration_due_to_gravity) * 2"
+    );
+}
+
+#[test]
+fn partial() {
+    let partial = |s: &str| -> Result<(usize, f64), Error> {
+        let mut slab = Slab::new();
+        let (expr_i, len) = Parser::new().parse_partial(s, &mut slab.ps)?;
+        let mut ns = |name: &str, _: Vec<f64>| match name {
+            "x" => Some(2.0),
+            "y" => Some(3.0),
+            _ => None,
+        };
+        let val = fasteval2::Evaler::eval(expr_i.from(&slab.ps), &slab, &mut ns)?;
+        Ok((len, val))
+    };
+
+    assert_eq!(partial("1+2} rest"), Ok((3, 3.0)));
+    assert_eq!(partial("x*y, next"), Ok((3, 6.0)));
+    assert_eq!(partial("  1 + 2  }} rest"), Ok((7, 3.0)));
+    assert_eq!(partial("max(1, 2), 3"), Ok((9, 2.0)));
+    assert_eq!(partial("(1, 2)"), Err(Error::Expected(")".to_string())));
+    assert_eq!(
+        partial(
+            "x \
+ }"
+        ),
+        Ok((1, 2.0))
+    );
+    assert_eq!(partial("1 2"), Ok((1, 1.0)));
+    assert_eq!(partial("-x"), Ok((2, -2.0)));
+    // A trailing binary operator isn't consumed:
+    assert_eq!(partial("1 + 2 *} rest"), Ok((5, 3.0)));
+    assert_eq!(partial("x +"), Ok((1, 2.0)));
+    assert_eq!(partial("x >= , y"), Ok((1, 2.0)));
+    // ...but a value that is cut short is still an error:
+    assert_eq!(partial("1 + -}"), Err(Error::InvalidValue));
+    assert_eq!(partial("1 + (2 }"), Err(Error::Expected(")".to_string())));
+    assert_eq!(partial("} 1"), Err(Error::InvalidValue));
+    assert_eq!(partial("   "), Err(Error::EmptyExpression));
+
+    // The whole input is the same as parse():
+    for s in ["1+2", "x * (y - 1)", " sin(x) "] {
+        let mut slab = Slab::new();
+        let (expr_i, len) = Parser::new().parse_partial(s, &mut slab.ps).unwrap();
+        assert_eq!(len, s.trim_end().len());
+        let partial = format!("{:?}", expr_i.from(&slab.ps));
+        let expr_i = Parser::new().parse(s, &mut slab.ps).unwrap();
+        assert_eq!(partial, format!("{:?}", expr_i.from(&slab.ps)));
+    }
+}
+
+#[test]
+fn case_insensitive_funcs() {
+    fn eval(parser: &Parser, s: &str) -> Result<f64, Error> {
+        let mut ns = fasteval2::EvalNSBuilder::new()
+            .var("X", 0.0)
+            .func("Foo", |args| Some(args[0] * 2.0))
+            .case_insensitive_funcs()
+            .build();
+        let mut slab = Slab::new();
+        let expr_ref = parser.parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut ns)
+    }
+    let parser = Parser::builder()
+        .case_insensitive_funcs(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(eval(&parser, "SIN(0)"), Ok(0.0));
+    assert_eq!(eval(&parser, "MAX(X, 2) + Abs(-1)"), Ok(3.0));
+    assert_eq!(eval(&parser, "FOO(2) + foo(1) + Foo(1)"), Ok(8.0));
+    // Errors and var_names() have the lowercase names:
+    assert_eq!(
+        eval(&parser, "SIN(1, 2)"),
+        Err(Error::WrongArgCount {
+            name: "sin".to_string(),
+            expected: Arity::Exact(1),
+            got: 2
+        })
+    );
+    assert_eq!(
+        eval(&parser, "BAR(1)"),
+        Err(Error::UndefinedFunction {
+            name: "bar".to_string(),
+            arg_count: 1
+        })
+    );
+    let mut slab = Slab::new();
+    let src = "FOO(X) + Bar(x)";
+    let expr_i = parser.parse(src, &mut slab.ps).unwrap();
+    let names = fasteval2::Evaler::var_names(expr_i.from(&slab.ps), &slab);
+    assert_eq!(
+        names.into_iter().collect::<Vec<_>>(),
+        ["X", "bar", "foo", "x"]
+    );
+    // Spans have the original spelling:
+    let parser = Parser::builder()
+        .case_insensitive_funcs(true)
+        .record_spans(true)
+        .build()
+        .unwrap();
+    let expr_i = parser.parse(src, &mut slab.ps).unwrap();
+    assert_eq!(
+        expr_i.node_at(&slab.ps, 10).map(|span| &src[span]),
+        Some("Bar(x)")
+    );
+    // ...but variables keep their case:
+    assert_eq!(
+        eval(&parser, "x"),
+        Err(Error::UndefinedVariable {
+            name: "x".to_string()
+        })
+    );
+
+    // Off by default:
+    assert_eq!(
+        eval(&Parser::new(), "SIN(0)"),
+        Err(Error::UndefinedFunction {
+            name: "SIN".to_string(),
+            arg_count: 1
+        })
+    );
+    assert_eq!(
+        eval(&Parser::new(), "FOO(2)"),
+        Err(Error::UndefinedFunction {
+            name: "FOO".to_string(),
+            arg_count: 1
+        })
+    );
+}
+
+#[test]
+fn builder() {
+    let check = |parser: Parser, s: &str| {
+        parser
+            .parse(s, &mut Slab::new().ps)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    };
+
+    // The defaults are the same as Parser::new()'s:
+    assert_eq!(Parser::builder().build(), Ok(Parser::new()));
+    assert_eq!(Parser::default(), Parser::new());
+    assert_eq!(check(Parser::new(), "max(1,5)"), Ok(()));
+
+    let parser = Parser::builder().max_len(4).build().unwrap();
+    assert_eq!(check(parser.clone(), "1+23"), Ok(()));
+    assert_eq!(check(parser, "1+234"), Err("TooLong".to_string()));
+    let parser = Parser::builder().max_depth(2).build().unwrap();
+    assert_eq!(check(parser.clone(), "((1))"), Ok(()));
+    assert_eq!(check(parser, "(((1)))"), Err("TooDeep".to_string()));
+    let parser = Parser::builder().thousands_separators(true).build();
+    assert_eq!(check(parser.unwrap(), "max(1,000; 1)"), Ok(()));
+    let parser = Parser::builder().decimal_comma(true).build();
+    assert_eq!(check(parser.unwrap(), "1,5"), Ok(()));
+    let mut slab = Slab::new();
+    let parser = Parser::builder().record_spans(true).build().unwrap();
+    let expr_i = parser.parse("1 + 2", &mut slab.ps).unwrap();
+    assert_eq!(expr_i.span(&slab.ps), Some(0..5));
+
+    // Options that can't be used together are rejected by build():
+    assert_eq!(
+        Parser::builder()
+            .decimal_comma(true)
+            .thousands_separators(true)
+            .build(),
+        Err(Error::InvalidConfig(
+            "decimal_comma and thousands_separators both use ','".to_string()
+        ))
+    );
+    assert!(matches!(
+        Parser::builder().max_depth(0).build(),
+        Err(Error::InvalidConfig(_))
+    ));
+
+    // One Parser can be shared by many threads:
+    fn shareable<T: Clone + Send + Sync>(_: &T) {}
+    let parser = Parser::builder().max_len(100).build().unwrap();
+    shareable(&parser);
+    std::thread::scope(|scope| {
+        for _ in 0..2 {
+            scope.spawn(|| assert_eq!(check(parser.clone(), "1 + 2"), Ok(())));
+        }
+    });
+}
+
+#[test]
+fn multi_line() {
+    let eval = |s: &str| -> Result<f64, Error> {
+        let mut slab = Slab::new();
+        let expr_ref = Parser::new().parse(s, &mut slab.ps)?.from(&slab.ps);
+        fasteval2::Evaler::eval(expr_ref, &slab, &mut fasteval2::EmptyNamespace)
+    };
+
+    // A backslash-newline is whitespace:
+    assert_eq!(eval("2 + \\\n 3"), Ok(5.0));
+    assert_eq!(eval("2 +\\\n3"), Ok(5.0));
+    assert_eq!(eval("2 + \\\r\n 3"), Ok(5.0));
+    assert_eq!(eval("max(1, \\\n    \\\n    2) * \\\n\t4"), Ok(8.0));
+    assert_eq!(eval("\\\n 1 \\\n"), Ok(1.0));
+
+    // ...and so is a bare newline:
+    assert_eq!(eval("2 +\n 3"), Ok(5.0));
+    assert_eq!(eval("(1 +\r\n 2)\n * 3\n"), Ok(9.0));
+    assert_eq!(eval("2\n\n\n-\n1"), Ok(1.0));
+
+    // A backslash anywhere else is still an error:
+    let mut slab = Slab::new();
+    assert_eq!(
+        Parser::new().parse("2 + \\ 3", &mut slab.ps),
+        Err(Error::InvalidValue)
+    );
+    assert_eq!(
+        Parser::new().parse("2 \\", &mut slab.ps),
+        Err(Error::UnparsedTokensRemaining("\\".to_string()))
+    );
+    assert_eq!(
+        Parser::new().parse("2 \\\\\n 3", &mut slab.ps),
+        Err(Error::UnparsedTokensRemaining("\\\\\n 3".to_string()))
+    );
+}
+
+#[test]
+fn well_formed() {
+    let mut slab = Slab::new();
+    for s in [
+        "1",
+        "-(-x) + !!y * +3",
+        "((1 + 2) * (3 - -(4 ^ 5))) / 6 % 7",
+        "a && b || c == d != e < f <= g > h >= i",
+        "sin(cos(tan(x))) + log(2, x) + round(0.1, y) + abs(-x)",
+        "min(1, 2, 3) + max(x, -y) + fma(1, 2, 3)",
+        "piecewise(x < 0, -x, x < 1, x^2, 1)",
+        "sum(i, 1, n, sum(j, 1, i, i * j)) + interp(x, 0,0, 1,1, 2,4)",
+        r#"print("x is", x, -(x + 1)) + f(g(1), h(2, 3), k())"#,
+        "1e3 + 2k + pi + e()",
+    ] {
+        let expr_i = Parser::new().parse(s, &mut slab.ps).unwrap();
+        assert!(expr_i.is_well_formed(&slab.ps), "{}", s);
+    }
+
+    // Several expressions in one slab:
+    slab.ps.clear();
+    let xis: Vec<_> = ["-x", "(1 + 2) * -(y)", "max(-a, (b))"]
+        .iter()
+        .map(|s| Parser::new().parse_noclear(s, &mut slab.ps).unwrap())
+        .collect();
+    assert!(xis.iter().all(|xi| xi.is_well_formed(&slab.ps)));
+
+    // Recovered expressions too:
+    let (expr_opt, _) =
+        Parser::new().parse_with_recovery("-(1 + * 2) * sin(1, 2) + $", &mut slab.ps);
+    assert!(expr_opt.unwrap().is_well_formed(&slab.ps));
+
+    // Everything that parses, out of some generated garbage:
+    let alphabet = b"1x1x(),+-*^!<&";
+    let mut seed = 12345u64;
+    let mut parsed = 0;
+    for _ in 0..20_000 {
+        let len = 1 + seed as usize % 10;
+        let s: String = (0..len)
+            .map(|_| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                alphabet[(seed >> 33) as usize % alphabet.len()] as char
+            })
+            .collect();
+        if let Ok(expr_i) = Parser::new().parse(&s, &mut slab.ps) {
+            assert!(expr_i.is_well_formed(&slab.ps), "{}", s);
+            parsed += 1;
+        }
+        if let (Some(expr_i), _) = Parser::new().parse_with_recovery(&s, &mut slab.ps) {
+            assert!(expr_i.is_well_formed(&slab.ps), "{}", s);
+        }
+        match Parser::new().parse_all_errors(&s, &mut slab.ps) {
+            Ok(expr_i) => assert!(expr_i.is_well_formed(&slab.ps), "{}", s),
+            Err(errs) => assert!(
+                errs.windows(2).all(|w| w[0].span.start <= w[1].span.start)
+                    && errs.iter().all(|err| err.span.end <= s.len()),
+                "{}",
+                s
+            ),
+        }
+    }
+    assert!(parsed > 500, "{}", parsed);
+
+    assert!(!fasteval2::ExpressionI(0).is_well_formed(&Slab::new().ps));
+}
+
+#[test]
+fn arg_counts() {
+    let mut slab = Slab::new();
+    let mut arg_count = |expr_str: &str| match Parser::new().parse(expr_str, &mut slab.ps) {
+        Err(Error::WrongArgCount {
+            name,
+            expected,
+            got,
+        }) => (name, expected, got),
+        other => panic!("{}: {:?}", expr_str, other),
+    };
+
+    // Too few, too many, and variadic minimums:
+    assert_eq!(
+        arg_count("fma(1, 2)"),
+        ("fma".to_string(), Arity::Exact(3), 2)
+    );
+    assert_eq!(
+        arg_count("1 + sin(1, 2)"),
+        ("sin".to_string(), Arity::Exact(1), 2)
+    );
+    assert_eq!(arg_count("pi(1)"), ("pi".to_string(), Arity::Exact(0), 1));
+    assert_eq!(
+        arg_count("log(1, 2, 3)"),
+        ("log".to_string(), Arity::Range(1, 2), 3)
+    );
+    assert_eq!(
+        arg_count("round()"),
+        ("round".to_string(), Arity::Range(1, 2), 0)
+    );
+    assert_eq!(
+        arg_count("max()"),
+        ("max".to_string(), Arity::AtLeast(1), 0)
+    );
+    assert_eq!(
+        arg_count("interp_ext(x, 0,0)"),
+        ("interp_ext".to_string(), Arity::OddAtLeast(5), 3)
+    );
+    assert_eq!(
+        arg_count("piecewise(x < 1, 2)"),
+        ("piecewise".to_string(), Arity::OddAtLeast(1), 2)
+    );
+    assert_eq!(
+        arg_count("abs(sqrt(1, 2))"),
+        ("sqrt".to_string(), Arity::Exact(1), 2)
+    );
+
+    // Every built-in is in the table, and accepts its arity:
+    for (name, arity) in fasteval2::parser::BUILTIN_FUNCS {
+        let n = match *arity {
+            Arity::Exact(n) | Arity::Range(n, _) | Arity::AtLeast(n) | Arity::OddAtLeast(n) => n,
+            _ => unreachable!(),
+        };
+        let args = vec!["1"; n].join(", ");
+        let expr_str = format!("{}({})", name, args);
+        let expr_ref = Parser::new()
+            .parse(&expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        assert!(
+            !format!("{:?}", expr_ref).contains("EFunc {"),
+            "{}",
+            expr_str
+        );
+        assert!(arity.accepts(n) && !arity.accepts(n.wrapping_sub(1)));
+    }
+    assert_eq!(fasteval2::parser::builtin_arity("sum"), None);
+    // A 'sum' that doesn't look like the built-in is a custom function:
+    assert!(Parser::new().parse("sum(1, 2)", &mut slab.ps).is_ok());
+
+    assert_eq!(Arity::Exact(1).to_string(), "1 arg");
+    assert_eq!(Arity::Range(1, 2).to_string(), "1 to 2 args");
+    assert_eq!(Arity::AtLeast(0).to_string(), "at least 0 args");
+    assert_eq!(
+        Arity::OddAtLeast(5).to_string(),
+        "an odd number of args, at least 5"
+    );
+}
+
+#[test]
+fn parse_list() {
+    let list = |s: &str, sep: char| -> Result<Vec<f64>, (Error, std::ops::Range<usize>)> {
+        let mut slab = Slab::new();
+        let exprs = Parser::new()
+            .parse_list(s, sep, &mut slab.ps)
+            .map_err(|err| (err.error, err.span))?;
+        let mut ns = |name: &str, _: Vec<f64>| match name {
+            "x" => Some(2.0),
+            "y" => Some(3.0),
+            _ => None,
+        };
+        Ok(exprs
+            .iter()
+            .map(|expr_i| fasteval2::Evaler::eval(expr_i.from(&slab.ps), &slab, &mut ns).unwrap())
+            .collect())
+    };
+
+    assert_eq!(list("x+1, y*2, max(x,0,1)", ','), Ok(vec![3.0, 6.0, 2.0]));
+    assert_eq!(list("  x  ", ','), Ok(vec![2.0]));
+    // Separators inside of calls, parentheses and strings don't split:
+    assert_eq!(
+        list("max(1, min(y, [2]) ), (x + y) * 2", ','),
+        Ok(vec![2.0, 10.0])
+    );
+    assert_eq!(list(r#"print("a, b", x), y"#, ','), Ok(vec![2.0, 3.0]));
+    assert_eq!(
+        list("max(x, y); 1,5 ; 4", ';'),
+        Err((Error::UnparsedTokensRemaining(",5 ".to_string()), 12..15))
+    );
+    assert_eq!(list("max(x, y); -1 ; 4", ';'), Ok(vec![3.0, -1.0, 4.0]));
+    assert_eq!(list("x | y | 7", '|'), Ok(vec![2.0, 3.0, 7.0]));
+
+    // Empty items, including after a trailing separator:
+    assert_eq!(list("x, y,", ','), Err((Error::EmptyExpression, 5..5)));
+    assert_eq!(list("x,  , y", ','), Err((Error::EmptyExpression, 2..4)));
+    assert_eq!(list(",x", ','), Err((Error::EmptyExpression, 0..0)));
+    assert_eq!(list("", ','), Err((Error::EmptyExpression, 0..0)));
+
+    // Errors are positioned in the whole string:
+    assert_eq!(
+        list("x + 1, y + * 2", ','),
+        Err((Error::InvalidValue, 11..12))
+    );
+    assert_eq!(
+        list("1, sin(1, 2)", ','),
+        Err((
+            Error::WrongArgCount {
+                name: "sin".to_string(),
+                expected: Arity::Exact(1),
+                got: 2
+            },
+            3..12
+        ))
+    );
+    assert_eq!(
+        list("1, (2, 3", ','),
+        Err((Error::Expected(")".to_string()), 3..6))
+    );
+}
+
+#[test]
+fn tokens() {
+    let tokens =
+        |parser: &Parser, s: &str| -> Result<Vec<Token>, Error> { parser.tokens(s).collect() };
+    let kinds = |parser: &Parser, s: &str| -> Result<Vec<TokenKind>, Error> {
+        parser.tokens(s).map(|t| t.map(|t| t.kind)).collect()
+    };
+    let parser = Parser::new();
+
+    assert_eq!(
+        tokens(&parser, "2 + x"),
+        Ok(vec![
+            Token {
+                kind: TokenKind::Number(2.0),
+                span: 0..1
+            },
+            Token {
+                kind: TokenKind::BinaryOp(BinaryOp::EAdd),
+                span: 2..3
+            },
+            Token {
+                kind: TokenKind::Ident("x".to_string()),
+                span: 4..5
+            },
+        ])
+    );
+
+    // Signs belong to numbers, but `-` after a value is an operator:
+    assert_eq!(
+        kinds(&parser, "-2-3 - -x"),
+        Ok(vec![
+            TokenKind::Number(-2.0),
+            TokenKind::BinaryOp(BinaryOp::ESub),
+            TokenKind::Number(3.0),
+            TokenKind::BinaryOp(BinaryOp::ESub),
+            TokenKind::Neg,
+            TokenKind::Ident("x".to_string()),
+        ])
+    );
+    assert_eq!(
+        kinds(&parser, "!(a) || f[]; print(\"hi\", 1.5k)"),
+        Ok(vec![
+            TokenKind::Not,
+            TokenKind::OpenParen,
+            TokenKind::Ident("a".to_string()),
+            TokenKind::CloseParen,
+            TokenKind::BinaryOp(BinaryOp::EOR),
+            TokenKind::Ident("f".to_string()),
+            TokenKind::OpenBracket,
+            TokenKind::CloseBracket,
+            TokenKind::Semicolon,
+            TokenKind::Ident("print".to_string()),
+            TokenKind::OpenParen,
+            TokenKind::Str("hi".to_string()),
+            TokenKind::Comma,
+            TokenKind::Number(1500.0),
+            TokenKind::CloseParen,
+        ])
+    );
+    assert_eq!(
+        tokens(&parser, " x <=\\\n 10 ")
+            .map(|ts| ts.into_iter().map(|t| t.span).collect::<Vec<_>>()),
+        Ok(vec![1..2, 3..5, 8..10])
+    );
+    assert_eq!(kinds(&parser, " "), Ok(vec![]));
+
+    // The structure isn't checked, and the iterator stops at the first error:
+    assert_eq!(kinds(&parser, "(1 +").map(|ks| ks.len()), Ok(3));
+    let mut it = parser.tokens("1 + * 2");
+    assert_eq!(
+        it.next().map(|t| t.map(|t| t.kind)),
+        Some(Ok(TokenKind::Number(1.0)))
+    );
+    assert!(it.next().is_some());
+    assert_eq!(it.next(), Some(Err(Error::InvalidValue)));
+    assert_eq!(it.next(), None);
+    assert_eq!(
+        kinds(&parser, "1 x"),
+        Err(Error::UnparsedTokensRemaining("x".to_string()))
+    );
+
+    // With the options of the parser:
+    let parser = Parser::builder()
+        .decimal_comma(true)
+        .add_alias("mod", "%")
+        .add_alias("not", "!")
+        .build()
+        .unwrap();
+    assert_eq!(
+        kinds(&parser, "not x mod 2,5"),
+        Ok(vec![
+            TokenKind::Not,
+            TokenKind::Ident("x".to_string()),
+            TokenKind::BinaryOp(BinaryOp::EMod),
+            TokenKind::Number(2.5),
+        ])
+    );
+    let parser = Parser::builder().max_len(3).build().unwrap();
+    assert_eq!(kinds(&parser, "1 + 2"), Err(Error::TooLong));
+
+    // `let` is only a keyword in front of an assignment:
+    assert_eq!(
+        kinds(&Parser::new(), "let r = 2; r == let"),
+        Ok(vec![
+            TokenKind::Let,
+            TokenKind::Ident("r".to_string()),
+            TokenKind::Assign,
+            TokenKind::Number(2.0),
+            TokenKind::Semicolon,
+            TokenKind::Ident("r".to_string()),
+            TokenKind::BinaryOp(BinaryOp::EEQ),
+            TokenKind::Ident("let".to_string()),
+        ])
+    );
+}
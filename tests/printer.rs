@@ -0,0 +1,138 @@
+use fasteval2::{Evaler, Parser, Printer, Slab};
+
+const EXPRS: &[&str] = &[
+    "((x*2)) + (y-1)*0.30000000000000004",
+    "x - (y - 1) - 2",
+    "(x - y) - 1 + -x",
+    "x / (y / 2) / 3 % 4",
+    "(x * y) / 2 * (x / y)",
+    "(x ^ y) ^ 2 + 2 ^ 3 ^ 0.5",
+    "-(x + 1) ^ 2 - -2 ^ 2 + -(-2)",
+    "!(x < 1) == (y < 1) || x && !y",
+    "x <= 1 != (y >= 2) > 0",
+    "max(1, x, -y) + log(2, x) + log(x) * round(0.01, y / 3)",
+    "sum(i, 1, 4, i ^ 2 + x) + piecewise(x < 1, 2, y)",
+    "f(x, 2 * y) + interp(x, 0, 0, 10, 5) + e() * pi()",
+    "sin(x)*cos(y) + atanh(0.5) + sqrt(abs(-x)) + 1e-7 * 1e21",
+    "2 * (let r = x + 1; r*r) - let y = -y; y",
+];
+
+fn eval(parser: &Parser, expr_str: &str) -> f64 {
+    let mut slab = Slab::new();
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "x" => Some(1.25),
+        "y" => Some(-3.5),
+        "f" => Some(args.iter().sum()),
+        _ => None,
+    };
+    let expr_ref = parser.parse(expr_str, &mut slab.ps).unwrap().from(&slab.ps);
+    expr_ref.eval(&slab, &mut ns).unwrap()
+}
+
+// Prints every expression, and checks that the output evaluates exactly like
+// the source:
+fn print_all(printer: &Printer, parser: &Parser) -> Vec<String> {
+    EXPRS
+        .iter()
+        .map(|expr_str| {
+            let mut slab = Slab::new();
+            let expr = Parser::new().parse(expr_str, &mut slab.ps).unwrap();
+            let out = printer.print(expr.from(&slab.ps), &slab);
+            let (want, got) = (eval(&Parser::new(), expr_str), eval(parser, &out));
+            assert!(
+                want.to_bits() == got.to_bits() || want.is_nan() && got.is_nan(),
+                "{} -> {}: {} != {}",
+                expr_str,
+                out,
+                want,
+                got
+            );
+            out
+        })
+        .collect()
+}
+
+#[test]
+fn defaults() {
+    let out = print_all(&Printer::new(), &Parser::new());
+    assert_eq!(
+        out,
+        [
+            "x * 2 + (y - 1) * 0.30000000000000004",
+            "x - (y - 1) - 2",
+            "x - y - 1 + -x",
+            "x / (y / 2) / 3 % 4",
+            "(x * y) / 2 * x / y",
+            "(x ^ y) ^ 2 + 2 ^ 3 ^ 0.5",
+            "-(x + 1) ^ 2 - -2 ^ 2 + --2",
+            "!(x < 1) == (y < 1) || x && !y",
+            "x <= 1 != (y >= 2) > 0",
+            "max(1, x, -y) + log(2, x) + log(x) * round(0.01, y / 3)",
+            "sum(i, 1, 4, i ^ 2 + x) + piecewise(x < 1, 2, y)",
+            "f(x, 2 * y) + interp(x, 0, 0, 10, 5) + e() * pi()",
+            "sin(x) * cos(y) + atanh(0.5) + sqrt(abs(-x)) + 0.0000001 * 1000000000000000000000",
+            "2 * (let r = x + 1; r * r) - (let y = -y; y)",
+        ]
+    );
+    assert_eq!(Printer::default(), Printer::new());
+}
+
+#[test]
+fn spaces() {
+    let out = print_all(&Printer::new().spaces(false), &Parser::new());
+    assert_eq!(out[0], "x*2+(y-1)*0.30000000000000004");
+    assert_eq!(out[6], "-(x+1)^2--2^2+--2");
+    assert_eq!(out[7], "!(x<1)==(y<1)||x&&!y");
+    assert_eq!(out[9], "max(1, x, -y)+log(2, x)+log(x)*round(0.01, y/3)");
+    assert_eq!(out[13], "2*(let r=x+1;r*r)-(let y=-y;y)");
+}
+
+#[test]
+fn significant_digits() {
+    let printer = Printer::new().significant_digits(15);
+    let mut slab = Slab::new();
+    let mut print = |expr_str: &str| {
+        let expr = Parser::new().parse(expr_str, &mut slab.ps).unwrap();
+        printer.print(expr.from(&slab.ps), &slab)
+    };
+    assert_eq!(print("x * 0.30000000000000004"), "x * 0.3");
+    assert_eq!(print("1/3 + 2.5"), "1 / 3 + 2.5");
+    assert_eq!(print("0.1234567890123456789"), "0.123456789012346");
+    assert_eq!(print("-123456789012345678"), "-123456789012346000");
+    let printer = Printer::new().significant_digits(0);
+    let expr = Parser::new().parse("2.7 + 1.25e-10", &mut slab.ps).unwrap();
+    assert_eq!(
+        printer.print(expr.from(&slab.ps), &slab),
+        "3 + 0.0000000001"
+    );
+
+    // Constants that already fit aren't changed, so these still evaluate exactly:
+    let out = print_all(&Printer::new().significant_digits(17), &Parser::new());
+    assert_eq!(out[0], "x * 2 + (y - 1) * 0.30000000000000004");
+}
+
+#[test]
+fn mul_symbol() {
+    let parser = Parser::builder().add_alias("times", "*").build().unwrap();
+    let out = print_all(&Printer::new().spaces(false).mul_symbol("times"), &parser);
+    assert_eq!(out[0], "x times 2+(y-1) times 0.30000000000000004");
+    assert_eq!(out[4], "(x times y)/2 times x/y");
+
+    let out = print_all(&Printer::new().mul_symbol("*"), &Parser::new());
+    assert_eq!(out[4], "(x * y) / 2 * x / y");
+}
+
+#[test]
+fn keep_parens() {
+    let out = print_all(&Printer::new().keep_parens(true), &Parser::new());
+    assert_eq!(out[0], "((x * 2)) + (y - 1) * 0.30000000000000004");
+    assert_eq!(out[2], "(x - y) - 1 + -x");
+    assert_eq!(out[6], "-(x + 1) ^ 2 - -2 ^ 2 + -(-2)");
+    assert_eq!(out[8], "x <= 1 != (y >= 2) > 0");
+    assert_eq!(out[13], "2 * (let r = x + 1; r * r) - let y = -y; y");
+    let out = print_all(
+        &Printer::new().keep_parens(true).spaces(false),
+        &Parser::new(),
+    );
+    assert_eq!(out[5], "(x^y)^2+2^3^0.5");
+}
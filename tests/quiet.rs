@@ -0,0 +1,43 @@
+use fasteval2::{ez_eval, Compiler, EmptyNamespace, Evaler, Parser, Slab};
+
+use std::process::Command;
+
+static CHILD_ENV: &str = "FASTEVAL2_QUIET_CHILD";
+
+// Does the work that must not print.  It only runs in the child process that
+// `no_output()` starts, where stderr isn't captured by the test harness.
+#[test]
+fn child() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let mut ns = |name: &str, _: Vec<f64>| if name == "x" { Some(2.0) } else { None };
+    assert_eq!(ez_eval(r#"x + print("x is", x)"#, &mut ns), Ok(4.0));
+
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse(r#"print("compiled", x * 2) + sin(x) * 0"#, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let instr = expr_ref.compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    assert_eq!(instr.eval(&slab, &mut ns), Ok(4.0));
+
+    assert!(ez_eval("1 + * 2", &mut ns).is_err());
+    assert!(ez_eval("undefined_var", &mut ns).is_err());
+}
+
+#[test]
+fn no_output() {
+    let out = Command::new(std::env::current_exe().unwrap())
+        .args(["child", "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    if cfg!(feature = "trace") {
+        assert_eq!(stderr, "x is 2\ncompiled 4\n");
+    } else {
+        assert_eq!(stderr, "");
+    }
+}
@@ -0,0 +1,328 @@
+mod common;
+
+use fasteval2::parser::BinaryOp::*;
+use fasteval2::{Arity, Error, Evaler, Parser, RpnToken, Slab, SpannedError};
+
+static VARS: [&str; 3] = ["x", "y", "z"];
+
+fn rpn(expr_str: &str) -> Result<Vec<RpnToken>, Error> {
+    let mut slab = Slab::new();
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)?
+        .from(&slab.ps)
+        .to_rpn(&slab)
+}
+
+// Writes tokens in the form that parse_rpn() reads, like "2 3 2 ^ ^":
+fn show(tokens: &[RpnToken]) -> String {
+    tokens
+        .iter()
+        .map(|tok| tok.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The same tolerances as eval(), where NaN is neither equal nor unequal:
+fn eq(l: f64, r: f64) -> bool {
+    (l - r).abs() <= 8.0 * f64::EPSILON
+}
+
+fn ne(l: f64, r: f64) -> bool {
+    (l - r).abs() > 8.0 * f64::EPSILON
+}
+
+fn bool_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// A reference stack machine for the tokens, which supports the built-ins
+/// that `rand_expr()` uses.  Custom functions are called with `f`.
+fn run(tokens: &[RpnToken], vars: &[f64; 3], f: fn(&[f64]) -> f64) -> f64 {
+    let mut stack = Vec::<f64>::new();
+    for tok in tokens {
+        let val = match tok {
+            RpnToken::Const(c) => *c,
+            RpnToken::Var(name) => vars[VARS.iter().position(|v| v == name).unwrap()],
+            RpnToken::Op(op) => {
+                let r = stack.pop().unwrap();
+                let l = stack.pop().unwrap();
+                match op {
+                    EAdd => l + r,
+                    ESub => l - r,
+                    EMul => l * r,
+                    EDiv => l / r,
+                    EMod => l % r,
+                    EExp => l.powf(r),
+                    ELT => bool_f64(l < r),
+                    ELTE => bool_f64(l <= r),
+                    EEQ => bool_f64(eq(l, r)),
+                    ENE => bool_f64(ne(l, r)),
+                    EGTE => bool_f64(l >= r),
+                    EGT => bool_f64(l > r),
+                    EOR => {
+                        if ne(l, 0.0) {
+                            l
+                        } else {
+                            r
+                        }
+                    }
+                    EAND => {
+                        if eq(l, 0.0) {
+                            l
+                        } else {
+                            r
+                        }
+                    }
+                }
+            }
+            RpnToken::Call { name, argc } => {
+                let args = stack.split_off(stack.len() - argc);
+                match (name.as_str(), &args[..]) {
+                    ("neg", [a]) => -a,
+                    ("not", [a]) => bool_f64(eq(*a, 0.0)),
+                    ("abs", [a]) => a.abs(),
+                    ("sin", [a]) => a.sin(),
+                    ("floor", [a]) => a.floor(),
+                    ("fma", [a, b, c]) => a.mul_add(*b, *c),
+                    // Like eval(), a NaN from the first argument is kept:
+                    ("min", [first, rest @ ..]) if !first.is_nan() => {
+                        rest.iter().fold(*first, |m, v| m.min(*v))
+                    }
+                    ("max", [first, rest @ ..]) if !first.is_nan() => {
+                        rest.iter().fold(*first, |m, v| m.max(*v))
+                    }
+                    ("min" | "max", _) => f64::NAN,
+                    ("f", args) => f(args),
+                    _ => panic!("unexpected call: {} with {:?}", name, args),
+                }
+            }
+        };
+        stack.push(val);
+    }
+    assert_eq!(stack.len(), 1, "{}", show(tokens));
+    stack[0]
+}
+
+// Long chains of operators without parentheses exercise the grouping:
+fn rand_expr(rand: &mut impl FnMut() -> u64, depth: usize) -> String {
+    const OPS: [&str; 14] = [
+        "+", "-", "*", "/", "%", "^", "<", "<=", "==", "!=", ">=", ">", "||", "&&",
+    ];
+    let mut s = rand_value(rand, depth);
+    for _ in 0..rand() % 6 {
+        s.push_str(OPS[(rand() % 14) as usize]);
+        s.push_str(&rand_value(rand, depth));
+    }
+    s
+}
+
+fn rand_value(rand: &mut impl FnMut() -> u64, depth: usize) -> String {
+    if depth == 0 || rand().is_multiple_of(3) {
+        return match rand() % 4 {
+            0 | 1 => VARS[(rand() % 3) as usize].to_string(),
+            2 => format!("{}", rand() % 5),
+            _ => ["0.5", "1.5", "pi()", "e()"][(rand() % 4) as usize].to_string(),
+        };
+    }
+    let d = depth - 1;
+    match rand() % 8 {
+        0 => format!("({})", rand_expr(rand, d)),
+        1 => format!("-{}", rand_value(rand, d)),
+        2 => format!("!{}", rand_value(rand, d)),
+        3 => format!("abs({})", rand_expr(rand, d)),
+        4 => {
+            let name = ["sin", "floor"][(rand() % 2) as usize];
+            format!("{}({})", name, rand_expr(rand, d))
+        }
+        5 => format!(
+            "max({}, {}, {})",
+            rand_expr(rand, d),
+            rand_expr(rand, d),
+            rand_expr(rand, d)
+        ),
+        6 => format!(
+            "fma({}, {}, min({}))",
+            rand_expr(rand, d),
+            rand_expr(rand, d),
+            rand_expr(rand, d)
+        ),
+        _ => format!("f({}, {})", rand_expr(rand, d), rand_expr(rand, d)),
+    }
+}
+
+fn same(a: f64, b: f64) -> bool {
+    a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+}
+
+#[test]
+fn grouping() {
+    for (expr_str, want) in [
+        ("2^3^2", "2 3 2 ^ ^"),
+        ("8-3-2", "8 3 - 2 -"),
+        ("8/4/2", "8 4 / 2 /"),
+        ("7%4%3", "7 4 % 3 %"),
+        ("x-y+z", "x y - z +"),
+        ("x+y-z", "x y z - +"),
+        ("x*y/z", "x y z / *"),
+        ("x/y*z", "x y / z *"),
+        ("x-y*z^2", "x y z 2 ^ * -"),
+        ("(x-y)*z", "x y - z *"),
+        ("x < y == y > z", "x y < y == z >"),
+        ("x || y && z", "x y z && ||"),
+        ("-x^2", "x neg/1 2 ^"),
+        ("!+x", "x not/1"),
+        ("1 + 2 * 3", "1 2 3 * +"),
+        ("log(2, x) + round(x)", "2 x log/2 x round/1 +"),
+        ("piecewise(x < 0, -1, x)", "x 0 < -1 x piecewise/3"),
+        ("interp_ext(x, 0,1, 2,3)", "x 0 1 2 3 interp_ext/5"),
+        ("f() + g(x, y)", "f/0 x y g/2 +"),
+        ("e() * pi", &format!("{} pi *", std::f64::consts::E)),
+    ] {
+        assert_eq!(show(&rpn(expr_str).unwrap()), want, "{}", expr_str);
+    }
+
+    assert_eq!(
+        rpn("sum(i, 1, 3, i)"),
+        Err(Error::NotAllowed("sum".to_string()))
+    );
+    assert_eq!(
+        rpn("1 + print(\"x\", 2)"),
+        Err(Error::NotAllowed("print".to_string()))
+    );
+}
+
+#[test]
+fn matches_eval() {
+    let mut rand = common::rand_gen(0x9e37_79b9_7f4a_7c15);
+    let f = |args: &[f64]| args[0] * 2.0 - args[1];
+    for _ in 0..500 {
+        let expr_str = rand_expr(&mut rand, 2);
+        let mut slab = Slab::with_capacity(4096);
+        let expr = Parser::new()
+            .parse(&expr_str, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        let tokens = expr.to_rpn(&slab).unwrap();
+        // ...and back, through the text form:
+        let mut rpn_slab = Slab::with_capacity(4096);
+        let rpn_expr = Parser::new()
+            .parse_rpn(&show(&tokens), &mut rpn_slab.ps)
+            .unwrap()
+            .from(&rpn_slab.ps);
+        assert_eq!(rpn_expr.to_rpn(&rpn_slab), Ok(tokens.clone()));
+        for _ in 0..20 {
+            let vars = [(); 3].map(|_| (rand() % 41) as f64 / 4.0 - 5.0);
+            let mut ns = |name: &str, args: Vec<f64>| match name {
+                "f" => Some(f(&args)),
+                _ => VARS.iter().position(|v| *v == name).map(|i| vars[i]),
+            };
+            let want = expr.eval(&slab, &mut ns).unwrap();
+            let got = run(&tokens, &vars, f);
+            assert!(
+                same(got, want),
+                "{} with {:?}: rpn={:?} eval={:?} ({})",
+                expr_str,
+                vars,
+                got,
+                want,
+                show(&tokens)
+            );
+            let parsed = rpn_expr.eval(&rpn_slab, &mut ns).unwrap();
+            assert!(
+                same(parsed, want),
+                "{} with {:?}: parse_rpn={:?} eval={:?} ({})",
+                expr_str,
+                vars,
+                parsed,
+                want,
+                show(&tokens)
+            );
+        }
+    }
+}
+
+#[test]
+fn parse_rpn() {
+    let eval = |s: &str| {
+        let mut slab = Slab::new();
+        let mut ns = |name: &str, args: Vec<f64>| match (name, &args[..]) {
+            ("x", []) => Some(3.0),
+            ("twice", [a]) => Some(a * 2.0),
+            _ => None,
+        };
+        Parser::new()
+            .parse_rpn(s, &mut slab.ps)
+            .map(|expr_i| expr_i.from(&slab.ps).eval(&slab, &mut ns))
+    };
+    assert_eq!(eval("3 4 + 5 *"), Ok(Ok(35.0)));
+    assert_eq!(eval("  2\t3 2 ^ ^\n"), Ok(Ok(512.0)));
+    assert_eq!(eval("8 3 - 2 -"), Ok(Ok(3.0)));
+    assert_eq!(eval("8 3 2 - -"), Ok(Ok(7.0)));
+    assert_eq!(eval("x neg/1 2 ^ x not/1 +"), Ok(Ok(9.0)));
+    assert_eq!(eval("-1.5 +2 1e1 max/3 x twice/1 *"), Ok(Ok(60.0)));
+    assert_eq!(
+        eval("2 8 log/2 0.5 x round/2 pi/0 floor/1 + +"),
+        Ok(Ok(3.0 + 3.0 + 3.0))
+    );
+    assert_eq!(eval("x 0 < -1 x piecewise/3"), Ok(Ok(3.0)));
+    assert_eq!(eval("inf neg/1 NaN -inf min/3"), Ok(Ok(f64::NEG_INFINITY)));
+    assert_eq!(
+        eval("x 2 nope/2"),
+        Ok(Err(Error::UndefinedFunction {
+            name: "nope".to_string(),
+            arg_count: 2
+        }))
+    );
+
+    // sum() loops work, because the loop variable is a plain name:
+    assert_eq!(eval("i 1 4 i x * sum/4"), Ok(Ok(30.0)));
+
+    let err = |s: &'static str| {
+        let SpannedError { error, span } = eval(s).unwrap_err();
+        (error, &s[span])
+    };
+    let values = |n: &str| Error::Expected(n.to_string());
+    assert_eq!(err("1 +"), (values("2 values"), "+"));
+    assert_eq!(err("+"), (values("2 values"), "+"));
+    assert_eq!(
+        err("1 2 + neg/1 not/1 max/2"),
+        (values("2 values"), "max/2")
+    );
+    assert_eq!(err("neg/1"), (values("1 value"), "neg/1"));
+    assert_eq!(err("1 2 3 +"), (values("an operator"), "2 3 +"));
+    assert_eq!(err("1 2 3 + x"), (values("an operator"), "2 3 + x"));
+    assert_eq!(err("1 2 $ +"), (Error::InvalidValue, "$"));
+    assert_eq!(err("1 sin/x"), (Error::InvalidValue, "sin/x"));
+    assert_eq!(
+        err("1 2 sin/2"),
+        (
+            Error::WrongArgCount {
+                name: "sin".to_string(),
+                expected: Arity::Exact(1),
+                got: 2
+            },
+            "sin/2"
+        )
+    );
+    assert_eq!(
+        err("1 print/1"),
+        (Error::NotAllowed("print".to_string()), "print/1")
+    );
+    assert_eq!(err(" \n "), (Error::EmptyExpression, " \n "));
+
+    // The limits apply:
+    let parser = Parser::builder().max_depth(3).build().unwrap();
+    let mut slab = Slab::new();
+    assert!(parser.parse_rpn("1 1 + 1 +", &mut slab.ps).is_ok());
+    let e = parser
+        .parse_rpn("1 1 + 1 + 1 + 1 +", &mut slab.ps)
+        .unwrap_err();
+    assert_eq!((e.error, e.span), (Error::TooDeep, 12..13));
+    let parser = Parser::builder().max_len(4).build().unwrap();
+    let e = parser.parse_rpn("1 2 +", &mut slab.ps).unwrap_err();
+    assert_eq!((e.error, e.span), (Error::TooLong, 0..5));
+}
@@ -0,0 +1,173 @@
+#![cfg(feature = "serde")]
+
+use fasteval2::compiler::Instruction;
+use fasteval2::parser::BinaryOp;
+use fasteval2::{Compiler, EmptyNamespace, Error, Evaler, ExpressionI, Parser, Slab};
+
+use std::collections::BTreeMap;
+
+type Shipped = (Slab, ExpressionI, Instruction);
+
+// Covers every grammar type and variant that a parse can produce, so that
+// renaming one of them changes the fixture:
+static FIXTURE_EXPR: &str = "+x - -y * !z / 2 % 3 ^ 4 + (x < y) + (x <= y) + (x == y) + (x != y) \
+    + (x >= y) + (x > y) + (x && y) + (x || y) + f(x, 1) + int(x) + ceil(y) + floor(x) + abs(y) \
+    + sign(x) + log(y) + log(2, y) + round(y) + round(0.5, y) + min(x, y, 1) + max(x, 3) \
+    + piecewise(x < 0, -1, x > 1, 1, 0) + sum(i, 1, 3, i*x) + interp(x, 0,0, 10,100) \
+    + interp_ext(x, 0,0, 10,100) + e() + pi() + sin(x) + cos(x) + tan(x) + asin(x) + acos(x) \
+    + atan(x) + sinh(x) + cosh(x) + tanh(x) + asinh(x) + acosh(y) + atanh(x) + sqrt(y) \
+    + fma(x, y, z) + coalesce(x, 2) + norm(x, y) + sumsq(x, y) + print(\"x =\", x) \
+    + NaN + inf - 0.1";
+static FIXTURE_V1: &str = include_str!("fixtures/parse_slab_v1.json");
+
+fn via_json(slab: &Slab, expr_i: ExpressionI, instr: &Instruction) -> Shipped {
+    let s = serde_json::to_string(&(slab, expr_i, instr)).unwrap();
+    serde_json::from_str(&s).unwrap()
+}
+fn via_bincode(slab: &Slab, expr_i: ExpressionI, instr: &Instruction) -> Shipped {
+    let bs = bincode::serialize(&(slab, expr_i, instr)).unwrap();
+    bincode::deserialize(&bs).unwrap()
+}
+
+fn bits(res: Result<f64, Error>) -> Result<u64, Error> {
+    res.map(f64::to_bits)
+}
+
+#[test]
+fn roundtrip() {
+    let mut ns = BTreeMap::<String, f64>::new();
+    ns.insert("x".to_string(), 2.5);
+    ns.insert("y".to_string(), -1.0);
+
+    for expr_str in [
+        "1 + 2*3 - 4/5 ^ 2 % 3",
+        "x < y || !(x >= 2) && -y == 1",
+        "sin(x) + max(x, y, 3) + log(2, 8) + round(0.1, x) + int(ceil(abs(y)))",
+        "piecewise(x < 0, -1, x > 0, 1, 0) + sum(i, 1, 10, i*x) + interp(x, 0,0, 10,100)",
+        "f(x)",
+        "NaN",
+        "inf + x",
+        "-inf * x",
+        "0.1 * x",
+        "-0.0",
+        "1.0000000000000002",
+        "1.7976931348623157e308",
+        "5e-324",
+    ] {
+        for conv in [via_json, via_bincode] {
+            let mut slab = Slab::new();
+            let expr_i = Parser::new().parse(expr_str, &mut slab.ps).unwrap();
+            let instr = expr_i
+                .from(&slab.ps)
+                .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+            let want = bits(expr_i.from(&slab.ps).eval(&slab, &mut ns));
+            assert_eq!(bits(instr.eval(&slab, &mut ns)), want, "{}", expr_str);
+
+            let (slab2, expr_i2, instr2) = conv(&slab, expr_i, &instr);
+            assert_eq!(format!("{:?}", slab2), format!("{:?}", slab));
+            assert_eq!(format!("{:?}", instr2), format!("{:?}", instr));
+            assert_eq!(
+                bits(expr_i2.from(&slab2.ps).eval(&slab2, &mut ns)),
+                want,
+                "{}",
+                expr_str
+            );
+            assert_eq!(bits(instr2.eval(&slab2, &mut ns)), want, "{}", expr_str);
+        }
+    }
+}
+
+#[test]
+fn tuple() {
+    let parser = Parser::builder().tuples(true).build().unwrap();
+    for conv in [via_json, via_bincode] {
+        let mut slab = Slab::new();
+        let expr_i = parser.parse("1, 2 * 3", &mut slab.ps).unwrap();
+        let instr = expr_i
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+        let (slab2, expr_i2, _) = conv(&slab, expr_i, &instr);
+        assert_eq!(format!("{:?}", slab2), format!("{:?}", slab));
+        assert_eq!(
+            expr_i2
+                .from(&slab2.ps)
+                .eval_vec(&slab2, &mut EmptyNamespace),
+            Ok(vec![1.0, 6.0])
+        );
+    }
+}
+
+#[test]
+fn non_finite_json() {
+    let mut slab = Slab::new();
+    let expr_i = Parser::new()
+        .parse("NaN + inf - -inf", &mut slab.ps)
+        .unwrap();
+    let s = serde_json::to_string(&slab.ps).unwrap();
+    assert!(s.contains(r#""NaN""#) && s.contains(r#""inf""#) && s.contains(r#""-inf""#));
+
+    let ps: fasteval2::slab::ParseSlab = serde_json::from_str(&s).unwrap();
+    assert_eq!(
+        format!("{:?}", expr_i.from(&ps)),
+        format!("{:?}", expr_i.from(&slab.ps))
+    );
+
+    assert!(serde_json::from_str::<fasteval2::Value>(r#"{"EConstant":"nan"}"#).is_err());
+}
+
+#[test]
+fn fixture() {
+    let mut slab = Slab::with_capacity(256);
+    let expr_i = Parser::new().parse(FIXTURE_EXPR, &mut slab.ps).unwrap();
+    assert_eq!(
+        serde_json::to_string(&(&slab.ps, expr_i)).unwrap(),
+        FIXTURE_V1.trim_end()
+    );
+
+    let (ps, expr_i2): (fasteval2::slab::ParseSlab, ExpressionI) =
+        serde_json::from_str(FIXTURE_V1).unwrap();
+    assert_eq!(expr_i2, expr_i);
+    assert_eq!(
+        format!("{:?}", expr_i.from(&ps)),
+        format!("{:?}", expr_i.from(&slab.ps))
+    );
+}
+
+#[test]
+fn unknown_variant() {
+    assert_eq!(
+        serde_json::from_str::<BinaryOp>(r#""EAdd""#).unwrap(),
+        BinaryOp::EAdd
+    );
+
+    let err = serde_json::from_str::<BinaryOp>(r#""EFromTheFuture""#).unwrap_err();
+    assert!(err.to_string().contains("unknown variant"), "{}", err);
+    let err = serde_json::from_str::<Instruction>(r#"{"IFuncFromTheFuture":[1]}"#).unwrap_err();
+    assert!(err.to_string().contains("unknown variant"), "{}", err);
+
+    // bincode identifies variants by index:
+    let mut bs = bincode::serialize(&BinaryOp::EExp).unwrap();
+    bs[0] = 200;
+    assert!(bincode::deserialize::<BinaryOp>(&bs).is_err());
+}
+
+#[test]
+fn malformed_slab() {
+    let mut slab = Slab::new();
+    let expr_i = Parser::new().parse("1 + (2 * -x)", &mut slab.ps).unwrap();
+    let s = serde_json::to_string(&slab.ps).unwrap();
+    let ps: fasteval2::slab::ParseSlab = serde_json::from_str(&s).unwrap();
+    assert!(expr_i.is_well_formed(&ps));
+
+    // An expression that contains itself:
+    let looped = s.replace(r#"{"EParentheses":0}"#, r#"{"EParentheses":1}"#);
+    assert_ne!(looped, s);
+    let ps: fasteval2::slab::ParseSlab = serde_json::from_str(&looped).unwrap();
+    assert!(!expr_i.is_well_formed(&ps));
+
+    // A value that is missing:
+    let dangling = s.replace(r#"{"ENeg":0}"#, r#"{"ENeg":7}"#);
+    assert_ne!(dangling, s);
+    let ps: fasteval2::slab::ParseSlab = serde_json::from_str(&dangling).unwrap();
+    assert!(!expr_i.is_well_formed(&ps));
+}
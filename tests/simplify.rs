@@ -0,0 +1,154 @@
+use fasteval2::{EmptyNamespace, Error, EvalNS, EvalNamespace, Evaler, Parser, Slab};
+
+fn simplify(expr_str: &str, ns: &mut impl EvalNamespace) -> Result<String, Error> {
+    let mut slab = Slab::new();
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .to_simplified_string(&slab, ns)
+}
+
+fn eval(expr_str: &str, x: f64) -> f64 {
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "x" => Some(x),
+        "y" => Some(2.0),
+        "f" => Some(args.iter().sum()),
+        _ => None,
+    };
+    fasteval2::ez_eval(expr_str, &mut ns).unwrap()
+}
+
+#[test]
+fn constants() {
+    let mut ns = EmptyNamespace;
+    assert_eq!(simplify("2*3 + x*1", &mut ns), Ok("6 + x".to_string()));
+    assert_eq!(simplify("(1 + 2) * 3^2", &mut ns), Ok("27".to_string()));
+    // `0^0` depends on the Namespace of the evaluation:
+    assert_eq!(simplify("0^0 * 2", &mut ns), Ok("0 ^ 0 * 2".to_string()));
+    // So does `%` with non-integers:
+    assert_eq!(
+        simplify("5.5 % 2 + 7 % 2", &mut ns),
+        Ok("5.5 % 2 + 1".to_string())
+    );
+    assert_eq!(
+        simplify("sin(0) + x / (4 - 2) - y * 0", &mut ns),
+        Ok("x / 2 - y * 0".to_string())
+    );
+    assert_eq!(
+        simplify("max(1, 2) * x^1 + 0 + phi*0", &mut ns),
+        Ok("2 * x".to_string())
+    );
+    assert_eq!(
+        simplify("-(2) * -x + !(1 > 2) + !x", &mut ns),
+        Ok("-2 * -x + 1 + !x".to_string())
+    );
+    // Operators are grouped like eval() groups them, and not reassociated:
+    assert_eq!(simplify("x + 1 + 2", &mut ns), Ok("x + 3".to_string()));
+    assert_eq!(simplify("x - 1 - 2", &mut ns), Ok("x - 1 - 2".to_string()));
+    assert_eq!(simplify("1 + 2 + x", &mut ns), Ok("1 + 2 + x".to_string()));
+}
+
+#[test]
+fn grouping() {
+    let mut ns = EmptyNamespace;
+    for (expr_str, simplified) in [
+        ("(x + y) * 2", "(x + y) * 2"),
+        ("((x)) + ((y * 2))", "x + y * 2"),
+        ("x - (y - 1)", "x - (y - 1)"),
+        ("(x - y) - 1", "x - y - 1"),
+        ("x / (y / 2)", "x / (y / 2)"),
+        ("(x / y) * 2", "x / y * 2"),
+        ("(x * y) / 2", "(x * y) / 2"),
+        ("(x ^ y) ^ 2", "(x ^ y) ^ 2"),
+        ("x ^ (y ^ 2)", "x ^ y ^ 2"),
+        ("-(x + 1) ^ 2", "-(x + 1) ^ 2"),
+        ("(x < 1) == (y < 1)", "x < 1 == (y < 1)"),
+        ("x || y && 1 < 2", "x || y && 1"),
+    ] {
+        let simplified_str = simplify(expr_str, &mut ns).unwrap();
+        assert_eq!(simplified_str, simplified, "{}", expr_str);
+    }
+
+    // The output evaluates like the input:
+    for expr_str in [
+        "2*3 + x*1",
+        "x - (y - 1) * (2 + 3) / (x - 2 - 1)",
+        "-(x + 1) ^ 2 % 7 + x ^ y ^ 0.5",
+        "f(x, 1 + 1) / (f(2, 3) - y)",
+        "x >= 2 || x < -1 && !(y == 2)",
+        "sum(i, 1, x + 1, i * x) + sum(i, 1, 3, i * 2)",
+        "piecewise(x < 0, -x, x) * interp(x, 0,0, 10,100)",
+    ] {
+        let simplified_str = simplify(expr_str, &mut EmptyNamespace).unwrap();
+        for x in [-3.0, 0.0, 0.5, 4.0] {
+            let (simplified, original) = (eval(&simplified_str, x), eval(expr_str, x));
+            assert!(
+                simplified == original || simplified.is_nan() && original.is_nan(),
+                "{} => {}",
+                expr_str,
+                simplified_str
+            );
+        }
+    }
+}
+
+#[test]
+fn namespace() {
+    // Names that the Namespace defines are replaced:
+    let mut ns = |name: &str, args: Vec<f64>| match name {
+        "y" => Some(2.0),
+        "double" => args.first().map(|a| a * 2.0),
+        _ => None,
+    };
+    assert_eq!(
+        simplify("x * y + double(y) + double(x) + g(1)", &mut ns),
+        Ok("x * 2 + 4 + double(x) + g(1)".to_string())
+    );
+    // ...including inside loops, but not the loop variable:
+    assert_eq!(
+        simplify("sum(y, 1, 3, y) + sum(i, 1, 3, i * y)", &mut ns),
+        Ok("18".to_string())
+    );
+    assert_eq!(
+        simplify("sum(i, 1, x, i * y) + sum(i, 1, 3, i * x)", &mut ns),
+        Ok("sum(i, 1, x, i * 2) + sum(i, 1, 3, i * x)".to_string())
+    );
+    assert_eq!(
+        simplify("sum(i, 1, 3, sum(j, 1, i, j))", &mut ns),
+        Ok("10".to_string())
+    );
+    // ...and the same for the names of `let`s:
+    assert_eq!(
+        simplify("let y = y + 1; y * y", &mut ns),
+        Ok("9".to_string())
+    );
+    assert_eq!(
+        simplify("2 * let r = x * y; r + r", &mut ns),
+        Ok("2 * (let r = x * 2; r + r)".to_string())
+    );
+
+    // Random and print() calls stay:
+    let mut ns = EvalNS::new(EmptyNamespace).with_rng(1);
+    assert_eq!(
+        simplify(r#"rand() * (1 + 1) + print("x is", x * 1)"#, &mut ns),
+        Ok(r#"rand() * 2 + print("x is", x)"#.to_string())
+    );
+
+    // Errors from evaluation and parse recovery are returned:
+    assert_eq!(
+        simplify(
+            "x + sum(i, 1, 100, i)",
+            &mut EvalNS::new(EmptyNamespace).with_budget(10)
+        ),
+        Err(Error::BudgetExceeded(10))
+    );
+    let mut slab = Slab::new();
+    let (expr_opt, _) = Parser::new().parse_with_recovery("1 + * z", &mut slab.ps);
+    let expr_ref = expr_opt.unwrap().from(&slab.ps);
+    assert_eq!(
+        expr_ref.to_simplified_string(&slab, &mut EmptyNamespace),
+        Err(Error::InvalidValue)
+    );
+    assert!(expr_ref.eval(&slab, &mut EmptyNamespace).is_err());
+}
@@ -0,0 +1,354 @@
+use fasteval2::{Arity, Error, EvalNS, Evaler, ModMode, Parser, Slab, SpannedError};
+
+fn parse_err(src: &str) -> SpannedError {
+    Parser::new()
+        .parse_spanned(src, &mut Slab::new().ps)
+        .unwrap_err()
+}
+
+#[test]
+fn spans() {
+    for (src, error, span) in [
+        ("1 + * 2", Error::InvalidValue, 4..5),
+        ("1 +   ", Error::EofWhileParsing("value".to_string()), 6..6),
+        (
+            "1 + sin(1, 2) * 3",
+            Error::WrongArgCount {
+                name: "sin".to_string(),
+                expected: Arity::Exact(1),
+                got: 2,
+            },
+            4..13,
+        ),
+        (
+            "3abc + 1",
+            Error::UnparsedTokensRemaining("abc + 1".to_string()),
+            1..8,
+        ),
+        ("max(1, (2 + ))", Error::InvalidValue, 12..13),
+        ("   ", Error::EmptyExpression, 0..3),
+        ("1 + é", Error::InvalidValue, 4..6),
+    ] {
+        let err = parse_err(src);
+        assert_eq!(err.error, error, "{}", src);
+        assert_eq!(err.span, span, "{}", src);
+    }
+
+    let parser = Parser::builder().max_len(4).build().unwrap();
+    let err = parser
+        .parse_spanned("1 + 2 + 3", &mut Slab::new().ps)
+        .unwrap_err();
+    assert_eq!((err.error, err.span), (Error::TooLong, 0..9));
+
+    // Successful parses are the same as parse():
+    let mut slab = Slab::new();
+    let expr_i = Parser::new().parse_spanned("1 + 2", &mut slab.ps).unwrap();
+    assert_eq!(
+        format!("{:?}", expr_i.from(&slab.ps)),
+        format!(
+            "{:?}",
+            Parser::new()
+                .parse("1 + 2", &mut slab.ps)
+                .unwrap()
+                .from(&slab.ps)
+        )
+    );
+    // ...and the error converts back to a plain Error:
+    let err: Error = parse_err("1 +").into();
+    assert_eq!(err, Error::EofWhileParsing("value".to_string()));
+}
+
+#[test]
+fn render() {
+    let render = |src: &str| parse_err(src).render(src);
+
+    assert_eq!(
+        render("1 + * 2"),
+        "\
+error: InvalidValue
+ --> 1:5
+  |
+1 | 1 + * 2
+  |     ^"
+    );
+    assert_eq!(
+        render("1 +"),
+        "\
+error: EofWhileParsing(\"value\")
+ --> 1:4
+  |
+1 | 1 +
+  |    ^"
+    );
+    assert_eq!(
+        render("x * (1 +\n  sin(1, 2))\n- 3"),
+        "\
+error: WrongArgCount { name: \"sin\", expected: Exact(1), got: 2 }
+ --> 2:3
+  |
+2 |   sin(1, 2))
+  |   ^^^^^^^^^"
+    );
+    // Tabs are kept, and columns count characters:
+    assert_eq!(
+        render("print(\"é\") +\t* 2"),
+        "\
+error: InvalidValue
+ --> 1:14
+  |
+1 | print(\"é\") +\t* 2
+  |             \t^"
+    );
+    // Multi-line spans are underlined up to the end of their first line:
+    assert_eq!(
+        render("1 2\r\n+ 3"),
+        "\
+error: UnparsedTokensRemaining(\"2\\r\\n+ 3\")
+ --> 1:3
+  |
+1 | 1 2
+  |   ^"
+    );
+    let src = format!("{}1 +\n*", "\n".repeat(9));
+    assert_eq!(
+        render(&src),
+        "\
+error: InvalidValue
+  --> 11:1
+   |
+11 | *
+   | ^"
+    );
+    assert_eq!(parse_err(&src).line_col(&src), (11, 1));
+}
+
+#[test]
+fn all_errors() {
+    let all_errors = |src: &str| {
+        Parser::new()
+            .parse_all_errors(src, &mut Slab::new().ps)
+            .unwrap_err()
+            .into_iter()
+            .map(|err| (err.error, err.span))
+            .collect::<Vec<_>>()
+    };
+
+    // Three mistakes, each reported once, in source order:
+    let src = "sin(1, 2) + max(x y, 3) * (4 + * 5)";
+    let sep = || Error::Expected("',' or ';'".to_string());
+    assert_eq!(
+        all_errors(src),
+        vec![
+            (
+                Error::WrongArgCount {
+                    name: "sin".to_string(),
+                    expected: Arity::Exact(1),
+                    got: 2,
+                },
+                0..9
+            ),
+            (sep(), 18..19),
+            (Error::InvalidValue, 31..32),
+        ]
+    );
+    assert_eq!(all_errors(src), all_errors(src));
+    // The first one is the same as parse_spanned()'s:
+    let err = parse_err(src);
+    assert_eq!((err.error, err.span), all_errors(src)[0].clone());
+
+    // Garbage is skipped up to the next argument or closing parenthesis, past
+    // any brackets inside of it, and replaces the argument that it follows:
+    assert_eq!(
+        all_errors("sin(1 (2, 3) x) + (1 2 3) + [4 5]"),
+        vec![
+            (sep(), 6..14),
+            (Error::Expected(")".to_string()), 21..24),
+            (Error::Expected("]".to_string()), 31..32),
+        ]
+    );
+    assert_eq!(
+        all_errors(r#"print("x" 1, 2 $) + 3 4"#),
+        vec![
+            (sep(), 10..11),
+            (sep(), 15..16),
+            (Error::UnparsedTokensRemaining("4".to_string()), 22..23),
+        ]
+    );
+    let parser = Parser::builder()
+        .thousands_separators(true)
+        .build()
+        .unwrap();
+    let errs = parser
+        .parse_all_errors("max(1,000 2; 3) * 4", &mut Slab::new().ps)
+        .unwrap_err();
+    assert_eq!(
+        errs.iter()
+            .map(|err| (err.error.clone(), err.span.clone()))
+            .collect::<Vec<_>>(),
+        vec![(Error::Expected("';'".to_string()), 10..11)]
+    );
+
+    // Unrecoverable errors end the list:
+    assert_eq!(
+        all_errors("1 + * (2 3"),
+        vec![
+            (Error::InvalidValue, 4..5),
+            (Error::Expected(")".to_string()), 6..10)
+        ]
+    );
+    assert_eq!(all_errors("  "), vec![(Error::EmptyExpression, 0..2)]);
+
+    // Valid input parses normally:
+    let mut slab = Slab::new();
+    let expr_i = Parser::new()
+        .parse_all_errors("max(1, 2) + (3)", &mut slab.ps)
+        .unwrap();
+    assert_eq!(
+        expr_i
+            .from(&slab.ps)
+            .eval(&slab, &mut fasteval2::EmptyNamespace),
+        Ok(5.0)
+    );
+}
+
+#[test]
+fn node_spans() {
+    let mut slab = Slab::new();
+    let parser = Parser::new().with_spans();
+
+    let src = "2+3*4";
+    let expr_i = parser.parse(src, &mut slab.ps).unwrap();
+    assert_eq!(expr_i.span(&slab.ps), Some(0..5));
+    let node = |pos: usize| expr_i.node_at(&slab.ps, pos).map(|span| &src[span]);
+    assert_eq!(node(3), Some("3*4"));
+    assert_eq!(node(2), Some("3"));
+    assert_eq!(node(1), Some("2+3*4"));
+    assert_eq!(node(0), Some("2"));
+    assert_eq!(node(5), None);
+
+    // Nodes nest through parentheses, unary operators and function calls, and
+    // operators are grouped like eval() groups them:
+    let src = " -(x + 1) * max(2, y ^ 2 ^ z) - \\\n  print(\"a\", 1 / 2)  ";
+    let expr_i = parser.parse(src, &mut slab.ps).unwrap();
+    let node = |needle: &str| {
+        let pos = src.find(needle).unwrap();
+        &src[expr_i.node_at(&slab.ps, pos).unwrap()]
+    };
+    assert_eq!(&src[expr_i.span(&slab.ps).unwrap()], src.trim());
+    assert_eq!(node("-"), "-(x + 1)");
+    assert_eq!(node("("), "(x + 1)");
+    assert_eq!(node("x"), "x");
+    assert_eq!(node("+"), "x + 1");
+    assert_eq!(node(" * "), "-(x + 1) * max(2, y ^ 2 ^ z)");
+    assert_eq!(node("max"), "max(2, y ^ 2 ^ z)");
+    assert_eq!(node(", "), "max(2, y ^ 2 ^ z)");
+    assert_eq!(node("^ 2"), "y ^ 2 ^ z");
+    assert_eq!(node("^ z"), "2 ^ z");
+    assert_eq!(node(" - "), src.trim());
+    assert_eq!(node("print"), "print(\"a\", 1 / 2)");
+    assert_eq!(node("/"), "1 / 2");
+
+    // Spans aren't recorded by default:
+    let expr_i = Parser::new().parse("2+3*4", &mut slab.ps).unwrap();
+    assert_eq!(
+        (expr_i.span(&slab.ps), expr_i.node_at(&slab.ps, 3)),
+        (None, None)
+    );
+}
+
+#[test]
+fn eval_spans() {
+    let mut ns = |name: &str, args: Vec<f64>| match (name, args.len()) {
+        ("x", 0) => Some(2.0),
+        ("z", 0) | ("g", 1) => Some(1.0),
+        _ => None,
+    };
+    let mut eval_err = |src: &str| {
+        let mut slab = Slab::new();
+        let expr_ref = Parser::new()
+            .with_spans()
+            .parse(src, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        let err = expr_ref.eval_spanned(&slab, &mut ns).unwrap_err();
+        (err.error, src[err.span].to_string())
+    };
+
+    assert_eq!(
+        eval_err("x + sin(x * y) - y"),
+        (
+            Error::UndefinedVariable {
+                name: "y".to_string()
+            },
+            "y".to_string()
+        )
+    );
+    assert_eq!(
+        eval_err("x + -f(x, 1)"),
+        (
+            Error::UndefinedFunction {
+                name: "f".to_string(),
+                arg_count: 2
+            },
+            "f(x, 1)".to_string()
+        )
+    );
+    // Loop variables are only bound inside their loop:
+    assert_eq!(eval_err("sum(i, 1, 3, i * x) + i").1, "i".to_string());
+    // A function is told apart from a variable, and by its number of arguments:
+    assert_eq!(eval_err("z + z(1)").1, "z(1)".to_string());
+    assert_eq!(eval_err("g(1) + g(1, 2)").1, "g(1, 2)".to_string());
+
+    let mut slab = Slab::new();
+    let src = "x + (rand() * rand(x))";
+    let expr_ref = Parser::new()
+        .with_spans()
+        .parse(src, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut rng_ns = EvalNS::new(&mut ns).with_rng(1);
+    let err = expr_ref.eval_spanned(&slab, &mut rng_ns).unwrap_err();
+    assert_eq!(
+        err.error,
+        Error::WrongArgCount {
+            name: "rand".to_string(),
+            expected: Arity::Exact(0),
+            got: 1
+        }
+    );
+    assert_eq!(
+        err.render(src),
+        "\
+error: WrongArgCount { name: \"rand\", expected: Exact(0), got: 1 }
+ --> 1:15
+  |
+1 | x + (rand() * rand(x))
+  |               ^^^^^^^"
+    );
+
+    // Other errors span the whole expression:
+    let src = " 1 + x % 0 ";
+    let expr_ref = Parser::new()
+        .with_spans()
+        .parse(src, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let mut int_ns = EvalNS::new(&mut ns).with_mod_mode(ModMode::Integer);
+    let err = expr_ref.eval_spanned(&slab, &mut int_ns).unwrap_err();
+    assert_eq!((err.error, err.span), (Error::DivideByZero, 1..10));
+
+    // Without recorded spans, the spans are empty:
+    let expr_ref = Parser::new()
+        .parse("x + y", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let err = expr_ref.eval_spanned(&slab, &mut ns).unwrap_err();
+    assert_eq!(err.span, 0..0);
+    // ...and successes are the same as eval():
+    let expr_ref = Parser::new()
+        .with_spans()
+        .parse("x * 3", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(expr_ref.eval_spanned(&slab, &mut ns), Ok(6.0));
+}
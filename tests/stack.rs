@@ -0,0 +1,130 @@
+use fasteval2::{CompiledExpression, Compiler, EmptyNamespace, EvalNS, Parser, Slab};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations of the current thread, so that parallel tests don't interfere:
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocs<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCS.with(Cell::get);
+    let out = f();
+    (out, ALLOCS.with(Cell::get) - before)
+}
+
+fn evalns_cb(name: &str, _: Vec<f64>) -> Option<f64> {
+    match name {
+        "x" => Some(2.5),
+        "y" => Some(4.0),
+        _ => None,
+    }
+}
+
+fn compile_cse(expr_str: &str) -> CompiledExpression {
+    let mut slab = Slab::new();
+    let instr = Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .compile(&slab.ps, &mut slab.cs, &mut EmptyNamespace);
+    let (instr, _) = instr.eliminate_common_subexpressions(&mut slab.cs);
+    CompiledExpression::new(instr, slab).unwrap()
+}
+
+#[test]
+fn no_alloc() {
+    let compiled = compile_cse(
+        "interp(x, 0,0, 10,100) + sqrt(y)*(x-y)*(x-y) + sum(i, 1, 3, interp(i*x, 0,1, 20,0)) \
+         + piecewise(x < 0, -1, 1) + max(x, y, 2) + rand()",
+    );
+    let want = compiled
+        .eval(&mut EvalNS::new(evalns_cb).with_rng(7))
+        .unwrap();
+
+    let mut stack = Vec::with_capacity(compiled.stack_depth());
+    let capacity = stack.capacity();
+    let mut ns = EvalNS::new(evalns_cb).with_rng(7).with_budget(1000);
+    let (val, n) = allocs(|| compiled.eval_with_stack(&mut stack, &mut ns));
+    assert_eq!(val, Ok(want));
+    assert_eq!(n, 0);
+    assert_eq!(stack.capacity(), capacity);
+    assert!(stack.is_empty());
+
+    // The buffer can be reused:
+    let (_, n) = allocs(|| compiled.eval_with_stack(&mut stack, &mut ns).unwrap());
+    assert_eq!(n, 0);
+
+    // Without a stack, the temporaries and breakpoints are allocated:
+    let (_, n) = allocs(|| {
+        compiled
+            .eval(&mut EvalNS::new(evalns_cb).with_rng(7))
+            .unwrap()
+    });
+    assert!(n > 0);
+}
+
+#[test]
+fn small_stack() {
+    let compiled = compile_cse("interp(x, 0,0, 10,100) * (x-y)*(x-y)");
+    let want = compiled.eval(&mut evalns_cb).unwrap();
+
+    // Undersized buffers grow, and old contents are cleared:
+    let mut stack = Vec::new();
+    assert_eq!(
+        compiled.eval_with_stack(&mut stack, &mut evalns_cb),
+        Ok(want)
+    );
+    let mut stack = vec![1.0; 10];
+    assert_eq!(
+        compiled.eval_with_stack(&mut stack, &mut evalns_cb),
+        Ok(want)
+    );
+
+    let loaded = CompiledExpression::from_bytes(&compiled.to_bytes().unwrap()).unwrap();
+    assert_eq!(loaded.eval_with_stack(&mut stack, &mut evalns_cb), Ok(want));
+}
+
+#[test]
+fn stack_depth() {
+    // Each temporary takes two entries, and a repeated variable is a temporary too:
+    for (expr_str, depth) in [
+        ("x + 1", 0),
+        ("sin(x) * max(y, 3) + sum(i, 1, 10, i*2)", 0),
+        ("sin(x) * max(x, y, 3)", 2),
+        ("interp(x, 0,0, 1,10, 2,0)", 6),
+        ("interp(interp(x, 0,0, 1,1), 0,0, 1,1)", 4),
+        ("interp(x, 0,0, 1,interp(y, 0,0, 1,1))", 6),
+        ("(x-y)*(x-y)", 2),
+        ("(x-y)*(x-y) + sqrt(y)", 4),
+        ("(x-y)*(x-y) + sqrt(x)*sqrt(x)", 6),
+        ("(x-y)*(x-y) + interp(x, 0,0, 1,1)", 8),
+    ] {
+        let compiled = compile_cse(expr_str);
+        assert_eq!(compiled.stack_depth(), depth, "{}", expr_str);
+
+        let mut stack = Vec::with_capacity(depth);
+        let (_, n) = allocs(|| {
+            compiled
+                .eval_with_stack(&mut stack, &mut evalns_cb)
+                .unwrap()
+        });
+        assert_eq!(n, 0, "{}", expr_str);
+    }
+}
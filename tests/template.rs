@@ -0,0 +1,111 @@
+use fasteval2::{interpolate, Error, Parser, SpannedError, Template};
+
+use std::collections::BTreeMap;
+
+fn ns(x: f64) -> BTreeMap<String, f64> {
+    let mut ns = BTreeMap::new();
+    ns.insert("x".to_string(), x);
+    ns
+}
+
+fn err(template: &str) -> (Error, std::ops::Range<usize>) {
+    let SpannedError { error, span } = interpolate(template, &mut ns(1.0)).unwrap_err();
+    (error, span)
+}
+
+#[test]
+fn placeholders() {
+    assert_eq!(
+        interpolate("x=${x}, 2x=${x*2}${x+1}!", &mut ns(1.5)),
+        Ok("x=1.5, 2x=32.5!".to_string())
+    );
+    assert_eq!(interpolate("", &mut ns(1.0)), Ok(String::new()));
+    assert_eq!(
+        interpolate("no placeholders", &mut ns(1.0)),
+        Ok("no placeholders".to_string())
+    );
+    assert_eq!(
+        interpolate("${1/3}", &mut ns(1.0)),
+        Ok(format!("{}", 1.0 / 3.0))
+    );
+    // Braces inside of strings don't end a placeholder:
+    assert_eq!(
+        interpolate(r#"${print("}", x)} é ${x}"#, &mut ns(2.0)),
+        Err(SpannedError {
+            error: Error::Bytecode("print() can't be encoded".to_string()),
+            span: 0..16,
+        })
+    );
+
+    // Escapes:
+    assert_eq!(
+        interpolate("$${x} is ${x}", &mut ns(3.0)),
+        Ok("${x} is 3".to_string())
+    );
+    assert_eq!(interpolate("$$${x}", &mut ns(3.0)), Ok("$${x}".to_string()));
+    assert_eq!(
+        interpolate("$ {x} $x }", &mut ns(3.0)),
+        Ok("$ {x} $x }".to_string())
+    );
+    assert_eq!(interpolate("${x}$${", &mut ns(3.0)), Ok("3${".to_string()));
+}
+
+#[test]
+fn reuse_and_precision() {
+    let template = Template::new("${x} / 3 = ${x / 3}", &Parser::new()).unwrap();
+    assert_eq!(template.render(&mut ns(6.0)), Ok("6 / 3 = 2".to_string()));
+    assert_eq!(
+        template.render(&mut ns(1.5)),
+        Ok("1.5 / 3 = 0.5".to_string())
+    );
+    let mut cb = |name: &str, _: Vec<f64>| (name == "x").then_some(-1.0);
+    assert_eq!(
+        template.render(&mut cb),
+        Ok(format!("-1 / 3 = {}", -1.0 / 3.0))
+    );
+
+    let template = template.precision(2);
+    assert_eq!(
+        template.render(&mut ns(1.0)),
+        Ok("1.00 / 3 = 0.33".to_string())
+    );
+    let template = Template::new("${x}", &Parser::new()).unwrap().precision(0);
+    assert_eq!(template.render(&mut ns(2.5)), Ok("2".to_string()));
+    assert_eq!(template.render(&mut ns(3.7)), Ok("4".to_string()));
+
+    // The Parser's options apply to the placeholders:
+    let parser = Parser::builder().max_len(3).build().unwrap();
+    assert_eq!(
+        Template::new("${1+2} ${1 + 2}", &parser).unwrap_err(),
+        SpannedError {
+            error: Error::TooLong,
+            span: 9..14,
+        }
+    );
+}
+
+#[test]
+fn errors() {
+    // Parse errors point into the placeholder:
+    assert_eq!(err("a ${1 + * 2} b"), (Error::InvalidValue, 8..9));
+    assert_eq!(err("a ${x} ${}"), (Error::EmptyExpression, 9..9));
+    // Unterminated placeholders span to the end:
+    assert_eq!(
+        err("a ${x} ${x + 1"),
+        (Error::Expected("}".to_string()), 7..14)
+    );
+    assert_eq!(err(r#"${"}"#), (Error::Expected("}".to_string()), 0..4));
+    // Evaluation errors span their whole placeholder:
+    assert_eq!(
+        err("x=${x}, y=${y + 1}."),
+        (
+            Error::UndefinedVariable {
+                name: "y".to_string()
+            },
+            10..18
+        )
+    );
+    let template = "first\n${x} ${nope(x)}";
+    let e = interpolate(template, &mut ns(1.0)).unwrap_err();
+    assert_eq!(e.line_col(template), (2, 6));
+}
@@ -0,0 +1,295 @@
+use fasteval2::{Arity, Error, Parser, Slab, SpannedError, StrToF64Namespace, ValidationSchema};
+
+use std::collections::{HashMap, HashSet};
+
+fn validate(expr_str: &str, schema: &ValidationSchema) -> Result<(), Vec<Error>> {
+    let mut slab = Slab::new();
+    Parser::new()
+        .parse(expr_str, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps)
+        .validate(&slab, schema)
+        .map_err(|errs| errs.into_iter().map(|err| err.error).collect())
+}
+
+fn schema() -> ValidationSchema {
+    ValidationSchema::new()
+        .with_var("x")
+        .with_var("y")
+        .with_func("f", 1)
+        .with_func("g", 2)
+}
+
+fn undefined_fn(name: &str, arg_count: usize) -> Error {
+    Error::UndefinedFunction {
+        name: name.to_string(),
+        arg_count,
+    }
+}
+
+fn undefined(name: &str) -> Error {
+    Error::UndefinedVariable {
+        name: name.to_string(),
+    }
+}
+
+#[test]
+fn clean() {
+    let schema = schema();
+    for expr_str in [
+        "1",
+        "x + y*2",
+        "f(x) + g(x, f(y))",
+        "sin(x)^2 + max(x, y, 3) + log(2, y) + round(0.5, x) + fma(x, y, 1)",
+        "piecewise(x < 0, -x, x) + interp(x, 0,0, 1,10) + pi() * e()",
+        "sum(i, 1, 10, i*x) + sum(j, x, y, sum(k, 1, j, j*k))",
+        "phi * sqrt2 + ln2 - ln10",
+        r#"print("x is", x)"#,
+    ] {
+        assert_eq!(validate(expr_str, &schema), Ok(()), "{}", expr_str);
+    }
+}
+
+#[test]
+fn violations() {
+    let schema = schema();
+
+    // Everything is reported, in order:
+    assert_eq!(
+        validate("z + f(x, y) - h(1) * g(w) + f(q)", &schema),
+        Err(vec![
+            undefined("z"),
+            Error::WrongArgCount {
+                name: "f".to_string(),
+                expected: Arity::Exact(1),
+                got: 2
+            },
+            undefined_fn("h", 1),
+            Error::WrongArgCount {
+                name: "g".to_string(),
+                expected: Arity::Exact(2),
+                got: 1
+            },
+            undefined("w"),
+            undefined("q"),
+        ])
+    );
+
+    // Inside function arguments, parentheses, and loops:
+    assert_eq!(
+        validate("-(sin(a) + max(x, b)) + sum(i, 1, c, i*d)", &schema),
+        Err(vec![
+            undefined("a"),
+            undefined("b"),
+            undefined("c"),
+            undefined("d")
+        ])
+    );
+
+    // Repeated problems are reported once:
+    assert_eq!(
+        validate("z + z*z + u(1) + u(2)", &schema),
+        Err(vec![undefined("z"), undefined_fn("u", 1)])
+    );
+
+    // Variables and functions are separate:
+    assert_eq!(
+        validate("x(1) + f", &schema),
+        Err(vec![undefined_fn("x", 1), undefined("f")])
+    );
+
+    // Loop variables are only bound inside their loop:
+    assert_eq!(
+        validate("sum(i, 1, 3, i) + i", &schema),
+        Err(vec![undefined("i")])
+    );
+
+    // rand() is a custom function:
+    assert_eq!(
+        validate("rand()", &schema),
+        Err(vec![undefined_fn("rand", 0)])
+    );
+    assert_eq!(validate("rand()", &schema.with_func("rand", 0)), Ok(()));
+}
+
+#[test]
+fn flags() {
+    let mut schema = schema();
+    schema.comparisons = false;
+    schema.print = false;
+    assert_eq!(validate("x + y && !x", &schema), Ok(()));
+    assert_eq!(
+        validate(r#"x < 1 || x >= y || x == y || print("z", z)"#, &schema),
+        Err(vec![
+            Error::NotAllowed("<".to_string()),
+            Error::NotAllowed(">=".to_string()),
+            Error::NotAllowed("==".to_string()),
+            Error::NotAllowed("print".to_string()),
+            undefined("z"),
+        ])
+    );
+}
+
+#[test]
+fn from_names() {
+    let mut ns = StrToF64Namespace::new();
+    ns.insert("x", 1.0);
+    ns.insert("scale", 2.0);
+    let schema = ValidationSchema::from_names(ns.keys());
+    assert_eq!(validate("x * scale(1, 2, 3) + scale", &schema), Ok(()));
+    assert_eq!(validate("x + y", &schema), Err(vec![undefined("y")]));
+
+    let pairs = [("a", 1.0), ("b", 2.0)];
+    let schema = ValidationSchema::from_names(pairs.iter().map(|(name, _)| name));
+    assert_eq!(validate("a + b", &schema), Ok(()));
+}
+
+#[test]
+fn recovered() {
+    let mut slab = Slab::new();
+    let (expr_opt, errs) = Parser::new().parse_with_recovery("1 + * z", &mut slab.ps);
+    assert_eq!(errs, vec![Error::InvalidValue]);
+    let expr_ref = expr_opt.unwrap().from(&slab.ps);
+    let errs = expr_ref.validate(&slab, &schema()).unwrap_err();
+    assert_eq!(
+        errs.into_iter().map(|err| err.error).collect::<Vec<_>>(),
+        vec![Error::InvalidValue, undefined("z")]
+    );
+}
+
+#[test]
+fn spans() {
+    let src = "z + f(x, y) * z - g(1)";
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .with_spans()
+        .parse(src, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let errs = expr_ref.validate(&slab, &schema()).unwrap_err();
+    // Repeated problems keep the span of their first occurrence:
+    assert_eq!(
+        errs.iter()
+            .map(|err| (err.error.clone(), &src[err.span.clone()]))
+            .collect::<Vec<_>>(),
+        vec![
+            (undefined("z"), "z"),
+            (
+                Error::WrongArgCount {
+                    name: "f".to_string(),
+                    expected: Arity::Exact(1),
+                    got: 2
+                },
+                "f(x, y)"
+            ),
+            (
+                Error::WrongArgCount {
+                    name: "g".to_string(),
+                    expected: Arity::Exact(2),
+                    got: 1
+                },
+                "g(1)"
+            ),
+        ]
+    );
+
+    // Without spans, they are empty:
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse(src, &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    let errs = expr_ref.validate(&slab, &schema()).unwrap_err();
+    assert!(errs.iter().all(|err| err.span == (0..0)));
+}
+
+#[test]
+fn allow_list() {
+    let allowed_vars = HashSet::from(["x", "y"]);
+    let allowed_funcs = HashMap::from([
+        ("clip", Arity::Exact(3)),
+        ("max", Arity::Range(1, 2)),
+        ("piecewise", Arity::OddAtLeast(3)),
+        ("sum", Arity::Exact(4)),
+    ]);
+    let check = |src: &str| -> Result<(), Vec<(Error, String)>> {
+        let mut slab = Slab::new();
+        let expr_ref = Parser::new()
+            .with_spans()
+            .parse(src, &mut slab.ps)
+            .unwrap()
+            .from(&slab.ps);
+        expr_ref
+            .check_allowed(&slab, &allowed_vars, &allowed_funcs)
+            .map_err(|errs| {
+                errs.into_iter()
+                    .map(|err| (err.error, src[err.span].to_string()))
+                    .collect()
+            })
+    };
+
+    assert_eq!(
+        check("clip(x, 0, 1) + max(y) * sum(i, 1, 3, i * x)"),
+        Ok(())
+    );
+    assert_eq!(check("piecewise(x < 0, -x, max(x, y))"), Ok(()),);
+
+    // Disallowed names, functions and arities, in nested calls, special forms
+    // and both branches of a conditional:
+    assert_eq!(
+        check("clip(x, 0) + -secret * max(1, y, 3) - sin(x) + sum(i, 1, 3, i * z) + piecewise(x > 0, hack(2), (q))"),
+        Err(vec![
+            (
+                Error::WrongArgCount {
+                    name: "clip".to_string(),
+                    expected: Arity::Exact(3),
+                    got: 2
+                },
+                "clip(x, 0)".to_string()
+            ),
+            (undefined("secret"), "secret".to_string()),
+            (
+                Error::WrongArgCount {
+                    name: "max".to_string(),
+                    expected: Arity::Range(1, 2),
+                    got: 3
+                },
+                "max(1, y, 3)".to_string()
+            ),
+            (undefined_fn("sin", 1), "sin(x)".to_string()),
+            (undefined("z"), "z".to_string()),
+            (undefined_fn("hack", 1), "hack(2)".to_string()),
+            (undefined("q"), "q".to_string()),
+        ])
+    );
+
+    // Nothing is allowed by default, unlike with validate():
+    assert_eq!(
+        check("phi + pi()"),
+        Err(vec![
+            (undefined("phi"), "phi".to_string()),
+            (undefined_fn("pi", 0), "pi()".to_string()),
+        ])
+    );
+    assert_eq!(
+        check(r#"print("x", x)"#),
+        Err(vec![(
+            undefined_fn("print", 2),
+            r#"print("x", x)"#.to_string()
+        )])
+    );
+
+    // Without spans:
+    let mut slab = Slab::new();
+    let expr_ref = Parser::new()
+        .parse("x + w", &mut slab.ps)
+        .unwrap()
+        .from(&slab.ps);
+    assert_eq!(
+        expr_ref.check_allowed(&slab, &allowed_vars, &allowed_funcs),
+        Err(vec![SpannedError {
+            error: undefined("w"),
+            span: 0..0
+        }])
+    );
+}
@@ -0,0 +1,56 @@
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use fasteval2::wasm::{eval, last_error, parse};
+use js_sys::{Map, Object, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn vars(pairs: &[(&str, f64)]) -> JsValue {
+    let obj = Object::new();
+    for (name, val) in pairs {
+        Reflect::set(&obj, &(*name).into(), &(*val).into()).unwrap();
+    }
+    obj.into()
+}
+
+#[wasm_bindgen_test]
+fn formula_with_vars() {
+    let formula = parse("x * 2 + max(y, 1)").unwrap();
+    assert_eq!(eval(&formula, &vars(&[("x", 3.0), ("y", 5.0)])), Ok(11.0));
+    assert_eq!(eval(&formula, &vars(&[("x", 0.5), ("y", 0.0)])), Ok(2.0));
+    assert_eq!(last_error(), "");
+
+    let map = Map::new();
+    map.set(&"x".into(), &4.0.into());
+    map.set(&"y".into(), &2.0.into());
+    assert_eq!(eval(&formula, &map.into()), Ok(10.0));
+
+    let constant = parse("1 + 2").unwrap();
+    assert_eq!(eval(&constant, &JsValue::UNDEFINED), Ok(3.0));
+}
+
+#[wasm_bindgen_test]
+fn errors() {
+    let err = parse("1 + sin(1, 2)").unwrap_err();
+    assert!(err.is_instance_of::<js_sys::Error>());
+    let get = |key: &str| Reflect::get(&err, &key.into()).unwrap().as_f64();
+    assert_eq!((get("start"), get("end")), (Some(4.0), Some(13.0)));
+    assert!(last_error().contains("WrongArgCount"), "{}", last_error());
+
+    // Spans count UTF-16 code units, like JavaScript strings:
+    let err = parse("π + 1").unwrap_err();
+    let get = |key: &str| Reflect::get(&err, &key.into()).unwrap().as_f64();
+    assert_eq!((get("start"), get("end")), (Some(0.0), Some(1.0)));
+
+    let formula = parse("x + 1").unwrap();
+    assert!(eval(&formula, &vars(&[])).is_err());
+    assert!(last_error().contains("x"), "{}", last_error());
+    let bad = Object::new();
+    Reflect::set(&bad, &"x".into(), &"one".into()).unwrap();
+    assert!(eval(&formula, &bad.into()).is_err());
+    assert_eq!(last_error(), "Expected(\"a number for \\\"x\\\"\")");
+    assert!(eval(&formula, &JsValue::from(5)).is_err());
+
+    assert_eq!(eval(&formula, &vars(&[("x", 1.0)])), Ok(2.0));
+    assert_eq!(last_error(), "");
+}